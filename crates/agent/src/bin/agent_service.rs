@@ -0,0 +1,88 @@
+//! Command-line entrypoint for the agent service.
+
+use std::sync::Arc;
+
+use tokio::signal;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{info, warn};
+
+use voip_agent::{control::SipCallControl, AgentConfig, Engines, Orchestrator};
+use voip_common::{
+    events::{decode_event, subjects, CallEndedEvent, CallStartedEvent},
+    init_telemetry,
+    proto::{
+        media::media_service_client::MediaServiceClient, sip::sip_service_client::SipServiceClient,
+    },
+    EventBus, Result, VoipError,
+};
+
+fn channel(url: &str) -> Result<Channel> {
+    Ok(Endpoint::from_shared(url.to_string())
+        .map_err(|e| VoipError::Config(format!("invalid endpoint {}: {}", url, e)))?
+        .connect_lazy())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = voip_common::types::ServiceConfig::default();
+    init_telemetry("agent-service", &config)
+        .map_err(|e| VoipError::Internal(format!("init telemetry failed: {}", e)))?;
+    info!("starting agent service");
+
+    let agent_config = AgentConfig::from_env()?;
+    let engines = Engines::from_config(&agent_config)?;
+    let media = MediaServiceClient::new(channel(&agent_config.media_url)?);
+    let calls = Arc::new(SipCallControl::new(SipServiceClient::new(channel(
+        &agent_config.sip_url,
+    )?)));
+    let bus = EventBus::connect(&config.nats_url)
+        .await?
+        .with_service_name("agent");
+    let orchestrator = Arc::new(
+        Orchestrator::bind(&agent_config, engines, media, calls, Arc::new(bus.clone())).await?,
+    );
+
+    let mut subscriber = bus.subscribe(subjects::CALL_STARTED).await?;
+    let mut ended = bus.subscribe(subjects::CALL_ENDED).await?;
+    let forget = tokio::spawn({
+        let orchestrator = orchestrator.clone();
+        async move {
+            while let Some(message) = ended.next().await {
+                match decode_event::<CallEndedEvent>(&message.payload) {
+                    Ok(event) => orchestrator.on_call_ended(&event),
+                    Err(e) => warn!(error = %e, "undecodable call ended event"),
+                }
+            }
+        }
+    });
+    let calls = tokio::spawn({
+        let orchestrator = orchestrator.clone();
+        async move {
+            while let Some(message) = subscriber.next().await {
+                let orchestrator = orchestrator.clone();
+                // Joining a call waits on the media service; calls do not queue.
+                tokio::spawn(async move {
+                    let joined = match decode_event::<CallStartedEvent>(&message.payload) {
+                        Ok(event) => orchestrator.on_call_started(&event).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = joined {
+                        // NotFound: nothing started a relay for the call.
+                        warn!(error = %e, "agent did not join the call");
+                    }
+                });
+            }
+        }
+    });
+
+    signal::ctrl_c()
+        .await
+        .map_err(|e| VoipError::Internal(format!("waiting for ctrl+c failed: {}", e)))?;
+    info!("ctrl+c received");
+    calls.abort();
+    forget.abort();
+    orchestrator.shutdown();
+    info!("agent service stopped");
+    Ok(())
+}
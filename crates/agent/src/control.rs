@@ -0,0 +1,125 @@
+//! Ending and transferring calls on the agent's behalf.
+
+use async_trait::async_trait;
+use tonic::transport::Channel;
+use voip_common::{
+    proto::{
+        common::{CallId, SipUri},
+        sip::{sip_service_client::SipServiceClient, ByeRequest, TransferRequest},
+    },
+    Result, VoipError,
+};
+
+/// What the agent can do to a call besides talking.
+#[async_trait]
+pub trait CallControl: Send + Sync {
+    /// Hand `call_id` over to `target`, a SIP URI.
+    async fn transfer(&self, call_id: &str, target: &str) -> Result<()>;
+
+    /// End `call_id`.
+    async fn hang_up(&self, call_id: &str, reason: &str) -> Result<()>;
+}
+
+/// [`CallControl`] through the signalling service's `SipService`.
+#[derive(Debug, Clone)]
+pub struct SipCallControl {
+    client: SipServiceClient<Channel>,
+}
+
+impl SipCallControl {
+    /// Control calls through `client`.
+    pub fn new(client: SipServiceClient<Channel>) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl CallControl for SipCallControl {
+    async fn transfer(&self, call_id: &str, target: &str) -> Result<()> {
+        let request = TransferRequest {
+            call_id: Some(CallId {
+                id: call_id.to_owned(),
+                ..CallId::default()
+            }),
+            transfer_to: Some(sip_uri(target)?),
+            attended: false,
+        };
+        let response = self.client.clone().transfer(request).await?.into_inner();
+        if !response.success {
+            return Err(VoipError::Unavailable(format!(
+                "transfer of call {} failed: {}",
+                call_id,
+                response.error.map(|e| e.message).unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn hang_up(&self, call_id: &str, reason: &str) -> Result<()> {
+        let request = ByeRequest {
+            call_id: Some(CallId {
+                id: call_id.to_owned(),
+                ..CallId::default()
+            }),
+            reason: reason.to_owned(),
+        };
+        let response = self.client.clone().bye(request).await?.into_inner();
+        if !response.success {
+            return Err(VoipError::Unavailable(format!(
+                "hanging up call {} failed: {}",
+                call_id,
+                response.error.map(|e| e.message).unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parse a SIP URI the way the signalling service does; `sip:` is assumed
+/// when `uri` names no scheme.
+pub fn sip_uri(uri: &str) -> Result<SipUri> {
+    let has_scheme = ["sip:", "sips:", "tel:"].iter().any(|scheme| {
+        uri.get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    });
+    let parsed = if has_scheme {
+        uri.parse::<voip_signalling::sip::SipUri>()
+    } else {
+        format!("sip:{}", uri).parse()
+    };
+    parsed
+        .map(|uri| SipUri::from(&uri))
+        .map_err(|_| VoipError::Validation(format!("invalid SIP URI {:?}", uri)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sip_uris_are_split_into_their_parts() {
+        let uri = sip_uri("sip:desk@pbx.example.com:5080;transport=tcp").expect("uri");
+        assert_eq!(
+            (uri.user.as_str(), uri.domain.as_str(), uri.port),
+            ("desk", "pbx.example.com", 5080)
+        );
+        assert_eq!(uri.params.get("transport").map(String::as_str), Some("tcp"));
+        let uri = sip_uri("queue.example.com").expect("uri");
+        assert_eq!((uri.user.as_str(), uri.port), ("", 0));
+        assert!(sip_uri("sip:desk@").is_err());
+        assert!(sip_uri("sip:desk@pbx:port").is_err());
+    }
+
+    #[test]
+    fn secure_and_ipv6_uris_are_understood() {
+        let uri = sip_uri("sips:desk@pbx.example.com").expect("sips");
+        assert_eq!(
+            (uri.scheme.as_str(), uri.user.as_str(), uri.domain.as_str()),
+            ("sips", "desk", "pbx.example.com")
+        );
+        let uri = sip_uri("sip:desk@[2001:db8::10]").expect("ipv6");
+        assert_eq!((uri.domain.as_str(), uri.port), ("[2001:db8::10]", 0));
+        let uri = sip_uri("[2001:db8::10]:5080").expect("bare ipv6");
+        assert_eq!((uri.domain.as_str(), uri.port), ("[2001:db8::10]", 5080));
+    }
+}
@@ -0,0 +1,515 @@
+//! One conversation, held over the WebSocket a call's audio is forked to.
+//!
+//! The caller's audio goes to the speech recognizer; each final transcript is
+//! published on the event bus and answered by the call's [`Conversation`],
+//! in a task of its own so the stream is served while a reply is awaited.
+//! Replies are synthesized and sent back in turn, each followed by a mark; the reply's action is taken once
+//! the relay reports that mark played. When the caller barges in, the replies
+//! not played yet are dropped along with their audio, and a pending hang-up
+//! or transfer is taken at once.
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use futures::{
+    stream::{SplitStream, StreamExt},
+    SinkExt,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+    time,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tracing::{debug, info, warn};
+use voip_common::{proto::common::CallId, EventSink, Result, VoipError};
+use voip_media::{
+    fork::protocol::{decode_payload, from_bytes, to_bytes, track_id, AgentMessage, RelayMessage},
+    relay::Side,
+    stt::{publish_finals, SttOptions},
+    tts::TtsOptions,
+};
+
+use crate::{
+    control::CallControl,
+    policy::{Action, CallInfo, Reply},
+    Engines,
+};
+
+/// Longest wait for the relay's `start` once connected.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest wait for the last messages to reach the relay.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Incoming = SplitStream<WebSocketStream<TcpStream>>;
+
+/// What every dialog of an orchestrator shares.
+pub(crate) struct Context {
+    pub(crate) engines: Engines,
+    pub(crate) calls: Arc<dyn CallControl>,
+    /// Where final transcripts are published.
+    pub(crate) events: Arc<dyn EventSink>,
+    pub(crate) language: Option<String>,
+    pub(crate) voice: Option<String>,
+}
+
+/// Hold the conversation of the stream on `socket` until either side ends it.
+pub(crate) async fn run(socket: WebSocketStream<TcpStream>, context: Arc<Context>) {
+    let (mut sink, mut incoming) = socket.split();
+    let start = match time::timeout(START_TIMEOUT, incoming.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str(&text).ok(),
+        _ => None,
+    };
+    let Some(RelayMessage::Start {
+        stream_id,
+        call_id,
+        sip_call_id,
+        media_format,
+        metadata,
+        ..
+    }) = start
+    else {
+        warn!("agent stream closed before it started");
+        return;
+    };
+    let ids = CallId {
+        id: call_id.clone(),
+        sip_call_id,
+        correlation_id: metadata.get("correlation_id").cloned().unwrap_or_default(),
+    };
+    let call = CallInfo {
+        call_id,
+        from: metadata.get("from").cloned().unwrap_or_default(),
+        to: metadata.get("to").cloned().unwrap_or_default(),
+    };
+    info!(%stream_id, call_id = %call.call_id, "dialog started");
+
+    let (out, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+    let mut dialog = Dialog {
+        context,
+        call,
+        ids,
+        stream_id,
+        sample_rate: media_format.sample_rate,
+        out,
+        speaking: Vec::new(),
+        feeding: Arc::new(Mutex::new(())),
+        pending: VecDeque::new(),
+        turns: 0,
+        answering: None,
+    };
+    let stopped = match dialog.hold(incoming).await {
+        Ok(stopped) => stopped,
+        Err(e) => {
+            warn!(stream_id = %dialog.stream_id, error = %e, "dialog failed");
+            Some("dialog failed".to_owned())
+        }
+    };
+    for task in dialog.speaking.drain(..) {
+        task.abort();
+    }
+    if let Some(task) = dialog.answering.take() {
+        task.abort();
+    }
+    if let Some(reason) = stopped {
+        dialog.send(&AgentMessage::Stop {
+            reason: Some(reason),
+        });
+    }
+    info!(stream_id = %dialog.stream_id, "dialog ended");
+    drop(dialog);
+    let _ = time::timeout(CLOSE_TIMEOUT, writer).await;
+}
+
+struct Dialog {
+    context: Arc<Context>,
+    call: CallInfo,
+    /// The call as its transcripts are published.
+    ids: CallId,
+    stream_id: String,
+    sample_rate: u32,
+    /// Messages for the relay, written in order.
+    out: mpsc::UnboundedSender<Message>,
+    /// Tasks speaking replies, one after the other.
+    speaking: Vec<JoinHandle<()>>,
+    /// Held while a reply is spoken, so replies do not interleave.
+    feeding: Arc<Mutex<()>>,
+    /// Actions waiting for the marks sent after their words.
+    pending: VecDeque<(String, Action)>,
+    turns: u64,
+    /// Task answering the caller's utterances, one at a time.
+    answering: Option<JoinHandle<()>>,
+}
+
+impl Dialog {
+    /// Converse until the stream ends; returns why the agent stopped it, if
+    /// it did.
+    async fn hold(&mut self, mut incoming: Incoming) -> Result<Option<String>> {
+        let (audio, heard) = mpsc::unbounded_channel();
+        let options = SttOptions {
+            sample_rate: self.sample_rate,
+            language: self.context.language.clone(),
+        };
+        let transcripts = self
+            .context
+            .engines
+            .stt
+            .transcribe(&options, UnboundedReceiverStream::new(heard).boxed())
+            .await?;
+        let mut transcripts =
+            publish_finals(transcripts, self.ids.clone(), self.context.events.clone()).fuse();
+        let mut conversation = self.context.engines.policy.converse(&self.call);
+        let opening = conversation.open().await?;
+        self.take_turn(opening);
+        let (utterances, mut asked) = mpsc::unbounded_channel::<String>();
+        let (answers, mut replies) = mpsc::unbounded_channel();
+        self.answering = Some(tokio::spawn(async move {
+            while let Some(heard) = asked.recv().await {
+                if answers.send(conversation.reply(&heard).await).is_err() {
+                    break;
+                }
+            }
+        }));
+
+        loop {
+            tokio::select! {
+                message = incoming.next() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => {
+                            return Err(VoipError::Unavailable(format!(
+                                "agent stream {}: {}",
+                                self.stream_id, e
+                            )))
+                        }
+                        None => return Ok(None),
+                    };
+                    match message {
+                        Message::Binary(data) => {
+                            if data.first() == Some(&track_id(Side::Caller)) {
+                                let _ = audio.send(from_bytes(&data[1..]));
+                            }
+                        }
+                        Message::Text(text) => match serde_json::from_str(&text) {
+                            Ok(RelayMessage::Media { track, payload, .. }) if track == "caller" => {
+                                if let Some(samples) = decode_payload(&payload) {
+                                    let _ = audio.send(samples);
+                                }
+                            }
+                            Ok(RelayMessage::Mark { name, .. }) => {
+                                let action = self.played(&name);
+                                if let Some(reason) = self.act(action).await? {
+                                    return Ok(Some(reason));
+                                }
+                            }
+                            Ok(RelayMessage::SpeechStarted { barge_in: true, .. }) => {
+                                let action = self.interrupted();
+                                if let Some(reason) = self.act(action).await? {
+                                    return Ok(Some(reason));
+                                }
+                            }
+                            Ok(RelayMessage::Stop { reason, .. }) => {
+                                debug!(stream_id = %self.stream_id, %reason, "agent stream stopped");
+                                return Ok(None);
+                            }
+                            Ok(_) => {}
+                            Err(e) => debug!(error = %e, "ignoring relay message"),
+                        },
+                        Message::Close(_) => return Ok(None),
+                        _ => {}
+                    }
+                }
+                Some(transcript) = transcripts.next() => {
+                    let transcript = transcript?;
+                    let heard = transcript.text.trim();
+                    if !transcript.is_final || heard.is_empty() {
+                        continue;
+                    }
+                    debug!(stream_id = %self.stream_id, %heard, "caller said");
+                    let _ = utterances.send(heard.to_owned());
+                }
+                Some(reply) = replies.recv() => match reply {
+                    Ok(reply) => self.take_turn(reply),
+                    Err(e) => warn!(stream_id = %self.stream_id, error = %e, "caller not answered"),
+                },
+            }
+        }
+    }
+
+    /// Speak `reply` after the replies not played yet, then mark its end.
+    fn take_turn(&mut self, reply: Reply) {
+        self.turns += 1;
+        let mark = format!("turn-{}", self.turns);
+        let Reply { say, action } = reply;
+        self.pending.push_back((mark.clone(), action));
+
+        let tts = self.context.engines.tts.clone();
+        let options = TtsOptions {
+            sample_rate: self.sample_rate,
+            voice: self.context.voice.clone(),
+        };
+        let out = self.out.clone();
+        let feeding = self.feeding.clone();
+        self.speaking.retain(|task| !task.is_finished());
+        self.speaking.push(tokio::spawn(async move {
+            let _turn = feeding.lock().await;
+            if let Some(text) = say {
+                match tts.synthesize(&text, &options).await {
+                    Ok(mut speech) => {
+                        while let Some(chunk) = speech.next().await {
+                            if out.send(Message::binary(to_bytes(&chunk))).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => warn!(error = %e, "reply not spoken"),
+                }
+            }
+            let _ = out.send(text_message(&AgentMessage::Mark { name: mark }));
+        }));
+    }
+
+    /// The action of the turns played up to the mark `name`.
+    fn played(&mut self, name: &str) -> Action {
+        let Some(at) = self.pending.iter().position(|(mark, _)| mark == name) else {
+            return Action::Listen;
+        };
+        self.pending
+            .drain(..=at)
+            .map(|(_, action)| action)
+            .find(|action| *action != Action::Listen)
+            .unwrap_or_default()
+    }
+
+    /// Drop the replies not played yet; returns the action they carried.
+    fn interrupted(&mut self) -> Action {
+        for task in self.speaking.drain(..) {
+            task.abort();
+        }
+        self.send(&AgentMessage::Clear);
+        debug!(stream_id = %self.stream_id, "caller barged in");
+        self.pending
+            .drain(..)
+            .map(|(_, action)| action)
+            .find(|action| *action != Action::Listen)
+            .unwrap_or_default()
+    }
+
+    /// Take `action`; returns why the stream is over, if it is.
+    async fn act(&self, action: Action) -> Result<Option<String>> {
+        let calls = &self.context.calls;
+        let call_id = &self.call.call_id;
+        match action {
+            Action::Listen => Ok(None),
+            Action::Hangup => {
+                calls.hang_up(call_id, "agent hung up").await?;
+                info!(%call_id, "agent hung up");
+                Ok(Some("hung up".to_owned()))
+            }
+            Action::Transfer { target } => {
+                calls.transfer(call_id, &target).await?;
+                info!(%call_id, %target, "agent transferred the call");
+                Ok(Some(format!("transferred to {}", target)))
+            }
+        }
+    }
+
+    fn send(&self, message: &AgentMessage) {
+        // The writer only stops once the dialog is over.
+        let _ = self.out.send(text_message(message));
+    }
+}
+
+fn text_message(message: &AgentMessage) -> Message {
+    // Plain strings always serialize.
+    Message::text(serde_json::to_string(message).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    use async_trait::async_trait;
+    use voip_common::MemoryEventSink;
+
+    use crate::AgentConfig;
+
+    /// Records what the agent did to calls.
+    #[derive(Default)]
+    struct Recorded(StdMutex<Vec<String>>);
+
+    #[async_trait]
+    impl CallControl for Recorded {
+        async fn transfer(&self, call_id: &str, target: &str) -> Result<()> {
+            self.0
+                .lock()
+                .expect("recorded")
+                .push(format!("transfer {} {}", call_id, target));
+            Ok(())
+        }
+
+        async fn hang_up(&self, call_id: &str, reason: &str) -> Result<()> {
+            self.0
+                .lock()
+                .expect("recorded")
+                .push(format!("hang up {} {}", call_id, reason));
+            Ok(())
+        }
+    }
+
+    /// A dialog on call `c1` that never reaches its engines, and what it
+    /// sends the relay.
+    fn dialog(calls: Arc<Recorded>) -> (Dialog, mpsc::UnboundedReceiver<Message>) {
+        let engines = Engines::from_config(&AgentConfig {
+            stt_url: Some("ws://127.0.0.1:9".to_owned()),
+            tts_url: Some("ws://127.0.0.1:9".to_owned()),
+            llm_url: Some("http://127.0.0.1:9".to_owned()),
+            ..AgentConfig::default()
+        })
+        .expect("engines");
+        let (out, sent) = mpsc::unbounded_channel();
+        let dialog = Dialog {
+            context: Arc::new(Context {
+                engines,
+                calls,
+                events: Arc::new(MemoryEventSink::new()),
+                language: None,
+                voice: None,
+            }),
+            call: CallInfo {
+                call_id: "c1".to_owned(),
+                ..CallInfo::default()
+            },
+            ids: CallId::default(),
+            stream_id: "s1".to_owned(),
+            sample_rate: 8000,
+            out,
+            speaking: Vec::new(),
+            feeding: Arc::new(Mutex::new(())),
+            pending: VecDeque::new(),
+            turns: 0,
+            answering: None,
+        };
+        (dialog, sent)
+    }
+
+    fn silent(action: Action) -> Reply {
+        Reply { say: None, action }
+    }
+
+    fn transfer(target: &str) -> Action {
+        Action::Transfer {
+            target: target.to_owned(),
+        }
+    }
+
+    async fn next_message(sent: &mut mpsc::UnboundedReceiver<Message>) -> AgentMessage {
+        let message = time::timeout(Duration::from_secs(5), sent.recv())
+            .await
+            .expect("message in time")
+            .expect("message");
+        serde_json::from_str(message.to_text().expect("text message")).expect("agent message")
+    }
+
+    #[tokio::test]
+    async fn actions_are_taken_once_their_marks_played() {
+        let (mut dialog, mut sent) = dialog(Arc::default());
+        dialog.take_turn(silent(Action::Listen));
+        dialog.take_turn(silent(Action::Hangup));
+        dialog.take_turn(silent(Action::Listen));
+        for turn in 1..=3 {
+            assert_eq!(
+                next_message(&mut sent).await,
+                AgentMessage::Mark {
+                    name: format!("turn-{}", turn)
+                }
+            );
+        }
+
+        assert_eq!(dialog.played("turn-1"), Action::Listen);
+        assert_eq!(dialog.played("unknown"), Action::Listen);
+        assert_eq!(dialog.played("turn-2"), Action::Hangup);
+        assert_eq!(dialog.pending.len(), 1);
+        // Marks of turns already resolved are ignored.
+        assert_eq!(dialog.played("turn-2"), Action::Listen);
+        assert_eq!(dialog.played("turn-3"), Action::Listen);
+        assert!(dialog.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn marks_played_out_of_order_take_the_skipped_actions() {
+        let (mut dialog, _sent) = dialog(Arc::default());
+        dialog.take_turn(silent(transfer("sip:desk@example.com")));
+        dialog.take_turn(silent(Action::Listen));
+
+        assert_eq!(dialog.played("turn-2"), transfer("sip:desk@example.com"));
+        assert!(dialog.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn barge_in_drops_the_replies_not_played() {
+        let (mut dialog, mut sent) = dialog(Arc::default());
+        // Holding the turn keeps the replies from being spoken.
+        let feeding = dialog.feeding.clone();
+        let turn = feeding.lock().await;
+        dialog.take_turn(silent(Action::Listen));
+        dialog.take_turn(silent(transfer("sip:desk@example.com")));
+
+        assert_eq!(dialog.interrupted(), transfer("sip:desk@example.com"));
+        assert!(dialog.pending.is_empty());
+        assert!(dialog.speaking.is_empty());
+        drop(turn);
+        assert_eq!(next_message(&mut sent).await, AgentMessage::Clear);
+        drop(dialog);
+        assert!(sent.recv().await.is_none(), "dropped replies were spoken");
+    }
+
+    #[tokio::test]
+    async fn barge_in_without_replies_listens_on() {
+        let (mut dialog, mut sent) = dialog(Arc::default());
+        assert_eq!(dialog.interrupted(), Action::Listen);
+        assert_eq!(next_message(&mut sent).await, AgentMessage::Clear);
+    }
+
+    #[tokio::test]
+    async fn hanging_up_and_transferring_end_the_stream() {
+        let calls = Arc::new(Recorded::default());
+        let (dialog, _sent) = dialog(calls.clone());
+
+        assert_eq!(dialog.act(Action::Listen).await.expect("listen"), None);
+        assert_eq!(
+            dialog
+                .act(Action::Hangup)
+                .await
+                .expect("hang up")
+                .as_deref(),
+            Some("hung up")
+        );
+        assert_eq!(
+            dialog
+                .act(transfer("sip:desk@example.com"))
+                .await
+                .expect("transfer")
+                .as_deref(),
+            Some("transferred to sip:desk@example.com")
+        );
+        assert_eq!(
+            *calls.0.lock().expect("recorded"),
+            [
+                "hang up c1 agent hung up",
+                "transfer c1 sip:desk@example.com"
+            ]
+        );
+    }
+}
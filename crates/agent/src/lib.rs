@@ -0,0 +1,942 @@
+//! Conversational agent orchestrator.
+//!
+//! The orchestrator holds a dialog with the caller of every call it is told
+//! about. When a call starts, it asks the media service to fork the caller's
+//! audio to the orchestrator's WebSocket and to detect the caller's speech,
+//! with barge-in. The audio is transcribed by a [`SpeechToText`]; every final
+//! transcript is published on the event bus and goes to the call's
+//! conversation, opened by a [`DialogPolicy`].
+//! Replies are synthesized by a [`TextToSpeech`] and played to the caller,
+//! and once played their action is taken through [`CallControl`]: listening
+//! on, hanging up or transferring the call to a human.
+//!
+//! The agent joins a call once, on its inbound leg; for a bridged call both
+//! legs start, sharing a correlation id. It forks the audio of a relay the
+//! media service already runs for the call: whoever anchors the call's media
+//! must have started it with `MediaService::StartRelay` under the call's
+//! `CallId`. Calls without one are not joined.
+
+pub mod control;
+mod dialog;
+pub mod policy;
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tonic::transport::Channel;
+use tracing::{debug, info, warn};
+use voip_common::{
+    events::{CallEndedEvent, CallStartedEvent},
+    proto::{
+        media::{
+            media_service_client::MediaServiceClient, AgentTracks, SetSpeechDetectionRequest,
+            StartAgentStreamRequest,
+        },
+        sip::CallDirection,
+    },
+    types::ServiceConfig,
+    EventSink, Result, VoipError,
+};
+use voip_media::{
+    stt::{websocket::WebSocketStt, SpeechToText},
+    tts::{websocket::WebSocketTts, TextToSpeech},
+};
+
+use control::CallControl;
+use dialog::Context;
+use policy::{http::HttpPolicy, scripted::Script, scripted::ScriptedPolicy, DialogPolicy};
+
+/// Agent settings, read from the environment by the agent service or from
+/// the `agent` object of `ServiceConfig.extra`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// Address the WebSocket calls are forked to listens on.
+    pub listen_addr: SocketAddr,
+    /// `ws://` URL the media service reaches that socket at, when it is not
+    /// `listen_addr`.
+    pub advertised_url: Option<String>,
+    /// gRPC endpoint of the media service.
+    pub media_url: String,
+    /// gRPC endpoint of the signalling service.
+    pub sip_url: String,
+    /// `ws://` URL of the speech recognizer.
+    pub stt_url: Option<String>,
+    /// `ws://` URL of the speech synthesizer.
+    pub tts_url: Option<String>,
+    /// `http://` or `https://` URL of the dialog model; takes precedence over `script`.
+    pub llm_url: Option<String>,
+    /// Script followed without a dialog model.
+    pub script: Option<Script>,
+    /// Rate of the audio exchanged with the media service: 8000 or 16000.
+    pub sample_rate: u32,
+    /// Language callers speak, or the recognizer's default.
+    pub language: Option<String>,
+    /// Voice replies are spoken with, or the synthesizer's default.
+    pub voice: Option<String>,
+    /// Let callers interrupt the agent.
+    pub barge_in: bool,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], 8090)),
+            advertised_url: None,
+            media_url: format!("http://127.0.0.1:{}", voip_media::DEFAULT_GRPC_PORT),
+            sip_url: "http://127.0.0.1:50051".to_string(),
+            stt_url: None,
+            tts_url: None,
+            llm_url: None,
+            script: None,
+            sample_rate: 8000,
+            language: None,
+            voice: None,
+            barge_in: true,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Read the agent service's settings from the process environment; see
+    /// [`AgentConfig::from_vars`].
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Read from the JSON file named by `AGENT_CONFIG` when it is set, else
+    /// from `AGENT_*` variables named after the fields (`AGENT_STT_URL`...);
+    /// settings not given keep their defaults.
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+        let file = vars
+            .iter()
+            .find(|(name, _)| name == "AGENT_CONFIG")
+            .map(|(_, path)| path);
+        match file {
+            Some(path) => {
+                let json = std::fs::read_to_string(path).map_err(|e| {
+                    VoipError::Config(format!("reading agent config {} failed: {}", path, e))
+                })?;
+                serde_json::from_str(&json)
+                    .map_err(|e| VoipError::Config(format!("invalid agent config {}: {}", path, e)))
+            }
+            None => envy::prefixed("AGENT_")
+                .from_iter(vars)
+                .map_err(|e| VoipError::Config(format!("invalid agent environment: {}", e))),
+        }
+    }
+
+    /// Read from `extra.agent`, falling back to the defaults.
+    pub fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        match config.extra.get("agent") {
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|e| VoipError::Config(format!("invalid agent config: {}", e))),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// The engines dialogs are held with.
+#[derive(Clone)]
+pub struct Engines {
+    /// Transcribes the caller.
+    pub stt: Arc<dyn SpeechToText>,
+    /// Speaks the replies.
+    pub tts: Arc<dyn TextToSpeech>,
+    /// Decides the replies.
+    pub policy: Arc<dyn DialogPolicy>,
+}
+
+impl std::fmt::Debug for Engines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engines").finish_non_exhaustive()
+    }
+}
+
+impl Engines {
+    /// The WebSocket engines and the dialog policy `config` names.
+    pub fn from_config(config: &AgentConfig) -> Result<Self> {
+        let missing = |name: &str| VoipError::Config(format!("agent {} is not set", name));
+        let stt = config.stt_url.clone().ok_or_else(|| missing("stt_url"))?;
+        let tts = config.tts_url.clone().ok_or_else(|| missing("tts_url"))?;
+        let policy: Arc<dyn DialogPolicy> = match (&config.llm_url, &config.script) {
+            (Some(url), _) => Arc::new(HttpPolicy::new(url.clone())?),
+            (None, Some(script)) => Arc::new(ScriptedPolicy::new(script.clone())?),
+            (None, None) => return Err(missing("llm_url or script")),
+        };
+        Ok(Self {
+            stt: Arc::new(WebSocketStt::new(stt)?),
+            tts: Arc::new(WebSocketTts::new(tts)?),
+            policy,
+        })
+    }
+}
+
+/// Starts a dialog on every call and holds it over the forked audio.
+pub struct Orchestrator {
+    url: String,
+    media: MediaServiceClient<Channel>,
+    sample_rate: u32,
+    barge_in: bool,
+    /// Correlation ids of the calls joined and not yet ended.
+    joined: Mutex<HashSet<String>>,
+    accept: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for Orchestrator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Orchestrator")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Orchestrator {
+    /// Listen on `config.listen_addr` for the audio of the calls, forked by
+    /// `media`; final transcripts are published on `events`.
+    pub async fn bind(
+        config: &AgentConfig,
+        engines: Engines,
+        media: MediaServiceClient<Channel>,
+        calls: Arc<dyn CallControl>,
+        events: Arc<dyn EventSink>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(config.listen_addr).await?;
+        let url = match &config.advertised_url {
+            Some(url) => url.clone(),
+            None => format!("ws://{}", listener.local_addr()?),
+        };
+        let context = Arc::new(Context {
+            engines,
+            calls,
+            events,
+            language: config.language.clone(),
+            voice: config.voice.clone(),
+        });
+        let accept = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(error = %e, "accepting agent stream failed");
+                        continue;
+                    }
+                };
+                let context = context.clone();
+                tokio::spawn(async move {
+                    match tokio_tungstenite::accept_async(stream).await {
+                        Ok(socket) => dialog::run(socket, context).await,
+                        Err(e) => warn!(%peer, error = %e, "agent stream handshake failed"),
+                    }
+                });
+            }
+        });
+        info!(%url, "agent listening");
+        Ok(Self {
+            url,
+            media,
+            sample_rate: config.sample_rate,
+            barge_in: config.barge_in,
+            joined: Mutex::new(HashSet::new()),
+            accept,
+        })
+    }
+
+    /// URL the media service streams calls to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Start a dialog on `event`'s call; returns the agent stream's id, or
+    /// `None` for outbound legs and calls already joined.
+    ///
+    /// The call's relay must have been started with `StartRelay` under
+    /// `event.call_id`; without one the media service answers `NotFound`.
+    pub async fn on_call_started(&self, event: &CallStartedEvent) -> Result<Option<String>> {
+        if event.direction != CallDirection::DirectionInbound.as_str_name() {
+            debug!(call_id = %event.call_id, direction = %event.direction, "leg not joined");
+            return Ok(None);
+        }
+        let first = self
+            .joined
+            .lock()
+            .ok()
+            .is_some_and(|mut joined| joined.insert(event.correlation_id.clone()));
+        if !first {
+            debug!(
+                call_id = %event.call_id,
+                correlation_id = %event.correlation_id,
+                "call already joined"
+            );
+            return Ok(None);
+        }
+        let stream = self.join(event).await;
+        if stream.is_err() {
+            self.forget(&event.correlation_id);
+        }
+        stream.map(Some)
+    }
+
+    /// Forget `event`'s call, so a call reusing its correlation id is joined.
+    pub fn on_call_ended(&self, event: &CallEndedEvent) {
+        self.forget(&event.correlation_id);
+    }
+
+    fn forget(&self, correlation_id: &str) {
+        if let Ok(mut joined) = self.joined.lock() {
+            joined.remove(correlation_id);
+        }
+    }
+
+    async fn join(&self, event: &CallStartedEvent) -> Result<String> {
+        let mut media = self.media.clone();
+        let metadata = HashMap::from([
+            ("from".to_string(), event.from.clone()),
+            ("to".to_string(), event.to.clone()),
+            ("correlation_id".to_string(), event.correlation_id.clone()),
+        ]);
+        let stream = media
+            .start_agent_stream(StartAgentStreamRequest {
+                call_id: event.call_id.clone(),
+                url: self.url.clone(),
+                sample_rate: self.sample_rate,
+                binary: true,
+                tracks: AgentTracks::Caller as i32,
+                metadata,
+                ..StartAgentStreamRequest::default()
+            })
+            .await?
+            .into_inner();
+        media
+            .set_speech_detection(SetSpeechDetectionRequest {
+                relay_id: stream.relay_id.clone(),
+                caller: true,
+                barge_in: self.barge_in,
+                ..SetSpeechDetectionRequest::default()
+            })
+            .await?;
+        info!(
+            call_id = %event.call_id,
+            relay_id = %stream.relay_id,
+            stream_id = %stream.stream_id,
+            "agent joined the call"
+        );
+        Ok(stream.stream_id)
+    }
+
+    /// Stop taking new streams; dialogs in progress carry on.
+    pub fn shutdown(&self) {
+        self.accept.abort();
+    }
+}
+
+impl Drop for Orchestrator {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    use axum::{routing::post, Json, Router};
+    use futures::{SinkExt, StreamExt};
+    use tokio::{net::UdpSocket, sync::mpsc, time};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tonic::transport::Server;
+    use voip_common::{
+        events::{subjects, TranscriptEvent},
+        proto::{
+            common::CallId,
+            media::{media_service_server::MediaServiceServer, StartRelayRequest},
+            sip::{sip_service_client::SipServiceClient, sip_service_server::SipServiceServer},
+        },
+        MemoryEventSink,
+    };
+    use voip_media::{
+        codec::g711,
+        fork::protocol::{from_bytes, to_bytes},
+        grpc::MediaGrpcService,
+        rtp::RtpPacket,
+        stt::websocket as stt,
+        tts::websocket as tts,
+        MediaConfig, MediaRelay,
+    };
+    use voip_signalling::{
+        b2bua::B2buaConfig,
+        dialog::{CallManager, CallState},
+        grpc::SipGrpcService,
+        sip::{parse_message, CSeq, Method, SipMessage, SipRequest, StatusCode},
+        transport::{TransportConfig, TransportHandle},
+        SignallingService,
+    };
+
+    use crate::{
+        control::SipCallControl,
+        policy::http::{Role, TurnRequest},
+    };
+
+    const OFFER: &str = "v=0\r\n\
+        o=alice 1 1 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        c=IN IP4 127.0.0.1\r\n\
+        t=0 0\r\n\
+        m=audio 4000 RTP/AVP 8\r\n";
+
+    /// Serves `serve` on every WebSocket accepted on a fresh port.
+    async fn websocket_server<F, Fut>(serve: F) -> String
+    where
+        F: Fn(tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("ws://{}", listener.local_addr().expect("addr"));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(socket) = tokio_tungstenite::accept_async(stream).await {
+                    tokio::spawn(serve(socket));
+                }
+            }
+        });
+        url
+    }
+
+    /// Hears "I need a human" in the first half second of loud audio.
+    async fn stand_in_stt() -> String {
+        websocket_server(|mut socket| async move {
+            let mut loud = 0;
+            while let Some(Ok(message)) = socket.next().await {
+                let Message::Binary(data) = message else {
+                    continue;
+                };
+                let before = loud;
+                loud += from_bytes(&data).iter().filter(|s| s.abs() > 1000).count();
+                if before < 4000 && loud >= 4000 {
+                    let transcript = stt::EngineMessage::Transcript {
+                        text: "I need a human".into(),
+                        is_final: true,
+                        start_ms: 0,
+                        end_ms: 500,
+                        confidence: None,
+                    };
+                    let transcript = serde_json::to_string(&transcript).expect("encode");
+                    let _ = socket.send(Message::text(transcript)).await;
+                }
+            }
+        })
+        .await
+    }
+
+    /// Speaks 80ms of tone per word.
+    async fn stand_in_tts() -> String {
+        websocket_server(|mut socket| async move {
+            let Some(Ok(Message::Text(request))) = socket.next().await else {
+                return;
+            };
+            let Ok(tts::ClientMessage::Synthesize { text, .. }) = serde_json::from_str(&request)
+            else {
+                return;
+            };
+            for _ in text.split_whitespace() {
+                let word: Vec<i16> = (0..640)
+                    .map(|n| if n % 20 < 10 { 3000 } else { -3000 })
+                    .collect();
+                let _ = socket.send(Message::binary(to_bytes(&word))).await;
+            }
+            let done = serde_json::to_string(&tts::EngineMessage::Done).expect("encode");
+            let _ = socket.send(Message::text(done)).await;
+        })
+        .await
+    }
+
+    /// Greets, then transfers callers asking for a human; passes every
+    /// request on.
+    async fn stand_in_llm(turns: mpsc::UnboundedSender<TurnRequest>) -> String {
+        let model = Router::new().route(
+            "/",
+            post(move |Json(turn): Json<TurnRequest>| {
+                let asked_for_human = turn.messages.last().is_some_and(|message| {
+                    message.role == Role::Caller && message.content.contains("human")
+                });
+                let _ = turns.send(turn);
+                async move {
+                    Json(if asked_for_human {
+                        serde_json::json!({
+                            "say": "Putting you through.",
+                            "action": "transfer",
+                            "target": "sip:desk@example.com"
+                        })
+                    } else {
+                        serde_json::json!({"say": "Hello, how can I help?", "action": "listen"})
+                    })
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("http://{}/", listener.local_addr().expect("addr"));
+        tokio::spawn(async move { axum::serve(listener, model).await });
+        url
+    }
+
+    /// The SIP message `socket` receives that is `wanted`.
+    async fn recv_sip(
+        socket: &UdpSocket,
+        wanted: impl Fn(&SipMessage<'_>) -> bool,
+    ) -> SipMessage<'static> {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let (n, _) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+                .await
+                .expect("timed out")
+                .expect("recv");
+            let (message, _) = parse_message(&buf[..n]).expect("parse");
+            if wanted(&message) {
+                return message.into_owned();
+            }
+        }
+    }
+
+    fn request(method: Method) -> impl Fn(&SipMessage<'_>) -> bool {
+        move |m| matches!(m, SipMessage::Request(r) if r.method == method)
+    }
+
+    fn caller_request(
+        caller: &UdpSocket,
+        method: Method,
+        to_tag: Option<&str>,
+    ) -> SipRequest<'static> {
+        let port = caller.local_addr().expect("addr").port();
+        let to = match to_tag {
+            Some(tag) => format!("<sip:support@voip.local>;tag={}", tag),
+            None => "<sip:support@voip.local>".to_string(),
+        };
+        SipRequest::new(method.clone(), "sip:support@voip.local")
+            .with_header(
+                "Via",
+                format!(
+                    "SIP/2.0/UDP 127.0.0.1:{};branch=z9hG4bK{};rport",
+                    port, method
+                ),
+            )
+            .with_header("Max-Forwards", "70")
+            .with_header("From", "<sip:alice@voip.local>;tag=alice")
+            .with_header("To", to)
+            .with_header("Call-ID", "a-leg")
+            .with_header("CSeq", CSeq::new(1, method).to_string())
+            .with_header("Contact", format!("<sip:alice@127.0.0.1:{}>", port))
+    }
+
+    async fn send_sip(socket: &UdpSocket, message: impl Into<SipMessage<'static>>, to: SocketAddr) {
+        socket
+            .send_to(&message.into().to_bytes(), to)
+            .await
+            .expect("send");
+    }
+
+    /// Bridge a call from `caller` to `callee` through `signalling`'s B2BUA.
+    async fn bridge(caller: &UdpSocket, callee: &UdpSocket, signalling: SocketAddr) {
+        let mut invite = caller_request(caller, Method::Invite, None);
+        invite.headers.push("Content-Type", "application/sdp");
+        invite.set_body(OFFER.as_bytes());
+        send_sip(caller, invite, signalling).await;
+        let SipMessage::Request(b_invite) = recv_sip(callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        let mut ok = b_invite.response(StatusCode::OK).with_to_tag("bob");
+        let port = callee.local_addr().expect("addr").port();
+        ok.headers
+            .push("Contact", format!("<sip:bob@127.0.0.1:{}>", port));
+        ok.headers.push("Content-Type", "application/sdp");
+        ok.set_body(OFFER.replace("alice", "bob").into_bytes());
+        send_sip(callee, ok, signalling).await;
+        let SipMessage::Response(answered) = recv_sip(
+            caller,
+            |m| matches!(m, SipMessage::Response(r) if r.status == StatusCode::OK),
+        )
+        .await
+        else {
+            unreachable!()
+        };
+        let tag = answered
+            .headers
+            .to_addr()
+            .and_then(|to| to.tag().map(str::to_string))
+            .expect("tag");
+        send_sip(
+            caller,
+            caller_request(caller, Method::Ack, Some(&tag)),
+            signalling,
+        )
+        .await;
+    }
+
+    /// RTP packets received until none comes for 300ms.
+    async fn receive_all(phone: &UdpSocket) -> usize {
+        let mut packets = 0;
+        let mut buf = [0u8; 1500];
+        while let Ok(received) =
+            time::timeout(Duration::from_millis(300), phone.recv(&mut buf)).await
+        {
+            received.expect("recv");
+            packets += 1;
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn callers_asking_for_a_human_are_transferred() {
+        // Eight ports from an ephemeral one, clear of other tests' windows.
+        let probe = UdpSocket::bind("127.0.0.1:0").await.expect("probe");
+        let rtp_port_min = probe.local_addr().expect("addr").port() & !1;
+        drop(probe);
+        let media_config = MediaConfig {
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            rtp_port_min,
+            rtp_port_max: rtp_port_min + 7,
+            vad_hangover_ms: 200,
+            ..MediaConfig::default()
+        };
+        let relay = Arc::new(MediaRelay::from_config(&media_config).expect("relay"));
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let media_url = format!("http://{}", listener.local_addr().expect("addr"));
+        tokio::spawn(
+            Server::builder()
+                .add_service(MediaServiceServer::new(MediaGrpcService::new(
+                    relay,
+                    &media_config,
+                )))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut media = MediaServiceClient::connect(media_url).await.expect("media");
+
+        let (turns_tx, mut turns) = mpsc::unbounded_channel();
+        let config = AgentConfig {
+            listen_addr: "127.0.0.1:0".parse().expect("addr"),
+            stt_url: Some(stand_in_stt().await),
+            tts_url: Some(stand_in_tts().await),
+            llm_url: Some(stand_in_llm(turns_tx).await),
+            ..AgentConfig::default()
+        };
+        let caller = UdpSocket::bind("127.0.0.1:0").await.expect("caller");
+        let callee = UdpSocket::bind("127.0.0.1:0").await.expect("callee");
+        let sip_events = MemoryEventSink::new();
+        let signalling = SignallingService::bind(&TransportConfig {
+            udp: Some("127.0.0.1:0".parse().expect("addr")),
+            tcp: None,
+            ..TransportConfig::default()
+        })
+        .await
+        .expect("signalling")
+        .with_call_manager(CallManager::new().with_events(Arc::new(sip_events.clone())))
+        .with_b2bua(B2buaConfig {
+            enabled: true,
+            trunk: Some(format!("sip:{}", callee.local_addr().expect("addr"))),
+            advertised_host: None,
+        });
+        let signalling = Arc::new(signalling);
+        let sip_addr = signalling
+            .transport()
+            .and_then(TransportHandle::udp_addr)
+            .expect("udp");
+        signalling.clone().spawn();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let sip_url = format!("http://{}", listener.local_addr().expect("addr"));
+        tokio::spawn(
+            Server::builder()
+                .add_service(SipServiceServer::new(SipGrpcService::new(&signalling)))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let sip = SipServiceClient::connect(sip_url).await.expect("sip");
+
+        let events = MemoryEventSink::new();
+        let orchestrator = Orchestrator::bind(
+            &config,
+            Engines::from_config(&config).expect("engines"),
+            media.clone(),
+            Arc::new(SipCallControl::new(sip)),
+            Arc::new(events.clone()),
+        )
+        .await
+        .expect("orchestrator");
+
+        bridge(&caller, &callee, sip_addr).await;
+        let started: Vec<CallStartedEvent> = time::timeout(Duration::from_secs(5), async {
+            loop {
+                let started = sip_events.events(subjects::CALL_STARTED);
+                if started.len() == 2 {
+                    return started;
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("both legs started");
+        let (inbound, outbound): (Vec<_>, Vec<_>) = started
+            .into_iter()
+            .partition(|event| event.sip_call_id == "a-leg");
+        let (inbound, outbound) = (&inbound[0], &outbound[0]);
+
+        // Whoever anchors the call's media starts its relay under the call's id.
+        let started = media
+            .start_relay(StartRelayRequest {
+                call_id: Some(CallId {
+                    id: inbound.call_id.clone(),
+                    sip_call_id: inbound.sip_call_id.clone(),
+                    correlation_id: inbound.correlation_id.clone(),
+                }),
+                remote_sdp: OFFER.into(),
+                ..StartRelayRequest::default()
+            })
+            .await
+            .expect("relay")
+            .into_inner();
+        let port = started.remote_endpoint.expect("endpoint").rtp_port as u16;
+        let phone = UdpSocket::bind("127.0.0.1:0").await.expect("phone");
+        let mut sequence = 0u16;
+        let mut send = |payload: Vec<u8>| {
+            sequence += 1;
+            RtpPacket::new(8, sequence, u32::from(sequence) * 160, 7, payload)
+                .to_bytes()
+                .expect("encode")
+        };
+        // Silence first, so the relay learns where the phone is.
+        let silence = send(vec![g711::alaw_encode(0); 160]);
+        phone
+            .send_to(&silence, ("127.0.0.1", port))
+            .await
+            .expect("send");
+
+        assert_eq!(
+            orchestrator
+                .on_call_started(outbound)
+                .await
+                .expect("outbound"),
+            None
+        );
+        assert!(orchestrator
+            .on_call_started(inbound)
+            .await
+            .expect("join")
+            .is_some());
+        assert_eq!(
+            orchestrator.on_call_started(inbound).await.expect("again"),
+            None
+        );
+        let greeting = turns.recv().await.expect("greeting");
+        assert_eq!(greeting.call.from, "sip:alice@voip.local");
+        assert!(greeting.messages.is_empty());
+        // Four words of 80ms.
+        assert!(receive_all(&phone).await >= 16, "greeting played");
+
+        for n in 0..60 {
+            let payload = (n * 160..n * 160 + 160)
+                .map(|i| {
+                    let t = i as f64 / 8000.0;
+                    let sample = 3000.0 * (2.0 * std::f64::consts::PI * 300.0 * t).sin();
+                    g711::alaw_encode(sample as i16)
+                })
+                .collect();
+            let packet = send(payload);
+            phone
+                .send_to(&packet, ("127.0.0.1", port))
+                .await
+                .expect("send");
+        }
+        let asked = time::timeout(Duration::from_secs(5), turns.recv())
+            .await
+            .expect("turn")
+            .expect("turn");
+        let said: Vec<(Role, &str)> = asked
+            .messages
+            .iter()
+            .map(|message| (message.role, message.content.as_str()))
+            .collect();
+        assert_eq!(
+            said,
+            [
+                (Role::Agent, "Hello, how can I help?"),
+                (Role::Caller, "I need a human"),
+            ]
+        );
+        let heard: Vec<TranscriptEvent> = events.events(subjects::MEDIA_TRANSCRIPT);
+        assert_eq!(
+            heard
+                .iter()
+                .map(|event| (
+                    event.call_id.as_str(),
+                    event.sip_call_id.as_str(),
+                    event.correlation_id.as_str(),
+                    event.text.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            [(
+                inbound.call_id.as_str(),
+                "a-leg",
+                inbound.correlation_id.as_str(),
+                "I need a human"
+            )]
+        );
+        assert!(receive_all(&phone).await >= 12, "reply played");
+
+        let SipMessage::Request(refer) = recv_sip(&caller, request(Method::Refer)).await else {
+            unreachable!()
+        };
+        assert_eq!(
+            refer.headers.get("Refer-To"),
+            Some("<sip:desk@example.com>")
+        );
+        send_sip(&caller, refer.response(StatusCode::ACCEPTED), sip_addr).await;
+        time::timeout(Duration::from_secs(5), async {
+            while !signalling.calls().list().iter().any(|call| {
+                call.call_id.sip_call_id == "a-leg" && call.state() == CallState::StateTransferring
+            }) {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("caller transferring");
+        signalling.shutdown();
+    }
+
+    #[test]
+    fn the_service_config_builds_engines() {
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let config = AgentConfig::from_vars(vars(&[])).expect("defaults");
+        assert!(config.media_url.ends_with(":50052"));
+        assert!(Engines::from_config(&config).is_err());
+
+        let config = AgentConfig::from_vars(vars(&[
+            ("AGENT_STT_URL", "ws://127.0.0.1:9001"),
+            ("AGENT_TTS_URL", "ws://127.0.0.1:9002"),
+            ("AGENT_LLM_URL", "https://llm.example.com/turn"),
+            ("AGENT_BARGE_IN", "false"),
+            ("AGENT_LISTEN_ADDR", "127.0.0.1:9000"),
+        ]))
+        .expect("environment");
+        assert!(!config.barge_in);
+        assert_eq!(config.listen_addr, SocketAddr::from(([127, 0, 0, 1], 9000)));
+        Engines::from_config(&config).expect("engines from the environment");
+
+        let path = std::env::temp_dir().join(format!("agent-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "stt_url": "ws://127.0.0.1:9001",
+                "tts_url": "ws://127.0.0.1:9002",
+                "script": {
+                    "start": "greeting",
+                    "states": {"greeting": {"say": "Hello.", "routes": []}}
+                }
+            }"#,
+        )
+        .expect("writing the config");
+        let config = AgentConfig::from_vars(vars(&[
+            ("AGENT_CONFIG", path.to_str().expect("utf-8 path")),
+            ("AGENT_STT_URL", "not read"),
+        ]));
+        std::fs::remove_file(&path).expect("removing the config");
+        let config = config.expect("config file");
+        assert_eq!(config.stt_url.as_deref(), Some("ws://127.0.0.1:9001"));
+        Engines::from_config(&config).expect("engines from the file");
+
+        assert!(
+            AgentConfig::from_vars(vars(&[("AGENT_CONFIG", "/nonexistent/agent.json")])).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_first_inbound_leg_of_a_call_is_joined() {
+        let config = AgentConfig {
+            listen_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            stt_url: Some("ws://127.0.0.1:9".to_owned()),
+            tts_url: Some("ws://127.0.0.1:9".to_owned()),
+            llm_url: Some("http://127.0.0.1:9".to_owned()),
+            // Nothing listens there, so joining fails.
+            media_url: "http://127.0.0.1:9".to_owned(),
+            ..AgentConfig::default()
+        };
+        let media = MediaServiceClient::new(
+            tonic::transport::Endpoint::from_shared(config.media_url.clone())
+                .expect("media url")
+                .connect_lazy(),
+        );
+        let calls = SipCallControl::new(SipServiceClient::new(
+            tonic::transport::Endpoint::from_static("http://127.0.0.1:9").connect_lazy(),
+        ));
+        let orchestrator = Orchestrator::bind(
+            &config,
+            Engines::from_config(&config).expect("engines"),
+            media,
+            Arc::new(calls),
+            Arc::new(MemoryEventSink::new()),
+        )
+        .await
+        .expect("orchestrator");
+        let started = |direction: CallDirection| CallStartedEvent {
+            call_id: "leg".to_owned(),
+            from: "sip:alice@example.com".to_owned(),
+            to: "sip:agent@example.com".to_owned(),
+            timestamp: chrono::Utc::now(),
+            correlation_id: "call".to_owned(),
+            sip_call_id: "leg@example.com".to_owned(),
+            direction: direction.as_str_name().to_owned(),
+        };
+        let joined = |orchestrator: &Orchestrator| {
+            orchestrator.joined.lock().expect("joined").contains("call")
+        };
+
+        let outbound = started(CallDirection::DirectionOutbound);
+        assert_eq!(
+            orchestrator
+                .on_call_started(&outbound)
+                .await
+                .expect("outbound"),
+            None
+        );
+        assert!(!joined(&orchestrator));
+
+        let inbound = started(CallDirection::DirectionInbound);
+        orchestrator
+            .joined
+            .lock()
+            .expect("joined")
+            .insert("call".to_owned());
+        assert_eq!(
+            orchestrator
+                .on_call_started(&inbound)
+                .await
+                .expect("duplicate"),
+            None
+        );
+
+        orchestrator.on_call_ended(&CallEndedEvent {
+            call_id: "leg".to_owned(),
+            duration: chrono::Duration::seconds(1),
+            reason: "bye".to_owned(),
+            timestamp: chrono::Utc::now(),
+            correlation_id: "call".to_owned(),
+        });
+        assert!(!joined(&orchestrator));
+        // A call that cannot be joined is forgotten, so it can be retried.
+        assert!(orchestrator.on_call_started(&inbound).await.is_err());
+        assert!(!joined(&orchestrator));
+    }
+}
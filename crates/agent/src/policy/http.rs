@@ -0,0 +1,149 @@
+//! Conversations held by a language model behind an HTTP endpoint.
+//!
+//! Every turn POSTs the call and the conversation so far; the first turn is
+//! asked for with no messages. The model answers with the [`Reply`] to give:
+//!
+//! ```json
+//! {"call":{"call_id":"c1","from":"alice","to":"support"},
+//!  "messages":[{"role":"agent","content":"Hello, how can I help?"},
+//!              {"role":"caller","content":"I need a human"}]}
+//! {"say":"Putting you through.","action":"transfer","target":"sip:desk@example.com"}
+//! ```
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use voip_common::{Result, VoipError};
+
+use super::{CallInfo, Conversation, DialogPolicy, Reply};
+
+/// Longest wait for the model to answer a turn.
+const MODEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who said something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The agent, i.e. an earlier reply of the model.
+    Agent,
+    /// The caller, as transcribed.
+    Caller,
+}
+
+/// One utterance of the conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    /// Who said it.
+    pub role: Role,
+    /// What was said.
+    pub content: String,
+}
+
+/// Body of every request to the model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TurnRequest {
+    /// The call.
+    pub call: CallInfo,
+    /// Everything said so far, oldest first.
+    pub messages: Vec<Message>,
+}
+
+/// A [`DialogPolicy`] asking a model over HTTP.
+#[derive(Debug, Clone)]
+pub struct HttpPolicy {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpPolicy {
+    /// A model answering at the `http://` or `https://` `url`.
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(VoipError::Validation(format!(
+                "dialog model URL {:?} is not an http(s):// URL",
+                url
+            )));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(MODEL_TIMEOUT)
+            .build()
+            .map_err(|e| VoipError::Config(format!("dialog model client: {}", e)))?;
+        Ok(Self { url, client })
+    }
+}
+
+impl DialogPolicy for HttpPolicy {
+    fn converse(&self, call: &CallInfo) -> Box<dyn Conversation> {
+        Box::new(HttpConversation {
+            policy: self.clone(),
+            turn: TurnRequest {
+                call: call.clone(),
+                messages: Vec::new(),
+            },
+        })
+    }
+}
+
+struct HttpConversation {
+    policy: HttpPolicy,
+    turn: TurnRequest,
+}
+
+impl HttpConversation {
+    async fn ask(&mut self) -> Result<Reply> {
+        let reply = self.post().await?;
+        if let Some(say) = &reply.say {
+            self.turn.messages.push(Message {
+                role: Role::Agent,
+                content: say.clone(),
+            });
+        }
+        Ok(reply)
+    }
+
+    async fn post(&self) -> Result<Reply> {
+        let url = &self.policy.url;
+        let failed = |e: reqwest::Error| {
+            if e.is_timeout() {
+                VoipError::Timeout(format!("waiting for dialog model {}", url))
+            } else if e.is_connect() {
+                VoipError::Unavailable(format!("dialog model {}: {}", url, e))
+            } else {
+                VoipError::Http(format!("dialog model {}: {}", url, e))
+            }
+        };
+        let response = self
+            .policy
+            .client
+            .post(url.as_str())
+            .json(&self.turn)
+            .send()
+            .await
+            .map_err(failed)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(VoipError::Http(format!(
+                "dialog model {} answered {}",
+                url, status
+            )));
+        }
+        response.json().await.map_err(failed)
+    }
+}
+
+#[async_trait]
+impl Conversation for HttpConversation {
+    async fn open(&mut self) -> Result<Reply> {
+        self.ask().await
+    }
+
+    async fn reply(&mut self, heard: &str) -> Result<Reply> {
+        self.turn.messages.push(Message {
+            role: Role::Caller,
+            content: heard.to_owned(),
+        });
+        self.ask().await
+    }
+}
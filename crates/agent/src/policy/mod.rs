@@ -0,0 +1,80 @@
+//! What the agent says, and what it does once it has said it.
+//!
+//! A [`DialogPolicy`] opens one [`Conversation`] per call. The conversation
+//! is asked for the opening [`Reply`], then for a reply to every utterance of
+//! the caller. Each reply has words to speak and an [`Action`] taken once
+//! they have been played: listening on, hanging up or transferring the call.
+//!
+//! [`http::HttpPolicy`] asks a language model behind an HTTP endpoint;
+//! [`scripted::ScriptedPolicy`] follows a state machine of canned replies.
+
+pub mod http;
+pub mod scripted;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use voip_common::Result;
+
+/// The call a conversation is held on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallInfo {
+    /// Call identifier, as in the call events.
+    pub call_id: String,
+    /// Calling party.
+    pub from: String,
+    /// Called party.
+    pub to: String,
+}
+
+/// What to do once a reply has been spoken.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Wait for the caller to speak.
+    #[default]
+    Listen,
+    /// End the call.
+    Hangup,
+    /// Hand the call over to someone else.
+    Transfer {
+        /// SIP URI to transfer to.
+        target: String,
+    },
+}
+
+/// One turn of the agent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reply {
+    /// Words to speak, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub say: Option<String>,
+    /// Taken once the words have been played.
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl Reply {
+    /// Say `text`, then listen.
+    pub fn say(text: impl Into<String>) -> Self {
+        Self {
+            say: Some(text.into()),
+            action: Action::Listen,
+        }
+    }
+}
+
+/// The agent's side of one call.
+#[async_trait]
+pub trait Conversation: Send {
+    /// What to say once the call is answered.
+    async fn open(&mut self) -> Result<Reply>;
+
+    /// What to answer the caller, who just said `heard`.
+    async fn reply(&mut self, heard: &str) -> Result<Reply>;
+}
+
+/// Decides how the agent talks to callers.
+pub trait DialogPolicy: Send + Sync {
+    /// A new conversation on `call`.
+    fn converse(&self, call: &CallInfo) -> Box<dyn Conversation>;
+}
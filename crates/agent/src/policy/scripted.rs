@@ -0,0 +1,231 @@
+//! Conversations following a script of canned replies.
+//!
+//! A [`Script`] is a state machine. Entering a state speaks its `say` and
+//! then takes its action. What the caller says next is matched against the
+//! state's routes, in order: a route is taken when the utterance contains one
+//! of its phrases, whole words and case ignored. Without a match the script
+//! moves to `otherwise`, or repeats the state.
+//!
+//! ```json
+//! {
+//!   "start": "greeting",
+//!   "states": {
+//!     "greeting": {
+//!       "say": "Hello, how can I help?",
+//!       "routes": [{"words": ["human", "operator"], "to": "desk"}]
+//!     },
+//!     "desk": {
+//!       "say": "Putting you through.",
+//!       "then": {"action": "transfer", "target": "sip:desk@example.com"}
+//!     }
+//!   }
+//! }
+//! ```
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use voip_common::{Result, VoipError};
+
+use super::{Action, CallInfo, Conversation, DialogPolicy, Reply};
+
+/// States of the conversation, by name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Script {
+    /// State entered when the call is answered.
+    pub start: String,
+    /// Every state.
+    pub states: HashMap<String, State>,
+}
+
+/// A step of a [`Script`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    /// Spoken when the state is entered.
+    #[serde(default)]
+    pub say: Option<String>,
+    /// Taken once `say` has been played.
+    #[serde(default)]
+    pub then: Action,
+    /// Where the caller's words lead, tried in order.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// State entered when no route matches.
+    #[serde(default)]
+    pub otherwise: Option<String>,
+}
+
+/// A transition taken on hearing some words.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    /// Phrases, any of which takes the route.
+    pub words: Vec<String>,
+    /// State entered.
+    pub to: String,
+}
+
+/// A [`DialogPolicy`] holding every conversation to the same script.
+#[derive(Debug, Clone)]
+pub struct ScriptedPolicy {
+    script: Arc<Script>,
+}
+
+impl ScriptedPolicy {
+    /// Follow `script`, once checked that every state it names exists.
+    pub fn new(script: Script) -> Result<Self> {
+        let targets = script.states.values().flat_map(|state| {
+            state
+                .routes
+                .iter()
+                .map(|route| &route.to)
+                .chain(&state.otherwise)
+        });
+        for name in std::iter::once(&script.start).chain(targets) {
+            if !script.states.contains_key(name) {
+                return Err(VoipError::Config(format!(
+                    "script has no state named {:?}",
+                    name
+                )));
+            }
+        }
+        Ok(Self {
+            script: Arc::new(script),
+        })
+    }
+}
+
+impl DialogPolicy for ScriptedPolicy {
+    fn converse(&self, _call: &CallInfo) -> Box<dyn Conversation> {
+        Box::new(ScriptedConversation {
+            script: self.script.clone(),
+            state: self.script.start.clone(),
+        })
+    }
+}
+
+struct ScriptedConversation {
+    script: Arc<Script>,
+    state: String,
+}
+
+impl ScriptedConversation {
+    fn enter(&mut self, name: &str) -> Reply {
+        self.state = name.to_owned();
+        // Every name was checked by `ScriptedPolicy::new`.
+        let state = self.script.states.get(name).cloned().unwrap_or_default();
+        Reply {
+            say: state.say,
+            action: state.then,
+        }
+    }
+}
+
+#[async_trait]
+impl Conversation for ScriptedConversation {
+    async fn open(&mut self) -> Result<Reply> {
+        let start = self.script.start.clone();
+        Ok(self.enter(&start))
+    }
+
+    async fn reply(&mut self, heard: &str) -> Result<Reply> {
+        let heard = words(heard);
+        let Some(state) = self.script.states.get(&self.state) else {
+            return Ok(Reply::default());
+        };
+        let next = state
+            .routes
+            .iter()
+            .find(|route| {
+                route
+                    .words
+                    .iter()
+                    .any(|phrase| contains(&heard, &words(phrase)))
+            })
+            .map(|route| &route.to)
+            .or(state.otherwise.as_ref())
+            .unwrap_or(&self.state)
+            .clone();
+        Ok(self.enter(&next))
+    }
+}
+
+/// Lowercase words of `text`, punctuation dropped.
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn contains(heard: &[String], phrase: &[String]) -> bool {
+    !phrase.is_empty() && heard.windows(phrase.len()).any(|window| window == phrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script() -> Script {
+        serde_json::from_value(serde_json::json!({
+            "start": "greeting",
+            "states": {
+                "greeting": {
+                    "say": "Hello, how can I help?",
+                    "routes": [
+                        {"words": ["human", "real person"], "to": "desk"},
+                        {"words": ["bye"], "to": "goodbye"}
+                    ],
+                    "otherwise": "again"
+                },
+                "again": {
+                    "say": "Sorry, say that again?",
+                    "routes": [{"words": ["human"], "to": "desk"}]
+                },
+                "desk": {
+                    "say": "Putting you through.",
+                    "then": {"action": "transfer", "target": "sip:desk@example.com"}
+                },
+                "goodbye": {"say": "Goodbye.", "then": {"action": "hangup"}}
+            }
+        }))
+        .expect("script")
+    }
+
+    #[tokio::test]
+    async fn routes_follow_the_phrases_heard() {
+        let policy = ScriptedPolicy::new(script()).expect("policy");
+        let mut conversation = policy.converse(&CallInfo::default());
+        assert_eq!(
+            conversation.open().await.expect("open"),
+            Reply::say("Hello, how can I help?")
+        );
+        // "person" alone is not the phrase "real person".
+        let again = conversation.reply("A person, please").await.expect("reply");
+        assert_eq!(again, Reply::say("Sorry, say that again?"));
+        // No route and no `otherwise`: the state repeats.
+        assert_eq!(conversation.reply("what").await.expect("reply"), again);
+        assert_eq!(
+            conversation.reply("HUMAN!").await.expect("reply"),
+            Reply {
+                say: Some("Putting you through.".into()),
+                action: Action::Transfer {
+                    target: "sip:desk@example.com".into()
+                },
+            }
+        );
+
+        let mut conversation = policy.converse(&CallInfo::default());
+        conversation.open().await.expect("open");
+        let goodbye = conversation.reply("OK, bye then").await.expect("reply");
+        assert_eq!(goodbye.action, Action::Hangup);
+
+        let mut broken = script();
+        broken.states.remove("again");
+        assert!(matches!(
+            ScriptedPolicy::new(broken),
+            Err(VoipError::Config(_))
+        ));
+    }
+}
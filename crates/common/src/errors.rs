@@ -45,9 +45,9 @@ pub enum VoipError {
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
-    /// gRPC error
+    /// gRPC error, boxed to keep `Result`s small
     #[error("gRPC error: {0}")]
-    Grpc(#[from] tonic::Status),
+    Grpc(Box<tonic::Status>),
 
     /// Message queue error
     #[error("NATS error: {0}")]
@@ -104,7 +104,7 @@ impl VoipError {
             Self::RateLimit(msg) => tonic::Status::resource_exhausted(msg),
             Self::Timeout(msg) => tonic::Status::deadline_exceeded(msg),
             Self::Unavailable(msg) => tonic::Status::unavailable(msg),
-            Self::Grpc(status) => status.clone(),
+            Self::Grpc(status) => (**status).clone(),
             _ => tonic::Status::internal(self.to_string()),
         }
     }
@@ -152,6 +152,12 @@ impl VoipError {
     }
 }
 
+impl From<tonic::Status> for VoipError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Grpc(Box::new(status))
+    }
+}
+
 /// Extension trait for error context
 pub trait ErrorContext<T> {
     fn context(self, msg: impl fmt::Display) -> Result<T>;
@@ -194,4 +200,13 @@ mod tests {
         assert_eq!(VoipError::NotFound("test".into()).to_http_status(), 404);
         assert_eq!(VoipError::RateLimit("test".into()).to_http_status(), 429);
     }
+
+    #[test]
+    fn test_grpc_status_round_trip() {
+        let error = VoipError::from(tonic::Status::not_found("call c1"));
+        assert_eq!(error.error_code(), "GRPC_ERROR");
+        let status = error.to_status();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(status.message(), "call c1");
+    }
 }
\ No newline at end of file
@@ -5,7 +5,7 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tracing::{debug, error, info};
 
 use crate::errors::{Result, VoipError};
@@ -44,21 +44,7 @@ impl EventBus {
     where
         T: Serialize,
     {
-        let payload = bincode::serialize(event)
-            .map_err(|e| VoipError::Internal(format!("Failed to serialize event: {}", e)))?;
-
-        self.client
-            .publish(subject.to_string(), Bytes::from(payload))
-            .await
-            .map_err(|e| VoipError::Nats(Box::new(e)))?;
-
-        debug!(
-            service = %self.service_name,
-            subject = %subject,
-            "Event published"
-        );
-
-        Ok(())
+        self.publish_bytes(subject, encode_event(event)?).await
     }
 
     /// Publish a proto event
@@ -149,6 +135,106 @@ impl EventBus {
     }
 }
 
+/// Encode an event the way [`EventBus::publish`] puts it on the wire
+pub fn encode_event<T: Serialize>(event: &T) -> Result<Bytes> {
+    bincode::serialize(event)
+        .map(Bytes::from)
+        .map_err(|e| VoipError::Internal(format!("Failed to serialize event: {}", e)))
+}
+
+/// Decode a payload published with [`EventBus::publish`]
+pub fn decode_event<T>(payload: &[u8]) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    bincode::deserialize(payload)
+        .map_err(|e| VoipError::Internal(format!("Failed to deserialize event: {}", e)))
+}
+
+/// Destination for published events
+///
+/// Services publish through this trait so tests can swap NATS for [`MemoryEventSink`].
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Publish an already encoded payload
+    async fn publish_bytes(&self, subject: &str, payload: Bytes) -> Result<()>;
+}
+
+/// Publish a serializable event through any sink
+pub async fn publish_event<T>(sink: &dyn EventSink, subject: &str, event: &T) -> Result<()>
+where
+    T: Serialize + Sync,
+{
+    sink.publish_bytes(subject, encode_event(event)?).await
+}
+
+#[async_trait]
+impl EventSink for EventBus {
+    async fn publish_bytes(&self, subject: &str, payload: Bytes) -> Result<()> {
+        self.client
+            .publish(subject.to_string(), payload)
+            .await
+            .map_err(|e| VoipError::Nats(Box::new(e)))?;
+
+        debug!(
+            service = %self.service_name,
+            subject = %subject,
+            "Event published"
+        );
+
+        Ok(())
+    }
+}
+
+/// In-memory sink recording every published event, for tests
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEventSink {
+    events: Arc<Mutex<Vec<(String, Bytes)>>>,
+}
+
+impl MemoryEventSink {
+    /// Create an empty sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subjects published so far, in order
+    pub fn subjects(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .map(|events| events.iter().map(|(s, _)| s.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Decode every event published on `subject`
+    pub fn events<T>(&self, subject: &str) -> Vec<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        self.events
+            .lock()
+            .map(|events| {
+                events
+                    .iter()
+                    .filter(|(s, _)| s == subject)
+                    .filter_map(|(_, payload)| bincode::deserialize(payload).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl EventSink for MemoryEventSink {
+    async fn publish_bytes(&self, subject: &str, payload: Bytes) -> Result<()> {
+        self.events
+            .lock()
+            .map_err(|_| VoipError::Internal("event sink poisoned".into()))?
+            .push((subject.to_string(), payload));
+        Ok(())
+    }
+}
+
 /// Event handler trait
 #[async_trait]
 pub trait EventHandler: Send + Sync {
@@ -202,20 +288,117 @@ impl EventProcessor {
 }
 
 /// Common event types
+///
+/// Events are bincode-encoded, which is positional: fields are only ever added
+/// after `timestamp`. Consumers built before an addition still decode the
+/// fields they know, but cannot read events from producers built before it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CallStartedEvent {
     pub call_id: String,
     pub from: String,
     pub to: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Shared by both legs of a bridged call
+    pub correlation_id: String,
+    pub sip_call_id: String,
+    /// `voip.sip.CallDirection` name of the leg, e.g. `DIRECTION_INBOUND`
+    pub direction: String,
 }
 
+/// Extended like [`CallStartedEvent`]: new fields go after `timestamp`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CallEndedEvent {
     pub call_id: String,
     pub duration: chrono::Duration,
     pub reason: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Shared by both legs of a bridged call
+    pub correlation_id: String,
+}
+
+/// Published by the media relay when a session ends, so CDRs can carry its quality
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaStoppedEvent {
+    pub session_id: String,
+    pub codec: String,
+    pub duration: chrono::Duration,
+    /// Estimated MOS of the worse direction, if any RTP was received
+    pub mos: Option<f64>,
+    pub r_factor: Option<f64>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Published by the media relay for each DTMF digit a party sends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DtmfEvent {
+    pub session_id: String,
+    /// "caller" or "callee"
+    pub leg: String,
+    pub digit: char,
+    pub duration_ms: u64,
+    /// Detected from tones in the audio rather than RFC 4733 telephone-events
+    pub in_band: bool,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Published by the media relay when a party stops sending media, so
+/// signalling can hang the call up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaTimeoutEvent {
+    pub session_id: String,
+    /// Call the session carries, as given when the relay started; may be empty
+    pub call_id: String,
+    pub sip_call_id: String,
+    /// "caller" or "callee"
+    pub leg: String,
+    pub reason: String,
+    pub silent_for_ms: u64,
+    /// Whether the call was on hold, where RTCP alone keeps it alive
+    pub held: bool,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Published by the media relay when a party starts or stops talking, on
+/// legs where speech detection is enabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeechEvent {
+    pub session_id: String,
+    pub call_id: String,
+    pub sip_call_id: String,
+    /// "caller" or "callee"
+    pub leg: String,
+    /// Whether the party started talking rather than stopped
+    pub started: bool,
+    /// Whether a prompt or agent playing to the leg was stopped for it
+    pub barge_in: bool,
+    /// Length of the speech, once it ended
+    pub duration_ms: Option<u64>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Published for each final transcript of what a party said
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptEvent {
+    /// Call transcribed, as in `voip.common.CallId`
+    pub call_id: String,
+    pub sip_call_id: String,
+    pub correlation_id: String,
+    pub text: String,
+    /// Where the utterance starts and ends in the transcribed audio
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub confidence: Option<f32>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationEvent {
+    pub aor: String,
+    pub contacts: Vec<String>,
+    pub expires: u32,
+    pub source: String,
+    pub reason: Option<String>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -246,6 +429,7 @@ pub mod subjects {
     /// Registration events
     pub const REGISTRATION_SUCCESS: &str = "voip.registration.success";
     pub const REGISTRATION_FAILED: &str = "voip.registration.failed";
+    pub const REGISTRATION_EXPIRED: &str = "voip.registration.expired";
 
     /// Service events
     pub const SERVICE_HEALTH: &str = "voip.service.health";
@@ -255,6 +439,10 @@ pub mod subjects {
     /// Media events
     pub const MEDIA_STARTED: &str = "voip.media.started";
     pub const MEDIA_STOPPED: &str = "voip.media.stopped";
+    pub const MEDIA_DTMF: &str = "voip.media.dtmf";
+    pub const MEDIA_TIMEOUT: &str = "voip.media.timeout";
+    pub const MEDIA_TRANSCRIPT: &str = "voip.media.transcript";
+    pub const MEDIA_SPEECH: &str = "voip.media.speech";
 }
 
 #[cfg(test)]
@@ -267,6 +455,56 @@ mod tests {
         assert_eq!(subjects::SERVICE_HEALTH, "voip.service.health");
     }
 
+    #[tokio::test]
+    async fn test_memory_sink_round_trip() {
+        let sink = MemoryEventSink::new();
+        let event = CallStartedEvent {
+            call_id: "c1".to_string(),
+            correlation_id: "corr-1".to_string(),
+            sip_call_id: "abc@example.com".to_string(),
+            direction: "DIRECTION_INBOUND".to_string(),
+            from: "sip:alice@example.com".to_string(),
+            to: "sip:bob@example.com".to_string(),
+            timestamp: chrono::Utc::now(),
+        };
+        publish_event(&sink, subjects::CALL_STARTED, &event)
+            .await
+            .expect("publish");
+
+        assert_eq!(sink.subjects(), vec![subjects::CALL_STARTED.to_string()]);
+        let decoded: Vec<CallStartedEvent> = sink.events(subjects::CALL_STARTED);
+        assert_eq!(decoded[0].call_id, "c1");
+        let payload = encode_event(&event).expect("encode");
+        let decoded = decode_event::<CallStartedEvent>(&payload).expect("decode");
+        assert_eq!(decoded.to, event.to);
+    }
+
+    #[test]
+    fn test_call_started_keeps_the_original_fields_first() {
+        #[derive(Deserialize)]
+        struct Original {
+            call_id: String,
+            from: String,
+            to: String,
+            timestamp: chrono::DateTime<chrono::Utc>,
+        }
+        let event = CallStartedEvent {
+            call_id: "c1".to_string(),
+            from: "sip:alice@example.com".to_string(),
+            to: "sip:bob@example.com".to_string(),
+            timestamp: chrono::Utc::now(),
+            correlation_id: "corr-1".to_string(),
+            sip_call_id: "abc@example.com".to_string(),
+            direction: "DIRECTION_INBOUND".to_string(),
+        };
+        let payload = encode_event(&event).expect("encode");
+        let original: Original = decode_event(&payload).expect("decode");
+        assert_eq!(
+            (original.call_id, original.from, original.to, original.timestamp),
+            (event.call_id, event.from, event.to, event.timestamp)
+        );
+    }
+
     #[test]
     fn test_service_metrics() {
         let metrics = ServiceMetrics {
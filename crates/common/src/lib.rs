@@ -2,6 +2,7 @@
 
 pub mod errors;
 pub mod events;
+pub mod sdp;
 pub mod telemetry;
 pub mod types;
 
@@ -38,7 +39,7 @@ pub mod proto {
 
 // Re-export commonly used types
 pub use errors::{VoipError, Result};
-pub use events::{EventBus, EventHandler};
+pub use events::{EventBus, EventHandler, EventSink, MemoryEventSink};
 pub use telemetry::{init_telemetry, Metrics, TraceContext};
 pub use types::{CallId, ServiceConfig, ServiceInfo};
 
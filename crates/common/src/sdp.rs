@@ -0,0 +1,1083 @@
+//! SDP session descriptions (RFC 4566) and offer/answer negotiation (RFC 3264)
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::{proto::common::Codec, Result, VoipError};
+
+/// Static RTP/AVP payload types (RFC 3551) usable without an rtpmap
+const STATIC_PAYLOAD_TYPES: &[(u32, &str, u32)] = &[
+    (0, "PCMU", 8000),
+    (3, "GSM", 8000),
+    (4, "G723", 8000),
+    (8, "PCMA", 8000),
+    (9, "G722", 8000),
+    (13, "CN", 8000),
+    (18, "G729", 8000),
+];
+
+/// Media direction attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// `a=sendrecv` (the default)
+    SendRecv,
+    /// `a=sendonly`, used to put the peer on hold
+    SendOnly,
+    /// `a=recvonly`
+    RecvOnly,
+    /// `a=inactive`
+    Inactive,
+}
+
+impl Direction {
+    /// Attribute name
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::SendRecv => "sendrecv",
+            Self::SendOnly => "sendonly",
+            Self::RecvOnly => "recvonly",
+            Self::Inactive => "inactive",
+        }
+    }
+
+    /// Parse an attribute name
+    pub fn from_attribute(name: &str) -> Option<Self> {
+        match name {
+            "sendrecv" => Some(Self::SendRecv),
+            "sendonly" => Some(Self::SendOnly),
+            "recvonly" => Some(Self::RecvOnly),
+            "inactive" => Some(Self::Inactive),
+            _ => None,
+        }
+    }
+
+    /// Whether media flows from the side using this direction
+    pub const fn sends(self) -> bool {
+        matches!(self, Self::SendRecv | Self::SendOnly)
+    }
+
+    /// Whether media flows to the side using this direction
+    pub const fn receives(self) -> bool {
+        matches!(self, Self::SendRecv | Self::RecvOnly)
+    }
+
+    /// Direction to answer an offer in `self` with, given what we are willing to do (RFC 3264 §6.1)
+    pub const fn answer(self, local: Self) -> Self {
+        let send = self.receives() && local.sends();
+        let recv = self.sends() && local.receives();
+        match (send, recv) {
+            (true, true) => Self::SendRecv,
+            (true, false) => Self::SendOnly,
+            (false, true) => Self::RecvOnly,
+            (false, false) => Self::Inactive,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `o=` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// User name, `-` when unused
+    pub username: String,
+
+    /// Session identifier
+    pub session_id: u64,
+
+    /// Version, incremented on every new offer
+    pub session_version: u64,
+
+    /// Address of the originating host
+    pub connection: Connection,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.username, self.session_id, self.session_version, self.connection
+        )
+    }
+}
+
+/// `c=` line, also the address part of `o=`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    /// Network type, `IN`
+    pub net_type: String,
+
+    /// `IP4` or `IP6`
+    pub addr_type: String,
+
+    /// Address, with an optional `/ttl` suffix for multicast
+    pub address: String,
+}
+
+impl Connection {
+    /// `IN IP4` or `IN IP6` connection for `address`
+    pub fn new(address: impl Into<String>) -> Self {
+        let address = address.into();
+        let addr_type = if address.contains(':') { "IP6" } else { "IP4" };
+        Self {
+            net_type: "IN".to_string(),
+            addr_type: addr_type.to_string(),
+            address,
+        }
+    }
+
+    /// Whether this is the RFC 2543 hold address `0.0.0.0`
+    pub fn is_hold(&self) -> bool {
+        self.address == "0.0.0.0"
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.net_type, self.addr_type, self.address)
+    }
+}
+
+impl FromStr for Connection {
+    type Err = VoipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(net_type), Some(addr_type), Some(address), None) => Ok(Self {
+                net_type: net_type.to_string(),
+                addr_type: addr_type.to_string(),
+                address: address.to_string(),
+            }),
+            _ => Err(invalid("connection", s)),
+        }
+    }
+}
+
+/// `a=` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    /// Attribute name
+    pub name: String,
+
+    /// Value after `:`, `None` for property attributes
+    pub value: Option<String>,
+}
+
+impl Attribute {
+    /// Build an attribute
+    pub fn new(name: impl Into<String>, value: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            value,
+        }
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}:{}", self.name, value),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl From<&str> for Attribute {
+    fn from(s: &str) -> Self {
+        match s.split_once(':') {
+            Some((name, value)) => Self::new(name, Some(value.to_string())),
+            None => Self::new(s, None),
+        }
+    }
+}
+
+/// `a=crypto` line of SDES key exchange (RFC 4568)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoAttribute {
+    /// Tag pairing an answer with the offered line
+    pub tag: u32,
+
+    /// Crypto suite, e.g. `AES_CM_128_HMAC_SHA1_80`
+    pub suite: String,
+
+    /// `inline:<key||salt>[|lifetime][|MKI:length]`, several separated by `;`
+    pub key_params: String,
+
+    /// Session parameters, kept verbatim
+    pub session_params: Vec<String>,
+}
+
+impl CryptoAttribute {
+    /// Base64 key and salt of the first inline key parameter
+    pub fn inline_key(&self) -> Option<&str> {
+        let key = self.key_params.split(';').next()?.strip_prefix("inline:")?;
+        key.split('|').next()
+    }
+}
+
+impl fmt::Display for CryptoAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.tag, self.suite, self.key_params)?;
+        for param in &self.session_params {
+            write!(f, " {}", param)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CryptoAttribute {
+    type Err = VoipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let (Some(tag), Some(suite), Some(key_params)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("crypto", s));
+        };
+        Ok(Self {
+            tag: tag.parse().map_err(|_| invalid("crypto", s))?,
+            suite: suite.to_string(),
+            key_params: key_params.to_string(),
+            session_params: parts.map(str::to_string).collect(),
+        })
+    }
+}
+
+/// An `m=` section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaDescription {
+    /// Media type (`audio`, `video`...)
+    pub media: String,
+
+    /// Transport port; 0 rejects or disables the stream
+    pub port: u16,
+
+    /// Number of ports for `port/count` forms
+    pub port_count: Option<u16>,
+
+    /// Transport protocol (`RTP/AVP`, `RTP/SAVP`...)
+    pub protocol: String,
+
+    /// Media formats; RTP payload type numbers for RTP profiles
+    pub formats: Vec<String>,
+
+    /// Media-level `c=` line
+    pub connection: Option<Connection>,
+
+    /// Other lines (`i=`, `b=`, `k=`) kept verbatim, in order
+    pub lines: Vec<(char, String)>,
+
+    /// `a=` lines
+    pub attributes: Vec<Attribute>,
+}
+
+impl MediaDescription {
+    /// RTP audio section offering `codecs` on `port`
+    pub fn audio(port: u16, codecs: &[Codec]) -> Self {
+        let mut media = Self {
+            media: "audio".to_string(),
+            port,
+            port_count: None,
+            protocol: "RTP/AVP".to_string(),
+            formats: Vec::new(),
+            connection: None,
+            lines: Vec::new(),
+            attributes: Vec::new(),
+        };
+        for codec in codecs {
+            media.push_codec(codec);
+        }
+        media
+    }
+
+    /// Value of the first attribute called `name`; property attributes yield `Some("")`
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+
+    /// Media-level direction attribute
+    pub fn direction(&self) -> Option<Direction> {
+        find_direction(&self.attributes)
+    }
+
+    /// Replace the direction attribute
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.attributes
+            .retain(|a| Direction::from_attribute(&a.name).is_none());
+        self.attributes
+            .push(Attribute::new(direction.as_str(), None));
+    }
+
+    /// Whether the stream is RTP-based, so formats are payload types
+    pub fn is_rtp(&self) -> bool {
+        self.protocol.starts_with("RTP/")
+    }
+
+    /// Whether the stream uses SRTP (`RTP/SAVP`, `RTP/SAVPF`)
+    pub fn is_secure(&self) -> bool {
+        self.is_rtp() && self.protocol.contains("SAVP")
+    }
+
+    /// Valid `a=crypto` lines, in offer order
+    pub fn crypto(&self) -> Vec<CryptoAttribute> {
+        self.attributes
+            .iter()
+            .filter(|a| a.name == "crypto")
+            .filter_map(|a| a.value.as_deref()?.parse().ok())
+            .collect()
+    }
+
+    /// Codecs in format order, resolved through rtpmap/fmtp or the static payload table
+    pub fn codecs(&self) -> Vec<Codec> {
+        if !self.is_rtp() {
+            return Vec::new();
+        }
+        let mut rtpmaps: HashMap<u32, (String, u32, u32)> = HashMap::new();
+        let mut fmtps: HashMap<u32, HashMap<String, String>> = HashMap::new();
+        for attribute in &self.attributes {
+            let Some(value) = &attribute.value else {
+                continue;
+            };
+            let Some((pt, rest)) = value.split_once(' ') else {
+                continue;
+            };
+            let Ok(pt) = pt.parse::<u32>() else {
+                continue;
+            };
+            match attribute.name.as_str() {
+                "rtpmap" => {
+                    let mut parts = rest.trim().split('/');
+                    let name = parts.next().unwrap_or_default().to_string();
+                    let rate = parts.next().and_then(|r| r.parse().ok()).unwrap_or(8000);
+                    let channels = parts.next().and_then(|c| c.parse().ok()).unwrap_or(1);
+                    rtpmaps.insert(pt, (name, rate, channels));
+                }
+                "fmtp" => {
+                    fmtps.insert(pt, parse_fmtp(rest.trim()));
+                }
+                _ => {}
+            }
+        }
+
+        self.formats
+            .iter()
+            .filter_map(|format| {
+                let pt = format.parse::<u32>().ok()?;
+                let (name, sample_rate, channels) = rtpmaps.remove(&pt).or_else(|| {
+                    STATIC_PAYLOAD_TYPES
+                        .iter()
+                        .find(|(static_pt, _, _)| *static_pt == pt)
+                        .map(|(_, name, rate)| (name.to_string(), *rate, 1))
+                })?;
+                Some(Codec {
+                    name,
+                    payload_type: pt,
+                    sample_rate,
+                    channels,
+                    parameters: fmtps.remove(&pt).unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// Append a codec with its rtpmap and fmtp attributes
+    pub fn push_codec(&mut self, codec: &Codec) {
+        self.formats.push(codec.payload_type.to_string());
+        let channels = if codec.channels > 1 {
+            format!("/{}", codec.channels)
+        } else {
+            String::new()
+        };
+        self.attributes.push(Attribute::new(
+            "rtpmap",
+            Some(format!(
+                "{} {}/{}{}",
+                codec.payload_type, codec.name, codec.sample_rate, channels
+            )),
+        ));
+        if !codec.parameters.is_empty() {
+            self.attributes.push(Attribute::new(
+                "fmtp",
+                Some(format!(
+                    "{} {}",
+                    codec.payload_type,
+                    format_fmtp(&codec.parameters)
+                )),
+            ));
+        }
+    }
+}
+
+impl fmt::Display for MediaDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m={} {}", self.media, self.port)?;
+        if let Some(count) = self.port_count {
+            write!(f, "/{}", count)?;
+        }
+        write!(f, " {}", self.protocol)?;
+        for format in &self.formats {
+            write!(f, " {}", format)?;
+        }
+        f.write_str("\r\n")?;
+        for (kind, value) in self.lines.iter().filter(|(kind, _)| *kind == 'i') {
+            write!(f, "{}={}\r\n", kind, value)?;
+        }
+        if let Some(connection) = &self.connection {
+            write!(f, "c={}\r\n", connection)?;
+        }
+        for (kind, value) in self.lines.iter().filter(|(kind, _)| *kind != 'i') {
+            write!(f, "{}={}\r\n", kind, value)?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "a={}\r\n", attribute)?;
+        }
+        Ok(())
+    }
+}
+
+/// A session description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionDescription {
+    /// `o=` line
+    pub origin: Origin,
+
+    /// `s=` line
+    pub session_name: String,
+
+    /// Session-level `c=` line
+    pub connection: Option<Connection>,
+
+    /// `t=` start and stop times; `0 0` for unbounded sessions
+    pub timing: (u64, u64),
+
+    /// Other session lines (`i=`, `u=`, `e=`, `p=`, `b=`, `r=`, `z=`, `k=`) kept verbatim, in order
+    pub lines: Vec<(char, String)>,
+
+    /// Session-level `a=` lines
+    pub attributes: Vec<Attribute>,
+
+    /// `m=` sections
+    pub media: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    /// Parse an SDP body
+    pub fn parse(sdp: &str) -> Result<Self> {
+        sdp.parse()
+    }
+
+    /// Value of the first session-level attribute called `name`
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+
+    /// Effective direction of a stream: its own attribute, then the session's, then sendrecv
+    pub fn direction_of(&self, media: &MediaDescription) -> Direction {
+        media
+            .direction()
+            .or_else(|| find_direction(&self.attributes))
+            .unwrap_or(Direction::SendRecv)
+    }
+
+    /// Effective connection of a stream
+    pub fn connection_of<'a>(&'a self, media: &'a MediaDescription) -> Option<&'a Connection> {
+        media.connection.as_ref().or(self.connection.as_ref())
+    }
+
+    /// First enabled RTP audio stream
+    pub fn audio(&self) -> Option<&MediaDescription> {
+        self.media
+            .iter()
+            .find(|m| m.media == "audio" && m.port != 0 && m.is_rtp())
+    }
+
+    /// Whether the audio stream is on hold: sendonly, inactive or the `0.0.0.0` address
+    pub fn is_hold(&self) -> bool {
+        self.audio().is_some_and(|audio| {
+            matches!(
+                self.direction_of(audio),
+                Direction::SendOnly | Direction::Inactive
+            ) || self.connection_of(audio).is_some_and(Connection::is_hold)
+        })
+    }
+
+    /// Answer this offer with our capabilities (RFC 3264 §6)
+    ///
+    /// The first audio stream with a codec in common is accepted, keeping the
+    /// offerer's payload type numbers in our preference order; every other
+    /// stream is rejected with port 0. SRTP streams also need a crypto suite
+    /// in common, answered with our key. Fails when no audio can be accepted.
+    pub fn answer(&self, local: &LocalMedia) -> Result<Self> {
+        let mut accepted = false;
+        let media = self
+            .media
+            .iter()
+            .map(|offered| {
+                if accepted || offered.media != "audio" || offered.port == 0 || !offered.is_rtp() {
+                    return rejected(offered);
+                }
+                let common = intersect(&offered.codecs(), &local.codecs);
+                if !common.iter().any(|c| !is_auxiliary(c)) {
+                    return rejected(offered);
+                }
+                let mut crypto = None;
+                if offered.is_secure() {
+                    match negotiate_crypto(&offered.crypto(), &local.crypto) {
+                        Some((_, ours)) => crypto = Some(ours),
+                        None => return rejected(offered),
+                    }
+                }
+                accepted = true;
+                let mut answer = MediaDescription::audio(local.port, &common);
+                answer.protocol = offered.protocol.clone();
+                if let Some(crypto) = crypto {
+                    answer
+                        .attributes
+                        .push(Attribute::new("crypto", Some(crypto.to_string())));
+                }
+                if let Some(ptime) = offered.attribute("ptime") {
+                    answer
+                        .attributes
+                        .push(Attribute::new("ptime", Some(ptime.to_string())));
+                }
+                answer.set_direction(self.direction_of(offered).answer(local.direction));
+                answer
+            })
+            .collect();
+        if !accepted {
+            return Err(VoipError::Media(
+                "no audio codec in common with offer".into(),
+            ));
+        }
+
+        let mut answer = local.session();
+        answer.media = media;
+        Ok(answer)
+    }
+
+    /// Codecs of the first enabled audio stream
+    pub fn audio_codecs(&self) -> Vec<Codec> {
+        self.audio()
+            .map(MediaDescription::codecs)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v=0\r\no={}\r\ns={}\r\n", self.origin, self.session_name)?;
+        // RFC 4566 §5 order: i u e p before c, then b, then t r, then z k.
+        let write_lines = |f: &mut fmt::Formatter<'_>, kinds: &str| -> fmt::Result {
+            for (kind, value) in self.lines.iter().filter(|(kind, _)| kinds.contains(*kind)) {
+                write!(f, "{}={}\r\n", kind, value)?;
+            }
+            Ok(())
+        };
+        write_lines(f, "iuep")?;
+        if let Some(connection) = &self.connection {
+            write!(f, "c={}\r\n", connection)?;
+        }
+        write_lines(f, "b")?;
+        write!(f, "t={} {}\r\n", self.timing.0, self.timing.1)?;
+        write_lines(f, "rzk")?;
+        for attribute in &self.attributes {
+            write!(f, "a={}\r\n", attribute)?;
+        }
+        for media in &self.media {
+            write!(f, "{}", media)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SessionDescription {
+    type Err = VoipError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut origin = None;
+        let mut session_name = None;
+        let mut version = None;
+        let mut session = SessionDescription {
+            origin: Origin {
+                username: "-".to_string(),
+                session_id: 0,
+                session_version: 0,
+                connection: Connection::new("0.0.0.0"),
+            },
+            session_name: String::new(),
+            connection: None,
+            timing: (0, 0),
+            lines: Vec::new(),
+            attributes: Vec::new(),
+            media: Vec::new(),
+        };
+
+        for line in s.lines().map(str::trim_end).filter(|l| !l.is_empty()) {
+            let (kind, value) = match line.split_once('=') {
+                Some((kind, value)) if kind.len() == 1 => {
+                    (kind.chars().next().unwrap_or('?'), value)
+                }
+                _ => return Err(invalid("line", line)),
+            };
+            if kind == 'm' {
+                session.media.push(parse_media(value)?);
+                continue;
+            }
+            if let Some(media) = session.media.last_mut() {
+                match kind {
+                    'c' => media.connection = Some(value.parse()?),
+                    'a' => media.attributes.push(Attribute::from(value)),
+                    _ => media.lines.push((kind, value.to_string())),
+                }
+                continue;
+            }
+            match kind {
+                'v' => version = Some(value),
+                'o' => origin = Some(parse_origin(value)?),
+                's' => session_name = Some(value.to_string()),
+                'c' => session.connection = Some(value.parse()?),
+                't' => {
+                    let mut parts = value.split_whitespace().map(str::parse::<u64>);
+                    session.timing = match (parts.next(), parts.next()) {
+                        (Some(Ok(start)), Some(Ok(stop))) => (start, stop),
+                        _ => return Err(invalid("timing", value)),
+                    };
+                }
+                'a' => session.attributes.push(Attribute::from(value)),
+                _ => session.lines.push((kind, value.to_string())),
+            }
+        }
+
+        if version != Some("0") {
+            return Err(invalid("version", version.unwrap_or_default()));
+        }
+        session.origin = origin.ok_or_else(|| invalid("origin", ""))?;
+        session.session_name = session_name.ok_or_else(|| invalid("session name", ""))?;
+        Ok(session)
+    }
+}
+
+/// What we can do with media, used to build offers and answers
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalMedia {
+    /// Address RTP is received on
+    pub address: String,
+
+    /// RTP port
+    pub port: u16,
+
+    /// Supported codecs, most preferred first
+    pub codecs: Vec<Codec>,
+
+    /// What we are willing to do; `SendOnly` puts the peer on hold
+    pub direction: Direction,
+
+    /// `o=` session identifier
+    pub session_id: u64,
+
+    /// `o=` version; bump it for every new offer in a session
+    pub session_version: u64,
+
+    /// SDES keys we offer, one per suite; empty for plain RTP
+    pub crypto: Vec<CryptoAttribute>,
+}
+
+impl LocalMedia {
+    /// Offer a single audio stream with every local codec
+    pub fn offer(&self) -> SessionDescription {
+        let mut session = self.session();
+        let mut audio = MediaDescription::audio(self.port, &self.codecs);
+        if !self.crypto.is_empty() {
+            audio.protocol = "RTP/SAVP".to_string();
+        }
+        for crypto in &self.crypto {
+            audio
+                .attributes
+                .push(Attribute::new("crypto", Some(crypto.to_string())));
+        }
+        audio.set_direction(self.direction);
+        session.media.push(audio);
+        session
+    }
+
+    fn session(&self) -> SessionDescription {
+        SessionDescription {
+            origin: Origin {
+                username: "-".to_string(),
+                session_id: self.session_id,
+                session_version: self.session_version,
+                connection: Connection::new(self.address.clone()),
+            },
+            session_name: "-".to_string(),
+            connection: Some(Connection::new(self.address.clone())),
+            timing: (0, 0),
+            lines: Vec::new(),
+            attributes: Vec::new(),
+            media: Vec::new(),
+        }
+    }
+}
+
+/// Whether two codec descriptions name the same encoding with compatible fmtp
+pub fn codecs_match(a: &Codec, b: &Codec) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name)
+        && a.sample_rate == b.sample_rate
+        && a.channels.max(1) == b.channels.max(1)
+        && fmtp_compatible(&a.parameters, &b.parameters)
+}
+
+/// Parameters set on both sides must agree, and so must bare values such as
+/// telephone-event lists; a side without fmtp takes the format's defaults.
+fn fmtp_compatible(a: &HashMap<String, String>, b: &HashMap<String, String>) -> bool {
+    let bare = |parameters: &HashMap<String, String>| -> Vec<String> {
+        let mut values: Vec<String> = parameters
+            .iter()
+            .filter(|(_, v)| v.is_empty())
+            .map(|(k, _)| k.to_ascii_lowercase())
+            .collect();
+        values.sort();
+        values
+    };
+    let (a_bare, b_bare) = (bare(a), bare(b));
+    let values_agree = a.iter().filter(|(_, v)| !v.is_empty()).all(|(key, value)| {
+        b.iter()
+            .find(|(k, v)| k.eq_ignore_ascii_case(key) && !v.is_empty())
+            .is_none_or(|(_, v)| v.eq_ignore_ascii_case(value))
+    });
+    values_agree && (a_bare.is_empty() || b_bare.is_empty() || a_bare == b_bare)
+}
+
+/// Codecs present on both sides, in `local` order, with the offerer's payload types and fmtp
+pub fn intersect(offered: &[Codec], local: &[Codec]) -> Vec<Codec> {
+    local
+        .iter()
+        .filter_map(|ours| offered.iter().find(|theirs| codecs_match(theirs, ours)))
+        .cloned()
+        .collect()
+}
+
+/// First offered crypto line with a suite we support, and our line answering it
+pub fn negotiate_crypto(
+    offered: &[CryptoAttribute],
+    local: &[CryptoAttribute],
+) -> Option<(CryptoAttribute, CryptoAttribute)> {
+    offered.iter().find_map(|theirs| {
+        let ours = local.iter().find(|ours| ours.suite == theirs.suite)?;
+        Some((
+            theirs.clone(),
+            CryptoAttribute {
+                tag: theirs.tag,
+                ..ours.clone()
+            },
+        ))
+    })
+}
+
+/// Comfort noise and DTMF events do not carry a call on their own.
+fn is_auxiliary(codec: &Codec) -> bool {
+    codec.name.eq_ignore_ascii_case("telephone-event") || codec.name.eq_ignore_ascii_case("CN")
+}
+
+fn rejected(offered: &MediaDescription) -> MediaDescription {
+    MediaDescription {
+        media: offered.media.clone(),
+        port: 0,
+        port_count: None,
+        protocol: offered.protocol.clone(),
+        formats: offered.formats.iter().take(1).cloned().collect(),
+        connection: None,
+        lines: Vec::new(),
+        attributes: Vec::new(),
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|a| a.name == name)
+        .map(|a| a.value.as_deref().unwrap_or_default())
+}
+
+fn find_direction(attributes: &[Attribute]) -> Option<Direction> {
+    attributes
+        .iter()
+        .find_map(|a| Direction::from_attribute(&a.name))
+}
+
+fn parse_origin(value: &str) -> Result<Origin> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [username, session_id, session_version, net_type, addr_type, address] = parts[..] else {
+        return Err(invalid("origin", value));
+    };
+    Ok(Origin {
+        username: username.to_string(),
+        session_id: session_id.parse().map_err(|_| invalid("origin", value))?,
+        session_version: session_version
+            .parse()
+            .map_err(|_| invalid("origin", value))?,
+        connection: Connection {
+            net_type: net_type.to_string(),
+            addr_type: addr_type.to_string(),
+            address: address.to_string(),
+        },
+    })
+}
+
+fn parse_media(value: &str) -> Result<MediaDescription> {
+    let mut parts = value.split_whitespace();
+    let (Some(media), Some(port), Some(protocol)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("media", value));
+    };
+    let (port, port_count) = match port.split_once('/') {
+        Some((port, count)) => (
+            port,
+            Some(count.parse().map_err(|_| invalid("media", value))?),
+        ),
+        None => (port, None),
+    };
+    Ok(MediaDescription {
+        media: media.to_string(),
+        port: port.parse().map_err(|_| invalid("media", value))?,
+        port_count,
+        protocol: protocol.to_string(),
+        formats: parts.map(str::to_string).collect(),
+        connection: None,
+        lines: Vec::new(),
+        attributes: Vec::new(),
+    })
+}
+
+/// `key=value;flag` fmtp parameters; bare values (`0-16`) become keys with an empty value.
+fn parse_fmtp(value: &str) -> HashMap<String, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (k.trim().to_string(), v.trim().to_string()),
+            None => (p.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn format_fmtp(parameters: &HashMap<String, String>) -> String {
+    let mut params: Vec<_> = parameters.iter().collect();
+    params.sort();
+    params
+        .into_iter()
+        .map(|(k, v)| {
+            if v.is_empty() {
+                k.clone()
+            } else {
+                format!("{}={}", k, v)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn invalid(what: &str, value: &str) -> VoipError {
+    VoipError::Validation(format!("invalid SDP {}: {:?}", what, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "v=0\r\n\
+        o=alice 2890844526 2890844526 IN IP4 192.0.2.10\r\n\
+        s=-\r\n\
+        c=IN IP4 192.0.2.10\r\n\
+        t=0 0\r\n\
+        m=audio 49170 RTP/AVP 0 8 9 101\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n\
+        a=fmtp:101 0-16\r\n\
+        a=ptime:20\r\n\
+        m=video 51372 RTP/AVP 31\r\n\
+        a=rtpmap:31 H261/90000\r\n";
+
+    fn codec(name: &str, payload_type: u32) -> Codec {
+        Codec {
+            name: name.to_string(),
+            payload_type,
+            sample_rate: 8000,
+            channels: 1,
+            parameters: HashMap::new(),
+        }
+    }
+
+    fn local(codecs: Vec<Codec>) -> LocalMedia {
+        LocalMedia {
+            address: "198.51.100.1".to_string(),
+            port: 30000,
+            codecs,
+            direction: Direction::SendRecv,
+            session_id: 1,
+            session_version: 1,
+            crypto: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let sdp = SessionDescription::parse(OFFER).expect("parse");
+        assert_eq!(sdp.origin.session_id, 2890844526);
+        assert_eq!(sdp.media.len(), 2);
+        let names: Vec<String> = sdp.audio_codecs().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["PCMU", "PCMA", "G722", "telephone-event"]);
+        assert_eq!(
+            sdp.audio_codecs()[3]
+                .parameters
+                .get("0-16")
+                .map(String::as_str),
+            Some("")
+        );
+        assert_eq!(sdp.to_string(), OFFER);
+        assert_eq!(
+            SessionDescription::parse(&sdp.to_string()).expect("reparse"),
+            sdp
+        );
+
+        assert!(SessionDescription::parse("v=1\r\no=- 1 1 IN IP4 1.1.1.1\r\ns=-\r\n").is_err());
+        assert!(SessionDescription::parse("v=0\r\ns=-\r\n").is_err());
+    }
+
+    #[test]
+    fn test_answer_intersects_codecs() {
+        let offer = SessionDescription::parse(OFFER).expect("parse");
+        let mut event = codec("telephone-event", 96);
+        event.parameters.insert("0-16".into(), String::new());
+        let answer = offer
+            .answer(&local(vec![codec("PCMA", 8), codec("G729", 18), event]))
+            .expect("answer");
+
+        assert_eq!(answer.media.len(), 2);
+        let audio = &answer.media[0];
+        assert_eq!(audio.port, 30000);
+        // Our preference order, the offerer's payload types and fmtp.
+        assert_eq!(audio.formats, vec!["8", "101"]);
+        assert_eq!(audio.attribute("fmtp"), Some("101 0-16"));
+        assert_eq!(audio.attribute("ptime"), Some("20"));
+        assert_eq!(audio.direction(), Some(Direction::SendRecv));
+        assert_eq!(answer.media[1].port, 0, "video is rejected");
+        assert_eq!(
+            answer.connection.as_ref().map(|c| c.address.as_str()),
+            Some("198.51.100.1")
+        );
+
+        let err = offer
+            .answer(&local(vec![codec("G729", 18)]))
+            .expect_err("no common codec");
+        assert!(matches!(err, VoipError::Media(_)));
+    }
+
+    #[test]
+    fn test_intersection_compares_fmtp() {
+        let mut offered_event = codec("telephone-event", 101);
+        offered_event
+            .parameters
+            .insert("0-16".into(), String::new());
+        let mut our_event = codec("telephone-event", 96);
+        our_event.parameters.insert("0-15".into(), String::new());
+        assert!(intersect(&[offered_event.clone()], &[our_event]).is_empty());
+        assert_eq!(
+            intersect(&[offered_event.clone()], &[codec("telephone-event", 96)]),
+            [offered_event],
+            "no fmtp takes the offerer's"
+        );
+
+        let opus = |stereo: &str| Codec {
+            name: "opus".to_string(),
+            payload_type: 111,
+            sample_rate: 48000,
+            channels: 2,
+            parameters: HashMap::from([
+                ("stereo".to_string(), stereo.to_string()),
+                ("useinbandfec".to_string(), "1".to_string()),
+            ]),
+        };
+        let mut mono = opus("0");
+        mono.parameters.remove("useinbandfec");
+        assert!(intersect(&[opus("1")], &[mono]).is_empty());
+        assert_eq!(intersect(&[opus("1")], &[opus("1")]), [opus("1")]);
+    }
+
+    #[test]
+    fn test_hold_directions() {
+        let mut hold = local(vec![codec("PCMU", 0)]);
+        hold.direction = Direction::SendOnly;
+        let offer = hold.offer();
+        assert!(offer.is_hold());
+
+        let answer = offer
+            .answer(&local(vec![codec("PCMU", 0)]))
+            .expect("answer");
+        assert_eq!(
+            answer.audio().and_then(|m| m.direction()),
+            Some(Direction::RecvOnly)
+        );
+        assert!(!answer.is_hold());
+
+        assert_eq!(
+            Direction::Inactive.answer(Direction::SendRecv),
+            Direction::Inactive
+        );
+        assert_eq!(
+            Direction::RecvOnly.answer(Direction::SendRecv),
+            Direction::SendOnly
+        );
+        let legacy = OFFER.replace("c=IN IP4 192.0.2.10", "c=IN IP4 0.0.0.0");
+        assert!(SessionDescription::parse(&legacy).expect("parse").is_hold());
+    }
+
+    #[test]
+    fn test_sdes_crypto_negotiation() {
+        let line = "1 AES_CM_128_HMAC_SHA1_80 inline:PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR|2^20|1:32 UNENCRYPTED_SRTCP";
+        let crypto: CryptoAttribute = line.parse().expect("crypto");
+        assert_eq!(
+            crypto.inline_key(),
+            Some("PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR")
+        );
+        assert_eq!(crypto.to_string(), line);
+        assert!("1 AES_CM_128_HMAC_SHA1_80"
+            .parse::<CryptoAttribute>()
+            .is_err());
+
+        let offer = OFFER.replace("RTP/AVP 0 8 9 101", "RTP/SAVP 0 8 9 101").replace(
+            "a=ptime:20\r\n",
+            "a=ptime:20\r\n\
+             a=crypto:1 AES_CM_128_HMAC_SHA1_32 inline:PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR\r\n\
+             a=crypto:2 AES_CM_128_HMAC_SHA1_80 inline:PS1uQCVeeCFCanVmcjkpPywjNWhcYD0mXXtxaVBR\r\n",
+        );
+        let offer = SessionDescription::parse(&offer).expect("parse");
+        assert!(offer.audio().expect("audio").is_secure());
+        assert_eq!(offer.audio().expect("audio").crypto().len(), 2);
+
+        let mut ours = local(vec![codec("PCMU", 0)]);
+        ours.crypto = vec![CryptoAttribute {
+            tag: 1,
+            suite: "AES_CM_128_HMAC_SHA1_80".to_string(),
+            key_params: "inline:WVNfX19zZW1jdGwgKCkgewkyMjA7fQp9CnVubGVz".to_string(),
+            session_params: Vec::new(),
+        }];
+        let answer = offer.answer(&ours).expect("answer");
+        let crypto = answer.audio().expect("audio").crypto();
+        assert_eq!(
+            (crypto.len(), crypto[0].tag),
+            (1, 2),
+            "answers the tag it accepts"
+        );
+        assert_eq!(crypto[0].key_params, ours.crypto[0].key_params);
+        assert_eq!(answer.audio().expect("audio").protocol, "RTP/SAVP");
+
+        // Our own offer switches to SAVP, and no suite in common rejects SRTP.
+        assert!(ours.offer().audio().expect("audio").is_secure());
+        assert!(offer.answer(&local(vec![codec("PCMU", 0)])).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Command-line entrypoint for the media service.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{signal, sync::oneshot};
+use tonic::transport::Server;
+use tracing::{info, warn};
+
+use voip_common::{
+    init_telemetry, proto::media::media_service_server::MediaServiceServer, types::ServiceConfig,
+    EventBus, EventSink, Result, VoipError,
+};
+use voip_media::{
+    grpc::MediaGrpcService, tts::websocket::WebSocketTts, MediaConfig, MediaRelay,
+    DEFAULT_GRPC_PORT,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = ServiceConfig {
+        bind_addr: SocketAddr::from(([0, 0, 0, 0], DEFAULT_GRPC_PORT)).to_string(),
+        ..ServiceConfig::default()
+    };
+    init_telemetry("media-service", &config)
+        .map_err(|e| VoipError::Internal(format!("init telemetry failed: {}", e)))?;
+    info!("starting media service");
+
+    let media_config = MediaConfig::from_service_config(&config)?;
+    let mut relay = MediaRelay::from_config(&media_config)?;
+    match EventBus::connect(&config.nats_url).await {
+        Ok(bus) => {
+            let events: Arc<dyn EventSink> = Arc::new(bus.with_service_name("media"));
+            relay = relay.with_events(events);
+        }
+        Err(e) => warn!(error = %e, "event bus unavailable, media events disabled"),
+    }
+    let relay = Arc::new(relay);
+    let supervisor = tokio::spawn({
+        let relay = relay.clone();
+        async move { relay.supervise().await }
+    });
+
+    let grpc_addr: SocketAddr = config
+        .bind_addr
+        .parse()
+        .map_err(|e| VoipError::Config(format!("invalid bind_addr {}: {}", config.bind_addr, e)))?;
+    let mut service = MediaGrpcService::new(relay.clone(), &media_config);
+    if let Some(url) = &media_config.tts_url {
+        service = service.with_tts(Arc::new(WebSocketTts::new(url.clone())?));
+    }
+    let (grpc_shutdown, grpc_stop) = oneshot::channel::<()>();
+    let grpc = tokio::spawn(
+        Server::builder()
+            .add_service(MediaServiceServer::new(service))
+            .serve_with_shutdown(grpc_addr, async {
+                let _ = grpc_stop.await;
+            }),
+    );
+    info!(addr = %grpc_addr, "MediaService listening");
+
+    signal::ctrl_c()
+        .await
+        .map_err(|e| VoipError::Internal(format!("waiting for ctrl+c failed: {}", e)))?;
+    info!("ctrl+c received");
+    relay.stop();
+    let _ = grpc_shutdown.send(());
+    grpc.await
+        .map_err(|e| VoipError::Internal(format!("joining gRPC task failed: {}", e)))?
+        .map_err(|e| VoipError::Internal(format!("gRPC server failed: {}", e)))?;
+
+    supervisor
+        .await
+        .map_err(|e| VoipError::Internal(format!("joining media supervisor failed: {}", e)))??;
+    info!("media service stopped");
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! G.711 μ-law and A-law companding (ITU-T G.711).
+
+/// Bias added before μ-law segment search.
+const ULAW_BIAS: i32 = 0x84;
+/// Largest magnitude μ-law can represent once biased.
+const ULAW_CLIP: i32 = 32635;
+/// Upper bound of each A-law segment on the 13-bit magnitude.
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+
+/// Compress a 16-bit sample to μ-law.
+pub fn ulaw_encode(sample: i16) -> u8 {
+    let mut magnitude = i32::from(sample);
+    let sign = if magnitude < 0 {
+        magnitude = -magnitude;
+        0x80
+    } else {
+        0
+    };
+    let biased = magnitude.min(ULAW_CLIP) + ULAW_BIAS;
+    let exponent = 31 - biased.leading_zeros() as i32 - 7;
+    let mantissa = (biased >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) | mantissa) as u8
+}
+
+/// Expand a μ-law byte to a 16-bit sample.
+pub fn ulaw_decode(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = i32::from(byte & 0x0f);
+    let magnitude = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+    (if byte & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }) as i16
+}
+
+/// Compress a 16-bit sample to A-law.
+pub fn alaw_encode(sample: i16) -> u8 {
+    let mut magnitude = i32::from(sample) >> 3;
+    let mask = if magnitude >= 0 {
+        0xd5
+    } else {
+        magnitude = -magnitude - 1;
+        0x55
+    };
+    let Some(segment) = ALAW_SEGMENT_ENDS.iter().position(|end| magnitude <= *end) else {
+        return (0x7f ^ mask) as u8;
+    };
+    let shift = if segment < 2 { 1 } else { segment };
+    let code = ((segment as i32) << 4) | ((magnitude >> shift) & 0x0f);
+    (code ^ mask) as u8
+}
+
+/// Expand an A-law byte to a 16-bit sample.
+pub fn alaw_decode(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let segment = (byte & 0x70) >> 4;
+    let mut magnitude = i32::from(byte & 0x0f) << 4;
+    magnitude += match segment {
+        0 => 8,
+        _ => 0x108,
+    };
+    if segment > 1 {
+        magnitude <<= segment - 1;
+    }
+    (if byte & 0x80 != 0 {
+        magnitude
+    } else {
+        -magnitude
+    }) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silence_and_full_scale_map_to_the_reference_codes() {
+        assert_eq!(ulaw_encode(0), 0xff);
+        assert_eq!(alaw_encode(0), 0xd5);
+        assert_eq!(ulaw_decode(0x80), 32124);
+        assert_eq!(ulaw_decode(0x00), -32124);
+        assert_eq!(alaw_decode(0xaa), 32256);
+        assert_eq!(alaw_decode(0x2a), -32256);
+        assert_eq!(ulaw_encode(i16::MAX), 0x80);
+        assert_eq!(alaw_encode(i16::MIN), 0x2a);
+    }
+
+    #[test]
+    fn every_code_survives_a_decode_encode_round_trip() {
+        for code in 0..=255u8 {
+            // 0x7f and 0xff both decode to zero in μ-law.
+            if code != 0x7f {
+                assert_eq!(ulaw_encode(ulaw_decode(code)), code, "ulaw {:#x}", code);
+            }
+            assert_eq!(alaw_encode(alaw_decode(code)), code, "alaw {:#x}", code);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! G.722 sub-band ADPCM at 64 kbit/s (ITU-T G.722, mode 1).
+//!
+//! Each byte carries 6 bits of the 0–4 kHz band and 2 bits of the 4–8 kHz
+//! band for one pair of 16 kHz samples.
+
+/// Transmit and receive QMF coefficients.
+const QMF: [i32; 12] = [3, -11, 12, 32, -210, 951, 3876, -805, 362, -156, 53, -11];
+
+/// Lower-band quantizer decision levels.
+const Q6: [i32; 32] = [
+    0, 35, 72, 110, 150, 190, 233, 276, 323, 370, 422, 473, 530, 587, 650, 714, 786, 858, 940,
+    1023, 1121, 1219, 1339, 1458, 1612, 1765, 1980, 2195, 2557, 2919, 0, 0,
+];
+/// Lower-band codes for negative and positive differences.
+const ILN: [i32; 32] = [
+    0, 63, 62, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+    10, 9, 8, 7, 6, 5, 4, 0,
+];
+const ILP: [i32; 32] = [
+    0, 61, 60, 59, 58, 57, 56, 55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 41, 40, 39,
+    38, 37, 36, 35, 34, 33, 32, 0,
+];
+/// Lower-band inverse quantizer outputs, 6- and 4-bit.
+const QM6: [i32; 64] = [
+    -136, -136, -136, -136, -24808, -21904, -19008, -16704, -14984, -13512, -12280, -11192, -10232,
+    -9360, -8576, -7856, -7192, -6576, -6000, -5456, -4944, -4464, -4008, -3576, -3168, -2776,
+    -2400, -2032, -1688, -1360, -1040, -728, 24808, 21904, 19008, 16704, 14984, 13512, 12280,
+    11192, 10232, 9360, 8576, 7856, 7192, 6576, 6000, 5456, 4944, 4464, 4008, 3576, 3168, 2776,
+    2400, 2032, 1688, 1360, 1040, 728, 432, 136, -432, -136,
+];
+const QM4: [i32; 16] = [
+    0, -20456, -12896, -8968, -6288, -4240, -2584, -1200, 20456, 12896, 8968, 6288, 4240, 2584,
+    1200, 0,
+];
+/// Lower-band log scale factor adaptation.
+const RL42: [usize; 16] = [0, 7, 6, 5, 4, 3, 2, 1, 7, 6, 5, 4, 3, 2, 1, 0];
+const WL: [i32; 8] = [-60, -30, 58, 172, 334, 538, 1198, 3042];
+/// Inverse log table shared by both bands.
+const ILB: [i32; 32] = [
+    2048, 2093, 2139, 2186, 2233, 2282, 2332, 2383, 2435, 2489, 2543, 2599, 2656, 2714, 2774, 2834,
+    2896, 2960, 3025, 3091, 3158, 3228, 3298, 3371, 3444, 3520, 3597, 3676, 3756, 3838, 3922, 4008,
+];
+/// Higher-band quantizer, inverse quantizer and scale factor adaptation.
+const IHN: [i32; 3] = [0, 1, 0];
+const IHP: [i32; 3] = [0, 3, 2];
+const QM2: [i32; 4] = [-7408, -1616, 7408, 1616];
+const RH2: [usize; 4] = [2, 1, 2, 1];
+const WH: [i32; 3] = [0, -214, 798];
+
+fn saturate(value: i32) -> i32 {
+    value.clamp(i32::from(i16::MIN), i32::from(i16::MAX))
+}
+
+/// Adaptive predictor and scale factor of one sub-band.
+#[derive(Debug, Clone, Default)]
+struct Band {
+    s: i32,
+    sp: i32,
+    sz: i32,
+    r: [i32; 3],
+    a: [i32; 3],
+    ap: [i32; 3],
+    p: [i32; 3],
+    d: [i32; 7],
+    b: [i32; 7],
+    bp: [i32; 7],
+    nb: i32,
+    det: i32,
+}
+
+impl Band {
+    fn new(det: i32) -> Self {
+        Self {
+            det,
+            ..Self::default()
+        }
+    }
+
+    /// LOGSCL/SCALEL (lower band) or LOGSCH/SCALEH (higher band).
+    fn scale(&mut self, weight: i32, max_nb: i32, shift: i32) {
+        self.nb = ((self.nb * 127) >> 7) + weight;
+        self.nb = self.nb.clamp(0, max_nb);
+        let index = ((self.nb >> 6) & 31) as usize;
+        let exponent = shift - (self.nb >> 11);
+        let det = if exponent < 0 {
+            ILB[index] << -exponent
+        } else {
+            ILB[index] >> exponent
+        };
+        self.det = det << 2;
+    }
+
+    /// Block 4: reconstruct, adapt the pole and zero predictors, predict.
+    fn predict(&mut self, d: i32) {
+        self.d[0] = d;
+        self.r[0] = saturate(self.s + d);
+        self.p[0] = saturate(self.sz + d);
+
+        // UPPOL2
+        let sg: [i32; 3] = [self.p[0] >> 15, self.p[1] >> 15, self.p[2] >> 15];
+        let wd1 = saturate(self.a[1] << 2);
+        let wd2 = if sg[0] == sg[1] { -wd1 } else { wd1 }.min(32767);
+        let mut wd3 = (wd2 >> 7) + if sg[0] == sg[2] { 128 } else { -128 };
+        wd3 += (self.a[2] * 32512) >> 15;
+        self.ap[2] = wd3.clamp(-12288, 12288);
+
+        // UPPOL1
+        let wd1 = if sg[0] == sg[1] { 192 } else { -192 };
+        let wd2 = (self.a[1] * 32640) >> 15;
+        let limit = saturate(15360 - self.ap[2]);
+        self.ap[1] = saturate(wd1 + wd2).clamp(-limit, limit);
+
+        // UPZERO
+        let step = if d == 0 { 0 } else { 128 };
+        let sign = d >> 15;
+        for i in 1..7 {
+            let wd2 = if self.d[i] >> 15 == sign { step } else { -step };
+            let wd3 = (self.b[i] * 32640) >> 15;
+            self.bp[i] = saturate(wd2 + wd3);
+        }
+
+        // DELAYA
+        for i in (1..7).rev() {
+            self.d[i] = self.d[i - 1];
+            self.b[i] = self.bp[i];
+        }
+        for i in (1..3).rev() {
+            self.r[i] = self.r[i - 1];
+            self.p[i] = self.p[i - 1];
+            self.a[i] = self.ap[i];
+        }
+
+        // FILTEP, FILTEZ, PREDIC
+        let wd1 = (self.a[1] * saturate(self.r[1] + self.r[1])) >> 15;
+        let wd2 = (self.a[2] * saturate(self.r[2] + self.r[2])) >> 15;
+        self.sp = saturate(wd1 + wd2);
+        self.sz = saturate(
+            (1..7)
+                .map(|i| (self.b[i] * saturate(self.d[i] + self.d[i])) >> 15)
+                .sum(),
+        );
+        self.s = saturate(self.sp + self.sz);
+    }
+}
+
+/// G.722 encoder state; feed it 16 kHz samples in pairs.
+#[derive(Debug, Clone)]
+pub struct G722Encoder {
+    bands: [Band; 2],
+    qmf: [i32; 24],
+}
+
+impl Default for G722Encoder {
+    fn default() -> Self {
+        Self {
+            bands: [Band::new(32), Band::new(8)],
+            qmf: [0; 24],
+        }
+    }
+}
+
+impl G722Encoder {
+    /// Encode 16 kHz samples, one byte per pair; an odd last sample is dropped.
+    pub fn encode(&mut self, pcm: &[i16], out: &mut Vec<u8>) {
+        for pair in pcm.chunks_exact(2) {
+            self.qmf.copy_within(2.., 0);
+            self.qmf[22] = i32::from(pair[0]);
+            self.qmf[23] = i32::from(pair[1]);
+            let (mut even, mut odd) = (0, 0);
+            for i in 0..12 {
+                odd += self.qmf[2 * i] * QMF[i];
+                even += self.qmf[2 * i + 1] * QMF[11 - i];
+            }
+            let xlow = (even + odd) >> 14;
+            let xhigh = (even - odd) >> 14;
+
+            let [low, high] = &mut self.bands;
+
+            // Lower band: QUANTL, INVQAL, adaptation.
+            let el = saturate(xlow - low.s);
+            let magnitude = if el >= 0 { el } else { -(el + 1) };
+            let level = (1..30)
+                .find(|&i| magnitude < (Q6[i] * low.det) >> 12)
+                .unwrap_or(30);
+            let ilow = if el < 0 { ILN[level] } else { ILP[level] };
+            let ril = (ilow >> 2) as usize;
+            let dlow = (low.det * QM4[ril]) >> 15;
+            low.scale(WL[RL42[ril]], 18432, 8);
+            low.predict(dlow);
+
+            // Higher band: QUANTH, INVQAH, adaptation.
+            let eh = saturate(xhigh - high.s);
+            let magnitude = if eh >= 0 { eh } else { -(eh + 1) };
+            let mih = if magnitude >= (564 * high.det) >> 12 {
+                2
+            } else {
+                1
+            };
+            let ihigh = if eh < 0 { IHN[mih] } else { IHP[mih] };
+            let dhigh = (high.det * QM2[ihigh as usize]) >> 15;
+            high.scale(WH[RH2[ihigh as usize]], 22528, 10);
+            high.predict(dhigh);
+
+            out.push(((ihigh << 6) | ilow) as u8);
+        }
+    }
+}
+
+/// G.722 decoder state; yields two 16 kHz samples per byte.
+#[derive(Debug, Clone)]
+pub struct G722Decoder {
+    bands: [Band; 2],
+    qmf: [i32; 24],
+}
+
+impl Default for G722Decoder {
+    fn default() -> Self {
+        Self {
+            bands: [Band::new(32), Band::new(8)],
+            qmf: [0; 24],
+        }
+    }
+}
+
+impl G722Decoder {
+    /// Decode bytes to 16 kHz samples.
+    pub fn decode(&mut self, data: &[u8], out: &mut Vec<i16>) {
+        for &code in data {
+            let [low, high] = &mut self.bands;
+            let ilow = usize::from(code & 0x3f);
+            let ihigh = usize::from(code >> 6);
+
+            // Lower band: INVQBL with all 6 bits, adaptation from the top 4.
+            let rlow = (low.s + ((low.det * QM6[ilow]) >> 15)).clamp(-16384, 16383);
+            let ril = ilow >> 2;
+            let dlow = (low.det * QM4[ril]) >> 15;
+            low.scale(WL[RL42[ril]], 18432, 8);
+            low.predict(dlow);
+
+            // Higher band.
+            let dhigh = (high.det * QM2[ihigh]) >> 15;
+            let rhigh = (dhigh + high.s).clamp(-16384, 16383);
+            high.scale(WH[RH2[ihigh]], 22528, 10);
+            high.predict(dhigh);
+
+            self.qmf.copy_within(2.., 0);
+            self.qmf[22] = rlow + rhigh;
+            self.qmf[23] = rlow - rhigh;
+            let (mut first, mut second) = (0, 0);
+            for i in 0..12 {
+                second += self.qmf[2 * i] * QMF[i];
+                first += self.qmf[2 * i + 1] * QMF[11 - i];
+            }
+            out.push(saturate(first >> 11) as i16);
+            out.push(saturate(second >> 11) as i16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_preserves_a_tone() {
+        let input: Vec<i16> = (0..3200)
+            .map(|n| {
+                (8000.0 * (2.0 * std::f64::consts::PI * 1000.0 * n as f64 / 16000.0).sin()) as i16
+            })
+            .collect();
+        let mut encoded = Vec::new();
+        G722Encoder::default().encode(&input, &mut encoded);
+        assert_eq!(encoded.len(), input.len() / 2);
+        let mut decoded = Vec::new();
+        G722Decoder::default().decode(&encoded, &mut decoded);
+        assert_eq!(decoded.len(), input.len());
+
+        // The QMF pair delays the signal by a couple of dozen samples.
+        let settled = 800..input.len() - 100;
+        let snr = (0..64)
+            .map(|lag| {
+                let (signal, noise) = settled.clone().fold((0.0, 0.0), |(s, n), i| {
+                    let x = f64::from(input[i - lag]);
+                    let e = f64::from(decoded[i]) - x;
+                    (s + x * x, n + e * e)
+                });
+                10.0 * (signal / noise.max(1.0)).log10()
+            })
+            .fold(f64::MIN, f64::max);
+        assert!(snr > 20.0, "SNR {:.1} dB", snr);
+    }
+}
@@ -0,0 +1,139 @@
+//! Audio codecs the relay can transcode between, and their PCM conversions.
+
+pub mod g711;
+pub mod g722;
+pub mod resample;
+
+pub use g722::{G722Decoder, G722Encoder};
+pub use resample::Resampler;
+
+/// Encodings the transcoder understands; all mono.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioCodec {
+    /// G.711 μ-law.
+    Pcmu,
+    /// G.711 A-law.
+    Pcma,
+    /// G.722 at 64 kbit/s.
+    G722,
+    /// Linear 16-bit big-endian PCM.
+    L16 {
+        /// 8000 or 16000.
+        sample_rate: u32,
+    },
+}
+
+impl AudioCodec {
+    /// Resolve an SDP rtpmap encoding; `None` for anything we cannot transcode.
+    pub fn from_sdp(name: &str, clock_rate: u32, channels: u32) -> Option<Self> {
+        if channels > 1 {
+            return None;
+        }
+        match (name.to_ascii_uppercase().as_str(), clock_rate) {
+            ("PCMU", 8000) => Some(Self::Pcmu),
+            ("PCMA", 8000) => Some(Self::Pcma),
+            ("G722", 8000) => Some(Self::G722),
+            ("L16", 8000 | 16000) => Some(Self::L16 {
+                sample_rate: clock_rate,
+            }),
+            _ => None,
+        }
+    }
+
+    /// SDP encoding name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pcmu => "PCMU",
+            Self::Pcma => "PCMA",
+            Self::G722 => "G722",
+            Self::L16 { .. } => "L16",
+        }
+    }
+
+    /// Rate of the PCM the codec carries.
+    pub fn sample_rate(self) -> u32 {
+        match self {
+            Self::Pcmu | Self::Pcma => 8000,
+            Self::G722 => 16000,
+            Self::L16 { sample_rate } => sample_rate,
+        }
+    }
+
+    /// RTP timestamp rate; G.722 keeps 8000 for historical reasons (RFC 3551 §4.5.2).
+    pub fn clock_rate(self) -> u32 {
+        match self {
+            Self::Pcmu | Self::Pcma | Self::G722 => 8000,
+            Self::L16 { sample_rate } => sample_rate,
+        }
+    }
+
+    /// Fresh decoder state.
+    pub fn decoder(self) -> Decoder {
+        match self {
+            Self::G722 => Decoder::G722(Box::default()),
+            codec => Decoder::Stateless(codec),
+        }
+    }
+
+    /// Fresh encoder state.
+    pub fn encoder(self) -> Encoder {
+        match self {
+            Self::G722 => Encoder::G722(Box::default()),
+            codec => Encoder::Stateless(codec),
+        }
+    }
+}
+
+/// Payload to PCM at the codec's sample rate.
+#[derive(Debug, Clone)]
+pub enum Decoder {
+    /// G.711 and L16 need no state.
+    Stateless(AudioCodec),
+    /// G.722 adaptive state.
+    G722(Box<G722Decoder>),
+}
+
+impl Decoder {
+    /// Append the samples carried by `payload` to `out`.
+    pub fn decode(&mut self, payload: &[u8], out: &mut Vec<i16>) {
+        match self {
+            Self::Stateless(AudioCodec::Pcmu) => {
+                out.extend(payload.iter().copied().map(g711::ulaw_decode))
+            }
+            Self::Stateless(AudioCodec::Pcma) => {
+                out.extend(payload.iter().copied().map(g711::alaw_decode))
+            }
+            Self::Stateless(_) => out.extend(
+                payload
+                    .chunks_exact(2)
+                    .map(|pair| i16::from_be_bytes([pair[0], pair[1]])),
+            ),
+            Self::G722(decoder) => decoder.decode(payload, out),
+        }
+    }
+}
+
+/// PCM at the codec's sample rate to payload.
+#[derive(Debug, Clone)]
+pub enum Encoder {
+    /// G.711 and L16 need no state.
+    Stateless(AudioCodec),
+    /// G.722 adaptive state.
+    G722(Box<G722Encoder>),
+}
+
+impl Encoder {
+    /// Append the encoding of `pcm` to `out`.
+    pub fn encode(&mut self, pcm: &[i16], out: &mut Vec<u8>) {
+        match self {
+            Self::Stateless(AudioCodec::Pcmu) => {
+                out.extend(pcm.iter().copied().map(g711::ulaw_encode))
+            }
+            Self::Stateless(AudioCodec::Pcma) => {
+                out.extend(pcm.iter().copied().map(g711::alaw_encode))
+            }
+            Self::Stateless(_) => out.extend(pcm.iter().flat_map(|sample| sample.to_be_bytes())),
+            Self::G722(encoder) => encoder.encode(pcm, out),
+        }
+    }
+}
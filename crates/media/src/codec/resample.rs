@@ -0,0 +1,146 @@
+//! Conversion between 8 kHz and 16 kHz with a half-band low-pass filter.
+
+use std::collections::VecDeque;
+
+use voip_common::{Result, VoipError};
+
+/// Taps of the anti-imaging/anti-aliasing filter, at 16 kHz.
+const TAPS: usize = 31;
+
+/// Windowed-sinc low-pass at a quarter of 16 kHz, with unity DC gain.
+fn half_band() -> [f64; TAPS] {
+    let centre = (TAPS / 2) as f64;
+    let mut taps = [0.0; TAPS];
+    for (n, tap) in taps.iter_mut().enumerate() {
+        let x = n as f64 - centre;
+        let sinc = if x == 0.0 {
+            0.5
+        } else {
+            (std::f64::consts::FRAC_PI_2 * x).sin() / (std::f64::consts::PI * x)
+        };
+        let window =
+            0.54 - 0.46 * (2.0 * std::f64::consts::PI * n as f64 / (TAPS - 1) as f64).cos();
+        *tap = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    taps.iter_mut().for_each(|tap| *tap /= sum);
+    taps
+}
+
+/// Streaming sample-rate converter; keeps filter state across calls.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from: u32,
+    to: u32,
+    taps: [f64; TAPS],
+    /// Most recent samples at 16 kHz, newest last.
+    history: VecDeque<f64>,
+}
+
+impl Resampler {
+    /// Convert from `from` Hz to `to` Hz; only 8000 and 16000 are supported.
+    pub fn new(from: u32, to: u32) -> Result<Self> {
+        match (from, to) {
+            (8000, 16000) | (16000, 8000) => {}
+            (from, to) if from == to => {}
+            _ => {
+                return Err(VoipError::Media(format!(
+                    "cannot resample {} Hz to {} Hz",
+                    from, to
+                )))
+            }
+        }
+        Ok(Self {
+            from,
+            to,
+            taps: half_band(),
+            history: VecDeque::from(vec![0.0; TAPS]),
+        })
+    }
+
+    /// Output rate.
+    pub fn output_rate(&self) -> u32 {
+        self.to
+    }
+
+    /// Resample a block; the output holds `input.len() * to / from` samples.
+    pub fn process(&mut self, input: &[i16]) -> Vec<i16> {
+        if self.from == self.to {
+            return input.to_vec();
+        }
+        if self.from < self.to {
+            // Zero-stuff to 16 kHz, filter, and restore the halved level.
+            let mut out = Vec::with_capacity(input.len() * 2);
+            for &sample in input {
+                self.push(f64::from(sample) * 2.0);
+                out.push(self.filter());
+                self.push(0.0);
+                out.push(self.filter());
+            }
+            out
+        } else {
+            let mut out = Vec::with_capacity(input.len() / 2);
+            for (i, &sample) in input.iter().enumerate() {
+                self.push(f64::from(sample));
+                if i % 2 == 1 {
+                    out.push(self.filter());
+                }
+            }
+            out
+        }
+    }
+
+    fn push(&mut self, sample: f64) {
+        self.history.pop_front();
+        self.history.push_back(sample);
+    }
+
+    fn filter(&self) -> i16 {
+        let value: f64 = self
+            .history
+            .iter()
+            .zip(self.taps.iter().rev())
+            .map(|(x, tap)| x * tap)
+            .sum();
+        value
+            .round()
+            .clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(hz: f64, rate: f64, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|n| (10000.0 * (2.0 * std::f64::consts::PI * hz * n as f64 / rate).sin()) as i16)
+            .collect()
+    }
+
+    fn rms(samples: &[i16]) -> f64 {
+        let sum: f64 = samples.iter().map(|&s| f64::from(s).powi(2)).sum();
+        (sum / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn keeps_voice_band_level_and_rejects_images() {
+        let mut up = Resampler::new(8000, 16000).expect("resampler");
+        let upsampled = up.process(&tone(1000.0, 8000.0, 800));
+        assert_eq!(upsampled.len(), 1600);
+        let level = rms(&upsampled[100..]) / rms(&tone(1000.0, 8000.0, 800));
+        assert!((level - 1.0).abs() < 0.05, "{}", level);
+
+        // 6 kHz cannot be represented at 8 kHz and must not alias down to 2 kHz.
+        let mut down = Resampler::new(16000, 8000).expect("resampler");
+        let aliased = down.process(&tone(6000.0, 16000.0, 1600));
+        assert_eq!(aliased.len(), 800);
+        assert!(
+            rms(&aliased[50..]) < 10000.0 * 0.05,
+            "{}",
+            rms(&aliased[50..])
+        );
+
+        assert!(Resampler::new(8000, 44100).is_err());
+    }
+}
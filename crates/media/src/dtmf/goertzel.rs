@@ -0,0 +1,178 @@
+//! In-band DTMF detection with the Goertzel algorithm on 8 kHz PCM.
+//!
+//! Audio is cut into 205-sample blocks (25.6 ms). A block holds a digit when
+//! one row and one column tone stand out from their groups, their levels are
+//! within the allowed twist, and together they carry most of the block's
+//! energy. A digit must span two blocks to count, which also rejects speech.
+
+use std::time::Duration;
+
+use super::Digit;
+
+/// Samples per analysis block at 8 kHz.
+const BLOCK: usize = 205;
+/// Sample rate the detector runs at.
+const SAMPLE_RATE: f64 = 8000.0;
+/// Low-group and high-group frequencies of the DTMF keypad.
+const ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const KEYPAD: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+/// Quietest block analysed, as a mean square (about −40 dBm0).
+const MIN_ENERGY: f64 = 100.0 * 100.0;
+/// Share of the block energy the two tones must carry.
+const MIN_PURITY: f64 = 0.7;
+/// Margin of each tone over the rest of its group (6 dB).
+const GROUP_MARGIN: f64 = 4.0;
+/// Column tone level relative to the row tone: 8 dB below (normal twist) to 4 dB above.
+const MIN_TWIST: f64 = 0.158;
+const MAX_TWIST: f64 = 2.512;
+/// Blocks a digit must persist for before it counts.
+const MIN_BLOCKS: u32 = 2;
+
+/// Power of `frequency` in `samples`, normalised so a full-block sine of
+/// amplitude `a` yields `a² / 4`.
+fn goertzel(samples: &[f64], frequency: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * std::f64::consts::PI * frequency / SAMPLE_RATE).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for &x in samples {
+        let s0 = x + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let n = samples.len() as f64;
+    (s1 * s1 + s2 * s2 - coefficient * s1 * s2) / (n * n)
+}
+
+/// Strongest tone of a group, if it clears the rest by the group margin.
+fn dominant(samples: &[f64], group: &[f64; 4]) -> Option<(usize, f64)> {
+    let powers = group.map(|frequency| goertzel(samples, frequency));
+    let (index, peak) = powers
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let clear = powers
+        .iter()
+        .enumerate()
+        .all(|(i, &power)| i == index || power * GROUP_MARGIN <= peak);
+    clear.then_some((index, peak))
+}
+
+/// Digit sounding in one block, if any.
+fn classify(samples: &[f64]) -> Option<char> {
+    let energy = samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64;
+    if energy < MIN_ENERGY {
+        return None;
+    }
+    let (row, row_power) = dominant(samples, &ROWS)?;
+    let (column, column_power) = dominant(samples, &COLUMNS)?;
+    let twist = column_power / row_power;
+    // A sine's mean square is twice its normalised Goertzel power.
+    let purity = 2.0 * (row_power + column_power) / energy;
+    ((MIN_TWIST..=MAX_TWIST).contains(&twist) && purity >= MIN_PURITY)
+        .then_some(KEYPAD[row][column])
+}
+
+/// Streaming detector; feed it decoded 8 kHz audio in any chunk size.
+#[derive(Debug, Clone, Default)]
+pub struct InBandDetector {
+    block: Vec<f64>,
+    /// Digit of the latest blocks and how many blocks in a row held it.
+    current: Option<(char, u32)>,
+}
+
+impl InBandDetector {
+    /// Analyse `pcm`; returns a digit once its tone stops.
+    pub fn process(&mut self, pcm: &[i16]) -> Option<Digit> {
+        let mut ended = None;
+        for &sample in pcm {
+            self.block.push(f64::from(sample));
+            if self.block.len() < BLOCK {
+                continue;
+            }
+            let digit = classify(&self.block);
+            self.block.clear();
+            match (&mut self.current, digit) {
+                (Some((current, blocks)), Some(digit)) if *current == digit => *blocks += 1,
+                (current, digit) => {
+                    if let Some((digit, blocks)) = current.take().filter(|(_, n)| *n >= MIN_BLOCKS)
+                    {
+                        ended = Some(Digit {
+                            digit,
+                            duration: Duration::from_secs_f64(
+                                f64::from(blocks) * BLOCK as f64 / SAMPLE_RATE,
+                            ),
+                            in_band: true,
+                        });
+                    }
+                    *current = digit.map(|digit| (digit, 1));
+                }
+            }
+        }
+        ended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::g711;
+
+    /// μ-law round trip of `tones` at `amplitude` each, for `ms` milliseconds.
+    fn tone(tones: &[f64], amplitude: f64, ms: usize) -> Vec<i16> {
+        (0..ms * 8)
+            .map(|n| {
+                let t = n as f64 / SAMPLE_RATE;
+                let x: f64 = tones
+                    .iter()
+                    .map(|f| amplitude * (2.0 * std::f64::consts::PI * f * t).sin())
+                    .sum();
+                g711::ulaw_decode(g711::ulaw_encode(x as i16))
+            })
+            .collect()
+    }
+
+    fn detect(audio: &[i16]) -> Vec<Digit> {
+        let mut detector = InBandDetector::default();
+        audio
+            .chunks(160)
+            .filter_map(|chunk| detector.process(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn detects_each_key_once_and_ignores_single_tones() {
+        let mut audio = Vec::new();
+        for (row, column) in [(697.0, 1209.0), (941.0, 1336.0), (941.0, 1633.0)] {
+            audio.extend(tone(&[row, column], 4000.0, 100));
+            audio.extend(tone(&[], 0.0, 60));
+        }
+        let digits: String = detect(&audio).iter().map(|d| d.digit).collect();
+        assert_eq!(digits, "10D");
+        let held = detect(&[tone(&[852.0, 1477.0], 4000.0, 200), tone(&[], 0.0, 60)].concat());
+        assert_eq!(held.len(), 1);
+        assert!(held[0].in_band);
+        assert!(
+            held[0].duration >= Duration::from_millis(150),
+            "{:?}",
+            held[0].duration
+        );
+
+        // A lone 1 kHz tone, a too-short burst and strong twist are not digits.
+        assert!(detect(&[tone(&[1000.0], 8000.0, 300), tone(&[], 0.0, 60)].concat()).is_empty());
+        assert!(
+            detect(&[tone(&[697.0, 1209.0], 4000.0, 30), tone(&[], 0.0, 60)].concat()).is_empty()
+        );
+        let twisted = tone(&[697.0], 8000.0, 200)
+            .iter()
+            .zip(tone(&[1209.0], 800.0, 200))
+            .map(|(a, b)| a.saturating_add(b))
+            .collect::<Vec<_>>();
+        assert!(detect(&[twisted, tone(&[], 0.0, 60)].concat()).is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+//! DTMF digits carried as RFC 4733 telephone-events or as in-band tones.
+//!
+//! A digit is reported once, when it ends, so its duration is known. Event
+//! packets repeat the start timestamp of their event, which is how updates
+//! and the retransmitted end packets are told apart from the next digit.
+
+pub mod goertzel;
+
+use std::time::Duration;
+
+use voip_common::{Result, VoipError};
+
+use crate::{codec::AudioCodec, rtp::RtpPacket, transcode::PayloadFormat};
+
+pub use goertzel::InBandDetector;
+
+/// Event codes 0–15 (RFC 4733 §3.2) in order.
+const DIGITS: [char; 16] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '*', '#', 'A', 'B', 'C', 'D',
+];
+
+/// Times the final packet of an event is sent (RFC 4733 §2.5.1.4).
+pub const END_RETRANSMISSIONS: usize = 3;
+
+/// Level written in generated events, in −dBm0.
+pub const DEFAULT_VOLUME: u8 = 10;
+
+/// Largest duration one event packet can carry.
+const MAX_SEGMENT: u32 = u16::MAX as u32;
+
+/// Digit for an event code.
+pub fn digit_for_event(event: u8) -> Option<char> {
+    DIGITS.get(usize::from(event)).copied()
+}
+
+/// Event code for a digit; `a`–`d` are accepted in lower case.
+pub fn event_for_digit(digit: char) -> Option<u8> {
+    let digit = digit.to_ascii_uppercase();
+    DIGITS
+        .iter()
+        .position(|&d| d == digit)
+        .map(|event| event as u8)
+}
+
+/// Payload of a telephone-event packet (RFC 4733 §2.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelephoneEvent {
+    /// Event code.
+    pub event: u8,
+    /// Set on the final packets of the event.
+    pub end: bool,
+    /// Power level in −dBm0, 0 to 63.
+    pub volume: u8,
+    /// Duration so far, in RTP clock units.
+    pub duration: u16,
+}
+
+impl TelephoneEvent {
+    /// Payload length.
+    pub const LEN: usize = 4;
+
+    /// Decode the first event of a payload.
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        if payload.len() < Self::LEN {
+            return Err(VoipError::Media(format!(
+                "truncated telephone-event: {} bytes",
+                payload.len()
+            )));
+        }
+        Ok(Self {
+            event: payload[0],
+            end: payload[1] & 0x80 != 0,
+            volume: payload[1] & 0x3f,
+            duration: u16::from_be_bytes([payload[2], payload[3]]),
+        })
+    }
+
+    /// Encode as a payload.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let [high, low] = self.duration.to_be_bytes();
+        let end = if self.end { 0x80 } else { 0 };
+        [self.event, end | (self.volume & 0x3f), high, low]
+    }
+}
+
+/// One packet of a generated event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventPacket {
+    /// Offset of the packet's RTP timestamp from the event start.
+    pub timestamp_offset: u32,
+    /// Marker bit; set on the first packet of the event.
+    pub marker: bool,
+    /// The payload.
+    pub event: TelephoneEvent,
+}
+
+/// Packets announcing `digit` for `duration`, one per `ptime`.
+///
+/// Events longer than a packet can express are split into segments with
+/// their own timestamps (RFC 4733 §2.5.1.3). The final packet is repeated
+/// [`END_RETRANSMISSIONS`] times.
+pub fn generate(
+    digit: char,
+    duration: Duration,
+    clock_rate: u32,
+    ptime: Duration,
+) -> Result<Vec<EventPacket>> {
+    let event = event_for_digit(digit)
+        .ok_or_else(|| VoipError::Validation(format!("{:?} is not a DTMF digit", digit)))?;
+    let units = |d: Duration| (d.as_secs_f64() * f64::from(clock_rate)).round() as u32;
+    let total = units(duration).max(1);
+    let step = units(ptime).max(1);
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    while offset < total {
+        let segment = (total - offset).min(MAX_SEGMENT);
+        let last = offset + segment == total;
+        let mut elapsed = step.min(segment);
+        loop {
+            let end = last && elapsed == segment;
+            let repeats = if end { END_RETRANSMISSIONS } else { 1 };
+            for _ in 0..repeats {
+                packets.push(EventPacket {
+                    timestamp_offset: offset,
+                    marker: offset == 0 && packets.is_empty(),
+                    event: TelephoneEvent {
+                        event,
+                        end,
+                        volume: DEFAULT_VOLUME,
+                        duration: elapsed as u16,
+                    },
+                });
+            }
+            if elapsed == segment {
+                break;
+            }
+            elapsed = (elapsed + step).min(segment);
+        }
+        offset += segment;
+    }
+    Ok(packets)
+}
+
+/// A digit seen on a leg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Digit {
+    /// `0`–`9`, `*`, `#` or `A`–`D`.
+    pub digit: char,
+    /// How long it was held.
+    pub duration: Duration,
+    /// Detected from tones in the audio rather than telephone-events.
+    pub in_band: bool,
+}
+
+/// An event still being received.
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    timestamp: u32,
+    event: u8,
+    /// Units covered by earlier segments of a long event.
+    base: u32,
+    duration: u16,
+}
+
+impl Pending {
+    fn digit(&self, clock_rate: u32) -> Option<Digit> {
+        let units = self.base + u32::from(self.duration);
+        Some(Digit {
+            digit: digit_for_event(self.event)?,
+            duration: Duration::from_secs_f64(f64::from(units) / f64::from(clock_rate.max(1))),
+            in_band: false,
+        })
+    }
+}
+
+/// Turns telephone-event packets into digits, each reported once.
+#[derive(Debug, Clone, Default)]
+pub struct EventReceiver {
+    current: Option<Pending>,
+    /// Timestamp of the last event that ended, to ignore its retransmissions.
+    ended: Option<u32>,
+}
+
+impl EventReceiver {
+    /// Account for a packet; returns the digit that just ended, if any.
+    ///
+    /// A digit whose end packets were all lost is reported when the next
+    /// event starts.
+    pub fn on_packet(
+        &mut self,
+        timestamp: u32,
+        event: TelephoneEvent,
+        clock_rate: u32,
+    ) -> Option<Digit> {
+        if self.ended == Some(timestamp) {
+            return None;
+        }
+        let mut finished = None;
+        let pending = match self.current {
+            Some(current) if current.timestamp == timestamp => Pending {
+                duration: current.duration.max(event.duration),
+                ..current
+            },
+            Some(current)
+                if current.event == event.event
+                    && u32::from(current.duration) == MAX_SEGMENT
+                    && timestamp == current.timestamp.wrapping_add(MAX_SEGMENT) =>
+            {
+                Pending {
+                    timestamp,
+                    base: current.base + MAX_SEGMENT,
+                    duration: event.duration,
+                    ..current
+                }
+            }
+            current => {
+                finished = current;
+                Pending {
+                    timestamp,
+                    event: event.event,
+                    base: 0,
+                    duration: event.duration,
+                }
+            }
+        };
+        if event.end {
+            self.current = None;
+            self.ended = Some(timestamp);
+            return pending.digit(clock_rate);
+        }
+        self.current = Some(pending);
+        finished.and_then(|digit| digit.digit(clock_rate))
+    }
+}
+
+/// Digit detection for one leg: telephone-events when negotiated, tones in
+/// G.711 audio otherwise.
+#[derive(Debug, Clone)]
+pub struct DigitDetector {
+    format: PayloadFormat,
+    events: EventReceiver,
+    in_band: InBandDetector,
+    pcm: Vec<i16>,
+}
+
+impl Default for DigitDetector {
+    fn default() -> Self {
+        Self::new(PayloadFormat {
+            payload_type: 0,
+            codec: AudioCodec::Pcmu,
+            events: Some(101),
+        })
+    }
+}
+
+impl DigitDetector {
+    /// Detector for a leg carrying `format`.
+    pub fn new(format: PayloadFormat) -> Self {
+        Self {
+            format,
+            events: EventReceiver::default(),
+            in_band: InBandDetector::default(),
+            pcm: Vec::new(),
+        }
+    }
+
+    /// Format the leg carries.
+    pub fn format(&self) -> PayloadFormat {
+        self.format
+    }
+
+    /// Follow a renegotiation of the leg.
+    pub fn set_format(&mut self, format: PayloadFormat) {
+        if format != self.format {
+            *self = Self::new(format);
+        }
+    }
+
+    /// Inspect an RTP packet received on the leg.
+    pub fn on_packet(&mut self, packet: &RtpPacket<'_>) -> Option<Digit> {
+        if Some(packet.payload_type) == self.format.events {
+            let event = TelephoneEvent::parse(&packet.payload).ok()?;
+            return self
+                .events
+                .on_packet(packet.timestamp, event, self.format.codec.clock_rate());
+        }
+        let g711 = matches!(self.format.codec, AudioCodec::Pcmu | AudioCodec::Pcma);
+        if packet.payload_type != self.format.payload_type || self.format.events.is_some() || !g711
+        {
+            return None;
+        }
+        self.pcm.clear();
+        self.format
+            .codec
+            .decoder()
+            .decode(&packet.payload, &mut self.pcm);
+        self.in_band.process(&self.pcm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(packets: &[EventPacket]) -> Vec<Digit> {
+        let mut receiver = EventReceiver::default();
+        packets
+            .iter()
+            .filter_map(|packet| {
+                receiver.on_packet(1000 + packet.timestamp_offset, packet.event, 8000)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generated_events_round_trip_once_despite_end_retransmissions() {
+        let packets = generate(
+            '#',
+            Duration::from_millis(100),
+            8000,
+            Duration::from_millis(20),
+        )
+        .expect("generate");
+        assert_eq!(packets.len(), 4 + END_RETRANSMISSIONS);
+        assert!(packets[0].marker && !packets[1].marker);
+        assert_eq!(packets[0].event.duration, 160);
+        assert!(packets[4..]
+            .iter()
+            .all(|p| p.event.end && p.event.duration == 800));
+        let bytes = packets[4].event.to_bytes();
+        assert_eq!(bytes, [11, 0x80 | DEFAULT_VOLUME, 0x03, 0x20]);
+        assert_eq!(
+            TelephoneEvent::parse(&bytes).expect("parse"),
+            packets[4].event
+        );
+
+        assert_eq!(
+            receive(&packets),
+            [Digit {
+                digit: '#',
+                duration: Duration::from_millis(100),
+                in_band: false,
+            }]
+        );
+        assert!(generate(
+            'x',
+            Duration::from_millis(100),
+            8000,
+            Duration::from_millis(20)
+        )
+        .is_err());
+        assert!(TelephoneEvent::parse(&[1, 2]).is_err());
+    }
+
+    #[test]
+    fn long_events_span_segments_and_lost_ends_surface_with_the_next_digit() {
+        let packets = generate(
+            '5',
+            Duration::from_secs(10),
+            8000,
+            Duration::from_millis(50),
+        )
+        .expect("generate");
+        let segments: Vec<_> = packets.iter().map(|p| p.timestamp_offset).collect();
+        assert_eq!((segments[0], *segments.last().expect("last")), (0, 65535));
+        let digits = receive(&packets);
+        assert_eq!(digits.len(), 1);
+        assert_eq!(digits[0].duration, Duration::from_secs(10));
+
+        // Drop every end packet of the first digit.
+        let mut receiver = EventReceiver::default();
+        let mut digits = Vec::new();
+        for (timestamp, digit) in [(0, '1'), (2000, '2')] {
+            let packets = generate(
+                digit,
+                Duration::from_millis(60),
+                8000,
+                Duration::from_millis(20),
+            )
+            .expect("generate");
+            let kept = packets.iter().filter(|p| digit == '2' || !p.event.end);
+            digits.extend(kept.filter_map(|p| receiver.on_packet(timestamp, p.event, 8000)));
+        }
+        let digits: Vec<_> = digits.iter().map(|d| (d.digit, d.duration)).collect();
+        assert_eq!(
+            digits,
+            [
+                ('1', Duration::from_millis(40)),
+                ('2', Duration::from_millis(60))
+            ]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! ITU-T G.107 E-model: transmission rating R and estimated MOS.
+//!
+//! Only the network-dependent impairments vary; the electrical and acoustic
+//! parameters keep their G.107 Table 3 defaults, which give R = 93.2 on a
+//! perfect G.711 path.
+
+/// Basic signal-to-noise ratio Ro with default SLR, noise and room levels.
+const RO: f64 = 94.77;
+/// Simultaneous impairment factor Is with default loudness and quantizing distortion.
+const IS: f64 = 1.41;
+/// Talker echo loudness rating (dB).
+const TELR: f64 = 65.0;
+/// Weighted echo path loss (dB).
+const WEPL: f64 = 110.0;
+
+/// Equipment impairment of a codec (ITU-T G.113 Appendix I).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodecImpairment {
+    /// Equipment impairment factor Ie without loss.
+    pub ie: f64,
+    /// Packet-loss robustness factor Bpl.
+    pub bpl: f64,
+    /// Packetization and look-ahead delay added to the path, in ms.
+    pub delay_ms: f64,
+}
+
+impl CodecImpairment {
+    /// G.711 with packet loss concealment, 20 ms packets.
+    pub const G711: Self = Self {
+        ie: 0.0,
+        bpl: 25.1,
+        delay_ms: 20.0,
+    };
+
+    /// Values for a codec by SDP encoding name; unknown codecs are scored as G.711.
+    ///
+    /// G.722 is rated on the narrowband scale, where it does no worse than G.711.
+    pub fn for_codec(name: &str) -> Self {
+        match name.to_ascii_uppercase().as_str() {
+            "G729" | "G729A" => Self {
+                ie: 11.0,
+                bpl: 19.0,
+                delay_ms: 25.0,
+            },
+            "ILBC" => Self {
+                ie: 11.0,
+                bpl: 32.0,
+                delay_ms: 30.0,
+            },
+            _ => Self::G711,
+        }
+    }
+}
+
+/// Network conditions over a measurement interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Conditions {
+    /// Mouth-to-ear delay in ms, codec and jitter buffer included.
+    pub one_way_delay_ms: f64,
+    /// Packets lost in the network or discarded as late, in percent.
+    pub packet_loss_percent: f64,
+    /// BurstR: 1 for random loss, above 1 when losses cluster.
+    pub burst_ratio: f64,
+}
+
+/// Rating and opinion score derived from it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// Transmission rating R, 0–100.
+    pub r_factor: f64,
+    /// Estimated conversational MOS, 1–4.5.
+    pub mos: f64,
+}
+
+impl Score {
+    /// Score for a codec under `conditions`.
+    pub fn estimate(codec: CodecImpairment, conditions: &Conditions) -> Self {
+        let r = r_factor(codec, conditions);
+        Self {
+            r_factor: r,
+            mos: mos(r),
+        }
+    }
+}
+
+/// R = Ro − Is − Id − Ie,eff (advantage factor A = 0).
+pub fn r_factor(codec: CodecImpairment, conditions: &Conditions) -> f64 {
+    let r =
+        RO - IS - delay_impairment(conditions.one_way_delay_ms) - effective_ie(codec, conditions);
+    r.clamp(0.0, 100.0)
+}
+
+/// Id: talker echo, listener echo and absolute delay impairments (G.107 §7.3).
+pub fn delay_impairment(delay_ms: f64) -> f64 {
+    let t = delay_ms.max(0.0);
+
+    let terv =
+        TELR - 40.0 * ((1.0 + t / 10.0) / (1.0 + t / 150.0)).log10() + 6.0 * (-0.3 * t * t).exp();
+    let roe_re = RO - (80.0 + 2.5 * (terv - 14.0));
+    let idte = (roe_re / 2.0 + (roe_re * roe_re / 4.0 + 100.0).sqrt() - 1.0) * (1.0 - (-t).exp());
+
+    let rle = 10.5 * (WEPL + 7.0) * (2.0 * t + 1.0).powf(-0.25);
+    let idle = (RO - rle) / 2.0 + ((RO - rle).powi(2) / 4.0 + 169.0).sqrt();
+
+    let idd = if t <= 100.0 {
+        0.0
+    } else {
+        let x = (t / 100.0).log2();
+        25.0 * ((1.0 + x.powi(6)).powf(1.0 / 6.0) - 3.0 * (1.0 + (x / 3.0).powi(6)).powf(1.0 / 6.0)
+            + 2.0)
+    };
+    idte + idle + idd
+}
+
+/// Ie,eff = Ie + (95 − Ie) · Ppl / (Ppl / BurstR + Bpl).
+pub fn effective_ie(codec: CodecImpairment, conditions: &Conditions) -> f64 {
+    let ppl = conditions.packet_loss_percent.clamp(0.0, 100.0);
+    if ppl == 0.0 {
+        return codec.ie;
+    }
+    let burst_ratio = if conditions.burst_ratio > 0.0 {
+        conditions.burst_ratio
+    } else {
+        1.0
+    };
+    codec.ie + (95.0 - codec.ie) * ppl / (ppl / burst_ratio + codec.bpl)
+}
+
+/// MOS from R (G.107 Annex B).
+pub fn mos(r: f64) -> f64 {
+    if r <= 0.0 {
+        1.0
+    } else if r >= 100.0 {
+        4.5
+    } else {
+        1.0 + 0.035 * r + r * (r - 60.0) * (100.0 - r) * 7e-6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn perfect_g711_path_rates_93_2() {
+        let score = Score::estimate(CodecImpairment::G711, &Conditions::default());
+        assert!(close(score.r_factor, 93.2, 0.05), "{}", score.r_factor);
+        assert!(close(score.mos, 4.41, 0.01), "{}", score.mos);
+        assert_eq!(mos(0.0), 1.0);
+        assert_eq!(mos(100.0), 4.5);
+    }
+
+    #[test]
+    fn delay_impairment_grows_past_the_knee() {
+        assert!(delay_impairment(0.0) < 0.2);
+        let at_150 = delay_impairment(150.0);
+        let at_400 = delay_impairment(400.0);
+        assert!(close(at_150, 3.8, 0.2), "{}", at_150);
+        assert!(at_400 > 25.0, "{}", at_400);
+    }
+
+    #[test]
+    fn bursty_loss_hurts_more_than_random_loss() {
+        let random = Conditions {
+            packet_loss_percent: 2.0,
+            burst_ratio: 1.0,
+            ..Conditions::default()
+        };
+        let bursty = Conditions {
+            burst_ratio: 3.0,
+            ..random
+        };
+        let g711 = CodecImpairment::for_codec("PCMU");
+        // 2 % random loss on G.711 with PLC costs about 7 points of R.
+        assert!(close(effective_ie(g711, &random), 7.0, 0.05));
+        assert!(effective_ie(g711, &bursty) > effective_ie(g711, &random));
+        assert!(
+            Score::estimate(CodecImpairment::for_codec("G729"), &random).mos
+                < Score::estimate(g711, &random).mos
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! A stand-in agent for integration tests and local runs.
+//!
+//! [`MockAgent`] accepts WebSocket connections on a loopback port, one at a
+//! time. Everything the relay sends comes out of [`MockAgent::recv`], and
+//! what the test wants the agent to say goes out through
+//! [`MockAgent::send`] and [`MockAgent::send_audio`].
+
+use std::net::Ipv4Addr;
+
+use futures::{SinkExt, StreamExt};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+use tokio_tungstenite::tungstenite::Message;
+use voip_common::Result;
+
+use super::protocol::{from_bytes, to_bytes, AgentMessage, RelayMessage};
+
+/// What the mock agent got from the relay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Received {
+    /// A JSON message.
+    Message(RelayMessage),
+    /// A binary audio frame.
+    Audio {
+        /// Leading track byte.
+        track: u8,
+        /// The samples after it.
+        samples: Vec<i16>,
+    },
+    /// The relay closed the connection.
+    Closed,
+}
+
+/// A WebSocket agent driven by the test.
+#[derive(Debug)]
+pub struct MockAgent {
+    url: String,
+    received: mpsc::UnboundedReceiver<Received>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    task: JoinHandle<()>,
+}
+
+impl MockAgent {
+    /// Listen on an ephemeral loopback port.
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let (received_tx, received) = mpsc::unbounded_channel();
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(serve(listener, received_tx, outgoing_rx));
+        Ok(Self {
+            url,
+            received,
+            outgoing,
+            task,
+        })
+    }
+
+    /// URL to fork calls to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Next thing received from the relay.
+    pub async fn recv(&mut self) -> Option<Received> {
+        self.received.recv().await
+    }
+
+    /// Send a message to the connected relay.
+    pub fn send(&self, message: &AgentMessage) {
+        if let Ok(text) = serde_json::to_string(message) {
+            let _ = self.outgoing.send(Message::text(text));
+        }
+    }
+
+    /// Send audio to the connected relay in a binary frame.
+    pub fn send_audio(&self, samples: &[i16]) {
+        let _ = self.outgoing.send(Message::binary(to_bytes(samples)));
+    }
+}
+
+impl Drop for MockAgent {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    received: mpsc::UnboundedSender<Received>,
+    mut outgoing: mpsc::UnboundedReceiver<Message>,
+) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+            continue;
+        };
+        loop {
+            tokio::select! {
+                message = socket.next() => {
+                    let event = match message {
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                            Ok(message) => Received::Message(message),
+                            Err(_) => continue,
+                        },
+                        Some(Ok(Message::Binary(data))) if !data.is_empty() => Received::Audio {
+                            track: data[0],
+                            samples: from_bytes(&data[1..]),
+                        },
+                        Some(Ok(Message::Close(_)) | Err(_)) | None => {
+                            let _ = received.send(Received::Closed);
+                            break;
+                        }
+                        Some(Ok(_)) => continue,
+                    };
+                    let _ = received.send(event);
+                }
+                Some(message) = outgoing.recv() => {
+                    if socket.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
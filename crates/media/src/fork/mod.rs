@@ -0,0 +1,422 @@
+//! Call audio streamed to a conversational agent over a WebSocket.
+//!
+//! The relay hands the RTP payloads of each leg to a [`ForkTap`]. The fork
+//! task decodes them to PCM at the agent's rate and sends each packet's worth
+//! as it arrives, framed as [`protocol`] describes. Audio the agent sends back
+//! is queued on a [`Playback`] the relay plays to the caller in place of the
+//! callee's audio, and the agent's marks are echoed once the audio before
+//! them has been played. The stream ends when either side sends `stop`, the
+//! connection drops or the relay stops; unless the agent ended it, the agent
+//! gets a `stop` with the reason.
+//!
+//! Speech detected on a streamed leg is announced to the agent as well. Once
+//! its audio was cleared by someone else, e.g. by the caller barging in, the
+//! agent's audio is dropped until it sends `clear` itself, so the rest of an
+//! interrupted answer is not played.
+
+pub mod mock;
+pub mod protocol;
+
+use std::{collections::HashMap, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, instrument, warn};
+use voip_common::{Result, VoipError};
+
+use self::protocol::{
+    decode_payload, encode_payload, from_bytes, to_bytes, track_id, AgentMessage, MediaFormat,
+    RelayMessage, ENCODING,
+};
+use crate::{
+    codec::{AudioCodec, Decoder, Resampler},
+    playback::Playback,
+    relay::{RelayEvent, Side},
+};
+
+/// Longest wait for the agent to accept the WebSocket, or to see it closed.
+const AGENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Legs an agent hears.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tracks {
+    /// The calling party only.
+    Caller,
+    /// The called party only.
+    Callee,
+    /// Both parties, as separate tracks.
+    #[default]
+    Both,
+}
+
+impl Tracks {
+    /// Whether audio from `side` is streamed.
+    pub const fn includes(self, side: Side) -> bool {
+        matches!(
+            (self, side),
+            (Self::Both, _) | (Self::Caller, Side::Caller) | (Self::Callee, Side::Callee)
+        )
+    }
+}
+
+/// Where the agent is and what it gets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkOptions {
+    /// `ws://` URL of the agent.
+    pub url: String,
+    /// Rate of the PCM in both directions: 8000 or 16000.
+    pub sample_rate: u32,
+    /// Send audio in binary frames rather than base64 `media` messages.
+    pub binary: bool,
+    /// Legs streamed.
+    pub tracks: Tracks,
+    /// Passed along in `start`.
+    pub metadata: HashMap<String, String>,
+}
+
+impl Default for ForkOptions {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            sample_rate: 8000,
+            binary: false,
+            tracks: Tracks::Both,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+/// Identifiers of the forked call, announced in `start`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForkedCall {
+    /// Relay the audio comes from.
+    pub relay_id: String,
+    /// Call carried by the relay, when known.
+    pub call_id: String,
+    /// SIP Call-ID of the call, when known.
+    pub sip_call_id: String,
+}
+
+#[derive(Debug)]
+struct Frame {
+    side: Side,
+    at: Instant,
+    codec: AudioCodec,
+    payload: Vec<u8>,
+}
+
+/// Hands RTP payloads to a running fork; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct ForkTap {
+    tx: mpsc::UnboundedSender<Frame>,
+}
+
+impl ForkTap {
+    /// Stream an RTP payload received from `side` just now.
+    pub fn push(&self, side: Side, codec: AudioCodec, payload: &[u8]) {
+        // An ended fork ignores late audio.
+        let _ = self.tx.send(Frame {
+            side,
+            at: Instant::now(),
+            codec,
+            payload: payload.to_vec(),
+        });
+    }
+}
+
+/// A stream of call audio to an agent.
+#[derive(Debug)]
+pub struct AgentFork {
+    stream_id: String,
+    playback: Playback,
+    tap: ForkTap,
+    stop_tx: Option<oneshot::Sender<String>>,
+    task: JoinHandle<()>,
+}
+
+impl AgentFork {
+    /// Connect to the agent and announce the stream; the speech in
+    /// `relay_events` is announced too.
+    pub async fn connect(
+        stream_id: &str,
+        call: ForkedCall,
+        options: ForkOptions,
+        relay_events: broadcast::Receiver<RelayEvent>,
+    ) -> Result<Self> {
+        if !options.url.starts_with("ws://") {
+            return Err(VoipError::Validation(format!(
+                "agent URL {:?} is not a ws:// URL",
+                options.url
+            )));
+        }
+        let (playback, marks) = Playback::new(options.sample_rate)?;
+        let (mut socket, _) = time::timeout(
+            AGENT_TIMEOUT,
+            tokio_tungstenite::connect_async(&options.url),
+        )
+        .await
+        .map_err(|_| VoipError::Timeout(format!("connecting to agent {}", options.url)))?
+        .map_err(|e| VoipError::Unavailable(format!("agent {}: {}", options.url, e)))?;
+        let start = RelayMessage::Start {
+            stream_id: stream_id.to_owned(),
+            relay_id: call.relay_id,
+            call_id: call.call_id,
+            sip_call_id: call.sip_call_id,
+            media_format: MediaFormat {
+                encoding: ENCODING.to_owned(),
+                sample_rate: options.sample_rate,
+                channels: 1,
+            },
+            tracks: [Side::Caller, Side::Callee]
+                .into_iter()
+                .filter(|side| options.tracks.includes(*side))
+                .map(|side| side.name().to_owned())
+                .collect(),
+            binary: options.binary,
+            metadata: options.metadata.clone(),
+        };
+        socket
+            .send(json(&start))
+            .await
+            .map_err(|e| VoipError::Unavailable(format!("agent {}: {}", options.url, e)))?;
+        info!(stream_id, url = %options.url, "agent stream started");
+
+        let (tx, frames) = mpsc::unbounded_channel();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let listener = Listener {
+            stream_id: stream_id.to_owned(),
+            options,
+            origin: Instant::now(),
+            legs: [None, None],
+        };
+        let task = tokio::spawn(run(
+            socket,
+            listener,
+            frames,
+            relay_events,
+            playback.clone(),
+            marks,
+            stop_rx,
+        ));
+        Ok(Self {
+            stream_id: stream_id.to_owned(),
+            playback,
+            tap: ForkTap { tx },
+            stop_tx: Some(stop_tx),
+            task,
+        })
+    }
+
+    /// Identifier announced in `start`.
+    pub fn stream_id(&self) -> &str {
+        &self.stream_id
+    }
+
+    /// Where the relay hands the audio it receives.
+    pub fn tap(&self) -> ForkTap {
+        self.tap.clone()
+    }
+
+    /// Where the agent's audio is queued, for the relay to play.
+    pub fn playback(&self) -> Playback {
+        self.playback.clone()
+    }
+
+    /// Whether the stream has ended.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Send `stop` with `reason` and close the connection.
+    pub async fn stop(mut self, reason: &str) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(reason.to_owned());
+        }
+        if time::timeout(AGENT_TIMEOUT, &mut self.task).await.is_err() {
+            warn!(stream_id = %self.stream_id, "agent stream did not close in time");
+            self.task.abort();
+        }
+    }
+}
+
+/// Decoding state of one streamed leg.
+#[derive(Debug)]
+struct Track {
+    codec: AudioCodec,
+    decoder: Decoder,
+    resampler: Resampler,
+    sequence: u64,
+}
+
+/// Turns received RTP payloads into messages for the agent.
+#[derive(Debug)]
+struct Listener {
+    stream_id: String,
+    options: ForkOptions,
+    origin: Instant,
+    legs: [Option<Track>; 2],
+}
+
+impl Listener {
+    /// What the agent is told of a relay event, if anything.
+    fn speech(&self, event: RelayEvent) -> Option<Message> {
+        let includes = |side| self.options.tracks.includes(side);
+        let message = match event {
+            RelayEvent::SpeechStarted { side, barge_in } if includes(side) => {
+                RelayMessage::SpeechStarted {
+                    stream_id: self.stream_id.clone(),
+                    track: side.name().to_owned(),
+                    barge_in,
+                }
+            }
+            RelayEvent::SpeechEnded { side, duration } if includes(side) => {
+                RelayMessage::SpeechEnded {
+                    stream_id: self.stream_id.clone(),
+                    track: side.name().to_owned(),
+                    duration_ms: duration.as_millis() as u64,
+                }
+            }
+            _ => return None,
+        };
+        Some(json(&message))
+    }
+
+    fn message(&mut self, frame: Frame) -> Option<Message> {
+        if !self.options.tracks.includes(frame.side) {
+            return None;
+        }
+        let leg = &mut self.legs[track_id(frame.side) as usize];
+        if leg.as_ref().is_none_or(|track| track.codec != frame.codec) {
+            *leg = Some(Track {
+                codec: frame.codec,
+                decoder: frame.codec.decoder(),
+                resampler: Resampler::new(frame.codec.sample_rate(), self.options.sample_rate)
+                    .ok()?,
+                sequence: 0,
+            });
+        }
+        let track = leg.as_mut()?;
+        let mut pcm = Vec::new();
+        track.decoder.decode(&frame.payload, &mut pcm);
+        let pcm = track.resampler.process(&pcm);
+        track.sequence += 1;
+        if self.options.binary {
+            let mut data = vec![track_id(frame.side)];
+            data.extend(to_bytes(&pcm));
+            return Some(Message::binary(data));
+        }
+        Some(json(&RelayMessage::Media {
+            stream_id: self.stream_id.clone(),
+            track: frame.side.name().to_owned(),
+            sequence: track.sequence,
+            timestamp_ms: frame.at.saturating_duration_since(self.origin).as_millis() as u64,
+            payload: encode_payload(&pcm),
+        }))
+    }
+}
+
+fn json(message: &RelayMessage) -> Message {
+    // Plain strings and numbers always serialize.
+    Message::text(serde_json::to_string(message).unwrap_or_default())
+}
+
+#[instrument(name = "media.fork", skip_all, fields(stream_id = %listener.stream_id))]
+async fn run(
+    mut socket: Socket,
+    mut listener: Listener,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    mut relay_events: broadcast::Receiver<RelayEvent>,
+    playback: Playback,
+    mut marks: mpsc::UnboundedReceiver<String>,
+    mut stop_rx: oneshot::Receiver<String>,
+) {
+    let stream_id = listener.stream_id.clone();
+    let mut clears = playback.clears();
+    let reason = loop {
+        tokio::select! {
+            // The fork holds a sender, so frames only end with `stop_rx`.
+            Some(frame) = frames.recv() => {
+                let Some(message) = listener.message(frame) else {
+                    continue;
+                };
+                if let Err(err) = socket.send(message).await {
+                    warn!(error = %err, "agent connection lost");
+                    break None;
+                }
+            }
+            // Lagging only loses speech events; a closed channel means the
+            // relay is gone and `stop_rx` follows.
+            Ok(event) = relay_events.recv() => {
+                let Some(message) = listener.speech(event) else {
+                    continue;
+                };
+                if let Err(err) = socket.send(message).await {
+                    warn!(error = %err, "agent connection lost");
+                    break None;
+                }
+            }
+            Some(name) = marks.recv() => {
+                let mark = RelayMessage::Mark {
+                    stream_id: stream_id.clone(),
+                    name,
+                };
+                if let Err(err) = socket.send(json(&mark)).await {
+                    warn!(error = %err, "agent connection lost");
+                    break None;
+                }
+            }
+            reason = &mut stop_rx => {
+                break Some(reason.unwrap_or_else(|_| "stream dropped".to_owned()));
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(AgentMessage::Media { payload }) => match decode_payload(&payload) {
+                        Some(samples) => {
+                            playback.push_uncleared(clears, &samples);
+                        }
+                        None => debug!("ignoring agent audio that is not base64"),
+                    },
+                    Ok(AgentMessage::Mark { name }) => playback.mark(name),
+                    Ok(AgentMessage::Clear) => {
+                        playback.clear();
+                        clears = playback.clears();
+                    }
+                    Ok(AgentMessage::Stop { reason }) => {
+                        info!(?reason, "agent ended the stream");
+                        break None;
+                    }
+                    Err(err) => debug!(error = %err, "ignoring agent message"),
+                },
+                Some(Ok(Message::Binary(data))) => {
+                    playback.push_uncleared(clears, &from_bytes(&data));
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    info!("agent closed the stream");
+                    break None;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    warn!(error = %err, "agent connection failed");
+                    break None;
+                }
+            },
+        }
+    };
+    playback.clear();
+    if let Some(reason) = reason {
+        let stop = RelayMessage::Stop { stream_id, reason };
+        if let Err(err) = socket.send(json(&stop)).await {
+            debug!(error = %err, "agent missed the end of the stream");
+        }
+        let _ = socket.close(None).await;
+    }
+    info!("agent stream ended");
+}
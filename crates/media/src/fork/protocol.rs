@@ -0,0 +1,205 @@
+//! Messages exchanged with an agent over the fork's WebSocket.
+//!
+//! Text frames are JSON objects tagged by `event`. Audio is mono 16-bit
+//! little-endian PCM at the rate announced in `start`. It travels base64
+//! encoded in `media` messages or, with binary framing, in binary frames:
+//! from the relay, one byte naming the track (see [`track_id`]) followed by
+//! the samples; from the agent, the samples alone.
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::relay::Side;
+
+/// Encoding name announced in `start`.
+pub const ENCODING: &str = "L16";
+
+/// PCM layout of the audio in both directions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaFormat {
+    /// Always [`ENCODING`].
+    pub encoding: String,
+    /// 8000 or 16000.
+    pub sample_rate: u32,
+    /// Always 1.
+    pub channels: u16,
+}
+
+/// From the relay to the agent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RelayMessage {
+    /// First message of a stream.
+    Start {
+        /// Identifies the stream in every later message.
+        stream_id: String,
+        /// Relay the audio comes from.
+        relay_id: String,
+        /// Call carried by the relay, when known.
+        call_id: String,
+        /// SIP Call-ID of the call, when known.
+        sip_call_id: String,
+        /// Layout of the audio.
+        media_format: MediaFormat,
+        /// Legs streamed: "caller", "callee" or both.
+        tracks: Vec<String>,
+        /// Whether audio comes in binary frames rather than `media` messages.
+        binary: bool,
+        /// Free-form values given when the fork started.
+        metadata: HashMap<String, String>,
+    },
+    /// Audio received on a leg, as decoded from one RTP packet.
+    Media {
+        /// The stream.
+        stream_id: String,
+        /// "caller" or "callee".
+        track: String,
+        /// Count of chunks sent on the track so far.
+        sequence: u64,
+        /// When the audio arrived, since the stream started.
+        timestamp_ms: u64,
+        /// Base64 of the samples.
+        payload: String,
+    },
+    /// The audio queued before the agent's mark of that name was played.
+    Mark {
+        /// The stream.
+        stream_id: String,
+        /// Name the agent gave.
+        name: String,
+    },
+    /// The party on a streamed leg started talking, when speech is detected
+    /// on the leg.
+    SpeechStarted {
+        /// The stream.
+        stream_id: String,
+        /// "caller" or "callee".
+        track: String,
+        /// Whether the agent's audio was cleared for it; the agent's audio is
+        /// then dropped until it sends `clear`.
+        barge_in: bool,
+    },
+    /// The party on a streamed leg stopped talking.
+    SpeechEnded {
+        /// The stream.
+        stream_id: String,
+        /// "caller" or "callee".
+        track: String,
+        /// From the start of the speech to its last voiced frame.
+        duration_ms: u64,
+    },
+    /// Last message of a stream.
+    Stop {
+        /// The stream.
+        stream_id: String,
+        /// Why it ended.
+        reason: String,
+    },
+}
+
+/// From the agent to the relay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgentMessage {
+    /// Audio to play to the caller after what is already queued.
+    Media {
+        /// Base64 of the samples.
+        payload: String,
+    },
+    /// Ask to be told once the audio sent so far has been played.
+    Mark {
+        /// Echoed back in the relay's `mark`.
+        name: String,
+    },
+    /// Drop the audio not played yet, e.g. when the caller interrupts.
+    Clear,
+    /// End the stream.
+    Stop {
+        /// Why, for the logs.
+        #[serde(default)]
+        reason: Option<String>,
+    },
+}
+
+/// Leading byte of the relay's binary frames for audio from `side`.
+pub const fn track_id(side: Side) -> u8 {
+    match side {
+        Side::Caller => 0,
+        Side::Callee => 1,
+    }
+}
+
+/// Little-endian bytes of `samples`.
+pub fn to_bytes(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+/// Samples of little-endian `bytes`; a trailing odd byte is ignored.
+pub fn from_bytes(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+/// Base64 `payload` of `samples`.
+pub fn encode_payload(samples: &[i16]) -> String {
+    STANDARD.encode(to_bytes(samples))
+}
+
+/// Samples of a base64 `payload`, `None` when it is not base64.
+pub fn decode_payload(payload: &str) -> Option<Vec<i16>> {
+    STANDARD
+        .decode(payload)
+        .ok()
+        .map(|bytes| from_bytes(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_tagged_by_event() {
+        let json = serde_json::to_value(RelayMessage::Media {
+            stream_id: "s".into(),
+            track: "caller".into(),
+            sequence: 3,
+            timestamp_ms: 60,
+            payload: encode_payload(&[1, -2]),
+        })
+        .expect("encode");
+        assert_eq!(json["event"], "media");
+        assert_eq!(json["payload"], "AQD+/w==");
+
+        let parsed: Vec<AgentMessage> = [
+            r#"{"event":"media","payload":"AQD+/w=="}"#,
+            r#"{"event":"mark","name":"greeting"}"#,
+            r#"{"event":"clear"}"#,
+            r#"{"event":"stop"}"#,
+        ]
+        .iter()
+        .map(|text| serde_json::from_str(text).expect("decode"))
+        .collect();
+        assert_eq!(
+            parsed,
+            [
+                AgentMessage::Media {
+                    payload: "AQD+/w==".into()
+                },
+                AgentMessage::Mark {
+                    name: "greeting".into()
+                },
+                AgentMessage::Clear,
+                AgentMessage::Stop { reason: None },
+            ]
+        );
+        assert_eq!(decode_payload("AQD+/w=="), Some(vec![1, -2]));
+        assert_eq!(decode_payload("not base64!"), None);
+    }
+}
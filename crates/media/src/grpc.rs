@@ -0,0 +1,1801 @@
+//! `voip.media.MediaService` over the [`MediaRelay`] session registry.
+//!
+//! `remote_sdp` describes the far end and is relayed on the caller leg;
+//! `local_sdp` describes our side of the call (the other leg or an agent) and
+//! is relayed on the callee leg. When the offer shares no codec with
+//! `local_sdp`, the caller leg is answered from [`DEFAULT_CODECS`] and the
+//! relay transcodes between the two. DTMF digits are detected in the format
+//! each leg negotiated and streamed through `StreamEvents`. An `RTP/SAVP`
+//! offer, or `enable_srtp`, makes the caller leg SRTP keyed with SDES while
+//! the callee leg stays plain. Recordings are WAV files; `enable_recording`
+//! records the call in stereo with the relay id as recording id. Recordings
+//! pause through `PauseRecording` or the `pause_digits` keyed on the call,
+//! and each pause is listed in the final `RecordingInfo`. A leg whose party
+//! stops sending media is reported as a stream interruption, and again when
+//! its media resumes. Packet loss and jitter are reported once they exceed
+//! the configured thresholds, and again only after recovering.
+//! `StartAgentStream` forks the call's audio to an agent over a WebSocket and
+//! plays the agent's answers to the caller. `PlayPrompt` speaks a text through
+//! the configured [`TextToSpeech`] or plays a WAV file to a leg, paced in its
+//! codec, until `CancelPrompt`. `SetSpeechDetection` reports when the party
+//! on a leg starts and stops talking and, with barge-in, stops the prompt or
+//! agent audio playing to the leg when its party starts. Failures are returned as `tonic::Status`
+//! through [`VoipError::to_status`].
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use uuid::Uuid;
+
+use voip_common::{
+    proto::{
+        common::{Codec, QosMetrics},
+        media::{
+            media_event, media_service_server::MediaService, play_prompt_request::Source,
+            update_media_request::Update, AgentTracks, CancelPromptRequest, CancelPromptResponse,
+            CodecUsage, DtmfEvent, GetStatsRequest, GetStatsResponse, JitterEvent, MediaEndpoint,
+            MediaEvent, MediaEventType, MediaStats, PacketLossEvent, PauseFill, PauseInterval,
+            PauseRecordingRequest, PauseRecordingResponse, PlayPromptRequest, PlayPromptResponse,
+            RecordingFormat, RecordingInfo, RecordingOptions, ResumeRecordingRequest,
+            ResumeRecordingResponse, SetSpeechDetectionRequest, SetSpeechDetectionResponse,
+            SpeechEvent, StartAgentStreamRequest, StartAgentStreamResponse, StartRecordingRequest,
+            StartRecordingResponse, StartRelayRequest, StartRelayResponse, StopAgentStreamRequest,
+            StopAgentStreamResponse, StopRecordingRequest, StopRecordingResponse, StopRelayRequest,
+            StopRelayResponse, StreamEventsRequest, StreamInterruptionEvent, UpdateMediaRequest,
+            UpdateMediaResponse,
+        },
+    },
+    sdp::{
+        codecs_match, intersect, negotiate_crypto, Direction, LocalMedia, MediaDescription,
+        SessionDescription,
+    },
+    Result, VoipError,
+};
+
+use crate::{
+    codec::AudioCodec,
+    fork::{ForkOptions, Tracks},
+    prompt::Prompt,
+    recording::{self, Channels},
+    relay::{RelayEvent, Side, SpeechDetection},
+    srtp::{self, SrtpPolicy},
+    transcode::PayloadFormat,
+    tts::{TextToSpeech, TtsOptions},
+    MediaConfig, MediaRelay, MediaSession,
+};
+
+/// Codecs relayed when `local_sdp` does not list any, most preferred first.
+const DEFAULT_CODECS: &[(&str, u32)] = &[
+    ("PCMU", 0),
+    ("PCMA", 8),
+    ("G722", 9),
+    ("telephone-event", 101),
+];
+
+/// SDP state of the caller leg, kept to answer re-offers and build our own.
+#[derive(Debug, Clone)]
+struct Negotiation {
+    /// Our side of the caller leg; `codecs` holds what we support.
+    local: LocalMedia,
+    /// Codecs agreed with the far end, preferred first.
+    codecs: Vec<Codec>,
+    /// Format of the callee leg when it shares no codec with the caller.
+    callee: Option<PayloadFormat>,
+}
+
+impl Negotiation {
+    /// `[caller, callee]` formats, when the agreed codecs can be decoded.
+    fn formats(&self) -> Option<[PayloadFormat; 2]> {
+        let caller = payload_format(&self.codecs)?;
+        Some([caller, self.callee.unwrap_or(caller)])
+    }
+
+    /// `[caller, callee]` formats to transcode between, `None` when they agree.
+    fn transcoding(&self) -> Option<[PayloadFormat; 2]> {
+        self.callee?;
+        self.formats().filter(|[caller, callee]| caller != callee)
+    }
+
+    /// A new offer from us with the agreed codecs.
+    fn reoffer(&mut self, direction: Direction) -> String {
+        self.local.direction = direction;
+        self.local.session_version += 1;
+        LocalMedia {
+            codecs: self.codecs.clone(),
+            ..self.local.clone()
+        }
+        .offer()
+        .to_string()
+    }
+}
+
+/// gRPC front end of the media relay.
+#[derive(Clone)]
+pub struct MediaGrpcService {
+    relay: Arc<MediaRelay>,
+    address: IpAddr,
+    negotiations: Arc<Mutex<HashMap<String, Negotiation>>>,
+    tts: Option<Arc<dyn TextToSpeech>>,
+}
+
+impl std::fmt::Debug for MediaGrpcService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MediaGrpcService")
+            .field("relay", &self.relay)
+            .field("address", &self.address)
+            .field("tts", &self.tts.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MediaGrpcService {
+    /// Serve `relay`, advertising the address from `config` in SDP and endpoints.
+    pub fn new(relay: Arc<MediaRelay>, config: &MediaConfig) -> Self {
+        Self {
+            relay,
+            address: config.advertised_ip(),
+            negotiations: Arc::new(Mutex::new(HashMap::new())),
+            tts: None,
+        }
+    }
+
+    /// Speak the texts of `PlayPrompt` with `tts`.
+    pub fn with_tts(mut self, tts: Arc<dyn TextToSpeech>) -> Self {
+        self.tts = Some(tts);
+        self
+    }
+
+    async fn start(&self, request: StartRelayRequest) -> Result<StartRelayResponse> {
+        let remote = parse_sdp(&request.remote_sdp)?;
+        let offered_srtp = remote
+            .as_ref()
+            .and_then(SessionDescription::audio)
+            .is_some_and(MediaDescription::is_secure);
+        if request.enable_srtp && remote.is_some() && !offered_srtp {
+            return Err(VoipError::Validation(
+                "enable_srtp needs an RTP/SAVP offer".into(),
+            ));
+        }
+        let crypto = if request.enable_srtp || offered_srtp {
+            srtp::sdes_offer()?
+        } else {
+            Vec::new()
+        };
+        let local = parse_sdp(&request.local_sdp)?;
+        let supported = local
+            .as_ref()
+            .map(SessionDescription::audio_codecs)
+            .filter(|codecs| !codecs.is_empty())
+            .unwrap_or_else(default_codecs);
+        let mut codecs = match &remote {
+            Some(remote) => intersect(&remote.audio_codecs(), &supported),
+            None => supported.clone(),
+        };
+        let mut ours = supported.clone();
+        let mut callee = None;
+        if let (Some(remote), None) = (&remote, primary(&codecs)) {
+            // Answer with what we can transcode and convert to the callee's codec.
+            if let Some(format) = payload_format(&supported) {
+                callee = Some(format);
+                ours = default_codecs();
+                codecs = intersect(&remote.audio_codecs(), &ours);
+            }
+        }
+        let codec = primary(&codecs)
+            .ok_or_else(|| VoipError::Media("no audio codec in common with offer".into()))?;
+
+        let relay_id = Uuid::new_v4().to_string();
+        let session = self.relay.start_session(relay_id.clone(), codec).await?;
+        let negotiation = Negotiation {
+            local: LocalMedia {
+                address: self.address.to_string(),
+                port: session.caller.rtp_port,
+                codecs: ours,
+                direction: Direction::SendRecv,
+                session_id: u64::try_from(Utc::now().timestamp()).unwrap_or_default(),
+                session_version: 1,
+                crypto,
+            },
+            codecs,
+            callee,
+        };
+        let negotiated_sdp = match &remote {
+            Some(remote) => match remote.answer(&negotiation.local) {
+                Ok(answer) => answer.to_string(),
+                Err(e) => {
+                    let _ = self.relay.stop_session(&relay_id).await;
+                    return Err(e);
+                }
+            },
+            None => negotiation.local.offer().to_string(),
+        };
+        let secured = match &remote {
+            Some(remote) => self.secure(&relay_id, remote, &negotiation.local),
+            None => Ok(()),
+        };
+        let recorded = if request.enable_recording {
+            self.relay
+                .start_recording(
+                    &relay_id,
+                    &relay_id,
+                    recording::RecordingOptions {
+                        channels: Channels::Stereo,
+                        ..recording::RecordingOptions::default()
+                    },
+                )
+                .map(drop)
+        } else {
+            Ok(())
+        };
+        let call = match &request.call_id {
+            Some(call) => self
+                .relay
+                .set_call_id(&relay_id, &call.id, &call.sip_call_id),
+            None => Ok(()),
+        };
+        if let Err(e) = secured
+            .and_then(|()| self.apply(&relay_id, &negotiation))
+            .and(recorded)
+            .and(call)
+        {
+            let _ = self.relay.stop_session(&relay_id).await;
+            return Err(e);
+        }
+        self.relay
+            .set_remote(&relay_id, Side::Caller, remote.as_ref().and_then(rtp_addr))?;
+        self.relay
+            .set_remote(&relay_id, Side::Callee, local.as_ref().and_then(rtp_addr))?;
+
+        let remote_endpoint = self.endpoint(&session, Side::Caller, &negotiation.codecs);
+        let local_endpoint = match negotiation.callee {
+            Some(_) => self.endpoint(&session, Side::Callee, &supported),
+            None => self.endpoint(&session, Side::Callee, &negotiation.codecs),
+        };
+        self.lock().insert(relay_id.clone(), negotiation);
+        info!(
+            %relay_id,
+            call_id = request.call_id.map(|c| c.id).unwrap_or_default(),
+            "relay started"
+        );
+        Ok(StartRelayResponse {
+            success: true,
+            relay_id,
+            negotiated_sdp,
+            local_endpoint: Some(local_endpoint),
+            remote_endpoint: Some(remote_endpoint),
+            error: None,
+        })
+    }
+
+    fn update(&self, request: UpdateMediaRequest) -> Result<UpdateMediaResponse> {
+        let relay_id = request.relay_id;
+        let update = request
+            .update
+            .ok_or_else(|| VoipError::Validation("update is required".into()))?;
+        let mut negotiations = self.lock();
+        let negotiation = negotiations
+            .get_mut(&relay_id)
+            .ok_or_else(|| not_found(&relay_id))?;
+
+        let negotiated_sdp = match update {
+            Update::NewSdp(sdp) => {
+                let offer = SessionDescription::parse(&sdp)?;
+                negotiation.local.session_version += 1;
+                negotiation.local.direction = Direction::SendRecv;
+                let answer = offer.answer(&negotiation.local)?;
+                self.secure(&relay_id, &offer, &negotiation.local)?;
+                let codecs = intersect(&offer.audio_codecs(), &negotiation.local.codecs);
+                let switched = primary(&codecs);
+                negotiation.codecs = codecs;
+                if let Some(codec) = switched {
+                    self.relay.set_codec(&relay_id, codec)?;
+                    self.apply(&relay_id, negotiation)?;
+                }
+                self.relay
+                    .set_remote(&relay_id, Side::Caller, rtp_addr(&offer))?;
+                self.relay.set_held(&relay_id, offer.is_hold())?;
+                answer.to_string()
+            }
+            update @ (Update::Hold(_) | Update::Resume(_)) => {
+                let held = matches!(update, Update::Hold(true) | Update::Resume(false));
+                self.relay.set_held(&relay_id, held)?;
+                negotiation.reoffer(if held {
+                    Direction::SendOnly
+                } else {
+                    Direction::SendRecv
+                })
+            }
+            Update::NewCodec(codec) => {
+                let chosen = negotiation
+                    .codecs
+                    .iter()
+                    .find(|ours| {
+                        codecs_match(ours, &codec)
+                            || (codec.name.is_empty() && ours.payload_type == codec.payload_type)
+                    })
+                    .cloned()
+                    .ok_or_else(|| {
+                        VoipError::Validation(format!("codec {} was not negotiated", codec.name))
+                    })?;
+                self.relay.set_codec(&relay_id, chosen.name.clone())?;
+                let mut codecs = vec![chosen];
+                codecs.extend(
+                    negotiation
+                        .codecs
+                        .iter()
+                        .filter(|c| is_auxiliary(c))
+                        .cloned(),
+                );
+                negotiation.codecs = codecs;
+                self.apply(&relay_id, negotiation)?;
+                let direction = negotiation.local.direction;
+                negotiation.reoffer(direction)
+            }
+        };
+        Ok(UpdateMediaResponse {
+            success: true,
+            negotiated_sdp,
+            error: None,
+        })
+    }
+
+    async fn record(&self, request: StartRecordingRequest) -> Result<StartRecordingResponse> {
+        match RecordingFormat::try_from(request.format) {
+            Ok(RecordingFormat::FormatUnknown | RecordingFormat::FormatWav) => {}
+            _ => return Err(Status::unimplemented("only WAV recordings are supported").into()),
+        }
+        let options = request.options.unwrap_or_default();
+        if options.pause_digits.is_empty() != options.resume_digits.is_empty() {
+            return Err(VoipError::Validation(
+                "pause_digits and resume_digits go together".into(),
+            ));
+        }
+        let recording_id = Uuid::new_v4().to_string();
+        let path = self.relay.start_recording(
+            &request.relay_id,
+            &recording_id,
+            recording_options(&options),
+        )?;
+        if !options.pause_digits.is_empty() {
+            if let Err(e) = self.relay.pause_recording_on_digits(
+                &recording_id,
+                &options.pause_digits,
+                &options.resume_digits,
+            ) {
+                if let Ok(info) = self.relay.stop_recording(&recording_id).await {
+                    let _ = std::fs::remove_file(info.path);
+                }
+                return Err(e);
+            }
+        }
+        Ok(StartRecordingResponse {
+            success: true,
+            recording_id,
+            file_path: path.display().to_string(),
+            error: None,
+        })
+    }
+
+    async fn fork(&self, request: StartAgentStreamRequest) -> Result<StartAgentStreamResponse> {
+        let tracks = match AgentTracks::try_from(request.tracks) {
+            Ok(AgentTracks::Both) => Tracks::Both,
+            Ok(AgentTracks::Caller) => Tracks::Caller,
+            Ok(AgentTracks::Callee) => Tracks::Callee,
+            Err(_) => {
+                return Err(VoipError::Validation(format!(
+                    "unknown agent tracks {}",
+                    request.tracks
+                )))
+            }
+        };
+        let options = ForkOptions {
+            url: request.url,
+            sample_rate: match request.sample_rate {
+                0 => ForkOptions::default().sample_rate,
+                rate => rate,
+            },
+            binary: request.binary,
+            tracks,
+            metadata: request.metadata,
+        };
+        let relay_id = match (request.relay_id.as_str(), request.call_id.as_str()) {
+            ("", "") => return Err(VoipError::Validation("no relay_id or call_id".into())),
+            ("", call_id) => self
+                .relay
+                .find_call(call_id)
+                .ok_or_else(|| VoipError::NotFound(format!("relay of call {}", call_id)))?,
+            (relay_id, _) => relay_id.to_owned(),
+        };
+        let stream_id = self.relay.start_fork(&relay_id, options).await?;
+        Ok(StartAgentStreamResponse {
+            success: true,
+            stream_id,
+            error: None,
+            relay_id,
+        })
+    }
+
+    async fn prompt(&self, request: PlayPromptRequest) -> Result<PlayPromptResponse> {
+        let side = leg(&request.leg)?;
+        let prompt = match request.source {
+            Some(Source::Text(text)) => {
+                let tts = self.tts.as_ref().ok_or_else(|| {
+                    VoipError::Unavailable("no text-to-speech engine configured".into())
+                })?;
+                // Wideband legs get wideband speech.
+                let sample_rate = self
+                    .lock()
+                    .get(&request.relay_id)
+                    .and_then(Negotiation::formats)
+                    .map(|[caller, callee]| match side {
+                        Side::Caller => caller.codec.sample_rate(),
+                        Side::Callee => callee.codec.sample_rate(),
+                    })
+                    .filter(|rate| *rate == 16000)
+                    .unwrap_or(8000);
+                let options = TtsOptions {
+                    sample_rate,
+                    voice: Some(request.voice).filter(|voice| !voice.is_empty()),
+                };
+                Prompt::Audio {
+                    audio: tts.synthesize(&text, &options).await?,
+                    sample_rate,
+                }
+            }
+            Some(Source::FilePath(path)) => Prompt::Wav(path.into()),
+            None => return Err(VoipError::Validation("prompt has no text or file".into())),
+        };
+        let handle = self.relay.play_prompt(&request.relay_id, side, prompt)?;
+        let prompt_id = handle.id().to_owned();
+        let completed = request.wait && handle.finished().await;
+        Ok(PlayPromptResponse {
+            success: true,
+            prompt_id,
+            completed,
+            error: None,
+        })
+    }
+
+    /// Key the caller leg from the SDES exchange with `remote`, or make it
+    /// plain RTP when `remote` is.
+    fn secure(
+        &self,
+        relay_id: &str,
+        remote: &SessionDescription,
+        local: &LocalMedia,
+    ) -> Result<()> {
+        let policy = match remote.audio().filter(|audio| audio.is_secure()) {
+            Some(audio) => {
+                let (theirs, ours) =
+                    negotiate_crypto(&audio.crypto(), &local.crypto).ok_or_else(|| {
+                        VoipError::Media("no crypto suite in common with offer".into())
+                    })?;
+                Some(SrtpPolicy::from_sdes(&ours, &theirs)?)
+            }
+            None => None,
+        };
+        self.relay.set_srtp(relay_id, Side::Caller, policy.as_ref())
+    }
+
+    /// Point transcoding and DTMF detection at the negotiated formats.
+    fn apply(&self, relay_id: &str, negotiation: &Negotiation) -> Result<()> {
+        self.relay
+            .set_transcoding(relay_id, negotiation.transcoding())?;
+        if let Some([caller, callee]) = negotiation.formats() {
+            self.relay.set_format(relay_id, Side::Caller, caller)?;
+            self.relay.set_format(relay_id, Side::Callee, callee)?;
+        }
+        Ok(())
+    }
+
+    /// Merge the events of the requested relays into one stream.
+    fn events(&self, request: StreamEventsRequest) -> Result<EventStream> {
+        if request.relay_ids.is_empty() {
+            return Err(VoipError::Validation("relay_ids is required".into()));
+        }
+        let subscriptions = request
+            .relay_ids
+            .iter()
+            .map(|relay_id| Ok((relay_id.clone(), self.relay.subscribe(relay_id)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        for (relay_id, mut events) in subscriptions {
+            let tx = tx.clone();
+            let types = request.event_types.clone();
+            tokio::spawn(async move {
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => media_event(&relay_id, &event),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if !types.is_empty() && !types.contains(&event.r#type) {
+                        continue;
+                    }
+                    if tx.send(Ok(event)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(UnboundedReceiverStream::new(rx))
+    }
+
+    fn endpoint(&self, session: &MediaSession, side: Side, codecs: &[Codec]) -> MediaEndpoint {
+        let leg = match side {
+            Side::Caller => &session.caller,
+            Side::Callee => &session.callee,
+        };
+        MediaEndpoint {
+            ip: self.address.to_string(),
+            rtp_port: u32::from(leg.rtp_port),
+            rtcp_port: u32::from(leg.rtcp_port),
+            codecs: codecs.to_vec(),
+        }
+    }
+
+    fn stats(&self, session: &MediaSession) -> MediaStats {
+        let codecs = self
+            .lock()
+            .get(&session.session_id)
+            .map(|n| n.codecs.clone())
+            .unwrap_or_default();
+        let total: u64 = session.codec_usage.iter().map(|u| u.packets).sum();
+        let codec_usage = session
+            .codec_usage
+            .iter()
+            .map(|usage| CodecUsage {
+                codec: Some(
+                    codecs
+                        .iter()
+                        .find(|c| c.name == usage.codec)
+                        .cloned()
+                        .unwrap_or_else(|| Codec {
+                            name: usage.codec.clone(),
+                            ..Codec::default()
+                        }),
+                ),
+                packets: usage.packets,
+                bytes: usage.bytes,
+                usage_percent: usage
+                    .packets
+                    .saturating_mul(100)
+                    .checked_div(total)
+                    .and_then(|p| u32::try_from(p).ok())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        MediaStats {
+            inbound: Some(QosMetrics {
+                jitter_ms: session.caller.jitter_ms,
+                packet_loss_percent: session.caller.loss_percent,
+                rtt_ms: session.caller.rtt_ms.unwrap_or_default(),
+                mos: session.caller.score.map_or(0.0, |score| score.mos),
+                packets_received: session.caller.packets_received,
+                bytes_received: session.caller.bytes_received,
+                packets_discarded: session.caller.packets_discarded,
+                packets_sent: session.callee.packets_sent,
+                bytes_sent: session.callee.bytes_sent,
+            }),
+            outbound: Some(QosMetrics {
+                jitter_ms: session.callee.jitter_ms,
+                packet_loss_percent: session.callee.loss_percent,
+                rtt_ms: session.callee.rtt_ms.unwrap_or_default(),
+                mos: session.callee.score.map_or(0.0, |score| score.mos),
+                packets_received: session.callee.packets_received,
+                bytes_received: session.callee.bytes_received,
+                packets_discarded: session.callee.packets_discarded,
+                packets_sent: session.caller.packets_sent,
+                bytes_sent: session.caller.bytes_sent,
+            }),
+            started_at: Some(proto_timestamp(session.started_at)),
+            duration_ms: u64::try_from((Utc::now() - session.started_at).num_milliseconds())
+                .unwrap_or_default(),
+            codec_usage,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Negotiation>> {
+        self.negotiations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+type RpcResult<T> = std::result::Result<Response<T>, Status>;
+
+type EventStream = UnboundedReceiverStream<std::result::Result<MediaEvent, Status>>;
+
+#[tonic::async_trait]
+impl MediaService for MediaGrpcService {
+    type StreamEventsStream = EventStream;
+
+    async fn start_relay(
+        &self,
+        request: Request<StartRelayRequest>,
+    ) -> RpcResult<StartRelayResponse> {
+        self.start(request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+
+    async fn stop_relay(&self, request: Request<StopRelayRequest>) -> RpcResult<StopRelayResponse> {
+        let request = request.into_inner();
+        let session = self
+            .relay
+            .stop_session(&request.relay_id)
+            .await
+            .map_err(|e| e.to_status())?;
+        let final_stats = self.stats(&session);
+        self.lock().remove(&request.relay_id);
+        info!(relay_id = %request.relay_id, reason = %request.reason, "relay stopped");
+        Ok(Response::new(StopRelayResponse {
+            success: true,
+            final_stats: Some(final_stats),
+            error: None,
+        }))
+    }
+
+    async fn update_media(
+        &self,
+        request: Request<UpdateMediaRequest>,
+    ) -> RpcResult<UpdateMediaResponse> {
+        self.update(request.into_inner())
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+
+    async fn get_stats(&self, request: Request<GetStatsRequest>) -> RpcResult<GetStatsResponse> {
+        let relay_id = request.into_inner().relay_id;
+        let session = self
+            .relay
+            .session(&relay_id)
+            .ok_or_else(|| not_found(&relay_id).to_status())?;
+        Ok(Response::new(GetStatsResponse {
+            stats: Some(self.stats(&session)),
+            error: None,
+        }))
+    }
+
+    async fn start_recording(
+        &self,
+        request: Request<StartRecordingRequest>,
+    ) -> RpcResult<StartRecordingResponse> {
+        self.record(request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+
+    async fn stop_recording(
+        &self,
+        request: Request<StopRecordingRequest>,
+    ) -> RpcResult<StopRecordingResponse> {
+        let info = self
+            .relay
+            .stop_recording(&request.into_inner().recording_id)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(StopRecordingResponse {
+            success: true,
+            info: Some(recording_info(&info)),
+            error: None,
+        }))
+    }
+
+    async fn pause_recording(
+        &self,
+        request: Request<PauseRecordingRequest>,
+    ) -> RpcResult<PauseRecordingResponse> {
+        self.relay
+            .pause_recording(&request.into_inner().recording_id)
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(PauseRecordingResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn resume_recording(
+        &self,
+        request: Request<ResumeRecordingRequest>,
+    ) -> RpcResult<ResumeRecordingResponse> {
+        self.relay
+            .resume_recording(&request.into_inner().recording_id)
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(ResumeRecordingResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn start_agent_stream(
+        &self,
+        request: Request<StartAgentStreamRequest>,
+    ) -> RpcResult<StartAgentStreamResponse> {
+        self.fork(request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+
+    async fn stop_agent_stream(
+        &self,
+        request: Request<StopAgentStreamRequest>,
+    ) -> RpcResult<StopAgentStreamResponse> {
+        let request = request.into_inner();
+        let reason = match request.reason.as_str() {
+            "" => "stopped",
+            reason => reason,
+        };
+        self.relay
+            .stop_fork(&request.relay_id, reason)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(StopAgentStreamResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn play_prompt(
+        &self,
+        request: Request<PlayPromptRequest>,
+    ) -> RpcResult<PlayPromptResponse> {
+        self.prompt(request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+
+    async fn cancel_prompt(
+        &self,
+        request: Request<CancelPromptRequest>,
+    ) -> RpcResult<CancelPromptResponse> {
+        let request = request.into_inner();
+        let cancelled = leg(&request.leg)
+            .and_then(|side| self.relay.cancel_prompts(&request.relay_id, side))
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(CancelPromptResponse {
+            success: true,
+            cancelled,
+            error: None,
+        }))
+    }
+
+    async fn set_speech_detection(
+        &self,
+        request: Request<SetSpeechDetectionRequest>,
+    ) -> RpcResult<SetSpeechDetectionResponse> {
+        let request = request.into_inner();
+        let mut vad = self.relay.vad_config();
+        if request.hangover_ms > 0 {
+            vad.hangover = Duration::from_millis(u64::from(request.hangover_ms));
+        }
+        let detection = SpeechDetection {
+            vad,
+            barge_in: request.barge_in,
+        };
+        for (side, enabled) in [
+            (Side::Caller, request.caller),
+            (Side::Callee, request.callee),
+        ] {
+            self.relay
+                .set_speech_detection(&request.relay_id, side, enabled.then_some(detection))
+                .map_err(|e| e.to_status())?;
+        }
+        Ok(Response::new(SetSpeechDetectionResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn stream_events(
+        &self,
+        request: Request<StreamEventsRequest>,
+    ) -> RpcResult<Self::StreamEventsStream> {
+        self.events(request.into_inner())
+            .map(Response::new)
+            .map_err(|e| e.to_status())
+    }
+}
+
+fn default_codecs() -> Vec<Codec> {
+    DEFAULT_CODECS
+        .iter()
+        .map(|(name, payload_type)| Codec {
+            name: (*name).to_string(),
+            payload_type: *payload_type,
+            sample_rate: 8000,
+            channels: 1,
+            parameters: HashMap::new(),
+        })
+        .collect()
+}
+
+/// Comfort noise and DTMF events ride along with the voice codec.
+fn is_auxiliary(codec: &Codec) -> bool {
+    codec.name.eq_ignore_ascii_case("telephone-event") || codec.name.eq_ignore_ascii_case("CN")
+}
+
+/// Voice codec in use: the first non-auxiliary one.
+fn primary(codecs: &[Codec]) -> Option<String> {
+    codecs
+        .iter()
+        .find(|c| !is_auxiliary(c))
+        .map(|c| c.name.clone())
+}
+
+/// Transcoder view of the first voice codec we can convert, with its DTMF events.
+fn payload_format(codecs: &[Codec]) -> Option<PayloadFormat> {
+    let (payload_type, codec) = codecs.iter().find_map(|c| {
+        let codec = AudioCodec::from_sdp(&c.name, c.sample_rate, c.channels)?;
+        Some((u8::try_from(c.payload_type).ok()?, codec))
+    })?;
+    let events = codecs
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case("telephone-event"))
+        .and_then(|c| u8::try_from(c.payload_type).ok());
+    Some(PayloadFormat {
+        payload_type,
+        codec,
+        events,
+    })
+}
+
+/// Mono mix or one leg per channel, at 8 kHz unless asked otherwise.
+fn recording_options(options: &RecordingOptions) -> recording::RecordingOptions {
+    recording::RecordingOptions {
+        channels: if options.mixed {
+            Channels::Mixed
+        } else {
+            Channels::Stereo
+        },
+        sample_rate: match options.sample_rate {
+            0 => recording::RecordingOptions::default().sample_rate,
+            rate => rate,
+        },
+        pause_fill: match options.pause_fill() {
+            PauseFill::Silence => recording::PauseFill::Silence,
+            PauseFill::Tone => recording::PauseFill::Tone,
+        },
+    }
+}
+
+fn recording_info(info: &recording::RecordingInfo) -> RecordingInfo {
+    RecordingInfo {
+        recording_id: info.recording_id.clone(),
+        file_path: info.path.display().to_string(),
+        file_size: info.file_size,
+        duration_ms: info.duration.as_millis() as u64,
+        format: RecordingFormat::FormatWav as i32,
+        started_at: Some(proto_timestamp(info.started_at)),
+        stopped_at: Some(proto_timestamp(info.stopped_at)),
+        pauses: info
+            .pauses
+            .iter()
+            .map(|pause| PauseInterval {
+                offset_ms: pause.offset.as_millis() as u64,
+                duration_ms: pause.duration.as_millis() as u64,
+                trigger: pause.trigger.name().to_owned(),
+                paused_at: Some(proto_timestamp(pause.paused_at)),
+            })
+            .collect(),
+    }
+}
+
+fn media_event(relay_id: &str, event: &RelayEvent) -> MediaEvent {
+    let (kind, event) = match event {
+        RelayEvent::Digit { side, digit } => (
+            MediaEventType::EventDtmf,
+            media_event::Event::Dtmf(DtmfEvent {
+                digit: digit.digit.to_string(),
+                duration_ms: u32::try_from(digit.duration.as_millis()).unwrap_or(u32::MAX),
+                in_band: digit.in_band,
+                leg: side.name().to_owned(),
+            }),
+        ),
+        RelayEvent::Interrupted {
+            side,
+            silent_for,
+            held,
+        } => (
+            MediaEventType::EventStreamInterruption,
+            media_event::Event::Interruption(StreamInterruptionEvent {
+                duration_ms: silent_for.as_millis() as u64,
+                reason: RelayEvent::interruption_reason(*held).to_owned(),
+                leg: side.name().to_owned(),
+                held: *held,
+            }),
+        ),
+        RelayEvent::Resumed { side, silent_for } => (
+            MediaEventType::EventStreamResumed,
+            media_event::Event::Interruption(StreamInterruptionEvent {
+                duration_ms: silent_for.as_millis() as u64,
+                reason: "media resumed".into(),
+                leg: side.name().to_owned(),
+                held: false,
+            }),
+        ),
+        RelayEvent::PacketLoss {
+            loss_percent,
+            lost_packets,
+            ..
+        } => (
+            MediaEventType::EventPacketLoss,
+            media_event::Event::PacketLoss(PacketLossEvent {
+                loss_percent: *loss_percent as f32,
+                lost_packets: u32::try_from(*lost_packets).unwrap_or(u32::MAX),
+            }),
+        ),
+        RelayEvent::HighJitter {
+            jitter_ms,
+            max_jitter_ms,
+            ..
+        } => (
+            MediaEventType::EventHighJitter,
+            media_event::Event::Jitter(JitterEvent {
+                jitter_ms: *jitter_ms as f32,
+                max_jitter_ms: *max_jitter_ms as f32,
+            }),
+        ),
+        RelayEvent::SpeechStarted { side, barge_in } => (
+            MediaEventType::EventSpeechStarted,
+            media_event::Event::Speech(SpeechEvent {
+                leg: side.name().to_owned(),
+                barge_in: *barge_in,
+                duration_ms: 0,
+            }),
+        ),
+        RelayEvent::SpeechEnded { side, duration } => (
+            MediaEventType::EventSpeechEnded,
+            media_event::Event::Speech(SpeechEvent {
+                leg: side.name().to_owned(),
+                barge_in: false,
+                duration_ms: duration.as_millis() as u64,
+            }),
+        ),
+    };
+    MediaEvent {
+        relay_id: relay_id.to_owned(),
+        r#type: kind as i32,
+        timestamp: Some(proto_timestamp(Utc::now())),
+        event: Some(event),
+    }
+}
+
+fn parse_sdp(sdp: &str) -> Result<Option<SessionDescription>> {
+    if sdp.trim().is_empty() {
+        return Ok(None);
+    }
+    SessionDescription::parse(sdp).map(Some)
+}
+
+/// Where the audio stream of `sdp` expects RTP, unless it is on hold with `0.0.0.0`.
+fn rtp_addr(sdp: &SessionDescription) -> Option<SocketAddr> {
+    let audio = sdp.audio()?;
+    let connection = sdp.connection_of(audio)?;
+    if connection.is_hold() {
+        return None;
+    }
+    let ip: IpAddr = connection.address.parse().ok()?;
+    Some(SocketAddr::new(ip, audio.port))
+}
+
+/// Leg named in a request; the caller's when unnamed.
+fn leg(name: &str) -> Result<Side> {
+    match name {
+        "" | "caller" => Ok(Side::Caller),
+        "callee" => Ok(Side::Callee),
+        other => Err(VoipError::Validation(format!("unknown leg {:?}", other))),
+    }
+}
+
+fn not_found(relay_id: &str) -> VoipError {
+    VoipError::NotFound(format!("relay {}", relay_id))
+}
+
+fn proto_timestamp(at: chrono::DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: i32::try_from(at.timestamp_subsec_nanos()).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use voip_common::proto::common::CallId;
+
+    use crate::{ports::free_range, srtp::MasterKey};
+
+    const OFFER: &str = "v=0\r\n\
+        o=alice 1 1 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        c=IN IP4 127.0.0.1\r\n\
+        t=0 0\r\n\
+        m=audio 4000 RTP/AVP 8 101\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n";
+
+    fn service() -> MediaGrpcService {
+        let (rtp_port_min, rtp_port_max) = free_range(8);
+        let config = MediaConfig {
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            rtp_port_min,
+            rtp_port_max,
+            recording_dir: std::env::temp_dir().join("voip-media-grpc-tests"),
+            ..MediaConfig::default()
+        };
+        let relay = Arc::new(MediaRelay::from_config(&config).expect("relay"));
+        MediaGrpcService::new(relay, &config)
+    }
+
+    fn start_request(remote_sdp: &str) -> Request<StartRelayRequest> {
+        Request::new(StartRelayRequest {
+            call_id: Some(CallId {
+                id: "call-1".into(),
+                ..CallId::default()
+            }),
+            remote_sdp: remote_sdp.into(),
+            ..StartRelayRequest::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn start_answers_offer_on_relay_ports_and_stop_returns_stats() {
+        let service = service();
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let answer = SessionDescription::parse(&started.negotiated_sdp).expect("answer");
+        let remote = started.remote_endpoint.expect("remote endpoint");
+        let local = started.local_endpoint.expect("local endpoint");
+        assert_eq!(answer.audio().expect("audio").port, remote.rtp_port as u16);
+        assert_ne!(remote.rtp_port, local.rtp_port);
+        let names: Vec<_> = remote.codecs.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["PCMA", "telephone-event"]);
+        let session = service.relay.session(&started.relay_id).expect("session");
+        assert_eq!(session.codec, "PCMA");
+        assert_eq!(
+            session.caller.remote,
+            Some("127.0.0.1:4000".parse().expect("addr"))
+        );
+
+        let stopped = service
+            .stop_relay(Request::new(StopRelayRequest {
+                relay_id: started.relay_id.clone(),
+                reason: "bye".into(),
+            }))
+            .await
+            .expect("stop")
+            .into_inner();
+        let stats = stopped.final_stats.expect("stats");
+        assert_eq!(
+            stats.codec_usage[0]
+                .codec
+                .as_ref()
+                .expect("codec")
+                .payload_type,
+            8
+        );
+        assert_eq!(
+            service
+                .get_stats(Request::new(GetStatsRequest {
+                    relay_id: started.relay_id,
+                }))
+                .await
+                .expect_err("stopped")
+                .code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn update_holds_resumes_and_switches_codec() {
+        let service = service();
+        let offer = OFFER.replace("RTP/AVP 8 101", "RTP/AVP 0 8 101");
+        let relay_id = service
+            .start_relay(start_request(&offer))
+            .await
+            .expect("start")
+            .into_inner()
+            .relay_id;
+        let update = |update| {
+            service.update(UpdateMediaRequest {
+                relay_id: relay_id.clone(),
+                update: Some(update),
+            })
+        };
+
+        let hold =
+            SessionDescription::parse(&update(Update::Hold(true)).expect("hold").negotiated_sdp)
+                .expect("sdp");
+        assert!(hold.is_hold());
+        assert_eq!(hold.origin.session_version, 2);
+        assert!(service.relay.session(&relay_id).expect("session").held);
+
+        let resume = update(Update::Resume(true)).expect("resume");
+        assert!(!SessionDescription::parse(&resume.negotiated_sdp)
+            .expect("sdp")
+            .is_hold());
+        assert!(!service.relay.session(&relay_id).expect("session").held);
+
+        let switched = update(Update::NewCodec(Codec {
+            name: "PCMA".into(),
+            sample_rate: 8000,
+            ..Codec::default()
+        }))
+        .expect("switch");
+        let codecs = SessionDescription::parse(&switched.negotiated_sdp)
+            .expect("sdp")
+            .audio_codecs();
+        assert_eq!(codecs[0].name, "PCMA");
+        assert_eq!(codecs.len(), 2);
+        assert_eq!(
+            service.relay.session(&relay_id).expect("session").codec,
+            "PCMA"
+        );
+
+        let err = update(Update::NewCodec(Codec {
+            name: "G729".into(),
+            sample_rate: 8000,
+            ..Codec::default()
+        }))
+        .expect_err("not negotiated");
+        assert_eq!(err.to_status().code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            service
+                .update(UpdateMediaRequest {
+                    relay_id: "missing".into(),
+                    update: Some(Update::Hold(true)),
+                })
+                .expect_err("unknown relay")
+                .to_status()
+                .code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn transcodes_when_the_legs_share_no_codec() {
+        let service = service();
+        let agent = OFFER
+            .replace("m=audio 4000", "m=audio 5000")
+            .replace("RTP/AVP 8 101", "RTP/AVP 0 101");
+        let mut request = start_request(OFFER);
+        request.get_mut().local_sdp = agent;
+        let started = service
+            .start_relay(request)
+            .await
+            .expect("start")
+            .into_inner();
+        let answer = SessionDescription::parse(&started.negotiated_sdp).expect("answer");
+        assert_eq!(answer.audio_codecs()[0].name, "PCMA");
+        let local = started.local_endpoint.expect("local endpoint");
+        assert_eq!(local.codecs[0].name, "PCMU");
+        let session = service.relay.session(&started.relay_id).expect("session");
+        let [caller, callee] = session.transcoding.expect("transcoding");
+        assert_eq!((caller.codec, caller.payload_type), (AudioCodec::Pcma, 8));
+        assert_eq!((callee.codec, callee.events), (AudioCodec::Pcmu, Some(101)));
+
+        // A re-offer with the callee's codec lets packets through untouched.
+        let offer = OFFER.replace("RTP/AVP 8 101", "RTP/AVP 0 101");
+        service
+            .update(UpdateMediaRequest {
+                relay_id: started.relay_id.clone(),
+                update: Some(Update::NewSdp(offer)),
+            })
+            .expect("re-offer");
+        let session = service.relay.session(&started.relay_id).expect("session");
+        assert_eq!(
+            (session.codec.as_str(), session.transcoding),
+            ("PCMU", None)
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_sdes_offers_and_keys_the_caller_leg() {
+        let service = service();
+        let phone_key = MasterKey::generate().expect("key");
+        let offer = OFFER.replace("RTP/AVP", "RTP/SAVP")
+            + &format!(
+                "a=crypto:3 AES_CM_128_HMAC_SHA1_32 inline:{}\r\n",
+                phone_key.to_inline()
+            );
+        let started = service
+            .start_relay(start_request(&offer))
+            .await
+            .expect("start")
+            .into_inner();
+        let answer = SessionDescription::parse(&started.negotiated_sdp).expect("answer");
+        let audio = answer.audio().expect("audio");
+        assert_eq!(audio.protocol, "RTP/SAVP");
+        let crypto = audio.crypto();
+        assert_eq!(
+            (crypto.len(), crypto[0].tag, crypto[0].suite.as_str()),
+            (1, 3, "AES_CM_128_HMAC_SHA1_32")
+        );
+        let stats = |relay_id: &str| {
+            let session = service.relay.session(relay_id).expect("session");
+            (session.caller.srtp, session.callee.srtp)
+        };
+        assert_eq!(stats(&started.relay_id), (true, false));
+
+        // A plain re-offer drops SRTP; plain offers cannot be forced to SRTP.
+        service
+            .update(UpdateMediaRequest {
+                relay_id: started.relay_id.clone(),
+                update: Some(Update::NewSdp(OFFER.to_owned())),
+            })
+            .expect("re-offer");
+        assert_eq!(stats(&started.relay_id), (false, false));
+        let mut request = start_request(OFFER);
+        request.get_mut().enable_srtp = true;
+        assert!(service.start_relay(request).await.is_err());
+        let unknown = OFFER.replace("RTP/AVP", "RTP/SAVP")
+            + "a=crypto:1 AEAD_AES_256_GCM inline:c2VjcmV0\r\n";
+        assert!(service.start_relay(start_request(&unknown)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn records_relays_to_wav_until_stopped() {
+        let service = service();
+        let mut request = start_request(OFFER);
+        request.get_mut().enable_recording = true;
+        let recorded = service
+            .start_relay(request)
+            .await
+            .expect("start")
+            .into_inner();
+        let other = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let record = |format: RecordingFormat| {
+            service.start_recording(Request::new(StartRecordingRequest {
+                relay_id: other.relay_id.clone(),
+                format: format as i32,
+                options: Some(RecordingOptions {
+                    mixed: true,
+                    sample_rate: 16000,
+                    ..RecordingOptions::default()
+                }),
+            }))
+        };
+        let status = record(RecordingFormat::FormatMp3).await.expect_err("mp3");
+        assert_eq!(status.code(), tonic::Code::Unimplemented);
+        let started = record(RecordingFormat::FormatWav)
+            .await
+            .expect("record")
+            .into_inner();
+        assert!(started
+            .file_path
+            .ends_with(&format!("{}.wav", started.recording_id)));
+
+        let stop = |recording_id: String| {
+            service.stop_recording(Request::new(StopRecordingRequest { recording_id }))
+        };
+        for recording_id in [recorded.relay_id.clone(), started.recording_id.clone()] {
+            let info = stop(recording_id.clone())
+                .await
+                .expect("stop")
+                .into_inner()
+                .info
+                .expect("info");
+            assert_eq!(info.recording_id, recording_id);
+            assert_eq!(info.format, RecordingFormat::FormatWav as i32);
+            assert!(info.file_size >= 44 && info.stopped_at.is_some());
+            std::fs::remove_file(&info.file_path).expect("cleanup");
+        }
+        let status = stop(started.recording_id).await.expect_err("stopped twice");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn pauses_recordings_on_request_and_on_dtmf() {
+        let service = service();
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let record = |pause_digits: &str, resume_digits: &str| {
+            service.start_recording(Request::new(StartRecordingRequest {
+                relay_id: started.relay_id.clone(),
+                options: Some(RecordingOptions {
+                    pause_fill: PauseFill::Tone as i32,
+                    pause_digits: pause_digits.into(),
+                    resume_digits: resume_digits.into(),
+                    ..RecordingOptions::default()
+                }),
+                ..StartRecordingRequest::default()
+            }))
+        };
+        for (pause, resume) in [("*1", ""), ("*x", "*2"), ("1", "*1")] {
+            let status = record(pause, resume).await.expect_err("invalid digits");
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        let recording_id = record("*1", "*2")
+            .await
+            .expect("record")
+            .into_inner()
+            .recording_id;
+        let pause = || {
+            service.pause_recording(Request::new(PauseRecordingRequest {
+                recording_id: recording_id.clone(),
+            }))
+        };
+        let resume = || {
+            service.resume_recording(Request::new(ResumeRecordingRequest {
+                recording_id: recording_id.clone(),
+            }))
+        };
+        assert!(resume().await.is_err());
+        pause().await.expect("pause");
+        assert!(pause().await.is_err());
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        resume().await.expect("resume");
+
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("phone");
+        let port = started.remote_endpoint.expect("remote endpoint").rtp_port as u16;
+        let mut sequence = 0u16;
+        for (timestamp, digit) in [(0, '*'), (800, '1')] {
+            let packets = crate::dtmf::generate(
+                digit,
+                std::time::Duration::from_millis(40),
+                8000,
+                std::time::Duration::from_millis(20),
+            )
+            .expect("events");
+            for packet in &packets {
+                let rtp = crate::rtp::RtpPacket::new(
+                    101,
+                    sequence,
+                    timestamp,
+                    3,
+                    packet.event.to_bytes().to_vec(),
+                );
+                sequence += 1;
+                phone
+                    .send_to(&rtp.to_bytes().expect("encode"), ("127.0.0.1", port))
+                    .await
+                    .expect("send");
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        // The digits paused the recording, so only a resume is accepted.
+        assert!(pause().await.is_err());
+
+        let info = service
+            .stop_recording(Request::new(StopRecordingRequest {
+                recording_id: recording_id.clone(),
+            }))
+            .await
+            .expect("stop")
+            .into_inner()
+            .info
+            .expect("info");
+        let triggers: Vec<_> = info.pauses.iter().map(|p| p.trigger.as_str()).collect();
+        assert_eq!(triggers, ["api", "dtmf"]);
+        assert!(info.pauses[0].duration_ms >= 50);
+        assert!(info.pauses[1].offset_ms > info.pauses[0].offset_ms);
+        assert!(info.pauses.iter().all(|p| p.paused_at.is_some()));
+        std::fs::remove_file(&info.file_path).expect("cleanup");
+        assert_eq!(
+            resume().await.expect_err("stopped").code(),
+            tonic::Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_events_delivers_digits_of_the_requested_relays() {
+        let service = service();
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let subscribe = |relay_ids: Vec<String>| {
+            service.stream_events(Request::new(StreamEventsRequest {
+                relay_ids,
+                event_types: vec![MediaEventType::EventDtmf as i32],
+            }))
+        };
+        let mut events = subscribe(vec![started.relay_id.clone()])
+            .await
+            .expect("subscribe")
+            .into_inner()
+            .into_inner();
+        for relay_ids in [vec![], vec!["missing".to_owned()]] {
+            assert!(subscribe(relay_ids).await.is_err());
+        }
+
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("phone");
+        let port = started.remote_endpoint.expect("remote endpoint").rtp_port as u16;
+        let packets = crate::dtmf::generate(
+            '4',
+            std::time::Duration::from_millis(40),
+            8000,
+            std::time::Duration::from_millis(20),
+        )
+        .expect("events");
+        for (sequence, packet) in packets.iter().enumerate() {
+            let rtp = crate::rtp::RtpPacket::new(
+                101,
+                sequence as u16,
+                0,
+                3,
+                packet.event.to_bytes().to_vec(),
+            );
+            phone
+                .send_to(&rtp.to_bytes().expect("encode"), ("127.0.0.1", port))
+                .await
+                .expect("send");
+        }
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(1), events.recv())
+            .await
+            .expect("event")
+            .expect("open")
+            .expect("ok");
+        assert_eq!(event.relay_id, started.relay_id);
+        assert_eq!(
+            event.event,
+            Some(media_event::Event::Dtmf(DtmfEvent {
+                digit: "4".into(),
+                duration_ms: 40,
+                in_band: false,
+                leg: "caller".into(),
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_events_reports_jitter_above_the_configured_threshold() {
+        let service = service();
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let mut events = service
+            .stream_events(Request::new(StreamEventsRequest {
+                relay_ids: vec![started.relay_id.clone()],
+                event_types: vec![MediaEventType::EventHighJitter as i32],
+            }))
+            .await
+            .expect("subscribe")
+            .into_inner()
+            .into_inner();
+
+        // Lossy and sent back to back for 200 ms of audio each: loss is
+        // announced first but filtered out.
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("phone");
+        let port = started.remote_endpoint.expect("remote endpoint").rtp_port as u16;
+        for sequence in (0..60u16).filter(|sequence| sequence % 4 != 3) {
+            let rtp = crate::rtp::RtpPacket::new(
+                0,
+                sequence,
+                u32::from(sequence) * 1600,
+                3,
+                vec![0xff; 160],
+            );
+            phone
+                .send_to(&rtp.to_bytes().expect("encode"), ("127.0.0.1", port))
+                .await
+                .expect("send");
+        }
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(2), events.recv())
+            .await
+            .expect("event")
+            .expect("open")
+            .expect("ok");
+        assert_eq!(
+            (event.relay_id, event.r#type),
+            (started.relay_id, MediaEventType::EventHighJitter as i32)
+        );
+        let Some(media_event::Event::Jitter(jitter)) = event.event else {
+            panic!("expected jitter");
+        };
+        assert!(jitter.jitter_ms > 30.0 && jitter.max_jitter_ms >= jitter.jitter_ms);
+    }
+
+    #[tokio::test]
+    async fn agent_streams_start_with_the_call_and_stop_on_request() {
+        use crate::fork::{
+            mock::{MockAgent, Received},
+            protocol::RelayMessage,
+        };
+
+        let service = service();
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let mut agent = MockAgent::start().await.expect("agent");
+        let fork = |url: &str| {
+            service.start_agent_stream(Request::new(StartAgentStreamRequest {
+                relay_id: started.relay_id.clone(),
+                url: url.into(),
+                tracks: AgentTracks::Caller as i32,
+                metadata: HashMap::from([("tenant".to_owned(), "acme".to_owned())]),
+                ..StartAgentStreamRequest::default()
+            }))
+        };
+        let status = fork("http://127.0.0.1:1").await.expect_err("not ws");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let stream_id = fork(agent.url())
+            .await
+            .expect("fork")
+            .into_inner()
+            .stream_id;
+        assert_eq!(
+            fork(agent.url()).await.expect_err("forked").code(),
+            tonic::Code::AlreadyExists
+        );
+        for (call_id, code) in [
+            ("call-1", tonic::Code::AlreadyExists),
+            ("call-9", tonic::Code::NotFound),
+            ("", tonic::Code::InvalidArgument),
+        ] {
+            let request = StartAgentStreamRequest {
+                call_id: call_id.into(),
+                url: agent.url().into(),
+                ..StartAgentStreamRequest::default()
+            };
+            let status = service
+                .start_agent_stream(Request::new(request))
+                .await
+                .expect_err("not forked");
+            assert_eq!(status.code(), code);
+        }
+
+        let Some(Received::Message(RelayMessage::Start {
+            stream_id: announced,
+            relay_id,
+            call_id,
+            media_format,
+            tracks,
+            metadata,
+            ..
+        })) = agent.recv().await
+        else {
+            panic!("expected start");
+        };
+        assert_eq!(
+            (announced.as_str(), relay_id),
+            (stream_id.as_str(), started.relay_id.clone())
+        );
+        assert_eq!(
+            (call_id.as_str(), media_format.sample_rate),
+            ("call-1", 8000)
+        );
+        assert_eq!(tracks, ["caller"]);
+        assert_eq!(metadata["tenant"], "acme");
+
+        service
+            .stop_agent_stream(Request::new(StopAgentStreamRequest {
+                relay_id: started.relay_id.clone(),
+                reason: "transfer".into(),
+            }))
+            .await
+            .expect("stop");
+        assert_eq!(
+            agent.recv().await,
+            Some(Received::Message(RelayMessage::Stop {
+                stream_id,
+                reason: "transfer".into()
+            }))
+        );
+        let status = service
+            .stop_agent_stream(Request::new(StopAgentStreamRequest {
+                relay_id: started.relay_id.clone(),
+                ..StopAgentStreamRequest::default()
+            }))
+            .await
+            .expect_err("stopped");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn prompts_speak_texts_until_cancelled() {
+        let service = service().with_tts(Arc::new(crate::tts::fake::FakeTts));
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let request = |source: Source, leg: &str| PlayPromptRequest {
+            relay_id: started.relay_id.clone(),
+            source: Some(source),
+            leg: leg.into(),
+            wait: true,
+            ..PlayPromptRequest::default()
+        };
+        let play = |request: PlayPromptRequest| {
+            let service = service.clone();
+            async move { service.play_prompt(Request::new(request)).await }
+        };
+
+        let begun = std::time::Instant::now();
+        let spoken = play(request(Source::Text("hello there".into()), ""))
+            .await
+            .expect("play")
+            .into_inner();
+        assert!(spoken.completed && !spoken.prompt_id.is_empty());
+        assert!(begun.elapsed() >= std::time::Duration::from_millis(400));
+
+        let endless = tokio::spawn(play(request(
+            Source::Text("please hold ".repeat(50)),
+            "callee",
+        )));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let cancelled = service
+            .cancel_prompt(Request::new(CancelPromptRequest {
+                relay_id: started.relay_id.clone(),
+                leg: "callee".into(),
+            }))
+            .await
+            .expect("cancel")
+            .into_inner();
+        assert!(cancelled.cancelled);
+        let interrupted = endless.await.expect("join").expect("play").into_inner();
+        assert!(!interrupted.completed);
+
+        for (source, leg, code) in [
+            (
+                Source::Text("hi".into()),
+                "operator",
+                tonic::Code::InvalidArgument,
+            ),
+            (
+                Source::FilePath("/nonexistent.wav".into()),
+                "",
+                tonic::Code::NotFound,
+            ),
+        ] {
+            let status = play(request(source, leg)).await.expect_err("rejected");
+            assert_eq!(status.code(), code);
+        }
+        let mut silent = service.clone();
+        silent.tts = None;
+        let status = silent
+            .play_prompt(Request::new(request(Source::Text("hi".into()), "")))
+            .await
+            .expect_err("no engine");
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn callers_talking_over_a_prompt_stop_it() {
+        let service = service().with_tts(Arc::new(crate::tts::fake::FakeTts));
+        let started = service
+            .start_relay(start_request(OFFER))
+            .await
+            .expect("start")
+            .into_inner();
+        let detect = |relay_id: &str| {
+            service.set_speech_detection(Request::new(SetSpeechDetectionRequest {
+                relay_id: relay_id.into(),
+                caller: true,
+                barge_in: true,
+                hangover_ms: 200,
+                ..SetSpeechDetectionRequest::default()
+            }))
+        };
+        assert_eq!(
+            detect("missing").await.expect_err("unknown").code(),
+            tonic::Code::NotFound
+        );
+        assert!(
+            detect(&started.relay_id)
+                .await
+                .expect("detect")
+                .into_inner()
+                .success
+        );
+        let mut events = service
+            .stream_events(Request::new(StreamEventsRequest {
+                relay_ids: vec![started.relay_id.clone()],
+                event_types: vec![
+                    MediaEventType::EventSpeechStarted as i32,
+                    MediaEventType::EventSpeechEnded as i32,
+                ],
+            }))
+            .await
+            .expect("subscribe")
+            .into_inner()
+            .into_inner();
+        let prompt = {
+            let service = service.clone();
+            let request = PlayPromptRequest {
+                relay_id: started.relay_id.clone(),
+                source: Some(Source::Text("please hold ".repeat(50))),
+                wait: true,
+                ..PlayPromptRequest::default()
+            };
+            tokio::spawn(async move { service.play_prompt(Request::new(request)).await })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let phone = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("phone");
+        let port = started.remote_endpoint.expect("remote endpoint").rtp_port as u16;
+        let tone = |n: usize| {
+            let t = n as f64 / 8000.0;
+            let x = 3000.0 * (2.0 * std::f64::consts::PI * 300.0 * t).sin();
+            crate::codec::g711::alaw_encode(x as i16)
+        };
+        for sequence in 0..25u16 {
+            let start = usize::from(sequence) * 160;
+            let payload: Vec<u8> = if sequence < 10 {
+                (start..start + 160).map(tone).collect()
+            } else {
+                vec![0xd5; 160]
+            };
+            let rtp =
+                crate::rtp::RtpPacket::new(8, sequence, u32::from(sequence) * 160, 3, payload);
+            phone
+                .send_to(&rtp.to_bytes().expect("encode"), ("127.0.0.1", port))
+                .await
+                .expect("send");
+        }
+
+        let mut speech = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(std::time::Duration::from_secs(1), events.recv())
+                .await
+                .expect("event")
+                .expect("open")
+                .expect("ok");
+            let Some(media_event::Event::Speech(detail)) = event.event else {
+                panic!("expected speech");
+            };
+            speech.push((event.r#type, detail));
+        }
+        assert_eq!(
+            speech,
+            [
+                (
+                    MediaEventType::EventSpeechStarted as i32,
+                    SpeechEvent {
+                        leg: "caller".into(),
+                        barge_in: true,
+                        duration_ms: 0,
+                    }
+                ),
+                (
+                    MediaEventType::EventSpeechEnded as i32,
+                    SpeechEvent {
+                        leg: "caller".into(),
+                        barge_in: false,
+                        duration_ms: 200,
+                    }
+                ),
+            ]
+        );
+        let interrupted = prompt.await.expect("join").expect("play").into_inner();
+        assert!(!interrupted.completed);
+    }
+}
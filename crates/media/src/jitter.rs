@@ -0,0 +1,402 @@
+//! Adaptive jitter buffer for paths that terminate media.
+//!
+//! Packets are reordered by sequence number and released when their RTP
+//! timestamp falls due: `min transit + timestamp + delay`, where the minimum
+//! transit is the fastest arrival seen so far. The delay follows the RFC 3550
+//! jitter estimate, growing at once and shrinking gradually. Time is passed in
+//! by the caller so traces replay deterministically.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::rtp::RtpPacket;
+
+/// Delay kept per unit of estimated jitter.
+const JITTER_FACTOR: f64 = 4.0;
+/// Largest delay reduction applied per packet.
+const SHRINK_STEP: Duration = Duration::from_millis(1);
+
+/// Sizing of a [`JitterBuffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterBufferConfig {
+    /// RTP clock rate of the payload.
+    pub clock_rate: u32,
+    /// Delay before the first adaptation.
+    pub initial_delay: Duration,
+    /// Lower bound of the adaptive delay.
+    pub min_delay: Duration,
+    /// Upper bound of the adaptive delay.
+    pub max_delay: Duration,
+    /// Packets held at most; later ones are rejected.
+    pub capacity: usize,
+}
+
+impl Default for JitterBufferConfig {
+    fn default() -> Self {
+        Self {
+            clock_rate: 8000,
+            initial_delay: Duration::from_millis(60),
+            min_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(200),
+            capacity: 64,
+        }
+    }
+}
+
+/// What became of a pushed packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pushed {
+    /// Queued for playout.
+    Buffered,
+    /// Its slot was already played out or concealed.
+    Late,
+    /// The same sequence number is already queued.
+    Duplicate,
+    /// The buffer is full.
+    Overflow,
+}
+
+/// The next frame to play.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// A received packet.
+    Packet(RtpPacket<'static>),
+    /// No packet arrived in time; the decoder should conceal this frame.
+    Missing {
+        /// Sequence number of the missing packet.
+        sequence: u16,
+        /// Its estimated timestamp.
+        timestamp: u32,
+    },
+}
+
+/// Counters since the buffer was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterBufferStats {
+    /// Packets accepted into the buffer.
+    pub buffered: u64,
+    /// Packets played out.
+    pub played: u64,
+    /// Frames reported missing.
+    pub concealed: u64,
+    /// Packets discarded because their slot had passed.
+    pub late: u64,
+    /// Packets discarded as duplicates.
+    pub duplicates: u64,
+    /// Packets rejected because the buffer was full.
+    pub overflow: u64,
+}
+
+/// Unwraps a 16- or 32-bit RTP counter against the last value seen.
+#[derive(Debug, Clone, Copy, Default)]
+struct Unwrapper {
+    last: u32,
+    extended: i64,
+}
+
+impl Unwrapper {
+    fn sequence(&mut self, sequence: u16) -> i64 {
+        let delta = i64::from(sequence.wrapping_sub(self.last as u16) as i16);
+        self.advance(u32::from(sequence), delta)
+    }
+
+    fn timestamp(&mut self, timestamp: u32) -> i64 {
+        let delta = i64::from(timestamp.wrapping_sub(self.last) as i32);
+        self.advance(timestamp, delta)
+    }
+
+    fn advance(&mut self, value: u32, delta: i64) -> i64 {
+        let extended = self.extended + delta;
+        if delta > 0 {
+            self.last = value;
+            self.extended = extended;
+        }
+        extended
+    }
+}
+
+/// Reorders RTP from one source and releases it at an adaptive delay.
+#[derive(Debug, Clone)]
+pub struct JitterBuffer {
+    config: JitterBufferConfig,
+    ssrc: Option<u32>,
+    /// First sequence number and timestamp; extended values count from them.
+    base: (u16, u32),
+    sequences: Unwrapper,
+    timestamps: Unwrapper,
+    /// Queued packets by extended sequence, with their extended timestamp.
+    packets: BTreeMap<i64, (i64, RtpPacket<'static>)>,
+    /// Extended sequence number of the next frame to play.
+    next: i64,
+    /// Timestamp units per packet, learnt from consecutive packets.
+    frame: i64,
+    /// Smallest `arrival - timestamp` seen, in seconds.
+    min_transit: f64,
+    last_transit: Option<f64>,
+    jitter: f64,
+    delay: Duration,
+    stats: JitterBufferStats,
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self::new(JitterBufferConfig::default())
+    }
+}
+
+impl JitterBuffer {
+    /// Empty buffer sized by `config`.
+    pub fn new(config: JitterBufferConfig) -> Self {
+        let config = JitterBufferConfig {
+            clock_rate: config.clock_rate.max(1),
+            max_delay: config.max_delay.max(config.min_delay),
+            ..config
+        };
+        Self {
+            ssrc: None,
+            base: (0, 0),
+            sequences: Unwrapper::default(),
+            timestamps: Unwrapper::default(),
+            packets: BTreeMap::new(),
+            next: 0,
+            frame: i64::from(config.clock_rate / 50),
+            min_transit: 0.0,
+            last_transit: None,
+            jitter: 0.0,
+            delay: config
+                .initial_delay
+                .clamp(config.min_delay, config.max_delay),
+            stats: JitterBufferStats::default(),
+            config,
+        }
+    }
+
+    /// Queue a packet that arrived `arrival` after an arbitrary fixed origin.
+    ///
+    /// A new SSRC restarts playout; counters are kept.
+    pub fn push(&mut self, packet: RtpPacket<'_>, arrival: Duration) -> Pushed {
+        if self.ssrc != Some(packet.ssrc) {
+            *self = Self {
+                stats: self.stats,
+                ..Self::new(self.config)
+            };
+            self.ssrc = Some(packet.ssrc);
+            self.base = (packet.sequence, packet.timestamp);
+            self.sequences.last = u32::from(packet.sequence);
+            self.timestamps.last = packet.timestamp;
+        }
+        let highest = self.sequences.extended;
+        let highest_timestamp = self.timestamps.extended;
+        let sequence = self.sequences.sequence(packet.sequence);
+        let timestamp = self.timestamps.timestamp(packet.timestamp);
+        if sequence == highest + 1 && timestamp > highest_timestamp {
+            self.frame = timestamp - highest_timestamp;
+        }
+        self.adapt(timestamp, arrival);
+
+        if sequence < self.next {
+            self.stats.late += 1;
+            return Pushed::Late;
+        }
+        if self.packets.contains_key(&sequence) {
+            self.stats.duplicates += 1;
+            return Pushed::Duplicate;
+        }
+        if self.packets.len() >= self.config.capacity {
+            self.stats.overflow += 1;
+            return Pushed::Overflow;
+        }
+        self.packets
+            .insert(sequence, (timestamp, packet.into_owned()));
+        self.stats.buffered += 1;
+        Pushed::Buffered
+    }
+
+    /// Next frame due at `now`, if any; `None` while waiting or empty.
+    pub fn pop(&mut self, now: Duration) -> Option<Playout> {
+        let (&sequence, &(timestamp, _)) = self.packets.first_key_value()?;
+        if sequence == self.next {
+            if now < self.due(timestamp) {
+                return None;
+            }
+            let (_, packet) = self.packets.remove(&sequence)?;
+            self.next += 1;
+            self.stats.played += 1;
+            return Some(Playout::Packet(packet));
+        }
+        let missing = timestamp - (sequence - self.next) * self.frame;
+        if now < self.due(missing) {
+            return None;
+        }
+        let playout = Playout::Missing {
+            sequence: self.base.0.wrapping_add(self.next as u16),
+            timestamp: self.base.1.wrapping_add(missing as u32),
+        };
+        self.next += 1;
+        self.stats.concealed += 1;
+        Some(playout)
+    }
+
+    /// Every frame due at `now`, in order.
+    pub fn drain(&mut self, now: Duration) -> Vec<Playout> {
+        std::iter::from_fn(|| self.pop(now)).collect()
+    }
+
+    /// Current playout delay.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Estimated interarrival jitter.
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+
+    /// Packets waiting for playout.
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Whether nothing waits for playout.
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Counters so far.
+    pub fn stats(&self) -> JitterBufferStats {
+        self.stats
+    }
+
+    /// Sizing in use.
+    pub fn config(&self) -> &JitterBufferConfig {
+        &self.config
+    }
+
+    fn due(&self, timestamp: i64) -> Duration {
+        let media = timestamp as f64 / f64::from(self.config.clock_rate);
+        Duration::from_secs_f64((self.min_transit + media).max(0.0)) + self.delay
+    }
+
+    fn adapt(&mut self, timestamp: i64, arrival: Duration) {
+        let transit = arrival.as_secs_f64() - timestamp as f64 / f64::from(self.config.clock_rate);
+        match self.last_transit {
+            Some(last) => {
+                self.jitter += ((transit - last).abs() - self.jitter) / 16.0;
+                self.min_transit = self.min_transit.min(transit);
+            }
+            None => self.min_transit = transit,
+        }
+        self.last_transit = Some(transit);
+
+        let target = Duration::from_secs_f64(self.jitter * JITTER_FACTOR)
+            .clamp(self.config.min_delay, self.config.max_delay);
+        self.delay = if target > self.delay {
+            target
+        } else {
+            target.max(self.delay.saturating_sub(SHRINK_STEP))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn packet(sequence: u16) -> RtpPacket<'static> {
+        RtpPacket::new(
+            0,
+            sequence,
+            u32::from(sequence).wrapping_mul(160),
+            0x5eed,
+            vec![sequence as u8],
+        )
+    }
+
+    fn played(frames: &[Playout]) -> Vec<Option<u16>> {
+        frames
+            .iter()
+            .map(|frame| match frame {
+                Playout::Packet(packet) => Some(packet.sequence),
+                Playout::Missing { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorders_and_releases_packets_at_the_buffer_delay() {
+        let mut buffer = JitterBuffer::default();
+        for (sequence, arrival) in [(0, 0), (2, 40), (1, 45), (3, 60)] {
+            assert_eq!(buffer.push(packet(sequence), ms(arrival)), Pushed::Buffered);
+        }
+        let delay = buffer.delay();
+        assert!(delay >= ms(20) && delay <= ms(60), "{:?}", delay);
+
+        assert!(buffer.drain(delay - ms(1)).is_empty());
+        assert_eq!(played(&buffer.drain(delay)), [Some(0)]);
+        assert_eq!(
+            played(&buffer.drain(delay + ms(60))),
+            [Some(1), Some(2), Some(3)]
+        );
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.stats().played, 4);
+    }
+
+    #[test]
+    fn conceals_missing_frames_and_discards_late_packets() {
+        let mut buffer = JitterBuffer::default();
+        buffer.push(packet(10), ms(0));
+        buffer.push(packet(12), ms(40));
+        assert_eq!(buffer.push(packet(12), ms(41)), Pushed::Duplicate);
+        let frames = buffer.drain(ms(200));
+        assert_eq!(played(&frames), [Some(10), None, Some(12)]);
+        assert_eq!(
+            frames[1],
+            Playout::Missing {
+                sequence: 11,
+                timestamp: 11 * 160
+            }
+        );
+
+        assert_eq!(buffer.push(packet(11), ms(210)), Pushed::Late);
+        let stats = buffer.stats();
+        assert_eq!((stats.concealed, stats.late, stats.duplicates), (1, 1, 1));
+    }
+
+    #[test]
+    fn delay_grows_with_jitter_and_shrinks_once_it_settles() {
+        let mut buffer = JitterBuffer::default();
+        // 20 ms packets, every other one 30 ms late.
+        for sequence in 0..100u16 {
+            let arrival = ms(u64::from(sequence) * 20 + u64::from(sequence % 2) * 30);
+            buffer.push(packet(sequence), arrival);
+            buffer.drain(arrival);
+        }
+        let jittery = buffer.delay();
+        assert!(jittery >= ms(100), "{:?}", jittery);
+        assert_eq!(buffer.stats().late, 0);
+
+        for sequence in 100..400u16 {
+            let arrival = ms(u64::from(sequence) * 20);
+            buffer.push(packet(sequence), arrival);
+            buffer.drain(arrival);
+        }
+        assert_eq!(buffer.delay(), buffer.config().min_delay);
+        assert_eq!(buffer.stats().concealed, 0);
+    }
+
+    #[test]
+    fn capacity_is_bounded_across_sequence_wrap() {
+        let mut buffer = JitterBuffer::new(JitterBufferConfig {
+            capacity: 2,
+            ..JitterBufferConfig::default()
+        });
+        assert_eq!(buffer.push(packet(65535), ms(0)), Pushed::Buffered);
+        assert_eq!(buffer.push(packet(0), ms(20)), Pushed::Buffered);
+        assert_eq!(buffer.push(packet(1), ms(40)), Pushed::Overflow);
+        assert_eq!(played(&buffer.drain(ms(1000))), [Some(65535), Some(0)]);
+        assert_eq!(buffer.push(packet(1), ms(1000)), Pushed::Buffered);
+    }
+}
@@ -1,35 +1,868 @@
 //! Media relay façade managing RTP proxies and QoS telemetry.
 
-use std::time::Duration;
+pub mod codec;
+pub mod dtmf;
+pub mod emodel;
+pub mod fork;
+pub mod grpc;
+pub mod jitter;
+pub mod playback;
+pub mod ports;
+pub mod prompt;
+pub mod qos;
+pub mod recording;
+pub mod relay;
+pub mod rtcp;
+pub mod rtp;
+pub mod srtp;
+pub mod stt;
+pub mod transcode;
+pub mod tts;
+pub mod vad;
 
-use tokio::{sync::watch, time};
-use tracing::{debug, instrument};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use voip_common::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio::{
+    sync::{broadcast, watch},
+    time,
+};
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
 
-/// Placeholder struct describing a media relay session.
+use voip_common::{
+    events::{
+        publish_event, subjects, DtmfEvent, MediaStoppedEvent, MediaTimeoutEvent, SpeechEvent,
+    },
+    types::ServiceConfig,
+    EventSink, Result, VoipError,
+};
+
+use crate::{
+    emodel::Score,
+    fork::{AgentFork, ForkOptions, ForkedCall},
+    playback::Playback,
+    ports::PortAllocator,
+    prompt::{Prompt, PromptHandle, Prompter},
+    recording::{PauseTrigger, Recorder, RecordingControl, RecordingInfo, RecordingOptions},
+    relay::{
+        InactivityTimeouts, LegStats, QosThresholds, RelayEvent, RelayHandle, Side, SpeechDetection,
+    },
+    srtp::SrtpPolicy,
+    transcode::PayloadFormat,
+    vad::VadConfig,
+};
+
+/// Port the media service's gRPC server listens on, clear of the signalling
+/// service's `ServiceConfig` default of 50051.
+pub const DEFAULT_GRPC_PORT: u16 = 50052;
+
+/// Media settings, read from the `media` object of `ServiceConfig.extra`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Address the RTP/RTCP sockets bind to.
+    pub bind_ip: IpAddr,
+    /// Lowest RTP port handed out (rounded up to even).
+    pub rtp_port_min: u16,
+    /// Highest port of the range, RTCP included.
+    pub rtp_port_max: u16,
+    /// Address written in SDP instead of `bind_ip`.
+    pub advertised_ip: Option<IpAddr>,
+    /// Directory recordings are written to, created on first use.
+    pub recording_dir: PathBuf,
+    /// Milliseconds without RTP after which a leg is interrupted; 0 disables.
+    pub rtp_timeout_ms: u64,
+    /// Milliseconds without RTP or RTCP on hold; 0 disables.
+    pub hold_timeout_ms: u64,
+    /// Packet loss in percent above which a leg is announced; 0 disables.
+    pub packet_loss_alert_percent: f64,
+    /// Jitter in milliseconds above which a leg is announced; 0 disables.
+    pub jitter_alert_ms: f64,
+    /// `ws://` URL of the speech synthesizer text prompts are spoken with.
+    pub tts_url: Option<String>,
+    /// Level in dBFS below which speech detection hears no speech.
+    pub vad_threshold_db: f64,
+    /// Milliseconds of silence that end a party's speech.
+    pub vad_hangover_ms: u64,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            bind_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            rtp_port_min: ports::DEFAULT_RTP_PORTS.0,
+            rtp_port_max: ports::DEFAULT_RTP_PORTS.1,
+            advertised_ip: None,
+            recording_dir: PathBuf::from("recordings"),
+            rtp_timeout_ms: 30_000,
+            hold_timeout_ms: 300_000,
+            packet_loss_alert_percent: 5.0,
+            jitter_alert_ms: 30.0,
+            tts_url: None,
+            vad_threshold_db: VadConfig::default().threshold_db,
+            vad_hangover_ms: VadConfig::default().hangover.as_millis() as u64,
+        }
+    }
+}
+
+impl MediaConfig {
+    /// Read from `extra.media`, falling back to the defaults.
+    pub fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        match config.extra.get("media") {
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|e| VoipError::Config(format!("invalid media config: {}", e))),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Inactivity timeouts handed to every relay.
+    pub fn inactivity_timeouts(&self) -> InactivityTimeouts {
+        let timeout = |ms| (ms > 0).then(|| Duration::from_millis(ms));
+        InactivityTimeouts {
+            rtp: timeout(self.rtp_timeout_ms),
+            held: timeout(self.hold_timeout_ms),
+        }
+    }
+
+    /// Loss and jitter alert levels handed to every relay.
+    pub fn qos_thresholds(&self) -> QosThresholds {
+        let threshold = |value: f64| (value > 0.0).then_some(value);
+        QosThresholds {
+            loss_percent: threshold(self.packet_loss_alert_percent),
+            jitter_ms: threshold(self.jitter_alert_ms),
+        }
+    }
+
+    /// Tuning of the speech detection enabled on legs.
+    pub fn vad_config(&self) -> VadConfig {
+        VadConfig {
+            threshold_db: self.vad_threshold_db,
+            hangover: Duration::from_millis(self.vad_hangover_ms),
+            ..VadConfig::default()
+        }
+    }
+
+    /// Address peers should send media to: `advertised_ip`, else `bind_ip`, else loopback.
+    pub fn advertised_ip(&self) -> IpAddr {
+        match self.advertised_ip {
+            Some(ip) => ip,
+            None if self.bind_ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            None => self.bind_ip,
+        }
+    }
+}
+
+/// Snapshot of a media relay session.
 #[derive(Debug, Clone)]
 pub struct MediaSession {
     /// Unique identifier for the media flow.
     pub session_id: String,
     /// Preferred codec negotiated for the session.
     pub codec: String,
+    /// Leg facing the calling party.
+    pub caller: LegStats,
+    /// Leg facing the called party.
+    pub callee: LegStats,
+    /// When relaying started.
+    pub started_at: DateTime<Utc>,
+    /// Whether forwarding is suspended for hold.
+    pub held: bool,
+    /// Traffic relayed under each codec, in order of first use.
+    pub codec_usage: Vec<CodecUsage>,
+    /// `[caller, callee]` formats when the legs are transcoded.
+    pub transcoding: Option<[PayloadFormat; 2]>,
+    /// Recording in progress, by id.
+    pub recording_id: Option<String>,
+    /// Stream to an agent in progress, by id.
+    pub agent_stream_id: Option<String>,
+}
+
+impl MediaSession {
+    /// Score of the worse direction, the one a CDR should carry.
+    pub fn score(&self) -> Option<Score> {
+        match (self.caller.score, self.callee.score) {
+            (Some(a), Some(b)) => Some(if a.r_factor <= b.r_factor { a } else { b }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Traffic relayed while a codec was in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecUsage {
+    /// Codec name.
+    pub codec: String,
+    /// Datagrams received from both legs.
+    pub packets: u64,
+    /// Bytes received from both legs.
+    pub bytes: u64,
 }
 
-/// Media relay manager in charge of supervising sessions.
 #[derive(Debug)]
+struct Session {
+    codec: String,
+    started_at: DateTime<Utc>,
+    /// Codec switches with the (packets, bytes) totals at the time.
+    switches: Vec<(String, u64, u64)>,
+    relay: RelayHandle,
+    recording: Option<Recorder>,
+    fork: Option<AgentFork>,
+    /// Prompts of each leg; the caller's queue is the agent's while forked.
+    prompters: HashMap<Side, Prompter>,
+    /// Call carried, shared with the task publishing its events.
+    call: Arc<Mutex<CallIds>>,
+}
+
+/// Signalling identifiers of the call a session carries.
+#[derive(Debug, Clone, Default)]
+struct CallIds {
+    call_id: String,
+    sip_call_id: String,
+}
+
+impl Session {
+    fn is_forked(&self) -> bool {
+        self.fork.as_ref().is_some_and(|fork| !fork.is_finished())
+    }
+
+    fn totals(&self) -> (u64, u64) {
+        let caller = self.relay.stats(Side::Caller);
+        let callee = self.relay.stats(Side::Callee);
+        (
+            caller.packets_received + callee.packets_received,
+            caller.bytes_received + callee.bytes_received,
+        )
+    }
+
+    fn snapshot(&self, session_id: &str) -> MediaSession {
+        let mut codec_usage: Vec<CodecUsage> = Vec::new();
+        let ends = self.switches.iter().skip(1).map(|(_, p, b)| (*p, *b));
+        for ((codec, packets, bytes), end) in self
+            .switches
+            .iter()
+            .zip(ends.chain(std::iter::once(self.totals())))
+        {
+            let (packets, bytes) = (end.0 - packets, end.1 - bytes);
+            match codec_usage.iter_mut().find(|usage| &usage.codec == codec) {
+                Some(usage) => {
+                    usage.packets += packets;
+                    usage.bytes += bytes;
+                }
+                None => codec_usage.push(CodecUsage {
+                    codec: codec.clone(),
+                    packets,
+                    bytes,
+                }),
+            }
+        }
+        MediaSession {
+            session_id: session_id.to_owned(),
+            codec: self.codec.clone(),
+            caller: self.relay.stats(Side::Caller),
+            callee: self.relay.stats(Side::Callee),
+            started_at: self.started_at,
+            held: self.relay.is_held(),
+            codec_usage,
+            transcoding: self.relay.transcoding(),
+            recording_id: self
+                .recording
+                .as_ref()
+                .map(|recorder| recorder.recording_id().to_owned()),
+            agent_stream_id: self
+                .fork
+                .as_ref()
+                .filter(|fork| !fork.is_finished())
+                .map(|fork| fork.stream_id().to_owned()),
+        }
+    }
+}
+
+/// Media relay manager in charge of supervising sessions.
 pub struct MediaRelay {
     stop_tx: watch::Sender<bool>,
+    ports: PortAllocator,
+    sessions: Mutex<HashMap<String, Session>>,
+    events: Option<Arc<dyn EventSink>>,
+    recording_dir: PathBuf,
+    timeouts: InactivityTimeouts,
+    alerts: QosThresholds,
+    vad: VadConfig,
+    /// Recordings finalized with their session, until `stop_recording` collects them.
+    finished: Mutex<HashMap<String, RecordingInfo>>,
+}
+
+impl std::fmt::Debug for MediaRelay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MediaRelay")
+            .field("ports", &self.ports)
+            .field("sessions", &self.session_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for MediaRelay {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MediaRelay {
-    /// Build a new media relay instance.
+    /// Build a relay on the default port range and settings.
     pub fn new() -> Self {
+        Self::with_ports(PortAllocator::default())
+    }
+
+    /// Build a relay allocating ports from `config`.
+    pub fn from_config(config: &MediaConfig) -> Result<Self> {
+        Ok(Self {
+            recording_dir: config.recording_dir.clone(),
+            timeouts: config.inactivity_timeouts(),
+            alerts: config.qos_thresholds(),
+            vad: config.vad_config(),
+            ..Self::with_ports(PortAllocator::new(
+                config.bind_ip,
+                config.rtp_port_min,
+                config.rtp_port_max,
+            )?)
+        })
+    }
+
+    fn with_ports(ports: PortAllocator) -> Self {
         let (stop_tx, _rx) = watch::channel(false);
-        Self { stop_tx }
+        Self {
+            stop_tx,
+            ports,
+            sessions: Mutex::new(HashMap::new()),
+            events: None,
+            recording_dir: MediaConfig::default().recording_dir,
+            timeouts: InactivityTimeouts::default(),
+            alerts: MediaConfig::default().qos_thresholds(),
+            vad: VadConfig::default(),
+            finished: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Publish a `MediaStoppedEvent` with the final score of every session,
+    /// a `DtmfEvent` per digit, a `MediaTimeoutEvent` when a party goes
+    /// silent and a `SpeechEvent` when one starts or stops talking, to
+    /// `events`.
+    pub fn with_events(mut self, events: Arc<dyn EventSink>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Allocate a port pair per leg and start relaying between them.
+    pub async fn start_session(
+        &self,
+        session_id: impl Into<String>,
+        codec: impl Into<String>,
+    ) -> Result<MediaSession> {
+        let session_id = session_id.into();
+        if self.lock().contains_key(&session_id) {
+            return Err(VoipError::AlreadyExists(format!(
+                "media session {}",
+                session_id
+            )));
+        }
+        let caller = self.ports.allocate().await?;
+        let callee = self.ports.allocate().await?;
+        let codec = codec.into();
+        let session = Session {
+            switches: vec![(codec.clone(), 0, 0)],
+            codec,
+            started_at: Utc::now(),
+            relay: RelayHandle::spawn(
+                &session_id,
+                caller,
+                callee,
+                self.timeouts,
+                self.stop_tx.subscribe(),
+            ),
+            recording: None,
+            fork: None,
+            prompters: HashMap::new(),
+            call: Arc::default(),
+        };
+        session.relay.set_codec(&session.codec);
+        session.relay.set_alerts(self.alerts);
+        let relay_events = session.relay.subscribe();
+        let call = session.call.clone();
+        let snapshot = session.snapshot(&session_id);
+        let duplicate = match self.lock().entry(session_id) {
+            Entry::Vacant(entry) => {
+                entry.insert(session);
+                None
+            }
+            Entry::Occupied(_) => Some(session),
+        };
+        if let Some(mut session) = duplicate {
+            session.relay.stop().await;
+            return Err(VoipError::AlreadyExists(format!(
+                "media session {}",
+                snapshot.session_id
+            )));
+        }
+        if let Some(events) = &self.events {
+            tokio::spawn(publish_relay_events(
+                snapshot.session_id.clone(),
+                call,
+                relay_events,
+                events.clone(),
+            ));
+        }
+        info!(
+            session_id = %snapshot.session_id,
+            caller_port = snapshot.caller.rtp_port,
+            callee_port = snapshot.callee.rtp_port,
+            "media session started"
+        );
+        Ok(snapshot)
+    }
+
+    /// Point a leg at the RTP address from its SDP until it latches.
+    pub fn set_remote(&self, session_id: &str, side: Side, rtp: Option<SocketAddr>) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_remote(side, rtp);
+        Ok(())
+    }
+
+    /// Name the call a session carries, for the events it publishes.
+    pub fn set_call_id(&self, session_id: &str, call_id: &str, sip_call_id: &str) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        *session.call.lock().unwrap_or_else(|e| e.into_inner()) = CallIds {
+            call_id: call_id.to_owned(),
+            sip_call_id: sip_call_id.to_owned(),
+        };
+        Ok(())
+    }
+
+    /// Suspend or restore forwarding for hold/resume.
+    pub fn set_held(&self, session_id: &str, held: bool) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_held(held);
+        Ok(())
+    }
+
+    /// Record a codec switch; later traffic counts towards `codec`.
+    pub fn set_codec(&self, session_id: &str, codec: impl Into<String>) -> Result<()> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        let codec = codec.into();
+        if codec != session.codec {
+            let (packets, bytes) = session.totals();
+            session.switches.push((codec.clone(), packets, bytes));
+            session.relay.set_codec(&codec);
+            session.codec = codec;
+        }
+        Ok(())
+    }
+
+    /// Transcode between `[caller, callee]` formats, or relay untouched with `None`.
+    pub fn set_transcoding(
+        &self,
+        session_id: &str,
+        formats: Option<[PayloadFormat; 2]>,
+    ) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_transcoding(formats)
+    }
+
+    /// How a leg carries audio and telephone-events, for DTMF detection.
+    pub fn set_format(&self, session_id: &str, side: Side, format: PayloadFormat) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_format(side, format);
+        Ok(())
+    }
+
+    /// Protect a leg with SRTP, or carry plain RTP with `None`.
+    pub fn set_srtp(
+        &self,
+        session_id: &str,
+        side: Side,
+        policy: Option<&SrtpPolicy>,
+    ) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_srtp(side, policy);
+        Ok(())
+    }
+
+    /// Record both legs of a session to `<recording_dir>/<recording_id>.wav`.
+    ///
+    /// Returns the file path. A session has one recording at a time; it is
+    /// finalized when stopped or when the session stops.
+    pub fn start_recording(
+        &self,
+        session_id: &str,
+        recording_id: &str,
+        options: RecordingOptions,
+    ) -> Result<PathBuf> {
+        if recording_id.is_empty()
+            || recording_id.starts_with('.')
+            || recording_id.contains(['/', '\\'])
+        {
+            return Err(VoipError::Validation(format!(
+                "invalid recording id {:?}",
+                recording_id
+            )));
+        }
+        let mut sessions = self.lock();
+        if sessions.values().any(|session| {
+            session
+                .recording
+                .as_ref()
+                .is_some_and(|recorder| recorder.recording_id() == recording_id)
+        }) || self.finished_recordings().contains_key(recording_id)
+        {
+            return Err(VoipError::AlreadyExists(format!(
+                "recording {}",
+                recording_id
+            )));
+        }
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        if let Some(recorder) = &session.recording {
+            return Err(VoipError::AlreadyExists(format!(
+                "session {} is already recording as {}",
+                session_id,
+                recorder.recording_id()
+            )));
+        }
+        std::fs::create_dir_all(&self.recording_dir)?;
+        let path = self.recording_dir.join(format!("{}.wav", recording_id));
+        let recorder = Recorder::start(recording_id, &path, options)?;
+        session.relay.set_recording(Some(recorder.tap()));
+        session.recording = Some(recorder);
+        info!(session_id, recording_id, path = %path.display(), "recording started");
+        Ok(path)
+    }
+
+    /// Finalize a recording, or collect one its session already finalized.
+    pub async fn stop_recording(&self, recording_id: &str) -> Result<RecordingInfo> {
+        let recorder = self.lock().values_mut().find_map(|session| {
+            if session
+                .recording
+                .as_ref()
+                .is_some_and(|recorder| recorder.recording_id() == recording_id)
+            {
+                session.relay.set_recording(None);
+                session.recording.take()
+            } else {
+                None
+            }
+        });
+        let info = match recorder {
+            Some(recorder) => recorder.stop().await?,
+            None => self
+                .finished_recordings()
+                .remove(recording_id)
+                .ok_or_else(|| VoipError::NotFound(format!("recording {}", recording_id)))?,
+        };
+        info!(
+            recording_id,
+            duration_ms = info.duration.as_millis() as u64,
+            file_size = info.file_size,
+            "recording stopped"
+        );
+        Ok(info)
+    }
+
+    /// Pause a live recording; its audio is left out until resumed.
+    pub fn pause_recording(&self, recording_id: &str) -> Result<()> {
+        let (control, _) = self.recording_control(recording_id)?;
+        control.pause(PauseTrigger::Api)?;
+        info!(recording_id, trigger = "api", "recording paused");
+        Ok(())
+    }
+
+    /// Resume a paused recording.
+    pub fn resume_recording(&self, recording_id: &str) -> Result<()> {
+        let (control, _) = self.recording_control(recording_id)?;
+        control.resume()?;
+        info!(recording_id, trigger = "api", "recording resumed");
+        Ok(())
+    }
+
+    /// Pause a live recording when `pause` is keyed on either leg, and resume
+    /// it on `resume`, until the recording stops.
+    pub fn pause_recording_on_digits(
+        &self,
+        recording_id: &str,
+        pause: &str,
+        resume: &str,
+    ) -> Result<()> {
+        for digits in [pause, resume] {
+            if digits.is_empty() || !digits.chars().all(|c| dtmf::event_for_digit(c).is_some()) {
+                return Err(VoipError::Validation(format!(
+                    "invalid DTMF sequence {:?}",
+                    digits
+                )));
+            }
+        }
+        if pause.ends_with(resume) || resume.ends_with(pause) {
+            return Err(VoipError::Validation(format!(
+                "DTMF sequences {:?} and {:?} overlap",
+                pause, resume
+            )));
+        }
+        let (control, relay_events) = self.recording_control(recording_id)?;
+        tokio::spawn(watch_pause_digits(
+            recording_id.to_owned(),
+            relay_events,
+            control,
+            pause.to_owned(),
+            resume.to_owned(),
+        ));
+        Ok(())
+    }
+
+    /// Tuning of the speech detection enabled on legs.
+    pub fn vad_config(&self) -> VadConfig {
+        self.vad
+    }
+
+    /// Detect speech on a leg of a session, announcing it on the session's
+    /// events, or stop with `None`.
+    pub fn set_speech_detection(
+        &self,
+        session_id: &str,
+        side: Side,
+        detection: Option<SpeechDetection>,
+    ) -> Result<()> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        session.relay.set_speech_detection(side, detection);
+        info!(
+            session_id,
+            leg = side.name(),
+            enabled = detection.is_some(),
+            barge_in = detection.is_some_and(|detection| detection.barge_in),
+            "speech detection set"
+        );
+        Ok(())
+    }
+
+    /// Stream the audio of a session to an agent, and play the agent's audio
+    /// to the caller instead of the callee's.
+    ///
+    /// Returns the stream id. A session streams to one agent at a time; the
+    /// stream ends when stopped, when the agent ends it, or with the session.
+    pub async fn start_fork(&self, session_id: &str, options: ForkOptions) -> Result<String> {
+        let (call, relay_events) = {
+            let sessions = self.lock();
+            let session = sessions
+                .get(session_id)
+                .ok_or_else(|| not_found(session_id))?;
+            if session.is_forked() {
+                return Err(already_forked(session_id));
+            }
+            let call = session
+                .call
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            let call = ForkedCall {
+                relay_id: session_id.to_owned(),
+                call_id: call.call_id,
+                sip_call_id: call.sip_call_id,
+            };
+            (call, session.relay.subscribe())
+        };
+        let stream_id = Uuid::new_v4().to_string();
+        let fork = AgentFork::connect(&stream_id, call, options, relay_events).await?;
+        let rejected = match self.lock().get_mut(session_id) {
+            None => Some((fork, not_found(session_id))),
+            Some(session) if session.is_forked() => Some((fork, already_forked(session_id))),
+            Some(session) => match session.relay.play(Side::Caller, Some(fork.playback())) {
+                Ok(()) => {
+                    if let Some(mut prompter) = session.prompters.remove(&Side::Caller) {
+                        prompter.cancel();
+                    }
+                    session
+                        .prompters
+                        .insert(Side::Caller, Prompter::new(fork.playback()));
+                    session.relay.set_fork(Some(fork.tap()));
+                    session.fork = Some(fork);
+                    None
+                }
+                Err(err) => Some((fork, err)),
+            },
+        };
+        if let Some((fork, err)) = rejected {
+            fork.stop(&err.to_string()).await;
+            return Err(err);
+        }
+        Ok(stream_id)
+    }
+
+    /// End the agent stream of a session.
+    pub async fn stop_fork(&self, session_id: &str, reason: &str) -> Result<()> {
+        let (fork, cleared) = {
+            let mut sessions = self.lock();
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| not_found(session_id))?;
+            let fork = session.fork.take().ok_or_else(|| {
+                VoipError::NotFound(format!("agent stream of media session {}", session_id))
+            })?;
+            session.relay.set_fork(None);
+            session.prompters.remove(&Side::Caller);
+            (fork, session.relay.play(Side::Caller, None))
+        };
+        fork.stop(reason).await;
+        cleared
+    }
+
+    /// Play `prompt` to a leg of a session once the prompts queued before it
+    /// have played; to the caller, it also waits for the agent's audio.
+    pub fn play_prompt(
+        &self,
+        session_id: &str,
+        side: Side,
+        prompt: Prompt,
+    ) -> Result<PromptHandle> {
+        let (audio, sample_rate) = prompt.open()?;
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        let prompter = match session.prompters.entry(side) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // Nobody waits for marks on prompts.
+                let (playback, _marks) = Playback::new(sample_rate)?;
+                session.relay.play(side, Some(playback.clone()))?;
+                entry.insert(Prompter::new(playback))
+            }
+        };
+        let handle = prompter.play(audio, sample_rate)?;
+        debug!(session_id, ?side, prompt_id = handle.id(), "prompt queued");
+        Ok(handle)
+    }
+
+    /// Drop the prompts playing or queued on a leg of a session, and the
+    /// agent's audio on the caller leg; returns whether there were any.
+    pub fn cancel_prompts(&self, session_id: &str, side: Side) -> Result<bool> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        Ok(session
+            .prompters
+            .get_mut(&side)
+            .is_some_and(Prompter::cancel))
+    }
+
+    /// Events of a session from now on, until it stops.
+    pub fn subscribe(&self, session_id: &str) -> Result<broadcast::Receiver<RelayEvent>> {
+        self.lock()
+            .get(session_id)
+            .map(|session| session.relay.subscribe())
+            .ok_or_else(|| not_found(session_id))
+    }
+
+    /// Current state of a session.
+    pub fn session(&self, session_id: &str) -> Option<MediaSession> {
+        self.lock()
+            .get(session_id)
+            .map(|session| session.snapshot(session_id))
+    }
+
+    /// Id of the session carrying the call `call_id`, as named by `set_call_id`.
+    pub fn find_call(&self, call_id: &str) -> Option<String> {
+        self.lock().iter().find_map(|(session_id, session)| {
+            let call = session.call.lock().unwrap_or_else(|e| e.into_inner());
+            (call.call_id == call_id).then(|| session_id.clone())
+        })
+    }
+
+    /// Number of sessions being relayed.
+    pub fn session_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Stop relaying, release the ports and return the final counters and score.
+    pub async fn stop_session(&self, session_id: &str) -> Result<MediaSession> {
+        let session = self
+            .lock()
+            .remove(session_id)
+            .ok_or_else(|| not_found(session_id))?;
+        Ok(self.finish(session_id, session).await)
     }
 
-    /// Start a dummy supervision loop.
+    /// Stop what `session` still runs, keep its recording for `stop_recording`
+    /// and announce it stopped.
+    async fn finish(&self, session_id: &str, mut session: Session) -> MediaSession {
+        // The relay rescores both streams as it stops.
+        session.relay.stop().await;
+        let snapshot = session.snapshot(session_id);
+        if let Some(fork) = session.fork.take() {
+            fork.stop("relay stopped").await;
+        }
+        if let Some(recorder) = session.recording.take() {
+            let recording_id = recorder.recording_id().to_owned();
+            match recorder.stop().await {
+                Ok(info) => {
+                    self.finished_recordings().insert(recording_id, info);
+                }
+                Err(err) => warn!(session_id, %recording_id, error = %err, "recording lost"),
+            }
+        }
+        let score = snapshot.score();
+        info!(
+            session_id,
+            mos = score.map(|score| score.mos),
+            "media session stopped"
+        );
+        if let Some(events) = &self.events {
+            let event = MediaStoppedEvent {
+                session_id: snapshot.session_id.clone(),
+                codec: snapshot.codec.clone(),
+                duration: Utc::now() - snapshot.started_at,
+                mos: score.map(|score| score.mos),
+                r_factor: score.map(|score| score.r_factor),
+                timestamp: Utc::now(),
+            };
+            if let Err(err) = publish_event(events.as_ref(), subjects::MEDIA_STOPPED, &event).await
+            {
+                warn!(error = %err, "failed to publish media stopped event");
+            }
+        }
+        snapshot
+    }
+
+    /// Finish sessions whose relay task exited, until [`MediaRelay::stop`];
+    /// then finish every session left.
     #[instrument(name = "media.supervise", skip_all)]
     pub async fn supervise(&self) -> Result<()> {
         let mut stop_rx = self.stop_tx.subscribe();
@@ -38,6 +871,7 @@ impl MediaRelay {
         loop {
             tokio::select! {
                 _ = ticker.tick() => {
+                    self.reap(|session| session.relay.is_finished()).await;
                     debug!("media supervisor heartbeat");
                 }
                 update = stop_rx.changed() => {
@@ -49,19 +883,229 @@ impl MediaRelay {
             }
         }
 
+        self.reap(|_| true).await;
         Ok(())
     }
 
-    /// Request the supervision loop to stop.
+    /// Request the supervision loop and every session task to stop.
     pub fn stop(&self) {
         let _ = self.stop_tx.send(true);
     }
+
+    /// Remove and finish the sessions `done` picks.
+    async fn reap(&self, done: impl Fn(&Session) -> bool) {
+        let reaped: Vec<(String, Session)> = {
+            let mut sessions = self.lock();
+            let ids: Vec<String> = sessions
+                .iter()
+                .filter(|(_, session)| done(session))
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| sessions.remove_entry(&id))
+                .collect()
+        };
+        for (session_id, session) in reaped {
+            debug!(%session_id, "reaping media session");
+            self.finish(&session_id, session).await;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn recording_control(
+        &self,
+        recording_id: &str,
+    ) -> Result<(RecordingControl, broadcast::Receiver<RelayEvent>)> {
+        self.lock()
+            .values()
+            .find_map(|session| {
+                session
+                    .recording
+                    .as_ref()
+                    .filter(|recorder| recorder.recording_id() == recording_id)
+                    .map(|recorder| (recorder.control(), session.relay.subscribe()))
+            })
+            .ok_or_else(|| VoipError::NotFound(format!("live recording {}", recording_id)))
+    }
+
+    fn finished_recordings(&self) -> std::sync::MutexGuard<'_, HashMap<String, RecordingInfo>> {
+        self.finished.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Forward the digits and interruptions of a session to the event bus until it stops.
+async fn publish_relay_events(
+    session_id: String,
+    call: Arc<Mutex<CallIds>>,
+    mut relay_events: broadcast::Receiver<RelayEvent>,
+    events: Arc<dyn EventSink>,
+) {
+    loop {
+        let published = match relay_events.recv().await {
+            Ok(RelayEvent::Digit { side, digit }) => {
+                info!(%session_id, leg = side.name(), digit = %digit.digit, "DTMF digit");
+                let event = DtmfEvent {
+                    session_id: session_id.clone(),
+                    leg: side.name().to_owned(),
+                    digit: digit.digit,
+                    duration_ms: digit.duration.as_millis() as u64,
+                    in_band: digit.in_band,
+                    timestamp: Utc::now(),
+                };
+                publish_event(events.as_ref(), subjects::MEDIA_DTMF, &event).await
+            }
+            Ok(RelayEvent::Interrupted {
+                side,
+                silent_for,
+                held,
+            }) => {
+                let call = call.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                let event = MediaTimeoutEvent {
+                    session_id: session_id.clone(),
+                    call_id: call.call_id,
+                    sip_call_id: call.sip_call_id,
+                    leg: side.name().to_owned(),
+                    reason: RelayEvent::interruption_reason(held).to_owned(),
+                    silent_for_ms: silent_for.as_millis() as u64,
+                    held,
+                    timestamp: Utc::now(),
+                };
+                publish_event(events.as_ref(), subjects::MEDIA_TIMEOUT, &event).await
+            }
+            Ok(RelayEvent::SpeechStarted { side, barge_in }) => {
+                let event = speech_event(&session_id, &call, side, None);
+                let event = SpeechEvent { barge_in, ..event };
+                publish_event(events.as_ref(), subjects::MEDIA_SPEECH, &event).await
+            }
+            Ok(RelayEvent::SpeechEnded { side, duration }) => {
+                let event = speech_event(&session_id, &call, side, Some(duration));
+                publish_event(events.as_ref(), subjects::MEDIA_SPEECH, &event).await
+            }
+            Ok(
+                RelayEvent::Resumed { .. }
+                | RelayEvent::PacketLoss { .. }
+                | RelayEvent::HighJitter { .. },
+            ) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(%session_id, missed, "media events dropped before publishing");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if let Err(err) = published {
+            warn!(%session_id, error = %err, "failed to publish media event");
+        }
+    }
+}
+
+/// A speech event without barge-in; `ended` after speech of that length.
+fn speech_event(
+    session_id: &str,
+    call: &Mutex<CallIds>,
+    side: Side,
+    ended: Option<Duration>,
+) -> SpeechEvent {
+    let call = call.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    SpeechEvent {
+        session_id: session_id.to_owned(),
+        call_id: call.call_id,
+        sip_call_id: call.sip_call_id,
+        leg: side.name().to_owned(),
+        started: ended.is_none(),
+        barge_in: false,
+        duration_ms: ended.map(|duration| duration.as_millis() as u64),
+        timestamp: Utc::now(),
+    }
+}
+
+/// Pause and resume a recording as its digit sequences are keyed.
+async fn watch_pause_digits(
+    recording_id: String,
+    mut relay_events: broadcast::Receiver<RelayEvent>,
+    control: RecordingControl,
+    pause: String,
+    resume: String,
+) {
+    let longest = pause.len().max(resume.len());
+    let mut keyed = String::new();
+    loop {
+        let digit = match relay_events.recv().await {
+            Ok(RelayEvent::Digit { digit, .. }) => digit.digit,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(%recording_id, missed, "digits missed by the recording control");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if control.is_stopped() {
+            break;
+        }
+        keyed.push(digit);
+        if keyed.len() > longest {
+            keyed.drain(..keyed.len() - longest);
+        }
+        let result = if !control.is_paused() && keyed.ends_with(&pause) {
+            control
+                .pause(PauseTrigger::Dtmf)
+                .map(|()| info!(%recording_id, trigger = "dtmf", "recording paused"))
+        } else if control.is_paused() && keyed.ends_with(&resume) {
+            control
+                .resume()
+                .map(|()| info!(%recording_id, trigger = "dtmf", "recording resumed"))
+        } else {
+            continue;
+        };
+        keyed.clear();
+        if let Err(err) = result {
+            debug!(%recording_id, error = %err, "DTMF recording control ignored");
+        }
+    }
+}
+
+fn already_forked(session_id: &str) -> VoipError {
+    VoipError::AlreadyExists(format!(
+        "media session {} is already streamed to an agent",
+        session_id
+    ))
+}
+
+fn not_found(session_id: &str) -> VoipError {
+    VoipError::NotFound(format!("media session {}", session_id))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+    use voip_common::MemoryEventSink;
+
+    /// A relay on localhost over a free port window, adjusted by `configure`.
+    fn relay_with(configure: impl FnOnce(&mut MediaConfig)) -> MediaRelay {
+        let (rtp_port_min, rtp_port_max) = ports::free_range(8);
+        let mut config = MediaConfig {
+            bind_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            rtp_port_min,
+            rtp_port_max,
+            ..MediaConfig::default()
+        };
+        configure(&mut config);
+        MediaRelay::from_config(&config).expect("relay")
+    }
+
+    async fn phone() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").await.expect("phone")
+    }
+
+    async fn send_rtp(phone: &UdpSocket, port: u16, packet: rtp::RtpPacket<'_>) {
+        phone
+            .send_to(&packet.to_bytes().expect("encode"), ("127.0.0.1", port))
+            .await
+            .expect("send");
+    }
 
     #[tokio::test]
     async fn supervisor_stops_when_requested() {
@@ -72,4 +1116,423 @@ mod tests {
         relay.stop();
         supervisor.await.expect("join").expect("result");
     }
+
+    #[tokio::test]
+    async fn supervisor_finishes_sessions_left_at_shutdown() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", uuid::Uuid::new_v4()));
+        let events = MemoryEventSink::new();
+        let relay = Arc::new(
+            relay_with(|config| config.recording_dir = dir.clone())
+                .with_events(Arc::new(events.clone())),
+        );
+        relay.start_session("call-9", "PCMU").await.expect("start");
+        let path = relay
+            .start_recording("call-9", "rec-9", RecordingOptions::default())
+            .expect("record");
+        let supervisor = tokio::spawn({
+            let relay = relay.clone();
+            async move { relay.supervise().await }
+        });
+        time::sleep(Duration::from_millis(50)).await;
+        relay.stop();
+        supervisor.await.expect("join").expect("result");
+
+        assert_eq!(relay.session_count(), 0);
+        let stopped: Vec<MediaStoppedEvent> = events.events(subjects::MEDIA_STOPPED);
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0].session_id, "call-9");
+        let info = relay
+            .stop_recording("rec-9")
+            .await
+            .expect("recording survives");
+        assert_eq!(info.path, path);
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[tokio::test]
+    async fn sessions_are_registered_until_stopped() {
+        let events = MemoryEventSink::new();
+        let relay = relay_with(|_| {}).with_events(Arc::new(events.clone()));
+        let session = relay.start_session("call-1", "PCMU").await.expect("start");
+        assert_ne!(session.caller.rtp_port, session.callee.rtp_port);
+        assert!(matches!(
+            relay.start_session("call-1", "PCMU").await,
+            Err(VoipError::AlreadyExists(_))
+        ));
+        relay
+            .set_remote(
+                "call-1",
+                Side::Callee,
+                Some("127.0.0.1:4000".parse().expect("addr")),
+            )
+            .expect("remote");
+        assert_eq!(
+            relay.session("call-1").expect("session").callee.remote,
+            Some("127.0.0.1:4000".parse().expect("addr"))
+        );
+
+        relay.set_codec("call-1", "PCMA").expect("codec");
+        relay.set_held("call-1", true).expect("hold");
+        assert!(relay.session("call-1").expect("session").held);
+
+        let stopped = relay.stop_session("call-1").await.expect("stop");
+        assert_eq!(stopped.codec, "PCMA");
+        let codecs: Vec<_> = stopped
+            .codec_usage
+            .iter()
+            .map(|u| u.codec.as_str())
+            .collect();
+        assert_eq!(codecs, ["PCMU", "PCMA"]);
+        assert_eq!(relay.session_count(), 0);
+        let stopped: Vec<MediaStoppedEvent> = events.events(subjects::MEDIA_STOPPED);
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0].codec, "PCMA");
+        assert_eq!(stopped[0].mos, None, "no RTP was relayed");
+        assert!(matches!(
+            relay.stop_session("call-1").await,
+            Err(VoipError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn digits_are_published_on_the_event_bus() {
+        let events = MemoryEventSink::new();
+        let relay = relay_with(|_| {}).with_events(Arc::new(events.clone()));
+        let session = relay.start_session("call-2", "PCMU").await.expect("start");
+        let phone = phone().await;
+        let packets = dtmf::generate(
+            '*',
+            Duration::from_millis(60),
+            8000,
+            Duration::from_millis(20),
+        )
+        .expect("events");
+        for (sequence, packet) in packets.iter().enumerate() {
+            let rtp =
+                rtp::RtpPacket::new(101, sequence as u16, 0, 9, packet.event.to_bytes().to_vec());
+            send_rtp(&phone, session.callee.rtp_port, rtp).await;
+        }
+
+        let mut published: Vec<DtmfEvent> = Vec::new();
+        for _ in 0..50 {
+            published = events.events(subjects::MEDIA_DTMF);
+            if !published.is_empty() {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(published.len(), 1);
+        assert_eq!(
+            (
+                published[0].digit,
+                published[0].leg.as_str(),
+                published[0].duration_ms
+            ),
+            ('*', "callee", 60)
+        );
+        relay.stop_session("call-2").await.expect("stop");
+    }
+
+    #[tokio::test]
+    async fn recordings_are_finalized_when_their_session_stops() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", uuid::Uuid::new_v4()));
+        let relay = relay_with(|config| config.recording_dir = dir.clone());
+        let session = relay.start_session("call-3", "PCMU").await.expect("start");
+        let options = RecordingOptions {
+            channels: recording::Channels::Stereo,
+            ..RecordingOptions::default()
+        };
+        let path = relay
+            .start_recording("call-3", "rec-3", options)
+            .expect("record");
+        assert_eq!(path, dir.join("rec-3.wav"));
+        assert!(relay.start_recording("call-3", "rec-4", options).is_err());
+        assert!(relay.start_recording("call-3", "../rec", options).is_err());
+        assert_eq!(
+            relay
+                .session("call-3")
+                .expect("session")
+                .recording_id
+                .as_deref(),
+            Some("rec-3")
+        );
+
+        let phone = phone().await;
+        for sequence in 0..10u16 {
+            let rtp =
+                rtp::RtpPacket::new(0, sequence, u32::from(sequence) * 160, 9, vec![0x80; 160]);
+            send_rtp(&phone, session.caller.rtp_port, rtp).await;
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        relay.stop_session("call-3").await.expect("stop");
+
+        let info = relay.stop_recording("rec-3").await.expect("collect");
+        assert_eq!(info.options, options);
+        assert!(
+            info.duration >= Duration::from_millis(200),
+            "{:?}",
+            info.duration
+        );
+        assert_eq!(
+            std::fs::metadata(&path).expect("file").len(),
+            info.file_size
+        );
+        assert!(matches!(
+            relay.stop_recording("rec-3").await,
+            Err(VoipError::NotFound(_))
+        ));
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[tokio::test]
+    async fn silent_parties_are_published_with_their_call() {
+        let events = MemoryEventSink::new();
+        let relay =
+            relay_with(|config| config.rtp_timeout_ms = 100).with_events(Arc::new(events.clone()));
+        let session = relay.start_session("call-4", "PCMU").await.expect("start");
+        relay
+            .set_call_id("call-4", "c0ffee", "abc@host")
+            .expect("call id");
+        let phone = phone().await;
+        let rtp = rtp::RtpPacket::new(0, 1, 160, 4, vec![0xff; 160]);
+        send_rtp(&phone, session.callee.rtp_port, rtp).await;
+        time::sleep(Duration::from_millis(300)).await;
+
+        let timeouts: Vec<MediaTimeoutEvent> = events.events(subjects::MEDIA_TIMEOUT);
+        assert_eq!(timeouts.len(), 1);
+        assert_eq!(
+            (
+                timeouts[0].call_id.as_str(),
+                timeouts[0].sip_call_id.as_str(),
+                timeouts[0].leg.as_str(),
+                timeouts[0].held
+            ),
+            ("c0ffee", "abc@host", "callee", false)
+        );
+        assert!(timeouts[0].silent_for_ms >= 100);
+        relay.stop_session("call-4").await.expect("stop");
+    }
+
+    #[tokio::test]
+    async fn forked_calls_stream_to_the_agent_and_play_its_answers() {
+        use fork::{
+            mock::{MockAgent, Received},
+            protocol::{decode_payload, AgentMessage, RelayMessage},
+        };
+
+        let relay = relay_with(|_| {});
+        let session = relay.start_session("call-5", "PCMU").await.expect("start");
+        let mut agent = MockAgent::start().await.expect("agent");
+        let options = || ForkOptions {
+            url: agent.url().to_owned(),
+            sample_rate: 16000,
+            ..ForkOptions::default()
+        };
+        let stream_id = relay.start_fork("call-5", options()).await.expect("fork");
+        assert!(matches!(
+            relay.start_fork("call-5", options()).await,
+            Err(VoipError::AlreadyExists(_))
+        ));
+        assert_eq!(
+            relay.session("call-5").expect("session").agent_stream_id,
+            Some(stream_id.clone())
+        );
+        assert!(matches!(
+            agent.recv().await,
+            Some(Received::Message(RelayMessage::Start { .. }))
+        ));
+
+        let phone = phone().await;
+        let rtp = rtp::RtpPacket::new(0, 1, 160, 5, vec![0xff; 160]);
+        send_rtp(&phone, session.caller.rtp_port, rtp).await;
+        let Some(Received::Message(RelayMessage::Media { track, payload, .. })) =
+            agent.recv().await
+        else {
+            panic!("expected media");
+        };
+        assert_eq!(track, "caller");
+        assert_eq!(decode_payload(&payload).expect("pcm").len(), 320);
+
+        agent.send_audio(&[1000; 640]);
+        agent.send(&AgentMessage::Mark {
+            name: "greeting".into(),
+        });
+        let mut buf = [0u8; 1500];
+        for _ in 0..2 {
+            let (len, _) = time::timeout(Duration::from_secs(1), phone.recv_from(&mut buf))
+                .await
+                .expect("played")
+                .expect("recv");
+            let played = rtp::RtpPacket::parse(&buf[..len]).expect("rtp");
+            assert_eq!((played.payload_type, played.payload.len()), (0, 160));
+        }
+        assert_eq!(
+            time::timeout(Duration::from_secs(1), agent.recv())
+                .await
+                .expect("mark"),
+            Some(Received::Message(RelayMessage::Mark {
+                stream_id: stream_id.clone(),
+                name: "greeting".into()
+            }))
+        );
+
+        relay.stop_session("call-5").await.expect("stop");
+        assert_eq!(
+            agent.recv().await,
+            Some(Received::Message(RelayMessage::Stop {
+                stream_id,
+                reason: "relay stopped".into()
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn prompts_are_played_to_the_leg_until_cancelled() {
+        use futures::StreamExt;
+
+        let relay = relay_with(|_| {});
+        let session = relay.start_session("call-6", "PCMU").await.expect("start");
+        let phone = phone().await;
+        let rtp = rtp::RtpPacket::new(0, 1, 160, 6, vec![0xff; 160]);
+        send_rtp(&phone, session.caller.rtp_port, rtp).await;
+        time::sleep(Duration::from_millis(20)).await;
+
+        let path = std::env::temp_dir().join(format!("prompt-{}.wav", Uuid::new_v4()));
+        let mut wav = recording::wav::WavWriter::create(&path, 1, 16000).expect("create");
+        wav.write(&[2000; 3200]).expect("write");
+        wav.finalize().expect("finalize");
+        let handle = relay
+            .play_prompt("call-6", Side::Caller, Prompt::Wav(path.clone()))
+            .expect("play");
+        let mut buf = [0u8; 1500];
+        for _ in 0..10 {
+            let (len, _) = time::timeout(Duration::from_secs(1), phone.recv_from(&mut buf))
+                .await
+                .expect("played")
+                .expect("recv");
+            let played = rtp::RtpPacket::parse(&buf[..len]).expect("rtp");
+            assert_eq!((played.payload_type, played.payload.len()), (0, 160));
+        }
+        assert!(handle.finished().await);
+        std::fs::remove_file(&path).expect("cleanup");
+
+        let endless = Prompt::Audio {
+            audio: futures::stream::pending().boxed(),
+            sample_rate: 8000,
+        };
+        let handle = relay
+            .play_prompt("call-6", Side::Caller, endless)
+            .expect("play");
+        assert!(
+            matches!(
+                relay.stop_fork("call-6", "done").await,
+                Err(VoipError::NotFound(_))
+            ),
+            "prompts outlive a missing agent stream"
+        );
+        assert!(relay
+            .cancel_prompts("call-6", Side::Caller)
+            .expect("cancel"));
+        assert!(!handle.finished().await);
+        assert!(!relay
+            .cancel_prompts("call-6", Side::Callee)
+            .expect("cancel"));
+        assert!(relay
+            .play_prompt("call-6", Side::Caller, Prompt::Wav(path))
+            .is_err());
+        relay.stop_session("call-6").await.expect("stop");
+    }
+
+    #[tokio::test]
+    async fn callers_barge_in_on_the_agent() {
+        use fork::{
+            mock::{MockAgent, Received},
+            protocol::RelayMessage,
+            Tracks,
+        };
+
+        let events = MemoryEventSink::new();
+        let relay =
+            relay_with(|config| config.vad_hangover_ms = 200).with_events(Arc::new(events.clone()));
+        let session = relay.start_session("call-7", "PCMU").await.expect("start");
+        relay
+            .set_call_id("call-7", "c0ffee", "abc@host")
+            .expect("call id");
+        let mut agent = MockAgent::start().await.expect("agent");
+        let options = ForkOptions {
+            url: agent.url().to_owned(),
+            tracks: Tracks::Caller,
+            ..ForkOptions::default()
+        };
+        let stream_id = relay.start_fork("call-7", options).await.expect("fork");
+        assert!(matches!(
+            agent.recv().await,
+            Some(Received::Message(RelayMessage::Start { .. }))
+        ));
+        let detection = SpeechDetection {
+            vad: relay.vad_config(),
+            barge_in: true,
+        };
+        relay
+            .set_speech_detection("call-7", Side::Caller, Some(detection))
+            .expect("detect");
+        agent.send_audio(&[1000; 16000]);
+        time::sleep(Duration::from_millis(50)).await;
+
+        let phone = phone().await;
+        let tone = |n: usize| {
+            let t = n as f64 / 8000.0;
+            codec::g711::ulaw_encode(
+                (3000.0 * (2.0 * std::f64::consts::PI * 300.0 * t).sin()) as i16,
+            )
+        };
+        for sequence in 0..25u16 {
+            let start = usize::from(sequence) * 160;
+            let payload: Vec<u8> = if sequence < 10 {
+                (start..start + 160).map(tone).collect()
+            } else {
+                vec![0xff; 160]
+            };
+            let rtp = rtp::RtpPacket::new(0, sequence, u32::from(sequence) * 160, 7, payload);
+            send_rtp(&phone, session.caller.rtp_port, rtp).await;
+        }
+        let mut speech = Vec::new();
+        while speech.len() < 2 {
+            match time::timeout(Duration::from_secs(1), agent.recv())
+                .await
+                .expect("message")
+            {
+                Some(Received::Message(RelayMessage::Media { .. })) => {}
+                Some(Received::Message(message)) => speech.push(message),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(
+            speech,
+            [
+                RelayMessage::SpeechStarted {
+                    stream_id: stream_id.clone(),
+                    track: "caller".into(),
+                    barge_in: true,
+                },
+                RelayMessage::SpeechEnded {
+                    stream_id,
+                    track: "caller".into(),
+                    duration_ms: 200,
+                },
+            ]
+        );
+
+        time::sleep(Duration::from_millis(50)).await;
+        let published: Vec<SpeechEvent> = events.events(subjects::MEDIA_SPEECH);
+        assert_eq!(
+            published
+                .iter()
+                .map(|event| (event.started, event.barge_in, event.duration_ms))
+                .collect::<Vec<_>>(),
+            [(true, true, None), (false, false, Some(200))]
+        );
+        assert_eq!(published[0].call_id, "c0ffee");
+        relay.stop_session("call-7").await.expect("stop");
+    }
 }
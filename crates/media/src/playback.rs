@@ -0,0 +1,223 @@
+//! Audio played into a call leg by the relay itself, e.g. an agent's answers.
+//!
+//! A [`Playback`] queues mono PCM at a fixed rate, with named marks placed
+//! between chunks. The relay takes one frame per packetization interval and
+//! sends it to the leg in the leg's own format. A mark is reported once the
+//! audio queued before it has been taken. Clearing drops what was not played
+//! yet and reports the pending marks at once, so nobody waits for them;
+//! [`Playback::played`] tells the two apart. Whoever feeds the queue can also
+//! stop once someone else cleared it, with [`Playback::push_uncleared`].
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{mpsc, oneshot};
+use voip_common::{Result, VoipError};
+
+#[derive(Debug)]
+enum Cue {
+    Mark(String),
+    /// Told whether the audio before it was played rather than cleared.
+    Played(oneshot::Sender<bool>),
+}
+
+#[derive(Debug)]
+struct Queue {
+    samples: VecDeque<i16>,
+    /// Cues with the number of samples taken once they are reached.
+    marks: VecDeque<(u64, Cue)>,
+    queued: u64,
+    taken: u64,
+    clears: u64,
+    reached: mpsc::UnboundedSender<String>,
+}
+
+impl Queue {
+    fn report_marks(&mut self, played: bool) {
+        while self.marks.front().is_some_and(|(at, _)| *at <= self.taken) {
+            // Nobody waiting for marks is fine.
+            match self.marks.pop_front() {
+                Some((_, Cue::Mark(name))) => {
+                    let _ = self.reached.send(name);
+                }
+                Some((_, Cue::Played(tx))) => {
+                    let _ = tx.send(played);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// PCM waiting to be played to a leg; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    queue: Arc<Mutex<Queue>>,
+    sample_rate: u32,
+}
+
+impl Playback {
+    /// An empty queue of PCM at `sample_rate` Hz, with the receiver of the
+    /// names of the marks reached.
+    pub fn new(sample_rate: u32) -> Result<(Self, mpsc::UnboundedReceiver<String>)> {
+        if !matches!(sample_rate, 8000 | 16000) {
+            return Err(VoipError::Validation(format!(
+                "cannot play audio at {} Hz",
+                sample_rate
+            )));
+        }
+        let (reached, marks) = mpsc::unbounded_channel();
+        let queue = Queue {
+            samples: VecDeque::new(),
+            marks: VecDeque::new(),
+            queued: 0,
+            taken: 0,
+            clears: 0,
+            reached,
+        };
+        Ok((
+            Self {
+                queue: Arc::new(Mutex::new(queue)),
+                sample_rate,
+            },
+            marks,
+        ))
+    }
+
+    /// Rate of the queued PCM.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queue samples after those already waiting.
+    pub fn push(&self, pcm: &[i16]) {
+        let mut queue = self.lock();
+        queue.samples.extend(pcm);
+        queue.queued += pcm.len() as u64;
+    }
+
+    /// How many times the queue was cleared so far.
+    pub fn clears(&self) -> u64 {
+        self.lock().clears
+    }
+
+    /// Queue samples unless the queue was cleared since [`Playback::clears`]
+    /// returned `clears`; returns whether they were queued.
+    pub fn push_uncleared(&self, clears: u64, pcm: &[i16]) -> bool {
+        let mut queue = self.lock();
+        if queue.clears != clears {
+            return false;
+        }
+        queue.samples.extend(pcm);
+        queue.queued += pcm.len() as u64;
+        true
+    }
+
+    /// Report `name` once everything queued so far has been played.
+    pub fn mark(&self, name: impl Into<String>) {
+        let mut queue = self.lock();
+        let at = queue.queued;
+        queue.marks.push_back((at, Cue::Mark(name.into())));
+    }
+
+    /// Resolves to `true` once everything queued so far has been played, or
+    /// to `false` if it is cleared first.
+    pub fn played(&self) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        let mut queue = self.lock();
+        let at = queue.queued;
+        queue.marks.push_back((at, Cue::Played(tx)));
+        queue.report_marks(true);
+        rx
+    }
+
+    /// Drop the audio not played yet and report the pending marks.
+    pub fn clear(&self) {
+        let mut queue = self.lock();
+        queue.samples.clear();
+        queue.taken = queue.queued;
+        queue.clears += 1;
+        queue.report_marks(false);
+    }
+
+    /// Whether audio is waiting to be played.
+    pub fn is_playing(&self) -> bool {
+        !self.lock().samples.is_empty()
+    }
+
+    /// The next `len` samples, padded with silence at the end of the queue,
+    /// or `None` when nothing is queued.
+    pub fn next_frame(&self, len: usize) -> Option<Vec<i16>> {
+        let mut queue = self.lock();
+        let take = len.min(queue.samples.len());
+        let mut frame: Vec<i16> = queue.samples.drain(..take).collect();
+        queue.taken += take as u64;
+        queue.report_marks(true);
+        if frame.is_empty() {
+            return None;
+        }
+        frame.resize(len, 0);
+        Some(frame)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_follow_the_audio_queued_before_them() {
+        let (playback, mut marks) = Playback::new(8000).expect("playback");
+        playback.mark("start");
+        playback.push(&[1; 200]);
+        playback.mark("greeting");
+        playback.push(&[2; 100]);
+        playback.mark("question");
+
+        assert_eq!(playback.next_frame(160), Some(vec![1; 160]));
+        assert_eq!(marks.try_recv().ok().as_deref(), Some("start"));
+        assert!(marks.try_recv().is_err());
+        let frame = playback.next_frame(160).expect("frame");
+        assert_eq!((frame[39], frame[40], frame[139]), (1, 2, 2));
+        assert_eq!(frame[140..], [0; 20], "padded with silence");
+        assert_eq!(marks.try_recv().ok().as_deref(), Some("greeting"));
+        assert_eq!(marks.try_recv().ok().as_deref(), Some("question"));
+        assert!(!playback.is_playing());
+        assert_eq!(playback.next_frame(160), None);
+
+        playback.push(&[3; 400]);
+        playback.mark("interrupted");
+        assert!(playback.is_playing());
+        playback.clear();
+        assert_eq!(marks.try_recv().ok().as_deref(), Some("interrupted"));
+        assert_eq!(playback.next_frame(160), None);
+        assert!(Playback::new(44100).is_err());
+    }
+
+    #[tokio::test]
+    async fn played_tells_played_audio_from_cleared_audio() {
+        let (playback, _marks) = Playback::new(16000).expect("playback");
+        assert_eq!(playback.played().await, Ok(true), "nothing queued");
+
+        playback.push(&[1; 400]);
+        let played = playback.played();
+        while playback.next_frame(320).is_some() {}
+        assert_eq!(played.await, Ok(true));
+
+        playback.push(&[1; 400]);
+        let cleared = playback.played();
+        let clears = playback.clears();
+        assert!(playback.push_uncleared(clears, &[1; 400]));
+        playback.next_frame(320);
+        playback.clear();
+        assert_eq!(cleared.await, Ok(false));
+        assert!(!playback.push_uncleared(clears, &[1; 400]));
+        assert!(!playback.is_playing());
+    }
+}
@@ -0,0 +1,223 @@
+//! UDP port-pair allocation for RTP/RTCP (RFC 3550 §11: even RTP port, RTCP on the next one).
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use voip_common::{Result, VoipError};
+
+/// Port range relays allocate from unless configured otherwise, RTCP included.
+pub const DEFAULT_RTP_PORTS: (u16, u16) = (10000, 20000);
+
+#[derive(Debug)]
+struct Pool {
+    min: u16,
+    max: u16,
+    next: u16,
+    in_use: HashSet<u16>,
+}
+
+/// Hands out bound RTP/RTCP socket pairs from an inclusive port range.
+#[derive(Debug, Clone)]
+pub struct PortAllocator {
+    ip: IpAddr,
+    pool: Arc<Mutex<Pool>>,
+}
+
+impl Default for PortAllocator {
+    /// [`DEFAULT_RTP_PORTS`] on all interfaces.
+    fn default() -> Self {
+        let (min, max) = DEFAULT_RTP_PORTS;
+        Self::range(IpAddr::V4(Ipv4Addr::UNSPECIFIED), min, max)
+    }
+}
+
+impl PortAllocator {
+    /// Allocate from `min..=max` on `ip`; `min` is rounded up to an even port.
+    pub fn new(ip: IpAddr, min: u16, max: u16) -> Result<Self> {
+        let min = min.saturating_add(min % 2);
+        if min == 0 || max <= min {
+            return Err(VoipError::Config(format!(
+                "invalid RTP port range {}-{}",
+                min, max
+            )));
+        }
+        Ok(Self::range(ip, min, max))
+    }
+
+    /// `min..=max` on `ip`, with `min` even and below `max`.
+    fn range(ip: IpAddr, min: u16, max: u16) -> Self {
+        Self {
+            ip,
+            pool: Arc::new(Mutex::new(Pool {
+                min,
+                max,
+                next: min,
+                in_use: HashSet::new(),
+            })),
+        }
+    }
+
+    /// Number of pairs the range can hold.
+    pub fn capacity(&self) -> usize {
+        let pool = self.lock();
+        usize::from((pool.max - pool.min).div_ceil(2))
+    }
+
+    /// Number of pairs currently handed out.
+    pub fn in_use(&self) -> usize {
+        self.lock().in_use.len()
+    }
+
+    /// Bind the next free even/odd pair, skipping ports taken by other processes.
+    pub async fn allocate(&self) -> Result<PortPair> {
+        for _ in 0..self.capacity() {
+            let Some(port) = self.reserve() else {
+                break;
+            };
+            let rtp = UdpSocket::bind(SocketAddr::new(self.ip, port)).await;
+            let rtcp = UdpSocket::bind(SocketAddr::new(self.ip, port + 1)).await;
+            match (rtp, rtcp) {
+                (Ok(rtp), Ok(rtcp)) => {
+                    return Ok(PortPair {
+                        rtp,
+                        rtcp,
+                        port,
+                        allocator: self.clone(),
+                    })
+                }
+                (rtp, rtcp) => {
+                    debug!(
+                        port,
+                        rtp_ok = rtp.is_ok(),
+                        rtcp_ok = rtcp.is_ok(),
+                        "RTP port busy"
+                    );
+                    self.release(port);
+                }
+            }
+        }
+        Err(VoipError::Unavailable("RTP port range exhausted".into()))
+    }
+
+    fn reserve(&self) -> Option<u16> {
+        let mut pool = self.lock();
+        let pairs = (pool.max - pool.min).div_ceil(2);
+        for _ in 0..pairs {
+            let port = pool.next;
+            pool.next = if u32::from(port) + 3 > u32::from(pool.max) {
+                pool.min
+            } else {
+                port + 2
+            };
+            if pool.in_use.insert(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    fn release(&self, port: u16) {
+        self.lock().in_use.remove(&port);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pool> {
+        self.pool.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A bound RTP/RTCP socket pair, returned to the pool on drop.
+#[derive(Debug)]
+pub struct PortPair {
+    /// Socket on the even port.
+    pub rtp: UdpSocket,
+    /// Socket on `rtp_port() + 1`.
+    pub rtcp: UdpSocket,
+    port: u16,
+    allocator: PortAllocator,
+}
+
+impl PortPair {
+    /// Local RTP port.
+    pub fn rtp_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Local RTCP port.
+    pub fn rtcp_port(&self) -> u16 {
+        self.port + 1
+    }
+}
+
+impl Drop for PortPair {
+    fn drop(&mut self) {
+        self.allocator.release(self.port);
+    }
+}
+
+/// `len` consecutive ports, from an even one, that are free on localhost right
+/// now; tests take their windows from here so that parallel runs do not collide.
+#[cfg(test)]
+pub(crate) fn free_range(len: u16) -> (u16, u16) {
+    for _ in 0..100 {
+        let Ok(port) = std::net::UdpSocket::bind("127.0.0.1:0")
+            .and_then(|probe| probe.local_addr())
+            .map(|addr| addr.port() & !1)
+        else {
+            continue;
+        };
+        let Some(max) = port.checked_add(len - 1) else {
+            continue;
+        };
+        let bound = (port..=max)
+            .map_while(|port| std::net::UdpSocket::bind(("127.0.0.1", port)).ok())
+            .count();
+        if bound == usize::from(len) {
+            return (port, max);
+        }
+    }
+    panic!("no {} consecutive free ports", len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn rejects_empty_range() {
+        assert!(PortAllocator::new(LOCALHOST, 30001, 30002).is_err());
+        assert!(PortAllocator::new(LOCALHOST, 0, 100).is_err());
+        let allocator = PortAllocator::new(LOCALHOST, 30001, 30005).expect("range");
+        assert_eq!(allocator.capacity(), 2);
+        assert_eq!(PortAllocator::default().capacity(), 5000);
+    }
+
+    #[tokio::test]
+    async fn allocates_even_pairs_until_exhausted_and_reuses_released_ports() {
+        let (min, max) = free_range(4);
+        let allocator = PortAllocator::new(LOCALHOST, min, max).expect("range");
+        let first = allocator.allocate().await.expect("first pair");
+        let second = allocator.allocate().await.expect("second pair");
+        assert_eq!(first.rtp_port() % 2, 0);
+        assert_eq!(first.rtcp_port(), first.rtp_port() + 1);
+        assert_ne!(first.rtp_port(), second.rtp_port());
+        assert_eq!(
+            first.rtcp.local_addr().expect("addr").port(),
+            first.rtcp_port()
+        );
+        assert!(allocator.allocate().await.is_err());
+
+        let port = first.rtp_port();
+        drop(first);
+        assert_eq!(allocator.in_use(), 1);
+        let third = allocator.allocate().await.expect("reused pair");
+        assert_eq!(third.rtp_port(), port);
+    }
+}
@@ -0,0 +1,198 @@
+//! Prompts played into a call leg: synthesized speech or WAV files.
+//!
+//! A [`Prompter`] feeds the prompts of one leg to the leg's [`Playback`], one
+//! after the other; the relay paces that out in real time in the leg's codec.
+//! Audio is resampled to the playback rate as it is fed, so synthesized speech
+//! starts playing before the engine is done with it. Cancelling drops every
+//! prompt not played yet, along with anything else queued on the playback;
+//! so does clearing the playback, e.g. when the party barges in.
+
+use std::{io::ErrorKind, path::PathBuf, sync::Arc};
+
+use futures::{stream, StreamExt};
+use tokio::{
+    sync::{oneshot, Mutex},
+    task::JoinHandle,
+};
+use uuid::Uuid;
+use voip_common::{Result, VoipError};
+
+use crate::{codec::Resampler, playback::Playback, recording::wav, stt::AudioStream};
+
+/// Audio to play.
+pub enum Prompt {
+    /// Mono PCM at `sample_rate`, e.g. from a [`TextToSpeech`](crate::tts::TextToSpeech).
+    Audio {
+        /// The audio, played as it comes.
+        audio: AudioStream,
+        /// 8000 or 16000.
+        sample_rate: u32,
+    },
+    /// A 16-bit PCM WAV file at 8 or 16 kHz; channels are mixed down.
+    Wav(PathBuf),
+}
+
+impl std::fmt::Debug for Prompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Audio { sample_rate, .. } => f
+                .debug_struct("Audio")
+                .field("sample_rate", sample_rate)
+                .finish_non_exhaustive(),
+            Self::Wav(path) => f.debug_tuple("Wav").field(path).finish(),
+        }
+    }
+}
+
+impl Prompt {
+    /// The audio with its rate, reading WAV files whole.
+    pub fn open(self) -> Result<(AudioStream, u32)> {
+        match self {
+            Self::Audio { audio, sample_rate } => Ok((audio, sample_rate)),
+            Self::Wav(path) => {
+                let wav = wav::read(&path).map_err(|e| match e {
+                    VoipError::Io(e) if e.kind() == ErrorKind::NotFound => {
+                        VoipError::NotFound(format!("prompt {}", path.display()))
+                    }
+                    e => e,
+                })?;
+                Ok((stream::iter([wav.mono()]).boxed(), wav.sample_rate))
+            }
+        }
+    }
+}
+
+/// A prompt queued by a [`Prompter`].
+#[derive(Debug)]
+pub struct PromptHandle {
+    id: String,
+    done: oneshot::Receiver<bool>,
+}
+
+impl PromptHandle {
+    /// Identifier of the prompt, for the logs.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Wait for the prompt to end: `true` once played to the end, `false`
+    /// when cancelled.
+    pub async fn finished(self) -> bool {
+        self.done.await.unwrap_or(false)
+    }
+}
+
+/// Plays prompts to one leg, in order.
+#[derive(Debug)]
+pub struct Prompter {
+    playback: Playback,
+    /// Held while a prompt is fed, so prompts do not interleave.
+    feeding: Arc<Mutex<()>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Prompter {
+    /// Feed prompts to `playback`.
+    pub fn new(playback: Playback) -> Self {
+        Self {
+            playback,
+            feeding: Arc::new(Mutex::new(())),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Where the prompts are queued.
+    pub fn playback(&self) -> Playback {
+        self.playback.clone()
+    }
+
+    /// Queue `audio` at `sample_rate` after the prompts already queued.
+    pub fn play(&mut self, mut audio: AudioStream, sample_rate: u32) -> Result<PromptHandle> {
+        let mut resampler = Resampler::new(sample_rate, self.playback.sample_rate())?;
+        let (tx, done) = oneshot::channel();
+        let playback = self.playback.clone();
+        let feeding = self.feeding.clone();
+        let clears = playback.clears();
+        self.tasks.retain(|task| !task.is_finished());
+        self.tasks.push(tokio::spawn(async move {
+            let fed = feeding.lock().await;
+            while let Some(chunk) = audio.next().await {
+                if !playback.push_uncleared(clears, &resampler.process(&chunk)) {
+                    let _ = tx.send(false);
+                    return;
+                }
+            }
+            let played = playback.played();
+            drop(fed);
+            let _ = tx.send(played.await.unwrap_or(false));
+        }));
+        Ok(PromptHandle {
+            id: Uuid::new_v4().to_string(),
+            done,
+        })
+    }
+
+    /// Stop feeding the queued prompts and drop the audio not played yet;
+    /// returns whether anything was playing or queued.
+    pub fn cancel(&mut self) -> bool {
+        let mut cancelled = self.playback.is_playing();
+        for task in self.tasks.drain(..) {
+            cancelled |= !task.is_finished();
+            task.abort();
+        }
+        self.playback.clear();
+        cancelled
+    }
+}
+
+impl Drop for Prompter {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn prompts_play_in_order_until_cancelled() {
+        let (playback, _marks) = Playback::new(16000).expect("playback");
+        let mut prompter = Prompter::new(playback.clone());
+        let (slow_tx, slow) = tokio::sync::mpsc::unbounded_channel::<Vec<i16>>();
+        let first = prompter
+            .play(
+                tokio_stream::wrappers::UnboundedReceiverStream::new(slow).boxed(),
+                8000,
+            )
+            .expect("first");
+        let second = prompter
+            .play(stream::iter([vec![2; 160]]).boxed(), 16000)
+            .expect("second");
+        slow_tx.send(vec![1; 80]).expect("chunk");
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(playback.next_frame(160).map(|f| f.len()), Some(160));
+        assert!(!playback.is_playing(), "the second prompt waits its turn");
+        drop(slow_tx);
+        assert!(first.finished().await);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(playback.next_frame(160), Some(vec![2; 160]));
+        assert!(second.finished().await);
+
+        let third = prompter
+            .play(stream::iter([vec![3; 1600]]).boxed(), 16000)
+            .expect("third");
+        let fourth = prompter
+            .play(stream::pending().boxed(), 16000)
+            .expect("fourth");
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(prompter.cancel());
+        assert!(!third.finished().await);
+        assert!(!fourth.finished().await);
+        assert_eq!(playback.next_frame(160), None);
+        assert!(!prompter.cancel());
+        assert!(prompter.play(stream::empty().boxed(), 44100).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Receive-side RTP statistics (RFC 3550 §6.4.1, Appendix A.1, A.3 and A.8).
+
+use std::time::Duration;
+
+use crate::{rtcp::ReportBlock, rtp::RtpPacket};
+
+/// Clock rate assumed for narrowband telephony payloads.
+pub const DEFAULT_CLOCK_RATE: u32 = 8000;
+
+/// Sequence jump treated as a restart rather than loss.
+const MAX_DROPOUT: u16 = 3000;
+/// Sequence step back still treated as reordering.
+const MAX_MISORDER: u16 = 100;
+
+/// Loss, jitter and sequence tracking for the source received on one leg.
+#[derive(Debug, Clone)]
+pub struct ReceptionStats {
+    clock_rate: u32,
+    ssrc: Option<u32>,
+    base_seq: u32,
+    max_seq: u16,
+    cycles: u32,
+    received: u64,
+    expected_prior: u64,
+    received_prior: u64,
+    transit: Option<u32>,
+    jitter: f64,
+    discarded: u64,
+    /// Loss events: runs of consecutive missing sequence numbers, plus discards.
+    loss_events: u64,
+}
+
+impl Default for ReceptionStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK_RATE)
+    }
+}
+
+impl ReceptionStats {
+    /// Track a source whose RTP clock runs at `clock_rate` Hz.
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate: clock_rate.max(1),
+            ssrc: None,
+            base_seq: 0,
+            max_seq: 0,
+            cycles: 0,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            transit: None,
+            jitter: 0.0,
+            discarded: 0,
+            loss_events: 0,
+        }
+    }
+
+    /// Source being tracked.
+    pub fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+
+    /// Account for a packet that arrived `arrival` after an arbitrary fixed origin.
+    pub fn on_packet(&mut self, packet: &RtpPacket<'_>, arrival: Duration) {
+        if self.ssrc != Some(packet.ssrc) {
+            *self = Self::new(self.clock_rate);
+            self.ssrc = Some(packet.ssrc);
+            self.restart(packet.sequence);
+        } else {
+            let delta = packet.sequence.wrapping_sub(self.max_seq);
+            if delta < MAX_DROPOUT {
+                if delta > 1 {
+                    self.loss_events += 1;
+                }
+                if packet.sequence < self.max_seq {
+                    self.cycles += 1 << 16;
+                }
+                self.max_seq = packet.sequence;
+            } else if delta <= u16::MAX - MAX_MISORDER {
+                // A large jump: the sender restarted its sequence.
+                self.restart(packet.sequence);
+            }
+        }
+        self.received += 1;
+
+        let arrival_units = (arrival.as_secs_f64() * f64::from(self.clock_rate)) as u64 as u32;
+        let transit = arrival_units.wrapping_sub(packet.timestamp);
+        if let Some(previous) = self.transit {
+            let d = f64::from((transit.wrapping_sub(previous) as i32).unsigned_abs());
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.transit = Some(transit);
+    }
+
+    /// Account for a received packet the jitter buffer had to throw away as late.
+    pub fn on_discard(&mut self) {
+        self.discarded += 1;
+        self.loss_events += 1;
+    }
+
+    fn restart(&mut self, sequence: u16) {
+        self.base_seq = u32::from(sequence);
+        self.max_seq = sequence;
+        self.cycles = 0;
+        self.received = 0;
+        self.expected_prior = 0;
+        self.received_prior = 0;
+        self.discarded = 0;
+        self.loss_events = 0;
+    }
+
+    /// Packets received, duplicates included.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Extended highest sequence number.
+    pub fn extended_max(&self) -> u32 {
+        self.cycles + u32::from(self.max_seq)
+    }
+
+    /// Packets expected from the sequence range seen.
+    pub fn expected(&self) -> u64 {
+        if self.ssrc.is_none() {
+            return 0;
+        }
+        u64::from(self.extended_max() - self.base_seq) + 1
+    }
+
+    /// Packets lost since reception began; negative with duplicates.
+    pub fn cumulative_lost(&self) -> i64 {
+        self.expected() as i64 - self.received as i64
+    }
+
+    /// Share of expected packets that never arrived, in percent.
+    pub fn loss_percent(&self) -> f64 {
+        match self.expected() {
+            0 => 0.0,
+            expected => self.cumulative_lost().max(0) as f64 * 100.0 / expected as f64,
+        }
+    }
+
+    /// Packets reported through [`ReceptionStats::on_discard`].
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Share of expected packets lost or discarded, as seen by the decoder.
+    pub fn effective_loss_percent(&self) -> f64 {
+        match self.expected() {
+            0 => 0.0,
+            expected => {
+                let lost = self.cumulative_lost().max(0) as u64 + self.discarded;
+                (lost as f64 * 100.0 / expected as f64).min(100.0)
+            }
+        }
+    }
+
+    /// BurstR (ITU-T G.107 §7.4): mean loss run length over the one random loss would give.
+    pub fn burst_ratio(&self) -> f64 {
+        let lost = self.cumulative_lost().max(0) as u64 + self.discarded;
+        let played = self.received.saturating_sub(self.discarded);
+        if lost == 0 || played == 0 || self.loss_events == 0 {
+            return 1.0;
+        }
+        // Two-state model: p = P(loss | previous received), q = P(received | previous lost).
+        let events = self.loss_events as f64;
+        let p = events / played as f64;
+        let q = (events / lost as f64).min(1.0);
+        1.0 / (p + q)
+    }
+
+    /// Interarrival jitter in timestamp units.
+    pub fn jitter(&self) -> u32 {
+        self.jitter as u32
+    }
+
+    /// Interarrival jitter in milliseconds.
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter * 1000.0 / f64::from(self.clock_rate)
+    }
+
+    /// Build a reception report and start a new interval for `fraction_lost`.
+    ///
+    /// `last_sr` pairs the LSR value of the source's last SR with the time elapsed
+    /// since it arrived.
+    pub fn report_block(&mut self, last_sr: Option<(u32, Duration)>) -> Option<ReportBlock> {
+        let ssrc = self.ssrc?;
+        let expected = self.expected();
+        let expected_interval = expected - self.expected_prior;
+        let received_interval = self.received - self.received_prior;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64).min(255) as u8
+        };
+        let (last_sr, delay_since_last_sr) = last_sr.map_or((0, 0), |(lsr, since)| {
+            (lsr, (since.as_secs_f64() * 65536.0) as u32)
+        });
+        Some(ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: self.cumulative_lost().clamp(-0x80_0000, 0x7f_ffff) as i32,
+            highest_sequence: self.extended_max(),
+            jitter: self.jitter(),
+            last_sr,
+            delay_since_last_sr,
+        })
+    }
+}
+
+/// Round-trip time from an RR/SR report block (RFC 3550 §6.4.1, Figure 2).
+///
+/// `sent_at` is when the SR referenced by `block.last_sr` was relayed towards
+/// the reporter and `now` is when the block came back.
+pub fn round_trip(block: &ReportBlock, sent_at: Duration, now: Duration) -> Option<Duration> {
+    let dlsr = Duration::from_secs_f64(f64::from(block.delay_since_last_sr) / 65536.0);
+    now.checked_sub(sent_at)?.checked_sub(dlsr)
+}
+
+/// Threshold alert with hysteresis: it fires when a metric rises above the
+/// threshold, then stays quiet until the metric falls back below `rearm`
+/// times the threshold.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Alert {
+    raised: bool,
+}
+
+impl Alert {
+    /// Feed the latest value; true when it has just crossed `threshold`.
+    pub fn update(&mut self, value: f64, threshold: f64, rearm: f64) -> bool {
+        if self.raised {
+            self.raised = value >= threshold * rearm;
+            return false;
+        }
+        self.raised = value > threshold;
+        self.raised
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: u16, timestamp: u32) -> RtpPacket<'static> {
+        RtpPacket::new(0, sequence, timestamp, 0xabcd, Vec::new())
+    }
+
+    #[test]
+    fn counts_loss_across_sequence_wrap() {
+        let mut stats = ReceptionStats::default();
+        for (i, seq) in [65533u16, 65534, 0, 1, 3].into_iter().enumerate() {
+            stats.on_packet(
+                &packet(seq, 160 * i as u32),
+                Duration::from_millis(20 * i as u64),
+            );
+        }
+        assert_eq!(stats.expected(), 7);
+        assert_eq!(stats.cumulative_lost(), 2);
+        assert_eq!(stats.extended_max(), 65536 + 3);
+
+        let block = stats.report_block(None).expect("block");
+        assert_eq!(block.fraction_lost, (2 * 256 / 7) as u8);
+        assert_eq!(block.cumulative_lost, 2);
+        stats.on_packet(&packet(4, 800), Duration::from_millis(100));
+        assert_eq!(
+            stats.report_block(None).expect("block").fraction_lost,
+            0,
+            "no loss in the second interval"
+        );
+    }
+
+    #[test]
+    fn jitter_follows_rfc3550_estimator() {
+        let mut stats = ReceptionStats::default();
+        // 20 ms packets; every other one arrives 10 ms late (80 timestamp units).
+        for i in 0..200u32 {
+            let late = if i % 2 == 1 { 10 } else { 0 };
+            stats.on_packet(
+                &packet(i as u16, i * 160),
+                Duration::from_millis(u64::from(i * 20 + late)),
+            );
+        }
+        assert!(
+            (stats.jitter() as i64 - 80).abs() <= 1,
+            "{}",
+            stats.jitter()
+        );
+        assert!((stats.jitter_ms() - 10.0).abs() < 0.2);
+        assert_eq!(stats.loss_percent(), 0.0);
+    }
+
+    #[test]
+    fn discards_and_loss_bursts_are_measured() {
+        let mut stats = ReceptionStats::default();
+        // 100 packets at 20 ms; 10 consecutive ones lost, one discarded as late.
+        for i in (0..100u16).filter(|i| !(40..50).contains(i)) {
+            stats.on_packet(
+                &packet(i, u32::from(i) * 160),
+                Duration::from_millis(u64::from(i) * 20),
+            );
+        }
+        stats.on_discard();
+        assert_eq!(stats.cumulative_lost(), 10);
+        assert_eq!(stats.discarded(), 1);
+        assert!((stats.effective_loss_percent() - 11.0).abs() < 1e-9);
+        assert!(stats.burst_ratio() > 4.0, "{}", stats.burst_ratio());
+    }
+
+    #[test]
+    fn round_trip_subtracts_remote_delay() {
+        let block = ReportBlock {
+            delay_since_last_sr: 65536 / 2,
+            ..ReportBlock::default()
+        };
+        let rtt = round_trip(
+            &block,
+            Duration::from_secs(10),
+            Duration::from_millis(10_600),
+        );
+        assert_eq!(rtt, Some(Duration::from_millis(100)));
+        assert_eq!(
+            round_trip(&block, Duration::from_secs(10), Duration::from_secs(10)),
+            None
+        );
+    }
+
+    #[test]
+    fn alert_fires_once_until_the_metric_recovers() {
+        let mut alert = Alert::default();
+        let fired: Vec<bool> = [2.0, 6.0, 8.0, 4.0, 3.0, 2.0, 7.0]
+            .into_iter()
+            .map(|value| alert.update(value, 5.0, 0.5))
+            .collect();
+        assert_eq!(
+            fired,
+            [false, true, false, false, false, false, true],
+            "4 and 3 stay above the 2.5 rearm level"
+        );
+    }
+}
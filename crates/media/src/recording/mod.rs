@@ -0,0 +1,729 @@
+//! Call recording to WAV, mixed to mono or with one leg per stereo channel.
+//!
+//! The relay hands the RTP payloads of each leg to a [`RecordingTap`]. A
+//! writer thread decodes them, places them on a common timeline by arrival
+//! time and fills gaps (DTX, loss, a leg that is not sending) with silence.
+//! Audio is written once it is [`LATENCY`] old, so the other leg's packets of
+//! the same moment can still be mixed in. The header gets its final sizes
+//! when the recording stops, which also happens when its relay stops.
+//!
+//! A [`RecordingControl`] pauses the recording, e.g. while a card number is
+//! read out: audio received while paused is dropped and the stretch is
+//! written as silence or a marker tone, so the file keeps the call's length.
+//! Each pause is reported in [`RecordingInfo::pauses`] for audit.
+
+pub mod wav;
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+use tracing::debug;
+use voip_common::{Result, VoipError};
+
+use crate::{
+    codec::{resample::Resampler, AudioCodec, Decoder},
+    relay::Side,
+};
+
+pub use wav::WavWriter;
+
+/// Age at which audio is written, padding a leg that has not caught up.
+pub const LATENCY: Duration = Duration::from_millis(200);
+/// How often the writer wakes up to write audio that is old enough.
+const TICK: Duration = Duration::from_millis(20);
+/// Lateness of a frame beyond which the time before it is left silent.
+const GAP_TOLERANCE: Duration = Duration::from_millis(60);
+/// Frequency of the marker tone written over paused stretches.
+const MARKER_HZ: f64 = 1000.0;
+/// Marker tone amplitude, about -16 dBFS.
+const MARKER_AMPLITUDE: f64 = 5000.0;
+/// The marker beeps for this long at the start of every second.
+const MARKER_BEEP: f64 = 0.25;
+
+/// How the two legs share the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channels {
+    /// Both legs summed into one channel.
+    Mixed,
+    /// Caller on the left channel, callee on the right.
+    Stereo,
+}
+
+impl Channels {
+    fn count(self) -> u16 {
+        match self {
+            Self::Mixed => 1,
+            Self::Stereo => 2,
+        }
+    }
+}
+
+/// What replaces the audio of a paused stretch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PauseFill {
+    /// Digital silence.
+    #[default]
+    Silence,
+    /// A 1 kHz beep at the start of every second, so the pause is audible.
+    Tone,
+}
+
+/// What a recording writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordingOptions {
+    /// Mono mix or one leg per channel.
+    pub channels: Channels,
+    /// Output rate; 8000 or 16000.
+    pub sample_rate: u32,
+    /// What paused stretches are written as.
+    pub pause_fill: PauseFill,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            channels: Channels::Mixed,
+            sample_rate: 8000,
+            pause_fill: PauseFill::Silence,
+        }
+    }
+}
+
+/// What paused or resumed a recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseTrigger {
+    /// A management API call.
+    Api,
+    /// A DTMF sequence keyed on the call.
+    Dtmf,
+}
+
+impl PauseTrigger {
+    /// Lowercase name for logs and metadata.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::Dtmf => "dtmf",
+        }
+    }
+}
+
+/// A stretch of a recording whose audio was left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauseInterval {
+    /// Where the pause starts in the recording.
+    pub offset: Duration,
+    /// How long it lasted; a pause still open at stop ends there.
+    pub duration: Duration,
+    /// What paused the recording.
+    pub trigger: PauseTrigger,
+    /// Wall clock time of the pause.
+    pub paused_at: DateTime<Utc>,
+}
+
+/// A recording that was stopped and finalized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingInfo {
+    /// Identifier given when it started.
+    pub recording_id: String,
+    /// WAV file written.
+    pub path: PathBuf,
+    /// File size, header included.
+    pub file_size: u64,
+    /// Audio length.
+    pub duration: Duration,
+    /// What was written.
+    pub options: RecordingOptions,
+    /// When recording started.
+    pub started_at: DateTime<Utc>,
+    /// When it stopped.
+    pub stopped_at: DateTime<Utc>,
+    /// Paused stretches, oldest first.
+    pub pauses: Vec<PauseInterval>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Frame {
+        side: Side,
+        at: Instant,
+        codec: AudioCodec,
+        payload: Vec<u8>,
+    },
+    Pause {
+        at: Instant,
+    },
+    Resume {
+        at: Instant,
+    },
+    Stop,
+}
+
+/// Feeds received audio to a recording; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct RecordingTap {
+    tx: mpsc::Sender<Message>,
+}
+
+impl RecordingTap {
+    /// Record an RTP payload received from `side` just now.
+    pub fn push(&self, side: Side, codec: AudioCodec, payload: &[u8]) {
+        // A stopped recording ignores late audio.
+        let _ = self.tx.send(Message::Frame {
+            side,
+            at: Instant::now(),
+            codec,
+            payload: payload.to_vec(),
+        });
+    }
+}
+
+/// Pauses seen by a [`RecordingControl`].
+#[derive(Debug, Default)]
+struct PauseLog {
+    /// The pause in progress, with its start.
+    open: Option<(Instant, PauseTrigger, DateTime<Utc>)>,
+    closed: Vec<PauseInterval>,
+    stopped: bool,
+}
+
+/// Pauses and resumes a recording; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct RecordingControl {
+    tx: mpsc::Sender<Message>,
+    origin: Instant,
+    log: Arc<Mutex<PauseLog>>,
+}
+
+impl RecordingControl {
+    /// Leave out the audio from now on until [`RecordingControl::resume`].
+    pub fn pause(&self, trigger: PauseTrigger) -> Result<()> {
+        let mut log = self.log();
+        if log.stopped {
+            return Err(VoipError::Validation("recording has stopped".into()));
+        }
+        if log.open.is_some() {
+            return Err(VoipError::Validation("recording is already paused".into()));
+        }
+        let at = Instant::now();
+        self.tx
+            .send(Message::Pause { at })
+            .map_err(|_| VoipError::Media("recording writer has stopped".into()))?;
+        log.open = Some((at, trigger, Utc::now()));
+        Ok(())
+    }
+
+    /// Record audio again.
+    pub fn resume(&self) -> Result<()> {
+        let mut log = self.log();
+        if log.stopped {
+            return Err(VoipError::Validation("recording has stopped".into()));
+        }
+        if log.open.is_none() {
+            return Err(VoipError::Validation("recording is not paused".into()));
+        }
+        let at = Instant::now();
+        self.tx
+            .send(Message::Resume { at })
+            .map_err(|_| VoipError::Media("recording writer has stopped".into()))?;
+        self.close(&mut log, at);
+        Ok(())
+    }
+
+    /// Whether the recording is paused right now.
+    pub fn is_paused(&self) -> bool {
+        self.log().open.is_some()
+    }
+
+    /// Whether the recording has stopped, so it can no longer be paused.
+    pub fn is_stopped(&self) -> bool {
+        self.log().stopped
+    }
+
+    /// Close a pause left open and return every pause.
+    fn finish(&self) -> Vec<PauseInterval> {
+        let mut log = self.log();
+        log.stopped = true;
+        self.close(&mut log, Instant::now());
+        std::mem::take(&mut log.closed)
+    }
+
+    fn close(&self, log: &mut PauseLog, at: Instant) {
+        if let Some((start, trigger, paused_at)) = log.open.take() {
+            log.closed.push(PauseInterval {
+                offset: start.saturating_duration_since(self.origin),
+                duration: at.saturating_duration_since(start),
+                trigger,
+                paused_at,
+            });
+        }
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, PauseLog> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A recording in progress.
+#[derive(Debug)]
+pub struct Recorder {
+    recording_id: String,
+    path: PathBuf,
+    options: RecordingOptions,
+    started_at: DateTime<Utc>,
+    tx: mpsc::Sender<Message>,
+    control: RecordingControl,
+    /// Writer thread, returning the frames written and the file size.
+    task: JoinHandle<Result<(u64, u64)>>,
+}
+
+impl Recorder {
+    /// Create `path` and start the writer; feed it through [`Recorder::tap`].
+    pub fn start(
+        recording_id: impl Into<String>,
+        path: impl Into<PathBuf>,
+        options: RecordingOptions,
+    ) -> Result<Self> {
+        if !matches!(options.sample_rate, 8000 | 16000) {
+            return Err(VoipError::Validation(format!(
+                "cannot record at {} Hz, use 8000 or 16000",
+                options.sample_rate
+            )));
+        }
+        let path = path.into();
+        let writer = WavWriter::create(&path, options.channels.count(), options.sample_rate)?;
+        let (tx, rx) = mpsc::channel();
+        let origin = Instant::now();
+        let task =
+            tokio::task::spawn_blocking(move || Timeline::new(options, origin).run(&rx, writer));
+        let control = RecordingControl {
+            tx: tx.clone(),
+            origin,
+            log: Arc::default(),
+        };
+        Ok(Self {
+            recording_id: recording_id.into(),
+            path,
+            options,
+            started_at: Utc::now(),
+            tx,
+            control,
+            task,
+        })
+    }
+
+    /// Identifier given at start.
+    pub fn recording_id(&self) -> &str {
+        &self.recording_id
+    }
+
+    /// File being written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A sender for the relay to push audio through.
+    pub fn tap(&self) -> RecordingTap {
+        RecordingTap {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// A handle to pause and resume the recording.
+    pub fn control(&self) -> RecordingControl {
+        self.control.clone()
+    }
+
+    /// Write out buffered audio and finalize the file.
+    pub async fn stop(self) -> Result<RecordingInfo> {
+        let pauses = self.control.finish();
+        // The writer may already have failed; its result says why.
+        let _ = self.tx.send(Message::Stop);
+        let (frames, file_size) = self
+            .task
+            .await
+            .map_err(|e| VoipError::Internal(format!("recording writer failed: {}", e)))??;
+        Ok(RecordingInfo {
+            recording_id: self.recording_id,
+            path: self.path,
+            file_size,
+            duration: Duration::from_secs_f64(frames as f64 / f64::from(self.options.sample_rate)),
+            options: self.options,
+            started_at: self.started_at,
+            stopped_at: Utc::now(),
+            pauses,
+        })
+    }
+}
+
+/// Decoded audio of one leg not yet written.
+#[derive(Debug, Default)]
+struct Track {
+    decoder: Option<(AudioCodec, Decoder, Resampler)>,
+    pending: VecDeque<i16>,
+    /// Timeline position where `pending` ends; never behind what was written.
+    end: u64,
+}
+
+impl Track {
+    fn pad_to(&mut self, position: u64) {
+        while self.end < position {
+            self.pending.push_back(0);
+            self.end += 1;
+        }
+    }
+}
+
+/// Places both legs on one sample clock and writes them out.
+#[derive(Debug)]
+struct Timeline {
+    options: RecordingOptions,
+    origin: Instant,
+    /// Frames written so far, which is also the timeline position written up to.
+    written: u64,
+    tracks: [Track; 2],
+    pcm: Vec<i16>,
+    /// Paused stretches as (start, end) positions; the last may be open.
+    pauses: Vec<(u64, Option<u64>)>,
+}
+
+impl Timeline {
+    fn new(options: RecordingOptions, origin: Instant) -> Self {
+        Self {
+            options,
+            origin,
+            written: 0,
+            tracks: Default::default(),
+            pcm: Vec::new(),
+            pauses: Vec::new(),
+        }
+    }
+
+    fn samples(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * f64::from(self.options.sample_rate)) as u64
+    }
+
+    fn position(&self, at: Instant) -> u64 {
+        self.samples(at.saturating_duration_since(self.origin))
+    }
+
+    fn run(mut self, rx: &mpsc::Receiver<Message>, mut writer: WavWriter) -> Result<(u64, u64)> {
+        loop {
+            match rx.recv_timeout(TICK) {
+                Ok(Message::Frame {
+                    side,
+                    at,
+                    codec,
+                    payload,
+                }) => self.push(side, at, codec, &payload),
+                Ok(Message::Pause { at }) => {
+                    let start = self.position(at).max(self.written);
+                    self.pauses.push((start, None));
+                }
+                Ok(Message::Resume { at }) => {
+                    let end = self.position(at);
+                    if let Some((start, open @ None)) = self.pauses.last_mut() {
+                        *open = Some(end.max(*start));
+                    }
+                    // Audio after the pause lines up with it, not with what came before.
+                    for track in &mut self.tracks {
+                        track.pad_to(end);
+                    }
+                }
+                Ok(Message::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+            let horizon = self
+                .position(Instant::now())
+                .saturating_sub(self.samples(LATENCY));
+            self.flush(horizon, &mut writer)?;
+        }
+        let mut end = self.tracks.iter().map(|track| track.end).max().unwrap_or(0);
+        if let Some((_, None)) = self.pauses.last() {
+            // A pause open at stop lasts until now.
+            end = end.max(self.position(Instant::now()));
+        }
+        self.flush(end, &mut writer)?;
+        let frames = writer.frames();
+        Ok((frames, writer.finalize()?))
+    }
+
+    /// Decode a payload that arrived at `at` and queue it on its leg.
+    fn push(&mut self, side: Side, at: Instant, codec: AudioCodec, payload: &[u8]) {
+        let index = match side {
+            Side::Caller => 0,
+            Side::Callee => 1,
+        };
+        let position = self.position(at);
+        if self.paused_at(position).is_some() {
+            return;
+        }
+        let tolerance = self.samples(GAP_TOLERANCE);
+        let track = &mut self.tracks[index];
+        if track
+            .decoder
+            .as_ref()
+            .is_none_or(|(current, ..)| *current != codec)
+        {
+            match Resampler::new(codec.sample_rate(), self.options.sample_rate) {
+                Ok(resampler) => track.decoder = Some((codec, codec.decoder(), resampler)),
+                Err(err) => {
+                    debug!(?side, error = %err, "audio left out of the recording");
+                    track.decoder = None;
+                    return;
+                }
+            }
+        }
+        let Some((_, decoder, resampler)) = track.decoder.as_mut() else {
+            return;
+        };
+        self.pcm.clear();
+        decoder.decode(payload, &mut self.pcm);
+        let pcm = resampler.process(&self.pcm);
+        // The frame arrived once fully captured, so it started before `position`.
+        let start = position.saturating_sub(pcm.len() as u64);
+        if start > track.end + tolerance {
+            track.pad_to(start);
+        }
+        track.end += pcm.len() as u64;
+        track.pending.extend(pcm);
+    }
+
+    /// Start of the pause covering `position`, if any.
+    fn paused_at(&self, position: u64) -> Option<u64> {
+        self.pauses
+            .iter()
+            .find(|(start, end)| *start <= position && end.is_none_or(|end| position < end))
+            .map(|(start, _)| *start)
+    }
+
+    /// Sample written `elapsed` samples into a pause.
+    fn fill(&self, elapsed: u64) -> i16 {
+        let t = elapsed as f64 / f64::from(self.options.sample_rate);
+        match self.options.pause_fill {
+            PauseFill::Tone if t.fract() < MARKER_BEEP => {
+                (MARKER_AMPLITUDE * (std::f64::consts::TAU * MARKER_HZ * t).sin()) as i16
+            }
+            PauseFill::Silence | PauseFill::Tone => 0,
+        }
+    }
+
+    /// Write the timeline up to `position`, silence where a leg has nothing.
+    fn flush(&mut self, position: u64, writer: &mut WavWriter) -> Result<()> {
+        if position <= self.written {
+            return Ok(());
+        }
+        let frames = (position - self.written) as usize;
+        for track in &mut self.tracks {
+            track.pad_to(position);
+        }
+        let [caller, callee] = &mut self.tracks;
+        let pairs: Vec<(i16, i16)> = caller
+            .pending
+            .drain(..frames)
+            .zip(callee.pending.drain(..frames))
+            .collect();
+        let mut out = Vec::with_capacity(frames * usize::from(self.options.channels.count()));
+        for (at, (a, b)) in (self.written..).zip(pairs) {
+            // Audio queued before the pause was known is left out as well.
+            match (self.paused_at(at), self.options.channels) {
+                (Some(start), Channels::Mixed) => out.push(self.fill(at - start)),
+                (Some(start), Channels::Stereo) => out.extend([self.fill(at - start); 2]),
+                (None, Channels::Mixed) => out.push(a.saturating_add(b)),
+                (None, Channels::Stereo) => out.extend([a, b]),
+            }
+        }
+        writer.write(&out)?;
+        self.written = position;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::g711;
+
+    fn temp_wav() -> PathBuf {
+        std::env::temp_dir().join(format!("recording-{}.wav", uuid::Uuid::new_v4()))
+    }
+
+    fn samples(path: &Path) -> Vec<i16> {
+        let bytes = std::fs::read(path).expect("read");
+        std::fs::remove_file(path).expect("remove");
+        bytes[wav::HEADER_LEN as usize..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    /// Run `frames` of (side, arrival in ms, μ-law byte) through a timeline.
+    fn record(options: RecordingOptions, frames: &[(Side, u64, u8)]) -> Vec<i16> {
+        record_paused(options, frames, &[])
+    }
+
+    /// Same, pausing over the (start, end) ms stretches of `pauses`.
+    fn record_paused(
+        options: RecordingOptions,
+        frames: &[(Side, u64, u8)],
+        pauses: &[(u64, u64)],
+    ) -> Vec<i16> {
+        let path = temp_wav();
+        let writer = WavWriter::create(&path, options.channels.count(), options.sample_rate)
+            .expect("create");
+        let origin = Instant::now();
+        let at = |ms| origin + Duration::from_millis(ms);
+        let (tx, rx) = mpsc::channel();
+        let mut messages: Vec<(u64, Message)> = frames
+            .iter()
+            .map(|&(side, ms, byte)| {
+                let frame = Message::Frame {
+                    side,
+                    at: at(ms),
+                    codec: AudioCodec::Pcmu,
+                    payload: vec![byte; 160],
+                };
+                (ms, frame)
+            })
+            .collect();
+        for &(start, end) in pauses {
+            messages.push((start, Message::Pause { at: at(start) }));
+            messages.push((end, Message::Resume { at: at(end) }));
+        }
+        messages.sort_by_key(|(ms, _)| *ms);
+        for (_, message) in messages {
+            tx.send(message).expect("send");
+        }
+        tx.send(Message::Stop).expect("stop");
+        Timeline::new(options, origin)
+            .run(&rx, writer)
+            .expect("run");
+        samples(&path)
+    }
+
+    #[test]
+    fn stereo_keeps_legs_apart_and_fills_gaps_with_silence() {
+        let loud = g711::ulaw_decode(0x80);
+        let options = RecordingOptions {
+            channels: Channels::Stereo,
+            ..RecordingOptions::default()
+        };
+        // The callee starts 100 ms late; the caller is silent from 40 to 120 ms.
+        let audio = record(
+            options,
+            &[
+                (Side::Caller, 20, 0x80),
+                (Side::Caller, 40, 0x80),
+                (Side::Caller, 140, 0x80),
+                (Side::Callee, 120, 0x80),
+            ],
+        );
+        let (left, right): (Vec<_>, Vec<_>) =
+            audio.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+        assert_eq!(left.len(), 1120);
+        assert!(left[..320].iter().all(|&s| s == loud));
+        assert!(left[320..960].iter().all(|&s| s == 0));
+        assert!(left[960..].iter().all(|&s| s == loud));
+        assert!(right[..800].iter().all(|&s| s == 0));
+        assert!(right[800..960].iter().all(|&s| s == loud));
+        assert!(right[960..].iter().all(|&s| s == 0));
+
+        let wide = record(
+            RecordingOptions {
+                channels: Channels::Mixed,
+                sample_rate: 16000,
+                ..RecordingOptions::default()
+            },
+            &[(Side::Caller, 20, 0xa0), (Side::Callee, 20, 0xa0)],
+        );
+        assert_eq!(wide.len(), 320);
+        // Past the filter's warm-up both legs add up.
+        let quiet = i32::from(g711::ulaw_decode(0xa0));
+        assert!(wide[100..220]
+            .iter()
+            .all(|&s| (i32::from(s) - 2 * quiet).abs() < 64));
+    }
+
+    #[test]
+    fn paused_stretches_are_blanked_or_marked() {
+        let loud = g711::ulaw_decode(0x80);
+        let frames: Vec<_> = (1..=10).map(|i| (Side::Caller, i * 20, 0x80)).collect();
+        // Frames arriving at 80 to 120 ms fall in the pause and are dropped.
+        let silent = record_paused(RecordingOptions::default(), &frames, &[(70, 130)]);
+        assert_eq!(silent.len(), 1680);
+        assert!(silent[..480].iter().all(|&s| s == loud));
+        assert!(silent[480..1040].iter().all(|&s| s == 0));
+        assert!(silent[1040..].iter().all(|&s| s == loud));
+
+        let marked = record_paused(
+            RecordingOptions {
+                pause_fill: PauseFill::Tone,
+                ..RecordingOptions::default()
+            },
+            &frames,
+            &[(70, 130)],
+        );
+        let beep = &marked[560..1040];
+        assert!(beep.iter().all(|&s| s != loud));
+        // 1 kHz at 8 kHz: one cycle every 8 samples.
+        assert!((f64::from(beep[2]) - MARKER_AMPLITUDE).abs() <= 1.0);
+        assert!((f64::from(beep[6]) + MARKER_AMPLITUDE).abs() <= 1.0);
+    }
+
+    #[tokio::test]
+    async fn stop_finalizes_the_file_and_reports_its_length() {
+        let path = temp_wav();
+        assert!(Recorder::start(
+            "bad",
+            &path,
+            RecordingOptions {
+                sample_rate: 44100,
+                ..RecordingOptions::default()
+            }
+        )
+        .is_err());
+        let recorder = Recorder::start("rec-1", &path, RecordingOptions::default()).expect("start");
+        let tap = recorder.tap();
+        let control = recorder.control();
+        assert!(control.resume().is_err());
+        for _ in 0..5 {
+            tap.push(Side::Caller, AudioCodec::Pcma, &[0xd5; 160]);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        control.pause(PauseTrigger::Dtmf).expect("pause");
+        assert!(control.pause(PauseTrigger::Api).is_err());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        let info = recorder.stop().await.expect("stop");
+        assert_eq!(info.recording_id, "rec-1");
+        // The pause left open is closed at stop.
+        assert_eq!(info.pauses.len(), 1);
+        assert_eq!(info.pauses[0].trigger, PauseTrigger::Dtmf);
+        assert!(info.pauses[0].offset >= Duration::from_millis(100));
+        assert!(info.pauses[0].duration >= Duration::from_millis(40));
+        // Up to a sample is lost to rounding.
+        assert!(
+            info.duration + Duration::from_millis(1)
+                >= info.pauses[0].offset + info.pauses[0].duration
+        );
+        assert!(control.is_stopped() && control.pause(PauseTrigger::Api).is_err());
+        assert!(
+            info.duration >= Duration::from_millis(100),
+            "{:?}",
+            info.duration
+        );
+        assert_eq!(
+            info.file_size,
+            std::fs::metadata(&path).expect("metadata").len()
+        );
+        let audio = samples(&path);
+        assert_eq!(audio.len() as u64 * 2 + wav::HEADER_LEN, info.file_size);
+        // Audio pushed after the stop goes nowhere.
+        tap.push(Side::Caller, AudioCodec::Pcma, &[0xd5; 160]);
+    }
+}
@@ -0,0 +1,215 @@
+//! RIFF/WAVE writer for 16-bit PCM whose sizes are patched in when it finishes,
+//! and a reader for the same format.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use voip_common::{Result, VoipError};
+
+/// Bytes of the canonical header: RIFF, `fmt ` and `data` chunk headers.
+pub const HEADER_LEN: u64 = 44;
+
+/// Bits per sample written.
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Streams interleaved samples to a WAV file.
+#[derive(Debug)]
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    /// Bytes of sample data written so far.
+    data_len: u32,
+}
+
+impl WavWriter {
+    /// Create `path` with a header announcing no data yet.
+    pub fn create(path: &Path, channels: u16, sample_rate: u32) -> Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            channels,
+            sample_rate,
+            data_len: 0,
+        };
+        let header = writer.header();
+        writer.file.write_all(&header)?;
+        Ok(writer)
+    }
+
+    /// Append interleaved samples.
+    pub fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let len = u32::try_from(samples.len() * 2)
+            .ok()
+            .and_then(|len| self.data_len.checked_add(len))
+            .filter(|&len| u64::from(len) + HEADER_LEN <= u64::from(u32::MAX))
+            .ok_or_else(|| VoipError::Media("WAV file size limit reached".into()))?;
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len = len;
+        Ok(())
+    }
+
+    /// Frames (one sample per channel) written so far.
+    pub fn frames(&self) -> u64 {
+        u64::from(self.data_len) / (2 * u64::from(self.channels))
+    }
+
+    /// Rewrite the header with the final sizes; returns the file size.
+    pub fn finalize(mut self) -> Result<u64> {
+        let header = self.header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        Ok(HEADER_LEN + u64::from(self.data_len))
+    }
+
+    fn header(&self) -> [u8; HEADER_LEN as usize] {
+        let block_align = self.channels * BITS_PER_SAMPLE / 8;
+        let byte_rate = self.sample_rate * u32::from(block_align);
+        let mut header = [0u8; HEADER_LEN as usize];
+        let fields: [&[u8]; 13] = [
+            b"RIFF",
+            &(HEADER_LEN as u32 - 8 + self.data_len).to_le_bytes(),
+            b"WAVE",
+            b"fmt ",
+            &16u32.to_le_bytes(),
+            // PCM
+            &1u16.to_le_bytes(),
+            &self.channels.to_le_bytes(),
+            &self.sample_rate.to_le_bytes(),
+            &byte_rate.to_le_bytes(),
+            &block_align.to_le_bytes(),
+            &BITS_PER_SAMPLE.to_le_bytes(),
+            b"data",
+            &self.data_len.to_le_bytes(),
+        ];
+        let mut offset = 0;
+        for field in fields {
+            header[offset..offset + field.len()].copy_from_slice(field);
+            offset += field.len();
+        }
+        header
+    }
+}
+
+/// Samples of a WAV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WavAudio {
+    /// Interleaved channels.
+    pub channels: u16,
+    /// Frames per second.
+    pub sample_rate: u32,
+    /// Interleaved samples.
+    pub samples: Vec<i16>,
+}
+
+impl WavAudio {
+    /// The channels averaged into one.
+    pub fn mono(&self) -> Vec<i16> {
+        let channels = usize::from(self.channels.max(1));
+        self.samples
+            .chunks_exact(channels)
+            .map(|frame| {
+                let sum: i32 = frame.iter().map(|&s| i32::from(s)).sum();
+                (sum / channels as i32) as i16
+            })
+            .collect()
+    }
+}
+
+/// Read a 16-bit PCM WAV file, skipping chunks other than `fmt ` and `data`.
+pub fn read(path: &Path) -> Result<WavAudio> {
+    let bytes = std::fs::read(path)?;
+    let invalid = |why: &str| VoipError::Media(format!("{}: {}", path.display(), why));
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+    let mut format = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let len = u32::from_le_bytes([bytes[at + 4], bytes[at + 5], bytes[at + 6], bytes[at + 7]]);
+        let body = &bytes[at + 8..(at + 8).saturating_add(len as usize).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+                let (tag, channels, bits) = (u16_at(0), u16_at(2), u16_at(14));
+                // PCM, or WAVE_FORMAT_EXTENSIBLE
+                if !matches!(tag, 1 | 0xfffe) || bits != BITS_PER_SAMPLE || channels == 0 {
+                    return Err(invalid("not 16-bit PCM"));
+                }
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                format = Some((channels, sample_rate));
+            }
+            b"data" => {
+                let (channels, sample_rate) = format.ok_or_else(|| invalid("data before fmt"))?;
+                return Ok(WavAudio {
+                    channels,
+                    sample_rate,
+                    samples: body
+                        .chunks_exact(2)
+                        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                        .collect(),
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        at += 8 + len as usize + (len as usize & 1);
+    }
+    Err(invalid("no data chunk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finalize_patches_riff_and_data_sizes() {
+        let path = std::env::temp_dir().join(format!("wav-{}.wav", uuid::Uuid::new_v4()));
+        let mut writer = WavWriter::create(&path, 2, 16000).expect("create");
+        writer.write(&[1, -1, 2, -2, 3, -3]).expect("write");
+        assert_eq!(writer.frames(), 3);
+        assert_eq!(writer.finalize().expect("finalize"), HEADER_LEN + 12);
+
+        let bytes = std::fs::read(&path).expect("read");
+        std::fs::remove_file(&path).expect("remove");
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("u32"));
+        let u16_at = |at: usize| u16::from_le_bytes(bytes[at..at + 2].try_into().expect("u16"));
+        assert_eq!(
+            (&bytes[..4], &bytes[8..16]),
+            (&b"RIFF"[..], &b"WAVEfmt "[..])
+        );
+        assert_eq!(u32_at(4), 36 + 12);
+        assert_eq!((u16_at(20), u16_at(22), u32_at(24)), (1, 2, 16000));
+        assert_eq!((u32_at(28), u16_at(32), u16_at(34)), (64000, 4, 16));
+        assert_eq!((&bytes[36..40], u32_at(40)), (&b"data"[..], 12));
+        assert_eq!(
+            bytes[44..],
+            [1, 0, 255, 255, 2, 0, 254, 255, 3, 0, 253, 255]
+        );
+    }
+
+    #[test]
+    fn read_returns_what_was_written() {
+        let path = std::env::temp_dir().join(format!("wav-{}.wav", uuid::Uuid::new_v4()));
+        let mut writer = WavWriter::create(&path, 2, 8000).expect("create");
+        writer.write(&[100, 300, -100, -300]).expect("write");
+        writer.finalize().expect("finalize");
+        let audio = read(&path).expect("read");
+        assert_eq!(
+            (audio.channels, audio.sample_rate, &audio.samples[..]),
+            (2, 8000, &[100, 300, -100, -300][..])
+        );
+        assert_eq!(audio.mono(), [200, -200]);
+
+        std::fs::write(&path, b"RIFF\0\0\0\0WAVEjunk").expect("write");
+        assert!(matches!(read(&path), Err(VoipError::Media(_))));
+        std::fs::remove_file(&path).expect("remove");
+    }
+}
@@ -0,0 +1,1978 @@
+//! Symmetric RTP relay between two call legs (RFC 4961).
+//!
+//! Each leg owns an RTP/RTCP port pair. The first valid packet received on a
+//! leg latches its source address; packets are then forwarded to the other
+//! leg's latched (or signalled) address from that leg's own socket, so both
+//! endpoints see a single address per stream even behind NAT.
+//!
+//! RTCP passing through is inspected for RTT (SR relayed one way, RR coming
+//! back), CNAMEs and XR VoIP metrics. Each leg also gets periodic reception
+//! reports about the stream it sends, and an RTCP BYE when the relay stops.
+//! With each report the E-model score of both streams is refreshed.
+//!
+//! Received RTP also goes through an adaptive jitter buffer per leg, whose
+//! playout delay and late discards are what a terminating endpoint would see.
+//! When the legs share no codec, RTP is transcoded on its way across. DTMF
+//! digits received on either leg are announced through [`RelayHandle::subscribe`].
+//!
+//! While a recording or an agent fork is attached, the RTP of both legs is
+//! also handed to it. Audio queued on a [`Playback`] is sent to its leg in
+//! 20 ms packets of the leg's format, in place of the RTP relayed from the
+//! other leg.
+//!
+//! A leg set up with SRTP has its packets authenticated and decrypted on
+//! arrival and protected again on the way out, relay reports included, so a
+//! secure leg can be bridged to a plain one.
+//!
+//! Once a party has sent RTP, its silence is watched: a leg is announced
+//! interrupted when no RTP arrived for [`InactivityTimeouts::rtp`], or, on
+//! hold, when neither RTP nor RTCP arrived for [`InactivityTimeouts::held`].
+//! It is announced resumed when media comes back.
+//!
+//! Loss and jitter of the RTP received on each leg are checked against
+//! [`QosThresholds`] as well, and announced once per excursion above them.
+//!
+//! With [`SpeechDetection`] on a leg, its audio is decoded for a [`Vad`] and
+//! the party's speech is announced as it starts and ends. With barge-in, the
+//! party starting to talk also stops the audio being played to its leg.
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    net::UdpSocket,
+    sync::{broadcast, watch},
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tracing::{debug, info, instrument, trace, warn};
+use uuid::Uuid;
+
+use voip_common::Result;
+
+use crate::{
+    codec::{AudioCodec, Decoder, Encoder, Resampler},
+    dtmf::{Digit, DigitDetector},
+    emodel::{CodecImpairment, Conditions, Score},
+    fork::ForkTap,
+    jitter::{JitterBuffer, Playout, Pushed},
+    playback::Playback,
+    ports::PortPair,
+    qos::{round_trip, Alert, ReceptionStats},
+    recording::RecordingTap,
+    rtcp::{ntp_middle, RtcpPacket, SdesChunk, SdesItem, VoipMetrics, SDES_CNAME},
+    rtp::{is_rtcp, RtpPacket},
+    srtp::{SrtpPolicy, SrtpSession},
+    transcode::{PayloadFormat, Transcoder},
+    vad::{Vad, VadConfig, VadEvent},
+};
+
+/// Largest datagram accepted on a media port.
+const MAX_DATAGRAM: usize = 2048;
+
+/// Interval between the relay's own reception reports (RFC 3550 §6.2 minimum).
+pub const RTCP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// SRs remembered per leg for matching the LSR of returning reports.
+const SR_HISTORY: usize = 8;
+
+/// Events queued per subscriber before the oldest are dropped.
+const EVENT_CAPACITY: usize = 64;
+
+/// Longest interval between two inactivity checks.
+const MAX_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Packets expected before loss over them is checked against its threshold.
+const LOSS_WINDOW: u64 = 50;
+
+/// Share of a threshold a metric must fall below before it is announced again.
+const ALERT_REARM: f64 = 0.5;
+
+/// Packetization of the audio the relay plays itself.
+const FRAME: Duration = Duration::from_millis(20);
+
+/// Side of a relayed call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    /// Leg facing the calling party.
+    Caller,
+    /// Leg facing the called party.
+    Callee,
+}
+
+impl Side {
+    /// The opposite leg.
+    pub const fn other(self) -> Self {
+        match self {
+            Self::Caller => Self::Callee,
+            Self::Callee => Self::Caller,
+        }
+    }
+
+    /// Name used in events.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Caller => "caller",
+            Self::Callee => "callee",
+        }
+    }
+
+    const fn index(self) -> usize {
+        match self {
+            Self::Caller => 0,
+            Self::Callee => 1,
+        }
+    }
+}
+
+/// Point-in-time view of one leg.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LegStats {
+    /// Local RTP port the remote party sends to.
+    pub rtp_port: u16,
+    /// Local RTCP port.
+    pub rtcp_port: u16,
+    /// Remote RTP address, latched or taken from signalling.
+    pub remote: Option<SocketAddr>,
+    /// Whether `remote` was learnt from received traffic.
+    pub latched: bool,
+    /// Datagrams received from the remote party.
+    pub packets_received: u64,
+    /// Bytes received from the remote party.
+    pub bytes_received: u64,
+    /// Datagrams relayed to the remote party.
+    pub packets_sent: u64,
+    /// Bytes relayed to the remote party.
+    pub bytes_sent: u64,
+    /// Datagrams discarded: malformed, from an unexpected source, on hold,
+    /// with nowhere to go, or replaced by played audio.
+    pub packets_dropped: u64,
+    /// Interarrival jitter of the RTP received from the remote party.
+    pub jitter_ms: f64,
+    /// RTP packets from the remote party that never arrived.
+    pub packets_lost: i64,
+    /// `packets_lost` as a share of the packets expected.
+    pub loss_percent: f64,
+    /// RTP packets from the remote party that arrived after their playout slot.
+    pub packets_discarded: u64,
+    /// Current adaptive playout delay for the remote party's RTP.
+    pub playout_delay_ms: f64,
+    /// Round trip between the relay and the remote party, once an RR answered a relayed SR.
+    pub rtt_ms: Option<f64>,
+    /// CNAME announced by the remote party.
+    pub cname: Option<String>,
+    /// Whether media on this leg is SRTP.
+    pub srtp: bool,
+    /// Latest RTCP XR VoIP metrics sent by the remote party.
+    pub remote_metrics: Option<VoipMetrics>,
+    /// E-model score of the stream received from the remote party, as of the last report.
+    pub score: Option<Score>,
+}
+
+/// Silence after which a leg counts as interrupted; `None` disables a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InactivityTimeouts {
+    /// Without RTP while the call is active.
+    pub rtp: Option<Duration>,
+    /// Without RTP or RTCP while the call is on hold.
+    pub held: Option<Duration>,
+}
+
+impl Default for InactivityTimeouts {
+    fn default() -> Self {
+        Self {
+            rtp: Some(Duration::from_secs(30)),
+            held: Some(Duration::from_secs(300)),
+        }
+    }
+}
+
+impl InactivityTimeouts {
+    /// How often to check: a quarter of the shortest timeout, at most a second.
+    fn watch_interval(&self) -> Duration {
+        [self.rtp, self.held]
+            .into_iter()
+            .flatten()
+            .map(|timeout| timeout / 4)
+            .fold(MAX_WATCH_INTERVAL, Duration::min)
+            .max(Duration::from_millis(1))
+    }
+}
+
+/// Levels above which loss and jitter are announced; `None` disables an alert.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QosThresholds {
+    /// Packets lost, in percent of those expected over [`LOSS_WINDOW`] packets.
+    pub loss_percent: Option<f64>,
+    /// Interarrival jitter in milliseconds.
+    pub jitter_ms: Option<f64>,
+}
+
+/// How speech is detected on a leg.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpeechDetection {
+    /// Tuning of the detector.
+    pub vad: VadConfig,
+    /// Stop the audio played to the leg when its party starts talking.
+    pub barge_in: bool,
+}
+
+/// Something the relay noticed in the media it forwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelayEvent {
+    /// A DTMF digit ended on a leg.
+    Digit {
+        /// Leg the digit was received on.
+        side: Side,
+        /// The digit.
+        digit: Digit,
+    },
+    /// A leg stayed silent for longer than its inactivity timeout.
+    Interrupted {
+        /// The silent leg.
+        side: Side,
+        /// Time since its last packet.
+        silent_for: Duration,
+        /// Whether the call was on hold, so RTCP alone counted.
+        held: bool,
+    },
+    /// Media came back on an interrupted leg.
+    Resumed {
+        /// The leg.
+        side: Side,
+        /// Time between the last packet before the interruption and this one.
+        silent_for: Duration,
+    },
+    /// Loss of the RTP received on a leg rose above its threshold.
+    PacketLoss {
+        /// Leg the RTP was received on.
+        side: Side,
+        /// Share of the packets expected lately that never arrived.
+        loss_percent: f64,
+        /// Packets missing over that window.
+        lost_packets: u64,
+    },
+    /// Jitter of the RTP received on a leg rose above its threshold.
+    HighJitter {
+        /// Leg the RTP was received on.
+        side: Side,
+        /// Current interarrival jitter.
+        jitter_ms: f64,
+        /// Highest jitter checked on the leg so far.
+        max_jitter_ms: f64,
+    },
+    /// The party on a leg started talking.
+    SpeechStarted {
+        /// The leg.
+        side: Side,
+        /// Whether audio played to the leg was stopped for it.
+        barge_in: bool,
+    },
+    /// The party on a leg stopped talking.
+    SpeechEnded {
+        /// The leg.
+        side: Side,
+        /// From the start of the speech to its last voiced frame.
+        duration: Duration,
+    },
+}
+
+impl RelayEvent {
+    /// Why a leg counts as interrupted.
+    pub fn interruption_reason(held: bool) -> &'static str {
+        if held {
+            "no RTP or RTCP while on hold"
+        } else {
+            "no RTP"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Latch {
+    addr: Option<SocketAddr>,
+    latched: bool,
+}
+
+impl Latch {
+    /// Latch onto `source` if nothing was received yet; otherwise require a match.
+    fn accept(&mut self, source: SocketAddr) -> bool {
+        if self.latched {
+            return self.addr == Some(source);
+        }
+        self.addr = Some(source);
+        self.latched = true;
+        true
+    }
+}
+
+/// When a party was last heard from.
+#[derive(Debug, Default)]
+struct Activity {
+    rtp: Option<Duration>,
+    /// RTP or RTCP.
+    media: Option<Duration>,
+    /// Last packet before an interruption still going on.
+    interrupted: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Rtp,
+    Rtcp,
+}
+
+/// What RTP and RTCP from one party tell us about its path.
+#[derive(Debug, Default)]
+struct LegQos {
+    reception: ReceptionStats,
+    playout: JitterBuffer,
+    /// SRs relayed towards this party: LSR value and when.
+    sr_relayed: VecDeque<(u32, Duration)>,
+    /// Last SR received from this party.
+    last_sr: Option<(u32, Duration)>,
+    rtt: Option<Duration>,
+    cname: Option<String>,
+    remote_metrics: Option<VoipMetrics>,
+    /// Conversational score, and listening score with delay left out.
+    score: Option<Score>,
+    listening: Option<Score>,
+    /// Expected and lost packet counts when loss was last checked.
+    loss_checked: (u64, i64),
+    loss_alert: Alert,
+    jitter_alert: Alert,
+    max_jitter_ms: f64,
+}
+
+#[derive(Debug, Default)]
+struct LegState {
+    rtp_port: u16,
+    rtcp_port: u16,
+    rtp: Mutex<Latch>,
+    rtcp: Mutex<Latch>,
+    qos: Mutex<LegQos>,
+    dtmf: Mutex<DigitDetector>,
+    activity: Mutex<Activity>,
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    packets_dropped: AtomicU64,
+}
+
+impl LegState {
+    fn latch(&self, stream: Stream) -> std::sync::MutexGuard<'_, Latch> {
+        let latch = match stream {
+            Stream::Rtp => &self.rtp,
+            Stream::Rtcp => &self.rtcp,
+        };
+        latch.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn qos(&self) -> std::sync::MutexGuard<'_, LegQos> {
+        self.qos.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn dtmf(&self) -> std::sync::MutexGuard<'_, DigitDetector> {
+        self.dtmf.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn activity(&self) -> std::sync::MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Note a packet from this leg's party; returns the silence it ends, if
+    /// the leg was interrupted and the packet is the kind that was missing.
+    fn heard(&self, rtp: bool, held: bool, now: Duration) -> Option<Duration> {
+        let mut activity = self.activity();
+        activity.media = Some(now);
+        if rtp {
+            activity.rtp = Some(now);
+        }
+        if !rtp && !held {
+            return None;
+        }
+        let last = activity.interrupted.take()?;
+        Some(now.saturating_sub(last))
+    }
+
+    /// The silence of this leg's party once it first exceeds `timeout`,
+    /// counting from `since` at the earliest.
+    fn went_silent(
+        &self,
+        held: bool,
+        since: Duration,
+        timeout: Duration,
+        now: Duration,
+    ) -> Option<Duration> {
+        let mut activity = self.activity();
+        if activity.interrupted.is_some() {
+            return None;
+        }
+        let last = if held { activity.media } else { activity.rtp }?;
+        if now.saturating_sub(last.max(since)) < timeout {
+            return None;
+        }
+        activity.interrupted = Some(last);
+        Some(now.saturating_sub(last))
+    }
+
+    fn drop_packet(&self) {
+        self.packets_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> LegStats {
+        let rtp = *self.latch(Stream::Rtp);
+        let qos = self.qos();
+        LegStats {
+            rtp_port: self.rtp_port,
+            rtcp_port: self.rtcp_port,
+            remote: rtp.addr,
+            latched: rtp.latched,
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            packets_dropped: self.packets_dropped.load(Ordering::Relaxed),
+            jitter_ms: qos.reception.jitter_ms(),
+            packets_lost: qos.reception.cumulative_lost(),
+            loss_percent: qos.reception.loss_percent(),
+            packets_discarded: qos.reception.discarded(),
+            playout_delay_ms: qos.playout.delay().as_secs_f64() * 1000.0,
+            rtt_ms: qos.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            cname: qos.cname.clone(),
+            // Known to the relay rather than the leg.
+            srtp: false,
+            remote_metrics: qos.remote_metrics,
+            score: qos.score,
+        }
+    }
+
+    /// Loss and jitter alerts for the stream received from this leg's party.
+    fn check_alerts(&self, side: Side, thresholds: &QosThresholds) -> Vec<RelayEvent> {
+        let mut qos = self.qos();
+        let mut alerts = Vec::new();
+        let expected = qos.reception.expected();
+        if expected == 0 {
+            return alerts;
+        }
+        let lost = qos.reception.cumulative_lost();
+        if expected < qos.loss_checked.0 {
+            // The source restarted its sequence.
+            qos.loss_checked = (0, 0);
+        }
+        let window = expected - qos.loss_checked.0;
+        if window >= LOSS_WINDOW {
+            let lost_packets = (lost - qos.loss_checked.1).max(0) as u64;
+            let loss_percent = lost_packets as f64 * 100.0 / window as f64;
+            qos.loss_checked = (expected, lost);
+            if let Some(threshold) = thresholds.loss_percent {
+                if qos.loss_alert.update(loss_percent, threshold, ALERT_REARM) {
+                    alerts.push(RelayEvent::PacketLoss {
+                        side,
+                        loss_percent,
+                        lost_packets,
+                    });
+                }
+            }
+        }
+        let jitter_ms = qos.reception.jitter_ms();
+        qos.max_jitter_ms = qos.max_jitter_ms.max(jitter_ms);
+        if let Some(threshold) = thresholds.jitter_ms {
+            if qos.jitter_alert.update(jitter_ms, threshold, ALERT_REARM) {
+                alerts.push(RelayEvent::HighJitter {
+                    side,
+                    jitter_ms,
+                    max_jitter_ms: qos.max_jitter_ms,
+                });
+            }
+        }
+        alerts
+    }
+
+    /// Score the stream received from this leg's party, once it has sent RTP.
+    fn rate(&self, codec: CodecImpairment, network_delay: Duration) {
+        let mut qos = self.qos();
+        if qos.reception.expected() == 0 {
+            return;
+        }
+        let mut conditions = Conditions {
+            one_way_delay_ms: 0.0,
+            packet_loss_percent: qos.reception.effective_loss_percent(),
+            burst_ratio: qos.reception.burst_ratio(),
+        };
+        qos.listening = Some(Score::estimate(codec, &conditions));
+        conditions.one_way_delay_ms =
+            (network_delay + qos.playout.delay()).as_secs_f64() * 1000.0 + codec.delay_ms;
+        qos.score = Some(Score::estimate(codec, &conditions));
+    }
+
+    /// Half this leg's round trip, or zero until one was measured.
+    fn one_way_delay(&self) -> Duration {
+        self.qos().rtt.map_or(Duration::ZERO, |rtt| rtt / 2)
+    }
+
+    /// Account for RTP from this leg's party and play out what fell due.
+    fn observe_rtp(&self, packet: &RtpPacket<'_>, now: Duration) -> Vec<Playout> {
+        let mut qos = self.qos();
+        qos.reception.on_packet(packet, now);
+        if qos.playout.push(packet.clone(), now) == Pushed::Late {
+            qos.reception.on_discard();
+        }
+        qos.playout.drain(now)
+    }
+
+    /// Record what a compound RTCP packet from this leg's party says.
+    fn observe_rtcp(&self, to: &Self, packets: &[RtcpPacket], now: Duration) {
+        let mut qos = self.qos();
+        for packet in packets {
+            let reports = match packet {
+                RtcpPacket::SenderReport { info, reports, .. } => {
+                    let lsr = ntp_middle(info.ntp_timestamp);
+                    qos.last_sr = Some((lsr, now));
+                    let mut peer = to.qos();
+                    if peer.sr_relayed.len() == SR_HISTORY {
+                        peer.sr_relayed.pop_front();
+                    }
+                    peer.sr_relayed.push_back((lsr, now));
+                    reports
+                }
+                RtcpPacket::ReceiverReport { reports, .. } => reports,
+                RtcpPacket::SourceDescription(chunks) => {
+                    qos.cname = chunks
+                        .iter()
+                        .flat_map(|chunk| &chunk.items)
+                        .find(|item| item.kind == SDES_CNAME)
+                        .map(|item| item.text.clone())
+                        .or(qos.cname.take());
+                    continue;
+                }
+                RtcpPacket::ExtendedReport { voip_metrics, .. } => {
+                    if let Some(metrics) = voip_metrics.last() {
+                        qos.remote_metrics = Some(*metrics);
+                    }
+                    continue;
+                }
+                RtcpPacket::Goodbye { reason, .. } => {
+                    debug!(?reason, "remote party sent RTCP BYE");
+                    continue;
+                }
+                RtcpPacket::Other { .. } => continue,
+            };
+            for block in reports.iter().filter(|block| block.last_sr != 0) {
+                let sent_at = qos
+                    .sr_relayed
+                    .iter()
+                    .find(|(lsr, _)| *lsr == block.last_sr)
+                    .map(|(_, at)| *at);
+                if let Some(rtt) = sent_at.and_then(|at| round_trip(block, at, now)) {
+                    qos.rtt = Some(rtt);
+                }
+            }
+        }
+    }
+
+    /// Our reception report for this leg's party, optionally followed by a BYE.
+    fn report(&self, ssrc: u32, cname: &str, now: Duration, bye: bool) -> Vec<RtcpPacket> {
+        let mut qos = self.qos();
+        let last_sr = qos.last_sr.map(|(lsr, at)| (lsr, now.saturating_sub(at)));
+        let block = qos.reception.report_block(last_sr);
+        let expected = qos.reception.expected().max(1);
+        let discard_rate = (qos.reception.discarded() * 256 / expected).min(255) as u8;
+        let scaled = |score: Option<Score>, value: fn(Score) -> f64| {
+            score.map_or(VoipMetrics::UNAVAILABLE, |score| value(score).round() as u8)
+        };
+        let mut packets = vec![
+            RtcpPacket::ReceiverReport {
+                ssrc,
+                reports: block.into_iter().collect(),
+            },
+            RtcpPacket::SourceDescription(vec![SdesChunk {
+                ssrc,
+                items: vec![SdesItem {
+                    kind: SDES_CNAME,
+                    text: cname.to_owned(),
+                }],
+            }]),
+        ];
+        if let Some(block) = block {
+            let rtt_ms = qos.rtt.map_or(0, |rtt| rtt.as_millis());
+            packets.push(RtcpPacket::ExtendedReport {
+                ssrc,
+                voip_metrics: vec![VoipMetrics {
+                    ssrc: block.ssrc,
+                    loss_rate: block.fraction_lost,
+                    discard_rate,
+                    round_trip_delay: u16::try_from(rtt_ms).unwrap_or(u16::MAX),
+                    r_factor: scaled(qos.score, |score| score.r_factor),
+                    ext_r_factor: VoipMetrics::UNAVAILABLE,
+                    mos_lq: scaled(qos.listening, |score| score.mos * 10.0),
+                    mos_cq: scaled(qos.score, |score| score.mos * 10.0),
+                    jb_nominal: qos.playout.delay().as_millis() as u16,
+                    jb_abs_max: qos.playout.config().max_delay.as_millis() as u16,
+                    ..VoipMetrics::default()
+                }],
+            });
+        }
+        if bye {
+            packets.push(RtcpPacket::Goodbye {
+                sources: vec![ssrc],
+                reason: Some("session ended".into()),
+            });
+        }
+        packets
+    }
+}
+
+/// Audio from a [`Playback`] on its way to one leg.
+#[derive(Debug)]
+struct Player {
+    playback: Playback,
+    format: PayloadFormat,
+    resampler: Resampler,
+    encoder: Encoder,
+    sequence: u16,
+    timestamp: u32,
+    /// Whether the last frame carried audio, so the next talkspurt gets a marker.
+    talking: bool,
+}
+
+impl Player {
+    fn new(playback: Playback, format: PayloadFormat) -> Result<Self> {
+        Ok(Self {
+            resampler: Resampler::new(playback.sample_rate(), format.codec.sample_rate())?,
+            encoder: format.codec.encoder(),
+            playback,
+            format,
+            // Random starts (RFC 3550 §5.1).
+            sequence: Uuid::new_v4().as_u128() as u16,
+            timestamp: Uuid::new_v4().as_u128() as u32,
+            talking: false,
+        })
+    }
+
+    /// The next frame for a leg now carrying `format`, or `None` in silence.
+    fn next_packet(&mut self, format: PayloadFormat, ssrc: u32) -> Option<RtpPacket<'static>> {
+        if format != self.format {
+            *self = Self::new(self.playback.clone(), format).ok()?;
+        }
+        let rate = self.playback.sample_rate();
+        let len = (u128::from(rate) * FRAME.as_millis() / 1000) as usize;
+        let Some(pcm) = self.playback.next_frame(len) else {
+            self.talking = false;
+            return None;
+        };
+        let pcm = self.resampler.process(&pcm);
+        let mut payload = Vec::with_capacity(pcm.len() * 2);
+        self.encoder.encode(&pcm, &mut payload);
+        let packet = RtpPacket::new(
+            format.payload_type,
+            self.sequence,
+            self.timestamp,
+            ssrc,
+            payload,
+        )
+        .with_marker(!self.talking);
+        self.talking = true;
+        self.sequence = self.sequence.wrapping_add(1);
+        let clock = u128::from(format.codec.clock_rate());
+        self.timestamp = self
+            .timestamp
+            .wrapping_add((clock * FRAME.as_millis() / 1000) as u32);
+        Some(packet)
+    }
+}
+
+/// Speech detection on the audio of one leg.
+#[derive(Debug)]
+struct SpeechDetector {
+    detection: SpeechDetection,
+    /// Decoder and detector for the codec last received.
+    state: Option<(AudioCodec, Decoder, Vad)>,
+    pcm: Vec<i16>,
+}
+
+impl SpeechDetector {
+    fn new(detection: SpeechDetection) -> Self {
+        Self {
+            detection,
+            state: None,
+            pcm: Vec::new(),
+        }
+    }
+
+    fn process(&mut self, codec: AudioCodec, payload: &[u8]) -> Option<VadEvent> {
+        if self.state.as_ref().is_none_or(|(last, ..)| *last != codec) {
+            let vad = Vad::new(self.detection.vad, codec.sample_rate());
+            self.state = Some((codec, codec.decoder(), vad));
+        }
+        let (_, decoder, vad) = self.state.as_mut()?;
+        self.pcm.clear();
+        decoder.decode(payload, &mut self.pcm);
+        vad.process(&self.pcm)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    legs: [LegState; 2],
+    held: AtomicBool,
+    /// When hold last started or ended; silence is counted from then at most.
+    hold_changed: Mutex<Duration>,
+    timeouts: InactivityTimeouts,
+    alerts: Mutex<QosThresholds>,
+    codec: Mutex<CodecImpairment>,
+    /// Conversion applied to RTP from each side, when the legs share no codec.
+    transcoders: [Mutex<Option<Transcoder>>; 2],
+    /// SRTP state of each leg; `None` for plain RTP.
+    srtp: [Mutex<Option<SrtpSession>>; 2],
+    /// Where received audio goes while the call is recorded.
+    recording: Mutex<Option<RecordingTap>>,
+    /// Where received audio goes while an agent listens to the call.
+    fork: Mutex<Option<ForkTap>>,
+    /// Audio played to each leg instead of what the other leg sends.
+    players: [Mutex<Option<Player>>; 2],
+    /// Speech detection on each leg, when enabled.
+    detectors: [Mutex<Option<SpeechDetector>>; 2],
+    events: broadcast::Sender<RelayEvent>,
+    /// SSRC and CNAME of the relay's own RTCP.
+    ssrc: u32,
+    cname: String,
+    /// Origin of the arrival times fed to the QoS estimators.
+    origin: Instant,
+}
+
+impl Shared {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn transcoder(&self, side: Side) -> std::sync::MutexGuard<'_, Option<Transcoder>> {
+        self.transcoders[side.index()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn srtp(&self, side: Side) -> std::sync::MutexGuard<'_, Option<SrtpSession>> {
+        self.srtp[side.index()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// What `side` sent with its SRTP protection removed, `None` on a plain leg.
+    fn unprotect(&self, side: Side, rtcp: bool, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut srtp = self.srtp(side);
+        let Some(srtp) = srtp.as_mut() else {
+            return Ok(None);
+        };
+        if rtcp {
+            srtp.unprotect_rtcp(data).map(Some)
+        } else {
+            srtp.unprotect_rtp(data).map(Some)
+        }
+    }
+
+    /// `data` protected for sending to `side`, `None` on a plain leg.
+    fn protect(&self, side: Side, rtcp: bool, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut srtp = self.srtp(side);
+        let Some(srtp) = srtp.as_mut() else {
+            return Ok(None);
+        };
+        if rtcp {
+            srtp.protect_rtcp(data).map(Some)
+        } else {
+            srtp.protect_rtp(data).map(Some)
+        }
+    }
+
+    fn player(&self, side: Side) -> std::sync::MutexGuard<'_, Option<Player>> {
+        self.players[side.index()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn detector(&self, side: Side) -> std::sync::MutexGuard<'_, Option<SpeechDetector>> {
+        self.detectors[side.index()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether audio is being played to `side`.
+    fn playing(&self, side: Side) -> bool {
+        self.player(side)
+            .as_ref()
+            .is_some_and(|player| player.playback.is_playing())
+    }
+
+    /// Hand the audio of an RTP packet from `side` to the recording, the fork
+    /// and the speech detector, if any.
+    fn tap(&self, side: Side, packet: &RtpPacket<'_>) {
+        let format = self.legs[side.index()].dtmf().format();
+        if packet.payload_type != format.payload_type {
+            return;
+        }
+        let recording = self.recording.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tap) = recording.as_ref() {
+            tap.push(side, format.codec, &packet.payload);
+        }
+        drop(recording);
+        let fork = self.fork.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tap) = fork.as_ref() {
+            tap.push(side, format.codec, &packet.payload);
+        }
+        drop(fork);
+        self.detect_speech(side, format.codec, &packet.payload);
+    }
+
+    /// Announce the party on `side` starting or stopping to talk.
+    fn detect_speech(&self, side: Side, codec: AudioCodec, payload: &[u8]) {
+        let mut detector = self.detector(side);
+        let Some(detector) = detector.as_mut() else {
+            return;
+        };
+        let barge_in = detector.detection.barge_in;
+        let event = match detector.process(codec, payload) {
+            Some(VadEvent::SpeechStarted) => {
+                // Stopping audio that is not playing would only drop what
+                // comes next.
+                let stopped = barge_in
+                    && self.player(side).as_ref().is_some_and(|player| {
+                        let playing = player.playback.is_playing();
+                        if playing {
+                            player.playback.clear();
+                        }
+                        playing
+                    });
+                debug!(?side, barge_in = stopped, "speech started");
+                RelayEvent::SpeechStarted {
+                    side,
+                    barge_in: stopped,
+                }
+            }
+            Some(VadEvent::SpeechEnded { duration }) => {
+                debug!(
+                    ?side,
+                    duration_ms = duration.as_millis() as u64,
+                    "speech ended"
+                );
+                RelayEvent::SpeechEnded { side, duration }
+            }
+            None => return,
+        };
+        let _ = self.events.send(event);
+    }
+
+    /// Announce the legs whose party has been silent for too long.
+    fn watch(&self) {
+        let held = self.held.load(Ordering::Relaxed);
+        let timeout = if held {
+            self.timeouts.held
+        } else {
+            self.timeouts.rtp
+        };
+        let Some(timeout) = timeout else {
+            return;
+        };
+        let since = *self.hold_changed.lock().unwrap_or_else(|e| e.into_inner());
+        let now = self.now();
+        for side in [Side::Caller, Side::Callee] {
+            let leg = &self.legs[side.index()];
+            if let Some(silent_for) = leg.went_silent(held, since, timeout, now) {
+                warn!(
+                    ?side,
+                    silent_ms = silent_for.as_millis() as u64,
+                    held,
+                    "media interrupted"
+                );
+                let _ = self.events.send(RelayEvent::Interrupted {
+                    side,
+                    silent_for,
+                    held,
+                });
+            }
+        }
+    }
+
+    /// Announce the legs whose loss or jitter just crossed its threshold.
+    fn check_alerts(&self) {
+        let thresholds = *self.alerts.lock().unwrap_or_else(|e| e.into_inner());
+        for side in [Side::Caller, Side::Callee] {
+            for alert in self.legs[side.index()].check_alerts(side, &thresholds) {
+                warn!(?alert, "media quality degraded");
+                let _ = self.events.send(alert);
+            }
+        }
+    }
+
+    /// `packet` in the other leg's format, or `None` to relay it untouched.
+    fn transcode(&self, side: Side, packet: &RtpPacket<'_>) -> Option<Vec<u8>> {
+        let packet = self.transcoder(side).as_mut()?.transcode(packet)?;
+        packet.to_bytes().ok()
+    }
+}
+
+/// A running relay task forwarding between the caller and callee legs.
+#[derive(Debug)]
+pub struct RelayHandle {
+    shared: Arc<Shared>,
+    stop_tx: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl RelayHandle {
+    /// Spawn the forwarding task; it ends when `stop()` is called or `shutdown` flips to true.
+    pub fn spawn(
+        session_id: &str,
+        caller: PortPair,
+        callee: PortPair,
+        timeouts: InactivityTimeouts,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        let leg = |pair: &PortPair| LegState {
+            rtp_port: pair.rtp_port(),
+            rtcp_port: pair.rtcp_port(),
+            ..LegState::default()
+        };
+        let shared = Arc::new(Shared {
+            legs: [leg(&caller), leg(&callee)],
+            held: AtomicBool::new(false),
+            hold_changed: Mutex::new(Duration::ZERO),
+            timeouts,
+            alerts: Mutex::new(QosThresholds::default()),
+            codec: Mutex::new(CodecImpairment::G711),
+            transcoders: Default::default(),
+            srtp: Default::default(),
+            recording: Mutex::new(None),
+            fork: Mutex::new(None),
+            players: Default::default(),
+            detectors: Default::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            ssrc: Uuid::new_v4().as_u128() as u32,
+            cname: format!("relay-{}", session_id),
+            origin: Instant::now(),
+        });
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(run(
+            session_id.to_owned(),
+            [caller, callee],
+            shared.clone(),
+            stop_rx,
+            shutdown,
+        ));
+        Self {
+            shared,
+            stop_tx,
+            task,
+        }
+    }
+
+    /// Remote RTP address from signalling; RTCP is assumed on the next port.
+    ///
+    /// Clears any latched address so the leg re-latches on its next packet.
+    pub fn set_remote(&self, side: Side, rtp: Option<SocketAddr>) {
+        let leg = &self.shared.legs[side.index()];
+        *leg.latch(Stream::Rtp) = Latch {
+            addr: rtp,
+            latched: false,
+        };
+        *leg.latch(Stream::Rtcp) = Latch {
+            addr: rtp.map(|addr| SocketAddr::new(addr.ip(), addr.port().wrapping_add(1))),
+            latched: false,
+        };
+    }
+
+    /// Counters and addresses of one leg.
+    pub fn stats(&self, side: Side) -> LegStats {
+        LegStats {
+            srtp: self.shared.srtp(side).is_some(),
+            ..self.shared.legs[side.index()].stats()
+        }
+    }
+
+    /// Suspend or restore forwarding in both directions; latching continues while held.
+    pub fn set_held(&self, held: bool) {
+        if self.shared.held.swap(held, Ordering::Relaxed) != held {
+            *self
+                .shared
+                .hold_changed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = self.shared.now();
+        }
+    }
+
+    /// Whether forwarding is suspended.
+    pub fn is_held(&self) -> bool {
+        self.shared.held.load(Ordering::Relaxed)
+    }
+
+    /// Codec whose impairment the E-model applies from the next report on.
+    pub fn set_codec(&self, codec: &str) {
+        *self.shared.codec.lock().unwrap_or_else(|e| e.into_inner()) =
+            CodecImpairment::for_codec(codec);
+    }
+
+    /// Levels of loss and jitter announced from now on; none by default.
+    pub fn set_alerts(&self, thresholds: QosThresholds) {
+        *self.shared.alerts.lock().unwrap_or_else(|e| e.into_inner()) = thresholds;
+    }
+
+    /// Transcode between the `[caller, callee]` formats, or relay RTP untouched with `None`.
+    pub fn set_transcoding(&self, formats: Option<[PayloadFormat; 2]>) -> Result<()> {
+        let (to_callee, to_caller) = match formats {
+            Some([caller, callee]) => (
+                Some(Transcoder::new(caller, callee)?),
+                Some(Transcoder::new(callee, caller)?),
+            ),
+            None => (None, None),
+        };
+        *self.shared.transcoder(Side::Caller) = to_callee;
+        *self.shared.transcoder(Side::Callee) = to_caller;
+        Ok(())
+    }
+
+    /// The `[caller, callee]` formats being transcoded between.
+    pub fn transcoding(&self) -> Option<[PayloadFormat; 2]> {
+        self.shared
+            .transcoder(Side::Caller)
+            .as_ref()
+            .map(|transcoder| [transcoder.from(), transcoder.to()])
+    }
+
+    /// How a leg carries audio and telephone-events, for digit detection.
+    pub fn set_format(&self, side: Side, format: PayloadFormat) {
+        self.shared.legs[side.index()].dtmf().set_format(format);
+    }
+
+    /// Send the audio of both legs to a recording, or stop with `None`.
+    pub fn set_recording(&self, tap: Option<RecordingTap>) {
+        *self
+            .shared
+            .recording
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = tap;
+    }
+
+    /// Send the audio of both legs to an agent fork, or stop with `None`.
+    pub fn set_fork(&self, tap: Option<ForkTap>) {
+        *self.shared.fork.lock().unwrap_or_else(|e| e.into_inner()) = tap;
+    }
+
+    /// Play what is queued on `playback` to a leg in its current format, or
+    /// stop playing with `None`.
+    pub fn play(&self, side: Side, playback: Option<Playback>) -> Result<()> {
+        let format = self.shared.legs[side.index()].dtmf().format();
+        *self.shared.player(side) = playback
+            .map(|playback| Player::new(playback, format))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Detect speech on a leg, or stop with `None`.
+    pub fn set_speech_detection(&self, side: Side, detection: Option<SpeechDetection>) {
+        *self.shared.detector(side) = detection.map(SpeechDetector::new);
+    }
+
+    /// How speech is detected on a leg, if it is.
+    pub fn speech_detection(&self, side: Side) -> Option<SpeechDetection> {
+        self.shared
+            .detector(side)
+            .as_ref()
+            .map(|detector| detector.detection)
+    }
+
+    /// Protect a leg with SRTP, or carry plain RTP with `None`.
+    pub fn set_srtp(&self, side: Side, policy: Option<&SrtpPolicy>) {
+        *self.shared.srtp(side) = policy.map(SrtpSession::new);
+    }
+
+    /// Receive events from now on; the channel closes once the relay is dropped.
+    pub fn subscribe(&self) -> broadcast::Receiver<RelayEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Whether the forwarding task has exited.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stop forwarding and release both port pairs; statistics stay readable.
+    pub async fn stop(&mut self) {
+        let _ = self.stop_tx.send(true);
+        if !self.task.is_finished() {
+            let _ = (&mut self.task).await;
+        }
+    }
+}
+
+#[instrument(name = "media.relay", skip_all, fields(session_id = %session_id))]
+async fn run(
+    session_id: String,
+    pairs: [PortPair; 2],
+    shared: Arc<Shared>,
+    mut stop_rx: watch::Receiver<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!(
+        caller_port = shared.legs[0].rtp_port,
+        callee_port = shared.legs[1].rtp_port,
+        "relay started"
+    );
+    let mut bufs = [[0u8; MAX_DATAGRAM]; 4];
+    let [caller_rtp, caller_rtcp, callee_rtp, callee_rtcp] = &mut bufs;
+    let mut reports = time::interval_at(Instant::now() + RTCP_INTERVAL, RTCP_INTERVAL);
+    let mut watchdog = time::interval(shared.timeouts.watch_interval());
+    let mut playout = time::interval(FRAME);
+    playout.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let (side, stream, received, buf) = tokio::select! {
+            r = pairs[0].rtp.recv_from(caller_rtp) => (Side::Caller, Stream::Rtp, r, &caller_rtp[..]),
+            r = pairs[0].rtcp.recv_from(caller_rtcp) => (Side::Caller, Stream::Rtcp, r, &caller_rtcp[..]),
+            r = pairs[1].rtp.recv_from(callee_rtp) => (Side::Callee, Stream::Rtp, r, &callee_rtp[..]),
+            r = pairs[1].rtcp.recv_from(callee_rtcp) => (Side::Callee, Stream::Rtcp, r, &callee_rtcp[..]),
+            update = stop_rx.changed() => {
+                if update.is_err() || *stop_rx.borrow() {
+                    break;
+                }
+                continue;
+            }
+            update = shutdown.changed() => {
+                if update.is_err() || *shutdown.borrow() {
+                    break;
+                }
+                continue;
+            }
+            _ = reports.tick() => {
+                send_reports(&pairs, &shared, false).await;
+                continue;
+            }
+            _ = watchdog.tick() => {
+                shared.watch();
+                shared.check_alerts();
+                continue;
+            }
+            _ = playout.tick() => {
+                play(&pairs, &shared).await;
+                continue;
+            }
+        };
+        match received {
+            Ok((len, source)) => {
+                forward(&pairs, &shared, side, stream, &buf[..len], source).await;
+            }
+            // ICMP port unreachable from a previous send surfaces here on some platforms.
+            Err(err) => trace!(?side, error = %err, "media receive failed"),
+        }
+    }
+    send_reports(&pairs, &shared, true).await;
+    info!("relay stopped");
+}
+
+/// Send each leg being played to its next frame of audio.
+async fn play(pairs: &[PortPair; 2], shared: &Shared) {
+    for side in [Side::Caller, Side::Callee] {
+        let leg = &shared.legs[side.index()];
+        let format = leg.dtmf().format();
+        let packet = shared
+            .player(side)
+            .as_mut()
+            .and_then(|player| player.next_packet(format, shared.ssrc));
+        let (Some(packet), Some(dest)) = (packet, leg.latch(Stream::Rtp).addr) else {
+            continue;
+        };
+        let wire = match packet.to_bytes() {
+            Ok(wire) => wire,
+            Err(err) => {
+                debug!(error = %err, "played RTP encoding failed");
+                continue;
+            }
+        };
+        let wire = match shared.protect(side, false, &wire) {
+            Ok(protected) => protected.unwrap_or(wire),
+            Err(err) => {
+                debug!(?side, error = %err, "SRTP protection of played audio failed");
+                continue;
+            }
+        };
+        match pairs[side.index()].rtp.send_to(&wire, dest).await {
+            Ok(sent) => {
+                leg.packets_sent.fetch_add(1, Ordering::Relaxed);
+                leg.bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+            }
+            Err(err) => debug!(?side, %dest, error = %err, "played audio send failed"),
+        }
+    }
+}
+
+/// Rescore both streams, then send each leg with a known RTCP address our
+/// report about the stream it sends.
+async fn send_reports(pairs: &[PortPair; 2], shared: &Shared, bye: bool) {
+    let now = shared.now();
+    let codec = *shared.codec.lock().unwrap_or_else(|e| e.into_inner());
+    // Media crosses both legs whichever way it flows.
+    let network_delay = shared.legs[0].one_way_delay() + shared.legs[1].one_way_delay();
+    for leg in &shared.legs {
+        leg.rate(codec, network_delay);
+    }
+    for ((side, pair), leg) in [Side::Caller, Side::Callee]
+        .into_iter()
+        .zip(pairs)
+        .zip(&shared.legs)
+    {
+        let Some(dest) = leg.latch(Stream::Rtcp).addr else {
+            continue;
+        };
+        let packets = leg.report(shared.ssrc, &shared.cname, now, bye);
+        let wire = match RtcpPacket::compound_to_bytes(&packets) {
+            Ok(wire) => wire,
+            Err(err) => {
+                debug!(error = %err, "RTCP report encoding failed");
+                continue;
+            }
+        };
+        let wire = match shared.protect(side, true, &wire) {
+            Ok(protected) => protected.unwrap_or(wire),
+            Err(err) => {
+                debug!(?side, error = %err, "SRTCP report protection failed");
+                continue;
+            }
+        };
+        if let Err(err) = pair.rtcp.send_to(&wire, dest).await {
+            debug!(%dest, error = %err, "RTCP report send failed");
+        }
+    }
+}
+
+/// A datagram that passed validation.
+enum Inspected<'a> {
+    Rtp(RtpPacket<'a>),
+    Rtcp(Vec<RtcpPacket>),
+}
+
+async fn forward(
+    pairs: &[PortPair; 2],
+    shared: &Shared,
+    side: Side,
+    stream: Stream,
+    data: &[u8],
+    source: SocketAddr,
+) {
+    let from = &shared.legs[side.index()];
+    let to_side = side.other();
+    let to = &shared.legs[to_side.index()];
+    let received = data.len();
+    // RTCP may share the RTP port (RFC 5761); it is still relayed on the RTP path.
+    let rtcp = stream == Stream::Rtcp || is_rtcp(data);
+    let decrypted = match shared.unprotect(side, rtcp, data) {
+        Ok(decrypted) => decrypted,
+        Err(err) => {
+            trace!(?side, ?stream, %source, error = %err, "dropping unauthenticated media packet");
+            from.drop_packet();
+            return;
+        }
+    };
+    let data = decrypted.as_deref().unwrap_or(data);
+    let inspected = if rtcp {
+        RtcpPacket::parse_compound(data).ok().map(Inspected::Rtcp)
+    } else {
+        RtpPacket::parse(data).ok().map(Inspected::Rtp)
+    };
+    let Some(inspected) = inspected.filter(|_| from.latch(stream).accept(source)) else {
+        trace!(?side, ?stream, %source, "dropping unexpected media packet");
+        from.drop_packet();
+        return;
+    };
+    from.packets_received.fetch_add(1, Ordering::Relaxed);
+    from.bytes_received
+        .fetch_add(received as u64, Ordering::Relaxed);
+    let held = shared.held.load(Ordering::Relaxed);
+    let is_rtp = matches!(inspected, Inspected::Rtp(_));
+    if let Some(silent_for) = from.heard(is_rtp, held, shared.now()) {
+        info!(
+            ?side,
+            silent_ms = silent_for.as_millis() as u64,
+            "media resumed"
+        );
+        let _ = shared.events.send(RelayEvent::Resumed { side, silent_for });
+    }
+    match &inspected {
+        Inspected::Rtp(packet) => {
+            // Nothing terminates media on the relay path yet; played frames are dropped.
+            from.observe_rtp(packet, shared.now());
+            if let Some(digit) = from.dtmf().on_packet(packet) {
+                debug!(?side, digit = %digit.digit, in_band = digit.in_band, "DTMF digit");
+                // Nobody listening is fine.
+                let _ = shared.events.send(RelayEvent::Digit { side, digit });
+            }
+            shared.tap(side, packet);
+        }
+        Inspected::Rtcp(packets) => from.observe_rtcp(to, packets, shared.now()),
+    }
+
+    if held || (!rtcp && shared.playing(to_side)) {
+        from.drop_packet();
+        return;
+    }
+
+    let Some(dest) = to.latch(stream).addr else {
+        from.drop_packet();
+        return;
+    };
+    let transcoded = match &inspected {
+        Inspected::Rtp(packet) if stream == Stream::Rtp => shared.transcode(side, packet),
+        _ => None,
+    };
+    let data = transcoded.as_deref().unwrap_or(data);
+    let protected = match shared.protect(to_side, rtcp, data) {
+        Ok(protected) => protected,
+        Err(err) => {
+            debug!(?to_side, error = %err, "SRTP protection failed");
+            to.drop_packet();
+            return;
+        }
+    };
+    let data = protected.as_deref().unwrap_or(data);
+    let socket: &UdpSocket = match stream {
+        Stream::Rtp => &pairs[to_side.index()].rtp,
+        Stream::Rtcp => &pairs[to_side.index()].rtcp,
+    };
+    match socket.send_to(data, dest).await {
+        Ok(sent) => {
+            to.packets_sent.fetch_add(1, Ordering::Relaxed);
+            to.bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+        }
+        Err(err) => {
+            debug!(?to_side, %dest, error = %err, "media send failed");
+            to.drop_packet();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codec::{g711, AudioCodec},
+        dtmf,
+        ports::{free_range, PortAllocator},
+        rtcp::{ReportBlock, SenderInfo},
+        srtp::{CryptoSuite, MasterKey},
+    };
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+    use tokio::time;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    async fn relay() -> (RelayHandle, watch::Sender<bool>) {
+        watched_relay(InactivityTimeouts::default()).await
+    }
+
+    async fn watched_relay(timeouts: InactivityTimeouts) -> (RelayHandle, watch::Sender<bool>) {
+        let (min, max) = free_range(4);
+        let ports = PortAllocator::new(LOCALHOST, min, max).expect("range");
+        let caller = ports.allocate().await.expect("caller ports");
+        let callee = ports.allocate().await.expect("callee ports");
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        (
+            RelayHandle::spawn("test", caller, callee, timeouts, shutdown_rx),
+            shutdown_tx,
+        )
+    }
+
+    async fn recv(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buf = [0u8; MAX_DATAGRAM];
+        let (len, from) = time::timeout(Duration::from_secs(1), socket.recv_from(&mut buf))
+            .await
+            .expect("packet relayed")
+            .expect("recv");
+        (buf[..len].to_vec(), from)
+    }
+
+    async fn next(events: &mut broadcast::Receiver<RelayEvent>) -> RelayEvent {
+        time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("event")
+            .expect("open")
+    }
+
+    #[tokio::test]
+    async fn latches_both_legs_and_forwards_symmetrically() {
+        let (mut relay, _shutdown) = relay().await;
+        let caller_addr = |port| SocketAddr::new(LOCALHOST, port);
+        let caller_port = relay.stats(Side::Caller).rtp_port;
+        let callee_port = relay.stats(Side::Callee).rtp_port;
+
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let bob = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob");
+        let rogue = UdpSocket::bind((LOCALHOST, 0)).await.expect("rogue");
+        let packet = RtpPacket::new(0, 1, 160, 7, &[0xffu8; 160][..])
+            .to_bytes()
+            .expect("encode");
+
+        // Bob latches first; Alice's packet then has somewhere to go.
+        bob.send_to(&packet, caller_addr(callee_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        alice
+            .send_to(&packet, caller_addr(caller_port))
+            .await
+            .expect("send");
+        let (data, from) = recv(&bob).await;
+        assert_eq!(data, packet);
+        assert_eq!(from.port(), callee_port);
+
+        bob.send_to(&packet, caller_addr(callee_port))
+            .await
+            .expect("send");
+        let (_, from) = recv(&alice).await;
+        assert_eq!(from.port(), caller_port);
+
+        rogue
+            .send_to(&packet, caller_addr(caller_port))
+            .await
+            .expect("send");
+        alice
+            .send_to(b"not rtp", caller_addr(caller_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+
+        let caller = relay.stats(Side::Caller);
+        assert!(caller.latched);
+        assert_eq!(caller.remote, Some(alice.local_addr().expect("addr")));
+        assert_eq!(caller.packets_received, 1);
+        assert_eq!(caller.packets_sent, 1);
+        assert_eq!(caller.packets_dropped, 2);
+        let callee = relay.stats(Side::Callee);
+        assert_eq!(callee.packets_received, 2);
+        assert_eq!(callee.packets_dropped, 1, "first packet had no destination");
+        relay.stop().await;
+    }
+
+    #[tokio::test]
+    async fn forwards_to_signalled_address_unless_held_and_stops_on_shutdown() {
+        let (relay, shutdown) = relay().await;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let bob = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob");
+        relay.set_remote(Side::Callee, Some(bob.local_addr().expect("addr")));
+
+        let caller_rtcp = relay.stats(Side::Caller).rtcp_port;
+        let report = [0x80, 201, 0, 1, 0, 0, 0, 7];
+        alice
+            .send_to(&report, (LOCALHOST, caller_rtcp))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        let callee = relay.stats(Side::Callee);
+        assert!(!callee.latched);
+        assert_eq!(callee.packets_sent, 1, "RTCP went to the port after RTP");
+
+        let packet = RtpPacket::new(8, 1, 0, 1, &[0u8; 4][..])
+            .to_bytes()
+            .expect("encode");
+        let caller_rtp = (LOCALHOST, relay.stats(Side::Caller).rtp_port);
+        relay.set_held(true);
+        alice.send_to(&packet, caller_rtp).await.expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(relay.stats(Side::Caller).packets_dropped, 1, "held");
+        relay.set_held(false);
+        alice.send_to(&packet, caller_rtp).await.expect("send");
+        assert_eq!(recv(&bob).await.0, packet);
+
+        shutdown.send(true).expect("shutdown");
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(relay.is_finished());
+    }
+
+    #[tokio::test]
+    async fn measures_loss_and_rtt_and_says_bye_on_stop() {
+        let (mut relay, _shutdown) = relay().await;
+        let caller = relay.stats(Side::Caller);
+        let callee = relay.stats(Side::Callee);
+        let alice_rtp = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice rtp");
+        let alice_rtcp = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice rtcp");
+        let bob_rtcp = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob rtcp");
+        let rtcp = |packets: &[RtcpPacket]| RtcpPacket::compound_to_bytes(packets).expect("encode");
+
+        for sequence in [1, 2, 4] {
+            let packet =
+                RtpPacket::new(0, sequence, u32::from(sequence) * 160, 0xa11ce, Vec::new())
+                    .to_bytes()
+                    .expect("encode");
+            alice_rtp
+                .send_to(&packet, (LOCALHOST, caller.rtp_port))
+                .await
+                .expect("send");
+        }
+        let hello = rtcp(&[
+            RtcpPacket::ReceiverReport {
+                ssrc: 0xa11ce,
+                reports: Vec::new(),
+            },
+            RtcpPacket::SourceDescription(vec![SdesChunk {
+                ssrc: 0xa11ce,
+                items: vec![SdesItem {
+                    kind: SDES_CNAME,
+                    text: "alice@example.com".into(),
+                }],
+            }]),
+        ]);
+        alice_rtcp
+            .send_to(&hello, (LOCALHOST, caller.rtcp_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+
+        // Bob's SR reaches Alice through the relay; her RR referencing it yields the RTT.
+        let ntp = 0xe8f0_1234_5678_0000;
+        let sr = rtcp(&[RtcpPacket::SenderReport {
+            ssrc: 0xb0b,
+            info: SenderInfo {
+                ntp_timestamp: ntp,
+                ..SenderInfo::default()
+            },
+            reports: Vec::new(),
+        }]);
+        bob_rtcp
+            .send_to(&sr, (LOCALHOST, callee.rtcp_port))
+            .await
+            .expect("send");
+        assert_eq!(recv(&alice_rtcp).await.0, sr);
+        let rr = rtcp(&[RtcpPacket::ReceiverReport {
+            ssrc: 0xa11ce,
+            reports: vec![ReportBlock {
+                ssrc: 0xb0b,
+                last_sr: ntp_middle(ntp),
+                ..ReportBlock::default()
+            }],
+        }]);
+        alice_rtcp
+            .send_to(&rr, (LOCALHOST, caller.rtcp_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+
+        let caller = relay.stats(Side::Caller);
+        assert_eq!(caller.packets_lost, 1);
+        assert!((caller.loss_percent - 25.0).abs() < f64::EPSILON);
+        assert_eq!(caller.cname.as_deref(), Some("alice@example.com"));
+        let rtt = caller.rtt_ms.expect("rtt");
+        assert!((0.0..1000.0).contains(&rtt), "{}", rtt);
+
+        relay.stop().await;
+        let (bye, _) = recv(&alice_rtcp).await;
+        let packets = RtcpPacket::parse_compound(&bye).expect("parse");
+        let RtcpPacket::ReceiverReport { reports, .. } = &packets[0] else {
+            panic!("compound must start with a report: {:?}", packets);
+        };
+        assert_eq!(reports[0].ssrc, 0xa11ce);
+        assert_eq!(reports[0].cumulative_lost, 1);
+        assert!(matches!(packets.last(), Some(RtcpPacket::Goodbye { .. })));
+
+        // The final report carries the E-model score of what Alice sent.
+        let score = relay.stats(Side::Caller).score.expect("score");
+        assert!(score.mos < 3.0, "{:?}", score);
+        let xr = packets.iter().find_map(|packet| match packet {
+            RtcpPacket::ExtendedReport { voip_metrics, .. } => voip_metrics.first().copied(),
+            _ => None,
+        });
+        assert_eq!(xr.map(|m| m.r_factor), Some(score.r_factor.round() as u8));
+        assert_eq!(relay.stats(Side::Callee).score, None);
+    }
+
+    #[tokio::test]
+    async fn transcodes_between_legs_without_a_common_codec() {
+        let (relay, _shutdown) = relay().await;
+        let format = |payload_type, codec| PayloadFormat {
+            payload_type,
+            codec,
+            events: None,
+        };
+        let formats = [format(0, AudioCodec::Pcmu), format(8, AudioCodec::Pcma)];
+        relay.set_transcoding(Some(formats)).expect("transcoding");
+        assert_eq!(relay.transcoding(), Some(formats));
+        let caller_port = relay.stats(Side::Caller).rtp_port;
+        let callee_port = relay.stats(Side::Callee).rtp_port;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let bob = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob");
+        let silence = |payload_type, byte| {
+            RtpPacket::new(payload_type, 1, 160, 7, vec![byte; 160])
+                .to_bytes()
+                .expect("encode")
+        };
+
+        bob.send_to(&silence(8, 0xd5), (LOCALHOST, callee_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        alice
+            .send_to(&silence(0, 0xff), (LOCALHOST, caller_port))
+            .await
+            .expect("send");
+        assert_eq!(recv(&bob).await.0, silence(8, 0xd5));
+        bob.send_to(&silence(8, 0xd5), (LOCALHOST, callee_port))
+            .await
+            .expect("send");
+        // A-law silence sits a step above zero, which μ-law can represent.
+        let quiet = g711::ulaw_encode(g711::alaw_decode(0xd5));
+        assert_eq!(recv(&alice).await.0, silence(0, quiet));
+
+        relay.set_transcoding(None).expect("passthrough");
+        assert_eq!(relay.transcoding(), None);
+    }
+
+    #[tokio::test]
+    async fn announces_dtmf_digits_from_either_leg() {
+        let (relay, _shutdown) = relay().await;
+        let mut events = relay.subscribe();
+        let caller_port = relay.stats(Side::Caller).rtp_port;
+        let callee_port = relay.stats(Side::Callee).rtp_port;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let bob = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob");
+
+        let packets = dtmf::generate(
+            '7',
+            Duration::from_millis(80),
+            8000,
+            Duration::from_millis(20),
+        )
+        .expect("events");
+        for (seq, packet) in packets.iter().enumerate() {
+            let rtp = RtpPacket::new(101, seq as u16, 4000, 1, packet.event.to_bytes().to_vec())
+                .with_marker(packet.marker);
+            alice
+                .send_to(&rtp.to_bytes().expect("encode"), (LOCALHOST, caller_port))
+                .await
+                .expect("send");
+        }
+
+        // Bob's phone has no telephone-event and plays the tones instead.
+        relay.set_format(
+            Side::Callee,
+            PayloadFormat {
+                payload_type: 8,
+                codec: AudioCodec::Pcma,
+                events: None,
+            },
+        );
+        let nine = |n: usize| {
+            let t = n as f64 / 8000.0;
+            let x = [852.0, 1477.0]
+                .iter()
+                .map(|f: &f64| 4000.0 * (2.0 * std::f64::consts::PI * f * t).sin())
+                .sum::<f64>();
+            g711::alaw_encode(if n < 960 { x as i16 } else { 0 })
+        };
+        for seq in 0..10u16 {
+            let start = usize::from(seq) * 160;
+            let payload: Vec<u8> = (start..start + 160).map(nine).collect();
+            let rtp = RtpPacket::new(8, seq, u32::from(seq) * 160, 2, payload);
+            bob.send_to(&rtp.to_bytes().expect("encode"), (LOCALHOST, callee_port))
+                .await
+                .expect("send");
+        }
+
+        let RelayEvent::Digit { side, digit } =
+            time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("event")
+                .expect("open")
+        else {
+            panic!("expected a digit");
+        };
+        assert_eq!(
+            (side, digit.digit, digit.in_band),
+            (Side::Caller, '7', false)
+        );
+        assert_eq!(digit.duration, Duration::from_millis(80));
+        let RelayEvent::Digit { side, digit } =
+            time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("event")
+                .expect("open")
+        else {
+            panic!("expected a digit");
+        };
+        assert_eq!(
+            (side, digit.digit, digit.in_band),
+            (Side::Callee, '9', true)
+        );
+    }
+
+    #[tokio::test]
+    async fn bridges_an_srtp_leg_to_a_plain_one() {
+        let (relay, _shutdown) = relay().await;
+        let (phone_key, relay_key) = (
+            MasterKey::generate().expect("key"),
+            MasterKey::generate().expect("key"),
+        );
+        let policy = |local: &MasterKey, remote: &MasterKey| SrtpPolicy {
+            suite: CryptoSuite::AES_CM_128_HMAC_SHA1_80,
+            local: local.clone(),
+            remote: remote.clone(),
+        };
+        relay.set_srtp(Side::Caller, Some(&policy(&relay_key, &phone_key)));
+        assert!(relay.stats(Side::Caller).srtp && !relay.stats(Side::Callee).srtp);
+        let mut phone = SrtpSession::new(&policy(&phone_key, &relay_key));
+        let caller_port = relay.stats(Side::Caller).rtp_port;
+        let callee_port = relay.stats(Side::Callee).rtp_port;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let bob = UdpSocket::bind((LOCALHOST, 0)).await.expect("bob");
+        let packet = |seq| {
+            RtpPacket::new(0, seq, u32::from(seq) * 160, 7, vec![0x7f; 160])
+                .to_bytes()
+                .expect("encode")
+        };
+
+        bob.send_to(&packet(1), (LOCALHOST, callee_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        let secure = phone.protect_rtp(&packet(1)).expect("protect");
+        alice
+            .send_to(&secure, (LOCALHOST, caller_port))
+            .await
+            .expect("send");
+        assert_eq!(recv(&bob).await.0, packet(1), "decrypted for the plain leg");
+
+        bob.send_to(&packet(2), (LOCALHOST, callee_port))
+            .await
+            .expect("send");
+        let (received, _) = recv(&alice).await;
+        assert_ne!(received, packet(2));
+        assert_eq!(
+            phone.unprotect_rtp(&received).expect("unprotect"),
+            packet(2)
+        );
+
+        // Replays and packets under another key are dropped, not relayed.
+        let dropped = relay.stats(Side::Caller).packets_dropped;
+        alice
+            .send_to(&secure, (LOCALHOST, caller_port))
+            .await
+            .expect("send");
+        alice
+            .send_to(&packet(3), (LOCALHOST, caller_port))
+            .await
+            .expect("send");
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(relay.stats(Side::Caller).packets_dropped, dropped + 2);
+    }
+
+    #[tokio::test]
+    async fn announces_silent_legs_with_a_longer_timeout_on_hold() {
+        let timeouts = InactivityTimeouts {
+            rtp: Some(Duration::from_millis(150)),
+            held: Some(Duration::from_millis(400)),
+        };
+        let (relay, _shutdown) = watched_relay(timeouts).await;
+        let mut events = relay.subscribe();
+        let caller = relay.stats(Side::Caller);
+        let alice_rtp = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice rtp");
+        let alice_rtcp = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice rtcp");
+        let send_rtp = |sequence: u16| {
+            let packet = RtpPacket::new(0, sequence, u32::from(sequence) * 160, 5, vec![0; 160])
+                .to_bytes()
+                .expect("encode");
+            let socket = &alice_rtp;
+            async move {
+                socket
+                    .send_to(&packet, (LOCALHOST, caller.rtp_port))
+                    .await
+                    .expect("send");
+            }
+        };
+        for sequence in 0..3 {
+            send_rtp(sequence).await;
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        // The callee never sent anything and is not watched.
+        let RelayEvent::Interrupted {
+            side,
+            silent_for,
+            held,
+        } = next(&mut events).await
+        else {
+            panic!("expected an interruption");
+        };
+        assert_eq!((side, held), (Side::Caller, false));
+        assert!(silent_for >= Duration::from_millis(150));
+        send_rtp(3).await;
+        let RelayEvent::Resumed { side, silent_for } = next(&mut events).await else {
+            panic!("expected a resumption");
+        };
+        assert_eq!(side, Side::Caller);
+        assert!(silent_for >= Duration::from_millis(150));
+
+        // On hold, RTCP alone keeps the leg alive.
+        relay.set_held(true);
+        let rr = RtcpPacket::compound_to_bytes(&[RtcpPacket::ReceiverReport {
+            ssrc: 5,
+            reports: Vec::new(),
+        }])
+        .expect("encode");
+        for _ in 0..6 {
+            alice_rtcp
+                .send_to(&rr, (LOCALHOST, caller.rtcp_port))
+                .await
+                .expect("send");
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        let started = time::Instant::now();
+        let RelayEvent::Interrupted { side, held, .. } = next(&mut events).await else {
+            panic!("expected an interruption");
+        };
+        assert_eq!((side, held), (Side::Caller, true));
+        assert!(started.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn announces_loss_and_jitter_once_per_excursion() {
+        let timeouts = InactivityTimeouts {
+            rtp: Some(Duration::from_millis(400)),
+            held: None,
+        };
+        let (relay, _shutdown) = watched_relay(timeouts).await;
+        relay.set_alerts(QosThresholds {
+            loss_percent: Some(10.0),
+            jitter_ms: Some(20.0),
+        });
+        let mut events = relay.subscribe();
+        let port = relay.stats(Side::Caller).rtp_port;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let mut sequence = 0u16;
+        // Back to back, so only the timestamp step makes jitter.
+        let mut send = |count: u16, lossy: bool, step: u32| {
+            let packets: Vec<Vec<u8>> = (0..count)
+                .map(|_| {
+                    sequence += 1;
+                    RtpPacket::new(0, sequence, u32::from(sequence) * step, 7, vec![0; 160])
+                })
+                .filter(|packet| !lossy || packet.sequence % 5 != 0)
+                .map(|packet| packet.to_bytes().expect("encode"))
+                .collect();
+            let alice = &alice;
+            async move {
+                for packet in packets {
+                    alice
+                        .send_to(&packet, (LOCALHOST, port))
+                        .await
+                        .expect("send");
+                }
+                time::sleep(Duration::from_millis(250)).await;
+            }
+        };
+
+        send(60, true, 0).await;
+        let Ok(RelayEvent::PacketLoss {
+            side,
+            loss_percent,
+            lost_packets,
+        }) = events.try_recv()
+        else {
+            panic!("expected packet loss");
+        };
+        assert_eq!(side, Side::Caller);
+        assert!(loss_percent > 10.0 && lost_packets >= 10, "{loss_percent}");
+        send(60, true, 0).await;
+        assert!(
+            events.try_recv().is_err(),
+            "loss still above the rearm level"
+        );
+
+        send(60, false, 1600).await;
+        let Ok(RelayEvent::HighJitter {
+            side,
+            jitter_ms,
+            max_jitter_ms,
+        }) = events.try_recv()
+        else {
+            panic!("expected high jitter");
+        };
+        assert_eq!(side, Side::Caller);
+        assert!(
+            jitter_ms > 20.0 && max_jitter_ms >= jitter_ms,
+            "{jitter_ms}"
+        );
+        assert!(events.try_recv().is_err());
+
+        // Loss recovered over the last window, so it is announced again.
+        send(60, true, 1600).await;
+        assert!(matches!(
+            events.try_recv(),
+            Ok(RelayEvent::PacketLoss {
+                side: Side::Caller,
+                ..
+            })
+        ));
+        assert!(events.try_recv().is_err(), "jitter never recovered");
+    }
+
+    #[tokio::test]
+    async fn speech_on_a_leg_stops_what_plays_to_it() {
+        let (relay, _shutdown) = relay().await;
+        let detection = SpeechDetection {
+            vad: VadConfig {
+                hangover: Duration::from_millis(200),
+                ..VadConfig::default()
+            },
+            barge_in: true,
+        };
+        relay.set_speech_detection(Side::Caller, Some(detection));
+        assert_eq!(relay.speech_detection(Side::Caller), Some(detection));
+        assert_eq!(relay.speech_detection(Side::Callee), None);
+        let (playback, _marks) = Playback::new(8000).expect("playback");
+        playback.push(&[1000; 40_000]);
+        relay
+            .play(Side::Caller, Some(playback.clone()))
+            .expect("play");
+        let mut events = relay.subscribe();
+        let port = relay.stats(Side::Caller).rtp_port;
+        let alice = UdpSocket::bind((LOCALHOST, 0)).await.expect("alice");
+        let tone = |n: usize| {
+            let t = n as f64 / 8000.0;
+            g711::ulaw_encode((3000.0 * (2.0 * std::f64::consts::PI * 300.0 * t).sin()) as i16)
+        };
+        // 200 ms of talking, then 300 ms of silence.
+        for seq in 0..25u16 {
+            let start = usize::from(seq) * 160;
+            let payload: Vec<u8> = if seq < 10 {
+                (start..start + 160).map(tone).collect()
+            } else {
+                vec![0xff; 160]
+            };
+            let rtp = RtpPacket::new(0, seq, u32::from(seq) * 160, 7, payload);
+            alice
+                .send_to(&rtp.to_bytes().expect("encode"), (LOCALHOST, port))
+                .await
+                .expect("send");
+        }
+
+        assert_eq!(
+            next(&mut events).await,
+            RelayEvent::SpeechStarted {
+                side: Side::Caller,
+                barge_in: true
+            }
+        );
+        assert!(!playback.is_playing(), "the caller barged in");
+        assert_eq!(
+            next(&mut events).await,
+            RelayEvent::SpeechEnded {
+                side: Side::Caller,
+                duration: Duration::from_millis(200)
+            }
+        );
+    }
+}
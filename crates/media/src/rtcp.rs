@@ -0,0 +1,658 @@
+//! RTCP compound packets: SR, RR, SDES, BYE (RFC 3550 §6.4–6.6) and
+//! XR VoIP metrics (RFC 3611 §4.7).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use voip_common::VoipError;
+
+use crate::rtp::RTP_VERSION;
+
+/// Sender report packet type.
+pub const PT_SR: u8 = 200;
+/// Receiver report packet type.
+pub const PT_RR: u8 = 201;
+/// Source description packet type.
+pub const PT_SDES: u8 = 202;
+/// Goodbye packet type.
+pub const PT_BYE: u8 = 203;
+/// Extended report packet type.
+pub const PT_XR: u8 = 207;
+
+/// SDES CNAME item type.
+pub const SDES_CNAME: u8 = 1;
+
+/// XR block type of VoIP metrics.
+const XR_VOIP_METRICS: u8 = 7;
+
+/// Seconds between the NTP (1900) and Unix (1970) epochs.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Errors raised while decoding RTCP.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RtcpError {
+    /// A packet or block ends before its declared length.
+    #[error("truncated RTCP packet")]
+    Truncated,
+
+    /// Version field other than 2.
+    #[error("unsupported RTCP version {0}")]
+    Version(u8),
+
+    /// A field does not fit in its wire encoding.
+    #[error("RTCP field out of range: {0}")]
+    OutOfRange(&'static str),
+}
+
+impl From<RtcpError> for VoipError {
+    fn from(err: RtcpError) -> Self {
+        Self::Media(err.to_string())
+    }
+}
+
+/// Reception report about one source (RFC 3550 §6.4.1).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReportBlock {
+    /// Source the report is about.
+    pub ssrc: u32,
+    /// Loss since the previous report, in 1/256ths.
+    pub fraction_lost: u8,
+    /// Packets lost since reception began; 24-bit signed on the wire.
+    pub cumulative_lost: i32,
+    /// Extended highest sequence number received.
+    pub highest_sequence: u32,
+    /// Interarrival jitter in timestamp units.
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the last SR received, or 0.
+    pub last_sr: u32,
+    /// Delay since that SR in 1/65536 s.
+    pub delay_since_last_sr: u32,
+}
+
+/// Sender information of an SR.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderInfo {
+    /// 64-bit NTP wall-clock time.
+    pub ntp_timestamp: u64,
+    /// RTP timestamp for the same instant.
+    pub rtp_timestamp: u32,
+    /// Packets sent since starting transmission.
+    pub packet_count: u32,
+    /// Payload octets sent since starting transmission.
+    pub octet_count: u32,
+}
+
+/// One SDES item, e.g. CNAME.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdesItem {
+    /// Item type, [`SDES_CNAME`] for canonical names.
+    pub kind: u8,
+    /// Item text.
+    pub text: String,
+}
+
+/// SDES items of one source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdesChunk {
+    /// Source described.
+    pub ssrc: u32,
+    /// Its items.
+    pub items: Vec<SdesItem>,
+}
+
+/// VoIP metrics report block (RFC 3611 §4.7); 127 means unavailable for the score fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VoipMetrics {
+    /// Source the metrics are about.
+    pub ssrc: u32,
+    /// Lost packets in 1/256ths.
+    pub loss_rate: u8,
+    /// Packets discarded by the jitter buffer in 1/256ths.
+    pub discard_rate: u8,
+    /// Loss and discard density within bursts in 1/256ths.
+    pub burst_density: u8,
+    /// Loss and discard density within gaps in 1/256ths.
+    pub gap_density: u8,
+    /// Mean burst duration in ms.
+    pub burst_duration: u16,
+    /// Mean gap duration in ms.
+    pub gap_duration: u16,
+    /// Round-trip delay in ms.
+    pub round_trip_delay: u16,
+    /// End system delay in ms.
+    pub end_system_delay: u16,
+    /// Signal level in dBm0.
+    pub signal_level: i8,
+    /// Noise level in dBm0.
+    pub noise_level: i8,
+    /// Residual echo return loss in dB.
+    pub rerl: u8,
+    /// Gap threshold.
+    pub gmin: u8,
+    /// R-factor, 0–100.
+    pub r_factor: u8,
+    /// External R-factor, 0–100.
+    pub ext_r_factor: u8,
+    /// Listening quality MOS × 10.
+    pub mos_lq: u8,
+    /// Conversational quality MOS × 10.
+    pub mos_cq: u8,
+    /// Packet loss concealment and jitter buffer configuration.
+    pub rx_config: u8,
+    /// Nominal jitter buffer delay in ms.
+    pub jb_nominal: u16,
+    /// Current maximum jitter buffer delay in ms.
+    pub jb_maximum: u16,
+    /// Absolute maximum jitter buffer delay in ms.
+    pub jb_abs_max: u16,
+}
+
+impl VoipMetrics {
+    /// Value of the score fields when no estimate is available.
+    pub const UNAVAILABLE: u8 = 127;
+}
+
+/// One packet of a compound RTCP datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtcpPacket {
+    /// Sender report.
+    SenderReport {
+        /// Sender SSRC.
+        ssrc: u32,
+        /// Sender information.
+        info: SenderInfo,
+        /// Reception reports, at most 31.
+        reports: Vec<ReportBlock>,
+    },
+    /// Receiver report.
+    ReceiverReport {
+        /// Reporter SSRC.
+        ssrc: u32,
+        /// Reception reports, at most 31.
+        reports: Vec<ReportBlock>,
+    },
+    /// Source description.
+    SourceDescription(Vec<SdesChunk>),
+    /// Sources leaving the session.
+    Goodbye {
+        /// Departing sources.
+        sources: Vec<u32>,
+        /// Optional reason text.
+        reason: Option<String>,
+    },
+    /// Extended report; only VoIP metrics blocks are kept.
+    ExtendedReport {
+        /// Reporter SSRC.
+        ssrc: u32,
+        /// VoIP metrics blocks.
+        voip_metrics: Vec<VoipMetrics>,
+    },
+    /// Any other packet type, kept verbatim.
+    Other {
+        /// Packet type.
+        packet_type: u8,
+        /// Five-bit count/subtype field.
+        count: u8,
+        /// Body after the 4-byte header.
+        body: Vec<u8>,
+    },
+}
+
+impl RtcpPacket {
+    /// Decode a compound datagram.
+    pub fn parse_compound(buf: &[u8]) -> Result<Vec<Self>, RtcpError> {
+        let mut packets = Vec::new();
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(RtcpError::Truncated);
+            }
+            let version = rest[0] >> 6;
+            if version != RTP_VERSION {
+                return Err(RtcpError::Version(version));
+            }
+            let len = (usize::from(u16::from_be_bytes([rest[2], rest[3]])) + 1) * 4;
+            if rest.len() < len {
+                return Err(RtcpError::Truncated);
+            }
+            let mut body = &rest[4..len];
+            if rest[0] & 0x20 != 0 {
+                let padding = usize::from(*body.last().ok_or(RtcpError::Truncated)?);
+                if padding == 0 || padding > body.len() {
+                    return Err(RtcpError::Truncated);
+                }
+                body = &body[..body.len() - padding];
+            }
+            packets.push(Self::parse_body(rest[0] & 0x1f, rest[1], body)?);
+            rest = &rest[len..];
+        }
+        Ok(packets)
+    }
+
+    fn parse_body(count: u8, packet_type: u8, body: &[u8]) -> Result<Self, RtcpError> {
+        let mut reader = Reader(body);
+        Ok(match packet_type {
+            PT_SR => {
+                let ssrc = reader.u32()?;
+                let info = SenderInfo {
+                    ntp_timestamp: u64::from(reader.u32()?) << 32 | u64::from(reader.u32()?),
+                    rtp_timestamp: reader.u32()?,
+                    packet_count: reader.u32()?,
+                    octet_count: reader.u32()?,
+                };
+                let reports = reader.report_blocks(count)?;
+                Self::SenderReport {
+                    ssrc,
+                    info,
+                    reports,
+                }
+            }
+            PT_RR => Self::ReceiverReport {
+                ssrc: reader.u32()?,
+                reports: reader.report_blocks(count)?,
+            },
+            PT_SDES => {
+                let mut chunks = Vec::with_capacity(usize::from(count));
+                for _ in 0..count {
+                    chunks.push(reader.sdes_chunk()?);
+                }
+                Self::SourceDescription(chunks)
+            }
+            PT_BYE => {
+                let mut sources = Vec::with_capacity(usize::from(count));
+                for _ in 0..count {
+                    sources.push(reader.u32()?);
+                }
+                let reason = match reader.0.split_first() {
+                    Some((&len, text)) if len > 0 => {
+                        let text = text.get(..usize::from(len)).ok_or(RtcpError::Truncated)?;
+                        Some(String::from_utf8_lossy(text).into_owned())
+                    }
+                    _ => None,
+                };
+                Self::Goodbye { sources, reason }
+            }
+            PT_XR => {
+                let ssrc = reader.u32()?;
+                let mut voip_metrics = Vec::new();
+                while !reader.0.is_empty() {
+                    let block_type = reader.u8()?;
+                    reader.u8()?;
+                    let len = usize::from(reader.u16()?) * 4;
+                    let mut block = Reader(reader.take(len)?);
+                    if block_type == XR_VOIP_METRICS {
+                        voip_metrics.push(block.voip_metrics()?);
+                    }
+                }
+                Self::ExtendedReport { ssrc, voip_metrics }
+            }
+            _ => Self::Other {
+                packet_type,
+                count,
+                body: body.to_vec(),
+            },
+        })
+    }
+
+    /// Append the wire encoding of this packet to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) -> Result<(), RtcpError> {
+        let start = out.len();
+        out.extend_from_slice(&[0; 4]);
+        let (count, packet_type) = match self {
+            Self::SenderReport {
+                ssrc,
+                info,
+                reports,
+            } => {
+                put_u32(out, *ssrc);
+                put_u32(out, (info.ntp_timestamp >> 32) as u32);
+                put_u32(out, info.ntp_timestamp as u32);
+                put_u32(out, info.rtp_timestamp);
+                put_u32(out, info.packet_count);
+                put_u32(out, info.octet_count);
+                (write_report_blocks(out, reports)?, PT_SR)
+            }
+            Self::ReceiverReport { ssrc, reports } => {
+                put_u32(out, *ssrc);
+                (write_report_blocks(out, reports)?, PT_RR)
+            }
+            Self::SourceDescription(chunks) => {
+                for chunk in chunks {
+                    put_u32(out, chunk.ssrc);
+                    for item in &chunk.items {
+                        let len = u8::try_from(item.text.len())
+                            .map_err(|_| RtcpError::OutOfRange("SDES item length"))?;
+                        out.push(item.kind);
+                        out.push(len);
+                        out.extend_from_slice(item.text.as_bytes());
+                    }
+                    // Terminating null item, then pad the chunk to a word boundary.
+                    out.push(0);
+                    pad(out);
+                }
+                (count_of(chunks.len(), "SDES chunks")?, PT_SDES)
+            }
+            Self::Goodbye { sources, reason } => {
+                for source in sources {
+                    put_u32(out, *source);
+                }
+                if let Some(reason) = reason {
+                    let len = u8::try_from(reason.len())
+                        .map_err(|_| RtcpError::OutOfRange("BYE reason length"))?;
+                    out.push(len);
+                    out.extend_from_slice(reason.as_bytes());
+                    pad(out);
+                }
+                (count_of(sources.len(), "BYE sources")?, PT_BYE)
+            }
+            Self::ExtendedReport { ssrc, voip_metrics } => {
+                put_u32(out, *ssrc);
+                for metrics in voip_metrics {
+                    write_voip_metrics(out, metrics);
+                }
+                (0, PT_XR)
+            }
+            Self::Other {
+                packet_type,
+                count,
+                body,
+            } => {
+                out.extend_from_slice(body);
+                pad(out);
+                (*count & 0x1f, *packet_type)
+            }
+        };
+        let words = u16::try_from((out.len() - start) / 4 - 1)
+            .map_err(|_| RtcpError::OutOfRange("packet length"))?;
+        out[start] = (RTP_VERSION << 6) | count;
+        out[start + 1] = packet_type;
+        out[start + 2..start + 4].copy_from_slice(&words.to_be_bytes());
+        Ok(())
+    }
+
+    /// Encode a compound datagram.
+    pub fn compound_to_bytes(packets: &[Self]) -> Result<Vec<u8>, RtcpError> {
+        let mut out = Vec::new();
+        for packet in packets {
+            packet.write(&mut out)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Current wall-clock time as a 64-bit NTP timestamp.
+pub fn ntp_now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (u64::from(since_epoch.subsec_nanos()) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// Middle 32 bits of an NTP timestamp, as carried in LSR.
+pub const fn ntp_middle(ntp: u64) -> u32 {
+    (ntp >> 16) as u32
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RtcpError> {
+        if self.0.len() < len {
+            return Err(RtcpError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, RtcpError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RtcpError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, RtcpError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn report_blocks(&mut self, count: u8) -> Result<Vec<ReportBlock>, RtcpError> {
+        (0..count)
+            .map(|_| {
+                let ssrc = self.u32()?;
+                let loss = self.u32()?;
+                // Sign-extend the 24-bit cumulative loss.
+                let cumulative_lost = ((loss << 8) as i32) >> 8;
+                Ok(ReportBlock {
+                    ssrc,
+                    fraction_lost: (loss >> 24) as u8,
+                    cumulative_lost,
+                    highest_sequence: self.u32()?,
+                    jitter: self.u32()?,
+                    last_sr: self.u32()?,
+                    delay_since_last_sr: self.u32()?,
+                })
+            })
+            .collect()
+    }
+
+    fn sdes_chunk(&mut self) -> Result<SdesChunk, RtcpError> {
+        let ssrc = self.u32()?;
+        let mut items = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let kind = self.u8()?;
+            consumed += 1;
+            if kind == 0 {
+                break;
+            }
+            let len = self.u8()?;
+            let text = self.take(usize::from(len))?;
+            consumed += 1 + usize::from(len);
+            items.push(SdesItem {
+                kind,
+                text: String::from_utf8_lossy(text).into_owned(),
+            });
+        }
+        let padding = (4 - consumed % 4) % 4;
+        self.take(padding.min(self.0.len()))?;
+        Ok(SdesChunk { ssrc, items })
+    }
+
+    fn voip_metrics(&mut self) -> Result<VoipMetrics, RtcpError> {
+        let ssrc = self.u32()?;
+        let metrics = VoipMetrics {
+            ssrc,
+            loss_rate: self.u8()?,
+            discard_rate: self.u8()?,
+            burst_density: self.u8()?,
+            gap_density: self.u8()?,
+            burst_duration: self.u16()?,
+            gap_duration: self.u16()?,
+            round_trip_delay: self.u16()?,
+            end_system_delay: self.u16()?,
+            signal_level: self.u8()? as i8,
+            noise_level: self.u8()? as i8,
+            rerl: self.u8()?,
+            gmin: self.u8()?,
+            r_factor: self.u8()?,
+            ext_r_factor: self.u8()?,
+            mos_lq: self.u8()?,
+            mos_cq: self.u8()?,
+            rx_config: self.u8()?,
+            jb_nominal: {
+                self.u8()?;
+                self.u16()?
+            },
+            jb_maximum: self.u16()?,
+            jb_abs_max: self.u16()?,
+        };
+        Ok(metrics)
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn pad(out: &mut Vec<u8>) {
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+}
+
+fn count_of(len: usize, what: &'static str) -> Result<u8, RtcpError> {
+    u8::try_from(len)
+        .ok()
+        .filter(|count| *count < 32)
+        .ok_or(RtcpError::OutOfRange(what))
+}
+
+fn write_report_blocks(out: &mut Vec<u8>, reports: &[ReportBlock]) -> Result<u8, RtcpError> {
+    let count = count_of(reports.len(), "report blocks")?;
+    for block in reports {
+        put_u32(out, block.ssrc);
+        let lost = (block.cumulative_lost.clamp(-0x80_0000, 0x7f_ffff) as u32) & 0x00ff_ffff;
+        put_u32(out, u32::from(block.fraction_lost) << 24 | lost);
+        put_u32(out, block.highest_sequence);
+        put_u32(out, block.jitter);
+        put_u32(out, block.last_sr);
+        put_u32(out, block.delay_since_last_sr);
+    }
+    Ok(count)
+}
+
+fn write_voip_metrics(out: &mut Vec<u8>, m: &VoipMetrics) {
+    out.extend_from_slice(&[XR_VOIP_METRICS, 0, 0, 8]);
+    put_u32(out, m.ssrc);
+    out.extend_from_slice(&[m.loss_rate, m.discard_rate, m.burst_density, m.gap_density]);
+    for value in [
+        m.burst_duration,
+        m.gap_duration,
+        m.round_trip_delay,
+        m.end_system_delay,
+    ] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.extend_from_slice(&[
+        m.signal_level as u8,
+        m.noise_level as u8,
+        m.rerl,
+        m.gmin,
+        m.r_factor,
+        m.ext_r_factor,
+        m.mos_lq,
+        m.mos_cq,
+        m.rx_config,
+        0,
+    ]);
+    for value in [m.jb_nominal, m.jb_maximum, m.jb_abs_max] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(ssrc: u32) -> ReportBlock {
+        ReportBlock {
+            ssrc,
+            fraction_lost: 25,
+            cumulative_lost: -3,
+            highest_sequence: 70_000,
+            jitter: 42,
+            last_sr: 0x1234_5678,
+            delay_since_last_sr: 65_536,
+        }
+    }
+
+    #[test]
+    fn test_compound_round_trip() {
+        let packets = vec![
+            RtcpPacket::SenderReport {
+                ssrc: 1,
+                info: SenderInfo {
+                    ntp_timestamp: 0xe8f0_0000_8000_0000,
+                    rtp_timestamp: 160,
+                    packet_count: 10,
+                    octet_count: 1600,
+                },
+                reports: vec![block(2)],
+            },
+            RtcpPacket::SourceDescription(vec![SdesChunk {
+                ssrc: 1,
+                items: vec![SdesItem {
+                    kind: SDES_CNAME,
+                    text: "alice@example.com".into(),
+                }],
+            }]),
+            RtcpPacket::ExtendedReport {
+                ssrc: 1,
+                voip_metrics: vec![VoipMetrics {
+                    ssrc: 2,
+                    loss_rate: 12,
+                    round_trip_delay: 80,
+                    signal_level: -20,
+                    r_factor: 88,
+                    mos_lq: 41,
+                    mos_cq: 40,
+                    jb_nominal: 40,
+                    ..VoipMetrics::default()
+                }],
+            },
+            RtcpPacket::Goodbye {
+                sources: vec![1],
+                reason: Some("hangup".into()),
+            },
+        ];
+        let wire = RtcpPacket::compound_to_bytes(&packets).expect("encode");
+        assert_eq!(wire.len() % 4, 0);
+        assert_eq!(RtcpPacket::parse_compound(&wire).expect("parse"), packets);
+    }
+
+    #[test]
+    fn test_parse_receiver_report() {
+        // RR with one block, as sent by a typical softphone.
+        let wire = [
+            0x81, 201, 0, 7, 0, 0, 0, 9, 0, 0, 0, 2, 0x40, 0xff, 0xff, 0xfe, 0, 1, 0, 5, 0, 0, 0,
+            20, 0x12, 0x34, 0x56, 0x78, 0, 0, 0x80, 0,
+        ];
+        let packets = RtcpPacket::parse_compound(&wire).expect("parse");
+        let [RtcpPacket::ReceiverReport { ssrc: 9, reports }] = &packets[..] else {
+            panic!("unexpected {:?}", packets);
+        };
+        assert_eq!(reports[0].fraction_lost, 64);
+        assert_eq!(reports[0].cumulative_lost, -2);
+        assert_eq!(reports[0].highest_sequence, 65_541);
+        assert_eq!(reports[0].delay_since_last_sr, 0x8000);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert_eq!(
+            RtcpPacket::parse_compound(&[0x81, 201, 0, 7, 0, 0, 0, 9]),
+            Err(RtcpError::Truncated)
+        );
+        assert_eq!(
+            RtcpPacket::parse_compound(&[0x41, 201, 0, 0]),
+            Err(RtcpError::Version(1))
+        );
+        assert_eq!(
+            RtcpPacket::parse_compound(&[0x81, 201, 0, 1, 0, 0, 0, 9]),
+            Err(RtcpError::Truncated),
+            "count promises a report block"
+        );
+    }
+
+    #[test]
+    fn test_ntp_middle_bits() {
+        assert_eq!(ntp_middle(0x1122_3344_5566_7788), 0x3344_5566);
+        assert!(ntp_now() >> 32 > NTP_UNIX_OFFSET);
+    }
+}
@@ -0,0 +1,339 @@
+//! RTP fixed header, CSRC list, header extension and padding (RFC 3550 §5.1, §5.3.1).
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+use voip_common::VoipError;
+
+/// The only RTP version in use.
+pub const RTP_VERSION: u8 = 2;
+
+/// Size of the fixed header without CSRCs.
+pub const FIXED_HEADER_LEN: usize = 12;
+
+/// Errors raised while decoding an RTP packet.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RtpError {
+    /// The datagram ends before the declared header does.
+    #[error("truncated RTP packet: need {needed} bytes, got {len}")]
+    Truncated {
+        /// Bytes required by the header fields read so far.
+        needed: usize,
+        /// Datagram length.
+        len: usize,
+    },
+
+    /// Version field other than 2.
+    #[error("unsupported RTP version {0}")]
+    Version(u8),
+
+    /// Padding count of zero or larger than the payload.
+    #[error("invalid RTP padding length {0}")]
+    Padding(u8),
+
+    /// More than 15 CSRCs, or an extension longer than 65535 words.
+    #[error("RTP header field out of range: {0}")]
+    OutOfRange(&'static str),
+}
+
+impl From<RtpError> for VoipError {
+    fn from(err: RtpError) -> Self {
+        Self::Media(err.to_string())
+    }
+}
+
+/// Header extension (RFC 3550 §5.3.1); `data` length is a multiple of four.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpExtension<'a> {
+    /// Profile-defined 16-bit identifier (0xBEDE for RFC 8285 one-byte headers).
+    pub profile: u16,
+    /// Extension body without the 4-byte extension header.
+    pub data: Cow<'a, [u8]>,
+}
+
+/// An RTP packet borrowing its payload from the received datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket<'a> {
+    /// Marker bit, e.g. start of a talkspurt.
+    pub marker: bool,
+    /// 7-bit payload type.
+    pub payload_type: u8,
+    /// Sequence number.
+    pub sequence: u16,
+    /// Media timestamp.
+    pub timestamp: u32,
+    /// Synchronization source.
+    pub ssrc: u32,
+    /// Contributing sources, at most 15.
+    pub csrcs: Vec<u32>,
+    /// Optional header extension.
+    pub extension: Option<RtpExtension<'a>>,
+    /// Payload with padding removed.
+    pub payload: Cow<'a, [u8]>,
+    /// Padding octets appended after the payload, including the count octet.
+    pub padding: u8,
+}
+
+impl<'a> RtpPacket<'a> {
+    /// Build a packet with no CSRCs, extension or padding.
+    pub fn new(
+        payload_type: u8,
+        sequence: u16,
+        timestamp: u32,
+        ssrc: u32,
+        payload: impl Into<Cow<'a, [u8]>>,
+    ) -> Self {
+        Self {
+            marker: false,
+            payload_type: payload_type & 0x7f,
+            sequence,
+            timestamp,
+            ssrc,
+            csrcs: Vec::new(),
+            extension: None,
+            payload: payload.into(),
+            padding: 0,
+        }
+    }
+
+    /// Set the marker bit.
+    pub fn with_marker(mut self, marker: bool) -> Self {
+        self.marker = marker;
+        self
+    }
+
+    /// Decode a datagram.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, RtpError> {
+        let truncated = |needed| RtpError::Truncated {
+            needed,
+            len: buf.len(),
+        };
+        if buf.len() < FIXED_HEADER_LEN {
+            return Err(truncated(FIXED_HEADER_LEN));
+        }
+        let version = buf[0] >> 6;
+        if version != RTP_VERSION {
+            return Err(RtpError::Version(version));
+        }
+        let has_padding = buf[0] & 0x20 != 0;
+        let has_extension = buf[0] & 0x10 != 0;
+        let csrc_count = usize::from(buf[0] & 0x0f);
+
+        let mut offset = FIXED_HEADER_LEN + csrc_count * 4;
+        if buf.len() < offset {
+            return Err(truncated(offset));
+        }
+        let csrcs = buf[FIXED_HEADER_LEN..offset]
+            .chunks_exact(4)
+            .map(read_u32)
+            .collect();
+
+        let extension = if has_extension {
+            if buf.len() < offset + 4 {
+                return Err(truncated(offset + 4));
+            }
+            let profile = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+            let words = usize::from(u16::from_be_bytes([buf[offset + 2], buf[offset + 3]]));
+            let start = offset + 4;
+            offset = start + words * 4;
+            if buf.len() < offset {
+                return Err(truncated(offset));
+            }
+            Some(RtpExtension {
+                profile,
+                data: Cow::Borrowed(&buf[start..offset]),
+            })
+        } else {
+            None
+        };
+
+        let mut end = buf.len();
+        let mut padding = 0;
+        if has_padding {
+            padding = buf[end - 1];
+            if padding == 0 || usize::from(padding) > end - offset {
+                return Err(RtpError::Padding(padding));
+            }
+            end -= usize::from(padding);
+        }
+
+        Ok(Self {
+            marker: buf[1] & 0x80 != 0,
+            payload_type: buf[1] & 0x7f,
+            sequence: u16::from_be_bytes([buf[2], buf[3]]),
+            timestamp: read_u32(&buf[4..8]),
+            ssrc: read_u32(&buf[8..12]),
+            csrcs,
+            extension,
+            payload: Cow::Borrowed(&buf[offset..end]),
+            padding,
+        })
+    }
+
+    /// Header length including CSRCs and extension.
+    pub fn header_len(&self) -> usize {
+        FIXED_HEADER_LEN
+            + self.csrcs.len() * 4
+            + self.extension.as_ref().map_or(0, |ext| 4 + ext.data.len())
+    }
+
+    /// Encode for the wire, appending `padding` octets when set.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RtpError> {
+        if self.csrcs.len() > 15 {
+            return Err(RtpError::OutOfRange("CSRC count"));
+        }
+        let mut out =
+            Vec::with_capacity(self.header_len() + self.payload.len() + usize::from(self.padding));
+        out.push(
+            (RTP_VERSION << 6)
+                | (u8::from(self.padding > 0) << 5)
+                | (u8::from(self.extension.is_some()) << 4)
+                | self.csrcs.len() as u8,
+        );
+        out.push((u8::from(self.marker) << 7) | (self.payload_type & 0x7f));
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        for csrc in &self.csrcs {
+            out.extend_from_slice(&csrc.to_be_bytes());
+        }
+        if let Some(ext) = &self.extension {
+            let words = u16::try_from(ext.data.len() / 4)
+                .map_err(|_| RtpError::OutOfRange("extension length"))?;
+            if ext.data.len() % 4 != 0 {
+                return Err(RtpError::OutOfRange("extension length"));
+            }
+            out.extend_from_slice(&ext.profile.to_be_bytes());
+            out.extend_from_slice(&words.to_be_bytes());
+            out.extend_from_slice(&ext.data);
+        }
+        out.extend_from_slice(&self.payload);
+        if self.padding > 0 {
+            out.resize(out.len() + usize::from(self.padding) - 1, 0);
+            out.push(self.padding);
+        }
+        Ok(out)
+    }
+
+    /// Detach from the source buffer.
+    pub fn into_owned(self) -> RtpPacket<'static> {
+        RtpPacket {
+            marker: self.marker,
+            payload_type: self.payload_type,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            ssrc: self.ssrc,
+            csrcs: self.csrcs,
+            extension: self.extension.map(|ext| RtpExtension {
+                profile: ext.profile,
+                data: Cow::Owned(ext.data.into_owned()),
+            }),
+            payload: Cow::Owned(self.payload.into_owned()),
+            padding: self.padding,
+        }
+    }
+}
+
+/// Whether a datagram sharing the RTP port is RTCP (RFC 5761 §4: PT 192–223).
+pub fn is_rtcp(buf: &[u8]) -> bool {
+    buf.len() >= 8 && buf[0] >> 6 == RTP_VERSION && (192..=223).contains(&buf[1])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_fixed_header() {
+        let mut wire = vec![
+            0x80, 0x88, 0x12, 0x34, 0, 0, 0x01, 0x40, 0xde, 0xad, 0xbe, 0xef,
+        ];
+        wire.extend_from_slice(&[0xd5; 160]);
+        let packet = RtpPacket::parse(&wire).expect("parse");
+        assert!(packet.marker);
+        assert_eq!(packet.payload_type, 8);
+        assert_eq!(packet.sequence, 0x1234);
+        assert_eq!(packet.timestamp, 320);
+        assert_eq!(packet.ssrc, 0xdead_beef);
+        assert_eq!(packet.payload.len(), 160);
+        assert_eq!(packet.to_bytes().expect("encode"), wire);
+    }
+
+    #[test]
+    fn test_parse_csrc_extension_and_padding() {
+        let mut packet = RtpPacket::new(0, 7, 8000, 42, &[1u8, 2, 3][..]);
+        packet.csrcs = vec![1, 2];
+        packet.extension = Some(RtpExtension {
+            profile: 0xbede,
+            data: Cow::Borrowed(&[0x10, 0xff, 0, 0]),
+        });
+        packet.padding = 5;
+        let wire = packet.to_bytes().expect("encode");
+        assert_eq!(wire.len(), FIXED_HEADER_LEN + 8 + 8 + 3 + 5);
+        assert_eq!(wire[0], 0xb2);
+
+        let parsed = RtpPacket::parse(&wire).expect("parse");
+        assert_eq!(parsed, packet);
+        assert_eq!(parsed.header_len(), FIXED_HEADER_LEN + 16);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(matches!(
+            RtpPacket::parse(&[0x80; 4]),
+            Err(RtpError::Truncated { needed: 12, .. })
+        ));
+        assert_eq!(RtpPacket::parse(&[0x40; 12]), Err(RtpError::Version(1)));
+        let mut csrcs = vec![0x83, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        csrcs.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            RtpPacket::parse(&csrcs),
+            Err(RtpError::Truncated { needed: 24, .. })
+        ));
+        let padding = [0xa0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9];
+        assert_eq!(RtpPacket::parse(&padding), Err(RtpError::Padding(9)));
+    }
+
+    #[test]
+    fn test_is_rtcp() {
+        assert!(is_rtcp(&[0x81, 200, 0, 6, 0, 0, 0, 1]));
+        assert!(!is_rtcp(&[0x80, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]));
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_then_parse_round_trips(
+            marker in any::<bool>(),
+            payload_type in 0u8..128,
+            sequence in any::<u16>(),
+            timestamp in any::<u32>(),
+            ssrc in any::<u32>(),
+            csrcs in prop::collection::vec(any::<u32>(), 0..16),
+            extension in prop::option::of((any::<u16>(), prop::collection::vec(any::<u8>(), 0..8))),
+            payload in prop::collection::vec(any::<u8>(), 0..64),
+            padding in 0u8..16,
+        ) {
+            let mut packet = RtpPacket::new(payload_type, sequence, timestamp, ssrc, payload)
+                .with_marker(marker);
+            packet.csrcs = csrcs;
+            packet.extension = extension.map(|(profile, mut data)| {
+                data.resize(data.len() / 4 * 4, 0);
+                RtpExtension { profile, data: Cow::Owned(data) }
+            });
+            packet.padding = padding;
+            let wire = packet.to_bytes().expect("encode");
+            let parsed = RtpPacket::parse(&wire).expect("parse");
+            prop_assert_eq!(parsed, packet);
+        }
+
+        #[test]
+        fn test_parse_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..128)) {
+            let _ = RtpPacket::parse(&bytes);
+        }
+    }
+}
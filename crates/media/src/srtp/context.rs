@@ -0,0 +1,451 @@
+//! One direction of an SRTP leg: session keys, rollover counters and the
+//! replay windows of every SSRC seen.
+
+use std::collections::HashMap;
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use ring::hmac;
+use voip_common::Result;
+
+use super::{CryptoSuite, MasterKey, SrtpError, KEY_LEN, SALT_LEN};
+use crate::rtp::{FIXED_HEADER_LEN, RTP_VERSION};
+
+/// Key derivation labels (RFC 3711 §4.3.2).
+const LABEL_RTP_ENCRYPTION: u8 = 0;
+const LABEL_RTP_AUTH: u8 = 1;
+const LABEL_RTP_SALT: u8 = 2;
+const LABEL_RTCP_ENCRYPTION: u8 = 3;
+const LABEL_RTCP_AUTH: u8 = 4;
+const LABEL_RTCP_SALT: u8 = 5;
+
+/// HMAC-SHA1 session key length.
+const AUTH_KEY_LEN: usize = 20;
+/// Packets remembered behind the highest index.
+const REPLAY_WINDOW: u64 = 64;
+/// Encrypted flag of the SRTCP index word.
+const SRTCP_E: u32 = 0x8000_0000;
+/// Largest SRTCP index.
+const SRTCP_INDEX_MASK: u32 = 0x7fff_ffff;
+/// Fixed RTCP header left in clear: V/P/RC, type, length and sender SSRC.
+const RTCP_HEADER_LEN: usize = 8;
+
+/// Clear header length, sequence number and SSRC of an RTP packet.
+///
+/// Padding is not looked at: on SRTP packets its count octet is encrypted.
+fn rtp_header(packet: &[u8]) -> std::result::Result<(usize, u16, u32), SrtpError> {
+    let truncated = SrtpError::Truncated(packet.len());
+    if packet.len() < FIXED_HEADER_LEN || packet[0] >> 6 != RTP_VERSION {
+        return Err(truncated);
+    }
+    let mut len = FIXED_HEADER_LEN + usize::from(packet[0] & 0x0f) * 4;
+    if packet[0] & 0x10 != 0 {
+        let words = packet.get(len + 2..len + 4).ok_or(truncated.clone())?;
+        len += 4 + usize::from(u16::from_be_bytes([words[0], words[1]])) * 4;
+    }
+    if len > packet.len() {
+        return Err(truncated);
+    }
+    let seq = u16::from_be_bytes([packet[2], packet[3]]);
+    let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+    Ok((len, seq, ssrc))
+}
+
+/// XOR the AES counter-mode keystream starting at `iv` into `data`.
+fn apply_keystream(cipher: &Aes128, iv: [u8; 16], data: &mut [u8]) {
+    let counter = u128::from_be_bytes(iv);
+    for (block, chunk) in data.chunks_mut(16).enumerate() {
+        let mut keystream = GenericArray::from(counter.wrapping_add(block as u128).to_be_bytes());
+        cipher.encrypt_block(&mut keystream);
+        for (byte, key) in chunk.iter_mut().zip(keystream) {
+            *byte ^= key;
+        }
+    }
+}
+
+/// Counter-mode IV of a packet (RFC 3711 §4.1.1).
+fn packet_iv(salt: &[u8; SALT_LEN], ssrc: u32, index: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..SALT_LEN].copy_from_slice(salt);
+    for (byte, x) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+        *byte ^= x;
+    }
+    for (byte, x) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+        *byte ^= x;
+    }
+    iv
+}
+
+/// Session key material for `label`, with a key derivation rate of zero.
+fn derive<const N: usize>(master: &MasterKey, label: u8) -> [u8; N] {
+    let cipher = Aes128::new(&GenericArray::from(master.key));
+    let mut iv = [0u8; 16];
+    iv[..SALT_LEN].copy_from_slice(&master.salt);
+    iv[7] ^= label;
+    let mut out = [0u8; N];
+    apply_keystream(&cipher, iv, &mut out);
+    out
+}
+
+/// Cipher, salt and authentication key of one of SRTP or SRTCP.
+struct SessionKeys {
+    cipher: Aes128,
+    salt: [u8; SALT_LEN],
+    auth: hmac::Key,
+}
+
+impl SessionKeys {
+    fn derive(master: &MasterKey, labels: [u8; 3]) -> Self {
+        let [encryption, auth, salt] = labels;
+        let key: [u8; KEY_LEN] = derive(master, encryption);
+        let auth: [u8; AUTH_KEY_LEN] = derive(master, auth);
+        Self {
+            cipher: Aes128::new(&GenericArray::from(key)),
+            salt: derive(master, salt),
+            auth: hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &auth),
+        }
+    }
+
+    fn tag(&self, parts: &[&[u8]]) -> hmac::Tag {
+        let mut context = hmac::Context::with_key(&self.auth);
+        for part in parts {
+            context.update(part);
+        }
+        context.sign()
+    }
+
+    /// Compare `tag` with ours in constant time.
+    fn verify(&self, parts: &[&[u8]], tag: &[u8]) -> std::result::Result<(), SrtpError> {
+        let expected = self.tag(parts);
+        let expected = &expected.as_ref()[..tag.len()];
+        let diff = expected
+            .iter()
+            .zip(tag)
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if diff == 0 {
+            Ok(())
+        } else {
+            Err(SrtpError::Authentication)
+        }
+    }
+}
+
+/// Packets already accepted near the highest index.
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `n` is set when `highest - n` was received.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn check(&self, index: u64) -> std::result::Result<(), SrtpError> {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                let behind = highest - index;
+                if behind >= REPLAY_WINDOW || self.seen & (1 << behind) != 0 {
+                    Err(SrtpError::Replay(index))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn accept(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                // Senders can go back further than the window; nothing to record then.
+                if highest - index < REPLAY_WINDOW {
+                    self.seen |= 1 << (highest - index);
+                }
+            }
+            highest => {
+                let ahead = highest.map_or(REPLAY_WINDOW, |highest| index - highest);
+                self.seen = if ahead >= REPLAY_WINDOW {
+                    1
+                } else {
+                    (self.seen << ahead) | 1
+                };
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+/// 48-bit index of `seq` given the highest index so far, guessing the ROC
+/// as in RFC 3711 Appendix A.
+fn estimate_index(highest: Option<u64>, seq: u16) -> u64 {
+    let Some(highest) = highest else {
+        return u64::from(seq);
+    };
+    let roc = highest >> 16;
+    let last = (highest & 0xffff) as u16;
+    let guess = if last < 0x8000 {
+        if seq > last && seq - last > 0x8000 {
+            // Before the first rollover there is no earlier ROC.
+            roc.saturating_sub(1)
+        } else {
+            roc
+        }
+    } else if last - 0x8000 > seq {
+        roc + 1
+    } else {
+        roc
+    };
+    guess << 16 | u64::from(seq)
+}
+
+/// SRTP and SRTCP state for packets keyed by one master key.
+pub struct SrtpContext {
+    suite: CryptoSuite,
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+    /// Highest index and replay state per SSRC, which also tracks the ROC.
+    rtp_streams: HashMap<u32, ReplayWindow>,
+    /// Next SRTCP index per SSRC when sending, replay window when receiving.
+    rtcp_sent: HashMap<u32, u32>,
+    rtcp_received: HashMap<u32, ReplayWindow>,
+}
+
+impl std::fmt::Debug for SrtpContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SrtpContext")
+            .field("suite", &self.suite)
+            .field("ssrcs", &self.rtp_streams.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SrtpContext {
+    /// Context deriving its session keys from `master`.
+    pub fn new(suite: CryptoSuite, master: &MasterKey) -> Self {
+        Self {
+            suite,
+            rtp: SessionKeys::derive(
+                master,
+                [LABEL_RTP_ENCRYPTION, LABEL_RTP_AUTH, LABEL_RTP_SALT],
+            ),
+            rtcp: SessionKeys::derive(
+                master,
+                [LABEL_RTCP_ENCRYPTION, LABEL_RTCP_AUTH, LABEL_RTCP_SALT],
+            ),
+            rtp_streams: HashMap::new(),
+            rtcp_sent: HashMap::new(),
+            rtcp_received: HashMap::new(),
+        }
+    }
+
+    /// Encrypt the payload of an RTP packet and append its tag.
+    pub fn protect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let (header, seq, ssrc) = rtp_header(packet)?;
+        let stream = self.rtp_streams.entry(ssrc).or_default();
+        let index = estimate_index(stream.highest, seq);
+        stream.accept(index);
+
+        let mut out = packet.to_vec();
+        let iv = packet_iv(&self.rtp.salt, ssrc, index);
+        apply_keystream(&self.rtp.cipher, iv, &mut out[header..]);
+        let roc = ((index >> 16) as u32).to_be_bytes();
+        let tag = self.rtp.tag(&[&out, &roc]);
+        out.extend_from_slice(&tag.as_ref()[..self.suite.rtp_tag_len()]);
+        Ok(out)
+    }
+
+    /// Check the tag and replay state of an SRTP packet and decrypt it.
+    pub fn unprotect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let tag_len = self.suite.rtp_tag_len();
+        let body_len = packet
+            .len()
+            .checked_sub(tag_len)
+            .ok_or(SrtpError::Truncated(packet.len()))?;
+        let (body, tag) = packet.split_at(body_len);
+        let (header, seq, ssrc) = rtp_header(body)?;
+        let mut stream = self.rtp_streams.get(&ssrc).copied().unwrap_or_default();
+        let index = estimate_index(stream.highest, seq);
+        stream.check(index)?;
+        let roc = ((index >> 16) as u32).to_be_bytes();
+        self.rtp.verify(&[body, &roc], tag)?;
+
+        stream.accept(index);
+        self.rtp_streams.insert(ssrc, stream);
+        let mut out = body.to_vec();
+        let iv = packet_iv(&self.rtp.salt, ssrc, index);
+        apply_keystream(&self.rtp.cipher, iv, &mut out[header..]);
+        Ok(out)
+    }
+
+    /// Encrypt a compound RTCP packet and append its index and tag.
+    pub fn protect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        if packet.len() < RTCP_HEADER_LEN {
+            return Err(SrtpError::Truncated(packet.len()).into());
+        }
+        let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let next = self.rtcp_sent.entry(ssrc).or_default();
+        let index = *next;
+        *next = (index + 1) & SRTCP_INDEX_MASK;
+
+        let mut out = packet.to_vec();
+        let iv = packet_iv(&self.rtcp.salt, ssrc, u64::from(index));
+        apply_keystream(&self.rtcp.cipher, iv, &mut out[RTCP_HEADER_LEN..]);
+        out.extend_from_slice(&(SRTCP_E | index).to_be_bytes());
+        let tag = self.rtcp.tag(&[&out]);
+        out.extend_from_slice(&tag.as_ref()[..self.suite.rtcp_tag_len()]);
+        Ok(out)
+    }
+
+    /// Check the tag and replay state of an SRTCP packet and decrypt it.
+    pub fn unprotect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let tag_len = self.suite.rtcp_tag_len();
+        if packet.len() < RTCP_HEADER_LEN + 4 + tag_len {
+            return Err(SrtpError::Truncated(packet.len()).into());
+        }
+        let (authenticated, tag) = packet.split_at(packet.len() - tag_len);
+        let (body, word) = authenticated.split_at(authenticated.len() - 4);
+        let word = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        let index = word & SRTCP_INDEX_MASK;
+        let ssrc = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+        let mut window = self.rtcp_received.get(&ssrc).copied().unwrap_or_default();
+        window.check(u64::from(index))?;
+        self.rtcp.verify(&[authenticated], tag)?;
+
+        window.accept(u64::from(index));
+        self.rtcp_received.insert(ssrc, window);
+        let mut out = body.to_vec();
+        if word & SRTCP_E != 0 {
+            let iv = packet_iv(&self.rtcp.salt, ssrc, u64::from(index));
+            apply_keystream(&self.rtcp.cipher, iv, &mut out[RTCP_HEADER_LEN..]);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voip_common::VoipError;
+
+    use crate::{
+        rtp::RtpPacket,
+        srtp::{SrtpPolicy, SrtpSession},
+    };
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("hex"))
+            .collect()
+    }
+
+    fn master(key: &str, salt: &str) -> MasterKey {
+        MasterKey {
+            key: hex(key).try_into().expect("key"),
+            salt: hex(salt).try_into().expect("salt"),
+        }
+    }
+
+    fn rtp(seq: u16, payload: &[u8]) -> Vec<u8> {
+        RtpPacket::new(0, seq, u32::from(seq) * 160, 0xdead_beef, payload)
+            .to_bytes()
+            .expect("encode")
+    }
+
+    #[test]
+    fn matches_the_rfc_3711_keystream_and_key_derivation_vectors() {
+        // Appendix B.2: AES-CM keystream.
+        let cipher = Aes128::new(&GenericArray::from(
+            <[u8; 16]>::try_from(hex("2B7E151628AED2A6ABF7158809CF4F3C")).expect("key"),
+        ));
+        let salt: [u8; SALT_LEN] = hex("F0F1F2F3F4F5F6F7F8F9FAFBFCFD")
+            .try_into()
+            .expect("salt");
+        let mut keystream = [0u8; 32];
+        apply_keystream(&cipher, packet_iv(&salt, 0, 0), &mut keystream);
+        assert_eq!(
+            keystream.to_vec(),
+            hex("E03EAD0935C95E80E166B16DD92B4EB4D23513162B02D0F72A43A2FE4A5F97AB")
+        );
+
+        // Appendix B.3: key derivation.
+        let master = master(
+            "E1F97A0D3E018BE0D64FA32C06DE4139",
+            "0EC675AD498AFEEBB6960B3AABE6",
+        );
+        let key: [u8; KEY_LEN] = derive(&master, LABEL_RTP_ENCRYPTION);
+        let salt: [u8; SALT_LEN] = derive(&master, LABEL_RTP_SALT);
+        let auth: [u8; AUTH_KEY_LEN] = derive(&master, LABEL_RTP_AUTH);
+        assert_eq!(key.to_vec(), hex("C61E7A93744F39EE10734AFE3FF7A087"));
+        assert_eq!(salt.to_vec(), hex("30CBBC08863D8C85D49DB34A9AE1"));
+        assert_eq!(
+            auth.to_vec(),
+            hex("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4")
+        );
+    }
+
+    #[test]
+    fn round_trips_and_rejects_replayed_or_tampered_packets() {
+        let (a, b) = (
+            MasterKey::generate().expect("a"),
+            MasterKey::generate().expect("b"),
+        );
+        for suite in CryptoSuite::ALL {
+            let mut alice = SrtpSession::new(&SrtpPolicy {
+                suite,
+                local: a.clone(),
+                remote: b.clone(),
+            });
+            let mut bob = SrtpSession::new(&SrtpPolicy {
+                suite,
+                local: b.clone(),
+                remote: a.clone(),
+            });
+
+            let plain = rtp(7, &[0x55; 160]);
+            let secure = alice.protect_rtp(&plain).expect("protect");
+            assert_eq!(secure.len(), plain.len() + suite.rtp_tag_len());
+            assert_eq!(secure[..12], plain[..12]);
+            assert_ne!(secure[12..172], plain[12..]);
+            assert_eq!(bob.unprotect_rtp(&secure).expect("unprotect"), plain);
+            assert!(bob.unprotect_rtp(&secure).is_err(), "replay accepted");
+            assert!(alice.unprotect_rtp(&secure).is_err(), "wrong key accepted");
+
+            let mut tampered = alice.protect_rtp(&rtp(8, &[0x55; 160])).expect("protect");
+            tampered[20] ^= 1;
+            assert!(bob.unprotect_rtp(&tampered).is_err(), "tampering accepted");
+            // Reordered but unseen packets are fine.
+            let late = alice.protect_rtp(&rtp(6, &[1; 20])).expect("protect");
+            assert_eq!(bob.unprotect_rtp(&late).expect("late"), rtp(6, &[1; 20]));
+
+            let report = [0x80, 200, 0, 6, 0xde, 0xad, 0xbe, 0xef, 1, 2, 3, 4];
+            let secure = alice.protect_rtcp(&report).expect("protect");
+            assert_eq!(secure.len(), report.len() + 4 + 10);
+            assert_eq!(bob.unprotect_rtcp(&secure).expect("unprotect"), report);
+            assert!(bob.unprotect_rtcp(&secure).is_err(), "replay accepted");
+        }
+    }
+
+    #[test]
+    fn tracks_the_rollover_counter_across_sequence_wraps() {
+        let key = MasterKey::generate().expect("key");
+        let mut sender = SrtpContext::new(CryptoSuite::AES_CM_128_HMAC_SHA1_80, &key);
+        let mut receiver = SrtpContext::new(CryptoSuite::AES_CM_128_HMAC_SHA1_80, &key);
+        for seq in (65_000..=u16::MAX).chain(0..500) {
+            let plain = rtp(seq, &[seq as u8; 40]);
+            let secure = sender.protect_rtp(&plain).expect("protect");
+            assert_eq!(receiver.unprotect_rtp(&secure).expect("unprotect"), plain);
+        }
+        assert_eq!(
+            receiver.rtp_streams[&0xdead_beef].highest,
+            Some(0x1_0000 + 499)
+        );
+        // A straggler from before the wrap still maps to ROC 0, and is now too old.
+        let old = sender.protect_rtp(&rtp(65_400, &[0; 40])).expect("protect");
+        assert_eq!(
+            receiver.unprotect_rtp(&old).map_err(|e| e.to_string()),
+            Err(VoipError::from(SrtpError::Replay(65_400)).to_string())
+        );
+    }
+}
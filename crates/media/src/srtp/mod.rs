@@ -0,0 +1,222 @@
+//! SRTP and SRTCP (RFC 3711) with AES_CM_128 and HMAC-SHA1, keyed through
+//! SDES `a=crypto` lines (RFC 4568).
+//!
+//! Each party encrypts with its own master key, so a leg pairs an outbound
+//! context keyed by us with an inbound one keyed by the remote party. Only
+//! the key derivation rate of zero and no MKI are supported, which is what
+//! SDES endpoints use in practice.
+
+mod context;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+use voip_common::{sdp::CryptoAttribute, Result, VoipError};
+
+pub use context::SrtpContext;
+
+/// Master key length of AES_CM_128.
+pub const KEY_LEN: usize = 16;
+/// Master salt length.
+pub const SALT_LEN: usize = 14;
+
+/// Errors raised while protecting or unprotecting a packet.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SrtpError {
+    /// The packet is shorter than its header and authentication tag.
+    #[error("truncated SRTP packet of {0} bytes")]
+    Truncated(usize),
+
+    /// The authentication tag does not match.
+    #[error("SRTP authentication failed")]
+    Authentication,
+
+    /// The packet index was already received or is older than the replay window.
+    #[error("replayed SRTP packet index {0}")]
+    Replay(u64),
+}
+
+impl From<SrtpError> for VoipError {
+    fn from(err: SrtpError) -> Self {
+        Self::Media(err.to_string())
+    }
+}
+
+/// Crypto suites negotiated with SDES.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CryptoSuite {
+    /// AES-128 counter mode with an 80-bit HMAC-SHA1 tag.
+    AES_CM_128_HMAC_SHA1_80,
+    /// Same with a 32-bit tag on SRTP; SRTCP keeps 80 bits.
+    AES_CM_128_HMAC_SHA1_32,
+}
+
+impl CryptoSuite {
+    /// Every supported suite, most preferred first.
+    pub const ALL: [Self; 2] = [Self::AES_CM_128_HMAC_SHA1_80, Self::AES_CM_128_HMAC_SHA1_32];
+
+    /// Name used in `a=crypto`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AES_CM_128_HMAC_SHA1_80 => "AES_CM_128_HMAC_SHA1_80",
+            Self::AES_CM_128_HMAC_SHA1_32 => "AES_CM_128_HMAC_SHA1_32",
+        }
+    }
+
+    /// Suite called `name`, if supported.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|suite| suite.name() == name)
+    }
+
+    /// Bytes of authentication tag appended to SRTP packets.
+    pub fn rtp_tag_len(self) -> usize {
+        match self {
+            Self::AES_CM_128_HMAC_SHA1_80 => 10,
+            Self::AES_CM_128_HMAC_SHA1_32 => 4,
+        }
+    }
+
+    /// Bytes of authentication tag appended to SRTCP packets (RFC 4568 §6.2).
+    pub fn rtcp_tag_len(self) -> usize {
+        10
+    }
+}
+
+/// Master key and salt one party encrypts with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey {
+    /// AES master key.
+    pub key: [u8; KEY_LEN],
+    /// Master salt.
+    pub salt: [u8; SALT_LEN],
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKey").finish_non_exhaustive()
+    }
+}
+
+impl MasterKey {
+    /// Fresh random key and salt.
+    pub fn generate() -> Result<Self> {
+        let mut material = [0u8; KEY_LEN + SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut material)
+            .map_err(|_| VoipError::Internal("no randomness for SRTP keys".into()))?;
+        Ok(Self::from_material(&material))
+    }
+
+    /// Decode the base64 `key||salt` of an `inline:` key parameter.
+    pub fn from_inline(inline: &str) -> Result<Self> {
+        let material = STANDARD
+            .decode(inline)
+            .map_err(|e| VoipError::Validation(format!("invalid SDES key: {}", e)))?;
+        if material.len() != KEY_LEN + SALT_LEN {
+            return Err(VoipError::Validation(format!(
+                "SDES key of {} bytes, expected {}",
+                material.len(),
+                KEY_LEN + SALT_LEN
+            )));
+        }
+        Ok(Self::from_material(&material))
+    }
+
+    /// Base64 `key||salt` for an `inline:` key parameter.
+    pub fn to_inline(&self) -> String {
+        STANDARD.encode([&self.key[..], &self.salt[..]].concat())
+    }
+
+    fn from_material(material: &[u8]) -> Self {
+        let mut key = [0u8; KEY_LEN];
+        let mut salt = [0u8; SALT_LEN];
+        key.copy_from_slice(&material[..KEY_LEN]);
+        salt.copy_from_slice(&material[KEY_LEN..]);
+        Self { key, salt }
+    }
+}
+
+/// Keys of one SRTP leg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrtpPolicy {
+    /// Suite both parties agreed on.
+    pub suite: CryptoSuite,
+    /// Our key, protecting what we send.
+    pub local: MasterKey,
+    /// The remote party's key, protecting what we receive.
+    pub remote: MasterKey,
+}
+
+impl SrtpPolicy {
+    /// Policy from our `a=crypto` line and the one of the remote party.
+    pub fn from_sdes(ours: &CryptoAttribute, theirs: &CryptoAttribute) -> Result<Self> {
+        let suite = CryptoSuite::from_name(&theirs.suite).ok_or_else(|| {
+            VoipError::Validation(format!("unsupported crypto suite {}", theirs.suite))
+        })?;
+        let key = |crypto: &CryptoAttribute| {
+            crypto
+                .inline_key()
+                .ok_or_else(|| VoipError::Validation("a=crypto has no inline key".into()))
+                .and_then(MasterKey::from_inline)
+        };
+        Ok(Self {
+            suite,
+            local: key(ours)?,
+            remote: key(theirs)?,
+        })
+    }
+}
+
+/// `a=crypto` lines offering every suite, each with a fresh key.
+pub fn sdes_offer() -> Result<Vec<CryptoAttribute>> {
+    CryptoSuite::ALL
+        .into_iter()
+        .zip(1..)
+        .map(|(suite, tag)| {
+            Ok(CryptoAttribute {
+                tag,
+                suite: suite.name().to_string(),
+                key_params: format!("inline:{}", MasterKey::generate()?.to_inline()),
+                session_params: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Both directions of an SRTP leg.
+#[derive(Debug)]
+pub struct SrtpSession {
+    outbound: SrtpContext,
+    inbound: SrtpContext,
+}
+
+impl SrtpSession {
+    /// Contexts for `policy`.
+    pub fn new(policy: &SrtpPolicy) -> Self {
+        Self {
+            outbound: SrtpContext::new(policy.suite, &policy.local),
+            inbound: SrtpContext::new(policy.suite, &policy.remote),
+        }
+    }
+
+    /// Encrypt and authenticate an RTP packet we send.
+    pub fn protect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        self.outbound.protect_rtp(packet)
+    }
+
+    /// Authenticate and decrypt an SRTP packet we received.
+    pub fn unprotect_rtp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        self.inbound.unprotect_rtp(packet)
+    }
+
+    /// Encrypt and authenticate a compound RTCP packet we send.
+    pub fn protect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        self.outbound.protect_rtcp(packet)
+    }
+
+    /// Authenticate and decrypt an SRTCP packet we received.
+    pub fn unprotect_rtcp(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        self.inbound.unprotect_rtcp(packet)
+    }
+}
@@ -0,0 +1,223 @@
+//! A scripted recognizer for tests.
+//!
+//! [`FakeStt`] knows nothing of speech: each utterance it hears, a run of
+//! loud frames ended by [`PAUSE`] of quiet or by the end of the audio, is
+//! transcribed as the next phrase of its script. Partials reveal one more word
+//! of the phrase per [`WORD`] of speech, so the same audio always yields the
+//! same transcripts at the same offsets. Utterances past the end of the
+//! script are not transcribed.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{stream, StreamExt};
+use voip_common::{Result, VoipError};
+
+use super::{offset, AudioStream, SpeechToText, SttOptions, Transcript, TranscriptStream};
+
+/// RMS above which a frame counts as speech.
+pub const SPEECH_LEVEL: f64 = 500.0;
+
+/// Speech revealing one more word in partial transcripts.
+pub const WORD: Duration = Duration::from_millis(200);
+
+/// Quiet that ends an utterance.
+pub const PAUSE: Duration = Duration::from_millis(400);
+
+/// Transcribes each utterance as the next phrase of a script.
+#[derive(Debug, Clone, Default)]
+pub struct FakeStt {
+    script: Vec<String>,
+}
+
+impl FakeStt {
+    /// A recognizer hearing `script`, one phrase per utterance, in every
+    /// stream it transcribes.
+    pub fn new(script: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            script: script.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl SpeechToText for FakeStt {
+    async fn transcribe(
+        &self,
+        options: &SttOptions,
+        audio: AudioStream,
+    ) -> Result<TranscriptStream> {
+        if options.sample_rate == 0 {
+            return Err(VoipError::Validation("sample rate of 0 Hz".into()));
+        }
+        let mut recognizer = Recognizer {
+            script: self.script.clone().into_iter(),
+            sample_rate: options.sample_rate,
+            heard: 0,
+            utterance: None,
+        };
+        Ok(audio
+            .map(Some)
+            .chain(stream::once(async { None }))
+            .filter_map(move |frame| {
+                let transcript = match frame {
+                    Some(frame) => recognizer.hear(&frame),
+                    None => recognizer.finish(),
+                };
+                async move { transcript.map(Ok) }
+            })
+            .boxed())
+    }
+}
+
+#[derive(Debug)]
+struct Utterance {
+    words: Vec<String>,
+    /// Offsets in samples.
+    start: u64,
+    end: u64,
+    voiced: u64,
+    revealed: usize,
+}
+
+#[derive(Debug)]
+struct Recognizer {
+    script: std::vec::IntoIter<String>,
+    sample_rate: u32,
+    heard: u64,
+    utterance: Option<Utterance>,
+}
+
+impl Recognizer {
+    fn hear(&mut self, frame: &[i16]) -> Option<Transcript> {
+        let start = self.heard;
+        self.heard += frame.len() as u64;
+        if !is_speech(frame) {
+            let pause = samples(PAUSE, self.sample_rate);
+            if self
+                .utterance
+                .as_ref()
+                .is_some_and(|utterance| self.heard - utterance.end >= pause)
+            {
+                return self.finish();
+            }
+            return None;
+        }
+        let word = samples(WORD, self.sample_rate);
+        let script = &mut self.script;
+        let utterance = self.utterance.get_or_insert_with(|| Utterance {
+            words: script
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+            start,
+            end: start,
+            voiced: 0,
+            revealed: 0,
+        });
+        utterance.end = self.heard;
+        utterance.voiced += frame.len() as u64;
+        let revealed = ((1 + (utterance.voiced - 1) / word) as usize).min(utterance.words.len());
+        if revealed <= utterance.revealed {
+            return None;
+        }
+        utterance.revealed = revealed;
+        Some(utterance.transcript(false, self.sample_rate))
+    }
+
+    fn finish(&mut self) -> Option<Transcript> {
+        self.utterance
+            .take()
+            .filter(|utterance| !utterance.words.is_empty())
+            .map(|utterance| utterance.transcript(true, self.sample_rate))
+    }
+}
+
+impl Utterance {
+    fn transcript(&self, is_final: bool, sample_rate: u32) -> Transcript {
+        let words = if is_final {
+            &self.words[..]
+        } else {
+            &self.words[..self.revealed]
+        };
+        Transcript {
+            text: words.join(" "),
+            is_final,
+            start: offset(self.start, sample_rate),
+            end: offset(self.end, sample_rate),
+            confidence: Some(1.0),
+        }
+    }
+}
+
+fn samples(duration: Duration, sample_rate: u32) -> u64 {
+    duration.as_millis() as u64 * u64::from(sample_rate) / 1000
+}
+
+fn is_speech(frame: &[i16]) -> bool {
+    if frame.is_empty() {
+        return false;
+    }
+    let energy: f64 = frame.iter().map(|&s| f64::from(s) * f64::from(s)).sum();
+    (energy / frame.len() as f64).sqrt() > SPEECH_LEVEL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn partials_reveal_the_phrase_until_a_pause_finalizes_it() {
+        let stt = FakeStt::new(["please hold the line"]);
+        let frame = |level: i16| vec![level; 320];
+        // 500ms of speech at 16kHz, a short breath, more speech, a pause, and
+        // a second utterance with nothing left in the script.
+        let audio = std::iter::repeat_n(frame(3000), 25)
+            .chain(std::iter::repeat_n(frame(0), 5))
+            .chain(std::iter::repeat_n(frame(-3000), 10))
+            .chain(std::iter::repeat_n(frame(0), 20))
+            .chain(std::iter::repeat_n(frame(3000), 10))
+            .collect::<Vec<_>>();
+        let options = SttOptions {
+            sample_rate: 16000,
+            ..SttOptions::default()
+        };
+        let transcripts: Vec<_> = stt
+            .transcribe(&options, stream::iter(audio).boxed())
+            .await
+            .expect("transcribe")
+            .map(|transcript| {
+                let transcript = transcript.expect("transcript");
+                (
+                    transcript.text,
+                    transcript.is_final,
+                    transcript.end.as_millis(),
+                )
+            })
+            .collect()
+            .await;
+        let expected = [
+            ("please", false, 20),
+            ("please hold", false, 220),
+            ("please hold the", false, 420),
+            ("please hold the line", false, 720),
+            ("please hold the line", true, 800),
+        ];
+        assert_eq!(
+            transcripts,
+            expected.map(|(text, is_final, end)| (text.to_owned(), is_final, end))
+        );
+        assert!(stt
+            .transcribe(
+                &SttOptions {
+                    sample_rate: 0,
+                    ..SttOptions::default()
+                },
+                stream::empty().boxed()
+            )
+            .await
+            .is_err());
+    }
+}
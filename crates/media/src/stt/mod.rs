@@ -0,0 +1,174 @@
+//! Speech-to-text engines behind one streaming interface.
+//!
+//! A [`SpeechToText`] takes mono PCM frames as they are heard and yields
+//! transcripts as the engine revises its guess: partial transcripts of the
+//! utterance so far, then one final transcript per utterance. Offsets are
+//! measured in the audio given, so they line up with what was heard whatever
+//! the engine's latency. [`publish_finals`] announces the final transcripts
+//! of a call on the event bus.
+//!
+//! [`websocket::WebSocketStt`] talks to a streaming recognizer over a
+//! WebSocket; [`fake::FakeStt`] transcribes a script for tests.
+
+pub mod fake;
+pub mod websocket;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{stream::BoxStream, StreamExt};
+use tracing::warn;
+use voip_common::{
+    events::{publish_event, subjects, EventSink, TranscriptEvent},
+    proto::common::CallId,
+    Result,
+};
+
+/// Mono PCM frames, in the order heard.
+pub type AudioStream = BoxStream<'static, Vec<i16>>;
+
+/// Transcripts in the order the engine produced them; an error ends it.
+pub type TranscriptStream = BoxStream<'static, Result<Transcript>>;
+
+/// What the engine heard in an utterance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    /// Words recognized so far.
+    pub text: String,
+    /// Whether the engine is done with the utterance; partials are replaced
+    /// by later transcripts.
+    pub is_final: bool,
+    /// Where the utterance starts in the audio.
+    pub start: Duration,
+    /// Where the audio transcribed so far ends.
+    pub end: Duration,
+    /// Engine's confidence in the text, from 0 to 1, when it says.
+    pub confidence: Option<f32>,
+}
+
+/// How the audio given to an engine is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SttOptions {
+    /// Rate of the PCM: 8000 or 16000.
+    pub sample_rate: u32,
+    /// BCP 47 tag of the spoken language, or the engine's default.
+    pub language: Option<String>,
+}
+
+impl Default for SttOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 8000,
+            language: None,
+        }
+    }
+}
+
+/// A streaming speech recognizer.
+#[async_trait]
+pub trait SpeechToText: Send + Sync {
+    /// Start transcribing `audio`; transcripts stop once the audio has ended
+    /// and the engine has finalized what it heard.
+    async fn transcribe(
+        &self,
+        options: &SttOptions,
+        audio: AudioStream,
+    ) -> Result<TranscriptStream>;
+}
+
+/// Pass `transcripts` through, publishing each final one for `call` on
+/// `events` as it goes by.
+pub fn publish_finals(
+    transcripts: TranscriptStream,
+    call: CallId,
+    events: Arc<dyn EventSink>,
+) -> TranscriptStream {
+    transcripts
+        .then(move |transcript| {
+            let call = call.clone();
+            let events = events.clone();
+            async move {
+                if let Some(final_transcript) = transcript.as_ref().ok().filter(|t| t.is_final) {
+                    let event = TranscriptEvent {
+                        call_id: call.id,
+                        sip_call_id: call.sip_call_id,
+                        correlation_id: call.correlation_id,
+                        text: final_transcript.text.clone(),
+                        start_ms: final_transcript.start.as_millis() as u64,
+                        end_ms: final_transcript.end.as_millis() as u64,
+                        confidence: final_transcript.confidence,
+                        timestamp: Utc::now(),
+                    };
+                    if let Err(e) =
+                        publish_event(events.as_ref(), subjects::MEDIA_TRANSCRIPT, &event).await
+                    {
+                        warn!(call_id = %event.call_id, error = %e, "failed to publish transcript");
+                    }
+                }
+                transcript
+            }
+        })
+        .boxed()
+}
+
+/// Duration of `samples` PCM samples at `sample_rate`.
+pub(crate) fn offset(samples: u64, sample_rate: u32) -> Duration {
+    Duration::from_micros(samples * 1_000_000 / u64::from(sample_rate.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use voip_common::MemoryEventSink;
+
+    #[tokio::test]
+    async fn final_transcripts_are_published_with_their_call() {
+        let stt = fake::FakeStt::new(["hello there", "goodbye"]);
+        let speech = |frames: usize| std::iter::repeat_n(vec![4000; 160], frames);
+        let silence = |frames: usize| std::iter::repeat_n(vec![0; 160], frames);
+        let audio = speech(25)
+            .chain(silence(25))
+            .chain(speech(10))
+            .collect::<Vec<_>>();
+        let transcripts = stt
+            .transcribe(&SttOptions::default(), stream::iter(audio).boxed())
+            .await
+            .expect("transcribe");
+        let events = MemoryEventSink::new();
+        let call = CallId {
+            id: "c0ffee".into(),
+            sip_call_id: "abc@host".into(),
+            correlation_id: "corr-1".into(),
+        };
+        let transcripts: Vec<Transcript> =
+            publish_finals(transcripts, call, Arc::new(events.clone()))
+                .map(|transcript| transcript.expect("transcript"))
+                .collect()
+                .await;
+        assert!(transcripts.iter().any(|t| !t.is_final));
+
+        let published: Vec<TranscriptEvent> = events.events(subjects::MEDIA_TRANSCRIPT);
+        let published: Vec<_> = published
+            .iter()
+            .map(|e| {
+                (
+                    e.call_id.as_str(),
+                    e.sip_call_id.as_str(),
+                    e.correlation_id.as_str(),
+                    e.text.as_str(),
+                    e.start_ms,
+                    e.end_ms,
+                )
+            })
+            .collect();
+        assert_eq!(
+            published,
+            [
+                ("c0ffee", "abc@host", "corr-1", "hello there", 0, 500),
+                ("c0ffee", "abc@host", "corr-1", "goodbye", 1000, 1200),
+            ]
+        );
+    }
+}
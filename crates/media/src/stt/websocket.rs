@@ -0,0 +1,295 @@
+//! Streaming recognizers reached over a WebSocket.
+//!
+//! Once connected, the client sends a `start` JSON message describing the
+//! audio, then the audio itself in binary frames of mono 16-bit little-endian
+//! PCM, then `stop` when the audio ends:
+//!
+//! ```json
+//! {"event":"start","encoding":"L16","sample_rate":8000,"language":"en-US"}
+//! {"event":"stop"}
+//! ```
+//!
+//! The engine answers with `transcript` messages, offsets in milliseconds of
+//! audio, and closes the connection once it has finalized the last utterance;
+//! an `error` message ends the stream with that error:
+//!
+//! ```json
+//! {"event":"transcript","text":"hello","is_final":false,"start_ms":0,"end_ms":420,"confidence":0.8}
+//! {"event":"error","message":"unsupported language"}
+//! ```
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::mpsc, time};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, instrument, warn};
+use voip_common::{Result, VoipError};
+
+use super::{AudioStream, SpeechToText, SttOptions, Transcript, TranscriptStream};
+use crate::fork::protocol::{to_bytes, ENCODING};
+
+/// Longest wait for the engine to accept the connection, and for its last
+/// transcripts once the audio has ended.
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// From the client to the engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// First message, before any audio.
+    Start {
+        /// Always [`ENCODING`].
+        encoding: String,
+        /// Rate of the PCM.
+        sample_rate: u32,
+        /// Spoken language, or the engine's default.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+    },
+    /// No more audio will come.
+    Stop,
+}
+
+/// From the engine to the client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineMessage {
+    /// A partial or final transcript.
+    Transcript {
+        /// Words recognized.
+        text: String,
+        /// Whether the utterance is finalized.
+        is_final: bool,
+        /// Where the utterance starts in the audio.
+        start_ms: u64,
+        /// Where the audio transcribed ends.
+        end_ms: u64,
+        /// From 0 to 1.
+        #[serde(default)]
+        confidence: Option<f32>,
+    },
+    /// The engine gave up.
+    Error {
+        /// What went wrong.
+        message: String,
+    },
+}
+
+/// A recognizer speaking the protocol above.
+#[derive(Debug, Clone)]
+pub struct WebSocketStt {
+    url: String,
+}
+
+impl WebSocketStt {
+    /// A recognizer at the `ws://` `url`; every stream opens a connection.
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        if !url.starts_with("ws://") {
+            return Err(VoipError::Validation(format!(
+                "speech recognizer URL {:?} is not a ws:// URL",
+                url
+            )));
+        }
+        Ok(Self { url })
+    }
+}
+
+#[async_trait]
+impl SpeechToText for WebSocketStt {
+    async fn transcribe(
+        &self,
+        options: &SttOptions,
+        audio: AudioStream,
+    ) -> Result<TranscriptStream> {
+        let unavailable = |e: tokio_tungstenite::tungstenite::Error| {
+            VoipError::Unavailable(format!("speech recognizer {}: {}", self.url, e))
+        };
+        let (mut socket, _) =
+            time::timeout(ENGINE_TIMEOUT, tokio_tungstenite::connect_async(&self.url))
+                .await
+                .map_err(|_| VoipError::Timeout(format!("connecting to {}", self.url)))?
+                .map_err(unavailable)?;
+        let start = ClientMessage::Start {
+            encoding: ENCODING.to_owned(),
+            sample_rate: options.sample_rate,
+            language: options.language.clone(),
+        };
+        socket.send(json(&start)).await.map_err(unavailable)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(socket, audio, tx));
+        Ok(UnboundedReceiverStream::new(rx).boxed())
+    }
+}
+
+fn json(message: &ClientMessage) -> Message {
+    // Plain strings and numbers always serialize.
+    Message::text(serde_json::to_string(message).unwrap_or_default())
+}
+
+#[instrument(name = "media.stt", skip_all)]
+async fn run(
+    mut socket: Socket,
+    mut audio: AudioStream,
+    tx: mpsc::UnboundedSender<Result<Transcript>>,
+) {
+    let lost = |e: tokio_tungstenite::tungstenite::Error| {
+        VoipError::Unavailable(format!("speech recognizer connection lost: {}", e))
+    };
+    // Set once the audio has ended.
+    let mut deadline = None;
+    loop {
+        tokio::select! {
+            frame = audio.next(), if deadline.is_none() => {
+                let sent = match frame {
+                    Some(frame) => socket.send(Message::binary(to_bytes(&frame))).await,
+                    None => {
+                        deadline = Some(time::Instant::now() + ENGINE_TIMEOUT);
+                        socket.send(json(&ClientMessage::Stop)).await
+                    }
+                };
+                if let Err(e) = sent {
+                    let _ = tx.send(Err(lost(e)));
+                    break;
+                }
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(EngineMessage::Transcript {
+                        text,
+                        is_final,
+                        start_ms,
+                        end_ms,
+                        confidence,
+                    }) => {
+                        let transcript = Transcript {
+                            text,
+                            is_final,
+                            start: Duration::from_millis(start_ms),
+                            end: Duration::from_millis(end_ms),
+                            confidence,
+                        };
+                        if tx.send(Ok(transcript)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(EngineMessage::Error { message }) => {
+                        let error = VoipError::Media(format!("speech recognizer: {}", message));
+                        let _ = tx.send(Err(error));
+                        break;
+                    }
+                    Err(e) => debug!(error = %e, "ignoring speech recognizer message"),
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    let _ = tx.send(Err(lost(e)));
+                    break;
+                }
+            },
+            _ = time::sleep_until(deadline.unwrap_or_else(time::Instant::now)),
+                if deadline.is_some() =>
+            {
+                warn!("speech recognizer did not finish in time");
+                break;
+            }
+            _ = tx.closed() => break,
+        }
+    }
+    let _ = socket.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fork::protocol::from_bytes;
+    use futures::stream;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn streams_audio_and_relays_the_engine_transcripts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("ws://{}", listener.local_addr().expect("addr"));
+        let engine = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            let mut socket = tokio_tungstenite::accept_async(stream)
+                .await
+                .expect("handshake");
+            let mut heard = Vec::new();
+            let mut start = None;
+            while let Some(Ok(message)) = socket.next().await {
+                match message {
+                    Message::Binary(data) => heard.extend(from_bytes(&data)),
+                    Message::Text(text) => match serde_json::from_str(&text).expect("client") {
+                        ClientMessage::Start { .. } => {
+                            start = Some(serde_json::from_str(&text).expect("start"))
+                        }
+                        ClientMessage::Stop => break,
+                    },
+                    _ => {}
+                }
+            }
+            for (text, is_final) in [("good", false), ("good morning", true)] {
+                let transcript = EngineMessage::Transcript {
+                    text: text.into(),
+                    is_final,
+                    start_ms: 0,
+                    end_ms: heard.len() as u64 / 8,
+                    confidence: Some(0.9),
+                };
+                let text = serde_json::to_string(&transcript).expect("encode");
+                socket.send(Message::text(text)).await.expect("send");
+            }
+            let error = EngineMessage::Error {
+                message: "quota exceeded".into(),
+            };
+            let text = serde_json::to_string(&error).expect("encode");
+            socket.send(Message::text(text)).await.expect("send");
+            start
+        });
+
+        let stt = WebSocketStt::new(url).expect("stt");
+        let options = SttOptions {
+            language: Some("en-GB".into()),
+            ..SttOptions::default()
+        };
+        let audio = stream::iter(vec![vec![7i16; 160]; 5]).boxed();
+        let transcripts: Vec<_> = stt
+            .transcribe(&options, audio)
+            .await
+            .expect("transcribe")
+            .collect()
+            .await;
+        let Ok(last) = &transcripts[1] else {
+            panic!("expected the final transcript");
+        };
+        assert_eq!(
+            (last.text.as_str(), last.is_final, last.end.as_millis()),
+            ("good morning", true, 100)
+        );
+        assert!(matches!(
+            transcripts[0],
+            Ok(Transcript {
+                is_final: false,
+                ..
+            })
+        ));
+        assert!(matches!(transcripts[2], Err(VoipError::Media(_))));
+        assert_eq!(transcripts.len(), 3);
+        assert_eq!(
+            engine.await.expect("engine"),
+            Some(ClientMessage::Start {
+                encoding: ENCODING.into(),
+                sample_rate: 8000,
+                language: Some("en-GB".into()),
+            })
+        );
+        assert!(WebSocketStt::new("http://127.0.0.1:1").is_err());
+    }
+}
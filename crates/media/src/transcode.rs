@@ -0,0 +1,173 @@
+//! Per-direction transcoding between two legs that share no codec.
+//!
+//! Voice packets are decoded to PCM, resampled when the two codecs run at
+//! different rates, and re-encoded one for one, so sequence numbers, SSRC and
+//! marker bits carry over unchanged. Timestamps are rescaled to the outgoing
+//! clock. RFC 4733 events only have their payload type and clock rewritten.
+
+use voip_common::Result;
+
+use crate::{
+    codec::{AudioCodec, Decoder, Encoder, Resampler},
+    rtp::RtpPacket,
+};
+
+/// How one leg carries audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadFormat {
+    /// Payload type of the voice codec.
+    pub payload_type: u8,
+    /// The voice codec.
+    pub codec: AudioCodec,
+    /// Payload type of telephone-event, when negotiated.
+    pub events: Option<u8>,
+}
+
+/// Converts packets from one leg's format to the other's.
+#[derive(Debug, Clone)]
+pub struct Transcoder {
+    from: PayloadFormat,
+    to: PayloadFormat,
+    decoder: Decoder,
+    resampler: Resampler,
+    encoder: Encoder,
+    pcm: Vec<i16>,
+}
+
+impl Transcoder {
+    /// Pipeline from `from` to `to`.
+    pub fn new(from: PayloadFormat, to: PayloadFormat) -> Result<Self> {
+        Ok(Self {
+            decoder: from.codec.decoder(),
+            resampler: Resampler::new(from.codec.sample_rate(), to.codec.sample_rate())?,
+            encoder: to.codec.encoder(),
+            pcm: Vec::new(),
+            from,
+            to,
+        })
+    }
+
+    /// Format packets arrive in.
+    pub fn from(&self) -> PayloadFormat {
+        self.from
+    }
+
+    /// Format packets leave in.
+    pub fn to(&self) -> PayloadFormat {
+        self.to
+    }
+
+    /// Convert a packet; `None` when its payload type is neither voice nor events.
+    pub fn transcode(&mut self, packet: &RtpPacket<'_>) -> Option<RtpPacket<'static>> {
+        let timestamp = self.timestamp(packet.timestamp);
+        if Some(packet.payload_type) == self.from.events {
+            let mut event = packet.payload.to_vec();
+            if event.len() >= 4 {
+                let duration = u32::from(u16::from_be_bytes([event[2], event[3]]));
+                let duration = self.timestamp(duration).min(u32::from(u16::MAX)) as u16;
+                event[2..4].copy_from_slice(&duration.to_be_bytes());
+            }
+            return Some(self.rewrite(packet, self.to.events?, timestamp, event));
+        }
+        if packet.payload_type != self.from.payload_type {
+            return None;
+        }
+        self.pcm.clear();
+        self.decoder.decode(&packet.payload, &mut self.pcm);
+        let pcm = self.resampler.process(&self.pcm);
+        let mut payload = Vec::with_capacity(pcm.len() * 2);
+        self.encoder.encode(&pcm, &mut payload);
+        Some(self.rewrite(packet, self.to.payload_type, timestamp, payload))
+    }
+
+    /// Rescale a timestamp or duration from the incoming to the outgoing clock.
+    fn timestamp(&self, units: u32) -> u32 {
+        (u64::from(units) * u64::from(self.to.codec.clock_rate())
+            / u64::from(self.from.codec.clock_rate())) as u32
+    }
+
+    fn rewrite(
+        &self,
+        packet: &RtpPacket<'_>,
+        payload_type: u8,
+        timestamp: u32,
+        payload: Vec<u8>,
+    ) -> RtpPacket<'static> {
+        let mut out = RtpPacket::new(
+            payload_type,
+            packet.sequence,
+            timestamp,
+            packet.ssrc,
+            payload,
+        )
+        .with_marker(packet.marker);
+        out.csrcs = packet.csrcs.clone();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::g711;
+
+    fn format(payload_type: u8, codec: AudioCodec) -> PayloadFormat {
+        PayloadFormat {
+            payload_type,
+            codec,
+            events: Some(101),
+        }
+    }
+
+    #[test]
+    fn pcmu_to_pcma_keeps_the_samples_and_rtp_identity() {
+        let mut transcoder =
+            Transcoder::new(format(0, AudioCodec::Pcmu), format(8, AudioCodec::Pcma)).expect("new");
+        let samples: Vec<i16> = (0..160).map(|n| (n * 200 - 16000) as i16).collect();
+        let payload: Vec<u8> = samples.iter().copied().map(g711::ulaw_encode).collect();
+        let packet = RtpPacket::new(0, 7, 1600, 0xfeed, payload.clone()).with_marker(true);
+
+        let out = transcoder.transcode(&packet).expect("voice");
+        assert_eq!(
+            (
+                out.payload_type,
+                out.sequence,
+                out.timestamp,
+                out.ssrc,
+                out.marker
+            ),
+            (8, 7, 1600, 0xfeed, true)
+        );
+        for (ulaw, alaw) in payload.iter().zip(out.payload.iter()) {
+            let (a, b) = (g711::ulaw_decode(*ulaw), g711::alaw_decode(*alaw));
+            assert!((i32::from(a) - i32::from(b)).abs() <= i32::from(a).abs() / 16 + 16);
+        }
+        assert!(transcoder
+            .transcode(&RtpPacket::new(13, 8, 1760, 0xfeed, vec![0u8]))
+            .is_none());
+    }
+
+    #[test]
+    fn rate_changes_resize_payloads_and_rescale_clocks() {
+        let l16 = AudioCodec::L16 { sample_rate: 16000 };
+        let mut up = Transcoder::new(format(0, AudioCodec::Pcmu), format(96, l16)).expect("new");
+        let out = up
+            .transcode(&RtpPacket::new(0, 1, 8000, 1, vec![0xffu8; 160]))
+            .expect("voice");
+        assert_eq!((out.payload.len(), out.timestamp), (640, 16000));
+
+        // G.722 samples at 16 kHz but its RTP clock stays at 8 kHz.
+        let mut down =
+            Transcoder::new(format(9, AudioCodec::G722), format(8, AudioCodec::Pcma)).expect("new");
+        let out = down
+            .transcode(&RtpPacket::new(9, 1, 8000, 1, vec![0u8; 160]))
+            .expect("voice");
+        assert_eq!((out.payload.len(), out.timestamp), (160, 8000));
+
+        // A 100 ms event at 8 kHz lasts 1600 units at 16 kHz.
+        let event = RtpPacket::new(101, 2, 8000, 1, vec![5u8, 0x0a, 0x03, 0x20]);
+        let out = up.transcode(&event).expect("event");
+        assert_eq!((out.payload_type, out.timestamp), (101, 16000));
+        assert_eq!(&out.payload[..], &[5, 0x0a, 0x06, 0x40]);
+    }
+}
@@ -0,0 +1,78 @@
+//! A synthesizer for tests that speaks in tones.
+//!
+//! [`FakeTts`] renders each word of the text as a [`WORD`] long tone followed
+//! by a [`GAP`] of silence, one chunk per word, so the length of the audio
+//! says how many words were spoken.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{stream, StreamExt};
+use voip_common::{Result, VoipError};
+
+use super::{TextToSpeech, TtsOptions};
+use crate::stt::AudioStream;
+
+/// Tone standing for a word.
+pub const WORD: Duration = Duration::from_millis(200);
+
+/// Silence after each word.
+pub const GAP: Duration = Duration::from_millis(50);
+
+/// Pitch of the tones.
+const TONE_HZ: f64 = 440.0;
+
+/// Level of the tones.
+const AMPLITUDE: f64 = 8000.0;
+
+/// Speaks every word as a tone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FakeTts;
+
+#[async_trait]
+impl TextToSpeech for FakeTts {
+    async fn synthesize(&self, text: &str, options: &TtsOptions) -> Result<AudioStream> {
+        if options.sample_rate == 0 {
+            return Err(VoipError::Validation("sample rate of 0 Hz".into()));
+        }
+        let rate = f64::from(options.sample_rate);
+        let tone = (WORD.as_secs_f64() * rate) as usize;
+        let gap = (GAP.as_secs_f64() * rate) as usize;
+        let word: Vec<i16> = (0..tone + gap)
+            .map(|n| {
+                let level = (2.0 * std::f64::consts::PI * TONE_HZ * n as f64 / rate).sin();
+                if n < tone {
+                    (AMPLITUDE * level) as i16
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let words = text.split_whitespace().count();
+        Ok(stream::iter(vec![word; words]).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn every_word_is_a_tone_and_a_gap() {
+        let options = TtsOptions {
+            sample_rate: 16000,
+            ..TtsOptions::default()
+        };
+        let chunks: Vec<Vec<i16>> = FakeTts
+            .synthesize("thanks for calling", &options)
+            .await
+            .expect("synthesize")
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.len() == 4000));
+        let peak = chunks[0][..3200].iter().map(|s| s.abs()).max();
+        assert!(peak > Some(7000));
+        assert!(chunks[0][3200..].iter().all(|&s| s == 0));
+    }
+}
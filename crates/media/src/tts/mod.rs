@@ -0,0 +1,42 @@
+//! Text-to-speech engines behind one streaming interface.
+//!
+//! A [`TextToSpeech`] turns a text into mono PCM, handed over in chunks as it
+//! is synthesized so playback can start before the engine is done. The audio
+//! is played into calls as a [`Prompt`](crate::prompt::Prompt).
+//!
+//! [`websocket::WebSocketTts`] talks to a synthesizer over a WebSocket;
+//! [`fake::FakeTts`] renders words as tones for tests.
+
+pub mod fake;
+pub mod websocket;
+
+use async_trait::async_trait;
+use voip_common::Result;
+
+use crate::stt::AudioStream;
+
+/// How the speech should sound and be laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TtsOptions {
+    /// Rate of the PCM: 8000 or 16000.
+    pub sample_rate: u32,
+    /// Engine voice, or its default.
+    pub voice: Option<String>,
+}
+
+impl Default for TtsOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 8000,
+            voice: None,
+        }
+    }
+}
+
+/// A speech synthesizer.
+#[async_trait]
+pub trait TextToSpeech: Send + Sync {
+    /// Start synthesizing `text`; the audio ends with the speech, or early
+    /// if the engine fails midway.
+    async fn synthesize(&self, text: &str, options: &TtsOptions) -> Result<AudioStream>;
+}
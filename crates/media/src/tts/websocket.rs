@@ -0,0 +1,202 @@
+//! Synthesizers reached over a WebSocket.
+//!
+//! Each text opens a connection. The client sends one `synthesize` message;
+//! the engine answers with the speech in binary frames of mono 16-bit
+//! little-endian PCM, then `done`, or `error` if it cannot go on:
+//!
+//! ```json
+//! {"event":"synthesize","text":"Hello","encoding":"L16","sample_rate":8000,"voice":"anna"}
+//! {"event":"done"}
+//! {"event":"error","message":"unknown voice"}
+//! ```
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, warn};
+use voip_common::{Result, VoipError};
+
+use super::{TextToSpeech, TtsOptions};
+use crate::{
+    fork::protocol::{from_bytes, ENCODING},
+    stt::AudioStream,
+};
+
+/// Longest wait for the engine to accept the connection, and between two
+/// frames of speech.
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// From the client to the engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// The only message of a connection.
+    Synthesize {
+        /// What to say.
+        text: String,
+        /// Always [`ENCODING`].
+        encoding: String,
+        /// Rate of the PCM.
+        sample_rate: u32,
+        /// Engine voice, or its default.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        voice: Option<String>,
+    },
+}
+
+/// From the engine to the client, besides the audio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineMessage {
+    /// All the speech was sent.
+    Done,
+    /// The engine gave up.
+    Error {
+        /// What went wrong.
+        message: String,
+    },
+}
+
+/// A synthesizer speaking the protocol above.
+#[derive(Debug, Clone)]
+pub struct WebSocketTts {
+    url: String,
+}
+
+impl WebSocketTts {
+    /// A synthesizer at the `ws://` `url`.
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        if !url.starts_with("ws://") {
+            return Err(VoipError::Validation(format!(
+                "speech synthesizer URL {:?} is not a ws:// URL",
+                url
+            )));
+        }
+        Ok(Self { url })
+    }
+}
+
+#[async_trait]
+impl TextToSpeech for WebSocketTts {
+    async fn synthesize(&self, text: &str, options: &TtsOptions) -> Result<AudioStream> {
+        let unavailable = |e: tokio_tungstenite::tungstenite::Error| {
+            VoipError::Unavailable(format!("speech synthesizer {}: {}", self.url, e))
+        };
+        let (mut socket, _) =
+            time::timeout(ENGINE_TIMEOUT, tokio_tungstenite::connect_async(&self.url))
+                .await
+                .map_err(|_| VoipError::Timeout(format!("connecting to {}", self.url)))?
+                .map_err(unavailable)?;
+        let request = ClientMessage::Synthesize {
+            text: text.to_owned(),
+            encoding: ENCODING.to_owned(),
+            sample_rate: options.sample_rate,
+            voice: options.voice.clone(),
+        };
+        // Plain strings and numbers always serialize.
+        let request = serde_json::to_string(&request).unwrap_or_default();
+        socket
+            .send(Message::text(request))
+            .await
+            .map_err(unavailable)?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let message = match time::timeout(ENGINE_TIMEOUT, socket.next()).await {
+                    Ok(Some(Ok(message))) => message,
+                    Ok(Some(Err(e))) => {
+                        warn!(error = %e, "speech synthesizer connection lost");
+                        break;
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        warn!("speech synthesizer stalled");
+                        break;
+                    }
+                };
+                match message {
+                    Message::Binary(data) => {
+                        if tx.send(from_bytes(&data)).is_err() {
+                            break;
+                        }
+                    }
+                    Message::Text(text) => match serde_json::from_str(&text) {
+                        Ok(EngineMessage::Done) => break,
+                        Ok(EngineMessage::Error { message }) => {
+                            warn!(%message, "speech synthesizer failed");
+                            break;
+                        }
+                        Err(e) => debug!(error = %e, "ignoring speech synthesizer message"),
+                    },
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            let _ = socket.close(None).await;
+        });
+        Ok(UnboundedReceiverStream::new(rx).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fork::protocol::to_bytes;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn streams_the_speech_until_the_engine_is_done() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("ws://{}", listener.local_addr().expect("addr"));
+        let engine = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            let mut socket = tokio_tungstenite::accept_async(stream)
+                .await
+                .expect("handshake");
+            let Some(Ok(Message::Text(request))) = socket.next().await else {
+                panic!("expected the request");
+            };
+            for chunk in [[1i16; 80], [2; 80]] {
+                socket
+                    .send(Message::binary(to_bytes(&chunk)))
+                    .await
+                    .expect("send");
+            }
+            let done = serde_json::to_string(&EngineMessage::Done).expect("encode");
+            socket.send(Message::text(done)).await.expect("send");
+            // Ignored after `done`.
+            let _ = socket.send(Message::binary(to_bytes(&[3; 80]))).await;
+            serde_json::from_str::<ClientMessage>(&request).expect("request")
+        });
+
+        let tts = WebSocketTts::new(url).expect("tts");
+        let options = TtsOptions {
+            voice: Some("anna".into()),
+            ..TtsOptions::default()
+        };
+        let chunks: Vec<Vec<i16>> = tts
+            .synthesize("Hello", &options)
+            .await
+            .expect("synthesize")
+            .collect()
+            .await;
+        assert_eq!(chunks, [vec![1; 80], vec![2; 80]]);
+        assert_eq!(
+            engine.await.expect("engine"),
+            ClientMessage::Synthesize {
+                text: "Hello".into(),
+                encoding: ENCODING.into(),
+                sample_rate: 8000,
+                voice: Some("anna".into()),
+            }
+        );
+        assert!(WebSocketTts::new("wss://tts.example.com").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! Voice activity detection on decoded PCM.
+//!
+//! A frame is voiced when its level is above [`VadConfig::threshold_db`] and
+//! [`VadConfig::margin_db`] above the noise floor the detector tracks, and its
+//! zero-crossing rate stays below that of hiss: a cheap look at the spectrum
+//! that keeps steady broadband noise from passing for a voice. Speech starts
+//! once [`VadConfig::min_speech`] of voiced audio runs unbroken, so clicks do
+//! not count, and ends after [`VadConfig::hangover`] without a voiced frame,
+//! so the pauses between words do not split an utterance.
+
+use std::time::Duration;
+
+/// Share of samples changing sign above which a frame is taken for noise.
+const MAX_CROSSING_RATE: f64 = 0.4;
+
+/// How fast the noise floor follows louder unvoiced frames, per frame.
+const NOISE_RISE: f64 = 0.05;
+
+/// Level reported for digital silence.
+const SILENCE_DB: f64 = -100.0;
+
+/// Tuning of a [`Vad`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VadConfig {
+    /// Level in dBFS below which nothing is speech.
+    pub threshold_db: f64,
+    /// How far above the noise floor speech must be, in dB.
+    pub margin_db: f64,
+    /// Voiced audio needed before speech starts.
+    pub min_speech: Duration,
+    /// Silence after which speech ends.
+    pub hangover: Duration,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -40.0,
+            margin_db: 9.0,
+            min_speech: Duration::from_millis(60),
+            hangover: Duration::from_millis(400),
+        }
+    }
+}
+
+/// A change in whether a party is talking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadEvent {
+    /// The party started talking, `min_speech` ago.
+    SpeechStarted,
+    /// The party stopped talking, `hangover` ago.
+    SpeechEnded {
+        /// From the start of the speech to its last voiced frame.
+        duration: Duration,
+    },
+}
+
+/// Follows one party's speech, frame by frame.
+#[derive(Debug, Clone)]
+pub struct Vad {
+    config: VadConfig,
+    sample_rate: u32,
+    noise_db: f64,
+    speaking: bool,
+    /// Unbroken voiced audio while not speaking.
+    onset: Duration,
+    /// Length of the current speech, trailing silence included.
+    speech: Duration,
+    /// Silence since the last voiced frame while speaking.
+    quiet: Duration,
+}
+
+impl Vad {
+    /// A detector for PCM at `sample_rate`.
+    pub fn new(config: VadConfig, sample_rate: u32) -> Self {
+        Self {
+            config,
+            sample_rate: sample_rate.max(1),
+            noise_db: SILENCE_DB,
+            speaking: false,
+            onset: Duration::ZERO,
+            speech: Duration::ZERO,
+            quiet: Duration::ZERO,
+        }
+    }
+
+    /// Whether the party is talking.
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Take the next frame; returns the change it brought, if any.
+    pub fn process(&mut self, frame: &[i16]) -> Option<VadEvent> {
+        if frame.is_empty() {
+            return None;
+        }
+        let len =
+            Duration::from_micros(frame.len() as u64 * 1_000_000 / u64::from(self.sample_rate));
+        let voiced = self.voiced(frame);
+        if !self.speaking {
+            self.onset = if voiced {
+                self.onset + len
+            } else {
+                Duration::ZERO
+            };
+            if self.onset < self.config.min_speech {
+                return None;
+            }
+            self.speaking = true;
+            self.speech = self.onset;
+            self.quiet = Duration::ZERO;
+            self.onset = Duration::ZERO;
+            return Some(VadEvent::SpeechStarted);
+        }
+        self.speech += len;
+        self.quiet = if voiced {
+            Duration::ZERO
+        } else {
+            self.quiet + len
+        };
+        if self.quiet < self.config.hangover {
+            return None;
+        }
+        self.speaking = false;
+        Some(VadEvent::SpeechEnded {
+            duration: self.speech.saturating_sub(self.quiet),
+        })
+    }
+
+    fn voiced(&mut self, frame: &[i16]) -> bool {
+        let level = level_db(frame);
+        let crossings = frame
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        let voiced = level >= self.config.threshold_db
+            && level >= self.noise_db + self.config.margin_db
+            && (crossings as f64) < MAX_CROSSING_RATE * frame.len() as f64;
+        if !voiced {
+            self.noise_db = if level < self.noise_db {
+                level
+            } else {
+                self.noise_db + NOISE_RISE * (level - self.noise_db)
+            };
+        }
+        voiced
+    }
+}
+
+/// RMS level of `frame` in dBFS.
+pub fn level_db(frame: &[i16]) -> f64 {
+    if frame.is_empty() {
+        return SILENCE_DB;
+    }
+    let energy: f64 = frame.iter().map(|&s| f64::from(s) * f64::from(s)).sum();
+    let rms = (energy / frame.len() as f64).sqrt() / f64::from(i16::MAX);
+    if rms > 0.0 {
+        (20.0 * rms.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20ms frames at 8 kHz.
+    fn tone(frames: usize) -> Vec<Vec<i16>> {
+        (0..frames)
+            .map(|f| {
+                (0..160)
+                    .map(|n| {
+                        let t = (f * 160 + n) as f64 / 8000.0;
+                        (3000.0 * (2.0 * std::f64::consts::PI * 300.0 * t).sin()) as i16
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn hiss(frames: usize) -> Vec<Vec<i16>> {
+        // Alternating signs: as loud as the tone, but all treble.
+        vec![
+            (0..160)
+                .map(|n| if n % 2 == 0 { 2500 } else { -2500 })
+                .collect();
+            frames
+        ]
+    }
+
+    fn silence(frames: usize) -> Vec<Vec<i16>> {
+        vec![vec![0; 160]; frames]
+    }
+
+    #[test]
+    fn speech_survives_short_pauses_and_ends_after_the_hangover() {
+        let mut vad = Vad::new(VadConfig::default(), 8000);
+        let frames = [hiss(25), tone(10), silence(10), tone(10), silence(20)].concat();
+        let events: Vec<(usize, VadEvent)> = frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, frame)| vad.process(frame).map(|event| (i, event)))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                // 60ms into the tone.
+                (27, VadEvent::SpeechStarted),
+                // 400ms after the second burst.
+                (
+                    74,
+                    VadEvent::SpeechEnded {
+                        duration: Duration::from_millis(600)
+                    }
+                ),
+            ]
+        );
+        assert!(!vad.is_speaking());
+        assert_eq!(level_db(&[i16::MAX; 160]).round(), 0.0);
+        assert_eq!(level_db(&[0; 160]), SILENCE_DB);
+    }
+}
@@ -0,0 +1,1548 @@
+//! Back-to-back user agent (RFC 7092 §3.1).
+//!
+//! Each inbound INVITE is answered here and re-originated as an independent
+//! outbound call. The two legs have their own Call-IDs, tags and CSeq spaces
+//! but share one `CallId::correlation_id`.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::Deserialize;
+use tokio::{
+    sync::Notify,
+    time::{self, Instant},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use voip_common::{sdp::SessionDescription, types::ServiceConfig, CallId, Result, VoipError};
+
+use crate::{
+    dialog::{is_terminal, Call, CallDirection, CallManager, CallState, Dialog, DialogId},
+    registrar::Registrar,
+    sip::{CSeq, Method, NameAddr, SipRequest, SipResponse, SipUri, StatusCode, Via},
+    transaction::{new_tag, ClientEvent, ClientTransaction, ServerTransaction, TransactionLayer},
+    transport::{LocalEndpoint, Target, TransportHandle, TransportKind},
+    ua::sipfrag_status,
+};
+
+/// B2BUA settings, read from the `b2bua` object of `ServiceConfig.extra`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct B2buaConfig {
+    /// Bridge INVITEs instead of answering them with 501.
+    pub enabled: bool,
+    /// Next hop (`sip:gw.example.com;transport=tcp`) for callees with no registered contact.
+    pub trunk: Option<String>,
+    /// Host written in our Via and Contact headers instead of the listener address.
+    pub advertised_host: Option<String>,
+}
+
+impl B2buaConfig {
+    /// Read from `extra.b2bua`, falling back to a disabled B2BUA.
+    pub fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        match config.extra.get("b2bua") {
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|e| VoipError::Config(format!("invalid b2bua config: {}", e))),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// One side of a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Leg {
+    /// The caller's leg, where we are UAS.
+    Inbound,
+    /// The leg we originated towards the callee, where we are UAC.
+    Outbound,
+}
+
+impl Leg {
+    fn other(self) -> Self {
+        match self {
+            Self::Inbound => Self::Outbound,
+            Self::Outbound => Self::Inbound,
+        }
+    }
+}
+
+/// The two legs of a bridged call.
+#[derive(Debug)]
+struct Bridge {
+    inbound: CallId,
+    outbound: CallId,
+    inbound_target: Target,
+    outbound_target: Target,
+    inbound_invite: SipRequest<'static>,
+    inbound_txn: ServerTransaction,
+    inbound_tag: String,
+    outbound_invite: SipRequest<'static>,
+    /// The 2xx relayed to the caller, answered again to retransmitted INVITEs.
+    answer: Mutex<Option<SipResponse<'static>>>,
+    cancelled: AtomicBool,
+    inbound_ack: Notify,
+    outbound_ack: Notify,
+}
+
+impl Bridge {
+    fn correlation_id(&self) -> &str {
+        &self.inbound.correlation_id
+    }
+
+    fn answer(&self) -> Option<SipResponse<'static>> {
+        self.answer.lock().ok()?.clone()
+    }
+
+    fn call_id(&self, leg: Leg) -> &CallId {
+        match leg {
+            Leg::Inbound => &self.inbound,
+            Leg::Outbound => &self.outbound,
+        }
+    }
+
+    fn target(&self, leg: Leg) -> Target {
+        match leg {
+            Leg::Inbound => self.inbound_target,
+            Leg::Outbound => self.outbound_target,
+        }
+    }
+
+    /// Signalled when the ACK for a 2xx we sent on `leg` arrives.
+    fn ack(&self, leg: Leg) -> &Notify {
+        match leg {
+            Leg::Inbound => &self.inbound_ack,
+            Leg::Outbound => &self.outbound_ack,
+        }
+    }
+}
+
+/// Bridges inbound calls to the registered contact of the callee or to a trunk.
+#[derive(Debug)]
+pub struct B2bua {
+    config: B2buaConfig,
+    transactions: TransactionLayer,
+    registrar: Arc<Registrar>,
+    calls: Arc<CallManager>,
+    endpoint: LocalEndpoint,
+    bridges: Mutex<HashMap<String, (Arc<Bridge>, Leg)>>,
+}
+
+impl B2bua {
+    /// Create a B2BUA sending through `transactions` and tracking both legs in `calls`.
+    pub fn new(
+        config: B2buaConfig,
+        transactions: TransactionLayer,
+        registrar: Arc<Registrar>,
+        calls: Arc<CallManager>,
+        transport: Option<TransportHandle>,
+    ) -> Self {
+        let endpoint = LocalEndpoint::new(transport, config.advertised_host.clone());
+        Self {
+            config,
+            transactions,
+            registrar,
+            calls,
+            endpoint,
+            bridges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Settings in use.
+    pub fn config(&self) -> &B2buaConfig {
+        &self.config
+    }
+
+    /// Number of bridged calls in progress.
+    pub fn active(&self) -> usize {
+        self.bridges.lock().map(|b| b.len() / 2).unwrap_or_default()
+    }
+
+    /// Handle INVITE, re-INVITE, ACK, CANCEL and BYE.
+    pub fn handle_request(
+        self: &Arc<Self>,
+        request: SipRequest<'static>,
+        source: Target,
+        transaction: Option<ServerTransaction>,
+    ) {
+        let Some(transaction) = transaction else {
+            if request.method == Method::Ack {
+                self.on_ack(&request);
+            }
+            return;
+        };
+        let this = self.clone();
+        match request.method {
+            Method::Invite if request.in_dialog() => {
+                tokio::spawn(this.reinvite(request, transaction));
+            }
+            Method::Invite => match self.lookup(&request) {
+                // A retransmission outliving its transaction; never bridge twice.
+                Some((bridge, Leg::Inbound)) => match bridge.answer() {
+                    Some(answer) => respond(&transaction, answer),
+                    None => debug!("retransmitted INVITE of a ringing call ignored"),
+                },
+                Some((_, Leg::Outbound)) => debug!("INVITE for our own outbound leg ignored"),
+                None => {
+                    tokio::spawn(this.originate(request, source, transaction));
+                }
+            },
+            Method::Cancel => {
+                tokio::spawn(this.cancel(request, transaction));
+            }
+            Method::Bye => {
+                tokio::spawn(this.bye(request, transaction));
+            }
+            Method::Notify => {
+                tokio::spawn(this.notify(request, transaction));
+            }
+            _ => respond(
+                &transaction,
+                request.response(StatusCode::METHOD_NOT_ALLOWED),
+            ),
+        }
+    }
+
+    /// Hang up both legs of the bridge `sip_call_id` belongs to.
+    pub async fn hang_up(&self, sip_call_id: &str, reason: &str) -> Result<()> {
+        let (bridge, _) = self.bridge(sip_call_id)?;
+        let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+        async {
+            info!(reason, "hanging up");
+            self.release(&bridge, reason).await;
+        }
+        .instrument(span)
+        .await;
+        Ok(())
+    }
+
+    /// Refer the party of the leg `sip_call_id` names to `to` (RFC 3515).
+    ///
+    /// Both legs are hung up once the party reports the transfer succeeded.
+    pub async fn transfer(&self, sip_call_id: &str, to: &SipUri) -> Result<()> {
+        let (bridge, leg) = self.bridge(sip_call_id)?;
+        let call_id = bridge.call_id(leg);
+        let state = self
+            .calls
+            .get(call_id)
+            .map(|call| call.state())
+            .ok_or_else(|| VoipError::NotFound(format!("call {} not found", call_id)))?;
+        if !matches!(state, CallState::StateAnswered | CallState::StateHeld) {
+            return Err(VoipError::Validation(format!(
+                "call {} is {}",
+                call_id,
+                state.as_str_name()
+            )));
+        }
+        let mut refer = self.dialog_request(&bridge, leg, Method::Refer).await?;
+        refer.headers.set("Refer-To", format!("<{}>", to));
+        let mut client = self.transactions.send_request(refer, bridge.target(leg))?;
+        let response = client.final_response().await?;
+        if !response.status.is_success() {
+            return Err(VoipError::Sip {
+                code: response.status.0,
+                reason: format!("REFER rejected: {}", response.reason),
+            });
+        }
+        info!(correlation_id = %bridge.correlation_id(), to = %to, "bridged call transferring");
+        self.calls
+            .transition(call_id, CallState::StateTransferring)
+            .await
+    }
+
+    /// Handle a response no client transaction matched: a retransmitted 2xx whose ACK was lost.
+    pub fn handle_stray_response(self: &Arc<Self>, response: SipResponse<'static>) {
+        let is_invite_2xx = response.status.is_success()
+            && response
+                .headers
+                .cseq()
+                .is_some_and(|cseq| cseq.method == Method::Invite);
+        let bridge = response
+            .headers
+            .call_id()
+            .and_then(|call_id| self.bridges.lock().ok()?.get(call_id).cloned());
+        match bridge {
+            Some((bridge, Leg::Outbound)) if is_invite_2xx => {
+                let this = self.clone();
+                tokio::spawn(async move { this.ack(&bridge, Leg::Outbound).await });
+            }
+            _ => debug!(status = %response.status, "stray response ignored"),
+        }
+    }
+
+    fn bridge(&self, sip_call_id: &str) -> Result<(Arc<Bridge>, Leg)> {
+        self.bridges
+            .lock()
+            .ok()
+            .and_then(|bridges| bridges.get(sip_call_id).cloned())
+            .ok_or_else(|| VoipError::NotFound(format!("bridged call {}", sip_call_id)))
+    }
+
+    fn lookup(&self, request: &SipRequest<'_>) -> Option<(Arc<Bridge>, Leg)> {
+        let call_id = request.headers.call_id()?;
+        self.bridges.lock().ok()?.get(call_id).cloned()
+    }
+
+    fn on_ack(&self, ack: &SipRequest<'_>) {
+        match self.lookup(ack) {
+            Some((bridge, leg)) => bridge.ack(leg).notify_one(),
+            None => debug!("ACK for unknown call ignored"),
+        }
+    }
+
+    async fn originate(
+        self: Arc<Self>,
+        invite: SipRequest<'static>,
+        source: Target,
+        transaction: ServerTransaction,
+    ) {
+        match self.open(&invite, source, &transaction).await {
+            Ok((bridge, client)) => {
+                let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+                self.relay_invite(bridge, client).instrument(span).await;
+            }
+            Err(err) => {
+                warn!(error = %err, uri = %invite.uri, "cannot bridge INVITE");
+                respond(
+                    &transaction,
+                    invite
+                        .response(StatusCode::for_error(&err))
+                        .with_to_tag(&new_tag()),
+                );
+            }
+        }
+    }
+
+    /// Route the INVITE, register both legs and send the outbound INVITE.
+    async fn open(
+        &self,
+        invite: &SipRequest<'static>,
+        source: Target,
+        transaction: &ServerTransaction,
+    ) -> Result<(Arc<Bridge>, ClientTransaction)> {
+        let missing = |header: &str| VoipError::Sip {
+            code: StatusCode::BAD_REQUEST.0,
+            reason: format!("missing or invalid {}", header),
+        };
+        let sip_call_id = invite.headers.call_id().ok_or_else(|| missing("Call-ID"))?;
+        let from = invite.headers.from_addr().ok_or_else(|| missing("From"))?;
+        let to = invite.headers.to_addr().ok_or_else(|| missing("To"))?;
+        let max_forwards = match invite.headers.get("Max-Forwards") {
+            Some(value) => value
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| missing("Max-Forwards"))?,
+            None => 70,
+        };
+        if max_forwards == 0 {
+            return Err(VoipError::Sip {
+                code: StatusCode::TOO_MANY_HOPS.0,
+                reason: "Max-Forwards exhausted".into(),
+            });
+        }
+        let ruri: SipUri = invite.uri.parse()?;
+        let (uri, target) = self
+            .registrar
+            .route(&ruri, self.config.trunk.as_deref())
+            .await?;
+
+        let inbound = CallId::from_sip(sip_call_id.to_string());
+        let outbound = CallId {
+            id: Uuid::new_v4(),
+            sip_call_id: format!("{}@b2bua", Uuid::new_v4().simple()),
+            correlation_id: inbound.correlation_id.clone(),
+        };
+
+        // Only the identities cross the bridge; tags, Call-ID and Via are ours.
+        let mut local = NameAddr {
+            display_name: from.display_name.clone(),
+            uri: from.uri.clone(),
+            params: Vec::new(),
+        };
+        local.set_param("tag", Some(&new_tag()));
+        let remote = NameAddr {
+            display_name: to.display_name.clone(),
+            uri: to.uri.clone(),
+            params: Vec::new(),
+        };
+        let mut request = SipRequest::new(Method::Invite, uri.to_string())
+            .with_header("Via", self.via(target).to_string())
+            .with_header("Max-Forwards", (max_forwards - 1).to_string())
+            .with_header("From", local.to_string())
+            .with_header("To", remote.to_string())
+            .with_header("Call-ID", outbound.sip_call_id.clone())
+            .with_header("CSeq", CSeq::new(1, Method::Invite).to_string())
+            .with_header("Contact", self.contact(target.transport));
+        copy_body(&mut request.headers, invite.headers.get("Content-Type"));
+        request.set_body(invite.body.to_vec());
+
+        let bridge = Arc::new(Bridge {
+            inbound: inbound.clone(),
+            outbound: outbound.clone(),
+            inbound_target: source,
+            outbound_target: target,
+            inbound_invite: invite.clone(),
+            inbound_txn: transaction.clone(),
+            inbound_tag: new_tag(),
+            outbound_invite: request.clone(),
+            answer: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+            inbound_ack: Notify::new(),
+            outbound_ack: Notify::new(),
+        });
+        self.calls.insert(Call::new(
+            inbound.clone(),
+            CallDirection::DirectionInbound,
+            from.uri,
+            to.uri,
+        ));
+        self.calls.insert(Call::new(
+            outbound.clone(),
+            CallDirection::DirectionOutbound,
+            local.uri,
+            uri,
+        ));
+        if let Ok(mut bridges) = self.bridges.lock() {
+            bridges.insert(inbound.sip_call_id.clone(), (bridge.clone(), Leg::Inbound));
+            bridges.insert(
+                outbound.sip_call_id.clone(),
+                (bridge.clone(), Leg::Outbound),
+            );
+        }
+        info!(
+            correlation_id = %inbound.correlation_id,
+            inbound = %inbound.sip_call_id,
+            outbound = %outbound.sip_call_id,
+            target = %target.addr,
+            "bridging call"
+        );
+
+        match self.transactions.send_request(request, target) {
+            Ok(client) => Ok((bridge, client)),
+            Err(err) => {
+                self.finish(&bridge);
+                Err(err)
+            }
+        }
+    }
+
+    /// Relay responses of the outbound INVITE to the caller.
+    async fn relay_invite(&self, bridge: Arc<Bridge>, mut client: ClientTransaction) {
+        while let Some(event) = client.recv().await {
+            let response = match event {
+                ClientEvent::Response(response) => response,
+                ClientEvent::Timeout => {
+                    warn!("outbound leg timed out");
+                    if !bridge.cancelled.load(Ordering::SeqCst) {
+                        let timeout = bridge.inbound_invite.response(StatusCode::REQUEST_TIMEOUT);
+                        respond(
+                            &bridge.inbound_txn,
+                            timeout.with_to_tag(&bridge.inbound_tag),
+                        );
+                    }
+                    self.end(
+                        &bridge,
+                        Leg::Inbound,
+                        CallState::StateFailed,
+                        "callee timeout",
+                    )
+                    .await;
+                    self.end(&bridge, Leg::Outbound, CallState::StateFailed, "timeout")
+                        .await;
+                    self.finish(&bridge);
+                    return;
+                }
+            };
+            let status = response.status;
+            if status == StatusCode::TRYING {
+                continue;
+            }
+            let outbound_invite = &bridge.outbound_invite;
+            if let Err(err) = self
+                .calls
+                .update(&bridge.outbound, |call| {
+                    call.on_invite_response(outbound_invite, &response)
+                })
+                .await
+            {
+                debug!(error = %err, "outbound call state not updated");
+            }
+
+            if bridge.cancelled.load(Ordering::SeqCst) {
+                if status.is_success() {
+                    // The callee answered while our CANCEL was in flight.
+                    self.ack(&bridge, Leg::Outbound).await;
+                    self.send_bye(&bridge, Leg::Outbound).await;
+                    self.end(
+                        &bridge,
+                        Leg::Outbound,
+                        CallState::StateTerminated,
+                        "cancelled",
+                    )
+                    .await;
+                }
+                if status.is_final() {
+                    self.finish(&bridge);
+                    return;
+                }
+                continue;
+            }
+
+            let relayed = self
+                .relay(&bridge.inbound_invite, &response, bridge.inbound_target)
+                .with_to_tag(&bridge.inbound_tag);
+            if status.is_provisional() {
+                let early = Dialog::uas(&bridge.inbound_invite, &bridge.inbound_tag, true);
+                self.update(&bridge, Leg::Inbound, |call| {
+                    if let Some(dialog) = early {
+                        call.add_dialog(dialog);
+                    }
+                    call.transition(CallState::StateRinging)
+                })
+                .await;
+                respond(&bridge.inbound_txn, relayed);
+            } else if status.is_success() {
+                self.ack(&bridge, Leg::Outbound).await;
+                let confirmed = Dialog::uas(&bridge.inbound_invite, &bridge.inbound_tag, false);
+                self.update(&bridge, Leg::Inbound, |call| {
+                    if let Some(dialog) = confirmed {
+                        call.add_dialog(dialog);
+                    }
+                    call.transition(CallState::StateAnswered)
+                })
+                .await;
+                info!("call answered");
+                if let Ok(mut answer) = bridge.answer.lock() {
+                    *answer = Some(relayed.clone());
+                }
+                respond(&bridge.inbound_txn, relayed.clone());
+                self.retransmit_2xx(&bridge, Leg::Inbound, relayed).await;
+                return;
+            } else {
+                let state = if status == StatusCode::REQUEST_TERMINATED {
+                    CallState::StateTerminated
+                } else {
+                    CallState::StateFailed
+                };
+                info!(status = %status, "call rejected by callee");
+                respond(&bridge.inbound_txn, relayed);
+                self.end(
+                    &bridge,
+                    Leg::Inbound,
+                    state,
+                    &format!("{} {}", status, response.reason),
+                )
+                .await;
+                self.finish(&bridge);
+                return;
+            }
+        }
+    }
+
+    async fn cancel(self: Arc<Self>, cancel: SipRequest<'static>, transaction: ServerTransaction) {
+        let Some((bridge, Leg::Inbound)) = self.lookup(&cancel) else {
+            respond(
+                &transaction,
+                cancel.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        respond(&transaction, cancel.response(StatusCode::OK));
+        // A CANCEL after the 2xx has no effect (RFC 3261 §9.2).
+        let answered = self
+            .calls
+            .get(&bridge.inbound)
+            .is_none_or(|call| call.answered_at.is_some());
+        if answered || bridge.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+        async {
+            info!("call cancelled by caller");
+            let terminated = bridge
+                .inbound_invite
+                .response(StatusCode::REQUEST_TERMINATED);
+            respond(
+                &bridge.inbound_txn,
+                terminated.with_to_tag(&bridge.inbound_tag),
+            );
+            self.end(
+                &bridge,
+                Leg::Inbound,
+                CallState::StateTerminated,
+                "cancelled by caller",
+            )
+            .await;
+
+            let request = bridge.outbound_invite.cancel();
+            match self
+                .transactions
+                .send_request(request, bridge.outbound_target)
+            {
+                Ok(mut client) => {
+                    if let Err(err) = client.final_response().await {
+                        debug!(error = %err, "CANCEL unanswered");
+                    }
+                }
+                Err(err) => warn!(error = %err, "failed to send CANCEL"),
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    async fn bye(self: Arc<Self>, bye: SipRequest<'static>, transaction: ServerTransaction) {
+        let Some((bridge, leg)) = self.lookup(&bye) else {
+            respond(&transaction, bye.response(StatusCode::CALL_DOES_NOT_EXIST));
+            return;
+        };
+        let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+        async {
+            if let Err(err) = self.check_request(&bridge, leg, &bye).await {
+                respond(&transaction, bye.response(StatusCode::for_error(&err)));
+                return;
+            }
+            respond(&transaction, bye.response(StatusCode::OK));
+            let reason = match leg {
+                Leg::Inbound => "caller hung up",
+                Leg::Outbound => "callee hung up",
+            };
+            info!(reason, "call ended");
+            self.end(&bridge, leg, CallState::StateTerminated, reason)
+                .await;
+            self.send_bye(&bridge, leg.other()).await;
+            self.end(&bridge, leg.other(), CallState::StateTerminated, reason)
+                .await;
+            self.finish(&bridge);
+        }
+        .instrument(span)
+        .await;
+    }
+
+    /// Follow the progress of a transfer we referred one of the parties to.
+    async fn notify(self: Arc<Self>, notify: SipRequest<'static>, transaction: ServerTransaction) {
+        let Some((bridge, leg)) = self.lookup(&notify) else {
+            respond(
+                &transaction,
+                notify.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        if let Err(err) = self.check_request(&bridge, leg, &notify).await {
+            respond(&transaction, notify.response(StatusCode::for_error(&err)));
+            return;
+        }
+        respond(&transaction, notify.response(StatusCode::OK));
+        let is_refer = notify
+            .headers
+            .get("Event")
+            .is_some_and(|event| event.trim().starts_with("refer"));
+        let Some(status) = is_refer.then(|| sipfrag_status(&notify.body)).flatten() else {
+            return;
+        };
+        let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+        async {
+            if status.is_success() {
+                info!("call transferred");
+                self.release(&bridge, "transferred").await;
+            } else if status.is_final() {
+                info!(status = %status, "transfer failed");
+                self.update(&bridge, leg, |call| {
+                    call.transition(CallState::StateAnswered)
+                })
+                .await;
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    async fn reinvite(
+        self: Arc<Self>,
+        request: SipRequest<'static>,
+        transaction: ServerTransaction,
+    ) {
+        let Some((bridge, leg)) = self.lookup(&request) else {
+            respond(
+                &transaction,
+                request.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        let span = info_span!("b2bua", correlation_id = %bridge.correlation_id());
+        async {
+            let response = match self.forward_reinvite(&bridge, leg, &request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    debug!(error = %err, "re-INVITE failed");
+                    request.response(StatusCode::for_error(&err))
+                }
+            };
+            let accepted = response.status.is_success();
+            respond(&transaction, response.clone());
+            if accepted {
+                let state = if is_hold(&request.body) {
+                    CallState::StateHeld
+                } else {
+                    CallState::StateAnswered
+                };
+                for leg in [Leg::Inbound, Leg::Outbound] {
+                    self.update(&bridge, leg, |call| call.transition(state))
+                        .await;
+                }
+                self.retransmit_2xx(&bridge, leg, response).await;
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    /// Send the offer of a re-INVITE to the other leg and build the answer for the originator.
+    async fn forward_reinvite(
+        &self,
+        bridge: &Bridge,
+        leg: Leg,
+        request: &SipRequest<'_>,
+    ) -> Result<SipResponse<'static>> {
+        self.check_request(bridge, leg, request).await?;
+        let other = leg.other();
+        let mut forward = self.dialog_request(bridge, other, Method::Invite).await?;
+        copy_body(&mut forward.headers, request.headers.get("Content-Type"));
+        forward.set_body(request.body.to_vec());
+
+        let mut client = self
+            .transactions
+            .send_request(forward, bridge.target(other))?;
+        let answer = client.final_response().await?;
+        if answer.status.is_success() {
+            self.calls
+                .update(bridge.call_id(other), |call| {
+                    if let Some(id) = DialogId::outgoing(&answer.headers) {
+                        if let Some(dialog) = call.dialog_mut(&id) {
+                            dialog.refresh_target(&answer.headers);
+                        }
+                    }
+                    Ok(())
+                })
+                .await?;
+            self.ack(bridge, other).await;
+        }
+        Ok(self.relay(request, &answer, bridge.target(leg)))
+    }
+
+    /// Validate the CSeq of an in-dialog request and apply its target refresh.
+    async fn check_request(
+        &self,
+        bridge: &Bridge,
+        leg: Leg,
+        request: &SipRequest<'_>,
+    ) -> Result<()> {
+        let no_dialog = || VoipError::Sip {
+            code: StatusCode::CALL_DOES_NOT_EXIST.0,
+            reason: "no matching dialog".into(),
+        };
+        let id = DialogId::incoming(&request.headers).ok_or_else(no_dialog)?;
+        let cseq = request.headers.cseq().ok_or_else(|| VoipError::Sip {
+            code: StatusCode::BAD_REQUEST.0,
+            reason: "missing CSeq".into(),
+        })?;
+        self.calls
+            .update(bridge.call_id(leg), |call| {
+                let dialog = call.dialog_mut(&id).ok_or_else(no_dialog)?;
+                dialog.validate_remote_cseq(&cseq)?;
+                if request.method == Method::Invite {
+                    dialog.refresh_target(&request.headers);
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Build the response to `request` carrying the status and body the other leg answered with.
+    fn relay(
+        &self,
+        request: &SipRequest<'_>,
+        answer: &SipResponse<'_>,
+        target: Target,
+    ) -> SipResponse<'static> {
+        let mut response = request.response(answer.status);
+        response.reason = Cow::Owned(answer.reason.to_string());
+        if answer.status.is_provisional() || answer.status.is_success() {
+            response
+                .headers
+                .push("Contact", self.contact(target.transport));
+        }
+        if !answer.body.is_empty() {
+            copy_body(&mut response.headers, answer.headers.get("Content-Type"));
+            response.set_body(answer.body.to_vec());
+        }
+        response
+    }
+
+    /// Build an in-dialog request on `leg`.
+    async fn dialog_request(
+        &self,
+        bridge: &Bridge,
+        leg: Leg,
+        method: Method,
+    ) -> Result<SipRequest<'static>> {
+        let target = bridge.target(leg);
+        let via = self.via(target);
+        let is_invite = method == Method::Invite;
+        let mut request = self
+            .calls
+            .update(bridge.call_id(leg), |call| {
+                let call_id = call.call_id.clone();
+                let no_dialog = || VoipError::NotFound(format!("no dialog for {}", call_id));
+                let id = call
+                    .dialog()
+                    .map(|dialog| dialog.id.clone())
+                    .ok_or_else(no_dialog)?;
+                let request = call
+                    .dialog_mut(&id)
+                    .map(|dialog| dialog.create_request(method, via));
+                request.ok_or_else(no_dialog)
+            })
+            .await?;
+        if is_invite {
+            request
+                .headers
+                .push("Contact", self.contact(target.transport));
+        }
+        Ok(request)
+    }
+
+    /// Acknowledge the 2xx to our latest INVITE on `leg`.
+    async fn ack(&self, bridge: &Bridge, leg: Leg) {
+        match self.dialog_request(bridge, leg, Method::Ack).await {
+            Ok(ack) => self
+                .transactions
+                .send_stateless(bridge.target(leg), ack.into()),
+            Err(err) => warn!(error = %err, "cannot build ACK"),
+        }
+    }
+
+    async fn send_bye(&self, bridge: &Bridge, leg: Leg) {
+        let bye = match self.dialog_request(bridge, leg, Method::Bye).await {
+            Ok(bye) => bye,
+            Err(err) => {
+                warn!(error = %err, "cannot build BYE");
+                return;
+            }
+        };
+        match self.transactions.send_request(bye, bridge.target(leg)) {
+            Ok(mut client) => {
+                if let Err(err) = client.final_response().await {
+                    debug!(error = %err, "BYE unanswered");
+                }
+            }
+            Err(err) => warn!(error = %err, "failed to send BYE"),
+        }
+    }
+
+    /// Retransmit a 2xx we sent on `leg` until its ACK arrives (RFC 3261 §13.3.1.4).
+    async fn retransmit_2xx(&self, bridge: &Bridge, leg: Leg, response: SipResponse<'static>) {
+        let target = bridge.target(leg);
+        if target.transport.is_reliable() {
+            return;
+        }
+        let timers = self.transactions.timers();
+        let deadline = Instant::now() + timers.t1 * 64;
+        let mut interval = timers.t1;
+        loop {
+            tokio::select! {
+                () = bridge.ack(leg).notified() => return,
+                () = time::sleep(interval) => {}
+            }
+            if Instant::now() >= deadline {
+                warn!("2xx never acknowledged, hanging up");
+                for leg in [Leg::Inbound, Leg::Outbound] {
+                    self.send_bye(bridge, leg).await;
+                    self.end(bridge, leg, CallState::StateTerminated, "no ACK")
+                        .await;
+                }
+                self.finish(bridge);
+                return;
+            }
+            self.transactions
+                .send_stateless(target, response.clone().into());
+            interval = (interval * 2).min(timers.t2);
+        }
+    }
+
+    async fn update(&self, bridge: &Bridge, leg: Leg, f: impl FnOnce(&mut Call) -> Result<()>) {
+        if let Err(err) = self.calls.update(bridge.call_id(leg), f).await {
+            debug!(error = %err, "call state not updated");
+        }
+    }
+
+    async fn end(&self, bridge: &Bridge, leg: Leg, state: CallState, reason: &str) {
+        self.update(bridge, leg, |call| {
+            if is_terminal(call.state()) {
+                return Ok(());
+            }
+            call.end(state, reason)
+        })
+        .await;
+    }
+
+    /// Hang up both legs and forget them.
+    async fn release(&self, bridge: &Bridge, reason: &str) {
+        for leg in [Leg::Inbound, Leg::Outbound] {
+            self.send_bye(bridge, leg).await;
+            self.end(bridge, leg, CallState::StateTerminated, reason)
+                .await;
+        }
+        self.finish(bridge);
+    }
+
+    /// Forget both legs.
+    fn finish(&self, bridge: &Bridge) {
+        if let Ok(mut bridges) = self.bridges.lock() {
+            bridges.remove(&bridge.inbound.sip_call_id);
+            bridges.remove(&bridge.outbound.sip_call_id);
+        }
+        self.calls.remove(&bridge.inbound);
+        self.calls.remove(&bridge.outbound);
+        debug!(correlation_id = %bridge.correlation_id(), "bridge closed");
+    }
+
+    fn via(&self, target: Target) -> Via {
+        self.endpoint.via(target.transport)
+    }
+
+    fn contact(&self, transport: TransportKind) -> String {
+        self.endpoint.contact(transport, "b2bua")
+    }
+}
+
+fn respond(transaction: &ServerTransaction, response: SipResponse<'static>) {
+    if let Err(err) = transaction.respond(response) {
+        debug!(error = %err, "response dropped");
+    }
+}
+
+fn copy_body(headers: &mut crate::sip::Headers<'_>, content_type: Option<&str>) {
+    if let Some(content_type) = content_type {
+        headers.set("Content-Type", content_type.to_string());
+    }
+}
+
+/// Whether an SDP offer puts the call on hold (RFC 3264 §8.4, or the RFC 2543 `0.0.0.0` form).
+fn is_hold(sdp: &[u8]) -> bool {
+    std::str::from_utf8(sdp)
+        .ok()
+        .and_then(|sdp| SessionDescription::parse(sdp).ok())
+        .is_some_and(|sdp| sdp.is_hold())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::UdpSocket;
+    use tonic::Request;
+    use voip_common::{
+        events::{subjects, CallEndedEvent, CallStartedEvent, MediaTimeoutEvent},
+        proto::{
+            self,
+            sip::{sip_service_server::SipService, ByeRequest, HoldRequest, TransferRequest},
+        },
+        MemoryEventSink,
+    };
+
+    use super::*;
+    use crate::{
+        grpc::SipGrpcService,
+        sip::{parse_message, SipMessage},
+        transport::TransportConfig,
+        SignallingService,
+    };
+
+    const OFFER: &[u8] = b"v=0\r\no=alice 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+    const ANSWER: &[u8] = b"v=0\r\no=bob 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 5000 RTP/AVP 0\r\n";
+
+    struct Harness {
+        service: Arc<SignallingService>,
+        events: MemoryEventSink,
+        caller: UdpSocket,
+        callee: UdpSocket,
+        addr: SocketAddr,
+    }
+
+    async fn harness() -> Harness {
+        let caller = UdpSocket::bind("127.0.0.1:0").await.expect("caller");
+        let callee = UdpSocket::bind("127.0.0.1:0").await.expect("callee");
+        let config = TransportConfig {
+            udp: Some("127.0.0.1:0".parse().expect("addr")),
+            tcp: None,
+            ..TransportConfig::default()
+        };
+        let events = MemoryEventSink::new();
+        let service = SignallingService::bind(&config)
+            .await
+            .expect("bind")
+            .with_call_manager(CallManager::new().with_events(Arc::new(events.clone())))
+            .with_b2bua(B2buaConfig {
+                enabled: true,
+                trunk: Some(format!("sip:{}", callee.local_addr().expect("addr"))),
+                advertised_host: None,
+            });
+        let service = Arc::new(service);
+        let addr = service
+            .transport()
+            .and_then(TransportHandle::udp_addr)
+            .expect("udp");
+        service.clone().spawn();
+        Harness {
+            service,
+            events,
+            caller,
+            callee,
+            addr,
+        }
+    }
+
+    async fn recv(
+        socket: &UdpSocket,
+        wanted: impl Fn(&SipMessage<'_>) -> bool,
+    ) -> SipMessage<'static> {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let (n, _) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+                .await
+                .expect("timed out")
+                .expect("recv");
+            let (message, _) = parse_message(&buf[..n]).expect("parse");
+            if wanted(&message) {
+                return message.into_owned();
+            }
+        }
+    }
+
+    fn request(method: Method) -> impl Fn(&SipMessage<'_>) -> bool {
+        move |m| matches!(m, SipMessage::Request(r) if r.method == method)
+    }
+
+    fn response(status: StatusCode, method: Method) -> impl Fn(&SipMessage<'_>) -> bool {
+        move |m| {
+            matches!(m, SipMessage::Response(r)
+                if r.status == status && r.headers.cseq().is_some_and(|c| c.method == method))
+        }
+    }
+
+    fn caller_request(
+        h: &Harness,
+        method: Method,
+        branch: &str,
+        cseq: u32,
+        to_tag: Option<&str>,
+    ) -> SipRequest<'static> {
+        let port = h.caller.local_addr().expect("addr").port();
+        let to = match to_tag {
+            Some(tag) => format!("<sip:bob@voip.local>;tag={}", tag),
+            None => "<sip:bob@voip.local>".to_string(),
+        };
+        SipRequest::new(method.clone(), "sip:bob@voip.local")
+            .with_header(
+                "Via",
+                format!("SIP/2.0/UDP 127.0.0.1:{};branch={};rport", port, branch),
+            )
+            .with_header("Max-Forwards", "70")
+            .with_header("From", "<sip:alice@voip.local>;tag=alice")
+            .with_header("To", to)
+            .with_header("Call-ID", "a-leg")
+            .with_header("CSeq", CSeq::new(cseq, method).to_string())
+            .with_header("Contact", format!("<sip:alice@127.0.0.1:{}>", port))
+    }
+
+    async fn send(socket: &UdpSocket, message: impl Into<SipMessage<'static>>, to: SocketAddr) {
+        socket
+            .send_to(&message.into().to_bytes(), to)
+            .await
+            .expect("send");
+    }
+
+    fn callee_response(
+        h: &Harness,
+        invite: &SipRequest<'_>,
+        status: StatusCode,
+    ) -> SipResponse<'static> {
+        let mut resp = invite.response(status).with_to_tag("bob");
+        let port = h.callee.local_addr().expect("addr").port();
+        resp.headers
+            .push("Contact", format!("<sip:bob@127.0.0.1:{}>", port));
+        resp
+    }
+
+    /// Place a call with `OFFER` that the callee answers; returns the
+    /// outbound INVITE and the caller's To tag. The caller has not ACKed yet.
+    async fn answered(h: &Harness, branch: &str) -> (SipRequest<'static>, String) {
+        send(&h.caller, offer(h, branch), h.addr).await;
+        let SipMessage::Request(b_invite) = recv(&h.callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        let mut ok = callee_response(h, &b_invite, StatusCode::OK);
+        ok.headers.push("Content-Type", "application/sdp");
+        ok.set_body(ANSWER);
+        send(&h.callee, ok, h.addr).await;
+        recv(&h.callee, request(Method::Ack)).await;
+        let SipMessage::Response(answered) =
+            recv(&h.caller, response(StatusCode::OK, Method::Invite)).await
+        else {
+            unreachable!()
+        };
+        let a_tag = answered
+            .headers
+            .to_addr()
+            .and_then(|to| to.tag().map(str::to_string))
+            .expect("tag");
+        (b_invite, a_tag)
+    }
+
+    fn offer(h: &Harness, branch: &str) -> SipRequest<'static> {
+        let mut invite = caller_request(h, Method::Invite, branch, 1, None);
+        invite.headers.push("Content-Type", "application/sdp");
+        invite.set_body(OFFER);
+        invite
+    }
+
+    /// Answer the BYE each leg receives.
+    async fn answer_byes(h: &Harness, b_invite: &SipRequest<'_>) {
+        for (socket, call_id) in [
+            (&h.caller, Some("a-leg")),
+            (&h.callee, b_invite.headers.call_id()),
+        ] {
+            let SipMessage::Request(bye) = recv(socket, request(Method::Bye)).await else {
+                unreachable!()
+            };
+            assert_eq!(bye.headers.call_id(), call_id);
+            send(socket, bye.response(StatusCode::OK), h.addr).await;
+        }
+    }
+
+    fn a_leg() -> Option<proto::common::CallId> {
+        Some(proto::common::CallId {
+            sip_call_id: "a-leg".to_string(),
+            ..Default::default()
+        })
+    }
+
+    async fn wait_idle(h: &Harness) {
+        let b2bua = h.service.b2bua().expect("b2bua").clone();
+        time::timeout(Duration::from_secs(5), async {
+            while b2bua.active() > 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("bridge closed");
+    }
+
+    #[tokio::test]
+    async fn answered_call_is_bridged_and_hung_up_on_both_legs() {
+        let h = harness().await;
+        let mut invite = caller_request(&h, Method::Invite, "z9hG4bKa1", 1, None);
+        invite.headers.push("Content-Type", "application/sdp");
+        invite.set_body(OFFER);
+        send(&h.caller, invite, h.addr).await;
+
+        let SipMessage::Request(b_invite) = recv(&h.callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        assert_ne!(b_invite.headers.call_id(), Some("a-leg"));
+        assert_eq!(b_invite.headers.get("Max-Forwards"), Some("69"));
+        assert_eq!(&*b_invite.body, OFFER);
+
+        let mut ringing = callee_response(&h, &b_invite, StatusCode::SESSION_PROGRESS);
+        ringing.headers.push("Content-Type", "application/sdp");
+        ringing.set_body(ANSWER);
+        send(&h.callee, ringing, h.addr).await;
+        let SipMessage::Response(early) = recv(
+            &h.caller,
+            response(StatusCode::SESSION_PROGRESS, Method::Invite),
+        )
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(&*early.body, ANSWER, "early media SDP is relayed");
+        let a_tag = early
+            .headers
+            .to_addr()
+            .and_then(|to| to.tag().map(str::to_string))
+            .expect("tag");
+
+        let mut ok = callee_response(&h, &b_invite, StatusCode::OK);
+        ok.headers.push("Content-Type", "application/sdp");
+        ok.set_body(ANSWER);
+        send(&h.callee, ok, h.addr).await;
+        recv(&h.callee, request(Method::Ack)).await;
+        let SipMessage::Response(answered) =
+            recv(&h.caller, response(StatusCode::OK, Method::Invite)).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(&*answered.body, ANSWER);
+        assert_eq!(
+            answered
+                .headers
+                .to_addr()
+                .and_then(|to| to.tag().map(str::to_string)),
+            Some(a_tag.clone())
+        );
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKa2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+
+        send(
+            &h.caller,
+            caller_request(&h, Method::Bye, "z9hG4bKa3", 2, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+        recv(&h.caller, response(StatusCode::OK, Method::Bye)).await;
+        let SipMessage::Request(b_bye) = recv(&h.callee, request(Method::Bye)).await else {
+            unreachable!()
+        };
+        assert_eq!(b_bye.headers.call_id(), b_invite.headers.call_id());
+        send(&h.callee, b_bye.response(StatusCode::OK), h.addr).await;
+        wait_idle(&h).await;
+
+        let started: Vec<CallStartedEvent> = h.events.events(subjects::CALL_STARTED);
+        let ended: Vec<CallEndedEvent> = h.events.events(subjects::CALL_ENDED);
+        assert_eq!((started.len(), ended.len()), (2, 2));
+        assert_eq!(started[0].correlation_id, started[1].correlation_id);
+        assert_ne!(started[0].call_id, started[1].call_id);
+        let inbound: Vec<&str> = started
+            .iter()
+            .filter(|e| e.direction == CallDirection::DirectionInbound.as_str_name())
+            .map(|e| e.sip_call_id.as_str())
+            .collect();
+        assert_eq!(inbound, ["a-leg"]);
+        assert!(
+            ended
+                .iter()
+                .all(|e| e.correlation_id == started[0].correlation_id
+                    && e.reason == "caller hung up")
+        );
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn media_timeout_hangs_up_both_legs() {
+        let h = harness().await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Invite, "z9hG4bKm1", 1, None),
+            h.addr,
+        )
+        .await;
+        let SipMessage::Request(b_invite) = recv(&h.callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        send(
+            &h.callee,
+            callee_response(&h, &b_invite, StatusCode::OK),
+            h.addr,
+        )
+        .await;
+        recv(&h.callee, request(Method::Ack)).await;
+        let SipMessage::Response(answered) =
+            recv(&h.caller, response(StatusCode::OK, Method::Invite)).await
+        else {
+            unreachable!()
+        };
+        let a_tag = answered
+            .headers
+            .to_addr()
+            .and_then(|to| to.tag().map(str::to_string))
+            .expect("tag");
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKm2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+
+        let timeout = MediaTimeoutEvent {
+            session_id: "relay".into(),
+            call_id: String::new(),
+            sip_call_id: "a-leg".into(),
+            leg: "callee".into(),
+            reason: "no RTP".into(),
+            silent_for_ms: 30_000,
+            held: false,
+            timestamp: chrono::Utc::now(),
+        };
+        let (hung_up, ()) = tokio::join!(
+            h.service.on_media_timeout(&timeout),
+            answer_byes(&h, &b_invite)
+        );
+        hung_up.expect("hung up");
+        wait_idle(&h).await;
+
+        let ended: Vec<CallEndedEvent> = h.events.events(subjects::CALL_ENDED);
+        assert_eq!(ended.len(), 2);
+        assert!(ended
+            .iter()
+            .all(|e| e.reason == "media timeout: no RTP on callee leg"));
+        assert!(h.service.on_media_timeout(&timeout).await.is_err());
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn caller_cancel_is_propagated_to_the_callee() {
+        let h = harness().await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Invite, "z9hG4bKc1", 1, None),
+            h.addr,
+        )
+        .await;
+        let SipMessage::Request(b_invite) = recv(&h.callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        send(
+            &h.callee,
+            callee_response(&h, &b_invite, StatusCode::RINGING),
+            h.addr,
+        )
+        .await;
+        recv(&h.caller, response(StatusCode::RINGING, Method::Invite)).await;
+
+        send(
+            &h.caller,
+            caller_request(&h, Method::Cancel, "z9hG4bKc1", 1, None),
+            h.addr,
+        )
+        .await;
+        recv(&h.caller, response(StatusCode::OK, Method::Cancel)).await;
+        recv(
+            &h.caller,
+            response(StatusCode::REQUEST_TERMINATED, Method::Invite),
+        )
+        .await;
+
+        let SipMessage::Request(b_cancel) = recv(&h.callee, request(Method::Cancel)).await else {
+            unreachable!()
+        };
+        assert_eq!(
+            b_cancel
+                .headers
+                .top_via()
+                .and_then(|v| v.branch().map(str::to_string)),
+            b_invite
+                .headers
+                .top_via()
+                .and_then(|v| v.branch().map(str::to_string))
+        );
+        send(&h.callee, b_cancel.response(StatusCode::OK), h.addr).await;
+        send(
+            &h.callee,
+            callee_response(&h, &b_invite, StatusCode::REQUEST_TERMINATED),
+            h.addr,
+        )
+        .await;
+        recv(&h.callee, request(Method::Ack)).await;
+        wait_idle(&h).await;
+
+        let ended: Vec<CallEndedEvent> = h.events.events(subjects::CALL_ENDED);
+        assert_eq!(ended.len(), 2);
+        assert!(h
+            .events
+            .events::<CallStartedEvent>(subjects::CALL_STARTED)
+            .is_empty());
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn retransmitted_invite_is_answered_again_without_a_second_bridge() {
+        let h = harness().await;
+        let (_, a_tag) = answered(&h, "z9hG4bKr1").await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKr2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+
+        // The 200 got lost on its way to the caller, who sends the INVITE again.
+        send(&h.caller, offer(&h, "z9hG4bKr1"), h.addr).await;
+        let SipMessage::Response(again) =
+            recv(&h.caller, response(StatusCode::OK, Method::Invite)).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(&*again.body, ANSWER);
+        assert_eq!(
+            again
+                .headers
+                .to_addr()
+                .and_then(|to| to.tag().map(str::to_string)),
+            Some(a_tag)
+        );
+        let second = time::timeout(
+            Duration::from_millis(300),
+            recv(&h.callee, request(Method::Invite)),
+        )
+        .await;
+        assert!(second.is_err(), "the callee is invited once");
+        assert_eq!(h.service.b2bua().expect("b2bua").active(), 1);
+        assert_eq!(
+            h.events
+                .events::<CallStartedEvent>(subjects::CALL_STARTED)
+                .len(),
+            2
+        );
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn hold_reinvite_is_relayed_to_the_callee() {
+        let h = harness().await;
+        let (b_invite, a_tag) = answered(&h, "z9hG4bKh1").await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKh2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+
+        let hold = [OFFER, b"a=sendonly\r\n"].concat();
+        let mut reinvite = caller_request(&h, Method::Invite, "z9hG4bKh3", 2, Some(&a_tag));
+        reinvite.headers.push("Content-Type", "application/sdp");
+        reinvite.set_body(hold.clone());
+        send(&h.caller, reinvite, h.addr).await;
+
+        let SipMessage::Request(b_reinvite) = recv(&h.callee, request(Method::Invite)).await else {
+            unreachable!()
+        };
+        assert_eq!(b_reinvite.headers.call_id(), b_invite.headers.call_id());
+        assert_eq!(&*b_reinvite.body, hold.as_slice());
+        let mut ok = callee_response(&h, &b_reinvite, StatusCode::OK);
+        ok.headers.push("Content-Type", "application/sdp");
+        ok.set_body(ANSWER);
+        send(&h.callee, ok, h.addr).await;
+        recv(&h.callee, request(Method::Ack)).await;
+
+        let SipMessage::Response(accepted) = recv(&h.caller, |m| {
+            matches!(m, SipMessage::Response(r) if r.status == StatusCode::OK
+                && r.headers.cseq().is_some_and(|c| c.seq == 2))
+        })
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(&*accepted.body, ANSWER);
+        let calls = h.service.calls().clone();
+        time::timeout(Duration::from_secs(5), async {
+            while !calls
+                .list()
+                .iter()
+                .all(|call| call.state() == CallState::StateHeld)
+            {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("both legs held");
+        assert_eq!(calls.list().len(), 2);
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn api_hangs_up_bridged_calls() {
+        let h = harness().await;
+        let (b_invite, a_tag) = answered(&h, "z9hG4bKg1").await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKg2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+        let grpc = SipGrpcService::new(&h.service);
+
+        let hold = grpc
+            .hold(Request::new(HoldRequest { call_id: a_leg() }))
+            .await;
+        assert_eq!(
+            hold.err().map(|s| s.code()),
+            Some(tonic::Code::FailedPrecondition)
+        );
+        let bye = grpc.bye(Request::new(ByeRequest {
+            call_id: a_leg(),
+            reason: "agent hung up".to_string(),
+        }));
+        let (bye, ()) = tokio::join!(bye, answer_byes(&h, &b_invite));
+        assert!(bye.expect("bye").into_inner().success);
+        wait_idle(&h).await;
+
+        let ended: Vec<CallEndedEvent> = h.events.events(subjects::CALL_ENDED);
+        assert_eq!(ended.len(), 2);
+        assert!(ended.iter().all(|e| e.reason == "agent hung up"));
+        h.service.shutdown();
+    }
+
+    #[tokio::test]
+    async fn api_transfers_the_caller_of_a_bridged_call() {
+        let h = harness().await;
+        let (b_invite, a_tag) = answered(&h, "z9hG4bKx1").await;
+        send(
+            &h.caller,
+            caller_request(&h, Method::Ack, "z9hG4bKx2", 1, Some(&a_tag)),
+            h.addr,
+        )
+        .await;
+        let grpc = SipGrpcService::new(&h.service);
+
+        let transfer = grpc.transfer(Request::new(TransferRequest {
+            call_id: a_leg(),
+            transfer_to: Some((&"sip:desk@voip.local".parse::<SipUri>().expect("uri")).into()),
+            attended: false,
+        }));
+        let accept = async {
+            let SipMessage::Request(refer) = recv(&h.caller, request(Method::Refer)).await else {
+                unreachable!()
+            };
+            assert_eq!(refer.headers.get("Refer-To"), Some("<sip:desk@voip.local>"));
+            send(&h.caller, refer.response(StatusCode::ACCEPTED), h.addr).await;
+        };
+        let (transfer, ()) = tokio::join!(transfer, accept);
+        assert!(transfer.expect("transfer").into_inner().success);
+
+        let mut notify = caller_request(&h, Method::Notify, "z9hG4bKx3", 2, Some(&a_tag))
+            .with_header("Event", "refer")
+            .with_header("Content-Type", "message/sipfrag");
+        notify.set_body(b"SIP/2.0 200 OK\r\n".to_vec());
+        send(&h.caller, notify, h.addr).await;
+        recv(&h.caller, response(StatusCode::OK, Method::Notify)).await;
+        answer_byes(&h, &b_invite).await;
+        wait_idle(&h).await;
+
+        let ended: Vec<CallEndedEvent> = h.events.events(subjects::CALL_ENDED);
+        assert_eq!(ended.len(), 2);
+        assert!(ended.iter().all(|e| e.reason == "transferred"));
+        h.service.shutdown();
+    }
+
+    #[test]
+    fn hold_offers_are_detected() {
+        let sendonly = [OFFER, b"a=sendonly\r\n"].concat();
+        assert!(is_hold(&sendonly));
+        let legacy =
+            String::from_utf8_lossy(OFFER).replace("c=IN IP4 127.0.0.1", "c=IN IP4 0.0.0.0");
+        assert!(is_hold(legacy.as_bytes()));
+        assert!(!is_hold(OFFER));
+    }
+}
@@ -1,12 +1,22 @@
 //! Command-line entrypoint for the signalling service.
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use tokio::signal;
-use tracing::info;
+use tokio::{signal, sync::oneshot};
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tracing::{info, warn};
 
-use voip_common::{init_telemetry, Result, VoipError};
-use voip_signalling::SignallingService;
+use voip_common::{
+    events::{decode_event, subjects, MediaTimeoutEvent},
+    init_telemetry,
+    proto::sip::sip_service_server::SipServiceServer,
+    EventBus, EventSink, Result, VoipError,
+};
+use voip_signalling::{
+    b2bua::B2buaConfig, dialog::CallManager, grpc::SipGrpcService, registrar::Registrar,
+    transport::TransportConfig, SignallingService,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,15 +25,73 @@ async fn main() -> Result<()> {
         .map_err(|e| VoipError::Internal(format!("init telemetry failed: {}", e)))?;
     info!("starting signalling service");
 
-    let service = Arc::new(SignallingService::new());
+    let transport_config = TransportConfig::from_service_config(&config)?;
+    let b2bua_config = B2buaConfig::from_service_config(&config)?;
+    let mut registrar = Registrar::from_service_config(&config).await?;
+    let mut calls = CallManager::new();
+    let mut media_timeouts = None;
+    match EventBus::connect(&config.nats_url).await {
+        Ok(bus) => {
+            let bus = bus.with_service_name("signalling");
+            match bus.subscribe(subjects::MEDIA_TIMEOUT).await {
+                Ok(subscriber) => media_timeouts = Some(subscriber),
+                Err(e) => warn!(error = %e, "calls will not be hung up on media timeouts"),
+            }
+            let events: Arc<dyn EventSink> = Arc::new(bus);
+            registrar = registrar.with_events(events.clone());
+            calls = calls.with_events(events);
+        }
+        Err(e) => warn!(error = %e, "event bus unavailable, registration and call events disabled"),
+    }
+    let service = Arc::new(
+        SignallingService::bind(&transport_config)
+            .await?
+            .with_registrar(registrar)
+            .with_call_manager(calls)
+            .with_b2bua(b2bua_config),
+    );
     let handle = service.clone().spawn();
+    if let Some(mut subscriber) = media_timeouts {
+        let service = service.clone();
+        tokio::spawn(async move {
+            while let Some(message) = subscriber.next().await {
+                let hung_up = match decode_event::<MediaTimeoutEvent>(&message.payload) {
+                    Ok(event) => service.on_media_timeout(&event).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = hung_up {
+                    warn!(error = %e, "media timeout not handled");
+                }
+            }
+        });
+    }
 
-    signal::ctrl_c().await
+    let grpc_addr: SocketAddr = config
+        .bind_addr
+        .parse()
+        .map_err(|e| VoipError::Config(format!("invalid bind_addr {}: {}", config.bind_addr, e)))?;
+    let (grpc_shutdown, grpc_stop) = oneshot::channel::<()>();
+    let grpc = tokio::spawn(
+        Server::builder()
+            .add_service(SipServiceServer::new(SipGrpcService::new(&service)))
+            .serve_with_shutdown(grpc_addr, async {
+                let _ = grpc_stop.await;
+            }),
+    );
+    info!(addr = %grpc_addr, "SipService listening");
+
+    signal::ctrl_c()
+        .await
         .map_err(|e| VoipError::Internal(format!("waiting for ctrl+c failed: {}", e)))?;
     info!("ctrl+c received");
     service.shutdown();
+    let _ = grpc_shutdown.send(());
+    grpc.await
+        .map_err(|e| VoipError::Internal(format!("joining gRPC task failed: {}", e)))?
+        .map_err(|e| VoipError::Internal(format!("gRPC server failed: {}", e)))?;
 
-    handle.await
+    handle
+        .await
         .map_err(|e| VoipError::Internal(format!("joining signalling task failed: {}", e)))??;
     info!("signalling service stopped");
     Ok(())
@@ -0,0 +1,778 @@
+//! SIP dialogs (RFC 3261 §12) and the call state machine behind `voip.sip.CallState`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use voip_common::{
+    events::{publish_event, subjects, CallEndedEvent, CallStartedEvent},
+    proto, CallId, EventSink, Result, VoipError,
+};
+
+pub use voip_common::proto::sip::{CallDirection, CallState};
+
+use crate::{
+    proto_timestamp,
+    sip::{CSeq, Headers, Method, NameAddr, SipRequest, SipResponse, SipUri, StatusCode, Via},
+};
+
+/// Whether `state` ends the call.
+pub fn is_terminal(state: CallState) -> bool {
+    matches!(state, CallState::StateTerminated | CallState::StateFailed)
+}
+
+/// Whether a call may move from `from` to `to`.
+pub fn is_valid_transition(from: CallState, to: CallState) -> bool {
+    use CallState::*;
+    matches!(
+        (from, to),
+        (StateUnknown, StateInitiating)
+            | (
+                StateInitiating | StateRinging,
+                StateRinging | StateAnswered | StateTerminated | StateFailed
+            )
+            | (
+                StateAnswered,
+                StateHeld | StateTransferring | StateTerminated
+            )
+            | (
+                StateHeld,
+                StateAnswered | StateTransferring | StateTerminated
+            )
+            | (StateTransferring, StateAnswered | StateTerminated)
+    )
+}
+
+/// Dialog lifecycle (RFC 3261 §12).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogState {
+    /// Created by a provisional response; may still be replaced by another fork.
+    Early,
+    /// Established by a 2xx.
+    Confirmed,
+    /// Ended by BYE, a failure response or a losing fork.
+    Terminated,
+}
+
+/// Dialog identifier seen from our side.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DialogId {
+    /// Call-ID.
+    pub call_id: String,
+    /// Our tag.
+    pub local_tag: String,
+    /// Peer tag.
+    pub remote_tag: String,
+}
+
+impl DialogId {
+    /// Identifier of the dialog a received request belongs to (To is us).
+    pub fn incoming(headers: &Headers<'_>) -> Option<Self> {
+        Some(Self {
+            call_id: headers.call_id()?.to_string(),
+            local_tag: headers.to_addr()?.tag()?.to_string(),
+            remote_tag: headers.from_addr()?.tag()?.to_string(),
+        })
+    }
+
+    /// Identifier of the dialog a response to one of our requests belongs to (From is us).
+    pub fn outgoing(headers: &Headers<'_>) -> Option<Self> {
+        Some(Self {
+            call_id: headers.call_id()?.to_string(),
+            local_tag: headers.from_addr()?.tag()?.to_string(),
+            remote_tag: headers.to_addr()?.tag()?.to_string(),
+        })
+    }
+}
+
+/// Dialog state shared by both ends of an INVITE session.
+#[derive(Debug, Clone)]
+pub struct Dialog {
+    /// Identifier.
+    pub id: DialogId,
+    /// Early, confirmed or terminated.
+    pub state: DialogState,
+    /// Our address, with our tag.
+    pub local: NameAddr,
+    /// Peer address, with its tag.
+    pub remote: NameAddr,
+    /// Peer Contact, where in-dialog requests go.
+    pub remote_target: SipUri,
+    /// Route set, in the order requests must traverse it.
+    pub route_set: Vec<String>,
+    /// Last CSeq we used.
+    pub local_seq: u32,
+    /// Highest CSeq received from the peer.
+    pub remote_seq: Option<u32>,
+}
+
+fn contact_uri(headers: &Headers<'_>) -> Option<SipUri> {
+    headers
+        .values("Contact")
+        .next()
+        .and_then(|c| c.parse::<NameAddr>().ok())
+        .map(|c| c.uri)
+}
+
+impl Dialog {
+    /// Dialog created by a response to an INVITE we sent (§12.1.2).
+    ///
+    /// Returns `None` for 100 Trying, failure responses or a response without a To tag.
+    pub fn uac(request: &SipRequest<'_>, response: &SipResponse<'_>) -> Option<Self> {
+        let status = response.status;
+        if status == StatusCode::TRYING || !(status.is_provisional() || status.is_success()) {
+            return None;
+        }
+        let id = DialogId::outgoing(&response.headers)?;
+        let mut route_set: Vec<String> = response
+            .headers
+            .values("Record-Route")
+            .map(|r| r.trim().to_string())
+            .collect();
+        route_set.reverse();
+        Some(Self {
+            id,
+            state: if status.is_success() {
+                DialogState::Confirmed
+            } else {
+                DialogState::Early
+            },
+            local: request.headers.from_addr()?,
+            remote: response.headers.to_addr()?,
+            remote_target: contact_uri(&response.headers).or_else(|| request.uri.parse().ok())?,
+            route_set,
+            local_seq: request.headers.cseq()?.seq,
+            remote_seq: None,
+        })
+    }
+
+    /// Dialog created by answering an INVITE with `local_tag` (§12.1.1).
+    pub fn uas(request: &SipRequest<'_>, local_tag: &str, early: bool) -> Option<Self> {
+        let remote = request.headers.from_addr()?;
+        let mut local = request.headers.to_addr()?;
+        local.set_param("tag", Some(local_tag));
+        Some(Self {
+            id: DialogId {
+                call_id: request.headers.call_id()?.to_string(),
+                local_tag: local_tag.to_string(),
+                remote_tag: remote.tag()?.to_string(),
+            },
+            state: if early {
+                DialogState::Early
+            } else {
+                DialogState::Confirmed
+            },
+            local,
+            remote_target: contact_uri(&request.headers)?,
+            remote,
+            route_set: request
+                .headers
+                .values("Record-Route")
+                .map(|r| r.trim().to_string())
+                .collect(),
+            local_seq: 0,
+            remote_seq: Some(request.headers.cseq()?.seq),
+        })
+    }
+
+    /// Move an early dialog to confirmed on the 2xx to our INVITE.
+    pub fn confirm(&mut self, response: &SipResponse<'_>) {
+        self.state = DialogState::Confirmed;
+        if let Some(target) = contact_uri(&response.headers) {
+            self.remote_target = target;
+        }
+        // The route set is fixed by the 2xx for UACs (§12.1.2).
+        let mut route_set: Vec<String> = response
+            .headers
+            .values("Record-Route")
+            .map(|r| r.trim().to_string())
+            .collect();
+        if !route_set.is_empty() {
+            route_set.reverse();
+            self.route_set = route_set;
+        }
+    }
+
+    /// Update the remote target from a target refresh request or response (re-INVITE, UPDATE).
+    pub fn refresh_target(&mut self, headers: &Headers<'_>) {
+        if let Some(target) = contact_uri(headers) {
+            self.remote_target = target;
+        }
+    }
+
+    /// Check a received in-dialog CSeq (§12.2.2); lower values get a 500.
+    pub fn validate_remote_cseq(&mut self, cseq: &CSeq) -> Result<()> {
+        // ACK and CANCEL reuse the CSeq of the request they refer to.
+        if matches!(cseq.method, Method::Ack | Method::Cancel) {
+            return Ok(());
+        }
+        match self.remote_seq {
+            Some(seen) if cseq.seq <= seen => Err(VoipError::Sip {
+                code: StatusCode::SERVER_INTERNAL_ERROR.0,
+                reason: format!("CSeq {} out of order (last {})", cseq.seq, seen),
+            }),
+            _ => {
+                self.remote_seq = Some(cseq.seq);
+                Ok(())
+            }
+        }
+    }
+
+    /// Build an in-dialog request (§12.2.1.1). ACK and CANCEL reuse the current CSeq.
+    pub fn create_request(&mut self, method: Method, via: Via) -> SipRequest<'static> {
+        if !matches!(method, Method::Ack | Method::Cancel) {
+            self.local_seq += 1;
+        }
+
+        let loose = self
+            .route_set
+            .first()
+            .and_then(|r| r.parse::<NameAddr>().ok())
+            .is_none_or(|r| r.uri.param("lr").is_some());
+        let (uri, routes): (String, Vec<String>) = if loose {
+            (self.remote_target.to_string(), self.route_set.clone())
+        } else {
+            // Strict router: it goes in the Request-URI and the target becomes the last route.
+            let first = self.route_set[0]
+                .parse::<NameAddr>()
+                .map(|r| r.uri.to_string())
+                .unwrap_or_default();
+            let mut routes = self.route_set[1..].to_vec();
+            routes.push(format!("<{}>", self.remote_target));
+            (first, routes)
+        };
+
+        let mut request = SipRequest::new(method.clone(), uri)
+            .with_header("Via", via.to_string())
+            .with_header("Max-Forwards", "70");
+        for route in routes {
+            request.headers.push("Route", route);
+        }
+        request.headers.push("From", self.local.to_string());
+        request.headers.push("To", self.remote.to_string());
+        request.headers.push("Call-ID", self.id.call_id.clone());
+        request
+            .headers
+            .push("CSeq", CSeq::new(self.local_seq, method).to_string());
+        request.headers.push("Content-Length", "0");
+        request
+    }
+}
+
+/// A call: the state machine exposed as `voip.sip.CallState` plus its dialogs.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// Identifier shared with the rest of the platform.
+    pub call_id: CallId,
+    /// Inbound (we are UAS) or outbound (we are UAC).
+    pub direction: CallDirection,
+    /// Caller.
+    pub from: SipUri,
+    /// Callee.
+    pub to: SipUri,
+    /// When the call was created.
+    pub started_at: DateTime<Utc>,
+    /// When it was first answered.
+    pub answered_at: Option<DateTime<Utc>>,
+    /// When it reached a terminal state.
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Why it ended.
+    pub end_reason: Option<String>,
+    state: CallState,
+    dialogs: Vec<Dialog>,
+}
+
+impl Call {
+    /// Create a call in `STATE_INITIATING`.
+    pub fn new(call_id: CallId, direction: CallDirection, from: SipUri, to: SipUri) -> Self {
+        Self {
+            call_id,
+            direction,
+            from,
+            to,
+            started_at: Utc::now(),
+            answered_at: None,
+            ended_at: None,
+            end_reason: None,
+            state: CallState::StateInitiating,
+            dialogs: Vec::new(),
+        }
+    }
+
+    /// Current state.
+    pub fn state(&self) -> CallState {
+        self.state
+    }
+
+    /// Move to `to`, rejecting transitions `voip.sip.CallState` does not allow.
+    ///
+    /// Re-entering the current non-terminal state (a second 180) is a no-op.
+    pub fn transition(&mut self, to: CallState) -> Result<()> {
+        if to == self.state && !is_terminal(to) {
+            return Ok(());
+        }
+        if !is_valid_transition(self.state, to) {
+            return Err(VoipError::Validation(format!(
+                "call {}: invalid transition {} -> {}",
+                self.call_id,
+                self.state.as_str_name(),
+                to.as_str_name()
+            )));
+        }
+        debug!(call_id = %self.call_id, from = self.state.as_str_name(), to = to.as_str_name(), "call state");
+        self.state = to;
+        let now = Utc::now();
+        if to == CallState::StateAnswered && self.answered_at.is_none() {
+            self.answered_at = Some(now);
+        }
+        if is_terminal(to) {
+            self.ended_at = Some(now);
+            for dialog in &mut self.dialogs {
+                dialog.state = DialogState::Terminated;
+            }
+        }
+        Ok(())
+    }
+
+    /// End the call with `state` (terminated or failed) and a reason.
+    pub fn end(&mut self, state: CallState, reason: impl Into<String>) -> Result<()> {
+        self.transition(state)?;
+        self.end_reason = Some(reason.into());
+        Ok(())
+    }
+
+    /// All dialogs, early ones included.
+    pub fn dialogs(&self) -> &[Dialog] {
+        &self.dialogs
+    }
+
+    /// The confirmed dialog, or the most recent early one.
+    pub fn dialog(&self) -> Option<&Dialog> {
+        self.dialogs
+            .iter()
+            .find(|d| d.state == DialogState::Confirmed)
+            .or_else(|| {
+                self.dialogs
+                    .iter()
+                    .rev()
+                    .find(|d| d.state == DialogState::Early)
+            })
+    }
+
+    /// Dialog with a given identifier.
+    pub fn dialog_mut(&mut self, id: &DialogId) -> Option<&mut Dialog> {
+        self.dialogs.iter_mut().find(|d| &d.id == id)
+    }
+
+    /// Track a dialog created on our UAS side.
+    pub fn add_dialog(&mut self, dialog: Dialog) {
+        match self.dialogs.iter_mut().find(|d| d.id == dialog.id) {
+            Some(existing) => *existing = dialog,
+            None => self.dialogs.push(dialog),
+        }
+    }
+
+    /// Apply a response to the INVITE we sent for this call.
+    pub fn on_invite_response(
+        &mut self,
+        invite: &SipRequest<'_>,
+        response: &SipResponse<'_>,
+    ) -> Result<()> {
+        let status = response.status;
+        if status.is_provisional() {
+            if let Some(dialog) = Dialog::uac(invite, response) {
+                if !self.dialogs.iter().any(|d| d.id == dialog.id) {
+                    self.dialogs.push(dialog);
+                }
+            }
+            if status != StatusCode::TRYING {
+                self.transition(CallState::StateRinging)?;
+            }
+            return Ok(());
+        }
+
+        if status.is_success() {
+            let id = DialogId::outgoing(&response.headers);
+            match self.dialogs.iter_mut().find(|d| Some(&d.id) == id.as_ref()) {
+                Some(dialog) => dialog.confirm(response),
+                None => {
+                    if let Some(dialog) = Dialog::uac(invite, response) {
+                        self.dialogs.push(dialog);
+                    }
+                }
+            }
+            // Early dialogs from other forks die with the first 2xx.
+            for dialog in &mut self.dialogs {
+                if dialog.state == DialogState::Early {
+                    dialog.state = DialogState::Terminated;
+                }
+            }
+            return self.transition(CallState::StateAnswered);
+        }
+
+        let state = if status == StatusCode::REQUEST_TERMINATED {
+            CallState::StateTerminated
+        } else {
+            CallState::StateFailed
+        };
+        self.end(state, format!("{} {}", status, response.reason))
+    }
+
+    /// Time since answer, up to the end of the call.
+    pub fn duration(&self) -> chrono::Duration {
+        match self.answered_at {
+            Some(answered) => self.ended_at.unwrap_or_else(Utc::now) - answered,
+            None => chrono::Duration::zero(),
+        }
+    }
+
+    /// Proto view used by `ListCalls`.
+    pub fn to_proto(&self) -> proto::sip::Call {
+        let duration = self.duration();
+        proto::sip::Call {
+            call_id: Some(proto::common::CallId {
+                id: self.call_id.id.to_string(),
+                sip_call_id: self.call_id.sip_call_id.clone(),
+                correlation_id: self.call_id.correlation_id.clone(),
+            }),
+            from: Some((&self.from).into()),
+            to: Some((&self.to).into()),
+            state: self.state as i32,
+            direction: self.direction as i32,
+            started_at: Some(proto_timestamp(self.started_at)),
+            answered_at: self.answered_at.map(proto_timestamp),
+            duration: Some(prost_types::Duration {
+                seconds: duration.num_seconds(),
+                nanos: duration.subsec_nanos(),
+            }),
+            codecs: Vec::new(),
+            qos: None,
+        }
+    }
+}
+
+/// A call that was created or changed state.
+#[derive(Debug, Clone)]
+pub struct CallUpdate {
+    /// State before the change; `None` for a new call.
+    pub previous: Option<CallState>,
+    /// Snapshot after the change.
+    pub call: Call,
+}
+
+/// Live calls keyed by [`CallId`], publishing call events as they change state.
+pub struct CallManager {
+    calls: Mutex<HashMap<CallId, Call>>,
+    events: Option<Arc<dyn EventSink>>,
+    updates: broadcast::Sender<CallUpdate>,
+}
+
+impl Default for CallManager {
+    fn default() -> Self {
+        let (updates, _rx) = broadcast::channel(256);
+        Self {
+            calls: Mutex::new(HashMap::new()),
+            events: None,
+            updates,
+        }
+    }
+}
+
+impl std::fmt::Debug for CallManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallManager")
+            .field(
+                "calls",
+                &self.calls.lock().map(|c| c.len()).unwrap_or_default(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallManager {
+    /// Create an empty manager.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish `CallStartedEvent`/`CallEndedEvent` to `events`.
+    pub fn with_events(mut self, events: Arc<dyn EventSink>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Receive every new call and state change.
+    pub fn subscribe(&self) -> broadcast::Receiver<CallUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Track a new call.
+    pub fn insert(&self, call: Call) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.insert(call.call_id.clone(), call.clone());
+        }
+        // No subscribers is not an error.
+        let _ = self.updates.send(CallUpdate {
+            previous: None,
+            call,
+        });
+    }
+
+    /// Snapshot of a call.
+    pub fn get(&self, call_id: &CallId) -> Option<Call> {
+        self.calls.lock().ok()?.get(call_id).cloned()
+    }
+
+    /// Snapshot of the call carrying a SIP Call-ID.
+    pub fn find_by_sip_call_id(&self, sip_call_id: &str) -> Option<Call> {
+        self.calls
+            .lock()
+            .ok()?
+            .values()
+            .find(|c| c.call_id.sip_call_id == sip_call_id)
+            .cloned()
+    }
+
+    /// Snapshot of every call.
+    pub fn list(&self) -> Vec<Call> {
+        self.calls
+            .lock()
+            .map(|calls| calls.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stop tracking a call.
+    pub fn remove(&self, call_id: &CallId) -> Option<Call> {
+        self.calls.lock().ok()?.remove(call_id)
+    }
+
+    /// Mutate a call and publish the events its state change implies.
+    pub async fn update<R>(
+        &self,
+        call_id: &CallId,
+        f: impl FnOnce(&mut Call) -> Result<R>,
+    ) -> Result<R> {
+        let (result, changed, started, ended) = {
+            let mut calls = self
+                .calls
+                .lock()
+                .map_err(|_| VoipError::Internal("call table poisoned".into()))?;
+            let call = calls
+                .get_mut(call_id)
+                .ok_or_else(|| VoipError::NotFound(format!("call {} not found", call_id)))?;
+            let was_answered = call.answered_at.is_some();
+            let was_terminal = is_terminal(call.state);
+            let previous = call.state;
+            let result = f(call)?;
+            let changed = (call.state != previous).then(|| CallUpdate {
+                previous: Some(previous),
+                call: call.clone(),
+            });
+            let started = (!was_answered && call.answered_at.is_some()).then(|| CallStartedEvent {
+                call_id: call.call_id.to_string(),
+                correlation_id: call.call_id.correlation_id.clone(),
+                sip_call_id: call.call_id.sip_call_id.clone(),
+                direction: call.direction.as_str_name().to_string(),
+                from: call.from.to_string(),
+                to: call.to.to_string(),
+                timestamp: call.answered_at.unwrap_or_else(Utc::now),
+            });
+            let ended = (!was_terminal && is_terminal(call.state)).then(|| CallEndedEvent {
+                call_id: call.call_id.to_string(),
+                correlation_id: call.call_id.correlation_id.clone(),
+                duration: call.duration(),
+                reason: call
+                    .end_reason
+                    .clone()
+                    .unwrap_or_else(|| call.state.as_str_name().to_string()),
+                timestamp: call.ended_at.unwrap_or_else(Utc::now),
+            });
+            (result, changed, started, ended)
+        };
+
+        if let Some(update) = changed {
+            let _ = self.updates.send(update);
+        }
+        if let Some(events) = &self.events {
+            if let Some(event) = started {
+                if let Err(err) =
+                    publish_event(events.as_ref(), subjects::CALL_STARTED, &event).await
+                {
+                    warn!(error = %err, "failed to publish call started event");
+                }
+            }
+            if let Some(event) = ended {
+                if let Err(err) = publish_event(events.as_ref(), subjects::CALL_ENDED, &event).await
+                {
+                    warn!(error = %err, "failed to publish call ended event");
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Move a call to `state`.
+    pub async fn transition(&self, call_id: &CallId, state: CallState) -> Result<()> {
+        self.update(call_id, |call| call.transition(state)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voip_common::MemoryEventSink;
+
+    fn invite() -> SipRequest<'static> {
+        SipRequest::new(Method::Invite, "sip:bob@biloxi.example.com")
+            .with_header("Via", "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bKinv")
+            .with_header("From", "<sip:alice@atlanta.example.com>;tag=a1")
+            .with_header("To", "<sip:bob@biloxi.example.com>")
+            .with_header("Call-ID", "dlg-1")
+            .with_header("CSeq", "1 INVITE")
+            .with_header("Contact", "<sip:alice@192.0.2.1>")
+    }
+
+    fn response(status: StatusCode) -> SipResponse<'static> {
+        let mut resp = invite().response(status);
+        resp.headers
+            .set("To", "<sip:bob@biloxi.example.com>;tag=b1");
+        resp.headers.push("Contact", "<sip:bob@198.51.100.4:5062>");
+        resp.headers.push(
+            "Record-Route",
+            "<sip:p2.example.com;lr>, <sip:p1.example.com;lr>",
+        );
+        resp
+    }
+
+    fn call() -> Call {
+        Call::new(
+            CallId::from_sip("dlg-1".to_string()),
+            CallDirection::DirectionOutbound,
+            "sip:alice@atlanta.example.com".parse().expect("uri"),
+            "sip:bob@biloxi.example.com".parse().expect("uri"),
+        )
+    }
+
+    #[test]
+    fn transitions_follow_call_state() {
+        let mut call = call();
+        call.transition(CallState::StateRinging).expect("ringing");
+        call.transition(CallState::StateRinging)
+            .expect("second 180");
+        call.transition(CallState::StateAnswered).expect("answered");
+        call.transition(CallState::StateHeld).expect("held");
+        assert!(call.transition(CallState::StateRinging).is_err());
+        call.transition(CallState::StateAnswered).expect("resumed");
+        call.end(CallState::StateTerminated, "BYE").expect("bye");
+        assert!(call.transition(CallState::StateAnswered).is_err());
+        assert!(call.transition(CallState::StateTerminated).is_err());
+        assert!(call.answered_at.is_some() && call.ended_at.is_some());
+    }
+
+    #[test]
+    fn uac_dialog_goes_early_then_confirmed() {
+        let mut call = call();
+        let invite = invite();
+        call.on_invite_response(&invite, &response(StatusCode::RINGING))
+            .expect("180");
+        assert_eq!(call.state(), CallState::StateRinging);
+        assert_eq!(call.dialog().map(|d| d.state), Some(DialogState::Early));
+
+        call.on_invite_response(&invite, &response(StatusCode::OK))
+            .expect("200");
+        assert_eq!(call.state(), CallState::StateAnswered);
+        let id = call.dialog().expect("dialog").id.clone();
+        let dialog = call.dialog_mut(&id).expect("dialog");
+        assert_eq!(dialog.state, DialogState::Confirmed);
+        assert_eq!(
+            dialog.route_set,
+            vec!["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+
+        let bye = dialog.create_request(
+            Method::Bye,
+            Via::new("UDP", "192.0.2.1", Some(5060), "z9hG4bKbye"),
+        );
+        assert_eq!(bye.uri, "sip:bob@198.51.100.4:5062");
+        assert_eq!(
+            bye.headers.get_all("Route").collect::<Vec<_>>(),
+            vec!["<sip:p1.example.com;lr>", "<sip:p2.example.com;lr>"]
+        );
+        assert_eq!(bye.headers.cseq(), Some(CSeq::new(2, Method::Bye)));
+        assert_eq!(DialogId::outgoing(&bye.headers), Some(id));
+    }
+
+    #[test]
+    fn uas_dialog_rejects_old_cseq() {
+        let mut request = invite();
+        request
+            .headers
+            .push("Record-Route", "<sip:p1.example.com;lr>");
+        let mut dialog = Dialog::uas(&request, "b1", true).expect("dialog");
+        assert_eq!(dialog.remote_target.to_string(), "sip:alice@192.0.2.1");
+        assert_eq!(dialog.route_set, vec!["<sip:p1.example.com;lr>"]);
+
+        assert!(dialog
+            .validate_remote_cseq(&CSeq::new(2, Method::Bye))
+            .is_ok());
+        assert!(dialog
+            .validate_remote_cseq(&CSeq::new(2, Method::Ack))
+            .is_ok());
+        let err = dialog
+            .validate_remote_cseq(&CSeq::new(1, Method::Info))
+            .unwrap_err();
+        assert!(matches!(err, VoipError::Sip { code: 500, .. }));
+    }
+
+    #[tokio::test]
+    async fn manager_publishes_start_and_end() {
+        let events = MemoryEventSink::new();
+        let manager = CallManager::new().with_events(Arc::new(events.clone()));
+        let answered = call();
+        let id = answered.call_id.clone();
+        manager.insert(answered);
+        manager
+            .transition(&id, CallState::StateAnswered)
+            .await
+            .expect("answer");
+        manager
+            .update(&id, |call| {
+                call.end(CallState::StateTerminated, "remote BYE")
+            })
+            .await
+            .expect("bye");
+
+        let mut failed = call();
+        failed.call_id = CallId::new();
+        let failed_id = failed.call_id.clone();
+        manager.insert(failed);
+        let invite = invite();
+        manager
+            .update(&failed_id, |call| {
+                call.on_invite_response(&invite, &response(StatusCode::BUSY_HERE))
+            })
+            .await
+            .expect("486");
+
+        assert_eq!(
+            events.subjects(),
+            vec![
+                subjects::CALL_STARTED,
+                subjects::CALL_ENDED,
+                subjects::CALL_ENDED
+            ]
+        );
+        let ended: Vec<CallEndedEvent> = events.events(subjects::CALL_ENDED);
+        assert_eq!(ended[0].reason, "remote BYE");
+        assert_eq!(ended[1].reason, "486 Busy Here");
+        assert_eq!(
+            manager.get(&failed_id).map(|c| c.state()),
+            Some(CallState::StateFailed)
+        );
+    }
+}
@@ -0,0 +1,685 @@
+//! `voip.sip.SipService` over the registrar, the call manager, the user agent and the B2BUA.
+//!
+//! Failures are returned as `tonic::Status` through [`VoipError::to_status`].
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::Utc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use voip_common::{
+    events::{subjects, RegistrationEvent},
+    proto,
+    proto::{
+        common::{Event, EventType},
+        sip::{
+            invite_event, sip_event, sip_service_server::SipService, AnswerRequest, AnswerResponse,
+            ByeRequest, ByeResponse, CancelRequest, CancelResponse, EventFilter, HoldRequest,
+            HoldResponse, InviteAnswer, InviteEvent, InviteFailed, InviteProgress, InviteRequest,
+            InviteRinging, ListCallsRequest, ListCallsResponse, ListRegistrationsRequest,
+            ListRegistrationsResponse, RegisterRequest, RegisterResponse, Registration,
+            ResumeRequest, ResumeResponse, SipEvent, SubscribeRequest, TransferRequest,
+            TransferResponse, UnregisterRequest, UnregisterResponse,
+        },
+    },
+    sdp::SessionDescription,
+    types::{PageInfo, PageRequest},
+    CallId, Result, VoipError,
+};
+
+use crate::{
+    b2bua::B2bua,
+    dialog::{CallManager, CallState, CallUpdate},
+    proto_timestamp,
+    registrar::{Registrar, RegistrationUpdate},
+    sip::SipUri,
+    ua::{InviteUpdate, UserAgent},
+    SignallingService,
+};
+
+/// gRPC front end of the signalling service.
+#[derive(Debug, Clone)]
+pub struct SipGrpcService {
+    registrar: Arc<Registrar>,
+    calls: Arc<CallManager>,
+    ua: Arc<UserAgent>,
+    b2bua: Option<Arc<B2bua>>,
+}
+
+impl SipGrpcService {
+    /// Serve the registrar, calls, user agent and B2BUA of `service`.
+    pub fn new(service: &SignallingService) -> Self {
+        Self {
+            registrar: service.registrar().clone(),
+            calls: service.calls().clone(),
+            ua: service.ua().clone(),
+            b2bua: service.b2bua().cloned(),
+        }
+    }
+
+    /// The B2BUA when `call_id` is one of its legs rather than a call of the API.
+    fn bridged(&self, call_id: &CallId) -> Option<&Arc<B2bua>> {
+        self.b2bua
+            .as_ref()
+            .filter(|_| !self.ua.owns(&call_id.sip_call_id))
+    }
+
+    /// Hold and resume re-offer our own SDP, which bridged calls do not have.
+    fn not_bridged(&self, call_id: &CallId) -> Result<()> {
+        match self.bridged(call_id) {
+            Some(_) => Err(Status::failed_precondition(format!(
+                "call {} is bridged; its endpoints hold and resume it",
+                call_id
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Full identity of a call from whichever of `id` or `sip_call_id` the client sent.
+    fn call_id(&self, call_id: Option<&proto::common::CallId>) -> Result<CallId> {
+        let call_id = call_id.ok_or_else(|| VoipError::Validation("call_id is required".into()))?;
+        self.calls
+            .list()
+            .into_iter()
+            .map(|call| call.call_id)
+            .find(|known| {
+                (!call_id.id.is_empty() && known.id.to_string() == call_id.id)
+                    || (!call_id.sip_call_id.is_empty() && known.sip_call_id == call_id.sip_call_id)
+            })
+            .ok_or_else(|| {
+                VoipError::NotFound(format!(
+                    "call {}{} not found",
+                    call_id.id, call_id.sip_call_id
+                ))
+            })
+    }
+}
+
+type RpcResult<T> = std::result::Result<Response<T>, Status>;
+
+#[tonic::async_trait]
+impl SipService for SipGrpcService {
+    type InviteStream = UnboundedReceiverStream<std::result::Result<InviteEvent, Status>>;
+    type SubscribeStream = UnboundedReceiverStream<std::result::Result<SipEvent, Status>>;
+
+    async fn register(&self, request: Request<RegisterRequest>) -> RpcResult<RegisterResponse> {
+        let source = request
+            .remote_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let request = request.into_inner();
+        let uri = sip_uri(request.uri.as_ref(), "uri").map_err(|e| e.to_status())?;
+        let binding = self
+            .registrar
+            .register(&uri, &request.contact, request.expires, &source)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(RegisterResponse {
+            success: true,
+            registration_id: binding.id.clone(),
+            expires: binding.expires_in(Utc::now()),
+            error: None,
+        }))
+    }
+
+    async fn unregister(
+        &self,
+        request: Request<UnregisterRequest>,
+    ) -> RpcResult<UnregisterResponse> {
+        self.registrar
+            .unregister(&request.into_inner().registration_id)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(UnregisterResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn invite(&self, request: Request<InviteRequest>) -> RpcResult<Self::InviteStream> {
+        let request = request.into_inner();
+        let from = sip_uri(request.from.as_ref(), "from").map_err(|e| e.to_status())?;
+        let to = sip_uri(request.to.as_ref(), "to").map_err(|e| e.to_status())?;
+        let (call_id, mut updates) = self
+            .ua
+            .invite(
+                from,
+                to,
+                &request.sdp_offer,
+                &request.headers,
+                Some(request.correlation_id),
+            )
+            .await
+            .map_err(|e| e.to_status())?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let call_id = proto_call_id(&call_id);
+        tokio::spawn(async move {
+            while let Some(update) = updates.recv().await {
+                let event = InviteEvent {
+                    call_id: Some(call_id.clone()),
+                    timestamp: Some(proto_timestamp(Utc::now())),
+                    event: Some(invite_event(update)),
+                };
+                if tx.send(Ok(event)).is_err() {
+                    debug!("invite stream closed by client");
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    async fn answer(&self, request: Request<AnswerRequest>) -> RpcResult<AnswerResponse> {
+        let request = request.into_inner();
+        let call_id = self
+            .call_id(request.call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        self.ua
+            .answer(&call_id, &request.sdp_answer)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(AnswerResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn bye(&self, request: Request<ByeRequest>) -> RpcResult<ByeResponse> {
+        let request = request.into_inner();
+        let call_id = self
+            .call_id(request.call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        match self.bridged(&call_id) {
+            Some(b2bua) => b2bua.hang_up(&call_id.sip_call_id, &request.reason).await,
+            None => self.ua.bye(&call_id, &request.reason).await,
+        }
+        .map_err(|e| e.to_status())?;
+        Ok(Response::new(ByeResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn cancel(&self, request: Request<CancelRequest>) -> RpcResult<CancelResponse> {
+        let request = request.into_inner();
+        let call_id = self
+            .call_id(request.call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        self.ua
+            .cancel(&call_id, &request.reason)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(CancelResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn transfer(&self, request: Request<TransferRequest>) -> RpcResult<TransferResponse> {
+        let request = request.into_inner();
+        let call_id = self
+            .call_id(request.call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        let to = sip_uri(request.transfer_to.as_ref(), "transfer_to").map_err(|e| e.to_status())?;
+        match self.bridged(&call_id) {
+            Some(_) if request.attended => Err(VoipError::Validation(
+                "attended transfer is not supported".into(),
+            )),
+            Some(b2bua) => b2bua.transfer(&call_id.sip_call_id, &to).await,
+            None => self.ua.transfer(&call_id, &to, request.attended).await,
+        }
+        .map_err(|e| e.to_status())?;
+        Ok(Response::new(TransferResponse {
+            success: true,
+            new_call_id: String::new(),
+            error: None,
+        }))
+    }
+
+    async fn hold(&self, request: Request<HoldRequest>) -> RpcResult<HoldResponse> {
+        let call_id = self
+            .call_id(request.into_inner().call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        self.not_bridged(&call_id).map_err(|e| e.to_status())?;
+        self.ua.hold(&call_id).await.map_err(|e| e.to_status())?;
+        Ok(Response::new(HoldResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn resume(&self, request: Request<ResumeRequest>) -> RpcResult<ResumeResponse> {
+        let call_id = self
+            .call_id(request.into_inner().call_id.as_ref())
+            .map_err(|e| e.to_status())?;
+        self.not_bridged(&call_id).map_err(|e| e.to_status())?;
+        self.ua.resume(&call_id).await.map_err(|e| e.to_status())?;
+        Ok(Response::new(ResumeResponse {
+            success: true,
+            error: None,
+        }))
+    }
+
+    async fn list_registrations(
+        &self,
+        request: Request<ListRegistrationsRequest>,
+    ) -> RpcResult<ListRegistrationsResponse> {
+        let request = request.into_inner();
+        let page = page_request(request.page.as_ref());
+        let (bindings, info) = self
+            .registrar
+            .list(&request.filter_domain, &request.filter_user, &page)
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(Response::new(ListRegistrationsResponse {
+            registrations: bindings.iter().map(Registration::from).collect(),
+            page_info: Some(proto_page_info(&info)),
+        }))
+    }
+
+    async fn list_calls(&self, request: Request<ListCallsRequest>) -> RpcResult<ListCallsResponse> {
+        let request = request.into_inner();
+        let page = page_request(request.page.as_ref());
+        let state = CallState::try_from(request.filter_state).unwrap_or(CallState::StateUnknown);
+        let mut calls: Vec<_> = self
+            .calls
+            .list()
+            .into_iter()
+            .filter(|call| state == CallState::StateUnknown || call.state() == state)
+            .filter(|call| {
+                request.filter_user.is_empty()
+                    || [&call.from, &call.to]
+                        .iter()
+                        .any(|uri| uri.user.as_deref() == Some(request.filter_user.as_str()))
+            })
+            .collect();
+        calls.sort_by(|a, b| (a.started_at, a.call_id.id).cmp(&(b.started_at, b.call_id.id)));
+
+        let info = PageInfo::new(&page, calls.len() as u64);
+        let calls = calls
+            .iter()
+            .skip(page.offset() as usize)
+            .take(page.limit() as usize)
+            .map(|call| call.to_proto())
+            .collect();
+        Ok(Response::new(ListCallsResponse {
+            calls,
+            page_info: Some(proto_page_info(&info)),
+        }))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> RpcResult<Self::SubscribeStream> {
+        let filters = request.into_inner().filters;
+        let mut calls = self.calls.subscribe();
+        let mut registrations = self.registrar.subscribe();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    update = calls.recv() => match update {
+                        Ok(update) => call_event(&update),
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            warn!(missed, "event subscriber lagging, call events dropped");
+                            None
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    update = registrations.recv() => match update {
+                        Ok(update) => registration_event(&update),
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            warn!(missed, "event subscriber lagging, registration events dropped");
+                            None
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    () = tx.closed() => return,
+                };
+                let Some((event, users)) = event else {
+                    continue;
+                };
+                if filters.is_empty() || filters.iter().any(|f| matches(f, &event, &users)) {
+                    let _ = tx.send(Ok(event));
+                }
+            }
+        });
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+}
+
+/// Required URI field of a request.
+fn sip_uri(uri: Option<&proto::common::SipUri>, field: &str) -> Result<SipUri> {
+    uri.filter(|uri| !uri.domain.is_empty())
+        .map(SipUri::from)
+        .ok_or_else(|| VoipError::Validation(format!("{} is required", field)))
+}
+
+fn proto_call_id(call_id: &CallId) -> proto::common::CallId {
+    proto::common::CallId {
+        id: call_id.id.to_string(),
+        sip_call_id: call_id.sip_call_id.clone(),
+        correlation_id: call_id.correlation_id.clone(),
+    }
+}
+
+/// Page 1 of 20 items unless the client asked otherwise.
+fn page_request(page: Option<&proto::common::PageRequest>) -> PageRequest {
+    let Some(page) = page else {
+        return PageRequest::default();
+    };
+    PageRequest {
+        page: page.page.max(1),
+        page_size: if page.page_size == 0 {
+            20
+        } else {
+            page.page_size
+        },
+        sort_by: (!page.sort_by.is_empty()).then(|| page.sort_by.clone()),
+        descending: page.descending,
+    }
+}
+
+fn proto_page_info(info: &PageInfo) -> proto::common::PageInfo {
+    proto::common::PageInfo {
+        page: info.page,
+        page_size: info.page_size,
+        total_pages: info.total_pages,
+        total_items: info.total_items,
+        has_next: info.has_next,
+        has_previous: info.has_previous,
+    }
+}
+
+fn invite_event(update: InviteUpdate) -> invite_event::Event {
+    match update {
+        InviteUpdate::Progress { code, reason } => invite_event::Event::Progress(InviteProgress {
+            message: reason,
+            code: u32::from(code),
+        }),
+        InviteUpdate::Ringing { sdp } => {
+            invite_event::Event::Ringing(InviteRinging { sdp_answer: sdp })
+        }
+        InviteUpdate::Answered { sdp } => {
+            let codecs = SessionDescription::parse(&sdp)
+                .map(|answer| answer.audio_codecs())
+                .unwrap_or_default();
+            invite_event::Event::Answer(InviteAnswer {
+                sdp_answer: sdp,
+                codecs,
+            })
+        }
+        InviteUpdate::Failed { code, reason } => invite_event::Event::Failed(InviteFailed {
+            sip_code: u32::from(code),
+            reason,
+        }),
+    }
+}
+
+/// Event type of a call change; ringing and other intermediate states have none.
+fn call_event_type(update: &CallUpdate) -> Option<EventType> {
+    let Some(previous) = update.previous else {
+        return Some(EventType::CallStarted);
+    };
+    match update.call.state() {
+        CallState::StateAnswered
+            if matches!(
+                previous,
+                CallState::StateHeld | CallState::StateTransferring
+            ) =>
+        {
+            Some(EventType::CallResumed)
+        }
+        CallState::StateAnswered => Some(EventType::CallAnswered),
+        CallState::StateHeld => Some(EventType::CallHeld),
+        CallState::StateTransferring => Some(EventType::CallTransferred),
+        CallState::StateTerminated => Some(EventType::CallEnded),
+        CallState::StateFailed => Some(EventType::CallFailed),
+        _ => None,
+    }
+}
+
+/// A `SipEvent` and the URIs subscriber filters match against.
+type Filterable = (SipEvent, Vec<SipUri>);
+
+fn call_event(update: &CallUpdate) -> Option<Filterable> {
+    let event_type = call_event_type(update)?;
+    let call = &update.call;
+    let mut metadata = HashMap::new();
+    if let Some(reason) = &call.end_reason {
+        metadata.insert("reason".to_string(), reason.clone());
+    }
+    let event = SipEvent {
+        event: Some(Event {
+            id: Uuid::new_v4().to_string(),
+            r#type: event_type as i32,
+            timestamp: Some(proto_timestamp(Utc::now())),
+            service: "signalling".to_string(),
+            correlation_id: call.call_id.correlation_id.clone(),
+            metadata,
+            payload: Vec::new(),
+        }),
+        payload: Some(sip_event::Payload::Call(call.to_proto())),
+    };
+    Some((event, vec![call.from.clone(), call.to.clone()]))
+}
+
+fn registration_event(update: &RegistrationUpdate) -> Option<Filterable> {
+    let event_type = match update.subject {
+        subjects::REGISTRATION_SUCCESS => EventType::RegistrationSuccess,
+        subjects::REGISTRATION_FAILED => EventType::RegistrationFailed,
+        subjects::REGISTRATION_EXPIRED => EventType::RegistrationExpired,
+        _ => return None,
+    };
+    let RegistrationEvent {
+        aor,
+        contacts,
+        expires,
+        source,
+        reason,
+        timestamp,
+    } = &update.event;
+    let uri = aor.parse::<SipUri>().ok();
+    let mut metadata = HashMap::new();
+    if let Some(reason) = reason {
+        metadata.insert("reason".to_string(), reason.clone());
+    }
+    let registration = Registration {
+        id: String::new(),
+        uri: uri.as_ref().map(proto::common::SipUri::from),
+        contact: contacts.first().cloned().unwrap_or_default(),
+        registered_at: Some(proto_timestamp(*timestamp)),
+        expires_at: Some(proto_timestamp(
+            *timestamp + chrono::Duration::seconds(i64::from(*expires)),
+        )),
+        user_agent: String::new(),
+        source_ip: source.clone(),
+    };
+    let event = SipEvent {
+        event: Some(Event {
+            id: Uuid::new_v4().to_string(),
+            r#type: event_type as i32,
+            timestamp: Some(proto_timestamp(*timestamp)),
+            service: "signalling".to_string(),
+            correlation_id: String::new(),
+            metadata,
+            payload: Vec::new(),
+        }),
+        payload: Some(sip_event::Payload::Registration(registration)),
+    };
+    Some((event, uri.into_iter().collect()))
+}
+
+/// Whether an event passes a filter; empty filter fields match everything.
+fn matches(filter: &EventFilter, event: &SipEvent, uris: &[SipUri]) -> bool {
+    let event_type = event.event.as_ref().map_or(0, |e| e.r#type);
+    (filter.event_types.is_empty() || filter.event_types.contains(&event_type))
+        && (filter.user_filters.is_empty()
+            || uris.iter().any(|uri| {
+                uri.user
+                    .as_deref()
+                    .is_some_and(|user| filter.user_filters.iter().any(|f| f == user))
+            }))
+        && (filter.domain_filters.is_empty()
+            || uris.iter().any(|uri| {
+                filter
+                    .domain_filters
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case(&uri.host))
+            }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::dialog::{Call, CallDirection};
+
+    fn call(from: &str, to: &str) -> Call {
+        Call::new(
+            CallId::new(),
+            CallDirection::DirectionInbound,
+            from.parse().expect("uri"),
+            to.parse().expect("uri"),
+        )
+    }
+
+    async fn next(
+        events: &mut UnboundedReceiverStream<std::result::Result<SipEvent, Status>>,
+    ) -> (i32, proto::sip::Call) {
+        let event = time::timeout(Duration::from_secs(1), events.next())
+            .await
+            .expect("event")
+            .expect("stream")
+            .expect("status");
+        let Some(sip_event::Payload::Call(call)) = event.payload else {
+            panic!("call payload expected");
+        };
+        (event.event.expect("event").r#type, call)
+    }
+
+    #[tokio::test]
+    async fn calls_are_listed_and_streamed_to_matching_subscribers() {
+        let service = SignallingService::new();
+        let grpc = SipGrpcService::new(&service);
+        let filter = EventFilter {
+            event_types: vec![EventType::CallStarted as i32, EventType::CallHeld as i32],
+            user_filters: vec!["bob".to_string()],
+            domain_filters: Vec::new(),
+        };
+        let mut events = grpc
+            .subscribe(Request::new(SubscribeRequest {
+                filters: vec![filter],
+            }))
+            .await
+            .expect("subscribe")
+            .into_inner();
+
+        let ignored = call("sip:carol@voip.local", "sip:dave@voip.local");
+        let watched = call("sip:alice@voip.local", "sip:bob@voip.local");
+        service.calls().insert(ignored);
+        service.calls().insert(watched.clone());
+        for state in [CallState::StateAnswered, CallState::StateHeld] {
+            service
+                .calls()
+                .transition(&watched.call_id, state)
+                .await
+                .expect("transition");
+        }
+
+        let (started, call) = next(&mut events).await;
+        assert_eq!(started, EventType::CallStarted as i32);
+        assert_eq!(call.to.expect("to").user, "bob");
+        let (held, _) = next(&mut events).await;
+        assert_eq!(
+            held,
+            EventType::CallHeld as i32,
+            "CALL_ANSWERED is filtered out"
+        );
+
+        let listed = grpc
+            .list_calls(Request::new(ListCallsRequest {
+                page: None,
+                filter_state: CallState::StateHeld as i32,
+                filter_user: String::new(),
+            }))
+            .await
+            .expect("list")
+            .into_inner();
+        assert_eq!(listed.calls.len(), 1);
+        assert_eq!(listed.page_info.expect("page").total_items, 1);
+    }
+
+    #[tokio::test]
+    async fn failures_map_to_grpc_status_codes() {
+        let grpc = SipGrpcService::new(&SignallingService::new());
+        let invite = grpc
+            .invite(Request::new(InviteRequest {
+                from: Some((&"sip:agent@voip.local".parse::<SipUri>().expect("uri")).into()),
+                to: None,
+                ..InviteRequest::default()
+            }))
+            .await;
+        assert_eq!(
+            invite.err().map(|s| s.code()),
+            Some(tonic::Code::InvalidArgument)
+        );
+
+        let no_route = grpc
+            .invite(Request::new(InviteRequest {
+                from: Some((&"sip:agent@voip.local".parse::<SipUri>().expect("uri")).into()),
+                to: Some((&"sip:nobody@voip.local".parse::<SipUri>().expect("uri")).into()),
+                ..InviteRequest::default()
+            }))
+            .await;
+        assert_eq!(
+            no_route.err().map(|s| s.code()),
+            Some(tonic::Code::NotFound)
+        );
+
+        let answer = grpc
+            .answer(Request::new(AnswerRequest {
+                call_id: Some(proto::common::CallId {
+                    sip_call_id: "unknown".to_string(),
+                    ..Default::default()
+                }),
+                sdp_answer: String::new(),
+            }))
+            .await;
+        assert_eq!(answer.err().map(|s| s.code()), Some(tonic::Code::NotFound));
+
+        let registered = grpc
+            .register(Request::new(RegisterRequest {
+                uri: Some((&"sip:alice@voip.local".parse::<SipUri>().expect("uri")).into()),
+                contact: "sip:alice@192.0.2.1:5060".to_string(),
+                expires: 3600,
+                ..RegisterRequest::default()
+            }))
+            .await
+            .expect("register")
+            .into_inner();
+        assert!(registered.success);
+        let listed = grpc
+            .list_registrations(Request::new(ListRegistrationsRequest {
+                page: None,
+                filter_domain: "voip.local".to_string(),
+                filter_user: String::new(),
+            }))
+            .await
+            .expect("list")
+            .into_inner();
+        assert_eq!(listed.registrations.len(), 1);
+        assert_eq!(listed.registrations[0].id, registered.registration_id);
+    }
+}
@@ -1,30 +1,204 @@
 //! Signalling service entry points and SIP session orchestrator stubs.
 
-use std::{sync::Arc, time::Duration};
+pub mod b2bua;
+pub mod dialog;
+pub mod grpc;
+pub mod registrar;
+pub mod sip;
+pub mod transaction;
+pub mod transport;
+pub mod ua;
 
-use tokio::{sync::broadcast, task::JoinHandle, time};
-use tracing::{info, instrument};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use voip_common::Result;
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time,
+};
+use tracing::{debug, info, instrument, warn};
 
-/// Placeholder SIP event enum; will be extended with actual protocol events.
+use voip_common::{events::MediaTimeoutEvent, Result, VoipError};
+
+use crate::{
+    b2bua::{B2bua, B2buaConfig},
+    dialog::CallManager,
+    registrar::{Registrar, RegistrarConfig},
+    sip::{Method, SipMessage, StatusCode},
+    transaction::{Outbound, TimerConfig, TransactionEvent, TransactionLayer},
+    transport::{InboundMessage, LocalEndpoint, TransportConfig, TransportHandle},
+    ua::UserAgent,
+};
+
+/// Methods answered by the service, advertised in `Allow`.
+const ALLOWED_METHODS: &str = "INVITE, ACK, CANCEL, BYE, NOTIFY, OPTIONS, REGISTER";
+
+/// Events produced by the signalling loop.
 #[derive(Debug, Clone)]
 pub enum SipEvent {
     /// A heartbeat used to keep the event loop alive during early development.
     Heartbeat,
+    /// A SIP message received on one of the transports.
+    Inbound(Arc<InboundMessage>),
 }
 
 /// Asynchronous signalling service that listens for SIP events and processes them.
 #[derive(Debug)]
 pub struct SignallingService {
     shutdown_tx: broadcast::Sender<()>,
+    events_tx: broadcast::Sender<SipEvent>,
+    transport: Option<TransportHandle>,
+    transactions: TransactionLayer,
+    registrar: Arc<Registrar>,
+    calls: Arc<CallManager>,
+    b2bua_config: B2buaConfig,
+    b2bua: Option<Arc<B2bua>>,
+    ua: Arc<UserAgent>,
+    inbound_rx: Mutex<Option<mpsc::Receiver<InboundMessage>>>,
+    outbound_rx: Mutex<Option<mpsc::UnboundedReceiver<Outbound>>>,
+    tu_rx: Mutex<Option<mpsc::UnboundedReceiver<TransactionEvent>>>,
+    listeners: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl SignallingService {
-    /// Create a new signalling service instance.
+    /// Create a new signalling service instance without network listeners.
     pub fn new() -> Self {
         let (shutdown_tx, _rx) = broadcast::channel(1);
-        Self { shutdown_tx }
+        let (events_tx, _rx) = broadcast::channel(256);
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (tu_tx, tu_rx) = mpsc::unbounded_channel();
+        let transactions = TransactionLayer::new(TimerConfig::default(), outbound_tx, tu_tx);
+        let registrar = Arc::new(Registrar::in_memory(RegistrarConfig::default()));
+        let calls = Arc::new(CallManager::new());
+        let ua = Arc::new(UserAgent::new(
+            transactions.clone(),
+            registrar.clone(),
+            calls.clone(),
+            LocalEndpoint::default(),
+            None,
+        ));
+        Self {
+            shutdown_tx,
+            events_tx,
+            transport: None,
+            transactions,
+            registrar,
+            calls,
+            b2bua_config: B2buaConfig::default(),
+            b2bua: None,
+            ua,
+            inbound_rx: Mutex::new(None),
+            outbound_rx: Mutex::new(Some(outbound_rx)),
+            tu_rx: Mutex::new(Some(tu_rx)),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Create a signalling service bound to the configured UDP/TCP listeners.
+    pub async fn bind(config: &TransportConfig) -> Result<Self> {
+        let mut service = Self::new();
+        let (inbound_tx, inbound_rx) = mpsc::channel(1024);
+        let (handle, listeners) =
+            transport::bind(config, inbound_tx, service.shutdown_tx.clone()).await?;
+        service.transport = Some(handle);
+        service.inbound_rx = Mutex::new(Some(inbound_rx));
+        service.listeners = Mutex::new(listeners);
+        service.rebuild();
+        Ok(service)
+    }
+
+    /// Replace the default in-memory registrar.
+    pub fn with_registrar(mut self, registrar: Registrar) -> Self {
+        self.registrar = Arc::new(registrar);
+        self.rebuild();
+        self
+    }
+
+    /// Replace the default call manager, e.g. to publish call events.
+    pub fn with_call_manager(mut self, calls: CallManager) -> Self {
+        self.calls = Arc::new(calls);
+        self.rebuild();
+        self
+    }
+
+    /// Bridge INVITEs through a B2BUA when `config.enabled` is set.
+    ///
+    /// Its trunk and advertised host also apply to calls placed through the API.
+    pub fn with_b2bua(mut self, config: B2buaConfig) -> Self {
+        self.b2bua_config = config;
+        self.rebuild();
+        self
+    }
+
+    /// Handle for sending messages through the bound transports.
+    pub fn transport(&self) -> Option<&TransportHandle> {
+        self.transport.as_ref()
+    }
+
+    /// Transaction layer sitting between the transports and the call logic.
+    pub fn transactions(&self) -> &TransactionLayer {
+        &self.transactions
+    }
+
+    /// Registrar answering REGISTER requests.
+    pub fn registrar(&self) -> &Arc<Registrar> {
+        &self.registrar
+    }
+
+    /// Calls tracked by the service.
+    pub fn calls(&self) -> &Arc<CallManager> {
+        &self.calls
+    }
+
+    /// The B2BUA, when enabled.
+    pub fn b2bua(&self) -> Option<&Arc<B2bua>> {
+        self.b2bua.as_ref()
+    }
+
+    /// User agent placing and answering calls for the gRPC API.
+    pub fn ua(&self) -> &Arc<UserAgent> {
+        &self.ua
+    }
+
+    /// Subscribe to events emitted by the signalling loop.
+    pub fn subscribe(&self) -> broadcast::Receiver<SipEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Hang up the call whose media the relay reported as gone, with a BYE
+    /// on each of its dialogs.
+    pub async fn on_media_timeout(&self, event: &MediaTimeoutEvent) -> Result<()> {
+        let call = self
+            .calls
+            .list()
+            .into_iter()
+            .find(|call| {
+                (!event.call_id.is_empty() && call.call_id.id.to_string() == event.call_id)
+                    || (!event.sip_call_id.is_empty()
+                        && call.call_id.sip_call_id == event.sip_call_id)
+            })
+            .ok_or_else(|| {
+                VoipError::NotFound(format!(
+                    "call {}{} of media session {}",
+                    event.call_id, event.sip_call_id, event.session_id
+                ))
+            })?;
+        let reason = format!("media timeout: {} on {} leg", event.reason, event.leg);
+        warn!(
+            call_id = %call.call_id,
+            session_id = %event.session_id,
+            silent_ms = event.silent_for_ms,
+            "hanging up call without media"
+        );
+        match &self.b2bua {
+            Some(b2bua) if !self.ua.owns(&call.call_id.sip_call_id) => {
+                b2bua.hang_up(&call.call_id.sip_call_id, &reason).await
+            }
+            _ => self.ua.bye(&call.call_id, &reason).await,
+        }
     }
 
     /// Run the signalling loop until `shutdown` is called.
@@ -32,12 +206,25 @@ impl SignallingService {
     pub async fn run(&self) -> Result<()> {
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         let mut ticker = time::interval(Duration::from_secs(5));
+        let mut inbound_rx = self.inbound_rx.lock().ok().and_then(|mut rx| rx.take());
+        let mut tu_rx = self.tu_rx.lock().ok().and_then(|mut rx| rx.take());
+        let outbound_rx = self.outbound_rx.lock().ok().and_then(|mut rx| rx.take());
+        let forwarder = match (self.transport.clone(), outbound_rx) {
+            (Some(transport), Some(rx)) => Some(tokio::spawn(forward_outbound(transport, rx))),
+            _ => None,
+        };
 
         loop {
             tokio::select! {
                 _ = ticker.tick() => {
                     info!(event = ?SipEvent::Heartbeat, "signalling heartbeat");
                 }
+                Some(inbound) = next_inbound(&mut inbound_rx) => {
+                    self.handle_inbound(inbound);
+                }
+                Some(event) = next_event(&mut tu_rx) => {
+                    self.handle_transaction_event(event);
+                }
                 _ = shutdown_rx.recv() => {
                     info!("shutdown signal received");
                     break;
@@ -45,6 +232,24 @@ impl SignallingService {
             }
         }
 
+        // Graceful drain: let listeners flush, then process what they already queued.
+        let listeners = self
+            .listeners
+            .lock()
+            .map(|mut l| std::mem::take(&mut *l))
+            .unwrap_or_default();
+        for listener in listeners {
+            let _ = listener.await;
+        }
+        if let Some(rx) = inbound_rx.as_mut() {
+            while let Ok(inbound) = rx.try_recv() {
+                self.handle_inbound(inbound);
+            }
+        }
+        if let Some(forwarder) = forwarder {
+            forwarder.abort();
+        }
+
         Ok(())
     }
 
@@ -58,6 +263,161 @@ impl SignallingService {
     pub fn spawn(self: Arc<Self>) -> JoinHandle<Result<()>> {
         tokio::spawn(async move { self.run().await })
     }
+
+    /// Rebuild the B2BUA and user agent over the current transport, registrar and calls.
+    fn rebuild(&mut self) {
+        let config = &self.b2bua_config;
+        self.b2bua = config.enabled.then(|| {
+            Arc::new(B2bua::new(
+                config.clone(),
+                self.transactions.clone(),
+                self.registrar.clone(),
+                self.calls.clone(),
+                self.transport.clone(),
+            ))
+        });
+        self.ua = Arc::new(UserAgent::new(
+            self.transactions.clone(),
+            self.registrar.clone(),
+            self.calls.clone(),
+            LocalEndpoint::new(self.transport.clone(), config.advertised_host.clone()),
+            config.trunk.clone(),
+        ));
+    }
+
+    fn handle_inbound(&self, inbound: InboundMessage) {
+        match &inbound.message {
+            SipMessage::Request(req) => debug!(
+                method = %req.method,
+                source = %inbound.source.addr,
+                call_id = req.headers.call_id().unwrap_or_default(),
+                "SIP request received"
+            ),
+            SipMessage::Response(resp) => debug!(
+                status = %resp.status,
+                source = %inbound.source.addr,
+                call_id = resp.headers.call_id().unwrap_or_default(),
+                "SIP response received"
+            ),
+        }
+        // No subscribers is not an error.
+        let _ = self
+            .events_tx
+            .send(SipEvent::Inbound(Arc::new(inbound.clone())));
+        self.transactions.handle_inbound(inbound);
+    }
+
+    fn handle_transaction_event(&self, event: TransactionEvent) {
+        match event {
+            TransactionEvent::Request {
+                request,
+                source,
+                transaction: Some(transaction),
+            } if request.method == Method::Register => {
+                let registrar = self.registrar.clone();
+                tokio::spawn(async move {
+                    let response = registrar.handle_register(&request, source).await;
+                    if let Err(err) = transaction.respond(response) {
+                        debug!(error = %err, "REGISTER response dropped");
+                    }
+                });
+            }
+            TransactionEvent::Request {
+                request,
+                source,
+                transaction,
+            } if request
+                .headers
+                .call_id()
+                .is_some_and(|call_id| self.ua.owns(call_id)) =>
+            {
+                self.ua.handle_request(request, source, transaction);
+            }
+            TransactionEvent::Request {
+                request,
+                source,
+                transaction,
+            } if matches!(
+                request.method,
+                Method::Invite | Method::Ack | Method::Cancel | Method::Bye | Method::Notify
+            ) =>
+            {
+                match &self.b2bua {
+                    Some(b2bua) => b2bua.handle_request(request, source, transaction),
+                    None => self.ua.handle_request(request, source, transaction),
+                }
+            }
+            TransactionEvent::Request {
+                request,
+                transaction: Some(transaction),
+                ..
+            } => {
+                let response = match request.method {
+                    Method::Options => request
+                        .response(StatusCode::OK)
+                        .with_header("Allow", ALLOWED_METHODS),
+                    _ => request
+                        .response(StatusCode::NOT_IMPLEMENTED)
+                        .with_header("Allow", ALLOWED_METHODS),
+                };
+                if let Err(err) = transaction.respond(response) {
+                    debug!(error = %err, "response dropped");
+                }
+            }
+            TransactionEvent::Request { request, .. } => {
+                debug!(method = %request.method, "request outside transaction ignored");
+            }
+            TransactionEvent::StrayResponse { response, source } => {
+                let owned = response
+                    .headers
+                    .call_id()
+                    .is_some_and(|call_id| self.ua.owns(call_id));
+                match &self.b2bua {
+                    _ if owned => self.ua.handle_stray_response(response),
+                    Some(b2bua) => b2bua.handle_stray_response(response),
+                    None => {
+                        debug!(status = %response.status, source = %source.addr, "stray response ignored");
+                    }
+                }
+            }
+            TransactionEvent::AckTimeout { key } => {
+                debug!(branch = %key.branch, "ACK timeout");
+            }
+        }
+    }
+}
+
+/// Convert a wall-clock time for proto messages.
+pub(crate) fn proto_timestamp(at: chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: i32::try_from(at.timestamp_subsec_nanos()).unwrap_or_default(),
+    }
+}
+
+async fn next_inbound(rx: &mut Option<mpsc::Receiver<InboundMessage>>) -> Option<InboundMessage> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_event(
+    rx: &mut Option<mpsc::UnboundedReceiver<TransactionEvent>>,
+) -> Option<TransactionEvent> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Hand transaction output to the transports.
+async fn forward_outbound(transport: TransportHandle, mut rx: mpsc::UnboundedReceiver<Outbound>) {
+    while let Some(outbound) = rx.recv().await {
+        if let Err(err) = transport.send(outbound.target, &outbound.message).await {
+            warn!(error = %err, target = %outbound.target.addr, "failed to send SIP message");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -76,4 +436,45 @@ mod tests {
         let res = handle.await.expect("join handle");
         assert!(res.is_ok());
     }
+
+    #[tokio::test]
+    async fn udp_messages_are_fed_into_the_service() {
+        let config = TransportConfig {
+            udp: Some("127.0.0.1:0".parse().expect("addr")),
+            tcp: None,
+            ..TransportConfig::default()
+        };
+        let service = Arc::new(SignallingService::bind(&config).await.expect("bind"));
+        let mut events = service.subscribe();
+        let udp_addr = service
+            .transport()
+            .and_then(TransportHandle::udp_addr)
+            .expect("udp");
+        let handle = service.clone().spawn();
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("client");
+        let raw = b"OPTIONS sip:gw SIP/2.0\r\nVia: SIP/2.0/UDP 10.1.1.1:5060;branch=z9hG4bKx;rport\r\nCall-ID: u1\r\nCSeq: 1 OPTIONS\r\nContent-Length: 0\r\n\r\n";
+        client.send_to(raw, udp_addr).await.expect("send");
+
+        let inbound = loop {
+            match events.recv().await.expect("event") {
+                SipEvent::Inbound(inbound) => break inbound,
+                SipEvent::Heartbeat => continue,
+            }
+        };
+        let via = inbound.message.headers().top_via().expect("via");
+        assert_eq!(via.received(), Some("127.0.0.1"));
+        assert_eq!(via.rport(), client.local_addr().ok().map(|a| a.port()));
+
+        // The OPTIONS is answered through its server transaction.
+        let mut buf = vec![0u8; 2048];
+        let (n, _) = client.recv_from(&mut buf).await.expect("response");
+        let (response, _) = sip::parse_message(&buf[..n]).expect("parse");
+        assert!(matches!(response, SipMessage::Response(ref r) if r.status == StatusCode::OK));
+
+        service.shutdown();
+        handle.await.expect("join handle").expect("run");
+    }
 }
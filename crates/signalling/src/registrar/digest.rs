@@ -0,0 +1,393 @@
+//! SIP Digest authentication (RFC 3261 §22.4, RFC 7616, RFC 8760).
+
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use tokio::{sync::Mutex, time::Instant};
+use uuid::Uuid;
+
+use voip_common::Result;
+
+use crate::sip::ParseError;
+
+/// Digest hash algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// `MD5`, kept for legacy user agents.
+    Md5,
+    /// `SHA-256`, preferred (RFC 8760).
+    Sha256,
+}
+
+impl Algorithm {
+    /// Token used in `algorithm=` parameters.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha256 => "SHA-256",
+        }
+    }
+
+    /// Parse an `algorithm=` token; `-sess` variants are not supported.
+    pub fn from_token(token: &str) -> Option<Self> {
+        if token.eq_ignore_ascii_case("MD5") {
+            Some(Self::Md5)
+        } else if token.eq_ignore_ascii_case("SHA-256") {
+            Some(Self::Sha256)
+        } else {
+            None
+        }
+    }
+
+    /// Lower-case hex digest of `data`.
+    pub fn hash(self, data: &str) -> String {
+        let bytes: Vec<u8> = match self {
+            Self::Md5 => Md5::digest(data.as_bytes()).to_vec(),
+            Self::Sha256 => ring::digest::digest(&ring::digest::SHA256, data.as_bytes())
+                .as_ref()
+                .to_vec(),
+        };
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// `WWW-Authenticate` challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    /// Protection realm.
+    pub realm: String,
+    /// Server nonce.
+    pub nonce: String,
+    /// Hash algorithm the client must use.
+    pub algorithm: Algorithm,
+    /// Set when re-challenging after an expired nonce.
+    pub stale: bool,
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Digest realm=\"{}\", nonce=\"{}\", algorithm={}, qop=\"auth\"",
+            self.realm,
+            self.nonce,
+            self.algorithm.as_str()
+        )?;
+        if self.stale {
+            f.write_str(", stale=true")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parsed `Authorization` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// User name.
+    pub username: String,
+    /// Realm echoed from the challenge.
+    pub realm: String,
+    /// Nonce echoed from the challenge.
+    pub nonce: String,
+    /// Digest URI (the Request-URI).
+    pub uri: String,
+    /// Client response hash.
+    pub response: String,
+    /// Hash algorithm, MD5 when absent.
+    pub algorithm: Algorithm,
+    /// Client nonce, present with `qop`.
+    pub cnonce: Option<String>,
+    /// Nonce count, present with `qop`.
+    pub nc: Option<u32>,
+    /// Quality of protection; only `auth` is supported.
+    pub qop: Option<String>,
+}
+
+impl Credentials {
+    /// Response hash the client should have computed for `method` and `password`.
+    pub fn expected_response(&self, method: &str, password: &str) -> String {
+        let h = |data: &str| self.algorithm.hash(data);
+        let ha1 = h(&format!("{}:{}:{}", self.username, self.realm, password));
+        let ha2 = h(&format!("{}:{}", method, self.uri));
+        match (&self.qop, self.nc, &self.cnonce) {
+            (Some(qop), Some(nc), Some(cnonce)) => h(&format!(
+                "{}:{}:{:08x}:{}:{}:{}",
+                ha1, self.nonce, nc, cnonce, qop, ha2
+            )),
+            _ => h(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        }
+    }
+
+    /// Nonce count the response hash covers: only present with `qop`,
+    /// `nc` and `cnonce` all given, as otherwise `nc` could be changed freely.
+    pub fn nonce_count(&self) -> Option<u32> {
+        match (&self.qop, self.nc, &self.cnonce) {
+            (Some(_), Some(nc), Some(_)) => Some(nc),
+            _ => None,
+        }
+    }
+
+    /// Check the response hash in constant time.
+    pub fn verify(&self, method: &str, password: &str) -> bool {
+        let expected = self.expected_response(method, password);
+        let given = self.response.to_ascii_lowercase();
+        expected.len() == given.len()
+            && expected
+                .bytes()
+                .zip(given.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ParseError::Value {
+            kind: "Authorization",
+            value: s.to_string(),
+        };
+        let s = s.trim();
+        let (scheme, rest) = s.split_once([' ', '\t']).ok_or_else(invalid)?;
+        if !scheme.eq_ignore_ascii_case("Digest") {
+            return Err(invalid());
+        }
+
+        let params = parse_auth_params(rest);
+        let get = |name: &str| params.get(name).cloned();
+        let required = |name: &str| get(name).ok_or_else(invalid);
+        let algorithm = match get("algorithm") {
+            Some(token) => Algorithm::from_token(&token).ok_or_else(invalid)?,
+            None => Algorithm::Md5,
+        };
+        let nc = match get("nc") {
+            Some(nc) => Some(u32::from_str_radix(&nc, 16).map_err(|_| invalid())?),
+            None => None,
+        };
+
+        Ok(Self {
+            username: required("username")?,
+            realm: required("realm")?,
+            nonce: required("nonce")?,
+            uri: required("uri")?,
+            response: required("response")?,
+            algorithm,
+            cnonce: get("cnonce"),
+            nc,
+            qop: get("qop"),
+        })
+    }
+}
+
+/// Split `k=v, k="quoted, value"` auth-params, lower-casing the names.
+fn parse_auth_params(s: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let Some((name, after)) = rest.split_once('=') else {
+            break;
+        };
+        let name = name.trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match after.find(',') {
+                Some(end) => (after[..end].trim(), &after[end..]),
+                None => (after.trim(), ""),
+            },
+        };
+        params.insert(name, value.to_string());
+        rest = remainder.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+/// Outcome of checking a nonce presented by a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceCheck {
+    /// Nonce is live and the nonce count moved forward.
+    Valid,
+    /// Nonce expired, was never issued, or already served a request without
+    /// a nonce count; re-challenge with `stale=true`.
+    Stale,
+    /// Nonce count did not increase: a replayed request.
+    Replay,
+}
+
+#[derive(Debug)]
+struct NonceState {
+    issued: Instant,
+    last_nc: u32,
+    /// Whether a request without a nonce count used the nonce.
+    used: bool,
+}
+
+/// Issues nonces and tracks their lifetime and nonce counts.
+#[derive(Debug)]
+pub struct NonceManager {
+    ttl: Duration,
+    nonces: Mutex<HashMap<String, NonceState>>,
+}
+
+impl NonceManager {
+    /// Create a manager whose nonces stay valid for `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issue a fresh nonce, dropping expired ones.
+    pub async fn issue(&self) -> String {
+        let nonce = Uuid::new_v4().simple().to_string();
+        let mut nonces = self.nonces.lock().await;
+        let ttl = self.ttl;
+        nonces.retain(|_, state| state.issued.elapsed() < ttl);
+        nonces.insert(
+            nonce.clone(),
+            NonceState {
+                issued: Instant::now(),
+                last_nc: 0,
+                used: false,
+            },
+        );
+        nonce
+    }
+
+    /// Validate a nonce and its nonce count, without recording them.
+    pub async fn check(&self, nonce: &str, nc: Option<u32>) -> NonceCheck {
+        self.advance(nonce, nc, false).await
+    }
+
+    /// Record the use of a nonce once the credentials presenting it were
+    /// verified; checks again, as another request may have used it since.
+    pub async fn commit(&self, nonce: &str, nc: Option<u32>) -> NonceCheck {
+        self.advance(nonce, nc, true).await
+    }
+
+    async fn advance(&self, nonce: &str, nc: Option<u32>, record: bool) -> NonceCheck {
+        let mut nonces = self.nonces.lock().await;
+        let Some(state) = nonces.get_mut(nonce) else {
+            return NonceCheck::Stale;
+        };
+        if state.issued.elapsed() >= self.ttl {
+            nonces.remove(nonce);
+            return NonceCheck::Stale;
+        }
+        match nc {
+            Some(nc) if nc <= state.last_nc => NonceCheck::Replay,
+            Some(nc) => {
+                if record {
+                    state.last_nc = nc;
+                }
+                NonceCheck::Valid
+            }
+            // Without a nonce count nothing tells a replay from a retry, so
+            // the nonce serves one request.
+            None if state.used || state.last_nc > 0 => NonceCheck::Stale,
+            None => {
+                state.used |= record;
+                NonceCheck::Valid
+            }
+        }
+    }
+}
+
+/// Source of user passwords for digest verification.
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    /// Password of `username` in `realm`, if the user exists.
+    async fn password(&self, username: &str, realm: &str) -> Result<Option<String>>;
+}
+
+/// Fixed username/password table, loaded from configuration.
+#[derive(Debug, Clone, Default)]
+pub struct StaticCredentials {
+    users: HashMap<String, String>,
+}
+
+impl StaticCredentials {
+    /// Build from a username to password map.
+    pub fn new(users: HashMap<String, String>) -> Self {
+        Self { users }
+    }
+}
+
+#[async_trait]
+impl CredentialStore for StaticCredentials {
+    async fn password(&self, username: &str, _realm: &str) -> Result<Option<String>> {
+        Ok(self.users.get(username).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7616 §3.9.1 example.
+    const RFC7616_AUTH: &str = "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+        uri=\"/dir/index.html\", algorithm=SHA-256, \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, \
+        cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+        response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+
+    #[test]
+    fn verifies_rfc7616_vectors() {
+        let creds: Credentials = RFC7616_AUTH.parse().expect("credentials");
+        assert_eq!(creds.algorithm, Algorithm::Sha256);
+        assert_eq!(creds.nc, Some(1));
+        assert!(creds.verify("GET", "Circle of Life"));
+        assert!(!creds.verify("GET", "circle of life"));
+
+        let md5 = Credentials {
+            algorithm: Algorithm::Md5,
+            response: "8ca523f5e9506fed4657c9700eebdbec".to_string(),
+            ..creds
+        };
+        assert!(md5.verify("GET", "Circle of Life"));
+    }
+
+    #[test]
+    fn challenge_rendering() {
+        let challenge = Challenge {
+            realm: "voip.local".to_string(),
+            nonce: "abc".to_string(),
+            algorithm: Algorithm::Sha256,
+            stale: true,
+        };
+        assert_eq!(
+            challenge.to_string(),
+            "Digest realm=\"voip.local\", nonce=\"abc\", algorithm=SHA-256, qop=\"auth\", stale=true"
+        );
+        assert!("Basic dXNlcjpwYXNz".parse::<Credentials>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nonces_expire_and_reject_replays() {
+        let nonces = NonceManager::new(Duration::from_secs(30));
+        let nonce = nonces.issue().await;
+        assert_eq!(nonces.check(&nonce, Some(1)).await, NonceCheck::Valid);
+        assert_eq!(nonces.check(&nonce, Some(1)).await, NonceCheck::Valid);
+        assert_eq!(nonces.commit(&nonce, Some(1)).await, NonceCheck::Valid);
+        assert_eq!(nonces.check(&nonce, Some(1)).await, NonceCheck::Replay);
+        assert_eq!(nonces.commit(&nonce, Some(1)).await, NonceCheck::Replay);
+        assert_eq!(nonces.check(&nonce, Some(2)).await, NonceCheck::Valid);
+        assert_eq!(nonces.check("unknown", Some(1)).await, NonceCheck::Stale);
+
+        // Without a nonce count, a nonce serves a single request.
+        let once = nonces.issue().await;
+        assert_eq!(nonces.check(&once, None).await, NonceCheck::Valid);
+        assert_eq!(nonces.commit(&once, None).await, NonceCheck::Valid);
+        assert_eq!(nonces.check(&once, None).await, NonceCheck::Stale);
+
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        assert_eq!(nonces.check(&nonce, Some(3)).await, NonceCheck::Stale);
+    }
+}
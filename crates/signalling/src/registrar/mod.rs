@@ -0,0 +1,949 @@
+//! SIP registrar (RFC 3261 §10) with Digest authentication.
+//!
+//! Bindings live in a [`BindingStore`] (Redis when `redis_url` is configured,
+//! memory otherwise). Added or refreshed bindings, removed bindings and
+//! rejections are published on the event bus; binding queries are not.
+
+pub mod digest;
+pub mod store;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use voip_common::{
+    events::{publish_event, subjects, RegistrationEvent},
+    types::{PageInfo, PageRequest},
+    EventSink, Result, ServiceConfig, VoipError,
+};
+
+pub use digest::{
+    Algorithm, Challenge, CredentialStore, Credentials, NonceCheck, NonceManager, StaticCredentials,
+};
+pub use store::{Binding, BindingStore, MemoryBindingStore, RedisBindingStore};
+
+use crate::{
+    sip::{NameAddr, SipRequest, SipResponse, SipUri, StatusCode},
+    transaction::new_tag,
+    transport::{uri_target, Target},
+};
+
+/// Registrar settings, read from the `registrar` object of `ServiceConfig.extra`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RegistrarConfig {
+    /// Digest realm.
+    pub realm: String,
+    /// Shortest registration accepted; shorter requests get 423.
+    pub min_expires: u32,
+    /// Longest registration granted; longer requests are clamped.
+    pub max_expires: u32,
+    /// Expiry used when the REGISTER names none.
+    pub default_expires: u32,
+    /// Lifetime of issued nonces, in seconds.
+    pub nonce_ttl_secs: u64,
+    /// Username to password table for [`StaticCredentials`].
+    pub users: HashMap<String, String>,
+}
+
+impl Default for RegistrarConfig {
+    fn default() -> Self {
+        Self {
+            realm: "voip.local".to_string(),
+            min_expires: 60,
+            max_expires: 7200,
+            default_expires: 3600,
+            nonce_ttl_secs: 300,
+            users: HashMap::new(),
+        }
+    }
+}
+
+impl RegistrarConfig {
+    /// Read the `registrar` object of `extra`, falling back to defaults.
+    pub fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        match config.extra.get("registrar") {
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|e| VoipError::Config(format!("invalid registrar config: {}", e))),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// A refused REGISTER.
+#[derive(Debug)]
+struct Rejection {
+    status: StatusCode,
+    reason: String,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Rejection {
+    fn new(status: StatusCode, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: reason.into(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl From<VoipError> for Rejection {
+    fn from(err: VoipError) -> Self {
+        Self::new(StatusCode::SERVER_INTERNAL_ERROR, err.to_string())
+    }
+}
+
+enum AuthOutcome {
+    Authenticated(String),
+    Challenge { stale: bool },
+    Rejected(&'static str),
+}
+
+/// A registration outcome, as published on the event bus.
+#[derive(Debug, Clone)]
+pub struct RegistrationUpdate {
+    /// Subject it was published under (`subjects::REGISTRATION_*`).
+    pub subject: &'static str,
+    /// The published event.
+    pub event: RegistrationEvent,
+}
+
+/// Location service answering REGISTER requests and the registration RPCs.
+pub struct Registrar {
+    config: RegistrarConfig,
+    store: Arc<dyn BindingStore>,
+    credentials: Arc<dyn CredentialStore>,
+    nonces: NonceManager,
+    events: Option<Arc<dyn EventSink>>,
+    updates: broadcast::Sender<RegistrationUpdate>,
+}
+
+impl std::fmt::Debug for Registrar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registrar")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Registrar {
+    /// Create a registrar over explicit storage and credential backends.
+    pub fn new(
+        config: RegistrarConfig,
+        store: Arc<dyn BindingStore>,
+        credentials: Arc<dyn CredentialStore>,
+    ) -> Self {
+        let nonces = NonceManager::new(Duration::from_secs(config.nonce_ttl_secs));
+        let (updates, _rx) = broadcast::channel(256);
+        Self {
+            config,
+            store,
+            credentials,
+            nonces,
+            events: None,
+            updates,
+        }
+    }
+
+    /// Registrar with in-memory bindings and the configured static users.
+    pub fn in_memory(config: RegistrarConfig) -> Self {
+        let credentials = Arc::new(StaticCredentials::new(config.users.clone()));
+        Self::new(config, Arc::new(MemoryBindingStore::new()), credentials)
+    }
+
+    /// Build from the service config, storing bindings in Redis when `redis_url` is set.
+    pub async fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        let registrar_config = RegistrarConfig::from_service_config(config)?;
+        match &config.redis_url {
+            Some(url) => {
+                let store = RedisBindingStore::connect(url).await?;
+                info!("registrar bindings stored in redis");
+                let credentials = Arc::new(StaticCredentials::new(registrar_config.users.clone()));
+                Ok(Self::new(registrar_config, Arc::new(store), credentials))
+            }
+            None => {
+                warn!("no redis_url configured, registrar bindings kept in memory");
+                Ok(Self::in_memory(registrar_config))
+            }
+        }
+    }
+
+    /// Publish registration outcomes to `events`.
+    pub fn with_events(mut self, events: Arc<dyn EventSink>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Active configuration.
+    pub fn config(&self) -> &RegistrarConfig {
+        &self.config
+    }
+
+    /// Live bindings of an address-of-record.
+    pub async fn lookup(&self, aor: &str) -> Result<Vec<Binding>> {
+        self.store.get(aor).await
+    }
+
+    /// Receive every registration outcome, whether or not an event sink is configured.
+    pub fn subscribe(&self) -> broadcast::Receiver<RegistrationUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Where a request for `uri` goes: a registered contact first, then `trunk`.
+    ///
+    /// Returns the Request-URI to send and the next hop.
+    pub async fn route(&self, uri: &SipUri, trunk: Option<&str>) -> Result<(SipUri, Target)> {
+        if let Some(binding) = self.lookup(&uri.aor()).await?.into_iter().next() {
+            let contact: SipUri = binding.contact.parse()?;
+            let mut target = uri_target(&contact).await?;
+            // Prefer the address the REGISTER came from, which survives NAT.
+            if let Ok(addr) = binding.source.parse::<std::net::SocketAddr>() {
+                target.addr = addr;
+            }
+            return Ok((contact, target));
+        }
+        if let Some(trunk) = trunk {
+            let trunk: SipUri = trunk.parse()?;
+            let target = uri_target(&trunk).await?;
+            let mut uri = uri.clone();
+            uri.host = trunk.host;
+            uri.port = trunk.port;
+            uri.params = trunk.params;
+            return Ok((uri, target));
+        }
+        Err(VoipError::NotFound(format!("no route to {}", uri.aor())))
+    }
+
+    /// Process a REGISTER request and build the response to send.
+    pub async fn handle_register(
+        &self,
+        request: &SipRequest<'_>,
+        source: Target,
+    ) -> SipResponse<'static> {
+        let aor = request.headers.to_addr().map(|to| to.uri.aor());
+        let mut response = match self.process(request, source).await {
+            Ok(response) => response,
+            Err(rejection) => {
+                debug!(
+                    aor = aor.as_deref().unwrap_or_default(),
+                    status = %rejection.status,
+                    reason = %rejection.reason,
+                    "REGISTER rejected"
+                );
+                self.publish(
+                    subjects::REGISTRATION_FAILED,
+                    aor.clone().unwrap_or_default(),
+                    Vec::new(),
+                    0,
+                    source.addr.to_string(),
+                    Some(rejection.reason.clone()),
+                )
+                .await;
+                let mut response = request.response(rejection.status);
+                for (name, value) in rejection.headers {
+                    response.headers.push(name, value);
+                }
+                response
+            }
+        };
+
+        if let Some(mut to) = response.headers.to_addr() {
+            if to.tag().is_none() {
+                to.set_param("tag", Some(&new_tag()));
+                response.headers.set("To", to.to_string());
+            }
+        }
+        response
+    }
+
+    /// `Register` RPC: bind `contact` to the AOR of `uri` without a SIP exchange.
+    pub async fn register(
+        &self,
+        uri: &SipUri,
+        contact: &str,
+        expires: u32,
+        source: &str,
+    ) -> Result<Binding> {
+        let contact: SipUri = contact
+            .parse()
+            .map_err(|e| VoipError::Validation(format!("invalid contact: {}", e)))?;
+        let expires = match expires {
+            0 => self.config.default_expires,
+            e if e < self.config.min_expires => {
+                return Err(VoipError::Validation(format!(
+                    "expires must be at least {}",
+                    self.config.min_expires
+                )))
+            }
+            e => e.min(self.config.max_expires),
+        };
+
+        let aor = uri.aor();
+        let contact = contact.to_string();
+        let existing = self
+            .store
+            .get(&aor)
+            .await?
+            .into_iter()
+            .find(|b| b.contact == contact);
+        let now = Utc::now();
+        let binding = Binding {
+            id: existing
+                .as_ref()
+                .map_or_else(|| Uuid::new_v4().to_string(), |b| b.id.clone()),
+            aor: aor.clone(),
+            contact: contact.clone(),
+            registered_at: existing.as_ref().map_or(now, |b| b.registered_at),
+            expires_at: now + chrono::Duration::seconds(i64::from(expires)),
+            call_id: format!("api-{}", Uuid::new_v4().simple()),
+            cseq: 1,
+            user_agent: None,
+            source: source.to_string(),
+        };
+        self.store.upsert(binding.clone()).await?;
+        self.publish(
+            subjects::REGISTRATION_SUCCESS,
+            aor,
+            vec![contact],
+            expires,
+            source.to_string(),
+            None,
+        )
+        .await;
+        Ok(binding)
+    }
+
+    /// `Unregister` RPC: drop a binding by registration id.
+    pub async fn unregister(&self, registration_id: &str) -> Result<Binding> {
+        let binding = self
+            .store
+            .remove_by_id(registration_id)
+            .await?
+            .ok_or_else(|| {
+                VoipError::NotFound(format!("registration {} not found", registration_id))
+            })?;
+        self.publish(
+            subjects::REGISTRATION_EXPIRED,
+            binding.aor.clone(),
+            vec![binding.contact.clone()],
+            0,
+            binding.source.clone(),
+            Some("unregistered".to_string()),
+        )
+        .await;
+        Ok(binding)
+    }
+
+    /// `ListRegistrations` RPC: live bindings filtered by domain and user, paginated.
+    pub async fn list(
+        &self,
+        filter_domain: &str,
+        filter_user: &str,
+        page: &PageRequest,
+    ) -> Result<(Vec<Binding>, PageInfo)> {
+        let mut bindings: Vec<Binding> = self
+            .store
+            .all()
+            .await?
+            .into_iter()
+            .filter(|b| {
+                let Ok(uri) = b.aor.parse::<SipUri>() else {
+                    return false;
+                };
+                (filter_domain.is_empty() || uri.host.eq_ignore_ascii_case(filter_domain))
+                    && (filter_user.is_empty() || uri.user.as_deref() == Some(filter_user))
+            })
+            .collect();
+        bindings.sort_by(|a, b| (&a.aor, &a.contact).cmp(&(&b.aor, &b.contact)));
+
+        let page = PageRequest {
+            page: page.page.max(1),
+            page_size: if page.page_size == 0 {
+                20
+            } else {
+                page.page_size
+            },
+            ..page.clone()
+        };
+        let info = PageInfo::new(&page, bindings.len() as u64);
+        let items = bindings
+            .into_iter()
+            .skip(page.offset() as usize)
+            .take(page.limit() as usize)
+            .collect();
+        Ok((items, info))
+    }
+
+    async fn process(
+        &self,
+        request: &SipRequest<'_>,
+        source: Target,
+    ) -> std::result::Result<SipResponse<'static>, Rejection> {
+        let to = request
+            .headers
+            .to_addr()
+            .ok_or_else(|| Rejection::new(StatusCode::BAD_REQUEST, "missing To header"))?;
+        let aor = to.uri.aor();
+
+        match self.authenticate(request).await? {
+            AuthOutcome::Authenticated(username) => {
+                let user = to.uri.user.as_deref().and_then(|u| u.split(':').next());
+                if user != Some(username.as_str()) {
+                    return Err(Rejection::new(
+                        StatusCode::FORBIDDEN,
+                        "username does not match address-of-record",
+                    ));
+                }
+            }
+            AuthOutcome::Challenge { stale } => return Ok(self.challenge(request, stale).await),
+            AuthOutcome::Rejected(reason) => {
+                return Err(Rejection::new(StatusCode::FORBIDDEN, reason))
+            }
+        }
+
+        let call_id = request
+            .headers
+            .call_id()
+            .ok_or_else(|| Rejection::new(StatusCode::BAD_REQUEST, "missing Call-ID"))?;
+        let cseq = request
+            .headers
+            .cseq()
+            .ok_or_else(|| Rejection::new(StatusCode::BAD_REQUEST, "missing CSeq"))?
+            .seq;
+        let header_expires = request
+            .headers
+            .get("Expires")
+            .and_then(|v| v.trim().parse::<u32>().ok());
+        let contacts: Vec<&str> = request.headers.values("Contact").map(str::trim).collect();
+        let existing = self.store.get(&aor).await?;
+        // Without Contact headers the request only asks for the bindings.
+        let mut refreshed = false;
+        let mut removed = Vec::new();
+
+        if contacts.contains(&"*") {
+            if contacts.len() != 1 || header_expires != Some(0) {
+                return Err(Rejection::new(
+                    StatusCode::BAD_REQUEST,
+                    "wildcard Contact requires Expires: 0 and no other contacts",
+                ));
+            }
+            for binding in &existing {
+                self.store.remove(&aor, &binding.contact).await?;
+                removed.push(binding.contact.clone());
+            }
+        } else {
+            let mut updates = Vec::with_capacity(contacts.len());
+            for contact in contacts {
+                let contact: NameAddr = contact.parse().map_err(|_| {
+                    Rejection::new(StatusCode::BAD_REQUEST, "invalid Contact header")
+                })?;
+                let expires = contact
+                    .param("expires")
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .or(header_expires)
+                    .unwrap_or(self.config.default_expires);
+                if expires != 0 && expires < self.config.min_expires {
+                    let mut rejection =
+                        Rejection::new(StatusCode::INTERVAL_TOO_BRIEF, "registration too brief");
+                    rejection
+                        .headers
+                        .push(("Min-Expires", self.config.min_expires.to_string()));
+                    return Err(rejection);
+                }
+                let uri = contact.uri.to_string();
+                let previous = existing.iter().find(|b| b.contact == uri);
+                if previous.is_some_and(|b| b.call_id == call_id && b.cseq >= cseq) {
+                    return Err(Rejection::new(
+                        StatusCode::SERVER_INTERNAL_ERROR,
+                        "out-of-order REGISTER",
+                    ));
+                }
+                updates.push((uri, expires.min(self.config.max_expires), previous));
+            }
+
+            let now = Utc::now();
+            for (contact, expires, previous) in updates {
+                if expires == 0 {
+                    self.store.remove(&aor, &contact).await?;
+                    if previous.is_some() {
+                        removed.push(contact);
+                    }
+                    continue;
+                }
+                refreshed = true;
+                self.store
+                    .upsert(Binding {
+                        id: previous.map_or_else(|| Uuid::new_v4().to_string(), |b| b.id.clone()),
+                        aor: aor.clone(),
+                        contact,
+                        registered_at: previous.map_or(now, |b| b.registered_at),
+                        expires_at: now + chrono::Duration::seconds(i64::from(expires)),
+                        call_id: call_id.to_string(),
+                        cseq,
+                        user_agent: request.headers.get("User-Agent").map(str::to_string),
+                        source: source.addr.to_string(),
+                    })
+                    .await?;
+            }
+        }
+
+        let bindings = self.store.get(&aor).await?;
+        let now = Utc::now();
+        let mut response = request.response(StatusCode::OK);
+        for binding in &bindings {
+            response.headers.push(
+                "Contact",
+                format!("<{}>;expires={}", binding.contact, binding.expires_in(now)),
+            );
+        }
+        if !refreshed && removed.is_empty() {
+            return Ok(response);
+        }
+        info!(aor = %aor, contacts = bindings.len(), "registration updated");
+        if refreshed {
+            self.publish(
+                subjects::REGISTRATION_SUCCESS,
+                aor.clone(),
+                bindings.iter().map(|b| b.contact.clone()).collect(),
+                bindings
+                    .iter()
+                    .map(|b| b.expires_in(now))
+                    .max()
+                    .unwrap_or_default(),
+                source.addr.to_string(),
+                None,
+            )
+            .await;
+        }
+        if !removed.is_empty() {
+            self.publish(
+                subjects::REGISTRATION_EXPIRED,
+                aor,
+                removed,
+                0,
+                source.addr.to_string(),
+                Some("unregistered".to_string()),
+            )
+            .await;
+        }
+        Ok(response)
+    }
+
+    async fn authenticate(&self, request: &SipRequest<'_>) -> Result<AuthOutcome> {
+        let credentials = request
+            .headers
+            .get_all("Authorization")
+            .filter_map(|value| value.parse::<Credentials>().ok())
+            .find(|c| c.realm == self.config.realm);
+        let Some(credentials) = credentials else {
+            return Ok(AuthOutcome::Challenge { stale: false });
+        };
+
+        if !same_uri(&credentials.uri, &request.uri) {
+            return Ok(AuthOutcome::Rejected(
+                "digest uri does not match the request",
+            ));
+        }
+        let nc = credentials.nonce_count();
+        if let Some(outcome) = nonce_outcome(self.nonces.check(&credentials.nonce, nc).await) {
+            return Ok(outcome);
+        }
+        if credentials.qop.as_deref().is_some_and(|qop| qop != "auth") {
+            return Ok(AuthOutcome::Rejected("unsupported qop"));
+        }
+
+        let password = self
+            .credentials
+            .password(&credentials.username, &credentials.realm)
+            .await?;
+        match password {
+            Some(password) if credentials.verify(request.method.as_str(), &password) => {
+                // Only a verified request moves the nonce count forward.
+                let committed = self.nonces.commit(&credentials.nonce, nc).await;
+                Ok(nonce_outcome(committed)
+                    .unwrap_or(AuthOutcome::Authenticated(credentials.username)))
+            }
+            Some(_) => Ok(AuthOutcome::Rejected("invalid credentials")),
+            None => Ok(AuthOutcome::Rejected("unknown user")),
+        }
+    }
+
+    /// 401 offering SHA-256 first and MD5 for older clients (RFC 8760 §2.4).
+    async fn challenge(&self, request: &SipRequest<'_>, stale: bool) -> SipResponse<'static> {
+        let nonce = self.nonces.issue().await;
+        let mut response = request.response(StatusCode::UNAUTHORIZED);
+        for algorithm in [Algorithm::Sha256, Algorithm::Md5] {
+            let challenge = Challenge {
+                realm: self.config.realm.clone(),
+                nonce: nonce.clone(),
+                algorithm,
+                stale,
+            };
+            response
+                .headers
+                .push("WWW-Authenticate", challenge.to_string());
+        }
+        response
+    }
+
+    async fn publish(
+        &self,
+        subject: &'static str,
+        aor: String,
+        contacts: Vec<String>,
+        expires: u32,
+        source: String,
+        reason: Option<String>,
+    ) {
+        let event = RegistrationEvent {
+            aor,
+            contacts,
+            expires,
+            source,
+            reason,
+            timestamp: Utc::now(),
+        };
+        // No subscribers is not an error.
+        let _ = self.updates.send(RegistrationUpdate {
+            subject,
+            event: event.clone(),
+        });
+        let Some(events) = &self.events else {
+            return;
+        };
+        if let Err(err) = publish_event(events.as_ref(), subject, &event).await {
+            warn!(error = %err, subject, "failed to publish registration event");
+        }
+    }
+}
+
+/// What to answer credentials whose nonce did not check out.
+fn nonce_outcome(check: NonceCheck) -> Option<AuthOutcome> {
+    match check {
+        NonceCheck::Valid => None,
+        NonceCheck::Stale => Some(AuthOutcome::Challenge { stale: true }),
+        NonceCheck::Replay => Some(AuthOutcome::Rejected("nonce count replayed")),
+    }
+}
+
+/// Whether the digest `uri` names the Request-URI, comparing parsed URIs.
+fn same_uri(digest_uri: &str, request_uri: &str) -> bool {
+    match (digest_uri.parse::<SipUri>(), request_uri.parse::<SipUri>()) {
+        (Ok(digest), Ok(request)) => digest == request,
+        _ => digest_uri == request_uri,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sip::Method, transport::TransportKind};
+    use voip_common::MemoryEventSink;
+
+    fn source() -> Target {
+        Target {
+            transport: TransportKind::Udp,
+            addr: "192.0.2.7:5060".parse().expect("addr"),
+        }
+    }
+
+    fn registrar() -> (Registrar, MemoryEventSink) {
+        let config = RegistrarConfig {
+            users: HashMap::from([("alice".to_string(), "secret".to_string())]),
+            ..RegistrarConfig::default()
+        };
+        let events = MemoryEventSink::new();
+        let registrar = Registrar::in_memory(config).with_events(Arc::new(events.clone()));
+        (registrar, events)
+    }
+
+    fn register(cseq: u32, contacts: &[&str], expires: Option<u32>) -> SipRequest<'static> {
+        let mut req = SipRequest::new(Method::Register, "sip:voip.local")
+            .with_header("Via", "SIP/2.0/UDP 192.0.2.7:5060;branch=z9hG4bKreg")
+            .with_header("From", "<sip:alice@voip.local>;tag=r1")
+            .with_header("To", "<sip:alice@voip.local>")
+            .with_header("Call-ID", "reg-call")
+            .with_header("CSeq", format!("{} REGISTER", cseq));
+        for contact in contacts {
+            req.headers.push("Contact", contact.to_string());
+        }
+        if let Some(expires) = expires {
+            req.headers.push("Expires", expires.to_string());
+        }
+        req
+    }
+
+    /// Nonce of the challenge of `response` for `algorithm`.
+    fn nonce(response: &SipResponse<'_>, algorithm: Algorithm) -> String {
+        let challenge = response
+            .headers
+            .get_all("WWW-Authenticate")
+            .find(|c| c.contains(&format!("algorithm={}", algorithm.as_str())))
+            .expect("challenge");
+        challenge
+            .split("nonce=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .expect("nonce")
+            .to_string()
+    }
+
+    /// Answer the first challenge of `response` with `algorithm` and `password`.
+    fn authorize(
+        req: &mut SipRequest<'static>,
+        response: &SipResponse<'_>,
+        algorithm: Algorithm,
+        password: &str,
+    ) {
+        let nonce = nonce(response, algorithm);
+        let mut credentials = Credentials {
+            username: "alice".to_string(),
+            realm: "voip.local".to_string(),
+            nonce,
+            uri: req.uri.to_string(),
+            response: String::new(),
+            algorithm,
+            cnonce: Some("c0ffee".to_string()),
+            nc: Some(1),
+            qop: Some("auth".to_string()),
+        };
+        credentials.response = credentials.expected_response("REGISTER", password);
+        req.headers.set(
+            "Authorization",
+            format!(
+                "Digest username=\"alice\", realm=\"voip.local\", nonce=\"{}\", uri=\"{}\", \
+                 response=\"{}\", algorithm={}, cnonce=\"c0ffee\", nc=00000001, qop=auth",
+                credentials.nonce,
+                credentials.uri,
+                credentials.response,
+                algorithm.as_str()
+            ),
+        );
+    }
+
+    /// Answer of `registrar` to `req`, authorized after its challenge.
+    async fn signed(registrar: &Registrar, mut req: SipRequest<'static>) -> SipResponse<'static> {
+        let challenge = registrar.handle_register(&req, source()).await;
+        authorize(&mut req, &challenge, Algorithm::Sha256, "secret");
+        registrar.handle_register(&req, source()).await
+    }
+
+    #[tokio::test]
+    async fn challenge_then_register_two_contacts() {
+        let (registrar, events) = registrar();
+        let mut req = register(
+            1,
+            &[
+                "<sip:alice@192.0.2.7:5060>",
+                "<sip:alice@198.51.100.2>;expires=120",
+            ],
+            Some(9999),
+        );
+
+        let challenge = registrar.handle_register(&req, source()).await;
+        assert_eq!(challenge.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(challenge.headers.get_all("WWW-Authenticate").count(), 2);
+        assert!(events.subjects().is_empty());
+
+        authorize(&mut req, &challenge, Algorithm::Sha256, "secret");
+        let ok = registrar.handle_register(&req, source()).await;
+        assert_eq!(ok.status, StatusCode::OK);
+        assert_eq!(ok.headers.values("Contact").count(), 2);
+        assert!(ok
+            .headers
+            .to_addr()
+            .and_then(|t| t.tag().map(str::to_string))
+            .is_some());
+
+        let bindings = registrar
+            .lookup("sip:alice@voip.local")
+            .await
+            .expect("lookup");
+        let expiries: Vec<u32> = {
+            let now = Utc::now();
+            let mut e: Vec<u32> = bindings.iter().map(|b| b.expires_in(now)).collect();
+            e.sort_unstable();
+            e
+        };
+        // Contact param wins over Expires; Expires is clamped to max_expires.
+        assert!(expiries[0] <= 120 && expiries[0] >= 118);
+        assert!(expiries[1] <= 7200 && expiries[1] >= 7198);
+
+        let published: Vec<RegistrationEvent> = events.events(subjects::REGISTRATION_SUCCESS);
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].contacts.len(), 2);
+
+        // Replaying the same nonce count is refused.
+        let replay = registrar.handle_register(&req, source()).await;
+        assert_eq!(replay.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejections_publish_failures() {
+        let (registrar, events) = registrar();
+
+        let mut wrong = register(1, &["<sip:alice@192.0.2.7>"], None);
+        let challenge = registrar.handle_register(&wrong, source()).await;
+        authorize(&mut wrong, &challenge, Algorithm::Md5, "guess");
+        let denied = registrar.handle_register(&wrong, source()).await;
+        assert_eq!(denied.status, StatusCode::FORBIDDEN);
+
+        let mut brief = register(2, &["<sip:alice@192.0.2.7>"], Some(10));
+        let challenge = registrar.handle_register(&brief, source()).await;
+        authorize(&mut brief, &challenge, Algorithm::Md5, "secret");
+        let too_brief = registrar.handle_register(&brief, source()).await;
+        assert_eq!(too_brief.status, StatusCode::INTERVAL_TOO_BRIEF);
+        assert_eq!(too_brief.headers.get("Min-Expires"), Some("60"));
+
+        let failures: Vec<RegistrationEvent> = events.events(subjects::REGISTRATION_FAILED);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].reason.as_deref(), Some("invalid credentials"));
+    }
+
+    #[tokio::test]
+    async fn wildcard_removes_all_and_stale_nonce_rechallenges() {
+        let (registrar, _events) = registrar();
+        let mut req = register(1, &["<sip:alice@192.0.2.7>"], Some(300));
+        let challenge = registrar.handle_register(&req, source()).await;
+        authorize(&mut req, &challenge, Algorithm::Sha256, "secret");
+        assert_eq!(
+            registrar.handle_register(&req, source()).await.status,
+            StatusCode::OK
+        );
+
+        let mut clear = register(2, &["*"], Some(0));
+        clear
+            .headers
+            .set("Authorization", "Digest username=\"alice\", realm=\"voip.local\", nonce=\"gone\", uri=\"sip:voip.local\", response=\"00\"");
+        let stale = registrar.handle_register(&clear, source()).await;
+        assert_eq!(stale.status, StatusCode::UNAUTHORIZED);
+        assert!(stale
+            .headers
+            .get("WWW-Authenticate")
+            .is_some_and(|c| c.contains("stale=true")));
+
+        authorize(&mut clear, &stale, Algorithm::Sha256, "secret");
+        assert_eq!(
+            registrar.handle_register(&clear, source()).await.status,
+            StatusCode::OK
+        );
+        assert!(registrar
+            .lookup("sip:alice@voip.local")
+            .await
+            .expect("lookup")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn queries_publish_nothing_and_removals_publish_expiry() {
+        let (registrar, events) = registrar();
+        let contacts = ["<sip:alice@192.0.2.7>", "<sip:alice@192.0.2.8>"];
+        let bound = signed(&registrar, register(1, &contacts, Some(300))).await;
+        assert_eq!(bound.status, StatusCode::OK);
+
+        let query = signed(&registrar, register(2, &[], None)).await;
+        assert_eq!(query.status, StatusCode::OK);
+        assert_eq!(query.headers.values("Contact").count(), 2);
+        assert_eq!(events.subjects(), [subjects::REGISTRATION_SUCCESS]);
+
+        let unbound = signed(&registrar, register(3, &[contacts[1]], Some(0))).await;
+        assert_eq!(unbound.headers.values("Contact").count(), 1);
+        let expired: Vec<RegistrationEvent> = events.events(subjects::REGISTRATION_EXPIRED);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].contacts, ["sip:alice@192.0.2.8"]);
+        assert_eq!(expired[0].reason.as_deref(), Some("unregistered"));
+        assert_eq!(
+            events
+                .events::<RegistrationEvent>(subjects::REGISTRATION_SUCCESS)
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn digest_must_name_the_request_and_serves_once_without_qop() {
+        let (registrar, _events) = registrar();
+        let mut req = register(1, &["<sip:alice@192.0.2.7>"], Some(300));
+        let challenge = registrar.handle_register(&req, source()).await;
+        let nonce = nonce(&challenge, Algorithm::Md5);
+        let mut answer = |uri: &str| {
+            let credentials = Credentials {
+                username: "alice".to_string(),
+                realm: "voip.local".to_string(),
+                nonce: nonce.clone(),
+                uri: uri.to_string(),
+                response: String::new(),
+                algorithm: Algorithm::Md5,
+                cnonce: None,
+                nc: None,
+                qop: None,
+            };
+            req.headers.set(
+                "Authorization",
+                format!(
+                    "Digest username=\"alice\", realm=\"voip.local\", nonce=\"{}\", \
+                     uri=\"{}\", response=\"{}\"",
+                    nonce,
+                    uri,
+                    credentials.expected_response("REGISTER", "secret")
+                ),
+            );
+            req.clone()
+        };
+
+        // Valid for another Request-URI only.
+        let elsewhere = answer("sip:other.example");
+        assert_eq!(
+            registrar.handle_register(&elsewhere, source()).await.status,
+            StatusCode::FORBIDDEN
+        );
+
+        let request = answer("sip:voip.local");
+        assert_eq!(
+            registrar.handle_register(&request, source()).await.status,
+            StatusCode::OK
+        );
+        // Sniffed and sent again: re-challenged rather than accepted.
+        let replay = registrar.handle_register(&request, source()).await;
+        assert_eq!(replay.status, StatusCode::UNAUTHORIZED);
+        assert!(replay
+            .headers
+            .get("WWW-Authenticate")
+            .is_some_and(|c| c.contains("stale=true")));
+    }
+
+    #[tokio::test]
+    async fn api_register_list_unregister() {
+        let (registrar, events) = registrar();
+        let uri: SipUri = "sip:bob@voip.local".parse().expect("uri");
+        let binding = registrar
+            .register(&uri, "sip:bob@203.0.113.9:5070", 0, "api")
+            .await
+            .expect("register");
+        assert!(registrar
+            .register(&uri, "sip:bob@203.0.113.9", 5, "api")
+            .await
+            .is_err());
+
+        let (listed, page) = registrar
+            .list("voip.local", "bob", &PageRequest::default())
+            .await
+            .expect("list");
+        assert_eq!(listed, vec![binding.clone()]);
+        assert_eq!(page.total_items, 1);
+
+        registrar.unregister(&binding.id).await.expect("unregister");
+        let expired: Vec<RegistrationEvent> = events.events(subjects::REGISTRATION_EXPIRED);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].contacts, [binding.contact.as_str()]);
+        assert!(matches!(
+            registrar.unregister(&binding.id).await,
+            Err(VoipError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,358 @@
+//! Location service storage for registrar bindings.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+
+use voip_common::{proto, Result, VoipError};
+
+use crate::{proto_timestamp, sip::SipUri};
+
+/// A Contact bound to an address-of-record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    /// Registration identifier exposed through the gRPC API.
+    pub id: String,
+    /// Address-of-record (`sip:user@domain`).
+    pub aor: String,
+    /// Contact URI.
+    pub contact: String,
+    /// When the binding was first created.
+    pub registered_at: DateTime<Utc>,
+    /// When the binding lapses unless refreshed.
+    pub expires_at: DateTime<Utc>,
+    /// Call-ID of the REGISTER that last refreshed it.
+    pub call_id: String,
+    /// CSeq of the REGISTER that last refreshed it.
+    pub cseq: u32,
+    /// User-Agent header of the registering client.
+    pub user_agent: Option<String>,
+    /// Network source (`ip:port`) of the REGISTER.
+    pub source: String,
+}
+
+impl Binding {
+    /// Seconds left before expiry.
+    pub fn expires_in(&self, now: DateTime<Utc>) -> u32 {
+        u32::try_from((self.expires_at - now).num_seconds().max(0)).unwrap_or(u32::MAX)
+    }
+
+    /// Whether the binding has lapsed.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+impl From<&Binding> for proto::sip::Registration {
+    fn from(binding: &Binding) -> Self {
+        Self {
+            id: binding.id.clone(),
+            uri: binding
+                .aor
+                .parse::<SipUri>()
+                .ok()
+                .map(|uri| proto::common::SipUri::from(&uri)),
+            contact: binding.contact.clone(),
+            registered_at: Some(proto_timestamp(binding.registered_at)),
+            expires_at: Some(proto_timestamp(binding.expires_at)),
+            user_agent: binding.user_agent.clone().unwrap_or_default(),
+            source_ip: binding.source.clone(),
+        }
+    }
+}
+
+/// Storage backend for bindings. Reads never return expired bindings.
+#[async_trait]
+pub trait BindingStore: Send + Sync {
+    /// Live bindings of an AOR.
+    async fn get(&self, aor: &str) -> Result<Vec<Binding>>;
+
+    /// Insert a binding, replacing any binding of the same AOR and contact.
+    async fn upsert(&self, binding: Binding) -> Result<()>;
+
+    /// Remove one contact of an AOR.
+    async fn remove(&self, aor: &str, contact: &str) -> Result<()>;
+
+    /// Remove a binding by registration identifier.
+    async fn remove_by_id(&self, id: &str) -> Result<Option<Binding>>;
+
+    /// Every live binding.
+    async fn all(&self) -> Result<Vec<Binding>>;
+}
+
+/// Process-local store used when no Redis is configured, and in tests.
+#[derive(Debug, Default)]
+pub struct MemoryBindingStore {
+    aors: Mutex<HashMap<String, Vec<Binding>>>,
+}
+
+impl MemoryBindingStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Vec<Binding>>>> {
+        self.aors
+            .lock()
+            .map_err(|_| VoipError::Internal("binding store poisoned".into()))
+    }
+}
+
+#[async_trait]
+impl BindingStore for MemoryBindingStore {
+    async fn get(&self, aor: &str) -> Result<Vec<Binding>> {
+        let now = Utc::now();
+        let mut aors = self.lock()?;
+        let Some(bindings) = aors.get_mut(aor) else {
+            return Ok(Vec::new());
+        };
+        bindings.retain(|b| !b.is_expired(now));
+        Ok(bindings.clone())
+    }
+
+    async fn upsert(&self, binding: Binding) -> Result<()> {
+        let mut aors = self.lock()?;
+        let bindings = aors.entry(binding.aor.clone()).or_default();
+        match bindings.iter_mut().find(|b| b.contact == binding.contact) {
+            Some(existing) => *existing = binding,
+            None => bindings.push(binding),
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, aor: &str, contact: &str) -> Result<()> {
+        let mut aors = self.lock()?;
+        if let Some(bindings) = aors.get_mut(aor) {
+            bindings.retain(|b| b.contact != contact);
+            if bindings.is_empty() {
+                aors.remove(aor);
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_by_id(&self, id: &str) -> Result<Option<Binding>> {
+        let mut aors = self.lock()?;
+        let mut removed = None;
+        // Also drops AORs left empty, e.g. by `get` hiding expired bindings.
+        aors.retain(|_, bindings| {
+            if removed.is_none() {
+                if let Some(pos) = bindings.iter().position(|b| b.id == id) {
+                    removed = Some(bindings.remove(pos));
+                }
+            }
+            !bindings.is_empty()
+        });
+        Ok(removed)
+    }
+
+    async fn all(&self) -> Result<Vec<Binding>> {
+        let now = Utc::now();
+        let aors = self.lock()?;
+        Ok(aors
+            .values()
+            .flatten()
+            .filter(|b| !b.is_expired(now))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Redis-backed store.
+///
+/// Layout: `{prefix}:aor:{aor}` is a hash of contact to JSON binding, expiring
+/// with its longest-lived binding; `{prefix}:aors` indexes AORs and
+/// `{prefix}:id:{id}` holds the AOR of a registration id, expiring with the
+/// binding so that lapsed registrations leave nothing behind.
+#[derive(Clone)]
+pub struct RedisBindingStore {
+    conn: ConnectionManager,
+    prefix: String,
+}
+
+impl std::fmt::Debug for RedisBindingStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisBindingStore")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl RedisBindingStore {
+    /// Connect to `url` and namespace keys under `voip:registrar`.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self {
+            conn,
+            prefix: "voip:registrar".to_string(),
+        })
+    }
+
+    fn aor_key(&self, aor: &str) -> String {
+        format!("{}:aor:{}", self.prefix, aor)
+    }
+
+    fn aors_key(&self) -> String {
+        format!("{}:aors", self.prefix)
+    }
+
+    fn id_key(&self, id: &str) -> String {
+        format!("{}:id:{}", self.prefix, id)
+    }
+
+    async fn refresh_ttl(&self, aor: &str, bindings: &[Binding]) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let now = Utc::now();
+        match bindings.iter().map(|b| b.expires_in(now)).max() {
+            Some(ttl) if ttl > 0 => {
+                let _: () = conn.expire(self.aor_key(aor), i64::from(ttl)).await?;
+            }
+            _ => {
+                let _: () = conn.del(self.aor_key(aor)).await?;
+                let _: () = conn.srem(self.aors_key(), aor).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode(json: &str) -> Option<Binding> {
+    serde_json::from_str(json).ok()
+}
+
+#[async_trait]
+impl BindingStore for RedisBindingStore {
+    async fn get(&self, aor: &str) -> Result<Vec<Binding>> {
+        let mut conn = self.conn.clone();
+        let raw: HashMap<String, String> = conn.hgetall(self.aor_key(aor)).await?;
+        let now = Utc::now();
+        let (live, expired): (Vec<Binding>, Vec<Binding>) = raw
+            .values()
+            .filter_map(|json| decode(json))
+            .partition(|b| !b.is_expired(now));
+        for binding in &expired {
+            let _: () = conn.hdel(self.aor_key(aor), &binding.contact).await?;
+            let _: () = conn.del(self.id_key(&binding.id)).await?;
+        }
+        Ok(live)
+    }
+
+    async fn upsert(&self, binding: Binding) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let json = serde_json::to_string(&binding)
+            .map_err(|e| VoipError::Internal(format!("failed to encode binding: {}", e)))?;
+        let aor = binding.aor.clone();
+        let _: () = conn
+            .hset(self.aor_key(&aor), &binding.contact, json)
+            .await?;
+        let _: () = conn.sadd(self.aors_key(), &aor).await?;
+        let ttl = binding.expires_in(Utc::now()).max(1);
+        let _: () = conn
+            .set_ex(self.id_key(&binding.id), &aor, u64::from(ttl))
+            .await?;
+        let bindings = self.get(&aor).await?;
+        self.refresh_ttl(&aor, &bindings).await
+    }
+
+    async fn remove(&self, aor: &str, contact: &str) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let existing: Option<String> = conn.hget(self.aor_key(aor), contact).await?;
+        if let Some(binding) = existing.as_deref().and_then(decode) {
+            let _: () = conn.del(self.id_key(&binding.id)).await?;
+        }
+        let _: () = conn.hdel(self.aor_key(aor), contact).await?;
+        let bindings = self.get(aor).await?;
+        self.refresh_ttl(aor, &bindings).await
+    }
+
+    async fn remove_by_id(&self, id: &str) -> Result<Option<Binding>> {
+        let mut conn = self.conn.clone();
+        let aor: Option<String> = conn.get(self.id_key(id)).await?;
+        let Some(aor) = aor else {
+            return Ok(None);
+        };
+        let binding = self.get(&aor).await?.into_iter().find(|b| b.id == id);
+        match &binding {
+            Some(binding) => self.remove(&aor, &binding.contact).await?,
+            None => {
+                let _: () = conn.del(self.id_key(id)).await?;
+            }
+        }
+        Ok(binding)
+    }
+
+    async fn all(&self) -> Result<Vec<Binding>> {
+        let mut conn = self.conn.clone();
+        let aors: Vec<String> = conn.smembers(self.aors_key()).await?;
+        let mut all = Vec::new();
+        for aor in aors {
+            let bindings = self.get(&aor).await?;
+            if bindings.is_empty() {
+                let _: () = conn.srem(self.aors_key(), &aor).await?;
+            }
+            all.extend(bindings);
+        }
+        Ok(all)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(contact: &str, expires_in: i64) -> Binding {
+        let now = Utc::now();
+        Binding {
+            id: format!("id-{}", contact),
+            aor: "sip:alice@example.com".to_string(),
+            contact: contact.to_string(),
+            registered_at: now,
+            expires_at: now + chrono::Duration::seconds(expires_in),
+            call_id: "reg-1".to_string(),
+            cseq: 1,
+            user_agent: None,
+            source: "192.0.2.1:5060".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_store_replaces_contacts_and_hides_expired() {
+        let store = MemoryBindingStore::new();
+        store
+            .upsert(binding("sip:a@192.0.2.1", 60))
+            .await
+            .expect("upsert");
+        store
+            .upsert(binding("sip:b@192.0.2.2", -1))
+            .await
+            .expect("upsert");
+        let mut refreshed = binding("sip:a@192.0.2.1", 120);
+        refreshed.cseq = 2;
+        store.upsert(refreshed).await.expect("upsert");
+
+        let live = store.get("sip:alice@example.com").await.expect("get");
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].cseq, 2);
+        assert_eq!(store.all().await.expect("all").len(), 1);
+
+        let removed = store
+            .remove_by_id("id-sip:a@192.0.2.1")
+            .await
+            .expect("remove");
+        assert!(removed.is_some());
+        assert!(store
+            .get("sip:alice@example.com")
+            .await
+            .expect("get")
+            .is_empty());
+        assert!(
+            store.lock().expect("lock").is_empty(),
+            "no empty AOR left behind"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Typed views over the headers the signalling core inspects (Via, From/To/Contact, CSeq).
+
+use std::{fmt, str::FromStr};
+
+use super::{
+    message::Method,
+    parser::ParseError,
+    uri::{find_param, parse_params, set_param, split_host_port, write_params, SipUri},
+};
+
+/// Magic cookie prefixing RFC 3261 branch identifiers.
+pub const BRANCH_MAGIC_COOKIE: &str = "z9hG4bK";
+
+/// A single Via header value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Via {
+    /// Transport token (`UDP`, `TCP`, `TLS`, `WS`...), upper-cased.
+    pub transport: String,
+    /// Sent-by host.
+    pub host: String,
+    /// Sent-by port.
+    pub port: Option<u16>,
+    /// Via parameters in order (`branch`, `rport`, `received`...).
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl Via {
+    /// Build a Via for a request we send.
+    pub fn new(transport: &str, host: impl Into<String>, port: Option<u16>, branch: &str) -> Self {
+        Self {
+            transport: transport.to_ascii_uppercase(),
+            host: host.into(),
+            port,
+            params: vec![("branch".to_string(), Some(branch.to_string()))],
+        }
+    }
+
+    /// Transaction branch identifier.
+    pub fn branch(&self) -> Option<&str> {
+        self.param("branch")
+    }
+
+    /// `received` parameter added by the next hop.
+    pub fn received(&self) -> Option<&str> {
+        self.param("received")
+    }
+
+    /// Whether the sender asked for symmetric response routing (RFC 3581).
+    pub fn has_rport(&self) -> bool {
+        self.param("rport").is_some()
+    }
+
+    /// Port filled into `rport` by the receiving hop.
+    pub fn rport(&self) -> Option<u16> {
+        self.param("rport").and_then(|p| p.parse().ok())
+    }
+
+    /// Value of a Via parameter; flags yield `Some("")`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.params, name)
+    }
+
+    /// Set or replace a Via parameter.
+    pub fn set_param(&mut self, name: &str, value: Option<&str>) {
+        set_param(&mut self.params, name, value);
+    }
+
+    /// Port implied by the sent-by when none is written (RFC 3261 §18.2.2).
+    pub fn port_or_default(&self) -> u16 {
+        self.port
+            .unwrap_or(if self.transport == "TLS" { 5061 } else { 5060 })
+    }
+}
+
+impl FromStr for Via {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::Value {
+            kind: "Via",
+            value: s.to_string(),
+        };
+        let s = s.trim();
+        let (protocol, rest) = s.split_once([' ', '\t']).ok_or_else(invalid)?;
+        let mut proto_parts = protocol.split('/').map(str::trim);
+        let (name, version, transport) = (
+            proto_parts.next().ok_or_else(invalid)?,
+            proto_parts.next().ok_or_else(invalid)?,
+            proto_parts.next().ok_or_else(invalid)?,
+        );
+        if !name.eq_ignore_ascii_case("SIP") || version != "2.0" || transport.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut parts = rest.trim().split(';');
+        let (host, port) = split_host_port(parts.next().unwrap_or_default()).ok_or_else(invalid)?;
+
+        Ok(Self {
+            transport: transport.to_ascii_uppercase(),
+            host,
+            port,
+            params: parse_params(parts),
+        })
+    }
+}
+
+impl fmt::Display for Via {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIP/2.0/{} {}", self.transport, self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write_params(f, &self.params)
+    }
+}
+
+/// `name-addr` / `addr-spec` value used by From, To, Contact and Route headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameAddr {
+    /// Optional display name (quotes removed).
+    pub display_name: Option<String>,
+    /// Address URI.
+    pub uri: SipUri,
+    /// Header parameters (`tag`, `expires`, `q`...), distinct from URI parameters.
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl NameAddr {
+    /// Wrap a URI with no display name or parameters.
+    pub fn new(uri: SipUri) -> Self {
+        Self {
+            display_name: None,
+            uri,
+            params: Vec::new(),
+        }
+    }
+
+    /// Dialog tag parameter.
+    pub fn tag(&self) -> Option<&str> {
+        self.param("tag").filter(|t| !t.is_empty())
+    }
+
+    /// Value of a header parameter; flags yield `Some("")`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.params, name)
+    }
+
+    /// Set or replace a header parameter.
+    pub fn set_param(&mut self, name: &str, value: Option<&str>) {
+        set_param(&mut self.params, name, value);
+    }
+}
+
+impl FromStr for NameAddr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::Value {
+            kind: "name-addr",
+            value: s.to_string(),
+        };
+        let s = s.trim();
+
+        if let Some(open) = s.find('<') {
+            let close = s[open..].find('>').map(|i| i + open).ok_or_else(invalid)?;
+            let display = s[..open].trim().trim_matches('"').trim();
+            let uri = s[open + 1..close].parse()?;
+            let params = parse_params(s[close + 1..].split(';'));
+            return Ok(Self {
+                display_name: (!display.is_empty()).then(|| display.to_string()),
+                uri,
+                params,
+            });
+        }
+
+        // addr-spec form: everything after the first ';' belongs to the header, not the URI.
+        let mut parts = s.split(';');
+        let uri = parts.next().ok_or_else(invalid)?.parse()?;
+        Ok(Self {
+            display_name: None,
+            uri,
+            params: parse_params(parts),
+        })
+    }
+}
+
+impl fmt::Display for NameAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(display) = &self.display_name {
+            write!(f, "\"{}\" ", display)?;
+        }
+        write!(f, "<{}>", self.uri)?;
+        write_params(f, &self.params)
+    }
+}
+
+/// CSeq header value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CSeq {
+    /// Sequence number.
+    pub seq: u32,
+    /// Method the sequence number applies to.
+    pub method: Method,
+}
+
+impl CSeq {
+    /// Build a CSeq value.
+    pub const fn new(seq: u32, method: Method) -> Self {
+        Self { seq, method }
+    }
+}
+
+impl FromStr for CSeq {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::Value {
+            kind: "CSeq",
+            value: s.to_string(),
+        };
+        let mut parts = s.split_whitespace();
+        let seq = parts
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid)?;
+        let method = parts.next().ok_or_else(invalid)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            seq,
+            method: Method::from_token(method),
+        })
+    }
+}
+
+impl fmt::Display for CSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.seq, self.method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn via_round_trip_with_rport() {
+        let mut via: Via = "SIP/2.0/udp 192.0.2.1:5060;branch=z9hG4bKnashds8;rport"
+            .parse()
+            .expect("via");
+        assert_eq!(via.transport, "UDP");
+        assert_eq!(via.branch(), Some("z9hG4bKnashds8"));
+        assert!(via.has_rport());
+        assert_eq!(via.rport(), None);
+
+        via.set_param("received", Some("203.0.113.5"));
+        via.set_param("rport", Some("40123"));
+        assert_eq!(
+            via.to_string(),
+            "SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bKnashds8;rport=40123;received=203.0.113.5"
+        );
+    }
+
+    #[test]
+    fn name_addr_forms() {
+        let to: NameAddr = "\"Bob\" <sip:bob@biloxi.com;transport=tcp>;tag=a6c85cf"
+            .parse()
+            .expect("name-addr");
+        assert_eq!(to.display_name.as_deref(), Some("Bob"));
+        assert_eq!(to.tag(), Some("a6c85cf"));
+        assert_eq!(to.uri.param("transport"), Some("tcp"));
+
+        let from: NameAddr = "sip:alice@atlanta.com;tag=1928301774"
+            .parse()
+            .expect("addr-spec");
+        assert_eq!(from.tag(), Some("1928301774"));
+        assert!(from.uri.params.is_empty());
+    }
+
+    #[test]
+    fn cseq_parse() {
+        let cseq: CSeq = "314159 INVITE".parse().expect("cseq");
+        assert_eq!(cseq, CSeq::new(314_159, Method::Invite));
+        assert!("INVITE 1".parse::<CSeq>().is_err());
+    }
+}
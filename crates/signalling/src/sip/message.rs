@@ -0,0 +1,815 @@
+//! SIP request/response types and their wire serialization.
+
+use std::{borrow::Cow, fmt};
+
+use voip_common::VoipError;
+
+use super::headers::{CSeq, NameAddr, Via};
+
+/// Protocol version written on every start line.
+pub const SIP_VERSION: &str = "SIP/2.0";
+
+/// Compact header forms (RFC 3261 §7.3.3 and later extensions) mapped to their long names.
+const COMPACT_FORMS: &[(&str, &str)] = &[
+    ("a", "Accept-Contact"),
+    ("b", "Referred-By"),
+    ("c", "Content-Type"),
+    ("d", "Request-Disposition"),
+    ("e", "Content-Encoding"),
+    ("f", "From"),
+    ("i", "Call-ID"),
+    ("j", "Reject-Contact"),
+    ("k", "Supported"),
+    ("l", "Content-Length"),
+    ("m", "Contact"),
+    ("n", "Identity-Info"),
+    ("o", "Event"),
+    ("r", "Refer-To"),
+    ("s", "Subject"),
+    ("t", "To"),
+    ("u", "Allow-Events"),
+    ("v", "Via"),
+    ("x", "Session-Expires"),
+    ("y", "Identity"),
+];
+
+/// Expand a compact header name to its long form; other names are returned unchanged.
+pub fn canonical_name(name: &str) -> &str {
+    if name.len() == 1 {
+        if let Some((_, long)) = COMPACT_FORMS
+            .iter()
+            .find(|(short, _)| short.eq_ignore_ascii_case(name))
+        {
+            return long;
+        }
+    }
+    name
+}
+
+/// Compare two header names case-insensitively, treating compact forms as their long names.
+pub fn names_match(a: &str, b: &str) -> bool {
+    canonical_name(a).eq_ignore_ascii_case(canonical_name(b))
+}
+
+/// SIP request method.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// Session establishment.
+    Invite,
+    /// Final-response acknowledgement for INVITE.
+    Ack,
+    /// Session termination.
+    Bye,
+    /// Pending request cancellation.
+    Cancel,
+    /// Contact binding registration.
+    Register,
+    /// Capability query / keep-alive.
+    Options,
+    /// Mid-dialog information (RFC 6086).
+    Info,
+    /// Session update without changing dialog state (RFC 3311).
+    Update,
+    /// Reliable provisional acknowledgement (RFC 3262).
+    Prack,
+    /// Event subscription (RFC 6665).
+    Subscribe,
+    /// Event notification (RFC 6665).
+    Notify,
+    /// Call transfer (RFC 3515).
+    Refer,
+    /// Instant message (RFC 3428).
+    Message,
+    /// Event state publication (RFC 3903).
+    Publish,
+    /// Any other extension method token.
+    Extension(String),
+}
+
+impl Method {
+    /// Build a method from its wire token (methods are case-sensitive).
+    pub fn from_token(token: &str) -> Self {
+        match token {
+            "INVITE" => Self::Invite,
+            "ACK" => Self::Ack,
+            "BYE" => Self::Bye,
+            "CANCEL" => Self::Cancel,
+            "REGISTER" => Self::Register,
+            "OPTIONS" => Self::Options,
+            "INFO" => Self::Info,
+            "UPDATE" => Self::Update,
+            "PRACK" => Self::Prack,
+            "SUBSCRIBE" => Self::Subscribe,
+            "NOTIFY" => Self::Notify,
+            "REFER" => Self::Refer,
+            "MESSAGE" => Self::Message,
+            "PUBLISH" => Self::Publish,
+            other => Self::Extension(other.to_string()),
+        }
+    }
+
+    /// Wire representation of the method.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Invite => "INVITE",
+            Self::Ack => "ACK",
+            Self::Bye => "BYE",
+            Self::Cancel => "CANCEL",
+            Self::Register => "REGISTER",
+            Self::Options => "OPTIONS",
+            Self::Info => "INFO",
+            Self::Update => "UPDATE",
+            Self::Prack => "PRACK",
+            Self::Subscribe => "SUBSCRIBE",
+            Self::Notify => "NOTIFY",
+            Self::Refer => "REFER",
+            Self::Message => "MESSAGE",
+            Self::Publish => "PUBLISH",
+            Self::Extension(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// SIP response status code (100-699).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(pub u16);
+
+impl StatusCode {
+    /// 100 Trying
+    pub const TRYING: Self = Self(100);
+    /// 180 Ringing
+    pub const RINGING: Self = Self(180);
+    /// 183 Session Progress
+    pub const SESSION_PROGRESS: Self = Self(183);
+    /// 200 OK
+    pub const OK: Self = Self(200);
+    /// 202 Accepted
+    pub const ACCEPTED: Self = Self(202);
+    /// 400 Bad Request
+    pub const BAD_REQUEST: Self = Self(400);
+    /// 401 Unauthorized
+    pub const UNAUTHORIZED: Self = Self(401);
+    /// 403 Forbidden
+    pub const FORBIDDEN: Self = Self(403);
+    /// 404 Not Found
+    pub const NOT_FOUND: Self = Self(404);
+    /// 405 Method Not Allowed
+    pub const METHOD_NOT_ALLOWED: Self = Self(405);
+    /// 407 Proxy Authentication Required
+    pub const PROXY_AUTHENTICATION_REQUIRED: Self = Self(407);
+    /// 408 Request Timeout
+    pub const REQUEST_TIMEOUT: Self = Self(408);
+    /// 423 Interval Too Brief
+    pub const INTERVAL_TOO_BRIEF: Self = Self(423);
+    /// 480 Temporarily Unavailable
+    pub const TEMPORARILY_UNAVAILABLE: Self = Self(480);
+    /// 481 Call/Transaction Does Not Exist
+    pub const CALL_DOES_NOT_EXIST: Self = Self(481);
+    /// 483 Too Many Hops
+    pub const TOO_MANY_HOPS: Self = Self(483);
+    /// 486 Busy Here
+    pub const BUSY_HERE: Self = Self(486);
+    /// 487 Request Terminated
+    pub const REQUEST_TERMINATED: Self = Self(487);
+    /// 488 Not Acceptable Here
+    pub const NOT_ACCEPTABLE_HERE: Self = Self(488);
+    /// 491 Request Pending
+    pub const REQUEST_PENDING: Self = Self(491);
+    /// 500 Server Internal Error
+    pub const SERVER_INTERNAL_ERROR: Self = Self(500);
+    /// 501 Not Implemented
+    pub const NOT_IMPLEMENTED: Self = Self(501);
+    /// 503 Service Unavailable
+    pub const SERVICE_UNAVAILABLE: Self = Self(503);
+    /// 603 Decline
+    pub const DECLINE: Self = Self(603);
+
+    /// 1xx responses.
+    pub const fn is_provisional(self) -> bool {
+        self.0 >= 100 && self.0 < 200
+    }
+
+    /// 2xx responses.
+    pub const fn is_success(self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// Any response that terminates a transaction (>= 200).
+    pub const fn is_final(self) -> bool {
+        self.0 >= 200
+    }
+
+    /// Response code to reject a request with when handling it failed.
+    pub fn for_error(err: &VoipError) -> Self {
+        match err {
+            VoipError::Sip { code, .. } => Self(*code),
+            VoipError::NotFound(_) => Self::NOT_FOUND,
+            VoipError::Validation(_) => Self::BAD_REQUEST,
+            VoipError::Timeout(_) => Self::REQUEST_TIMEOUT,
+            VoipError::Unavailable(_) | VoipError::Io(_) => Self::SERVICE_UNAVAILABLE,
+            _ => Self::SERVER_INTERNAL_ERROR,
+        }
+    }
+
+    /// Default reason phrase for well-known codes.
+    pub const fn canonical_reason(self) -> &'static str {
+        match self.0 {
+            100 => "Trying",
+            180 => "Ringing",
+            181 => "Call Is Being Forwarded",
+            182 => "Queued",
+            183 => "Session Progress",
+            200 => "OK",
+            202 => "Accepted",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Moved Temporarily",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            415 => "Unsupported Media Type",
+            420 => "Bad Extension",
+            423 => "Interval Too Brief",
+            480 => "Temporarily Unavailable",
+            481 => "Call/Transaction Does Not Exist",
+            482 => "Loop Detected",
+            483 => "Too Many Hops",
+            484 => "Address Incomplete",
+            486 => "Busy Here",
+            487 => "Request Terminated",
+            488 => "Not Acceptable Here",
+            491 => "Request Pending",
+            500 => "Server Internal Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Server Time-out",
+            600 => "Busy Everywhere",
+            603 => "Decline",
+            604 => "Does Not Exist Anywhere",
+            606 => "Not Acceptable",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A single header line as it appeared on the wire (name kept verbatim, value trimmed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header<'a> {
+    /// Header name, possibly in compact form.
+    pub name: Cow<'a, str>,
+    /// Header value with surrounding whitespace removed and folded lines joined.
+    pub value: Cow<'a, str>,
+}
+
+impl Header<'_> {
+    /// Detach the header from the buffer it was parsed from.
+    pub fn into_owned(self) -> Header<'static> {
+        Header {
+            name: Cow::Owned(self.name.into_owned()),
+            value: Cow::Owned(self.value.into_owned()),
+        }
+    }
+}
+
+/// Ordered header list; lookups are case-insensitive and compact-form aware.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers<'a>(Vec<Header<'a>>);
+
+impl<'a> Headers<'a> {
+    /// Create an empty header list.
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Number of header lines.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether no header is present.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over header lines in wire order.
+    pub fn iter(&self) -> std::slice::Iter<'_, Header<'a>> {
+        self.0.iter()
+    }
+
+    /// Append a header line.
+    pub fn push(&mut self, name: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) {
+        self.0.push(Header {
+            name: name.into(),
+            value: value.into(),
+        });
+    }
+
+    /// Insert a header line before all others (used for Via/Record-Route stacking).
+    pub fn push_front(&mut self, name: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) {
+        self.0.insert(
+            0,
+            Header {
+                name: name.into(),
+                value: value.into(),
+            },
+        );
+    }
+
+    /// Replace every occurrence of `name` with a single line at the position of the first one.
+    pub fn set(&mut self, name: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) {
+        let name = name.into();
+        let value = value.into();
+        match self.0.iter().position(|h| names_match(&h.name, &name)) {
+            Some(index) => {
+                self.0[index].value = value;
+                let mut seen = 0usize;
+                self.0.retain(|h| {
+                    if names_match(&h.name, &name) {
+                        seen += 1;
+                        seen == 1
+                    } else {
+                        true
+                    }
+                });
+            }
+            None => self.0.push(Header { name, value }),
+        }
+    }
+
+    /// Remove every occurrence of `name`, returning how many lines were dropped.
+    pub fn remove(&mut self, name: &str) -> usize {
+        let before = self.0.len();
+        self.0.retain(|h| !names_match(&h.name, name));
+        before - self.0.len()
+    }
+
+    /// Remove the first value of a (possibly comma-separated) header, e.g. the top Via.
+    pub fn pop_first_value(&mut self, name: &str) -> Option<String> {
+        let index = self.0.iter().position(|h| names_match(&h.name, name))?;
+        let values: Vec<String> = split_list(&self.0[index].value)
+            .map(str::to_string)
+            .collect();
+        let (first, rest) = values.split_first()?;
+        if rest.is_empty() {
+            self.0.remove(index);
+        } else {
+            self.0[index].value = Cow::Owned(rest.join(", "));
+        }
+        Some(first.clone())
+    }
+
+    /// First value of `name`, exactly as found on its header line.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|h| names_match(&h.name, name))
+            .map(|h| h.value.as_ref())
+    }
+
+    /// All header lines matching `name`, in wire order.
+    pub fn get_all<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s str> + 's {
+        self.0
+            .iter()
+            .filter(move |h| names_match(&h.name, name))
+            .map(|h| h.value.as_ref())
+    }
+
+    /// All values of a list-valued header (Via, Contact, Route...), splitting
+    /// comma-separated lines. Do not use on headers whose grammar embeds commas
+    /// (WWW-Authenticate, Authorization, Date).
+    pub fn values<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s str> + 's {
+        self.get_all(name).flat_map(split_list)
+    }
+
+    /// Whether `name` is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Call-ID value.
+    pub fn call_id(&self) -> Option<&str> {
+        self.get("Call-ID")
+    }
+
+    /// Parsed CSeq header.
+    pub fn cseq(&self) -> Option<CSeq> {
+        self.get("CSeq").and_then(|v| v.parse().ok())
+    }
+
+    /// Parsed topmost Via.
+    pub fn top_via(&self) -> Option<Via> {
+        self.values("Via").next().and_then(|v| v.parse().ok())
+    }
+
+    /// Parsed From header.
+    pub fn from_addr(&self) -> Option<NameAddr> {
+        self.get("From").and_then(|v| v.parse().ok())
+    }
+
+    /// Parsed To header.
+    pub fn to_addr(&self) -> Option<NameAddr> {
+        self.get("To").and_then(|v| v.parse().ok())
+    }
+
+    /// Declared Content-Length, if present and numeric.
+    pub fn content_length(&self) -> Option<usize> {
+        self.get("Content-Length")
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Detach all headers from the buffer they were parsed from.
+    pub fn into_owned(self) -> Headers<'static> {
+        Headers(self.0.into_iter().map(Header::into_owned).collect())
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        for header in &self.0 {
+            out.extend_from_slice(header.name.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(header.value.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+}
+
+impl<'s, 'a> IntoIterator for &'s Headers<'a> {
+    type Item = &'s Header<'a>;
+    type IntoIter = std::slice::Iter<'s, Header<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Split a header value on top-level commas, ignoring commas inside quotes or `<...>`.
+pub fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut angle_depth = 0usize;
+    let mut start = 0usize;
+
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => angle_depth += 1,
+            '>' if !in_quotes => angle_depth = angle_depth.saturating_sub(1),
+            ',' if !in_quotes && angle_depth == 0 => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts.into_iter().map(str::trim).filter(|p| !p.is_empty())
+}
+
+/// A SIP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SipRequest<'a> {
+    /// Request method.
+    pub method: Method,
+    /// Request-URI exactly as written on the start line.
+    pub uri: Cow<'a, str>,
+    /// Header lines.
+    pub headers: Headers<'a>,
+    /// Message body (may be empty).
+    pub body: Cow<'a, [u8]>,
+}
+
+impl<'a> SipRequest<'a> {
+    /// Create a request with no headers and an empty body.
+    pub fn new(method: Method, uri: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            method,
+            uri: uri.into(),
+            headers: Headers::new(),
+            body: Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Builder-style header append.
+    pub fn with_header(
+        mut self,
+        name: impl Into<Cow<'a, str>>,
+        value: impl Into<Cow<'a, str>>,
+    ) -> Self {
+        self.headers.push(name, value);
+        self
+    }
+
+    /// Replace the body and keep Content-Length in sync.
+    pub fn set_body(&mut self, body: impl Into<Cow<'a, [u8]>>) {
+        self.body = body.into();
+        self.headers
+            .set("Content-Length", self.body.len().to_string());
+    }
+
+    /// Build a response carrying the headers RFC 3261 §8.2.6.2 requires to be copied.
+    pub fn response(&self, status: StatusCode) -> SipResponse<'static> {
+        let mut response = SipResponse::new(status);
+        for header in &self.headers {
+            if ["Via", "From", "To", "Call-ID", "CSeq"]
+                .iter()
+                .any(|name| names_match(&header.name, name))
+            {
+                response
+                    .headers
+                    .push(header.name.to_string(), header.value.to_string());
+            }
+        }
+        response.headers.push("Content-Length", "0");
+        response
+    }
+
+    /// Whether the To header carries a tag, i.e. the request belongs to a dialog (§12.2).
+    pub fn in_dialog(&self) -> bool {
+        self.headers.to_addr().is_some_and(|to| to.tag().is_some())
+    }
+
+    /// CANCEL for this INVITE (§9.1): same Request-URI, top Via, Call-ID, From, To and CSeq number.
+    pub fn cancel(&self) -> SipRequest<'static> {
+        let mut request = SipRequest::new(Method::Cancel, self.uri.to_string());
+        if let Some(via) = self.headers.values("Via").next() {
+            request.headers.push("Via", via.trim().to_string());
+        }
+        for name in ["Max-Forwards", "Route", "From", "To", "Call-ID"] {
+            for value in self.headers.get_all(name) {
+                request.headers.push(name, value.to_string());
+            }
+        }
+        let seq = self.headers.cseq().map_or(1, |cseq| cseq.seq);
+        request
+            .headers
+            .push("CSeq", CSeq::new(seq, Method::Cancel).to_string());
+        request.headers.push("Content-Length", "0");
+        request
+    }
+
+    /// Detach the request from the buffer it was parsed from.
+    pub fn into_owned(self) -> SipRequest<'static> {
+        SipRequest {
+            method: self.method,
+            uri: Cow::Owned(self.uri.into_owned()),
+            headers: self.headers.into_owned(),
+            body: Cow::Owned(self.body.into_owned()),
+        }
+    }
+
+    /// Append the wire form of the request to `out`.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.method.as_str().as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.uri.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(SIP_VERSION.as_bytes());
+        out.extend_from_slice(b"\r\n");
+        self.headers.write_to(out);
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+    }
+
+    /// Serialize the request.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256 + self.body.len());
+        self.write_to(&mut out);
+        out
+    }
+}
+
+/// A SIP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SipResponse<'a> {
+    /// Status code.
+    pub status: StatusCode,
+    /// Reason phrase.
+    pub reason: Cow<'a, str>,
+    /// Header lines.
+    pub headers: Headers<'a>,
+    /// Message body (may be empty).
+    pub body: Cow<'a, [u8]>,
+}
+
+impl<'a> SipResponse<'a> {
+    /// Create a response with the canonical reason phrase and no headers.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            reason: Cow::Borrowed(status.canonical_reason()),
+            headers: Headers::new(),
+            body: Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Builder-style header append.
+    pub fn with_header(
+        mut self,
+        name: impl Into<Cow<'a, str>>,
+        value: impl Into<Cow<'a, str>>,
+    ) -> Self {
+        self.headers.push(name, value);
+        self
+    }
+
+    /// Replace the body and keep Content-Length in sync.
+    pub fn set_body(&mut self, body: impl Into<Cow<'a, [u8]>>) {
+        self.body = body.into();
+        self.headers
+            .set("Content-Length", self.body.len().to_string());
+    }
+
+    /// Add our To tag unless the request already carried one (§8.2.6.2).
+    pub fn with_to_tag(mut self, tag: &str) -> Self {
+        if let Some(mut to) = self.headers.to_addr() {
+            if to.tag().is_none() {
+                to.set_param("tag", Some(tag));
+                self.headers.set("To", to.to_string());
+            }
+        }
+        self
+    }
+
+    /// Detach the response from the buffer it was parsed from.
+    pub fn into_owned(self) -> SipResponse<'static> {
+        SipResponse {
+            status: self.status,
+            reason: Cow::Owned(self.reason.into_owned()),
+            headers: self.headers.into_owned(),
+            body: Cow::Owned(self.body.into_owned()),
+        }
+    }
+
+    /// Append the wire form of the response to `out`.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(SIP_VERSION.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.status.to_string().as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.reason.as_bytes());
+        out.extend_from_slice(b"\r\n");
+        self.headers.write_to(out);
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+    }
+
+    /// Serialize the response.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256 + self.body.len());
+        self.write_to(&mut out);
+        out
+    }
+}
+
+/// Either kind of SIP message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SipMessage<'a> {
+    /// Request message.
+    Request(SipRequest<'a>),
+    /// Response message.
+    Response(SipResponse<'a>),
+}
+
+impl<'a> SipMessage<'a> {
+    /// Header lines of the message.
+    pub fn headers(&self) -> &Headers<'a> {
+        match self {
+            Self::Request(req) => &req.headers,
+            Self::Response(resp) => &resp.headers,
+        }
+    }
+
+    /// Mutable header lines of the message.
+    pub fn headers_mut(&mut self) -> &mut Headers<'a> {
+        match self {
+            Self::Request(req) => &mut req.headers,
+            Self::Response(resp) => &mut resp.headers,
+        }
+    }
+
+    /// Message body.
+    pub fn body(&self) -> &[u8] {
+        match self {
+            Self::Request(req) => &req.body,
+            Self::Response(resp) => &resp.body,
+        }
+    }
+
+    /// Detach the message from the buffer it was parsed from.
+    pub fn into_owned(self) -> SipMessage<'static> {
+        match self {
+            Self::Request(req) => SipMessage::Request(req.into_owned()),
+            Self::Response(resp) => SipMessage::Response(resp.into_owned()),
+        }
+    }
+
+    /// Serialize the message.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Request(req) => req.to_bytes(),
+            Self::Response(resp) => resp.to_bytes(),
+        }
+    }
+}
+
+impl<'a> From<SipRequest<'a>> for SipMessage<'a> {
+    fn from(req: SipRequest<'a>) -> Self {
+        Self::Request(req)
+    }
+}
+
+impl<'a> From<SipResponse<'a>> for SipMessage<'a> {
+    fn from(resp: SipResponse<'a>) -> Self {
+        Self::Response(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_names_match_long_names() {
+        let mut headers = Headers::new();
+        headers.push("v", "SIP/2.0/UDP a.example.com;branch=z9hG4bK1");
+        headers.push("VIA", "SIP/2.0/TCP b.example.com;branch=z9hG4bK2");
+        headers.push("i", "abc@host");
+
+        assert_eq!(headers.get_all("Via").count(), 2);
+        assert_eq!(headers.call_id(), Some("abc@host"));
+        assert_eq!(
+            headers
+                .top_via()
+                .and_then(|v| v.branch().map(String::from))
+                .as_deref(),
+            Some("z9hG4bK1")
+        );
+    }
+
+    #[test]
+    fn list_values_respect_quotes_and_brackets() {
+        let mut headers = Headers::new();
+        headers.push("Contact", "\"Doe, John\" <sip:j@a;x=1,2>, <sip:k@b>");
+        headers.push("m", "sip:l@c");
+
+        let values: Vec<_> = headers.values("Contact").collect();
+        assert_eq!(
+            values,
+            vec!["\"Doe, John\" <sip:j@a;x=1,2>", "<sip:k@b>", "sip:l@c"]
+        );
+    }
+
+    #[test]
+    fn response_copies_dialog_headers() {
+        let mut req = SipRequest::new(Method::Options, "sip:bob@example.com")
+            .with_header("Via", "SIP/2.0/UDP pc33;branch=z9hG4bK776")
+            .with_header("Max-Forwards", "70")
+            .with_header("From", "<sip:alice@example.com>;tag=1")
+            .with_header("To", "<sip:bob@example.com>")
+            .with_header("Call-ID", "a84b4c76e66710")
+            .with_header("CSeq", "63104 OPTIONS");
+        req.set_body(b"hello".to_vec());
+
+        let resp = req.response(StatusCode::OK);
+        assert_eq!(resp.reason, "OK");
+        assert!(!resp.headers.contains("Max-Forwards"));
+        assert_eq!(resp.headers.get("CSeq"), Some("63104 OPTIONS"));
+        assert_eq!(resp.headers.content_length(), Some(0));
+        assert_eq!(req.headers.content_length(), Some(5));
+    }
+
+    #[test]
+    fn pop_first_value_splits_combined_via() {
+        let mut headers = Headers::new();
+        headers.push(
+            "Via",
+            "SIP/2.0/UDP a;branch=z9hG4bK1, SIP/2.0/UDP b;branch=z9hG4bK2",
+        );
+        let top = headers.pop_first_value("Via");
+        assert_eq!(top.as_deref(), Some("SIP/2.0/UDP a;branch=z9hG4bK1"));
+        assert_eq!(headers.get("Via"), Some("SIP/2.0/UDP b;branch=z9hG4bK2"));
+    }
+}
@@ -0,0 +1,14 @@
+//! SIP message model, parser and serializer (RFC 3261 §7 and §25).
+//!
+//! Parsed messages borrow from the receive buffer wherever possible; call
+//! [`SipMessage::into_owned`] to detach them before handing them to another task.
+
+pub mod headers;
+pub mod message;
+pub mod parser;
+pub mod uri;
+
+pub use headers::{CSeq, NameAddr, Via};
+pub use message::{Header, Headers, Method, SipMessage, SipRequest, SipResponse, StatusCode};
+pub use parser::{message_length, parse_message, ParseError};
+pub use uri::{Scheme, SipUri};
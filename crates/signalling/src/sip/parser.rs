@@ -0,0 +1,397 @@
+//! Zero-copy SIP message parser.
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+use voip_common::VoipError;
+
+use super::message::{
+    Headers, Method, SipMessage, SipRequest, SipResponse, StatusCode, SIP_VERSION,
+};
+
+/// Errors raised while decoding a SIP message.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    /// More bytes are needed to complete the message.
+    #[error("incomplete SIP message")]
+    Incomplete,
+
+    /// The start line and headers are not valid UTF-8.
+    #[error("SIP message head is not valid UTF-8")]
+    InvalidUtf8,
+
+    /// Request or status line could not be parsed.
+    #[error("malformed start line: {0}")]
+    StartLine(String),
+
+    /// Version other than SIP/2.0.
+    #[error("unsupported SIP version: {0}")]
+    Version(String),
+
+    /// Header line without a valid name or colon.
+    #[error("malformed header line: {0}")]
+    Header(String),
+
+    /// Content-Length is not a number.
+    #[error("invalid Content-Length: {0}")]
+    ContentLength(String),
+
+    /// Stream transports require Content-Length (RFC 3261 §18.3).
+    #[error("missing Content-Length on stream transport")]
+    MissingContentLength,
+
+    /// A typed header or URI value could not be parsed.
+    #[error("malformed {kind}: {value}")]
+    Value {
+        /// What was being parsed.
+        kind: &'static str,
+        /// Offending input.
+        value: String,
+    },
+}
+
+impl From<ParseError> for VoipError {
+    fn from(err: ParseError) -> Self {
+        Self::Sip {
+            code: StatusCode::BAD_REQUEST.0,
+            reason: err.to_string(),
+        }
+    }
+}
+
+/// Parse one message from the start of `buf`, returning it with the number of bytes consumed.
+///
+/// Leading CRLFs (keep-alives) are skipped. When Content-Length is absent the
+/// rest of the buffer is taken as the body, which is the datagram behaviour;
+/// stream transports should frame with [`message_length`] first.
+pub fn parse_message(buf: &[u8]) -> Result<(SipMessage<'_>, usize), ParseError> {
+    let start = leading_crlf(buf);
+    let head_end = find_head_end(&buf[start..]).ok_or(ParseError::Incomplete)? + start;
+    let head = std::str::from_utf8(&buf[start..head_end]).map_err(|_| ParseError::InvalidUtf8)?;
+
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next().unwrap_or_default();
+    let headers = parse_headers(lines)?;
+
+    let body_start = head_end + 4;
+    let (body, consumed) = match headers.get("Content-Length") {
+        Some(value) => {
+            let len: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::ContentLength(value.to_string()))?;
+            let end = body_start
+                .checked_add(len)
+                .ok_or_else(|| ParseError::ContentLength(value.to_string()))?;
+            if buf.len() < end {
+                return Err(ParseError::Incomplete);
+            }
+            (&buf[body_start..end], end)
+        }
+        None => (&buf[body_start..], buf.len()),
+    };
+
+    let message = if let Some(status_line) = start_line.strip_prefix(SIP_VERSION) {
+        let (status, reason) = parse_status_line(start_line, status_line)?;
+        SipMessage::Response(SipResponse {
+            status,
+            reason: Cow::Borrowed(reason),
+            headers,
+            body: Cow::Borrowed(body),
+        })
+    } else {
+        let (method, uri) = parse_request_line(start_line)?;
+        SipMessage::Request(SipRequest {
+            method,
+            uri: Cow::Borrowed(uri),
+            headers,
+            body: Cow::Borrowed(body),
+        })
+    };
+
+    Ok((message, consumed))
+}
+
+/// Total length of the first message in a stream buffer, or `None` if more bytes are needed.
+///
+/// The returned length includes any leading keep-alive CRLFs.
+pub fn message_length(buf: &[u8]) -> Result<Option<usize>, ParseError> {
+    let start = leading_crlf(buf);
+    let Some(head_end) = find_head_end(&buf[start..]).map(|end| end + start) else {
+        return Ok(None);
+    };
+    let head = std::str::from_utf8(&buf[start..head_end]).map_err(|_| ParseError::InvalidUtf8)?;
+
+    let content_length = head
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| super::message::names_match(name.trim_end(), "Content-Length"))
+        .map(|(_, value)| {
+            value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| ParseError::ContentLength(value.trim().to_string()))
+        })
+        .ok_or(ParseError::MissingContentLength)??;
+
+    let total = head_end + 4 + content_length;
+    Ok((buf.len() >= total).then_some(total))
+}
+
+fn leading_crlf(buf: &[u8]) -> usize {
+    buf.chunks(2).take_while(|pair| *pair == b"\r\n").count() * 2
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, &str), ParseError> {
+    let invalid = || ParseError::StartLine(line.to_string());
+    let mut parts = line.splitn(3, ' ');
+    let method = parts.next().filter(|m| is_token(m)).ok_or_else(invalid)?;
+    let uri = parts
+        .next()
+        .filter(|u| !u.is_empty() && u.contains(':'))
+        .ok_or_else(invalid)?;
+    let version = parts.next().ok_or_else(invalid)?;
+    if !version.eq_ignore_ascii_case(SIP_VERSION) {
+        return Err(ParseError::Version(version.to_string()));
+    }
+    Ok((Method::from_token(method), uri))
+}
+
+fn parse_status_line<'a>(line: &str, rest: &'a str) -> Result<(StatusCode, &'a str), ParseError> {
+    let invalid = || ParseError::StartLine(line.to_string());
+    let rest = rest.strip_prefix(' ').ok_or_else(invalid)?;
+    let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let code: u16 = code.parse().map_err(|_| invalid())?;
+    if !(100..700).contains(&code) {
+        return Err(invalid());
+    }
+    Ok((StatusCode(code), reason))
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Headers<'a>, ParseError> {
+    let mut headers: Vec<(&'a str, Cow<'a, str>)> = Vec::new();
+
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            // Folded continuation line (RFC 3261 §7.3.1): join with a single space.
+            let (_, value) = headers
+                .last_mut()
+                .ok_or_else(|| ParseError::Header(line.to_string()))?;
+            let continuation = line.trim();
+            if !continuation.is_empty() {
+                let joined = if value.is_empty() {
+                    continuation.to_string()
+                } else {
+                    format!("{} {}", value, continuation)
+                };
+                *value = Cow::Owned(joined);
+            }
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| ParseError::Header(line.to_string()))?;
+        let name = name.trim_end_matches([' ', '\t']);
+        if !is_token(name) {
+            return Err(ParseError::Header(line.to_string()));
+        }
+        headers.push((name, Cow::Borrowed(value.trim_matches([' ', '\t']))));
+    }
+
+    let mut out = Headers::new();
+    for (name, value) in headers {
+        out.push(name, value);
+    }
+    Ok(out)
+}
+
+/// RFC 3261 `token` production.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.!%*_+`'~".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const INVITE: &[u8] = b"INVITE sip:bob@biloxi.com SIP/2.0\r\n\
+Via: SIP/2.0/UDP pc33.atlanta.com;branch=z9hG4bK776asdhds\r\n\
+Max-Forwards: 70\r\n\
+To: Bob <sip:bob@biloxi.com>\r\n\
+f: Alice <sip:alice@atlanta.com>;tag=1928301774\r\n\
+i: a84b4c76e66710@pc33.atlanta.com\r\n\
+CSeq: 314159 INVITE\r\n\
+Subject: lunch\r\n  at noon\r\n\
+c: application/sdp\r\n\
+l: 5\r\n\
+\r\n\
+v=0\r\n";
+
+    #[test]
+    fn parses_request_with_compact_and_folded_headers() {
+        let (message, consumed) = parse_message(INVITE).expect("parse");
+        assert_eq!(consumed, INVITE.len());
+
+        let SipMessage::Request(req) = message else {
+            panic!("expected request");
+        };
+        assert_eq!(req.method, Method::Invite);
+        assert_eq!(req.uri, "sip:bob@biloxi.com");
+        assert_eq!(
+            req.headers.call_id(),
+            Some("a84b4c76e66710@pc33.atlanta.com")
+        );
+        assert_eq!(req.headers.get("Subject"), Some("lunch at noon"));
+        assert_eq!(req.headers.get("Content-Type"), Some("application/sdp"));
+        assert_eq!(
+            req.headers
+                .from_addr()
+                .and_then(|f| f.tag().map(String::from))
+                .as_deref(),
+            Some("1928301774")
+        );
+        assert_eq!(&*req.body, b"v=0\r\n");
+        assert!(matches!(req.uri, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn parses_response_and_skips_keepalive() {
+        let raw = b"\r\n\r\nSIP/2.0 180 Ringing\r\nContent-Length: 0\r\n\r\n";
+        let (message, consumed) = parse_message(raw).expect("parse");
+        assert_eq!(consumed, raw.len());
+        let SipMessage::Response(resp) = message else {
+            panic!("expected response");
+        };
+        assert_eq!(resp.status, StatusCode::RINGING);
+        assert_eq!(resp.reason, "Ringing");
+    }
+
+    #[test]
+    fn frames_stream_by_content_length() {
+        let first = b"SIP/2.0 200 OK\r\nl: 3\r\n\r\nabc";
+        let mut stream = first.to_vec();
+        stream.extend_from_slice(b"OPTIONS sip:x SIP/2.0\r\n");
+
+        assert_eq!(message_length(&stream), Ok(Some(first.len())));
+        assert_eq!(message_length(&first[..first.len() - 1]), Ok(None));
+        assert_eq!(
+            message_length(b"SIP/2.0 200 OK\r\n\r\n"),
+            Err(ParseError::MissingContentLength)
+        );
+        assert_eq!(
+            parse_message(&first[..first.len() - 1]),
+            Err(ParseError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(
+            parse_message(b"INVITE sip:x SIP/3.0\r\n\r\n"),
+            Err(ParseError::Version(_))
+        ));
+        assert!(matches!(
+            parse_message(b"SIP/2.0 99 Nope\r\n\r\n"),
+            Err(ParseError::StartLine(_))
+        ));
+        assert!(matches!(
+            parse_message(b"OPTIONS sip:x SIP/2.0\r\nBad Header\r\n\r\n"),
+            Err(ParseError::Header(_))
+        ));
+        assert!(matches!(
+            parse_message(b"OPTIONS sip:x SIP/2.0\r\nContent-Length: ten\r\n\r\n"),
+            Err(ParseError::ContentLength(_))
+        ));
+    }
+
+    fn token() -> impl Strategy<Value = String> {
+        "[A-Za-z][A-Za-z0-9!%*_+`'~.-]{1,15}"
+    }
+
+    fn header_value() -> impl Strategy<Value = String> {
+        // Visible characters with inner spaces; no leading/trailing whitespace.
+        "[!-~]([ -~]{0,40}[!-~])?"
+    }
+
+    fn method() -> impl Strategy<Value = Method> {
+        prop_oneof![
+            Just(Method::Invite),
+            Just(Method::Ack),
+            Just(Method::Bye),
+            Just(Method::Cancel),
+            Just(Method::Register),
+            Just(Method::Options),
+            Just(Method::Refer),
+            "[A-Z]{3,10}".prop_map(|t| Method::from_token(&t)),
+        ]
+    }
+
+    fn headers() -> impl Strategy<Value = Headers<'static>> {
+        prop::collection::vec(
+            (token(), header_value())
+                .prop_filter("Content-Length is managed by set_body", |(name, _)| {
+                    !super::super::message::names_match(name, "Content-Length")
+                }),
+            0..8,
+        )
+        .prop_map(|pairs| {
+            let mut headers = Headers::new();
+            for (name, value) in pairs {
+                headers.push(name, value);
+            }
+            headers
+        })
+    }
+
+    fn message() -> impl Strategy<Value = SipMessage<'static>> {
+        let body = prop::collection::vec(any::<u8>(), 0..64);
+        let request = (method(), "sips?:[a-z0-9.@]{1,20}", headers(), body.clone()).prop_map(
+            |(method, uri, headers, body)| {
+                let mut req = SipRequest::new(method, uri);
+                req.headers = headers;
+                req.set_body(body);
+                SipMessage::Request(req)
+            },
+        );
+        let response = (100u16..700, "[ -~]{0,20}", headers(), body).prop_map(
+            |(code, reason, headers, body)| {
+                let mut resp = SipResponse::new(StatusCode(code));
+                resp.reason = Cow::Owned(reason);
+                resp.headers = headers;
+                resp.set_body(body);
+                SipMessage::Response(resp)
+            },
+        );
+        prop_oneof![request, response]
+    }
+
+    proptest! {
+        #[test]
+        fn serialize_then_parse_round_trips(message in message()) {
+            let wire = message.to_bytes();
+            let (parsed, consumed) = parse_message(&wire).expect("parse serialized message");
+            prop_assert_eq!(consumed, wire.len());
+            prop_assert_eq!(message_length(&wire), Ok(Some(wire.len())));
+            prop_assert_eq!(parsed.to_bytes(), wire.clone());
+            prop_assert_eq!(parsed, message);
+        }
+
+        #[test]
+        fn parse_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = parse_message(&bytes);
+            let _ = message_length(&bytes);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! SIP/SIPS/TEL URI model (RFC 3261 §19.1, RFC 3966).
+
+use std::{fmt, str::FromStr};
+
+use voip_common::proto::common::SipUri as ProtoSipUri;
+
+use super::parser::ParseError;
+
+/// URI scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// `sip:`
+    Sip,
+    /// `sips:`
+    Sips,
+    /// `tel:`
+    Tel,
+}
+
+impl Scheme {
+    /// Scheme name without the trailing colon.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sip => "sip",
+            Self::Sips => "sips",
+            Self::Tel => "tel",
+        }
+    }
+}
+
+/// Parsed SIP URI.
+///
+/// For `tel:` URIs the subscriber number is stored in `host`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SipUri {
+    /// URI scheme.
+    pub scheme: Scheme,
+    /// User part (including an optional `:password`), if any.
+    pub user: Option<String>,
+    /// Host name, IPv4 address or bracketed IPv6 reference.
+    pub host: String,
+    /// Explicit port.
+    pub port: Option<u16>,
+    /// URI parameters (`;transport=tcp`, `;lr`...), in order.
+    pub params: Vec<(String, Option<String>)>,
+    /// Raw header component after `?`, if any.
+    pub headers: Option<String>,
+}
+
+impl SipUri {
+    /// Build a plain `sip:user@host` URI.
+    pub fn new(user: Option<&str>, host: impl Into<String>) -> Self {
+        Self {
+            scheme: Scheme::Sip,
+            user: user.map(str::to_string),
+            host: host.into(),
+            port: None,
+            params: Vec::new(),
+            headers: None,
+        }
+    }
+
+    /// Value of a URI parameter; flag parameters yield `Some("")`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.params, name)
+    }
+
+    /// Set or replace a URI parameter.
+    pub fn set_param(&mut self, name: &str, value: Option<&str>) {
+        set_param(&mut self.params, name, value);
+    }
+
+    /// Transport parameter, upper-cased, defaulting to UDP (TLS for `sips:`).
+    pub fn transport(&self) -> String {
+        self.param("transport").map_or_else(
+            || {
+                if self.scheme == Scheme::Sips {
+                    "TLS".to_string()
+                } else {
+                    "UDP".to_string()
+                }
+            },
+            str::to_ascii_uppercase,
+        )
+    }
+
+    /// Address-of-record form (`scheme:user@host`), used as a registrar key.
+    pub fn aor(&self) -> String {
+        match &self.user {
+            Some(user) => {
+                let user = user.split(':').next().unwrap_or(user);
+                format!(
+                    "{}:{}@{}",
+                    self.scheme.as_str(),
+                    user,
+                    self.host.to_ascii_lowercase()
+                )
+            }
+            None => format!(
+                "{}:{}",
+                self.scheme.as_str(),
+                self.host.to_ascii_lowercase()
+            ),
+        }
+    }
+
+    /// Host and port formatted as `host[:port]`.
+    pub fn host_port(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{}", self.host, port),
+            None => self.host.clone(),
+        }
+    }
+}
+
+impl FromStr for SipUri {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::Value {
+            kind: "URI",
+            value: s.to_string(),
+        };
+        let s = s.trim();
+        let (scheme, rest) = s.split_once(':').ok_or_else(invalid)?;
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "sip" => Scheme::Sip,
+            "sips" => Scheme::Sips,
+            "tel" => Scheme::Tel,
+            _ => return Err(invalid()),
+        };
+
+        let (rest, headers) = match rest.split_once('?') {
+            Some((rest, headers)) => (rest, Some(headers.to_string())),
+            None => (rest, None),
+        };
+
+        if scheme == Scheme::Tel {
+            let mut parts = rest.split(';');
+            let number = parts.next().filter(|n| !n.is_empty()).ok_or_else(invalid)?;
+            return Ok(Self {
+                scheme,
+                user: None,
+                host: number.to_string(),
+                port: None,
+                params: parse_params(parts),
+                headers,
+            });
+        }
+
+        let (user, host_part) = match rest.find('@') {
+            Some(at) => (Some(rest[..at].to_string()), &rest[at + 1..]),
+            None => (None, rest),
+        };
+        if user.as_deref() == Some("") {
+            return Err(invalid());
+        }
+
+        let mut parts = host_part.split(';');
+        let host_port = parts.next().unwrap_or_default();
+        let (host, port) = split_host_port(host_port).ok_or_else(invalid)?;
+
+        Ok(Self {
+            scheme,
+            user,
+            host,
+            port,
+            params: parse_params(parts),
+            headers,
+        })
+    }
+}
+
+impl fmt::Display for SipUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scheme.as_str())?;
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write_params(f, &self.params)?;
+        if let Some(headers) = &self.headers {
+            write!(f, "?{}", headers)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&SipUri> for ProtoSipUri {
+    fn from(uri: &SipUri) -> Self {
+        Self {
+            user: uri.user.clone().unwrap_or_default(),
+            domain: uri.host.clone(),
+            port: uri.port.map(u32::from).unwrap_or_default(),
+            params: uri
+                .params
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone().unwrap_or_default()))
+                .collect(),
+            scheme: uri.scheme.as_str().to_string(),
+        }
+    }
+}
+
+impl From<&ProtoSipUri> for SipUri {
+    fn from(uri: &ProtoSipUri) -> Self {
+        let mut params: Vec<(String, Option<String>)> = uri
+            .params
+            .iter()
+            .map(|(k, v)| (k.clone(), (!v.is_empty()).then(|| v.clone())))
+            .collect();
+        // Proto maps are unordered; keep the rendered URI stable.
+        params.sort();
+        let scheme = match uri.scheme.to_ascii_lowercase().as_str() {
+            "sips" => Scheme::Sips,
+            "tel" => Scheme::Tel,
+            _ => Scheme::Sip,
+        };
+        Self {
+            scheme,
+            user: (!uri.user.is_empty()).then(|| uri.user.clone()),
+            host: uri.domain.clone(),
+            port: u16::try_from(uri.port).ok().filter(|p| *p != 0),
+            params,
+            headers: None,
+        }
+    }
+}
+
+/// Split `host[:port]`, accepting bracketed IPv6 references.
+pub(crate) fn split_host_port(s: &str) -> Option<(String, Option<u16>)> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    if let Some(stripped) = s.strip_prefix('[') {
+        let end = stripped.find(']')?;
+        let host = format!("[{}]", &stripped[..end]);
+        let rest = &stripped[end + 1..];
+        return match rest.strip_prefix(':') {
+            Some(port) => Some((host, Some(port.parse().ok()?))),
+            None if rest.is_empty() => Some((host, None)),
+            None => None,
+        };
+    }
+    match s.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => {
+            Some((host.to_string(), Some(port.parse().ok()?)))
+        }
+        Some(_) => None,
+        None => Some((s.to_string(), None)),
+    }
+}
+
+/// Parse `name[=value]` parameters from an iterator of `;`-separated segments.
+pub(crate) fn parse_params<'a>(
+    segments: impl Iterator<Item = &'a str>,
+) -> Vec<(String, Option<String>)> {
+    segments
+        .map(str::trim)
+        .filter(|seg| !seg.is_empty())
+        .map(|seg| match seg.split_once('=') {
+            Some((name, value)) => (name.trim().to_string(), Some(value.trim().to_string())),
+            None => (seg.to_string(), None),
+        })
+        .collect()
+}
+
+/// Write parameters back in `;name[=value]` form.
+pub(crate) fn write_params(
+    f: &mut fmt::Formatter<'_>,
+    params: &[(String, Option<String>)],
+) -> fmt::Result {
+    for (name, value) in params {
+        match value {
+            Some(value) => write!(f, ";{}={}", name, value)?,
+            None => write!(f, ";{}", name)?,
+        }
+    }
+    Ok(())
+}
+
+/// Case-insensitive parameter lookup; flags yield `Some("")`.
+pub(crate) fn find_param<'p>(
+    params: &'p [(String, Option<String>)],
+    name: &str,
+) -> Option<&'p str> {
+    params
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_deref().unwrap_or(""))
+}
+
+/// Set or replace a parameter, preserving its position when it already exists.
+pub(crate) fn set_param(
+    params: &mut Vec<(String, Option<String>)>,
+    name: &str,
+    value: Option<&str>,
+) {
+    let value = value.map(str::to_string);
+    match params
+        .iter_mut()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+    {
+        Some(slot) => slot.1 = value,
+        None => params.push((name.to_string(), value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_full_sip_uri() {
+        let uri: SipUri = "sips:alice:secret@Atlanta.com:5061;transport=tcp;lr?subject=project"
+            .parse()
+            .expect("uri");
+        assert_eq!(uri.scheme, Scheme::Sips);
+        assert_eq!(uri.user.as_deref(), Some("alice:secret"));
+        assert_eq!(uri.port, Some(5061));
+        assert_eq!(uri.param("lr"), Some(""));
+        assert_eq!(uri.transport(), "TCP");
+        assert_eq!(uri.aor(), "sips:alice@atlanta.com");
+        assert_eq!(
+            uri.to_string(),
+            "sips:alice:secret@Atlanta.com:5061;transport=tcp;lr?subject=project"
+        );
+    }
+
+    #[test]
+    fn parses_ipv6_and_tel() {
+        let uri: SipUri = "sip:[2001:db8::10]:5070".parse().expect("uri");
+        assert_eq!(uri.host, "[2001:db8::10]");
+        assert_eq!(uri.port, Some(5070));
+
+        let tel: SipUri = "tel:+33123456789;phone-context=example.com"
+            .parse()
+            .expect("tel");
+        assert_eq!(tel.host, "+33123456789");
+        assert_eq!(tel.param("phone-context"), Some("example.com"));
+    }
+
+    #[test]
+    fn proto_uris_keep_their_scheme() {
+        for text in [
+            "sips:desk@[2001:db8::10];transport=tcp",
+            "sip:pbx.example.com:5080",
+        ] {
+            let uri: SipUri = text.parse().expect("uri");
+            let proto = ProtoSipUri::from(&uri);
+            assert_eq!(SipUri::from(&proto), uri);
+        }
+        let legacy = ProtoSipUri {
+            domain: "example.com".to_string(),
+            ..ProtoSipUri::default()
+        };
+        assert_eq!(SipUri::from(&legacy).scheme, Scheme::Sip);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!("mailto:bob@example.com".parse::<SipUri>().is_err());
+        assert!("sip:@example.com".parse::<SipUri>().is_err());
+        assert!("sip:bob@example.com:notaport".parse::<SipUri>().is_err());
+    }
+}
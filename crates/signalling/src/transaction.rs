@@ -0,0 +1,968 @@
+//! SIP transaction layer (RFC 3261 §17).
+//!
+//! Each client or server transaction runs in its own tokio task driving the
+//! RFC timers (A-K). Outgoing traffic is queued as [`Outbound`] values for the
+//! transport, and everything the transaction user (TU) must see is reported as
+//! [`TransactionEvent`]s or, for client transactions, [`ClientEvent`]s.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use voip_common::{Result, VoipError};
+
+use crate::{
+    sip::{
+        headers::BRANCH_MAGIC_COOKIE, CSeq, Method, SipMessage, SipRequest, SipResponse, StatusCode,
+    },
+    transport::{response_target, InboundMessage, Target},
+};
+
+/// RFC 3261 timer base values.
+#[derive(Debug, Clone, Copy)]
+pub struct TimerConfig {
+    /// RTT estimate (default 500 ms).
+    pub t1: Duration,
+    /// Maximum retransmit interval for non-INVITE requests and INVITE responses (default 4 s).
+    pub t2: Duration,
+    /// Maximum time a message remains in the network (default 5 s).
+    pub t4: Duration,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            t1: Duration::from_millis(500),
+            t2: Duration::from_secs(4),
+            t4: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TimerConfig {
+    /// Timers B, F, H and J: 64*T1.
+    fn timeout(&self) -> Duration {
+        self.t1 * 64
+    }
+}
+
+/// Generate a fresh RFC 3261 branch parameter.
+pub fn new_branch() -> String {
+    format!("{}{}", BRANCH_MAGIC_COOKIE, Uuid::new_v4().simple())
+}
+
+/// Generate a random From/To tag.
+pub fn new_tag() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+/// Transaction identifier (RFC 3261 §17.1.3 and §17.2.3).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionKey {
+    /// Top Via branch.
+    pub branch: String,
+    /// Top Via sent-by for server transactions, empty for client transactions.
+    pub sent_by: String,
+    /// Transaction method; ACK maps onto the INVITE it acknowledges.
+    pub method: Method,
+}
+
+impl TransactionKey {
+    /// Key of the server transaction a request belongs to.
+    pub fn for_server(request: &SipRequest<'_>) -> Option<Self> {
+        let via = request.headers.top_via()?;
+        let branch = via.branch()?.to_string();
+        let method = match request.method {
+            Method::Ack => Method::Invite,
+            ref other => other.clone(),
+        };
+        Some(Self {
+            branch,
+            sent_by: format!("{}:{}", via.host, via.port_or_default()),
+            method,
+        })
+    }
+
+    /// Key of the client transaction a request creates, or a response matches.
+    pub fn for_client(message: &SipMessage<'_>) -> Option<Self> {
+        let headers = message.headers();
+        let branch = headers.top_via()?.branch()?.to_string();
+        let method = match message {
+            SipMessage::Request(req) => req.method.clone(),
+            SipMessage::Response(_) => headers.cseq()?.method,
+        };
+        Some(Self {
+            branch,
+            sent_by: String::new(),
+            method,
+        })
+    }
+}
+
+/// A message the transaction layer wants the transport to send.
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    /// Destination.
+    pub target: Target,
+    /// Message to serialize.
+    pub message: SipMessage<'static>,
+}
+
+/// Events delivered to the transaction user.
+#[derive(Debug)]
+pub enum TransactionEvent {
+    /// A new request. `transaction` is `None` for ACKs to 2xx responses, which
+    /// are end-to-end and never belong to a transaction.
+    Request {
+        /// The request.
+        request: SipRequest<'static>,
+        /// Where it came from.
+        source: Target,
+        /// Server transaction to answer through.
+        transaction: Option<ServerTransaction>,
+    },
+    /// A response that matched no client transaction (e.g. a retransmitted 2xx).
+    StrayResponse {
+        /// The response.
+        response: SipResponse<'static>,
+        /// Where it came from.
+        source: Target,
+    },
+    /// An INVITE server transaction never received the ACK for its final response (Timer H).
+    AckTimeout {
+        /// Transaction that timed out.
+        key: TransactionKey,
+    },
+}
+
+/// Events delivered to the owner of a client transaction.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A provisional or final response.
+    Response(SipResponse<'static>),
+    /// Timer B or F fired before a final response arrived.
+    Timeout,
+}
+
+#[derive(Debug)]
+enum ServerInput {
+    Retransmission,
+    Ack,
+    Respond(SipResponse<'static>),
+}
+
+/// Handle used by the TU to answer a request.
+#[derive(Debug, Clone)]
+pub struct ServerTransaction {
+    key: TransactionKey,
+    input_tx: mpsc::UnboundedSender<ServerInput>,
+}
+
+impl ServerTransaction {
+    /// Transaction identifier.
+    pub fn key(&self) -> &TransactionKey {
+        &self.key
+    }
+
+    /// Send a response through the transaction.
+    pub fn respond(&self, response: SipResponse<'static>) -> Result<()> {
+        self.input_tx
+            .send(ServerInput::Respond(response))
+            .map_err(|_| VoipError::Sip {
+                code: StatusCode::CALL_DOES_NOT_EXIST.0,
+                reason: "server transaction terminated".into(),
+            })
+    }
+}
+
+/// Handle on a running client transaction.
+#[derive(Debug)]
+pub struct ClientTransaction {
+    key: TransactionKey,
+    events_rx: mpsc::UnboundedReceiver<ClientEvent>,
+}
+
+impl ClientTransaction {
+    /// Transaction identifier.
+    pub fn key(&self) -> &TransactionKey {
+        &self.key
+    }
+
+    /// Next response or timeout; `None` once the transaction has terminated.
+    pub async fn recv(&mut self) -> Option<ClientEvent> {
+        self.events_rx.recv().await
+    }
+
+    /// Wait for the final response, skipping provisionals.
+    pub async fn final_response(&mut self) -> Result<SipResponse<'static>> {
+        while let Some(event) = self.recv().await {
+            match event {
+                ClientEvent::Response(resp) if resp.status.is_final() => return Ok(resp),
+                ClientEvent::Response(_) => {}
+                ClientEvent::Timeout => break,
+            }
+        }
+        Err(VoipError::Timeout(format!(
+            "no final response for transaction {}",
+            self.key.branch
+        )))
+    }
+}
+
+type ServerTable = Arc<Mutex<HashMap<TransactionKey, mpsc::UnboundedSender<ServerInput>>>>;
+type ClientTable = Arc<Mutex<HashMap<TransactionKey, mpsc::UnboundedSender<SipResponse<'static>>>>>;
+
+/// Matches messages to transactions and spawns transaction tasks.
+#[derive(Debug, Clone)]
+pub struct TransactionLayer {
+    timers: TimerConfig,
+    outbound_tx: mpsc::UnboundedSender<Outbound>,
+    tu_tx: mpsc::UnboundedSender<TransactionEvent>,
+    server: ServerTable,
+    client: ClientTable,
+}
+
+impl TransactionLayer {
+    /// Create a transaction layer writing to `outbound_tx` and reporting to `tu_tx`.
+    pub fn new(
+        timers: TimerConfig,
+        outbound_tx: mpsc::UnboundedSender<Outbound>,
+        tu_tx: mpsc::UnboundedSender<TransactionEvent>,
+    ) -> Self {
+        Self {
+            timers,
+            outbound_tx,
+            tu_tx,
+            server: Arc::new(Mutex::new(HashMap::new())),
+            client: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Timer configuration in use.
+    pub fn timers(&self) -> TimerConfig {
+        self.timers
+    }
+
+    /// Number of live server and client transactions.
+    pub fn len(&self) -> (usize, usize) {
+        (
+            self.server.lock().map(|t| t.len()).unwrap_or_default(),
+            self.client.lock().map(|t| t.len()).unwrap_or_default(),
+        )
+    }
+
+    /// Route a message received from the transport.
+    pub fn handle_inbound(&self, inbound: InboundMessage) {
+        let source = inbound.source;
+        match inbound.message {
+            SipMessage::Request(request) => self.handle_request(request, source),
+            SipMessage::Response(response) => self.handle_response(response, source),
+        }
+    }
+
+    /// Start a client transaction. The request must carry a top Via with a branch.
+    pub fn send_request(
+        &self,
+        request: SipRequest<'static>,
+        target: Target,
+    ) -> Result<ClientTransaction> {
+        if request.method == Method::Ack {
+            return Err(VoipError::Validation(
+                "ACK is sent outside client transactions".into(),
+            ));
+        }
+        let message = SipMessage::Request(request);
+        let key = TransactionKey::for_client(&message)
+            .ok_or_else(|| VoipError::Validation("request needs a top Via with a branch".into()))?;
+        let SipMessage::Request(request) = message else {
+            unreachable!("constructed as a request above");
+        };
+
+        let (response_tx, response_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        self.client
+            .lock()
+            .map_err(|_| VoipError::Internal("client transaction table poisoned".into()))?
+            .insert(key.clone(), response_tx);
+
+        let task = ClientTask {
+            layer: self.clone(),
+            key: key.clone(),
+            request,
+            target,
+            events_tx,
+        };
+        if task.request.method == Method::Invite {
+            tokio::spawn(task.run_invite(response_rx));
+        } else {
+            tokio::spawn(task.run_non_invite(response_rx));
+        }
+
+        Ok(ClientTransaction { key, events_rx })
+    }
+
+    /// Send a message outside any transaction (ACK for 2xx, stateless replies).
+    pub fn send_stateless(&self, target: Target, message: SipMessage<'static>) {
+        let _ = self.outbound_tx.send(Outbound { target, message });
+    }
+
+    /// INVITE server transaction a CANCEL refers to (RFC 3261 §9.2).
+    pub fn matching_invite(&self, cancel: &SipRequest<'_>) -> Option<ServerTransaction> {
+        let mut key = TransactionKey::for_server(cancel)?;
+        key.method = Method::Invite;
+        let input_tx = self.server.lock().ok()?.get(&key).cloned()?;
+        Some(ServerTransaction { key, input_tx })
+    }
+
+    fn handle_request(&self, request: SipRequest<'static>, source: Target) {
+        let Some(key) = TransactionKey::for_server(&request) else {
+            if request.method != Method::Ack {
+                debug!(method = %request.method, "rejecting request without Via branch");
+                let response = request.response(StatusCode::BAD_REQUEST);
+                self.send_stateless(source, SipMessage::Response(response));
+            }
+            return;
+        };
+
+        let existing = self
+            .server
+            .lock()
+            .ok()
+            .and_then(|table| table.get(&key).cloned());
+        match (existing, &request.method) {
+            (Some(input_tx), Method::Ack) => {
+                let _ = input_tx.send(ServerInput::Ack);
+            }
+            (Some(input_tx), _) => {
+                let _ = input_tx.send(ServerInput::Retransmission);
+            }
+            (None, Method::Ack) => {
+                let _ = self.tu_tx.send(TransactionEvent::Request {
+                    request,
+                    source,
+                    transaction: None,
+                });
+            }
+            (None, _) => {
+                let (input_tx, input_rx) = mpsc::unbounded_channel();
+                if let Ok(mut table) = self.server.lock() {
+                    table.insert(key.clone(), input_tx.clone());
+                }
+                let task = ServerTask {
+                    layer: self.clone(),
+                    key: key.clone(),
+                    request: request.clone(),
+                    source,
+                };
+                if request.method == Method::Invite {
+                    tokio::spawn(task.run_invite(input_rx));
+                } else {
+                    tokio::spawn(task.run_non_invite(input_rx));
+                }
+                let _ = self.tu_tx.send(TransactionEvent::Request {
+                    request,
+                    source,
+                    transaction: Some(ServerTransaction { key, input_tx }),
+                });
+            }
+        }
+    }
+
+    fn handle_response(&self, response: SipResponse<'static>, source: Target) {
+        let message = SipMessage::Response(response);
+        let sender = TransactionKey::for_client(&message).and_then(|key| {
+            self.client
+                .lock()
+                .ok()
+                .and_then(|table| table.get(&key).cloned())
+        });
+        let SipMessage::Response(response) = message else {
+            return;
+        };
+        match sender {
+            Some(tx) => {
+                let _ = tx.send(response);
+            }
+            None => {
+                let _ = self
+                    .tu_tx
+                    .send(TransactionEvent::StrayResponse { response, source });
+            }
+        }
+    }
+
+    fn send(&self, target: Target, message: SipMessage<'static>) {
+        let _ = self.outbound_tx.send(Outbound { target, message });
+    }
+}
+
+/// Sleep until `deadline`, or forever when there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+struct ClientTask {
+    layer: TransactionLayer,
+    key: TransactionKey,
+    request: SipRequest<'static>,
+    target: Target,
+    events_tx: mpsc::UnboundedSender<ClientEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClientState {
+    Calling,
+    Proceeding,
+    Completed,
+}
+
+impl ClientTask {
+    /// INVITE client transaction (RFC 3261 §17.1.1, Timers A, B, D).
+    async fn run_invite(self, mut responses: mpsc::UnboundedReceiver<SipResponse<'static>>) {
+        let timers = self.layer.timers;
+        let unreliable = !self.target.transport.is_reliable();
+        let mut state = ClientState::Calling;
+        let mut ack: Option<SipRequest<'static>> = None;
+
+        self.retransmit();
+        let mut timer_a_interval = timers.t1;
+        let mut timer_a = unreliable.then(|| Instant::now() + timer_a_interval);
+        let timer_b = Instant::now() + timers.timeout();
+        let mut timer_d = None;
+
+        loop {
+            tokio::select! {
+                () = sleep_until(timer_a) => {
+                    self.retransmit();
+                    timer_a_interval *= 2;
+                    timer_a = Some(Instant::now() + timer_a_interval);
+                }
+                () = sleep_until(Some(timer_b)), if state == ClientState::Calling => {
+                    let _ = self.events_tx.send(ClientEvent::Timeout);
+                    break;
+                }
+                () = sleep_until(timer_d) => break,
+                response = responses.recv() => {
+                    let Some(response) = response else { break };
+                    match (state, response.status) {
+                        (ClientState::Calling | ClientState::Proceeding, status) if status.is_provisional() => {
+                            state = ClientState::Proceeding;
+                            timer_a = None;
+                            let _ = self.events_tx.send(ClientEvent::Response(response));
+                        }
+                        (ClientState::Calling | ClientState::Proceeding, status) if status.is_success() => {
+                            // The TU owns ACK and 2xx retransmissions.
+                            let _ = self.events_tx.send(ClientEvent::Response(response));
+                            break;
+                        }
+                        (ClientState::Calling | ClientState::Proceeding, _) => {
+                            let generated = build_ack(&self.request, &response);
+                            self.layer.send(self.target, SipMessage::Request(generated.clone()));
+                            ack = Some(generated);
+                            state = ClientState::Completed;
+                            timer_a = None;
+                            timer_d = Some(Instant::now() + if unreliable { Duration::from_secs(32) } else { Duration::ZERO });
+                            let _ = self.events_tx.send(ClientEvent::Response(response));
+                        }
+                        (ClientState::Completed, status) if status.is_final() && !status.is_success() => {
+                            if let Some(ack) = &ack {
+                                self.layer.send(self.target, SipMessage::Request(ack.clone()));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.terminate();
+    }
+
+    /// Non-INVITE client transaction (RFC 3261 §17.1.2, Timers E, F, K).
+    async fn run_non_invite(self, mut responses: mpsc::UnboundedReceiver<SipResponse<'static>>) {
+        let timers = self.layer.timers;
+        let unreliable = !self.target.transport.is_reliable();
+        let mut state = ClientState::Calling;
+
+        self.retransmit();
+        let mut timer_e_interval = timers.t1;
+        let mut timer_e = unreliable.then(|| Instant::now() + timer_e_interval);
+        let timer_f = Instant::now() + timers.timeout();
+        let mut timer_k = None;
+
+        loop {
+            tokio::select! {
+                () = sleep_until(timer_e) => {
+                    self.retransmit();
+                    timer_e_interval = if state == ClientState::Proceeding {
+                        timers.t2
+                    } else {
+                        (timer_e_interval * 2).min(timers.t2)
+                    };
+                    timer_e = Some(Instant::now() + timer_e_interval);
+                }
+                () = sleep_until(Some(timer_f)), if state != ClientState::Completed => {
+                    let _ = self.events_tx.send(ClientEvent::Timeout);
+                    break;
+                }
+                () = sleep_until(timer_k) => break,
+                response = responses.recv() => {
+                    let Some(response) = response else { break };
+                    if state == ClientState::Completed {
+                        continue; // absorb retransmitted finals
+                    }
+                    if response.status.is_provisional() {
+                        state = ClientState::Proceeding;
+                    } else {
+                        state = ClientState::Completed;
+                        timer_e = None;
+                        timer_k = Some(Instant::now() + if unreliable { timers.t4 } else { Duration::ZERO });
+                    }
+                    let _ = self.events_tx.send(ClientEvent::Response(response));
+                }
+            }
+        }
+        self.terminate();
+    }
+
+    fn retransmit(&self) {
+        self.layer
+            .send(self.target, SipMessage::Request(self.request.clone()));
+    }
+
+    fn terminate(&self) {
+        if let Ok(mut table) = self.layer.client.lock() {
+            table.remove(&self.key);
+        }
+        debug!(branch = %self.key.branch, method = %self.key.method, "client transaction terminated");
+    }
+}
+
+/// ACK for a non-2xx final response, built by the transaction (RFC 3261 §17.1.1.3).
+fn build_ack(invite: &SipRequest<'static>, response: &SipResponse<'static>) -> SipRequest<'static> {
+    let mut ack = SipRequest::new(Method::Ack, invite.uri.clone());
+    if let Some(via) = invite.headers.values("Via").next() {
+        ack.headers.push("Via", via.to_string());
+    }
+    for route in invite.headers.get_all("Route") {
+        ack.headers.push("Route", route.to_string());
+    }
+    ack.headers.push("Max-Forwards", "70");
+    for name in ["From", "Call-ID"] {
+        if let Some(value) = invite.headers.get(name) {
+            ack.headers.push(name, value.to_string());
+        }
+    }
+    if let Some(to) = response.headers.get("To") {
+        ack.headers.push("To", to.to_string());
+    }
+    let seq = invite.headers.cseq().map_or(1, |c| c.seq);
+    ack.headers
+        .push("CSeq", CSeq::new(seq, Method::Ack).to_string());
+    ack.headers.push("Content-Length", "0");
+    ack
+}
+
+struct ServerTask {
+    layer: TransactionLayer,
+    key: TransactionKey,
+    request: SipRequest<'static>,
+    source: Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerState {
+    Trying,
+    Proceeding,
+    Completed,
+    Confirmed,
+}
+
+impl ServerTask {
+    async fn reply_target(&self) -> Target {
+        if self.source.transport.is_reliable() {
+            return self.source;
+        }
+        match self.request.headers.top_via() {
+            Some(via) => response_target(&via, self.source.transport)
+                .await
+                .unwrap_or(self.source),
+            None => self.source,
+        }
+    }
+
+    /// INVITE server transaction (RFC 3261 §17.2.1, Timers G, H, I).
+    async fn run_invite(self, mut input: mpsc::UnboundedReceiver<ServerInput>) {
+        let timers = self.layer.timers;
+        let unreliable = !self.source.transport.is_reliable();
+        let target = self.reply_target().await;
+        let mut state = ServerState::Proceeding;
+
+        // Answer immediately so the client stops retransmitting (§17.2.1).
+        let mut last_response = self.request.response(StatusCode::TRYING);
+        self.layer
+            .send(target, SipMessage::Response(last_response.clone()));
+
+        let mut timer_g_interval = timers.t1;
+        let mut timer_g = None;
+        let mut timer_h = None;
+        let mut timer_i = None;
+
+        loop {
+            tokio::select! {
+                () = sleep_until(timer_g) => {
+                    self.layer.send(target, SipMessage::Response(last_response.clone()));
+                    timer_g_interval = (timer_g_interval * 2).min(timers.t2);
+                    timer_g = Some(Instant::now() + timer_g_interval);
+                }
+                () = sleep_until(timer_h) => {
+                    warn!(branch = %self.key.branch, "no ACK received for INVITE final response");
+                    let _ = self.layer.tu_tx.send(TransactionEvent::AckTimeout { key: self.key.clone() });
+                    break;
+                }
+                () = sleep_until(timer_i) => break,
+                message = input.recv() => {
+                    let Some(message) = message else { break };
+                    match (state, message) {
+                        (ServerState::Proceeding | ServerState::Completed, ServerInput::Retransmission) => {
+                            self.layer.send(target, SipMessage::Response(last_response.clone()));
+                        }
+                        (ServerState::Proceeding, ServerInput::Respond(response)) => {
+                            let status = response.status;
+                            self.layer.send(target, SipMessage::Response(response.clone()));
+                            last_response = response;
+                            if status.is_success() {
+                                // 2xx retransmission is the TU's job (§13.3.1.4).
+                                break;
+                            }
+                            if status.is_final() {
+                                state = ServerState::Completed;
+                                timer_g = unreliable.then(|| Instant::now() + timer_g_interval);
+                                timer_h = Some(Instant::now() + timers.timeout());
+                            }
+                        }
+                        (ServerState::Completed, ServerInput::Ack) => {
+                            state = ServerState::Confirmed;
+                            timer_g = None;
+                            timer_h = None;
+                            timer_i = Some(Instant::now() + if unreliable { timers.t4 } else { Duration::ZERO });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.terminate();
+    }
+
+    /// Non-INVITE server transaction (RFC 3261 §17.2.2, Timer J).
+    async fn run_non_invite(self, mut input: mpsc::UnboundedReceiver<ServerInput>) {
+        let timers = self.layer.timers;
+        let unreliable = !self.source.transport.is_reliable();
+        let target = self.reply_target().await;
+        let mut state = ServerState::Trying;
+        let mut last_response: Option<SipResponse<'static>> = None;
+        let mut timer_j = None;
+
+        loop {
+            tokio::select! {
+                () = sleep_until(timer_j) => break,
+                message = input.recv() => {
+                    let Some(message) = message else { break };
+                    match (state, message) {
+                        (ServerState::Proceeding | ServerState::Completed, ServerInput::Retransmission) => {
+                            if let Some(response) = &last_response {
+                                self.layer.send(target, SipMessage::Response(response.clone()));
+                            }
+                        }
+                        (ServerState::Trying | ServerState::Proceeding, ServerInput::Respond(response)) => {
+                            let final_response = response.status.is_final();
+                            self.layer.send(target, SipMessage::Response(response.clone()));
+                            last_response = Some(response);
+                            if final_response {
+                                state = ServerState::Completed;
+                                timer_j = Some(Instant::now() + if unreliable { timers.timeout() } else { Duration::ZERO });
+                            } else {
+                                state = ServerState::Proceeding;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.terminate();
+    }
+
+    fn terminate(&self) {
+        if let Ok(mut table) = self.layer.server.lock() {
+            table.remove(&self.key);
+        }
+        debug!(branch = %self.key.branch, method = %self.key.method, "server transaction terminated");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportKind;
+
+    fn udp_target() -> Target {
+        Target {
+            transport: TransportKind::Udp,
+            addr: "192.0.2.10:5060".parse().expect("addr"),
+        }
+    }
+
+    fn request(method: Method, branch: &str) -> SipRequest<'static> {
+        let seq = if method == Method::Invite {
+            "1 INVITE"
+        } else {
+            "1 OPTIONS"
+        };
+        let mut req = SipRequest::new(method, "sip:bob@192.0.2.10")
+            .with_header(
+                "Via",
+                format!("SIP/2.0/UDP 192.0.2.20:5060;branch={}", branch),
+            )
+            .with_header("From", "<sip:alice@example.com>;tag=a1")
+            .with_header("To", "<sip:bob@example.com>")
+            .with_header("Call-ID", "txn-test")
+            .with_header("CSeq", seq);
+        req.set_body(Vec::new());
+        req
+    }
+
+    fn response_to(req: &SipRequest<'static>, status: StatusCode) -> SipResponse<'static> {
+        let mut resp = req.response(status);
+        if let Some(to) = resp.headers.to_addr() {
+            let mut to = to;
+            to.set_param("tag", Some("b1"));
+            resp.headers.set("To", to.to_string());
+        }
+        resp
+    }
+
+    fn layer() -> (
+        TransactionLayer,
+        mpsc::UnboundedReceiver<Outbound>,
+        mpsc::UnboundedReceiver<TransactionEvent>,
+    ) {
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (tu_tx, tu_rx) = mpsc::unbounded_channel();
+        (
+            TransactionLayer::new(TimerConfig::default(), outbound_tx, tu_tx),
+            outbound_rx,
+            tu_rx,
+        )
+    }
+
+    fn inbound(message: impl Into<SipMessage<'static>>) -> InboundMessage {
+        InboundMessage {
+            message: message.into(),
+            source: udp_target(),
+            local: "192.0.2.1:5060".parse().expect("addr"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_client_retransmits_with_timer_a_until_timer_b() {
+        let (layer, mut outbound, _tu) = layer();
+        let start = Instant::now();
+        let mut txn = layer
+            .send_request(request(Method::Invite, "z9hG4bKa"), udp_target())
+            .expect("send");
+
+        let mut sent_at = Vec::new();
+        for _ in 0..7 {
+            outbound.recv().await.expect("retransmission");
+            sent_at.push((Instant::now() - start).as_millis());
+        }
+        assert_eq!(sent_at, vec![0, 500, 1500, 3500, 7500, 15500, 31500]);
+
+        assert!(matches!(txn.recv().await, Some(ClientEvent::Timeout)));
+        assert_eq!(Instant::now() - start, Duration::from_secs(32));
+        assert_eq!(layer.len(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_client_acks_non_2xx_and_absorbs_retransmissions() {
+        let (layer, mut outbound, _tu) = layer();
+        let invite = request(Method::Invite, "z9hG4bKb");
+        let mut txn = layer
+            .send_request(invite.clone(), udp_target())
+            .expect("send");
+        outbound.recv().await.expect("invite");
+
+        let busy = response_to(&invite, StatusCode::BUSY_HERE);
+        layer.handle_inbound(inbound(busy.clone()));
+        let resp = txn.final_response().await.expect("final");
+        assert_eq!(resp.status, StatusCode::BUSY_HERE);
+
+        let ack = outbound.recv().await.expect("ack");
+        let SipMessage::Request(ack) = ack.message else {
+            panic!("expected ACK");
+        };
+        assert_eq!(ack.method, Method::Ack);
+        assert_eq!(ack.headers.cseq(), Some(CSeq::new(1, Method::Ack)));
+        assert_eq!(
+            ack.headers
+                .to_addr()
+                .and_then(|t| t.tag().map(String::from))
+                .as_deref(),
+            Some("b1")
+        );
+        assert_eq!(
+            ack.headers
+                .top_via()
+                .and_then(|v| v.branch().map(String::from))
+                .as_deref(),
+            Some("z9hG4bKb")
+        );
+
+        // A retransmitted final is answered with the same ACK, not passed up.
+        layer.handle_inbound(inbound(busy));
+        let again = outbound.recv().await.expect("ack again");
+        assert!(matches!(again.message, SipMessage::Request(ref r) if r.method == Method::Ack));
+
+        // Timer D (32 s on UDP) ends the transaction.
+        assert!(txn.recv().await.is_none());
+        assert_eq!(layer.len(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn non_invite_client_caps_timer_e_at_t2() {
+        let (layer, mut outbound, _tu) = layer();
+        let start = Instant::now();
+        let options = request(Method::Options, "z9hG4bKc");
+        let mut txn = layer
+            .send_request(options.clone(), udp_target())
+            .expect("send");
+
+        let mut sent_at = Vec::new();
+        for _ in 0..6 {
+            outbound.recv().await.expect("retransmission");
+            sent_at.push((Instant::now() - start).as_millis());
+        }
+        // 0.5, 1, 2, 4 (capped), 4...
+        assert_eq!(sent_at, vec![0, 500, 1500, 3500, 7500, 11500]);
+
+        layer.handle_inbound(inbound(response_to(&options, StatusCode::OK)));
+        let resp = txn.final_response().await.expect("final");
+        assert_eq!(resp.status, StatusCode::OK);
+        assert!(txn.recv().await.is_none());
+        // Timer K is T4 on UDP.
+        assert_eq!((Instant::now() - start).as_millis(), 11500 + 5000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_server_absorbs_retransmissions_and_acks() {
+        let (layer, mut outbound, mut tu) = layer();
+        let invite = request(Method::Invite, "z9hG4bKd");
+        layer.handle_inbound(inbound(invite.clone()));
+
+        let Some(TransactionEvent::Request {
+            transaction: Some(txn),
+            ..
+        }) = tu.recv().await
+        else {
+            panic!("expected new INVITE");
+        };
+        let trying = outbound.recv().await.expect("100");
+        assert!(
+            matches!(trying.message, SipMessage::Response(ref r) if r.status == StatusCode::TRYING)
+        );
+
+        // Retransmitted INVITE: last response resent, TU not bothered.
+        layer.handle_inbound(inbound(invite.clone()));
+        let resent = outbound.recv().await.expect("100 again");
+        assert!(
+            matches!(resent.message, SipMessage::Response(ref r) if r.status == StatusCode::TRYING)
+        );
+
+        txn.respond(response_to(&invite, StatusCode::BUSY_HERE))
+            .expect("respond");
+        let start = Instant::now();
+        outbound.recv().await.expect("486");
+        // Timer G retransmits the final response until the ACK arrives.
+        outbound.recv().await.expect("486 retransmission");
+        assert_eq!((Instant::now() - start).as_millis(), 500);
+
+        let mut ack = request(Method::Ack, "z9hG4bKd");
+        ack.headers.set("CSeq", "1 ACK");
+        layer.handle_inbound(inbound(ack));
+        tokio::task::yield_now().await;
+        assert_eq!(layer.len().0, 1);
+        // Timer I (T4) then terminates; the ACK never reached the TU.
+        time::sleep(Duration::from_secs(6)).await;
+        assert_eq!(layer.len().0, 0);
+        assert!(tu.try_recv().is_err());
+        assert!(outbound.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_server_reports_missing_ack_with_timer_h() {
+        let (layer, mut outbound, mut tu) = layer();
+        let invite = request(Method::Invite, "z9hG4bKe");
+        layer.handle_inbound(inbound(invite.clone()));
+        let Some(TransactionEvent::Request {
+            transaction: Some(txn),
+            ..
+        }) = tu.recv().await
+        else {
+            panic!("expected new INVITE");
+        };
+        txn.respond(response_to(&invite, StatusCode::DECLINE))
+            .expect("respond");
+
+        let event = tu.recv().await;
+        assert!(
+            matches!(event, Some(TransactionEvent::AckTimeout { ref key }) if key.branch == "z9hG4bKe")
+        );
+        // 100 + 603 + retransmissions at 0.5, 1.5, 3.5, 7.5 then every 4 s up to 32 s.
+        let mut count = 0;
+        while outbound.try_recv().is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 2 + 4 + 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn non_invite_server_replays_final_until_timer_j() {
+        let (layer, mut outbound, mut tu) = layer();
+        let options = request(Method::Options, "z9hG4bKf");
+        layer.handle_inbound(inbound(options.clone()));
+        let Some(TransactionEvent::Request {
+            transaction: Some(txn),
+            ..
+        }) = tu.recv().await
+        else {
+            panic!("expected OPTIONS");
+        };
+
+        // Retransmission before any response is silently absorbed.
+        layer.handle_inbound(inbound(options.clone()));
+        txn.respond(response_to(&options, StatusCode::OK))
+            .expect("respond");
+        let first = outbound.recv().await.expect("200");
+        assert!(matches!(first.message, SipMessage::Response(ref r) if r.status == StatusCode::OK));
+
+        layer.handle_inbound(inbound(options.clone()));
+        outbound.recv().await.expect("200 replayed");
+        assert!(tu.try_recv().is_err());
+
+        time::sleep(Duration::from_secs(33)).await;
+        assert_eq!(layer.len(), (0, 0));
+    }
+}
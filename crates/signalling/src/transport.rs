@@ -0,0 +1,632 @@
+//! UDP and TCP SIP transports (RFC 3261 §18) with RFC 3581 symmetric response routing.
+//!
+//! Listener tasks parse incoming traffic and push [`InboundMessage`]s onto an
+//! mpsc channel consumed by [`crate::SignallingService`]. Outbound traffic goes
+//! through a cloneable [`TransportHandle`].
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use voip_common::{types::ServiceConfig, Result, VoipError};
+
+use crate::sip::{message_length, parse_message, SipMessage, SipUri, Via};
+
+/// Default SIP port for UDP and TCP.
+pub const DEFAULT_SIP_PORT: u16 = 5060;
+
+/// Transport protocol a message travelled on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+    /// Unreliable datagram transport.
+    Udp,
+    /// Reliable stream transport.
+    Tcp,
+}
+
+impl TransportKind {
+    /// Token used in Via headers.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Udp => "UDP",
+            Self::Tcp => "TCP",
+        }
+    }
+
+    /// Whether the transport retransmits on its own (disables Timer A/E/G).
+    pub const fn is_reliable(self) -> bool {
+        matches!(self, Self::Tcp)
+    }
+}
+
+/// Where to send a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+    /// Transport to use.
+    pub transport: TransportKind,
+    /// Remote socket address.
+    pub addr: SocketAddr,
+}
+
+/// A parsed message received from the network.
+#[derive(Debug, Clone)]
+pub struct InboundMessage {
+    /// Parsed message; requests already carry `received`/`rport` on their top Via.
+    pub message: SipMessage<'static>,
+    /// Remote address and transport the message came from.
+    pub source: Target,
+    /// Local address it was received on.
+    pub local: SocketAddr,
+}
+
+/// SIP listener configuration, read from the `sip` object of `ServiceConfig.extra`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransportConfig {
+    /// UDP listen address; `None` disables UDP.
+    pub udp: Option<SocketAddr>,
+    /// TCP listen address; `None` disables TCP.
+    pub tcp: Option<SocketAddr>,
+    /// Largest message accepted on a stream before the connection is dropped.
+    pub max_message_size: usize,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        let addr = SocketAddr::from(([0, 0, 0, 0], DEFAULT_SIP_PORT));
+        Self {
+            udp: Some(addr),
+            tcp: Some(addr),
+            max_message_size: 65_535,
+        }
+    }
+}
+
+impl TransportConfig {
+    /// Build the transport config from the service config.
+    ///
+    /// Listeners default to the host of `bind_addr` on port 5060; the `sip`
+    /// object in `extra` (`{"sip": {"udp": "...", "tcp": null}}`) overrides them.
+    pub fn from_service_config(config: &ServiceConfig) -> Result<Self> {
+        let host: IpAddr = config
+            .bind_addr
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .map_err(|e| {
+                VoipError::Config(format!("invalid bind_addr {}: {}", config.bind_addr, e))
+            })?;
+        let default_addr = SocketAddr::new(host, DEFAULT_SIP_PORT);
+
+        match config.extra.get("sip") {
+            Some(sip) => {
+                let mut defaults = serde_json::json!({
+                    "udp": default_addr,
+                    "tcp": default_addr,
+                });
+                if let (Some(target), Some(overrides)) = (defaults.as_object_mut(), sip.as_object())
+                {
+                    for (key, value) in overrides {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+                serde_json::from_value(defaults)
+                    .map_err(|e| VoipError::Config(format!("invalid sip transport config: {}", e)))
+            }
+            None => Ok(Self {
+                udp: Some(default_addr),
+                tcp: Some(default_addr),
+                ..Self::default()
+            }),
+        }
+    }
+}
+
+/// Add `received`/`rport` to the top Via of a request (RFC 3261 §18.2.1, RFC 3581 §4).
+pub fn apply_received(message: &mut SipMessage<'_>, source: SocketAddr) {
+    let SipMessage::Request(request) = message else {
+        return;
+    };
+    let Some(top) = request.headers.pop_first_value("Via") else {
+        return;
+    };
+    let Ok(mut via) = top.parse::<Via>() else {
+        // Leave unparseable Vias untouched; the transaction layer will reject the request.
+        request.headers.push_front("Via", top);
+        return;
+    };
+
+    let source_ip = source.ip().to_string();
+    let sent_by_matches = via.host.trim_start_matches('[').trim_end_matches(']') == source_ip;
+    if via.has_rport() {
+        via.set_param("rport", Some(&source.port().to_string()));
+        via.set_param("received", Some(&source_ip));
+    } else if !sent_by_matches {
+        via.set_param("received", Some(&source_ip));
+    }
+    request.headers.push_front("Via", via.to_string());
+}
+
+/// Address a response must be sent to, from the top Via (RFC 3261 §18.2.2, RFC 3581 §4).
+pub async fn response_target(via: &Via, transport: TransportKind) -> Result<Target> {
+    let host = via.received().unwrap_or(&via.host);
+    let port = via.rport().unwrap_or_else(|| via.port_or_default());
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addr = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| VoipError::Unavailable(format!("cannot resolve Via host {}", host)))?,
+    };
+    Ok(Target { transport, addr })
+}
+
+/// Where to send a request addressed to `uri` (RFC 3263 without NAPTR/SRV).
+pub async fn uri_target(uri: &SipUri) -> Result<Target> {
+    let transport = if uri.transport() == "TCP" {
+        TransportKind::Tcp
+    } else {
+        TransportKind::Udp
+    };
+    let host = uri.host.trim_start_matches('[').trim_end_matches(']');
+    let port = uri.port.unwrap_or(DEFAULT_SIP_PORT);
+    let addr = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| VoipError::Unavailable(format!("cannot resolve {}", host)))?,
+    };
+    Ok(Target { transport, addr })
+}
+
+type Connections = Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>>;
+
+/// Cloneable handle used to send messages through the bound transports.
+#[derive(Debug, Clone)]
+pub struct TransportHandle {
+    udp: Option<Arc<UdpSocket>>,
+    tcp_local: Option<SocketAddr>,
+    connections: Connections,
+    inbound_tx: mpsc::Sender<InboundMessage>,
+    shutdown_tx: broadcast::Sender<()>,
+    max_message_size: usize,
+}
+
+impl TransportHandle {
+    /// Local address of the UDP socket, if bound.
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        self.udp.as_ref().and_then(|s| s.local_addr().ok())
+    }
+
+    /// Local address of the TCP listener, if bound.
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        self.tcp_local
+    }
+
+    /// Local address used for a given transport (advertised in Via/Contact).
+    pub fn local_addr(&self, transport: TransportKind) -> Option<SocketAddr> {
+        match transport {
+            TransportKind::Udp => self.udp_addr(),
+            TransportKind::Tcp => self.tcp_addr(),
+        }
+    }
+
+    /// Serialize and send a message.
+    pub async fn send(&self, target: Target, message: &SipMessage<'_>) -> Result<()> {
+        self.send_bytes(target, message.to_bytes()).await
+    }
+
+    /// Send pre-serialized bytes, opening a TCP connection if none exists for the target.
+    pub async fn send_bytes(&self, target: Target, bytes: Vec<u8>) -> Result<()> {
+        match target.transport {
+            TransportKind::Udp => {
+                let socket = self
+                    .udp
+                    .as_ref()
+                    .ok_or_else(|| VoipError::Unavailable("UDP transport disabled".into()))?;
+                socket.send_to(&bytes, target.addr).await?;
+                Ok(())
+            }
+            TransportKind::Tcp => {
+                let existing = self
+                    .connections
+                    .lock()
+                    .map_err(|_| VoipError::Internal("connection table poisoned".into()))?
+                    .get(&target.addr)
+                    .cloned();
+                let sender = match existing {
+                    Some(sender) => sender,
+                    None => self.connect(target.addr).await?,
+                };
+                sender.send(bytes).await.map_err(|_| {
+                    VoipError::Unavailable(format!("connection to {} closed", target.addr))
+                })
+            }
+        }
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<mpsc::Sender<Vec<u8>>> {
+        let stream = TcpStream::connect(addr).await?;
+        debug!(peer = %addr, "opened outbound SIP connection");
+        Ok(self.register_connection(stream, addr))
+    }
+
+    fn register_connection(&self, stream: TcpStream, peer: SocketAddr) -> mpsc::Sender<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(64);
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.insert(peer, tx.clone());
+        }
+        tokio::spawn(run_connection(self.clone(), stream, peer, rx));
+        tx
+    }
+
+    fn forget_connection(&self, peer: SocketAddr) {
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.remove(&peer);
+        }
+    }
+}
+
+/// Our own address as written in the Via and Contact headers of requests we originate.
+#[derive(Debug, Clone, Default)]
+pub struct LocalEndpoint {
+    transport: Option<TransportHandle>,
+    advertised_host: Option<String>,
+}
+
+impl LocalEndpoint {
+    /// Advertise the listener addresses of `transport`, or `advertised_host` when set.
+    pub fn new(transport: Option<TransportHandle>, advertised_host: Option<String>) -> Self {
+        Self {
+            transport,
+            advertised_host,
+        }
+    }
+
+    /// Host and port reachable over `transport`, falling back to the UDP listener.
+    pub fn host_port(&self, transport: TransportKind) -> (String, u16) {
+        let local = self.transport.as_ref().and_then(|t| {
+            t.local_addr(transport)
+                .or_else(|| t.local_addr(TransportKind::Udp))
+        });
+        let host =
+            self.advertised_host
+                .clone()
+                .unwrap_or_else(|| match local.map(|addr| addr.ip()) {
+                    Some(IpAddr::V6(ip)) => format!("[{}]", ip),
+                    Some(ip) => ip.to_string(),
+                    None => "127.0.0.1".to_string(),
+                });
+        (host, local.map_or(DEFAULT_SIP_PORT, |addr| addr.port()))
+    }
+
+    /// A Via with a fresh branch and `rport` (RFC 3581 §3).
+    pub fn via(&self, transport: TransportKind) -> Via {
+        let (host, port) = self.host_port(transport);
+        let branch = crate::transaction::new_branch();
+        let mut via = Via::new(transport.as_str(), host, Some(port), &branch);
+        via.set_param("rport", None);
+        via
+    }
+
+    /// A Contact header value for `user` at our address.
+    pub fn contact(&self, transport: TransportKind, user: &str) -> String {
+        let (host, port) = self.host_port(transport);
+        let mut uri = SipUri::new(Some(user), host);
+        uri.port = Some(port);
+        if transport == TransportKind::Tcp {
+            uri.set_param("transport", Some("tcp"));
+        }
+        format!("<{}>", uri)
+    }
+}
+
+/// Bind the configured listeners and spawn their receive loops.
+///
+/// Parsed messages are delivered on `inbound_tx`; every task exits when
+/// `shutdown_tx` fires, after flushing queued outbound stream data.
+pub async fn bind(
+    config: &TransportConfig,
+    inbound_tx: mpsc::Sender<InboundMessage>,
+    shutdown_tx: broadcast::Sender<()>,
+) -> Result<(TransportHandle, Vec<JoinHandle<()>>)> {
+    let udp = match config.udp {
+        Some(addr) => Some(Arc::new(UdpSocket::bind(addr).await?)),
+        None => None,
+    };
+    let tcp = match config.tcp {
+        Some(addr) => Some(TcpListener::bind(addr).await?),
+        None => None,
+    };
+
+    let handle = TransportHandle {
+        udp: udp.clone(),
+        tcp_local: tcp.as_ref().and_then(|l| l.local_addr().ok()),
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        inbound_tx,
+        shutdown_tx,
+        max_message_size: config.max_message_size,
+    };
+
+    let mut tasks = Vec::new();
+    if let Some(socket) = udp {
+        info!(addr = ?socket.local_addr().ok(), "SIP UDP listener bound");
+        tasks.push(tokio::spawn(run_udp(handle.clone(), socket)));
+    }
+    if let Some(listener) = tcp {
+        info!(addr = ?handle.tcp_local, "SIP TCP listener bound");
+        tasks.push(tokio::spawn(run_tcp_accept(handle.clone(), listener)));
+    }
+
+    Ok((handle, tasks))
+}
+
+async fn run_udp(handle: TransportHandle, socket: Arc<UdpSocket>) {
+    let mut shutdown_rx = handle.shutdown_tx.subscribe();
+    let local = socket.local_addr().ok();
+    let mut buf = vec![0u8; 65_535];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, source) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        // ICMP port unreachable surfaces here on some platforms; keep listening.
+                        debug!(error = %e, "UDP receive error");
+                        continue;
+                    }
+                };
+                let datagram = &buf[..len];
+                if datagram.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue; // RFC 5626 keep-alive
+                }
+                match parse_message(datagram) {
+                    Ok((message, _)) => {
+                        let mut message = message.into_owned();
+                        apply_received(&mut message, source);
+                        let inbound = InboundMessage {
+                            message,
+                            source: Target { transport: TransportKind::Udp, addr: source },
+                            local: local.unwrap_or(source),
+                        };
+                        if handle.inbound_tx.send(inbound).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => debug!(%source, error = %e, "dropping malformed SIP datagram"),
+                }
+            }
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+    debug!("SIP UDP listener stopped");
+}
+
+async fn run_tcp_accept(handle: TransportHandle, listener: TcpListener) {
+    let mut shutdown_rx = handle.shutdown_tx.subscribe();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    debug!(%peer, "accepted SIP connection");
+                    handle.register_connection(stream, peer);
+                }
+                Err(e) => warn!(error = %e, "SIP TCP accept failed"),
+            },
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+    debug!("SIP TCP listener stopped");
+}
+
+async fn run_connection(
+    handle: TransportHandle,
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut outbound_rx: mpsc::Receiver<Vec<u8>>,
+) {
+    let mut shutdown_rx = handle.shutdown_tx.subscribe();
+    let local = stream.local_addr().unwrap_or(peer);
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buf: Vec<u8> = Vec::with_capacity(4096);
+    let mut chunk = vec![0u8; 8192];
+
+    loop {
+        tokio::select! {
+            read = reader.read(&mut chunk) => {
+                let n = match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                buf.extend_from_slice(&chunk[..n]);
+                if let Err(e) = drain_stream_buffer(&handle, &mut buf, peer, local).await {
+                    warn!(%peer, error = %e, "closing SIP connection");
+                    break;
+                }
+            }
+            outbound = outbound_rx.recv() => match outbound {
+                Some(bytes) => {
+                    if let Err(e) = writer.write_all(&bytes).await {
+                        debug!(%peer, error = %e, "SIP connection write failed");
+                        break;
+                    }
+                }
+                None => break,
+            },
+            _ = shutdown_rx.recv() => {
+                // Graceful drain: flush anything already queued before closing.
+                handle.forget_connection(peer);
+                while let Ok(bytes) = outbound_rx.try_recv() {
+                    if writer.write_all(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    handle.forget_connection(peer);
+    let _ = writer.shutdown().await;
+    debug!(%peer, "SIP connection closed");
+}
+
+/// Extract every complete message from a stream buffer.
+async fn drain_stream_buffer(
+    handle: &TransportHandle,
+    buf: &mut Vec<u8>,
+    peer: SocketAddr,
+    local: SocketAddr,
+) -> Result<()> {
+    loop {
+        let Some(len) = message_length(buf)? else {
+            if buf.len() > handle.max_message_size {
+                return Err(VoipError::Validation(format!(
+                    "stream message exceeds {} bytes",
+                    handle.max_message_size
+                )));
+            }
+            // Bare CRLF keep-alives carry no message.
+            if buf.iter().all(|b| *b == b'\r' || *b == b'\n') {
+                buf.clear();
+            }
+            return Ok(());
+        };
+
+        let (message, _) = parse_message(&buf[..len])?;
+        let mut message = message.into_owned();
+        buf.drain(..len);
+        apply_received(&mut message, peer);
+
+        let inbound = InboundMessage {
+            message,
+            source: Target {
+                transport: TransportKind::Tcp,
+                addr: peer,
+            },
+            local,
+        };
+        handle
+            .inbound_tx
+            .send(inbound)
+            .await
+            .map_err(|_| VoipError::Unavailable("signalling core stopped".into()))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip::{Method, SipRequest};
+
+    fn options(via: &str) -> SipMessage<'static> {
+        let mut req = SipRequest::new(Method::Options, "sip:gw.example.com")
+            .with_header("Via", via.to_string())
+            .with_header("Call-ID", "opt-1")
+            .with_header("CSeq", "1 OPTIONS");
+        req.set_body(Vec::new());
+        SipMessage::Request(req)
+    }
+
+    #[test]
+    fn received_and_rport_are_added() {
+        let source: SocketAddr = "203.0.113.9:40000".parse().expect("addr");
+
+        let mut msg = options("SIP/2.0/UDP 10.0.0.5:5060;branch=z9hG4bK1;rport");
+        apply_received(&mut msg, source);
+        let via = msg.headers().top_via().expect("via");
+        assert_eq!(via.received(), Some("203.0.113.9"));
+        assert_eq!(via.rport(), Some(40000));
+
+        let mut msg = options("SIP/2.0/UDP 203.0.113.9:5060;branch=z9hG4bK2");
+        apply_received(&mut msg, source);
+        let via = msg.headers().top_via().expect("via");
+        assert_eq!(via.received(), None);
+    }
+
+    #[tokio::test]
+    async fn response_target_prefers_received_and_rport() {
+        let via: Via = "SIP/2.0/UDP 10.0.0.5:5070;branch=z9hG4bK1;rport=40000;received=203.0.113.9"
+            .parse()
+            .expect("via");
+        let target = response_target(&via, TransportKind::Udp)
+            .await
+            .expect("target");
+        assert_eq!(
+            target.addr,
+            "203.0.113.9:40000".parse::<SocketAddr>().expect("addr")
+        );
+
+        let via: Via = "SIP/2.0/UDP 10.0.0.5;branch=z9hG4bK1".parse().expect("via");
+        let target = response_target(&via, TransportKind::Udp)
+            .await
+            .expect("target");
+        assert_eq!(
+            target.addr,
+            "10.0.0.5:5060".parse::<SocketAddr>().expect("addr")
+        );
+    }
+
+    #[tokio::test]
+    async fn tcp_stream_is_framed_by_content_length() {
+        let (inbound_tx, mut inbound_rx) = mpsc::channel(8);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let config = TransportConfig {
+            udp: None,
+            tcp: Some("127.0.0.1:0".parse().expect("addr")),
+            ..TransportConfig::default()
+        };
+        let (handle, tasks) = bind(&config, inbound_tx, shutdown_tx.clone())
+            .await
+            .expect("bind");
+
+        let mut client = TcpStream::connect(handle.tcp_addr().expect("tcp"))
+            .await
+            .expect("connect");
+        let mut wire = b"\r\n\r\n".to_vec();
+        let mut first = options("SIP/2.0/TCP 127.0.0.1:1;branch=z9hG4bKa");
+        if let SipMessage::Request(req) = &mut first {
+            req.set_body(b"ab".to_vec());
+        }
+        wire.extend(first.to_bytes());
+        wire.extend(options("SIP/2.0/TCP 127.0.0.1:1;branch=z9hG4bKb").to_bytes());
+        // Split the write mid-message to exercise partial reads.
+        client.write_all(&wire[..30]).await.expect("write");
+        client.flush().await.expect("flush");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        client.write_all(&wire[30..]).await.expect("write");
+
+        let a = inbound_rx.recv().await.expect("first");
+        let b = inbound_rx.recv().await.expect("second");
+        assert_eq!(a.message.body(), b"ab");
+        assert_eq!(a.source.transport, TransportKind::Tcp);
+        assert_eq!(
+            b.message
+                .headers()
+                .top_via()
+                .and_then(|v| v.branch().map(String::from))
+                .as_deref(),
+            Some("z9hG4bKb")
+        );
+
+        let _ = shutdown_tx.send(());
+        for task in tasks {
+            task.await.expect("listener task");
+        }
+    }
+}
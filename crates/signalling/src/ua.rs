@@ -0,0 +1,1267 @@
+//! User agent behind the call RPCs of `voip.sip.SipService`.
+//!
+//! Calls placed with `Invite` are originated here, and inbound INVITEs the
+//! B2BUA does not take ring until `Answer`. Both are tracked in the shared
+//! [`CallManager`], next to bridged legs.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::{
+    sync::{mpsc, Notify},
+    time::{self, Instant},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use voip_common::{
+    sdp::{Direction, SessionDescription},
+    CallId, Result, VoipError,
+};
+
+use crate::{
+    dialog::{
+        is_terminal, is_valid_transition, Call, CallDirection, CallManager, CallState, Dialog,
+        DialogId,
+    },
+    registrar::Registrar,
+    sip::{
+        message::names_match, CSeq, Method, NameAddr, SipRequest, SipResponse, SipUri, StatusCode,
+    },
+    transaction::{new_tag, ClientEvent, ClientTransaction, ServerTransaction, TransactionLayer},
+    transport::{LocalEndpoint, Target},
+};
+
+/// Headers the user agent writes itself; `Invite` callers cannot override them.
+const RESERVED_HEADERS: [&str; 9] = [
+    "Via",
+    "Max-Forwards",
+    "From",
+    "To",
+    "Call-ID",
+    "CSeq",
+    "Contact",
+    "Content-Type",
+    "Content-Length",
+];
+
+/// Progress of a call placed with [`UserAgent::invite`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteUpdate {
+    /// A provisional response other than ringing.
+    Progress {
+        /// Status code.
+        code: u16,
+        /// Reason phrase.
+        reason: String,
+    },
+    /// 180 or 183.
+    Ringing {
+        /// Early media SDP, empty when the response had none.
+        sdp: String,
+    },
+    /// The callee answered.
+    Answered {
+        /// SDP answer.
+        sdp: String,
+    },
+    /// The call was rejected, cancelled or timed out.
+    Failed {
+        /// Final status code.
+        code: u16,
+        /// Reason phrase.
+        reason: String,
+    },
+}
+
+/// One call handled by the user agent.
+#[derive(Debug)]
+struct Session {
+    call_id: CallId,
+    /// Where requests of this call go: the routed callee, or where the INVITE came from.
+    target: Target,
+    /// The INVITE we sent, or the one we received.
+    invite: SipRequest<'static>,
+    /// Server transaction of a received INVITE, answered by `Answer` or `Cancel`.
+    transaction: Option<ServerTransaction>,
+    local_tag: String,
+    contact: String,
+    /// Last SDP we sent, re-offered by hold and resume.
+    local_sdp: Mutex<String>,
+    cancelled: AtomicBool,
+    ack: Notify,
+}
+
+impl Session {
+    fn is_inbound(&self) -> bool {
+        self.transaction.is_some()
+    }
+}
+
+/// Originates and answers calls on behalf of gRPC clients.
+#[derive(Debug)]
+pub struct UserAgent {
+    transactions: TransactionLayer,
+    registrar: Arc<Registrar>,
+    calls: Arc<CallManager>,
+    endpoint: LocalEndpoint,
+    trunk: Option<String>,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+}
+
+impl UserAgent {
+    /// Create a user agent routing callees through `registrar`, then `trunk`.
+    pub fn new(
+        transactions: TransactionLayer,
+        registrar: Arc<Registrar>,
+        calls: Arc<CallManager>,
+        endpoint: LocalEndpoint,
+        trunk: Option<String>,
+    ) -> Self {
+        Self {
+            transactions,
+            registrar,
+            calls,
+            endpoint,
+            trunk,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the SIP Call-ID belongs to one of our calls.
+    pub fn owns(&self, sip_call_id: &str) -> bool {
+        self.sessions
+            .lock()
+            .is_ok_and(|sessions| sessions.contains_key(sip_call_id))
+    }
+
+    /// Place a call and report its progress on the returned channel.
+    pub async fn invite(
+        self: &Arc<Self>,
+        from: SipUri,
+        to: SipUri,
+        sdp_offer: &str,
+        headers: &HashMap<String, String>,
+        correlation_id: Option<String>,
+    ) -> Result<(CallId, mpsc::UnboundedReceiver<InviteUpdate>)> {
+        let (uri, target) = self.registrar.route(&to, self.trunk.as_deref()).await?;
+        let mut call_id = CallId::new();
+        if let Some(correlation_id) = correlation_id.filter(|c| !c.is_empty()) {
+            call_id.correlation_id = correlation_id;
+        }
+        let local_tag = new_tag();
+        let mut local = NameAddr::new(from.clone());
+        local.set_param("tag", Some(&local_tag));
+        let contact = self.endpoint.contact(
+            target.transport,
+            from.user.as_deref().unwrap_or("signalling"),
+        );
+
+        let mut request = SipRequest::new(Method::Invite, uri.to_string())
+            .with_header("Via", self.endpoint.via(target.transport).to_string())
+            .with_header("Max-Forwards", "70")
+            .with_header("From", local.to_string())
+            .with_header("To", NameAddr::new(to.clone()).to_string())
+            .with_header("Call-ID", call_id.sip_call_id.clone())
+            .with_header("CSeq", CSeq::new(1, Method::Invite).to_string())
+            .with_header("Contact", contact.clone());
+        for (name, value) in headers {
+            if RESERVED_HEADERS.iter().any(|r| names_match(r, name)) {
+                return Err(VoipError::Validation(format!(
+                    "header {} cannot be overridden",
+                    name
+                )));
+            }
+            request.headers.push(name.clone(), value.clone());
+        }
+        if !sdp_offer.is_empty() {
+            request.headers.push("Content-Type", "application/sdp");
+        }
+        request.set_body(sdp_offer.as_bytes().to_vec());
+
+        let session = Arc::new(Session {
+            call_id: call_id.clone(),
+            target,
+            invite: request.clone(),
+            transaction: None,
+            local_tag,
+            contact,
+            local_sdp: Mutex::new(sdp_offer.to_string()),
+            cancelled: AtomicBool::new(false),
+            ack: Notify::new(),
+        });
+        self.calls.insert(Call::new(
+            call_id.clone(),
+            CallDirection::DirectionOutbound,
+            from,
+            to,
+        ));
+        self.track(&session);
+        info!(
+            correlation_id = %call_id.correlation_id,
+            call_id = %call_id.sip_call_id,
+            target = %target.addr,
+            "placing call"
+        );
+
+        let client = match self.transactions.send_request(request, target) {
+            Ok(client) => client,
+            Err(err) => {
+                self.finish(&session);
+                return Err(err);
+            }
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        let span = info_span!("ua", correlation_id = %call_id.correlation_id);
+        tokio::spawn(
+            self.clone()
+                .drive_invite(session, client, tx)
+                .instrument(span),
+        );
+        Ok((call_id, rx))
+    }
+
+    /// Accept a ringing inbound call with `sdp_answer`.
+    pub async fn answer(self: &Arc<Self>, call_id: &CallId, sdp_answer: &str) -> Result<()> {
+        let session = self.session(call_id)?;
+        let Some(transaction) = &session.transaction else {
+            return Err(VoipError::Validation(format!(
+                "call {} is outbound and cannot be answered",
+                call_id
+            )));
+        };
+        self.expect_state(call_id, &[CallState::StateRinging])?;
+
+        let mut response = session
+            .invite
+            .response(StatusCode::OK)
+            .with_to_tag(&session.local_tag)
+            .with_header("Contact", session.contact.clone());
+        if !sdp_answer.is_empty() {
+            response.headers.push("Content-Type", "application/sdp");
+        }
+        response.set_body(sdp_answer.as_bytes().to_vec());
+        if let Ok(mut local) = session.local_sdp.lock() {
+            *local = sdp_answer.to_string();
+        }
+
+        let confirmed = Dialog::uas(&session.invite, &session.local_tag, false);
+        self.calls
+            .update(call_id, |call| {
+                if let Some(dialog) = confirmed {
+                    call.add_dialog(dialog);
+                }
+                call.transition(CallState::StateAnswered)
+            })
+            .await?;
+        transaction.respond(response.clone())?;
+        info!(call_id = %call_id.sip_call_id, "call answered");
+        tokio::spawn(self.clone().retransmit_2xx(session, response));
+        Ok(())
+    }
+
+    /// Hang up an established call.
+    pub async fn bye(&self, call_id: &CallId, reason: &str) -> Result<()> {
+        let session = self.session(call_id)?;
+        self.expect_state(
+            call_id,
+            &[
+                CallState::StateAnswered,
+                CallState::StateHeld,
+                CallState::StateTransferring,
+            ],
+        )?;
+        self.send_bye(&session).await;
+        let reason = if reason.is_empty() { "hung up" } else { reason };
+        self.end(&session, CallState::StateTerminated, reason).await;
+        self.finish(&session);
+        Ok(())
+    }
+
+    /// Abandon a call that is not answered yet: CANCEL it, or decline it when inbound.
+    pub async fn cancel(&self, call_id: &CallId, reason: &str) -> Result<()> {
+        let session = self.session(call_id)?;
+        self.expect_state(
+            call_id,
+            &[CallState::StateInitiating, CallState::StateRinging],
+        )?;
+        let reason = if reason.is_empty() {
+            "cancelled"
+        } else {
+            reason
+        };
+        match &session.transaction {
+            Some(transaction) => {
+                let declined = session
+                    .invite
+                    .response(StatusCode::DECLINE)
+                    .with_to_tag(&session.local_tag);
+                respond(transaction, declined);
+                self.end(&session, CallState::StateTerminated, reason).await;
+                self.finish(&session);
+            }
+            None => {
+                if session.cancelled.swap(true, Ordering::SeqCst) {
+                    return Ok(());
+                }
+                // The INVITE client transaction reports the 487 to the Invite stream.
+                let mut client = self
+                    .transactions
+                    .send_request(session.invite.cancel(), session.target)?;
+                tokio::spawn(async move {
+                    if let Err(err) = client.final_response().await {
+                        debug!(error = %err, "CANCEL unanswered");
+                    }
+                });
+            }
+        }
+        info!(call_id = %call_id.sip_call_id, reason, "call cancelled");
+        Ok(())
+    }
+
+    /// Put the peer on hold with a sendonly re-INVITE (RFC 3264 §8.4).
+    pub async fn hold(&self, call_id: &CallId) -> Result<()> {
+        self.reoffer(call_id, Direction::SendOnly, CallState::StateHeld)
+            .await
+    }
+
+    /// Take the peer off hold with a sendrecv re-INVITE.
+    pub async fn resume(&self, call_id: &CallId) -> Result<()> {
+        self.reoffer(call_id, Direction::SendRecv, CallState::StateAnswered)
+            .await
+    }
+
+    /// Blind transfer with REFER (RFC 3515); the call ends once the transferee reports success.
+    pub async fn transfer(&self, call_id: &CallId, to: &SipUri, attended: bool) -> Result<()> {
+        if attended {
+            return Err(VoipError::Validation(
+                "attended transfer is not supported".into(),
+            ));
+        }
+        let session = self.session(call_id)?;
+        self.expect_state(call_id, &[CallState::StateAnswered, CallState::StateHeld])?;
+        let mut refer = self.dialog_request(&session, Method::Refer).await?;
+        refer.headers.set("Refer-To", format!("<{}>", to));
+        let mut client = self.transactions.send_request(refer, session.target)?;
+        let response = client.final_response().await?;
+        if !response.status.is_success() {
+            return Err(VoipError::Sip {
+                code: response.status.0,
+                reason: format!("REFER rejected: {}", response.reason),
+            });
+        }
+        info!(call_id = %call_id.sip_call_id, to = %to, "call transferring");
+        self.calls
+            .transition(call_id, CallState::StateTransferring)
+            .await
+    }
+
+    /// Handle a request of one of our calls, or an INVITE nobody else takes.
+    pub fn handle_request(
+        self: &Arc<Self>,
+        request: SipRequest<'static>,
+        source: Target,
+        transaction: Option<ServerTransaction>,
+    ) {
+        let Some(transaction) = transaction else {
+            if request.method == Method::Ack {
+                match self.lookup(&request) {
+                    Some(session) => session.ack.notify_one(),
+                    None => debug!("ACK for unknown call ignored"),
+                }
+            }
+            return;
+        };
+        let this = self.clone();
+        match request.method {
+            Method::Invite if request.in_dialog() => {
+                tokio::spawn(this.reinvite(request, transaction));
+            }
+            // A retransmission outliving its transaction; the call already rings.
+            Method::Invite if self.lookup(&request).is_some() => {
+                debug!("retransmitted INVITE of a known call ignored");
+            }
+            Method::Invite => {
+                tokio::spawn(async move { this.incoming(request, source, transaction).await });
+            }
+            Method::Cancel => {
+                tokio::spawn(this.cancelled(request, transaction));
+            }
+            Method::Bye => {
+                tokio::spawn(this.remote_bye(request, transaction));
+            }
+            Method::Notify => {
+                tokio::spawn(this.notify(request, transaction));
+            }
+            _ => respond(
+                &transaction,
+                request.response(StatusCode::METHOD_NOT_ALLOWED),
+            ),
+        }
+    }
+
+    /// Re-acknowledge a retransmitted 2xx to one of our INVITEs.
+    pub fn handle_stray_response(self: &Arc<Self>, response: SipResponse<'static>) {
+        let is_invite_2xx = response.status.is_success()
+            && response
+                .headers
+                .cseq()
+                .is_some_and(|cseq| cseq.method == Method::Invite);
+        let session = response
+            .headers
+            .call_id()
+            .and_then(|call_id| self.sessions.lock().ok()?.get(call_id).cloned());
+        match session {
+            Some(session) if is_invite_2xx => {
+                let this = self.clone();
+                tokio::spawn(async move { this.ack(&session).await });
+            }
+            _ => debug!(status = %response.status, "stray response ignored"),
+        }
+    }
+
+    /// Report responses to our INVITE until it completes.
+    async fn drive_invite(
+        self: Arc<Self>,
+        session: Arc<Session>,
+        mut client: ClientTransaction,
+        updates: mpsc::UnboundedSender<InviteUpdate>,
+    ) {
+        // The caller may stop listening; the call goes on regardless.
+        let report = |update| {
+            let _ = updates.send(update);
+        };
+        while let Some(event) = client.recv().await {
+            let response = match event {
+                ClientEvent::Response(response) => response,
+                ClientEvent::Timeout => {
+                    warn!("INVITE timed out");
+                    report(InviteUpdate::Failed {
+                        code: StatusCode::REQUEST_TIMEOUT.0,
+                        reason: StatusCode::REQUEST_TIMEOUT.canonical_reason().to_string(),
+                    });
+                    self.end(&session, CallState::StateFailed, "timeout").await;
+                    self.finish(&session);
+                    return;
+                }
+            };
+            let status = response.status;
+            let invite = &session.invite;
+            if let Err(err) = self
+                .calls
+                .update(&session.call_id, |call| {
+                    call.on_invite_response(invite, &response)
+                })
+                .await
+            {
+                debug!(error = %err, "call state not updated");
+            }
+
+            if status == StatusCode::RINGING || status == StatusCode::SESSION_PROGRESS {
+                report(InviteUpdate::Ringing {
+                    sdp: body(&response),
+                });
+            } else if status.is_provisional() {
+                report(InviteUpdate::Progress {
+                    code: status.0,
+                    reason: response.reason.to_string(),
+                });
+            } else if status.is_success() {
+                self.ack(&session).await;
+                if session.cancelled.load(Ordering::SeqCst) {
+                    // The callee answered while our CANCEL was in flight.
+                    self.send_bye(&session).await;
+                    self.end(&session, CallState::StateTerminated, "cancelled")
+                        .await;
+                    self.finish(&session);
+                    report(InviteUpdate::Failed {
+                        code: StatusCode::REQUEST_TERMINATED.0,
+                        reason: StatusCode::REQUEST_TERMINATED
+                            .canonical_reason()
+                            .to_string(),
+                    });
+                    return;
+                }
+                info!("call answered");
+                report(InviteUpdate::Answered {
+                    sdp: body(&response),
+                });
+                return;
+            } else {
+                info!(status = %status, "call rejected");
+                report(InviteUpdate::Failed {
+                    code: status.0,
+                    reason: response.reason.to_string(),
+                });
+                self.finish(&session);
+                return;
+            }
+        }
+    }
+
+    /// Ring an inbound INVITE until `Answer` or `Cancel`.
+    async fn incoming(
+        &self,
+        invite: SipRequest<'static>,
+        source: Target,
+        transaction: ServerTransaction,
+    ) {
+        let local_tag = new_tag();
+        let parties = invite
+            .headers
+            .call_id()
+            .zip(invite.headers.from_addr())
+            .zip(invite.headers.to_addr());
+        let Some(((sip_call_id, from), to)) = parties else {
+            let invalid = invite
+                .response(StatusCode::BAD_REQUEST)
+                .with_to_tag(&local_tag);
+            respond(&transaction, invalid);
+            return;
+        };
+
+        let call_id = CallId::from_sip(sip_call_id.to_string());
+        let contact = self.endpoint.contact(
+            source.transport,
+            to.uri.user.as_deref().unwrap_or("signalling"),
+        );
+        let mut call = Call::new(
+            call_id.clone(),
+            CallDirection::DirectionInbound,
+            from.uri,
+            to.uri,
+        );
+        if let Some(dialog) = Dialog::uas(&invite, &local_tag, true) {
+            call.add_dialog(dialog);
+        }
+        if let Err(err) = call.transition(CallState::StateRinging) {
+            debug!(error = %err, "call state not updated");
+        }
+        let ringing = invite
+            .response(StatusCode::RINGING)
+            .with_to_tag(&local_tag)
+            .with_header("Contact", contact.clone());
+        let session = Arc::new(Session {
+            call_id: call_id.clone(),
+            target: source,
+            invite,
+            transaction: Some(transaction.clone()),
+            local_tag,
+            contact,
+            local_sdp: Mutex::new(String::new()),
+            cancelled: AtomicBool::new(false),
+            ack: Notify::new(),
+        });
+        self.calls.insert(call);
+        self.track(&session);
+        info!(
+            correlation_id = %call_id.correlation_id,
+            call_id = %call_id.sip_call_id,
+            "incoming call ringing"
+        );
+        respond(&transaction, ringing);
+    }
+
+    async fn cancelled(
+        self: Arc<Self>,
+        cancel: SipRequest<'static>,
+        transaction: ServerTransaction,
+    ) {
+        let session = self.lookup(&cancel).filter(|s| s.is_inbound());
+        let answered = session.as_ref().is_none_or(|s| {
+            self.calls
+                .get(&s.call_id)
+                .is_none_or(|call| call.answered_at.is_some() || is_terminal(call.state()))
+        });
+        let (Some(session), false) = (session, answered) else {
+            respond(
+                &transaction,
+                cancel.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        respond(&transaction, cancel.response(StatusCode::OK));
+        if let Some(invite) = &session.transaction {
+            let terminated = session
+                .invite
+                .response(StatusCode::REQUEST_TERMINATED)
+                .with_to_tag(&session.local_tag);
+            respond(invite, terminated);
+        }
+        info!(call_id = %session.call_id.sip_call_id, "call cancelled by caller");
+        self.end(&session, CallState::StateTerminated, "cancelled by caller")
+            .await;
+        self.finish(&session);
+    }
+
+    async fn remote_bye(self: Arc<Self>, bye: SipRequest<'static>, transaction: ServerTransaction) {
+        let Some(session) = self.lookup(&bye) else {
+            respond(&transaction, bye.response(StatusCode::CALL_DOES_NOT_EXIST));
+            return;
+        };
+        if let Err(err) = self.check_request(&session, &bye).await {
+            respond(&transaction, bye.response(StatusCode::for_error(&err)));
+            return;
+        }
+        respond(&transaction, bye.response(StatusCode::OK));
+        info!(call_id = %session.call_id.sip_call_id, "remote hung up");
+        self.end(&session, CallState::StateTerminated, "remote hung up")
+            .await;
+        self.finish(&session);
+    }
+
+    /// Answer a re-INVITE from the peer with our current SDP.
+    async fn reinvite(
+        self: Arc<Self>,
+        request: SipRequest<'static>,
+        transaction: ServerTransaction,
+    ) {
+        let Some(session) = self.lookup(&request) else {
+            respond(
+                &transaction,
+                request.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        let offer = std::str::from_utf8(&request.body)
+            .ok()
+            .filter(|sdp| !sdp.is_empty())
+            .map(SessionDescription::parse)
+            .transpose();
+        let held = offer
+            .as_ref()
+            .is_ok_and(|offer| offer.as_ref().is_some_and(SessionDescription::is_hold));
+        let answer = offer.and_then(|offer| self.answer_reoffer(&session, offer.as_ref()));
+        let answer = match self.check_request(&session, &request).await.and(answer) {
+            Ok(answer) => answer,
+            Err(err) => {
+                let status = match err {
+                    VoipError::Media(_) => StatusCode::NOT_ACCEPTABLE_HERE,
+                    ref err => StatusCode::for_error(err),
+                };
+                respond(&transaction, request.response(status));
+                return;
+            }
+        };
+
+        let mut response = request
+            .response(StatusCode::OK)
+            .with_header("Contact", session.contact.clone());
+        if !answer.is_empty() {
+            response.headers.push("Content-Type", "application/sdp");
+        }
+        response.set_body(answer.into_bytes());
+        respond(&transaction, response.clone());
+
+        let state = if held {
+            CallState::StateHeld
+        } else {
+            CallState::StateAnswered
+        };
+        if let Err(err) = self.calls.transition(&session.call_id, state).await {
+            debug!(error = %err, "call state not updated");
+        }
+        self.retransmit_2xx(session, response).await;
+    }
+
+    /// Our answer to an offer received mid-call: the last SDP we sent, in the matching direction.
+    fn answer_reoffer(
+        &self,
+        session: &Session,
+        offer: Option<&SessionDescription>,
+    ) -> Result<String> {
+        let mut local = session
+            .local_sdp
+            .lock()
+            .map_err(|_| VoipError::Internal("session poisoned".into()))?;
+        let (Some(offer), false) = (offer, local.is_empty()) else {
+            return Ok(local.clone());
+        };
+        let direction = offer
+            .audio()
+            .map_or(Direction::SendRecv, |audio| offer.direction_of(audio))
+            .answer(Direction::SendRecv);
+        *local = redirect(&local, direction)?;
+        Ok(local.clone())
+    }
+
+    /// Transfer progress (RFC 3515 §2.4.5): hang up once the transferee is connected.
+    async fn notify(self: Arc<Self>, notify: SipRequest<'static>, transaction: ServerTransaction) {
+        let Some(session) = self.lookup(&notify) else {
+            respond(
+                &transaction,
+                notify.response(StatusCode::CALL_DOES_NOT_EXIST),
+            );
+            return;
+        };
+        if let Err(err) = self.check_request(&session, &notify).await {
+            respond(&transaction, notify.response(StatusCode::for_error(&err)));
+            return;
+        }
+        respond(&transaction, notify.response(StatusCode::OK));
+        let is_refer = notify
+            .headers
+            .get("Event")
+            .is_some_and(|event| event.trim().starts_with("refer"));
+        let Some(status) = is_refer.then(|| sipfrag_status(&notify.body)).flatten() else {
+            return;
+        };
+        if status.is_success() {
+            info!(call_id = %session.call_id.sip_call_id, "call transferred");
+            self.send_bye(&session).await;
+            self.end(&session, CallState::StateTerminated, "transferred")
+                .await;
+            self.finish(&session);
+        } else if status.is_final() {
+            info!(call_id = %session.call_id.sip_call_id, status = %status, "transfer failed");
+            if let Err(err) = self
+                .calls
+                .transition(&session.call_id, CallState::StateAnswered)
+                .await
+            {
+                debug!(error = %err, "call state not updated");
+            }
+        }
+    }
+
+    /// Send our last SDP again with a new direction and move the call to `state` once accepted.
+    async fn reoffer(
+        &self,
+        call_id: &CallId,
+        direction: Direction,
+        state: CallState,
+    ) -> Result<()> {
+        let session = self.session(call_id)?;
+        let current = self
+            .calls
+            .get(call_id)
+            .map(|call| call.state())
+            .ok_or_else(|| VoipError::NotFound(format!("call {} not found", call_id)))?;
+        if current == state {
+            return Ok(());
+        }
+        if !is_valid_transition(current, state) {
+            return Err(VoipError::Validation(format!(
+                "call {} is {}",
+                call_id,
+                current.as_str_name()
+            )));
+        }
+        let offer = {
+            let local = session
+                .local_sdp
+                .lock()
+                .map_err(|_| VoipError::Internal("session poisoned".into()))?;
+            if local.is_empty() {
+                return Err(VoipError::Validation(format!(
+                    "call {} has no SDP to re-offer",
+                    call_id
+                )));
+            }
+            redirect(&local, direction)?
+        };
+
+        let mut request = self.dialog_request(&session, Method::Invite).await?;
+        request.headers.set("Content-Type", "application/sdp");
+        request.set_body(offer.clone().into_bytes());
+        let mut client = self.transactions.send_request(request, session.target)?;
+        let answer = client.final_response().await?;
+        if !answer.status.is_success() {
+            return Err(VoipError::Sip {
+                code: answer.status.0,
+                reason: format!("re-INVITE rejected: {}", answer.reason),
+            });
+        }
+        if let Ok(mut local) = session.local_sdp.lock() {
+            *local = offer;
+        }
+        self.calls
+            .update(call_id, |call| {
+                if let Some(id) = DialogId::outgoing(&answer.headers) {
+                    if let Some(dialog) = call.dialog_mut(&id) {
+                        dialog.refresh_target(&answer.headers);
+                    }
+                }
+                call.transition(state)
+            })
+            .await?;
+        self.ack(&session).await;
+        info!(call_id = %call_id.sip_call_id, state = state.as_str_name(), "call re-negotiated");
+        Ok(())
+    }
+
+    /// Validate the CSeq of an in-dialog request and apply its target refresh.
+    async fn check_request(&self, session: &Session, request: &SipRequest<'_>) -> Result<()> {
+        let no_dialog = || VoipError::Sip {
+            code: StatusCode::CALL_DOES_NOT_EXIST.0,
+            reason: "no matching dialog".into(),
+        };
+        let id = DialogId::incoming(&request.headers).ok_or_else(no_dialog)?;
+        let cseq = request.headers.cseq().ok_or_else(|| VoipError::Sip {
+            code: StatusCode::BAD_REQUEST.0,
+            reason: "missing CSeq".into(),
+        })?;
+        self.calls
+            .update(&session.call_id, |call| {
+                let dialog = call.dialog_mut(&id).ok_or_else(no_dialog)?;
+                dialog.validate_remote_cseq(&cseq)?;
+                if request.method == Method::Invite {
+                    dialog.refresh_target(&request.headers);
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Build an in-dialog request.
+    async fn dialog_request(
+        &self,
+        session: &Session,
+        method: Method,
+    ) -> Result<SipRequest<'static>> {
+        let via = self.endpoint.via(session.target.transport);
+        let is_target_refresh = matches!(method, Method::Invite | Method::Refer);
+        let call_id = session.call_id.clone();
+        let mut request = self
+            .calls
+            .update(&call_id, |call| {
+                let no_dialog = || VoipError::NotFound(format!("no dialog for {}", call_id));
+                let id = call
+                    .dialog()
+                    .map(|dialog| dialog.id.clone())
+                    .ok_or_else(no_dialog)?;
+                let request = call
+                    .dialog_mut(&id)
+                    .map(|dialog| dialog.create_request(method, via));
+                request.ok_or_else(no_dialog)
+            })
+            .await?;
+        if is_target_refresh {
+            request.headers.push("Contact", session.contact.clone());
+        }
+        Ok(request)
+    }
+
+    /// Acknowledge the 2xx to our latest INVITE.
+    async fn ack(&self, session: &Session) {
+        match self.dialog_request(session, Method::Ack).await {
+            Ok(ack) => self.transactions.send_stateless(session.target, ack.into()),
+            Err(err) => warn!(error = %err, "cannot build ACK"),
+        }
+    }
+
+    async fn send_bye(&self, session: &Session) {
+        let bye = match self.dialog_request(session, Method::Bye).await {
+            Ok(bye) => bye,
+            Err(err) => {
+                warn!(error = %err, "cannot build BYE");
+                return;
+            }
+        };
+        match self.transactions.send_request(bye, session.target) {
+            Ok(mut client) => {
+                if let Err(err) = client.final_response().await {
+                    debug!(error = %err, "BYE unanswered");
+                }
+            }
+            Err(err) => warn!(error = %err, "failed to send BYE"),
+        }
+    }
+
+    /// Retransmit a 2xx we sent until its ACK arrives (RFC 3261 §13.3.1.4).
+    async fn retransmit_2xx(
+        self: Arc<Self>,
+        session: Arc<Session>,
+        response: SipResponse<'static>,
+    ) {
+        let target = session.target;
+        if target.transport.is_reliable() {
+            return;
+        }
+        let timers = self.transactions.timers();
+        let deadline = Instant::now() + timers.t1 * 64;
+        let mut interval = timers.t1;
+        loop {
+            tokio::select! {
+                () = session.ack.notified() => return,
+                () = time::sleep(interval) => {}
+            }
+            if Instant::now() >= deadline {
+                warn!(call_id = %session.call_id.sip_call_id, "2xx never acknowledged, hanging up");
+                self.send_bye(&session).await;
+                self.end(&session, CallState::StateTerminated, "no ACK")
+                    .await;
+                self.finish(&session);
+                return;
+            }
+            self.transactions
+                .send_stateless(target, response.clone().into());
+            interval = (interval * 2).min(timers.t2);
+        }
+    }
+
+    fn expect_state(&self, call_id: &CallId, allowed: &[CallState]) -> Result<()> {
+        let call = self
+            .calls
+            .get(call_id)
+            .ok_or_else(|| VoipError::NotFound(format!("call {} not found", call_id)))?;
+        if allowed.contains(&call.state()) {
+            Ok(())
+        } else {
+            Err(VoipError::Validation(format!(
+                "call {} is {}",
+                call_id,
+                call.state().as_str_name()
+            )))
+        }
+    }
+
+    async fn end(&self, session: &Session, state: CallState, reason: &str) {
+        let result = self
+            .calls
+            .update(&session.call_id, |call| {
+                if is_terminal(call.state()) {
+                    return Ok(());
+                }
+                call.end(state, reason)
+            })
+            .await;
+        if let Err(err) = result {
+            debug!(error = %err, "call state not updated");
+        }
+    }
+
+    fn session(&self, call_id: &CallId) -> Result<Arc<Session>> {
+        self.sessions
+            .lock()
+            .ok()
+            .and_then(|sessions| sessions.get(&call_id.sip_call_id).cloned())
+            .ok_or_else(|| {
+                VoipError::NotFound(format!("call {} is not handled by the API", call_id))
+            })
+    }
+
+    fn lookup(&self, request: &SipRequest<'_>) -> Option<Arc<Session>> {
+        let call_id = request.headers.call_id()?;
+        self.sessions.lock().ok()?.get(call_id).cloned()
+    }
+
+    fn track(&self, session: &Arc<Session>) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.insert(session.call_id.sip_call_id.clone(), session.clone());
+        }
+    }
+
+    /// Forget a call.
+    fn finish(&self, session: &Session) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(&session.call_id.sip_call_id);
+        }
+        self.calls.remove(&session.call_id);
+        debug!(call_id = %session.call_id.sip_call_id, "call closed");
+    }
+}
+
+fn respond(transaction: &ServerTransaction, response: SipResponse<'static>) {
+    if let Err(err) = transaction.respond(response) {
+        debug!(error = %err, "response dropped");
+    }
+}
+
+fn body(response: &SipResponse<'_>) -> String {
+    String::from_utf8_lossy(&response.body).into_owned()
+}
+
+/// `sdp` as a new offer (version bumped) with every active stream set to `direction`.
+fn redirect(sdp: &str, direction: Direction) -> Result<String> {
+    let mut session = SessionDescription::parse(sdp)?;
+    session.origin.session_version += 1;
+    for media in session.media.iter_mut().filter(|m| m.port != 0) {
+        media.set_direction(direction);
+    }
+    Ok(session.to_string())
+}
+
+/// Status line of a `message/sipfrag` NOTIFY body (RFC 3420).
+pub(crate) fn sipfrag_status(body: &[u8]) -> Option<StatusCode> {
+    let line = std::str::from_utf8(body).ok()?.lines().next()?;
+    let code = line.strip_prefix("SIP/2.0 ")?.split_whitespace().next()?;
+    code.parse().ok().map(StatusCode)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::{
+        b2bua::B2buaConfig,
+        sip::{parse_message, SipMessage},
+        transport::{TransportConfig, TransportHandle},
+        SignallingService,
+    };
+
+    const OFFER: &str = "v=0\r\no=agent 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+    const ANSWER: &str = "v=0\r\no=bob 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 5000 RTP/AVP 0\r\n";
+
+    async fn service(peer: &UdpSocket) -> (Arc<SignallingService>, SocketAddr) {
+        let config = TransportConfig {
+            udp: Some("127.0.0.1:0".parse().expect("addr")),
+            tcp: None,
+            ..TransportConfig::default()
+        };
+        let service = SignallingService::bind(&config)
+            .await
+            .expect("bind")
+            .with_b2bua(B2buaConfig {
+                enabled: false,
+                trunk: Some(format!("sip:{}", peer.local_addr().expect("addr"))),
+                advertised_host: None,
+            });
+        let service = Arc::new(service);
+        let addr = service
+            .transport()
+            .and_then(TransportHandle::udp_addr)
+            .expect("udp");
+        service.clone().spawn();
+        (service, addr)
+    }
+
+    async fn recv(
+        socket: &UdpSocket,
+        wanted: impl Fn(&SipMessage<'_>) -> bool,
+    ) -> SipMessage<'static> {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let (n, _) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+                .await
+                .expect("timed out")
+                .expect("recv");
+            let (message, _) = parse_message(&buf[..n]).expect("parse");
+            if wanted(&message) {
+                return message.into_owned();
+            }
+        }
+    }
+
+    async fn recv_request(socket: &UdpSocket, method: Method) -> SipRequest<'static> {
+        match recv(
+            socket,
+            |m| matches!(m, SipMessage::Request(r) if r.method == method),
+        )
+        .await
+        {
+            SipMessage::Request(request) => request,
+            SipMessage::Response(_) => unreachable!(),
+        }
+    }
+
+    async fn recv_response(socket: &UdpSocket, status: StatusCode) -> SipResponse<'static> {
+        match recv(
+            socket,
+            |m| matches!(m, SipMessage::Response(r) if r.status == status),
+        )
+        .await
+        {
+            SipMessage::Response(response) => response,
+            SipMessage::Request(_) => unreachable!(),
+        }
+    }
+
+    async fn send(socket: &UdpSocket, message: impl Into<SipMessage<'static>>, to: SocketAddr) {
+        socket
+            .send_to(&message.into().to_bytes(), to)
+            .await
+            .expect("send");
+    }
+
+    fn reply(
+        socket: &UdpSocket,
+        request: &SipRequest<'_>,
+        status: StatusCode,
+        sdp: &str,
+    ) -> SipResponse<'static> {
+        let port = socket.local_addr().expect("addr").port();
+        let mut response = request
+            .response(status)
+            .with_to_tag("bob")
+            .with_header("Contact", format!("<sip:bob@127.0.0.1:{}>", port));
+        if !sdp.is_empty() {
+            response.headers.push("Content-Type", "application/sdp");
+            response.set_body(sdp.as_bytes().to_vec());
+        }
+        response
+    }
+
+    fn state(service: &SignallingService, call_id: &CallId) -> Option<CallState> {
+        service.calls().get(call_id).map(|call| call.state())
+    }
+
+    #[tokio::test]
+    async fn api_call_is_answered_held_and_hung_up() {
+        let callee = UdpSocket::bind("127.0.0.1:0").await.expect("callee");
+        let (service, addr) = service(&callee).await;
+        let ua = service.ua().clone();
+
+        let (call_id, mut updates) = ua
+            .invite(
+                "sip:agent@voip.local".parse().expect("uri"),
+                "sip:bob@voip.local".parse().expect("uri"),
+                OFFER,
+                &HashMap::from([("X-Campaign".to_string(), "42".to_string())]),
+                Some("corr-1".to_string()),
+            )
+            .await
+            .expect("invite");
+        assert_eq!(call_id.correlation_id, "corr-1");
+        let invite = recv_request(&callee, Method::Invite).await;
+        assert_eq!(invite.headers.get("X-Campaign"), Some("42"));
+        assert_eq!(&*invite.body, OFFER.as_bytes());
+
+        send(
+            &callee,
+            reply(&callee, &invite, StatusCode::RINGING, ""),
+            addr,
+        )
+        .await;
+        assert_eq!(
+            updates.recv().await,
+            Some(InviteUpdate::Ringing { sdp: String::new() })
+        );
+        send(
+            &callee,
+            reply(&callee, &invite, StatusCode::OK, ANSWER),
+            addr,
+        )
+        .await;
+        assert_eq!(
+            updates.recv().await,
+            Some(InviteUpdate::Answered {
+                sdp: ANSWER.to_string()
+            })
+        );
+        recv_request(&callee, Method::Ack).await;
+        assert_eq!(state(&service, &call_id), Some(CallState::StateAnswered));
+
+        let hold = tokio::spawn({
+            let (ua, call_id) = (ua.clone(), call_id.clone());
+            async move { ua.hold(&call_id).await }
+        });
+        let reinvite = recv_request(&callee, Method::Invite).await;
+        let offer = SessionDescription::parse(std::str::from_utf8(&reinvite.body).expect("utf8"))
+            .expect("sdp");
+        assert!(offer.is_hold());
+        assert_eq!(offer.origin.session_version, 2);
+        send(
+            &callee,
+            reply(&callee, &reinvite, StatusCode::OK, ANSWER),
+            addr,
+        )
+        .await;
+        hold.await.expect("join").expect("hold");
+        recv_request(&callee, Method::Ack).await;
+        assert_eq!(state(&service, &call_id), Some(CallState::StateHeld));
+
+        let bye = tokio::spawn({
+            let (ua, call_id) = (ua.clone(), call_id.clone());
+            async move { ua.bye(&call_id, "done").await }
+        });
+        let request = recv_request(&callee, Method::Bye).await;
+        send(&callee, reply(&callee, &request, StatusCode::OK, ""), addr).await;
+        bye.await.expect("join").expect("bye");
+        assert_eq!(state(&service, &call_id), None);
+        assert!(!ua.owns(&call_id.sip_call_id));
+    }
+
+    #[tokio::test]
+    async fn inbound_call_rings_until_answered() {
+        let caller = UdpSocket::bind("127.0.0.1:0").await.expect("caller");
+        let (service, addr) = service(&caller).await;
+        let port = caller.local_addr().expect("addr").port();
+        let mut invite = SipRequest::new(Method::Invite, "sip:agent@voip.local")
+            .with_header(
+                "Via",
+                format!("SIP/2.0/UDP 127.0.0.1:{};branch=z9hG4bKin1;rport", port),
+            )
+            .with_header("Max-Forwards", "70")
+            .with_header("From", "<sip:alice@voip.local>;tag=alice")
+            .with_header("To", "<sip:agent@voip.local>")
+            .with_header("Call-ID", "inbound-1")
+            .with_header("CSeq", "1 INVITE")
+            .with_header("Contact", format!("<sip:alice@127.0.0.1:{}>", port))
+            .with_header("Content-Type", "application/sdp");
+        invite.set_body(OFFER.as_bytes().to_vec());
+        send(&caller, invite.clone(), addr).await;
+
+        let ringing = recv_response(&caller, StatusCode::RINGING).await;
+        let tag = ringing
+            .headers
+            .to_addr()
+            .and_then(|to| to.tag().map(str::to_string))
+            .expect("tag");
+        let call = service
+            .calls()
+            .find_by_sip_call_id("inbound-1")
+            .expect("call");
+        assert_eq!(call.state(), CallState::StateRinging);
+
+        service
+            .ua()
+            .answer(&call.call_id, ANSWER)
+            .await
+            .expect("answer");
+        let ok = recv_response(&caller, StatusCode::OK).await;
+        assert_eq!(&*ok.body, ANSWER.as_bytes());
+        assert_eq!(
+            ok.headers
+                .to_addr()
+                .and_then(|to| to.tag().map(str::to_string)),
+            Some(tag.clone())
+        );
+        assert_eq!(
+            state(&service, &call.call_id),
+            Some(CallState::StateAnswered)
+        );
+
+        let in_dialog = |method: Method, branch: &str, cseq: u32| {
+            SipRequest::new(method.clone(), "sip:agent@voip.local")
+                .with_header(
+                    "Via",
+                    format!("SIP/2.0/UDP 127.0.0.1:{};branch={};rport", port, branch),
+                )
+                .with_header("From", "<sip:alice@voip.local>;tag=alice")
+                .with_header("To", format!("<sip:agent@voip.local>;tag={}", tag))
+                .with_header("Call-ID", "inbound-1")
+                .with_header("CSeq", CSeq::new(cseq, method).to_string())
+                .with_header("Content-Length", "0")
+        };
+        send(&caller, in_dialog(Method::Ack, "z9hG4bKin2", 1), addr).await;
+
+        // The 200 got lost and the INVITE is retransmitted; the call goes on.
+        send(&caller, invite, addr).await;
+        let rung = time::timeout(
+            Duration::from_millis(300),
+            recv_response(&caller, StatusCode::RINGING),
+        )
+        .await;
+        assert!(rung.is_err(), "the call does not ring again");
+        assert_eq!(service.calls().list().len(), 1);
+        assert_eq!(
+            state(&service, &call.call_id),
+            Some(CallState::StateAnswered)
+        );
+        send(&caller, in_dialog(Method::Bye, "z9hG4bKin3", 2), addr).await;
+        recv(&caller, |m| {
+            matches!(m, SipMessage::Response(r)
+                if r.status == StatusCode::OK && r.headers.cseq().is_some_and(|c| c.method == Method::Bye))
+        })
+        .await;
+        assert_eq!(state(&service, &call.call_id), None);
+    }
+}